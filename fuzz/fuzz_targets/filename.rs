@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use moklog::injest::static_file::{fingerprinted_name, parse_filename};
+
+fuzz_target!(|input: (u64, &str)| {
+    let (hash, name) = input;
+    let _ = parse_filename(name);
+    // a name fingerprinted as a whole comes back as it was
+    if let Some((_, fingerprinted)) = fingerprinted_name(hash, name) {
+        if !name.contains('/')
+            && name
+                .split_once('.')
+                .map_or(false, |(stem, _)| !stem.is_empty())
+        {
+            assert_eq!(
+                parse_filename(&fingerprinted),
+                Some((hash, name.to_string()))
+            );
+        }
+    }
+});
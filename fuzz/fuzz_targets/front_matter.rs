@@ -0,0 +1,15 @@
+#![no_main]
+
+use chrono::FixedOffset;
+use libfuzzer_sys::fuzz_target;
+use moklog::injest::config_meta::{front_matter, ConfigMeta};
+use moklog::injest::generate::{toml_v_to_json_v, PageHeader};
+
+fuzz_target!(|data: &str| {
+    assert!(data.starts_with(front_matter(data)));
+    let _ = PageHeader::parse(data, &FixedOffset::east_opt(9 * 3600).unwrap());
+    let _ = ConfigMeta::parse(data);
+    if let Ok(value) = toml::from_str::<toml::Value>(front_matter(data)) {
+        let _ = toml_v_to_json_v(value);
+    }
+});
@@ -0,0 +1,45 @@
+#![no_main]
+
+use language_tags::LanguageTag;
+use libfuzzer_sys::fuzz_target;
+use moklog::injest::assets::AssetStore;
+use moklog::injest::links::{LinkStyle, RoutePolicy, SiteUrl};
+use moklog::injest::processor::{html_post_processor, PostProcessContext};
+use moklog::injest::report::BuildReport;
+use moklog::injest::site::BuildOptions;
+use std::path::Path;
+use std::sync::Mutex;
+
+fuzz_target!(|html: &str| {
+    let urls = SiteUrl::new(
+        "https://example.com/",
+        LinkStyle::default(),
+        RoutePolicy::default(),
+    )
+    .unwrap();
+    // the stages that don't need anything outside the process
+    let options = BuildOptions {
+        structured_data: true,
+        accessibility_audit: true,
+        ..BuildOptions::default()
+    };
+    let report = Mutex::new(BuildReport::new());
+    let language = LanguageTag::parse("en").unwrap();
+    let post = PostProcessContext {
+        urls: &urls,
+        bundle: None,
+        options: &options,
+        breadcrumbs: &[],
+        structured_data: None,
+        canonical: "/fuzz",
+        alternates: &[],
+        site_root: Path::new("."),
+        source: Path::new("fuzz.md"),
+        report: &report,
+        og_image: Some("/fuzz/card.png"),
+        summary: Default::default(),
+        noindex: false,
+        language: &language,
+    };
+    let _ = html_post_processor("/fuzz", &AssetStore::new(), &post, html);
+});
@@ -1,5 +1,14 @@
-use color_eyre::Result;
+use crate::injest::links::{LinkStyle, RoutePolicy, SiteUrl, TrailingSlash};
+use chrono::FixedOffset;
+use color_eyre::{Report, Result};
+use ipnet::IpNet;
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::env::var;
+use std::net::SocketAddr;
+
+// where THEME points if it isn't set
+pub const THEME_DIR: &str = "theme";
 
 #[derive(Clone, Debug, PartialOrd, PartialEq, Eq)]
 pub struct Config {
@@ -10,6 +19,55 @@ pub struct Config {
     pub default_timezone: i32,
     pub sitename: String,
     pub index_dir: String,
+    // THEME, the directory of the theme the site is built with
+    pub theme_dir: String,
+    pub bind_address: SocketAddr,
+    pub site_url: SiteUrl,
+    // BUILD_INTERVAL_MINUTES, unset for no scheduled builds
+    pub build_interval: Option<u64>,
+    // ADMIN_ALLOW, the only networks the admin api answers, everywhere if unset
+    pub admin_allow: Vec<IpNet>,
+    // TRUSTED_PROXIES, the reverse proxies whose Forwarded and X-Forwarded-For are believed
+    pub trusted_proxies: Vec<IpNet>,
+    // AUTH_PROXY_SECRET, what an auth proxy in front sends along with X-Forwarded-User
+    pub auth_proxy_secret: Option<String>,
+    // PUSH_WEBHOOK_SECRET, what the git host signs push webhooks with, none are taken if unset
+    pub push_webhook_secret: Option<String>,
+    // TWO_FACTOR_REQUIRED, see TwoFactorPolicy
+    pub two_factor: TwoFactorPolicy,
+}
+
+// How much of a code from the admin's authenticator app each way of being admin is asked for.
+// TWO_FACTOR_REQUIRED is comma separated `role=enforcement`, `session=always,bearer=destructive`,
+// and a role on its own is `always`. Roles not listed keep their defaults.
+#[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct TwoFactorPolicy {
+    // browsers signed in at /api/login, `destructive` if not set and never `never`
+    pub session: Enforcement,
+    // the admin key as a bearer token, `never` if not set, scripts can't answer a code
+    pub bearer: Enforcement,
+    // users the auth proxy signed in, `never` if not set, the proxy has its own sign in
+    pub proxy: Enforcement,
+}
+
+impl Default for TwoFactorPolicy {
+    fn default() -> Self {
+        TwoFactorPolicy {
+            session: Enforcement::Destructive,
+            bearer: Enforcement::Never,
+            proxy: Enforcement::Never,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Enforcement {
+    Never,
+    // only destructive actions need a code, from an elevated session or in X-Otp-Code
+    Destructive,
+    // everything does, even before one is enrolled, when a session can do nothing but enroll
+    Always,
 }
 
 impl Config {
@@ -21,6 +79,22 @@ impl Config {
         let default_timezone = var("TIMEZONE_DEFAULT")?.parse::<i32>()?;
         let sitename = var("SITENAME")?;
         let index_dir = var("INDEX")?;
+        let theme_dir = var("THEME").unwrap_or_else(|_| THEME_DIR.to_string());
+        let bind_address = var("BIND_ADDRESS")?.parse::<SocketAddr>()?;
+        let site_url = site_url(&var("BASE_URL")?)?;
+        let build_interval = match var("BUILD_INTERVAL_MINUTES") {
+            Ok(minutes) => Some(minutes.parse::<u64>()?).filter(|minutes| *minutes > 0),
+            Err(_) => None,
+        };
+        let admin_allow = networks("ADMIN_ALLOW")?;
+        let trusted_proxies = networks("TRUSTED_PROXIES")?;
+        let auth_proxy_secret = var("AUTH_PROXY_SECRET")
+            .ok()
+            .filter(|secret| !secret.is_empty());
+        let push_webhook_secret = var("PUSH_WEBHOOK_SECRET")
+            .ok()
+            .filter(|secret| !secret.is_empty());
+        let two_factor = two_factor_policy()?;
 
         Ok(Config {
             postgres,
@@ -29,7 +103,45 @@ impl Config {
             branch,
             default_timezone,
             sitename,
-            index_dir
+            index_dir,
+            theme_dir,
+            bind_address,
+            site_url,
+            build_interval,
+            admin_allow,
+            trusted_proxies,
+            auth_proxy_secret,
+            push_webhook_secret,
+            two_factor,
+        })
+    }
+
+    // For building without a server, a dry run in ci for example. Only what the build reads is
+    // taken from the environment, BASE_URL falls back to localhost and the rest is left empty.
+    pub fn offline() -> Result<Config> {
+        let default_timezone = match var("TIMEZONE_DEFAULT") {
+            Ok(timezone) => timezone.parse::<i32>()?,
+            Err(_) => 0,
+        };
+        let base_url = var("BASE_URL").unwrap_or_else(|_| "http://localhost/".to_string());
+
+        Ok(Config {
+            postgres: String::new(),
+            admin_key: String::new(),
+            git: String::new(),
+            branch: String::new(),
+            default_timezone,
+            sitename: var("SITENAME").unwrap_or_default(),
+            index_dir: String::new(),
+            theme_dir: var("THEME").unwrap_or_else(|_| THEME_DIR.to_string()),
+            bind_address: SocketAddr::from(([127, 0, 0, 1], 0)),
+            site_url: site_url(&base_url)?,
+            build_interval: None,
+            admin_allow: vec![],
+            trusted_proxies: vec![],
+            auth_proxy_secret: None,
+            push_webhook_secret: None,
+            two_factor: TwoFactorPolicy::default(),
         })
     }
 
@@ -53,10 +165,135 @@ impl Config {
         self.default_timezone
     }
 
+    // TIMEZONE_DEFAULT is the offset from UTC in minutes, so +09:00 is 540
+    pub fn default_offset(&self) -> Result<FixedOffset> {
+        FixedOffset::east_opt(self.default_timezone * 60).ok_or_else(|| {
+            Report::msg(format!(
+                "TIMEZONE_DEFAULT {} is not a valid offset",
+                self.default_timezone
+            ))
+        })
+    }
+
     pub fn sitename(&self) -> &str {
         &self.sitename
     }
-    pub fn srv_large_subdomain(&self) -> bool {
-        self.srv_large_subdomain
+
+    pub fn theme_dir(&self) -> &str {
+        &self.theme_dir
+    }
+
+    pub fn bind_address(&self) -> SocketAddr {
+        self.bind_address
+    }
+
+    pub fn site_url(&self) -> &SiteUrl {
+        &self.site_url
+    }
+
+    pub fn build_interval(&self) -> Option<u64> {
+        self.build_interval
+    }
+
+    pub fn admin_allow(&self) -> &[IpNet] {
+        &self.admin_allow
+    }
+
+    pub fn trusted_proxies(&self) -> &[IpNet] {
+        &self.trusted_proxies
+    }
+
+    pub fn auth_proxy_secret(&self) -> Option<&str> {
+        self.auth_proxy_secret.as_deref()
+    }
+
+    pub fn push_webhook_secret(&self) -> Option<&str> {
+        self.push_webhook_secret.as_deref()
     }
+
+    pub fn two_factor(&self) -> TwoFactorPolicy {
+        self.two_factor
+    }
+}
+
+// comma separated, `10.0.0.0/8, 192.168.1.20`, an address on its own is a network of one
+fn networks(name: &str) -> Result<Vec<IpNet>> {
+    let list = match var(name) {
+        Ok(list) => list,
+        Err(_) => return Ok(vec![]),
+    };
+    list.split(',')
+        .map(str::trim)
+        .filter(|network| !network.is_empty())
+        .map(|network| {
+            network
+                .parse::<IpNet>()
+                .or_else(|_| network.parse::<std::net::IpAddr>().map(IpNet::from))
+                .map_err(|_| Report::msg(format!("{name}: {network} is not a network")))
+        })
+        .collect()
+}
+
+fn two_factor_policy() -> Result<TwoFactorPolicy> {
+    let mut policy = TwoFactorPolicy::default();
+    let list = match var("TWO_FACTOR_REQUIRED") {
+        Ok(list) => list,
+        Err(_) => return Ok(policy),
+    };
+    for entry in list
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let (role, enforcement) = match entry.split_once('=') {
+            Some((role, enforcement)) => (role.trim(), enforcement.trim()),
+            None => (entry, "always"),
+        };
+        let enforcement = match enforcement {
+            "never" => Enforcement::Never,
+            "destructive" => Enforcement::Destructive,
+            "always" => Enforcement::Always,
+            _ => {
+                return Err(Report::msg(format!(
+                    "TWO_FACTOR_REQUIRED: {enforcement} is not never, destructive or always"
+                )))
+            }
+        };
+        match role {
+            // signing in needs a code once one is enrolled anyway
+            "session" if enforcement == Enforcement::Never => {
+                return Err(Report::msg(
+                    "TWO_FACTOR_REQUIRED: sessions are asked for a code at least for destructive \
+                     actions",
+                ))
+            }
+            "session" => policy.session = enforcement,
+            "bearer" => policy.bearer = enforcement,
+            "proxy" => policy.proxy = enforcement,
+            _ => {
+                return Err(Report::msg(format!(
+                    "TWO_FACTOR_REQUIRED: {role} is not session, bearer or proxy"
+                )))
+            }
+        }
+    }
+    Ok(policy)
+}
+
+fn site_url(base_url: &str) -> Result<SiteUrl> {
+    let link_style = match var("LINK_STYLE") {
+        Ok(style) => style.parse::<LinkStyle>()?,
+        Err(_) => LinkStyle::default(),
+    };
+    let route_policy = RoutePolicy {
+        trailing_slash: match var("TRAILING_SLASH") {
+            Ok(policy) => policy.parse::<TrailingSlash>()?,
+            Err(_) => TrailingSlash::default(),
+        },
+        lowercase: match var("LOWERCASE_PATHS") {
+            Ok(lowercase) => lowercase.parse::<bool>()?,
+            Err(_) => false,
+        },
+    };
+    SiteUrl::new(base_url, link_style, route_policy)
 }
@@ -0,0 +1,68 @@
+use crate::config::Config;
+use crate::injest::{
+    build::{build_site, BuiltSite},
+    site::{BuildOptions, SiteMeta},
+    templates::build_site_theme,
+};
+use color_eyre::Result;
+use std::path::PathBuf;
+
+// A build run from another program, a docs site built in its own ci for example. None of the
+// server is involved: nothing is read from or written to the database, the serve dir or the
+// search index, only `output`.
+#[derive(Clone, Debug)]
+pub struct SiteBuild {
+    // the content repo, with its site.toml
+    pub content: PathBuf,
+    pub output: PathBuf,
+    // a theme directory, the one `moklog theme test` takes
+    pub theme: PathBuf,
+    pub config: Config,
+    // instead of `[build]` of site.toml
+    pub options: Option<BuildOptions>,
+}
+
+impl SiteBuild {
+    // the config a dry run uses, see Config::offline
+    pub fn new(
+        content: impl Into<PathBuf>,
+        output: impl Into<PathBuf>,
+        theme: impl Into<PathBuf>,
+    ) -> Result<SiteBuild> {
+        Ok(SiteBuild {
+            content: content.into(),
+            output: output.into(),
+            theme: theme.into(),
+            config: Config::offline()?,
+            options: None,
+        })
+    }
+
+    pub fn options(mut self, options: BuildOptions) -> SiteBuild {
+        self.options = Some(options);
+        self
+    }
+
+    // problems with the content are in the report of what's returned, an Err is for a build that
+    // couldn't run at all
+    pub async fn run(&self) -> Result<BuiltSite> {
+        let mut site = SiteMeta::load(&self.content)?;
+        if let Some(options) = &self.options {
+            site.build = options.clone();
+        }
+        let theme = build_site_theme(self.theme.to_string_lossy()).await?;
+        // the build itself blocks, off of the caller's runtime like a server build
+        let build = self.clone();
+        tokio::task::spawn_blocking(move || {
+            build_site(
+                &build.content,
+                &build.output,
+                &build.config,
+                &site,
+                &theme,
+                None,
+            )
+        })
+        .await?
+    }
+}
@@ -0,0 +1,93 @@
+use crate::injest::report::BuildReport;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+// `[[access]]` in site.toml, who may see a part of the site. Exactly one of `path` and `category`.
+//
+// [[access]]
+// category = "members"
+// roles = ["member", "patron"]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessRule {
+    // a site path prefix, `/members` covers `/members` and everything under it
+    pub path: Option<String>,
+    // a category directory of the content repo, `blog/members`
+    pub category: Option<String>,
+    // any one of them is enough
+    pub roles: BTreeSet<String>,
+}
+
+impl AccessRule {
+    // the site path prefix the rule covers, without a trailing slash
+    pub fn prefix(&self) -> String {
+        let prefix = match (&self.path, &self.category) {
+            (Some(path), _) => path.trim_matches('/'),
+            (None, Some(category)) => category.trim_matches('/'),
+            (None, None) => "",
+        };
+        format!("/{prefix}")
+    }
+
+    pub fn covers(&self, path: &str) -> bool {
+        let prefix = self.prefix();
+        prefix == "/"
+            || path == prefix
+            || path
+                .strip_prefix(&prefix)
+                .map_or(false, |rest| rest.starts_with('/'))
+    }
+
+    pub fn allows(&self, roles: &BTreeSet<String>) -> bool {
+        !self.roles.is_disjoint(roles)
+    }
+}
+
+// the most specific rule covering `path`, None if it's public
+pub fn rule_for<'a>(rules: &'a [AccessRule], path: &str) -> Option<&'a AccessRule> {
+    rules
+        .iter()
+        .filter(|rule| rule.covers(path))
+        .max_by_key(|rule| rule.prefix().len())
+}
+
+// roles end up comma separated in role tokens, and a rule nobody can pass is a mistake
+pub fn validate_rules(
+    rules: &[AccessRule],
+    site_root: &Path,
+    path: &Path,
+    report: &mut BuildReport,
+) {
+    for rule in rules {
+        match (&rule.path, &rule.category) {
+            (Some(_), Some(_)) | (None, None) => report.error(
+                path,
+                format!(
+                    "access rule for {} needs exactly one of `path` and `category`",
+                    rule.prefix()
+                ),
+            ),
+            (None, Some(category)) if !site_root.join(category.trim_matches('/')).is_dir() => {
+                report.error(
+                    path,
+                    format!("access rule for category {category}, which does not exist"),
+                )
+            }
+            _ => {}
+        }
+        if rule.roles.is_empty() {
+            report.error(
+                path,
+                format!(
+                    "access rule for {} has no roles, nobody could see it",
+                    rule.prefix()
+                ),
+            );
+        }
+        for role in rule.roles.iter() {
+            if role.is_empty() || role.contains([',', '.']) || role.contains(char::is_whitespace) {
+                report.error(path, format!("\"{role}\" can't be a role name"));
+            }
+        }
+    }
+}
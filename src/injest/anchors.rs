@@ -0,0 +1,233 @@
+use crate::injest::{links::SiteUrl, report::BuildReport, translation::translated_path, url_path};
+use crate::walker;
+use color_eyre::Result;
+use ignore::WalkBuilder;
+use language_tags::LanguageTag;
+use lol_html::{element, rewrite_str, text, Settings};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+// the ids of a built page and the links in it that point at one
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PageAnchors {
+    pub ids: BTreeSet<String>,
+    // hrefs with a fragment, as written
+    pub links: Vec<String>,
+}
+
+pub fn page_anchors(html: &str) -> Result<PageAnchors> {
+    let page = RefCell::new(PageAnchors::default());
+
+    rewrite_str(
+        html,
+        Settings {
+            element_content_handlers: vec![
+                element!("[id]", |el| {
+                    page.borrow_mut().ids.extend(el.get_attribute("id"));
+                    Ok(())
+                }),
+                // the old way of naming an anchor, still followed by browsers
+                element!("a[name]", |el| {
+                    page.borrow_mut().ids.extend(el.get_attribute("name"));
+                    Ok(())
+                }),
+                element!("a[href*='#']", |el| {
+                    page.borrow_mut().links.extend(el.get_attribute("href"));
+                    Ok(())
+                }),
+            ],
+            ..Settings::default()
+        },
+    )?;
+
+    Ok(page.into_inner())
+}
+
+const HEADINGS_WITH_IDS: &str = "h1[id], h2[id], h3[id], h4[id], h5[id], h6[id]";
+
+// a heading with an id, somewhere a link can point into the middle of a page
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Section {
+    pub id: String,
+    pub title: String,
+    // 1 for an h1
+    pub level: u8,
+    // the page's url and `#id`
+    pub url: String,
+}
+
+// The headings of `html` that have an id, in document order. Ids come from the text of their
+// heading (see slug.rs), so a section's url stays the same from build to build until the
+// heading is reworded.
+pub fn page_sections(html: &str, page_url: &str) -> Result<Vec<Section>> {
+    let sections = RefCell::new(Vec::<Section>::new());
+    rewrite_str(
+        html,
+        Settings {
+            element_content_handlers: vec![
+                element!(HEADINGS_WITH_IDS, |el| {
+                    let id = el.get_attribute("id").unwrap_or_default();
+                    sections.borrow_mut().push(Section {
+                        url: format!("{page_url}#{}", url_escape::encode_fragment(&id)),
+                        id,
+                        title: String::new(),
+                        level: el.tag_name()[1..].parse().unwrap_or(1),
+                    });
+                    Ok(())
+                }),
+                text!(HEADINGS_WITH_IDS, |chunk| {
+                    if let Some(section) = sections.borrow_mut().last_mut() {
+                        section.title.push_str(chunk.as_str());
+                    }
+                    Ok(())
+                }),
+            ],
+            ..Settings::default()
+        },
+    )?;
+
+    Ok(sections
+        .into_inner()
+        .into_iter()
+        .map(|section| Section {
+            title: html_escape::decode_html_entities(&section.title)
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+            ..section
+        })
+        .collect())
+}
+
+// `blog/post/index.html` -> `/blog/post`
+fn site_path(relative: &Path) -> String {
+    let path = format!("/{}", url_path(relative));
+    let path = path
+        .strip_suffix("index.html")
+        .unwrap_or(&path)
+        .trim_end_matches('/');
+    match path {
+        "" => "/".to_string(),
+        path => path.to_string(),
+    }
+}
+
+// the page and fragment `href` points to from the page at `from`, None if it's off the site or
+// has no fragment to check
+fn resolve(urls: &SiteUrl, from: &str, href: &str) -> Option<(String, String)> {
+    let (_, fragment) = href.split_once('#')?;
+    let fragment = url_escape::decode(fragment).to_string();
+    if fragment.is_empty() || fragment == "top" {
+        return None;
+    }
+    // pages are directories, so a relative link starts from inside them
+    let base = Url::parse(&urls.absolute(&format!("{}/", from.trim_end_matches('/')))).ok()?;
+    let target = base.join(href).ok()?;
+    if target.origin() != urls.base().origin() {
+        return None;
+    }
+    Some((
+        site_path(Path::new(urls.strip_base(target.path())?)),
+        fragment,
+    ))
+}
+
+// `/ko/blog/post` and `/blog/post` -> `/blog/post`
+fn untranslated<'a>(path: &'a str, languages: &[LanguageTag]) -> &'a str {
+    let rest = path.trim_start_matches('/');
+    let (first, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    if languages.iter().any(|language| language.as_str() == first) {
+        match rest {
+            "" => "/",
+            _ => &path[first.len() + 1..],
+        }
+    } else {
+        path
+    }
+}
+
+// Checks every `#fragment` link between built pages against the ids of the page it points to.
+// Heading ids come from the text of the heading, so a translation rarely has the ones of its
+// original, and a link that works in one language breaks in another. When the anchor is missing
+// but another language version of the page has it, that's said too.
+pub fn check_anchors(
+    site_output_path: impl AsRef<Path>,
+    urls: &SiteUrl,
+    default_language: &LanguageTag,
+    languages: &[LanguageTag],
+    prefix_default: bool,
+    report: &mut BuildReport,
+) -> Result<()> {
+    let output = site_output_path.as_ref();
+
+    let mut pages: BTreeMap<String, (PathBuf, PageAnchors)> = BTreeMap::new();
+    for entry in walker!(output).build() {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("html") {
+            continue;
+        }
+        let relative = path.strip_prefix(output)?;
+        pages.insert(
+            site_path(relative),
+            (
+                relative.to_path_buf(),
+                page_anchors(&read_to_string(path)?)?,
+            ),
+        );
+    }
+
+    let mut all_languages = vec![default_language.clone()];
+    all_languages.extend(languages.iter().cloned());
+
+    for (from, (file, anchors)) in &pages {
+        for href in &anchors.links {
+            let (target, fragment) = match resolve(urls, from, href) {
+                Some(resolved) => resolved,
+                None => continue,
+            };
+            // a link to a page that wasn't built isn't for this check to report
+            let ids = match pages.get(&target) {
+                Some((_, target_anchors)) => &target_anchors.ids,
+                None => continue,
+            };
+            if ids.contains(&fragment) {
+                continue;
+            }
+
+            let original = untranslated(&target, &all_languages);
+            let elsewhere = all_languages
+                .iter()
+                .map(|language| {
+                    let path =
+                        translated_path(original, language, default_language, prefix_default);
+                    (language, site_path(Path::new(&path)))
+                })
+                .filter(|(_, path)| *path != target)
+                .filter(|(_, path)| {
+                    pages
+                        .get(path)
+                        .map_or(false, |(_, variant)| variant.ids.contains(&fragment))
+                })
+                .map(|(language, path)| format!("{path} ({language})"))
+                .collect::<Vec<_>>();
+
+            let message = if elsewhere.is_empty() {
+                format!("links to {target}#{fragment}, which has no such anchor")
+            } else {
+                format!(
+                    "links to {target}#{fragment}, which has no such anchor, but {} does, \
+                     heading ids differ between languages",
+                    elsewhere.join(", ")
+                )
+            };
+            report.warn(file, message);
+        }
+    }
+    Ok(())
+}
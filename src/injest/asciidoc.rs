@@ -0,0 +1,199 @@
+use crate::injest::{build::SPLITTER, dates::parse_date, diagram::CommandRenderer};
+use chrono::FixedOffset;
+use color_eyre::{Report, Result};
+use serde::{Deserialize, Serialize};
+use toml::{map::Map, Value};
+
+// `[markup.asciidoc]` in site.toml, off unless turned on since it needs asciidoctor installed
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AsciiDocOptions {
+    #[serde(default)]
+    pub enabled: bool,
+    // reads asciidoc on stdin and writes the document without header and footer to stdout
+    #[serde(default = "default_command")]
+    pub command: CommandRenderer,
+}
+
+impl Default for AsciiDocOptions {
+    fn default() -> Self {
+        AsciiDocOptions {
+            enabled: false,
+            command: default_command(),
+        }
+    }
+}
+
+fn default_command() -> CommandRenderer {
+    CommandRenderer {
+        command: "asciidoctor".to_string(),
+        args: ["--embedded", "--out-file", "-", "-"]
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+    }
+}
+
+// what the asciidoc document header says about the page
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AsciiDocHeader {
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    pub date: Option<String>,
+    pub tags: Vec<String>,
+    pub description: Option<String>,
+    // `:moklog-<key>: <value>`, page settings that have no asciidoc attribute
+    pub moklog: Vec<(String, String)>,
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split([',', ';'])
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// `Jane Doe <jane@example.com>; John Doe` -> names only
+fn parse_author_line(line: &str) -> Vec<String> {
+    line.split(';')
+        .map(|author| author.split('<').next().unwrap_or_default().trim())
+        .filter(|author| !author.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// The header is the `= Title` line, the optional author and revision lines right below it, and
+// the `:name: value` attribute entries up to the first blank line.
+pub fn parse_header(document: &str) -> AsciiDocHeader {
+    let mut header = AsciiDocHeader::default();
+    let mut lines = document
+        .lines()
+        .skip_while(|line| line.trim().is_empty() || line.starts_with("//"))
+        .peekable();
+
+    if let Some(title) = lines.peek().and_then(|line| line.strip_prefix("= ")) {
+        header.title = Some(title.trim().to_string());
+        lines.next();
+
+        // implicit author line, then implicit revision line (`v1.0, 2023-04-01: remark`)
+        if let Some(line) = lines.next_if(|line| !line.is_empty() && !line.starts_with(':')) {
+            header.authors = parse_author_line(line);
+            if let Some(revision) = lines.next_if(|line| !line.is_empty() && !line.starts_with(':'))
+            {
+                header.date = revision
+                    .split(',')
+                    .nth(1)
+                    .and_then(|date| date.split(':').next())
+                    .map(|date| date.trim().to_string());
+            }
+        }
+    }
+
+    for line in lines.take_while(|line| !line.trim().is_empty()) {
+        let (name, value) = match line
+            .strip_prefix(':')
+            .and_then(|entry| entry.split_once(':'))
+        {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => continue,
+        };
+        match name {
+            "author" | "authors" => header.authors = split_list(value),
+            "revdate" | "date" => header.date = Some(value.to_string()),
+            "tags" | "keywords" => header.tags = split_list(value),
+            "description" => header.description = Some(value.to_string()),
+            name => {
+                if let Some(key) = name.strip_prefix("moklog-") {
+                    header.moklog.push((key.to_string(), value.to_string()));
+                }
+            }
+        }
+    }
+    header
+}
+
+// toml if it parses as a toml value (`true`, `3`, `["a", "b"]`), a string otherwise
+fn attribute_value(value: &str) -> Value {
+    toml::from_str::<Map<String, Value>>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+// an optional toml front matter, only if the part before the first `===` line really is toml
+fn split_front_matter(data: &str) -> (Option<Map<String, Value>>, &str) {
+    let mut offset = 0;
+    for line in data.split_inclusive('\n') {
+        if line.trim() == SPLITTER {
+            return match toml::from_str::<Map<String, Value>>(&data[..offset]) {
+                Ok(front_matter) => (Some(front_matter), &data[offset + line.len()..]),
+                Err(_) => (None, data),
+            };
+        }
+        offset += line.len();
+    }
+    (None, data)
+}
+
+// An .adoc page as the toml front matter + `===` + body every other page has. The document header
+// fills in whatever the front matter (if there is one) leaves out: title, authors, date and tags of
+// the page type, plus `:moklog-<key>:` for the page settings.
+pub fn normalize_asciidoc(data: &str, offset: &FixedOffset) -> Result<String> {
+    let (front_matter, body) = split_front_matter(data);
+    let mut front_matter = front_matter.unwrap_or_default();
+    let header = parse_header(body);
+
+    for (key, value) in &header.moklog {
+        front_matter
+            .entry(key.clone())
+            .or_insert_with(|| attribute_value(value));
+    }
+
+    // page_type is an externally tagged enum, `[page_type.GenericMeta]`
+    let page_type = front_matter
+        .entry("page_type")
+        .or_insert_with(|| Value::Table(Map::new()));
+    let variants = match page_type {
+        Value::Table(variants) => variants,
+        // `page_type = "None"`
+        _ => {
+            return Ok(format!(
+                "{}{SPLITTER}\n{body}",
+                toml::to_string(&front_matter)?
+            ))
+        }
+    };
+    if variants.is_empty() {
+        variants.insert("GenericMeta".to_string(), Value::Table(Map::new()));
+    }
+    let (variant, meta) = variants.iter_mut().next().unwrap();
+    let variant = variant.clone();
+    let meta = meta
+        .as_table_mut()
+        .ok_or_else(|| Report::msg(format!("page_type.{variant} is not a table")))?;
+
+    if let Some(title) = &header.title {
+        meta.entry("title")
+            .or_insert_with(|| Value::String(title.clone()));
+    }
+    if let Some(date) = &header.date {
+        let date = parse_date(date, offset)
+            .ok_or_else(|| Report::msg(format!("\"{date}\" is not a date")))?;
+        meta.entry("date")
+            .or_insert_with(|| Value::String(date.to_rfc3339()));
+    }
+    for (key, list) in [("authors", &header.authors), ("tags", &header.tags)] {
+        meta.entry(key)
+            .or_insert_with(|| Value::Array(list.iter().cloned().map(Value::String).collect()));
+    }
+    if let (Some(description), "ArticleMeta") = (&header.description, variant.as_str()) {
+        meta.entry("summary")
+            .or_insert_with(|| Value::String(description.clone()));
+    }
+
+    Ok(format!(
+        "{}{SPLITTER}\n{body}",
+        toml::to_string(&front_matter)?
+    ))
+}
@@ -0,0 +1,179 @@
+use crate::injest::{
+    blobs::BlobStore, media::is_media, static_file::fingerprinted_name, svg::SvgOptions,
+};
+use crate::util::{hash_path, same_contents};
+use color_eyre::{Report, Result};
+use dashmap::DashMap;
+use std::fs::{copy, create_dir_all, read_to_string, write};
+use std::path::{Component, Path, PathBuf};
+
+// where every hashed static file ends up, in the output and on the site
+pub const ASSET_DIR: &str = "static";
+
+// files of the content repo that are copied to the site, besides video and audio
+pub const STATIC_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "webp", "avif", "svg", "ico", "css", "js", "woff", "woff2", "ttf",
+    "otf", "txt", "pdf", "zip",
+];
+
+pub fn is_static_file(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => STATIC_EXTENSIONS.contains(&extension) || is_media(path),
+        None => false,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Asset {
+    // the first file seen with this content
+    pub source: PathBuf,
+    // `name-<hash>.ext`, under ASSET_DIR
+    pub output_name: String,
+}
+
+// Every static file of the theme and the content repo, by content. The same image used by ten
+// posts (or shipped by the theme and copied into the content) is hashed once and written once.
+#[derive(Debug, Default)]
+pub struct AssetStore {
+    by_path: DashMap<PathBuf, u64>,
+    by_hash: DashMap<u64, Asset>,
+}
+
+impl AssetStore {
+    pub fn new() -> AssetStore {
+        AssetStore::default()
+    }
+
+    // the content hash of the file, hashing it only the first time it is seen
+    pub fn add(&self, path: impl AsRef<Path>) -> Result<u64> {
+        let path = path.as_ref();
+        if let Some(hash) = self.by_path.get(path) {
+            return Ok(*hash);
+        }
+
+        // streamed, a multi gigabyte video is hashed without ever being in memory
+        let (hash, output_name) = fingerprinted_name(hash_path(path)?, path)
+            .ok_or_else(|| Report::msg(format!("{} has no file extension", path.display())))?;
+        let first = self
+            .by_hash
+            .entry(hash)
+            .or_insert_with(|| Asset {
+                source: path.to_path_buf(),
+                output_name,
+            })
+            .source
+            .clone();
+        // two files with one hash are the same file, unless the hash collided
+        if first != path && !same_contents(&first, path)? {
+            return Err(Report::msg(format!(
+                "{} and {} have the same hash but not the same contents, change a byte of either",
+                first.display(),
+                path.display()
+            )));
+        }
+        self.by_path.insert(path.to_path_buf(), hash);
+        Ok(hash)
+    }
+
+    // `source` is served as `output`, links to it end up at the converted file
+    pub fn add_converted(&self, source: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<u64> {
+        let hash = self.add(output)?;
+        self.by_path.insert(source.as_ref().to_path_buf(), hash);
+        Ok(hash)
+    }
+
+    // the site path the file is served at
+    pub fn site_path(&self, path: impl AsRef<Path>) -> Result<String> {
+        let hash = self.add(path)?;
+        let asset = self.by_hash.get(&hash).unwrap();
+        Ok(format!("/{ASSET_DIR}/{}", asset.output_name))
+    }
+
+    pub fn get(&self, hash: u64) -> Option<Asset> {
+        self.by_hash.get(&hash).map(|asset| asset.clone())
+    }
+
+    // unique files, not references
+    pub fn len(&self) -> usize {
+        self.by_hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_hash.is_empty()
+    }
+
+    // Svgs go through `svg` on the way out, `site_root` is what the trusted paths are relative to.
+    // With `blobs` every file goes into the store and is linked from the output.
+    pub fn write(
+        &self,
+        site_output_path: impl AsRef<Path>,
+        site_root: &Path,
+        svg: &SvgOptions,
+        blobs: Option<&BlobStore>,
+    ) -> Result<()> {
+        let dir = site_output_path.as_ref().join(ASSET_DIR);
+        create_dir_all(&dir)?;
+        for asset in self.by_hash.iter() {
+            let output = dir.join(&asset.output_name);
+            if asset.source.extension().unwrap_or_default() == "svg" {
+                let source = asset
+                    .source
+                    .strip_prefix(site_root)
+                    .unwrap_or(&asset.source);
+                let processed =
+                    svg.process(&read_to_string(&asset.source)?, &source.to_string_lossy())?;
+                match blobs {
+                    Some(blobs) => {
+                        blobs.put_bytes(&asset.output_name, processed.as_bytes())?;
+                        blobs.link(&asset.output_name, &output)?;
+                    }
+                    None => write(output, processed)?,
+                }
+                continue;
+            }
+            if let Some(blobs) = blobs {
+                blobs.put_file(&asset.output_name, &asset.source)?;
+                blobs.link(&asset.output_name, &output)?;
+                continue;
+            }
+            // left by an earlier build, the same name has to be the same file
+            if output.is_file() {
+                if !same_contents(&asset.source, &output)? {
+                    return Err(Report::msg(format!(
+                        "{} would overwrite {}, which has other contents under the same hash",
+                        asset.source.display(),
+                        output.display()
+                    )));
+                }
+                continue;
+            }
+            copy(&asset.source, output)?;
+        }
+        Ok(())
+    }
+}
+
+// A link in a page to a file of the content repo. `/x.png` is from the root of the content repo,
+// anything else is next to the page. None for links that leave the repo.
+pub fn resolve_asset(site_root: &Path, page_source: &Path, link: &str) -> Option<PathBuf> {
+    let link = link.split(['?', '#']).next()?;
+    let relative = match link.strip_prefix('/') {
+        Some(rooted) => PathBuf::from(rooted),
+        None => page_source.parent().unwrap_or(Path::new("")).join(link),
+    };
+
+    let mut normalized = PathBuf::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(site_root.join(normalized))
+}
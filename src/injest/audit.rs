@@ -0,0 +1,103 @@
+use color_eyre::Result;
+use lol_html::{element, rewrite_str, text, Settings};
+use std::cell::RefCell;
+
+struct LinkState {
+    href: String,
+    labelled: bool,
+}
+
+// Accessibility problems in a finished page, one message per problem. Only what can be told from
+// the markup alone: images without alt, links without any text, skipped heading levels, and a
+// missing lang on <html>.
+pub fn accessibility_audit(html: &str) -> Result<Vec<String>> {
+    let findings = RefCell::new(vec![]);
+    let links: RefCell<Vec<LinkState>> = RefCell::new(vec![]);
+    let last_heading = RefCell::new(None::<usize>);
+    let saw_html = RefCell::new(false);
+
+    let settings = Settings {
+        element_content_handlers: vec![
+            element!("html", |el| {
+                *saw_html.borrow_mut() = true;
+                if el
+                    .get_attribute("lang")
+                    .map_or(true, |lang| lang.trim().is_empty())
+                {
+                    findings
+                        .borrow_mut()
+                        .push("<html> has no lang attribute".to_string());
+                }
+                Ok(())
+            }),
+            // alt="" is fine, it marks the image as decorative
+            element!("img:not([alt])", |el| {
+                findings.borrow_mut().push(format!(
+                    "image {} has no alt text",
+                    el.get_attribute("src").unwrap_or_default()
+                ));
+                Ok(())
+            }),
+            element!("a[href]", |el| {
+                let labelled = ["aria-label", "aria-labelledby", "title"]
+                    .iter()
+                    .any(|attr| {
+                        el.get_attribute(attr)
+                            .map_or(false, |value| !value.trim().is_empty())
+                    });
+                links.borrow_mut().push(LinkState {
+                    href: el.get_attribute("href").unwrap_or_default(),
+                    labelled,
+                });
+                Ok(())
+            }),
+            element!("a[href] img[alt]", |el| {
+                if !el
+                    .get_attribute("alt")
+                    .unwrap_or_default()
+                    .trim()
+                    .is_empty()
+                {
+                    if let Some(link) = links.borrow_mut().last_mut() {
+                        link.labelled = true;
+                    }
+                }
+                Ok(())
+            }),
+            text!("a[href]", |txt| {
+                if !txt.as_str().trim().is_empty() {
+                    if let Some(link) = links.borrow_mut().last_mut() {
+                        link.labelled = true;
+                    }
+                }
+                Ok(())
+            }),
+            element!("h1, h2, h3, h4, h5, h6", |el| {
+                let level = el.tag_name()[1..].parse::<usize>().unwrap_or(1);
+                let mut last = last_heading.borrow_mut();
+                if let Some(previous) = *last {
+                    if level > previous + 1 {
+                        findings
+                            .borrow_mut()
+                            .push(format!("heading jumps from h{previous} to h{level}"));
+                    }
+                }
+                *last = Some(level);
+                Ok(())
+            }),
+        ],
+        ..Settings::default()
+    };
+    rewrite_str(html, settings)?;
+
+    let mut findings = findings.into_inner();
+    for link in links.into_inner() {
+        if !link.labelled {
+            findings.push(format!("link to {} has no text", link.href));
+        }
+    }
+    if !saw_html.into_inner() {
+        findings.push("no <html> element to put a lang attribute on".to_string());
+    }
+    Ok(findings)
+}
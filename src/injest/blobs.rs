@@ -0,0 +1,185 @@
+use crate::injest::{
+    assets::ASSET_DIR,
+    manifest::Manifest,
+    retention::{Prunable, PruneReason},
+};
+use crate::util::same_contents;
+use color_eyre::{Report, Result};
+use std::collections::BTreeMap;
+use std::fs::{
+    copy, create_dir_all, hard_link, metadata, read_dir, read_to_string, remove_file, rename, write,
+};
+use std::path::{Path, PathBuf};
+
+// the manifests of the builds that link out of the store, `<build id>.json`
+const MANIFEST_DIR: &str = "manifests";
+
+// Fingerprinted static files, shared by every build. A fingerprinted name is the address of the
+// contents, so a build links its assets out of here instead of copying them, and an asset that
+// didn't change is one file on disk however many builds have it.
+#[derive(Clone, Debug)]
+pub struct BlobStore {
+    dir: PathBuf,
+}
+
+impl BlobStore {
+    pub fn new(dir: impl Into<PathBuf>) -> BlobStore {
+        BlobStore { dir: dir.into() }
+    }
+
+    fn blob(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    // `source` as the blob `name`, unless it's in the store already
+    pub fn put_file(&self, name: &str, source: &Path) -> Result<PathBuf> {
+        let blob = self.blob(name);
+        if blob.is_file() {
+            if !same_contents(source, &blob)? {
+                return Err(collision(source, &blob));
+            }
+            return Ok(blob);
+        }
+        create_dir_all(&self.dir)?;
+        // a build that stops halfway never leaves half a blob under the real name
+        let partial = self.dir.join(format!(".{name}.partial"));
+        copy(source, &partial)?;
+        rename(&partial, &blob)?;
+        Ok(blob)
+    }
+
+    // the same for contents made by the build, a processed svg
+    pub fn put_bytes(&self, name: &str, contents: &[u8]) -> Result<PathBuf> {
+        let blob = self.blob(name);
+        if blob.is_file() {
+            return Ok(blob);
+        }
+        create_dir_all(&self.dir)?;
+        let partial = self.dir.join(format!(".{name}.partial"));
+        write(&partial, contents)?;
+        rename(&partial, &blob)?;
+        Ok(blob)
+    }
+
+    // Hard links the blob to `output`, or copies it where that doesn't work (the store on another
+    // file system). What's at `output` from an earlier build is replaced.
+    pub fn link(&self, name: &str, output: &Path) -> Result<()> {
+        let blob = self.blob(name);
+        if output.is_file() {
+            remove_file(output)?;
+        }
+        if hard_link(&blob, output).is_err() {
+            copy(&blob, output)?;
+        }
+        Ok(())
+    }
+
+    // kept for as long as the build is, see `garbage`
+    pub fn record_manifest(&self, build_id: u64, manifest: &Manifest) -> Result<()> {
+        let dir = self.dir.join(MANIFEST_DIR);
+        create_dir_all(&dir)?;
+        write(
+            dir.join(format!("{build_id}.json")),
+            serde_json::to_string(manifest)?,
+        )?;
+        Ok(())
+    }
+
+    // build id -> its manifest file, oldest first
+    fn manifests(&self) -> Result<BTreeMap<u64, PathBuf>> {
+        let dir = self.dir.join(MANIFEST_DIR);
+        let mut manifests = BTreeMap::new();
+        if !dir.is_dir() {
+            return Ok(manifests);
+        }
+        for entry in read_dir(dir)? {
+            let path = entry?.path();
+            let id = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok());
+            if let Some(id) = id {
+                manifests.insert(id, path);
+            }
+        }
+        Ok(manifests)
+    }
+
+    // blob name -> how many of the manifests of `builds` have it
+    pub fn references(&self, builds: &[u64]) -> Result<BTreeMap<String, usize>> {
+        let prefix = format!("/{ASSET_DIR}/");
+        let mut references = BTreeMap::new();
+        for (id, path) in self.manifests()? {
+            if !builds.contains(&id) {
+                continue;
+            }
+            let manifest = serde_json::from_str::<Manifest>(&read_to_string(&path)?)?;
+            for site_path in manifest.files.keys() {
+                if let Some(name) = site_path.strip_prefix(&prefix) {
+                    *references.entry(name.to_string()).or_insert(0) += 1;
+                }
+            }
+        }
+        Ok(references)
+    }
+
+    // The builds whose manifests are still kept: the ones from `oldest_kept` on, or all of them
+    // without it. The newest is kept whatever `oldest_kept` is, the site is linked to it.
+    pub fn kept_builds(&self, oldest_kept: Option<u64>) -> Result<Vec<u64>> {
+        let manifests = self.manifests()?;
+        let newest = manifests.keys().next_back().copied();
+        Ok(manifests
+            .into_keys()
+            .filter(|id| oldest_kept.map_or(true, |oldest| *id >= oldest) || Some(*id) == newest)
+            .collect())
+    }
+
+    // Blobs no kept build references, the links to them left in `serve_dir`, and the manifests of
+    // builds that aren't kept any more.
+    pub fn garbage(&self, kept: &[u64], serve_dir: &Path) -> Result<Vec<Prunable>> {
+        let mut garbage = vec![];
+        // nothing recorded yet, there's no telling what the site links to
+        if kept.is_empty() {
+            return Ok(garbage);
+        }
+        let references = self.references(kept)?;
+        let mut unreferenced = |path: PathBuf| -> Result<()> {
+            garbage.push(Prunable {
+                bytes: metadata(&path)?.len(),
+                path,
+                reason: PruneReason::Unreferenced,
+            });
+            Ok(())
+        };
+
+        for dir in [self.dir.clone(), serve_dir.join(ASSET_DIR)] {
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in read_dir(dir)? {
+                let path = entry?.path();
+                let name = path.file_name().and_then(|name| name.to_str());
+                match name {
+                    Some(name) if path.is_file() && !references.contains_key(name) => {
+                        unreferenced(path)?
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for (id, path) in self.manifests()? {
+            if !kept.contains(&id) {
+                unreferenced(path)?;
+            }
+        }
+        Ok(garbage)
+    }
+}
+
+fn collision(source: &Path, blob: &Path) -> Report {
+    Report::msg(format!(
+        "{} would be stored as {}, which has other contents under the same hash",
+        source.display(),
+        blob.display()
+    ))
+}
@@ -0,0 +1,84 @@
+use crate::injest::links::SiteUrl;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct Breadcrumb {
+    pub title: String,
+    pub url: String,
+    #[serde(skip)]
+    pub path: String,
+}
+
+// The chain from the site root down to the page at `path`, the page itself last. Ancestors are
+// titled from `titles` (site path -> title), falling back to their directory name.
+pub fn breadcrumbs(
+    path: &str,
+    title: &str,
+    titles: &HashMap<String, String>,
+    urls: &SiteUrl,
+) -> Vec<Breadcrumb> {
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut crumbs = vec![];
+    let mut ancestor = String::from("/");
+    if !segments.is_empty() {
+        if let Some(root) = titles.get(&ancestor) {
+            crumbs.push(Breadcrumb {
+                title: root.clone(),
+                url: urls.link(&ancestor),
+                path: ancestor.clone(),
+            });
+        }
+    }
+
+    for (idx, segment) in segments.iter().enumerate() {
+        if !ancestor.ends_with('/') {
+            ancestor.push('/');
+        }
+        ancestor.push_str(segment);
+
+        let title = if idx + 1 == segments.len() {
+            title.to_string()
+        } else {
+            titles
+                .get(&ancestor)
+                .cloned()
+                .unwrap_or_else(|| segment.to_string())
+        };
+        crumbs.push(Breadcrumb {
+            title,
+            url: urls.link(&ancestor),
+            path: ancestor.clone(),
+        });
+    }
+
+    crumbs
+}
+
+// schema.org BreadcrumbList for the <head>, with absolute urls as search engines want them
+pub fn breadcrumb_json_ld(crumbs: &[Breadcrumb], urls: &SiteUrl) -> Value {
+    let items = crumbs
+        .iter()
+        .enumerate()
+        .map(|(idx, crumb)| {
+            json!({
+                "@type": "ListItem",
+                "position": idx + 1,
+                "name": crumb.title,
+                "item": urls.absolute(&urls.policy().canonical_path(&crumb.path)),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "@context": "https://schema.org",
+        "@type": "BreadcrumbList",
+        "itemListElement": items,
+    })
+}
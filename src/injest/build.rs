@@ -1,33 +1,82 @@
+use crate::config::Config;
+use crate::plugin::{rhai::stdlib::ScriptStdlib, wasm::WasmPlugins};
 use crate::injest::{
-    path_relativizie_path,
+    anchors::check_anchors,
+    asciidoc::normalize_asciidoc,
+    assets::{is_static_file, AssetStore},
+    blobs::BlobStore,
+    bundle::{build_bundles, write_bundles},
+    codeblock::CODEBLOCK_TEMPLATE,
+    diagram::Diagrams,
+    docs::{DocsPage, DocsTree, DocsTrees},
+    downloads::{collect_downloads, write_downloads, DOWNLOADS_DIR},
+    dynamic::{write_dynamic_pages, DynamicPage, DynamicPages},
+    ebook::{book_parts, write_ebook, Book, BookPart},
+    errors::write_error_pages,
+    file_handler::handle_file,
+    fonts::subset_fonts,
+    fragment::{cache_blocks, register_fragment_cache, FragmentCache},
+    generate::{
+        build_generic, build_notebook, build_prebuilt, CoreBuildStuffs, Custom, MarkdownOptions,
+        PageAccess, PageHeader, PageMeta, PageTypeMeta, PrebuiltMeta,
+    },
+    graph::{write_link_graph, LinkGraph},
+    check::MOKLOG_FILE,
+    history::file_edit_times,
+    hooks::{EmittedFile, PageHooks},
+    limits::Deadline,
+    lint::lint_markdown,
+    listing::{listing_place, validate_pinned, Comparator, Listing, ListingEntry},
+    locale::register_locale_filters,
+    manifest::{build_manifest, write_manifest, Manifest},
+    markup::Markup,
+    notebook::{is_sidecar, sidecar_path, Notebook, NotebookMeta},
+    path_relativizie, path_relativizie_path,
+    report::{BuildReport, Severity},
+    redirect::{redirect_from_config, write_redirect_page, NavLink, RedirectEntry},
+    site::{SiteMeta, SITE_FILE},
+    slug::{register_slug_filter, SlugStrategy},
+    social_card::SocialCards,
+    spellcheck::SpellChecker,
+    static_file::hash_file,
+    tags::{report_near_duplicates, TagMap},
+    template_check::check_template_variables,
     templates::SiteTheme,
+    theme_docs::{ThemeCalls, ThemeItemKind},
+    translation::{resolve_translation, translated_path, translation_status},
+    validate::HtmlValidation,
+    versions::{VersionPage, VersionTree, VersionTrees},
+    wikilinks::{wikilinks, PageLinks, Resolution, WikiIndex, WikiPage},
 };
 use bidirectional_map::Bimap;
 use color_eyre::{Report, Result};
 use id_tree::{InsertBehavior, Node, RemoveBehavior, Tree};
 use ignore::WalkBuilder;
 use itertools::Itertools;
-use memmap2::MmapOptions;
-use rhai::{Engine, EvalAltResult, Scope, AST};
+use rhai::serde::{from_dynamic, to_dynamic};
+use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::cell::RefCell;
+use std::borrow::Cow;
 use std::path::PathBuf;
 use std::process::Command;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::{collections::HashMap, path::Path, str::FromStr};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::str::from_utf8;
 use axum::body::HttpBody;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, FixedOffset, Utc};
 use dashmap::DashMap;
 use language_tags::LanguageTag;
 use tera::{Context, Filter, Function, Tera};
 use tera::{Test, Value};
-use tracing::log::{error, log, warn};
-use crate::injest::static_file::{process_static_file};
-use crate::{mmap_load, walker};
+use tracing::log::{error, info, warn};
+use crate::injest::config_meta::ConfigMeta;
+use crate::util::{file_prefix, read_source};
+use crate::{walker, CACHE_DIR};
 
-#[derive(Clone, Debug, PartialOrd, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialOrd, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct BuildInformation {
     pub initiated: String,
     pub id: u64,
@@ -36,13 +85,15 @@ pub struct BuildInformation {
     pub status: BuildStatus,
 }
 
-#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum BuildStatus {
     Running,
     Succeeded,
     Failed,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ConfigurationType {
     Category,
     SubCategory,
@@ -61,37 +112,41 @@ pub enum ExternalType {
 struct RhaiFilter {
     engine: Engine,
     script: AST,
-    times_exec: AtomicU64,
+    times_exec: Arc<AtomicU64>,
 }
 
 impl Filter for RhaiFilter {
     fn filter(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
         let mut scope = Scope::new();
-        let exectimes = self.times_exec.load(Ordering::SeqCst);
+        let exectimes = self.times_exec.load(Ordering::SeqCst) as i64;
+        let value = to_dynamic(value).map_err(script_error)?;
+        let args = to_dynamic(args).map_err(script_error)?;
         let result = self
             .engine
-            .call_fn::<Value>(&mut scope, &self.script, "filter", (value, args, exectimes))
-            .map_err(|why| Err(tera::Error::msg(why.to_string())))?;
+            .call_fn::<Dynamic>(&mut scope, &self.script, "filter", (value, args, exectimes))
+            .map_err(script_error)?;
         self.times_exec.fetch_add(1, Ordering::SeqCst);
 
-        Ok(result)
+        from_dynamic(&result).map_err(script_error)
     }
 }
 
 struct RhaiTester {
     engine: Engine,
     script: AST,
-    times_exec: AtomicU64,
+    times_exec: Arc<AtomicU64>,
 }
 
 impl Test for RhaiTester {
     fn test(&self, value: Option<&Value>, args: &[Value]) -> tera::Result<bool> {
         let mut scope = Scope::new();
-        let exectimes = self.times_exec.load(Ordering::SeqCst);
+        let exectimes = self.times_exec.load(Ordering::SeqCst) as i64;
+        let value = to_dynamic(value).map_err(script_error)?;
+        let args = to_dynamic(args).map_err(script_error)?;
         let result = self
             .engine
-            .call_fn::<Value>(&mut scope, &self.script, "test", (value, args, exectimes))
-            .map_err(|why| Err(tera::Error::msg(why.to_string())))?;
+            .call_fn::<bool>(&mut scope, &self.script, "test", (value, args, exectimes))
+            .map_err(script_error)?;
         self.times_exec.fetch_add(1, Ordering::SeqCst);
 
         Ok(result)
@@ -101,26 +156,32 @@ impl Test for RhaiTester {
 struct RhaiFunction {
     engine: Engine,
     script: AST,
-    times_exec: AtomicU64,
+    times_exec: Arc<AtomicU64>,
 }
 
 impl Function for RhaiFunction {
     fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
         let mut scope = Scope::new();
-        let exectimes = self.times_exec.load(Ordering::SeqCst);
+        let exectimes = self.times_exec.load(Ordering::SeqCst) as i64;
+        let args = to_dynamic(args).map_err(script_error)?;
         let result = self
             .engine
-            .call_fn::<Value>(&mut scope, &self.script, "main", (args, exectimes))
-            .map_err(|why| Err(tera::Error::msg(why.to_string())))?;
+            .call_fn::<Dynamic>(&mut scope, &self.script, "main", (args, exectimes))
+            .map_err(script_error)?;
         self.times_exec.fetch_add(1, Ordering::SeqCst);
 
-        Ok(result)
+        from_dynamic(&result).map_err(script_error)
     }
 }
 
+// scripts take and give back rhai values, tera wants json
+fn script_error(why: impl ToString) -> tera::Error {
+    tera::Error::msg(why.to_string())
+}
+
 struct Shortcode {
-    tera: RefCell<Tera>,
-    times_exec: AtomicU64,
+    tera: Tera,
+    times_exec: Arc<AtomicU64>,
 }
 
 impl Function for Shortcode {
@@ -130,21 +191,23 @@ impl Function for Shortcode {
             ctx.insert(name, arg)
         }
         ctx.insert("times", &self.times_exec.load(Ordering::SeqCst));
-        let mut tera = self.tera.borrow_mut();
-        let render = tera.render_str("shortcode", &ctx)?;
+        let render = self.tera.render("shortcode", &ctx)?;
         self.times_exec.fetch_add(1, Ordering::SeqCst);
         Ok(Value::String(render))
     }
 }
 
 fn shell(cmd: &str) -> Result<(i32, String, String), Box<EvalAltResult>> {
-    if cmd == "" {
+    if cmd.is_empty() {
         return Err("Bad Command!".into());
     }
-    let exec = cmd.split_once(" ");
-    let mut command = match exec {
+    let mut command = match cmd.split_once(' ') {
         None => Command::new(cmd),
-        Some((c, a)) => Command::new(c).arg(a),
+        Some((c, a)) => {
+            let mut command = Command::new(c);
+            command.arg(a);
+            command
+        }
     };
     let out = match command.output() {
         Ok(out) => out,
@@ -156,24 +219,24 @@ fn shell(cmd: &str) -> Result<(i32, String, String), Box<EvalAltResult>> {
     let out_stderr = String::from_utf8(out.stderr).unwrap_or_default();
     let out_code = match out.status.code() {
         Some(c) => c,
-        None => i32::MIN_VALUE,
+        None => i32::MIN,
     };
     Ok((out_code, out_stdout, out_stderr))
 }
 
 fn log(out: &str) {
-    log!(out)
+    info!("{out}")
 }
 
 fn warn(out: &str) {
-    warn!(out)
+    warn!("{out}")
 }
 
 fn error(out: &str) {
-    error!(out)
+    error!("{out}")
 }
 
-const IGNORES: &'static [&str] = &["build.rhai"];
+const IGNORES: &'static [&str] = &["build.rhai", SITE_FILE];
 
 fn file_name_from_path(path: impl AsRef<Path>) -> Option<&str> {
     match path.as_ref().file_name() {
@@ -190,6 +253,14 @@ pub enum LeafPathType {
     Moklog,
     Page,
     PreBuilt,
+    Notebook,
+}
+
+// what a page source is rendered from once its header is read
+enum Page<'a> {
+    Markup(PageTypeMeta, &'a str),
+    Prebuilt(PrebuiltMeta, &'a str),
+    Notebook(Notebook, NotebookMeta),
 }
 
 pub struct LeafPath<T> where T: AsRef<[u8]> {
@@ -233,25 +304,144 @@ pub struct FilePath {
     is_file: bool,
 }
 
-const RESERVED_NAMES: &[&str] = &["template", "files", "static", "admin", "user", "me", "api", "stat", "error", "feed"];
+const RESERVED_NAMES: &[&str] = &["template", "files", "static", "admin", "user", "me", "api", "stat", "error", "feed", "plugins", "healthz", "readyz", DOWNLOADS_DIR];
 
-const RESERVED_CHARS: &[char] = &[
+pub const RESERVED_CHARS: &[char] = &[
     '{' , '}' , '|' , '\\' , '^' ,'[' , ']' , '`',
     ';' , '/' , '?' , ':' , '@' , '&' , '=' , '+' , '$' , ',',
     ' ', '<' , '>' , '#' , '%' , '"', '\''
 ];
 
-const SPLITTER: &str = "===";
+pub const SPLITTER: &str = "===";
+
+// the theme's templates with its rhai filters, testers, functions and shortcodes registered
+pub fn theme_tera(
+    template: &SiteTheme,
+    default_language: &LanguageTag,
+    offset: FixedOffset,
+    slugs: SlugStrategy,
+    stdlib: &ScriptStdlib,
+    calls: &ThemeCalls,
+    fragments: &FragmentCache,
+) -> Result<Tera> {
+    let mut templates = vec![];
+    for source in template.tera_templates.iter() {
+        templates.extend(cache_blocks(source.key(), source.value())?);
+    }
+    let mut tera = Tera::default();
+    tera.add_raw_templates(templates)?;
+    register_locale_filters(&mut tera, default_language, offset);
+    register_slug_filter(&mut tera, slugs);
+
+    for filter in template.filters.iter() {
+        let mut engine = Engine::new();
+        stdlib.register(&mut engine);
+        let script = engine.compile(filter.value())?;
+        tera.register_filter(
+            filter.key(),
+            RhaiFilter {
+                engine,
+                script,
+                times_exec: calls.counter(ThemeItemKind::Filter, filter.key()),
+            },
+        )
+    }
+
+    for test in template.testers.iter() {
+        let mut engine = Engine::new();
+        stdlib.register(&mut engine);
+        let script = engine.compile(test.value())?;
+        tera.register_tester(
+            test.key(),
+            RhaiTester {
+                engine,
+                script,
+                times_exec: calls.counter(ThemeItemKind::Tester, test.key()),
+            },
+        )
+    }
+
+    for function in template.functions.iter() {
+        let mut engine = Engine::new();
+        stdlib.register(&mut engine);
+        let script = engine.compile(function.value())?;
+        tera.register_function(
+            function.key(),
+            RhaiFunction {
+                engine,
+                script,
+                times_exec: calls.counter(ThemeItemKind::Function, function.key()),
+            },
+        )
+    }
+
+    for shortcode in template.shortcode.iter() {
+        let mut shortcode_tera = Tera::default();
+        shortcode_tera.add_raw_template("shortcode", shortcode.value())?;
+        tera.register_function(
+            shortcode.key(),
+            Shortcode {
+                tera: shortcode_tera,
+                times_exec: calls.counter(ThemeItemKind::Shortcode, shortcode.key()),
+            },
+        )
+    }
+    register_fragment_cache(&mut tera, fragments);
+
+    Ok(tera)
+}
+
+// everything the build produces that has to be persisted by the caller
+pub struct BuiltSite {
+    pub redirects: Vec<RedirectEntry>,
+    // site path of every page to the hash of its sources, for telling what changed
+    pub pages: BTreeMap<String, String>,
+    // only the pages that are unlisted, private or not published yet
+    pub access: BTreeMap<String, PageAccess>,
+    // pages of frozen versions, see versions.rs
+    pub frozen: BTreeSet<String>,
+    pub manifest: Manifest,
+    pub report: BuildReport,
+    // how often the theme's scripts ran
+    pub calls: ThemeCalls,
+    // redirect entries listed in each category, by the category's path
+    pub nav_links: BTreeMap<String, Vec<NavLink>>,
+    // the pages of each category in listing and feed order, by the category's directory
+    pub listings: BTreeMap<PathBuf, Listing>,
+}
 
 pub fn build_site(
     site_build_path: impl AsRef<Path>,
     site_output_path: impl AsRef<Path>,
+    config: &Config,
     site_config: &SiteMeta,
     template: &SiteTheme,
-) -> Result<()> {
+    // where the assets are linked from, they're copied into the output without one
+    blobs: Option<&BlobStore>,
+) -> Result<BuiltSite> {
+    let mut report = BuildReport::new();
+    let limits = &site_config.limits;
+    let deadline = Deadline::start(limits);
+    let site_variables = site_config.variables(
+        config,
+        &template.metadata,
+        &site_build_path.as_ref().join(SITE_FILE),
+        &mut report,
+    );
+
+    let mut stdlib = ScriptStdlib::new(
+        &site_config.scripts,
+        &template.metadata.name,
+        config.default_offset()?,
+    )?;
+    stdlib.limit(limits, deadline);
+
     // run site build script
     let mut engine = Engine::new();
-    engine.register_fn("shell", shell);
+    stdlib.register(&mut engine);
+    if stdlib.allow_shell() {
+        engine.register_fn("shell", shell);
+    }
     engine.register_fn("log", log);
     engine.register_fn("warn", warn);
     engine.register_fn("error", error);
@@ -276,14 +466,19 @@ pub fn build_site(
     let mut fs_path_store = Bimap::new();
     let mut fs_root_id = None;
 
-    let mut files = DashMap::new();
+    // theme and content files share one store, so a file both of them ship is written once
+    let assets = AssetStore::new();
 
-    for (hash, file) in template.files.iter().map(|x| (*x.key(), x.value().clone())) {
-        files.insert(hash, path_relativizie_path(&site_build_path, file.path));
+    for file in template.files.iter() {
+        if let Err(why) = assets.add(&file.value().path) {
+            warn!("failed to add theme file {}: {why}", file.value().file_name);
+        }
     }
 
 
+    let mut page_sources = 0;
     for file in sitebuild_traveller.build() {
+        deadline.check("while reading the content")?;
         let depth = file?.depth();
         let file = path_relativizie_path(&site_build_path, file?.into_path())?;
 
@@ -322,12 +517,16 @@ pub fn build_site(
             }
         };
 
+        if IGNORES.contains(&filename) {
+            continue;
+        }
+
         let file_extension = match file.extension().map(|x| x.to_str()).flatten() {
             Some(ext) => ext,
             None => return Err(Report::msg("non utf8 filename")),
         };
 
-        let file_nonext = match file.file_prefix().map(|x| x.to_str()).flatten() {
+        let file_nonext = match file_prefix(&file) {
             Some(ext) => ext,
             None => return Err(Report::msg("non utf8 filename")),
         };
@@ -339,15 +538,46 @@ pub fn build_site(
             };
 
             let path_type = match file_extension {
-                "md" => LeafPathType::Page,
+                "md" | "org" | "rst" => LeafPathType::Page,
+                "adoc" if site_config.markup.asciidoc.enabled => LeafPathType::Page,
                 "html" => LeafPathType::PreBuilt,
                 "moklog" => LeafPathType::Moklog,
-                _ => continue,
+                "ipynb" => LeafPathType::Notebook,
+                // front matter of a notebook, read when the notebook is built
+                "toml" if is_sidecar(&site_build_path.as_ref().join(&file)) => continue,
+                _ if is_static_file(&file) => {
+                    if let Err(why) = assets.add(site_build_path.as_ref().join(&file)) {
+                        warn!("failed to hash {}: {why}", file.display());
+                    }
+                    continue;
+                }
+                _ => {
+                    match handle_file(&site_config.files, &site_build_path.as_ref().join(&file), &assets) {
+                        Ok(true) => {}
+                        Ok(false) => report.warn(
+                            &file,
+                            format!("unknown file format, add a handler for .{file_extension} under [files] in {SITE_FILE}"),
+                        ),
+                        Err(why) => report.error(&file, format!("failed to process: {why}")),
+                    }
+                    continue;
+                }
             };
 
-            let filemap: Box<[u8]>  = mmap_load!(&file);
+            if path_type != LeafPathType::Moklog {
+                page_sources += 1;
+                limits.check_pages(page_sources)?;
+            }
+            let filemap = read_source(site_build_path.as_ref().join(&file), limits.file_bytes)?;
+            // the asciidoc document header becomes front matter like every other page has
+            let filemap = match file_extension {
+                "adoc" => normalize_asciidoc(from_utf8(&filemap)?, &config.default_offset()?)?
+                    .into_bytes()
+                    .into_boxed_slice(),
+                _ => filemap,
+            };
 
-            if ["index.md", "index.html", ".moklog"].contains(&filename) {
+            if ["index.md", "index.org", "index.rst", "index.adoc", "index.html", "index.ipynb", ".moklog"].contains(&filename) {
                 let parent_node = fs_tree.get_mut(parent)?;
 
                 let data = parent_node.data_mut();
@@ -359,7 +589,7 @@ pub fn build_site(
                         translations: Default::default(),
                     }
                 );
-            } else if file_extension == "md" || file_extension == "html" || file_extension == "moklog" {
+            } else if ["md", "org", "rst", "adoc", "html", "ipynb", "moklog"].contains(&file_extension) {
                 if let Ok(lang_tag) = LanguageTag::parse(file_nonext) {
                     // get parent
                     let parent_node = fs_tree.get_mut(parent)?;
@@ -378,15 +608,6 @@ pub fn build_site(
                 } else {
                     warn!("orphan file!");
                 }
-            } else {
-                match process_static_file(file) {
-                    Some(file) => {
-                        files.insert(file.0, file.1);
-                    }
-                    None => {
-                        warn!("failed to hash file!")
-                    }
-                }
             }
         } else {
             if let Ok(_) = LanguageTag::parse(filename) {
@@ -411,63 +632,120 @@ pub fn build_site(
 
     // start actual sitebuild
 
-    let mut tera = Tera::default();
-    tera.add_raw_templates(template.tera_templates.iter())?;
-
-    for filter in template.filters.iter() {
-        let engine = Engine::new();
-        let script = engine.compile(filter.value())?;
-        tera.register_filter(
-            filter.key(),
-            RhaiFilter {
-                engine,
-                script,
-                times_exec: AtomicU64::new(0),
-            },
-        )
-    }
-
-    for test in template.testers.iter() {
-        let engine = Engine::new();
-        let script = engine.compile(test.value())?;
-        tera.register_tester(
-            test.key(),
-            RhaiTester {
-                engine,
-                script,
-                times_exec: AtomicU64::new(0),
-            },
-        )
-    }
-
-    for function in template.functions.iter() {
-        let engine = Engine::new();
-        let script = engine.compile(function.value())?;
-        tera.register_function(
-            function.key(),
-            RhaiFunction {
-                engine,
-                script,
-                times_exec: AtomicU64::new(0),
-            },
-        )
-    }
-
-    for shortcode in template.shortcode.iter() {
-        let mut tera = Tera::default();
-        tera.add_raw_template("shortcode", shortcode.value())?;
-        tera.register_function(
-            shortcode.key(),
-            Shortcode {
-                tera: RefCell::new(tera),
-                times_exec: AtomicU64::new(0),
-            },
-        )
+    template
+        .metadata
+        .colors
+        .validate(&template.metadata.name, &mut report);
+    let bundles = build_bundles(template, &mut report);
+    write_bundles(&site_output_path, &bundles)?;
+
+    let diagrams = Diagrams::new(
+        &site_config.diagrams,
+        Some(Path::new(CACHE_DIR).join("diagrams")),
+    );
+
+    let calls = ThemeCalls::default();
+    // a fragment is rendered once per build, its ttl only matters for dynamic pages
+    let tera = theme_tera(
+        template,
+        &site_config.default_language(),
+        config.default_offset()?,
+        site_config.slugs.strategy,
+        &stdlib,
+        &calls,
+        &FragmentCache::new(),
+    )?;
+    check_template_variables(
+        template,
+        &tera,
+        site_config.build.template_variables,
+        &mut report,
+    )?;
+
+    let plugins = WasmPlugins::load(
+        site_build_path.as_ref(),
+        &site_config.wasm_plugins,
+        limits.plugin_fuel,
+        &mut report,
+    )?;
+    let hooks = PageHooks::new(template, &stdlib);
+    let social_cards = match &site_config.build.social_cards {
+        Some(options) => {
+            match SocialCards::new(options, site_build_path.as_ref(), &site_variables.title) {
+                Ok(cards) => Some(cards),
+                Err(why) => {
+                    report.error(
+                        site_build_path.as_ref().join(SITE_FILE),
+                        format!("social cards are off: {why}"),
+                    );
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    let emitted = std::sync::Mutex::new(Vec::<EmittedFile>::new());
+
+    let downloads = collect_downloads(&site_build_path, config.site_url(), &mut report)?;
+    write_downloads(
+        &site_build_path,
+        &site_output_path,
+        &downloads,
+        &tera,
+        &site_variables,
+    )?;
+
+    write_error_pages(&site_output_path, &tera, &site_variables)?;
+
+    let mut comparators = HashMap::new();
+    for comparator in template.comparators.iter() {
+        comparators.insert(comparator.key().clone(), Comparator::new(comparator.value())?);
     }
 
     let mut categories = HashMap::new();
     let mut category_subcat_map = HashMap::new();
     let mut sub_categories = HashMap::new();
+    let mut nav_links = HashMap::new();
+    let mut titles = HashMap::from([("/".to_string(), site_variables.title.clone())]);
+    let mut redirects = vec![];
+    let mut pages = BTreeMap::new();
+    let mut access = BTreeMap::new();
+    let mut dynamic_pages = DynamicPages::new();
+    // category directory -> its listed pages, for the categories that turn out to be docs
+    let mut docs_pages: HashMap<String, Vec<DocsPage>> = HashMap::new();
+    // category directory -> its pages a directory deeper, for the categories that are versioned
+    let mut version_pages: HashMap<String, Vec<VersionPage>> = HashMap::new();
+    // directory -> the listed pages in it, for the directories that turn out to be categories
+    let mut listing_entries: HashMap<PathBuf, Vec<ListingEntry>> = HashMap::new();
+    // every page for wikilinks to point at, and the markdown of the ones that can link
+    let mut wiki_pages = vec![];
+    let mut link_sources = vec![];
+    let mut page_tags = BTreeMap::new();
+    // completed series and every listed page that can be a part of one, for `[build.ebooks]`
+    let mut series = vec![];
+    let mut book_pages = vec![];
+    let mut lint_failed = false;
+    let tag_map = TagMap::load(
+        &site_config.tags,
+        site_build_path.as_ref(),
+        &site_build_path.as_ref().join(SITE_FILE),
+        &mut report,
+    )?;
+    // canonical tag -> the pages using it
+    let mut tag_uses: BTreeMap<String, BTreeSet<PathBuf>> = BTreeMap::new();
+    let mut spellchecker = match &site_config.build.spellcheck {
+        Some(options) => Some(SpellChecker::new(options, site_build_path.as_ref())?),
+        None => None,
+    };
+    if site_config.prefix_default_language {
+        // nothing lives at the root itself anymore
+        let to = translated_path("/", &site_config.default_language(), &site_config.default_language(), true);
+        redirects.push(RedirectEntry {
+            from: "/".to_string(),
+            to: config.site_url().link(&to),
+            permanent: false,
+        });
+    }
 
 
     if let Some(fs_rid) = fs_root_id {
@@ -489,7 +767,212 @@ pub fn build_site(
             }
         }
 
-        for possible_category in sitebuild_traveller.max_depth(Some(2)).build() {
+        // which pages are missing which translations, and which translations fell behind
+        let expected_languages = site_config.expected_languages();
+        let edit_times = match file_edit_times(&site_build_path) {
+            Ok(times) => times,
+            Err(why) => {
+                warn!("no edit history, not checking for stale translations: {why}");
+                HashMap::new()
+            }
+        };
+        for file_id in fs_tree.traverse_level_order_ids(&fs_rid)? {
+            deadline.check("while reading page headers")?;
+            let data = match &fs_tree.get(&file_id).unwrap().data().data {
+                Some(data) if data.typ == LeafPathType::Page => data,
+                _ => continue,
+            };
+            // `blog/post/index.md` is `/blog/post`, changing any of its translations changes it
+            let mut sources = data
+                .translations
+                .iter()
+                .map(|(language, leaf)| (language.to_string(), &*leaf.data))
+                .collect::<Vec<_>>();
+            sources.sort();
+            let mut hashed = data.data.to_vec();
+            for (language, source) in sources {
+                hashed.extend_from_slice(language.as_bytes());
+                hashed.extend_from_slice(source);
+            }
+            // a header that doesn't parse is reported when the page is built
+            let header = from_utf8(&data.data)
+                .map_err(Report::new)
+                .and_then(|source| PageHeader::parse(source, &config.default_offset()?));
+            let site_path = site_config.slugs.strategy.page_path(
+                data.true_path.parent().unwrap_or(Path::new("")),
+                header.as_ref().ok().and_then(|header| header.page.slug.as_deref()),
+            );
+            if let Ok(header) = header {
+                let page_access = header.page.access(&site_config.workflow);
+                if page_access != PageAccess::default() {
+                    access.insert(site_path.clone(), page_access);
+                }
+                let tags = tag_map.canonical_list(header.page_type.tags());
+                for tag in &tags {
+                    tag_uses.entry(tag.clone()).or_default().insert(data.true_path.clone());
+                }
+                page_tags.insert(site_path.clone(), tags);
+                let category = data
+                    .true_path
+                    .parent()
+                    .and_then(|dir| dir.components().next())
+                    .and_then(|category| category.as_os_str().to_str());
+                let mut dirs = data
+                    .true_path
+                    .parent()
+                    .into_iter()
+                    .flat_map(Path::components)
+                    .filter_map(|dir| dir.as_os_str().to_str());
+                if let (Some(category), Some(version)) = (dirs.next(), dirs.next()) {
+                    version_pages
+                        .entry(category.to_string())
+                        .or_default()
+                        .push(VersionPage {
+                            version: version.to_string(),
+                            root: site_config
+                                .slugs
+                                .strategy
+                                .page_path(&Path::new(category).join(version), None),
+                            path: site_path.clone(),
+                        });
+                }
+                let title = header
+                    .page_type
+                    .title()
+                    .unwrap_or_else(|| site_path.rsplit('/').next().unwrap_or_default());
+                wiki_pages.push(WikiPage {
+                    path: site_path.clone(),
+                    title: title.to_string(),
+                });
+                if let (Some((dir, slug)), true) =
+                    (listing_place(&data.true_path), header.page.is_listed())
+                {
+                    listing_entries
+                        .entry(dir.to_path_buf())
+                        .or_default()
+                        .push(ListingEntry {
+                            slug: slug.to_string(),
+                            title: title.to_string(),
+                            url: config.site_url().link(&site_path),
+                            date: header.page_type.date(),
+                            weight: header.page.weight,
+                            pinned: false,
+                        });
+                }
+                if data.true_path.extension().map_or(false, |extension| extension == "md") {
+                    let source = String::from_utf8_lossy(&data.data);
+                    if let Some((_, body)) = source.split_once(SPLITTER) {
+                        let body = body.to_string();
+                        link_sources.push((site_path.clone(), data.true_path.clone(), body));
+                    }
+                }
+                if let (Some(markup), true) =
+                    (Markup::from_path(&data.true_path), header.page.is_listed())
+                {
+                    let source = String::from_utf8_lossy(&data.data);
+                    if let Some((_, body)) = source.split_once(SPLITTER) {
+                        book_pages.push(BookPart {
+                            path: site_path.clone(),
+                            source: data.true_path.clone(),
+                            markup,
+                            title: title.to_string(),
+                            date: header.page_type.date(),
+                            weight: header.page.weight,
+                            body: body.to_string(),
+                        });
+                    }
+                }
+                if let PageTypeMeta::SeriesMeta(meta) = &header.page_type {
+                    if !meta.on_going && header.page.is_listed() {
+                        series.push((site_path.clone(), data.true_path.clone(), meta.clone()));
+                    }
+                }
+                if let (Some(category), true) = (category, header.page.is_listed()) {
+                    docs_pages
+                        .entry(category.to_string())
+                        .or_default()
+                        .push(DocsPage {
+                            path: site_path.clone(),
+                            title: title.to_string(),
+                            weight: header.page.weight,
+                        });
+                }
+                // rendered by the server when they're requested, there's no file to write
+                if let PageTypeMeta::DynamicMeta(meta) = &header.page_type {
+                    let source = String::from_utf8_lossy(&data.data);
+                    dynamic_pages
+                        .insert(site_path.clone(), DynamicPage::new(meta, &header, &source));
+                }
+            }
+            // two names that transliterate alike, or a slug that's another page's
+            if pages.contains_key(&site_path) {
+                report.error(
+                    &data.true_path,
+                    format!("{site_path} is the url of another page too"),
+                );
+            }
+            pages.insert(site_path, format!("{:016x}", hash_file(&hashed)));
+            if let Some(spellchecker) = &mut spellchecker {
+                let default_language = site_config.default_language();
+                let sources = std::iter::once((&data.true_path, &*data.data, &default_language))
+                    .chain(
+                        data.translations
+                            .iter()
+                            .map(|(language, leaf)| (&leaf.true_path, &*leaf.data, language)),
+                    );
+                for (source_path, source, language) in sources {
+                    if source_path.extension().map_or(true, |extension| extension != "md") {
+                        continue;
+                    }
+                    if let Ok(source) = from_utf8(source) {
+                        spellchecker.check(source_path, source, language, &mut report);
+                    }
+                }
+            }
+            if let Some(lint) = &site_config.build.lint {
+                let category = data
+                    .true_path
+                    .parent()
+                    .and_then(|dir| dir.components().next())
+                    .and_then(|category| category.as_os_str().to_str());
+                let sources = std::iter::once((&data.true_path, &*data.data)).chain(
+                    data.translations
+                        .values()
+                        .map(|leaf| (&leaf.true_path, &*leaf.data)),
+                );
+                for (source_path, source) in sources {
+                    if source_path.extension().map_or(true, |extension| extension != "md") {
+                        continue;
+                    }
+                    let source = match from_utf8(source) {
+                        Ok(source) => source,
+                        Err(_) => continue,
+                    };
+                    for finding in lint_markdown(lint, source, category) {
+                        lint_failed |= finding.severity == Severity::Error;
+                        report.push(source_path, finding.severity, finding.describe());
+                    }
+                }
+            }
+            let translations = data
+                .translations
+                .iter()
+                .map(|(language, leaf)| (language, leaf.true_path.as_path()))
+                .collect::<Vec<_>>();
+            report.translation(translation_status(
+                &data.true_path,
+                &translations,
+                &expected_languages,
+                &edit_times,
+            ));
+        }
+        report_near_duplicates(
+            &tag_uses,
+            &site_build_path.as_ref().join(SITE_FILE),
+            &mut report,
+        );
+
+        for possible_category in sitebuild_traveller.build() {
             let possible_category = possible_category?;
             let path = possible_category.path();
 
@@ -505,15 +988,43 @@ pub fn build_site(
 
                 match &path_data.data().data {
                     Some(data) => {
-                        let (cfg, _) = match from_utf8(&data.data)?.split_once(SPLITTER) {
-                            Some(v) => v,
-                            None => continue,
-                        };
+                        if data.typ != LeafPathType::Moklog {
+                            continue;
+                        }
+
+                        let moklog_config = ConfigMeta::parse(from_utf8(&data.data)?)?;
 
-                        let config = toml::from_str::<ConfigMeta>(cfg)?;
+                        let site_path = format!("/{}", path_relativizie(&site_build_path, path)?);
+                        if let Some((link, redirect)) = redirect_from_config(&moklog_config, &site_path, config.site_url()) {
+                            write_redirect_page(&site_output_path, &link.title, &redirect)?;
+                            // under the path of the category it's listed in, like `titles`
+                            let parent = match path.parent() {
+                                Some(parent) => {
+                                    format!("/{}", path_relativizie(&site_build_path, parent)?)
+                                }
+                                None => continue,
+                            };
+                            nav_links.entry(parent).or_insert_with(Vec::new).push(link);
+                            redirects.push(redirect);
+                            continue;
+                        }
+
+                        if possible_category.depth() > 2 {
+                            continue;
+                        }
 
-                        if let Some(cat_cfg) = config.category {
-                            let this_dir = match path.file_prefix().map(|x| x.to_str()).flatten() {
+                        if let Some(cat_cfg) = moklog_config.category {
+                            validate_pinned(&cat_cfg, path, &path.join(MOKLOG_FILE), &mut report);
+                            titles.insert(site_path.clone(), cat_cfg.title.clone());
+                            if let Some(name) = &cat_cfg.comparator {
+                                if !comparators.contains_key(name) {
+                                    report.error(
+                                        path.join(MOKLOG_FILE),
+                                        format!("the theme has no comparator named {name}"),
+                                    );
+                                }
+                            }
+                            let this_dir = match file_prefix(path) {
                                 Some(pre) => pre,
                                 None => continue,
                             };
@@ -522,13 +1033,13 @@ pub fn build_site(
                                     categories.insert(this_dir.to_string(), cat_cfg);
                                     category_subcat_map.insert(this_dir.to_string(), HashSet::new());
                                 } else  {
-                                    let parent = match path.parent().unwrap().file_prefix().map(|x| x.to_str()).flatten() {
+                                    let parent = match file_prefix(path.parent().unwrap()) {
                                         Some(pre) => pre,
                                         None => continue,
                                     };
 
-                                    if site_categories.contains_key(&parnet) {
-                                        category_subcat_map.get_mut(&parent).unwrap().insert(this_dir.to_string());
+                                    if categories.contains_key(parent) {
+                                        category_subcat_map.get_mut(parent).unwrap().insert(this_dir.to_string());
                                         sub_categories.insert(this_dir.to_string(), cat_cfg);
                                     } else {
                                         warn!("parent not in!");
@@ -543,17 +1054,478 @@ pub fn build_site(
         }
     }
 
+    let mut versions = VersionTrees::default();
+    let mut docs = DocsTrees::default();
+    for (dir, category) in &categories {
+        let root = site_config.slugs.strategy.page_path(Path::new(dir), None);
+        let tree = category.versioned.then(|| {
+            VersionTree::new(
+                &root,
+                version_pages.remove(dir).unwrap_or_default(),
+                category.latest_version.as_deref(),
+                &category.frozen_versions,
+            )
+        });
+        if let Some(tree) = &tree {
+            let moklog = site_build_path.as_ref().join(dir).join(MOKLOG_FILE);
+            for name in category.latest_version.iter().chain(&category.frozen_versions) {
+                if !tree.has_version(name) {
+                    report.warn(&moklog, format!("{dir} has no version named {name}"));
+                }
+            }
+        }
+        if category.docs {
+            let pages = docs_pages.remove(dir).unwrap_or_default();
+            match &tree {
+                // a sidebar for every version, of the pages of that version
+                Some(tree) => {
+                    for (name, version_root) in tree.roots() {
+                        let in_version = pages
+                            .iter()
+                            .filter(|page| page.path.starts_with(&format!("{version_root}/")))
+                            .cloned()
+                            .collect();
+                        let title = format!("{} {name}", category.title);
+                        docs.insert(DocsTree::new(version_root, &title, in_version));
+                    }
+                }
+                None => docs.insert(DocsTree::new(&root, &category.title, pages)),
+            }
+        }
+        if let Some(tree) = tree {
+            versions.insert(tree);
+        }
+    }
+    // every category's children in the order of its `sort`, pinned ones first
+    let mut listings = BTreeMap::new();
+    let category_dirs = categories
+        .iter()
+        .map(|(dir, category)| (PathBuf::from(dir), category))
+        .chain(
+            category_subcat_map
+                .iter()
+                .flat_map(|(parent, subcategories)| {
+                    subcategories.iter().filter_map(|dir| {
+                        let category = sub_categories.get(dir)?;
+                        Some((Path::new(parent).join(dir), category))
+                    })
+                }),
+        );
+    for (dir, category) in category_dirs {
+        let entries = listing_entries.remove(&dir).unwrap_or_default();
+        match Listing::new(entries, category, &comparators) {
+            Ok(listing) => {
+                listings.insert(dir, listing);
+            }
+            Err(why) => report.error(
+                site_build_path.as_ref().join(&dir).join(MOKLOG_FILE),
+                format!("can't sort this category: {why}"),
+            ),
+        }
+    }
+
+    let wiki_index = WikiIndex::new(&wiki_pages, site_config.slugs.strategy);
+    let mut page_links = PageLinks::new(&wiki_index);
+    for (site_path, source_path, body) in &link_sources {
+        let body = match site_config.markup.wikilinks {
+            true => {
+                for link in wikilinks(body) {
+                    match wiki_index.resolve(link.target) {
+                        Resolution::Page(_) => {}
+                        Resolution::Missing => report.warn(
+                            source_path,
+                            format!("[[{}]] is not the title or name of any page", link.target),
+                        ),
+                        Resolution::Ambiguous(paths) => report.warn(
+                            source_path,
+                            format!("[[{}]] could be any of {}", link.target, paths.join(", ")),
+                        ),
+                    }
+                }
+                wiki_index.expand(body)
+            }
+            false => body.clone(),
+        };
+        page_links.add(site_path, &body, config.site_url());
+    }
+
+    let hidden = access.keys().cloned().collect::<BTreeSet<_>>();
+    let graph = LinkGraph::new(
+        &page_links,
+        &page_tags,
+        &hidden,
+        config.site_url(),
+        site_config.build.link_graph,
+    );
+
+    let markdown = MarkdownOptions {
+        diagrams: &diagrams,
+        markup: &site_config.markup,
+        svg: &site_config.build.svg,
+        codeblock_template: tera
+            .get_template_names()
+            .any(|name| name == CODEBLOCK_TEMPLATE)
+            .then_some(&tera),
+        slugs: site_config.slugs.strategy,
+        wikilinks: site_config.markup.wikilinks.then_some(&wiki_index),
+    };
+
+    // series path -> the epub (and pdf) of it
+    let mut ebooks = BTreeMap::new();
+    if let Some(options) = &site_config.build.ebooks {
+        let language = site_config.default_language();
+        for (path, source_path, meta) in &series {
+            deadline.check("while making ebooks")?;
+            let book = Book {
+                path,
+                title: &meta.title,
+                authors: &meta.authors,
+                date_started: meta.date_started,
+                date_completed: meta.date_completed,
+                language: language.as_str(),
+                parts: book_parts(path, &book_pages),
+            };
+            if book.parts.is_empty() {
+                report.warn(source_path, "this series has no parts to make an ebook of");
+                continue;
+            }
+            match write_ebook(
+                &book,
+                options,
+                site_build_path.as_ref(),
+                site_output_path.as_ref(),
+                &markdown,
+                &assets,
+                config.site_url(),
+            ) {
+                Ok(links) => {
+                    ebooks.insert(path.clone(), links);
+                }
+                Err(why) => report.error(source_path, format!("no ebook of this series: {why}")),
+            }
+        }
+    }
+
+    // left as the last build wrote them unless the build is forced, see serve/builds.rs
+    let frozen = pages
+        .keys()
+        .filter(|path| versions.is_frozen(path))
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    let info = BuildInformation {
+        initiated: "build".to_string(),
+        id: Utc::now().timestamp_millis() as u64,
+        start_time: Utc::now(),
+        end_time: None,
+        status: BuildStatus::Running,
+    };
+    let offset = config.default_offset()?;
+    let default_language = site_config.default_language();
+    let expected_languages = site_config.expected_languages();
+    let languages = expected_languages.iter().collect::<Vec<_>>();
+    let category_links = Arc::new(
+        categories
+            .iter()
+            .map(|(dir, category)| (category.title.clone(), dir.clone()))
+            .collect::<HashMap<_, _>>(),
+    );
+    let category_subcat_map = Arc::new(category_subcat_map);
+    let nav_links = Arc::new(nav_links);
+    let titles = Arc::new(titles);
+    let docs = Arc::new(docs);
+    let versions = Arc::new(versions);
+    let page_links = Arc::new(page_links);
+    let graph = Arc::new(graph);
+    let ebooks = Arc::new(ebooks);
+    // pages report from behind a lock, merged into the build's once they're all written
+    let page_report = std::sync::Mutex::new(BuildReport::new());
+
     for fs_node_id in fs_tree.traverse_level_order_ids(&fs_root_id.unwrap())? {
-        let fs_node = fs_tree.get(&fs_node_id).unwrap();
+        deadline.check("while rendering pages")?;
+        let data = match &fs_tree.get(&fs_node_id).unwrap().data().data {
+            Some(data) if data.typ != LeafPathType::Moklog => data,
+            _ => continue,
+        };
+        let default_source = match from_utf8(&data.data) {
+            Ok(source) => source,
+            Err(why) => {
+                report.error(&data.true_path, format!("not utf8: {why}"));
+                continue;
+            }
+        };
+        let dir = data.true_path.parent().unwrap_or(Path::new(""));
+
+        // the default language first, it decides the path every translation goes under
+        let mut translations = data.translations.iter().collect::<Vec<_>>();
+        translations.sort_by_key(|(language, _)| language.as_str());
+        let mut sources = vec![(
+            &default_language,
+            data.typ,
+            &data.true_path,
+            Cow::Borrowed(default_source),
+        )];
+        for (language, leaf) in translations {
+            let source = match from_utf8(&leaf.data) {
+                Ok(source) => source,
+                Err(why) => {
+                    report.error(&leaf.true_path, format!("not utf8: {why}"));
+                    continue;
+                }
+            };
+            // only front matter merges, any other kind of translation is a whole page
+            let source = match (data.typ, leaf.typ) {
+                (LeafPathType::Page, LeafPathType::Page) => {
+                    match resolve_translation(default_source, source) {
+                        Ok(source) => Cow::Owned(source),
+                        Err(why) => {
+                            report.error(&leaf.true_path, format!("invalid header: {why}"));
+                            continue;
+                        }
+                    }
+                }
+                _ => Cow::Borrowed(source),
+            };
+            sources.push((language, leaf.typ, &leaf.true_path, source));
+        }
 
-        if fs_node_id == fs_root_id.unwrap() {
-            let insert_behaviour = InsertBehavior::AsRoot;
+        let mut site_path = None;
+        for (language, typ, source_path, source) in sources {
+            // the header of every kind of page, and what there is to render of it
+            let rendered = match typ {
+                LeafPathType::Page => PageHeader::parse(&source, &offset).map(|header| {
+                    let body = source
+                        .split_once(SPLITTER)
+                        .map(|(_, body)| body)
+                        .unwrap_or("");
+                    (
+                        header.page,
+                        header.custom,
+                        Page::Markup(header.page_type, body),
+                    )
+                }),
+                LeafPathType::PreBuilt => {
+                    PrebuiltMeta::parse(&source, &offset).map(|(meta, body)| {
+                        let page = PageMeta {
+                            display: meta.title.clone().unwrap_or_default(),
+                            index: meta.index,
+                            rss: meta.feed,
+                            ..PageMeta::default()
+                        };
+                        (page, Custom::default(), Page::Prebuilt(meta, body))
+                    })
+                }
+                LeafPathType::Notebook => Notebook::parse(&source).and_then(|notebook| {
+                    let sidecar = sidecar_path(&site_build_path.as_ref().join(source_path));
+                    let sidecar = std::fs::read_to_string(sidecar).ok();
+                    let meta = NotebookMeta::load(&notebook, sidecar.as_deref(), &offset)?;
+                    let page = PageMeta {
+                        display: meta.title.clone().unwrap_or_default(),
+                        index: true,
+                        rss: true,
+                        ..PageMeta::default()
+                    };
+                    Ok((page, Custom::default(), Page::Notebook(notebook, meta)))
+                }),
+                LeafPathType::Moklog => continue,
+            };
+            let (page, custom, rendered) = match rendered {
+                Ok(rendered) => rendered,
+                Err(why) => {
+                    report.error(source_path, format!("invalid header: {why}"));
+                    // without the default language there's no path for the translations either
+                    match site_path {
+                        Some(_) => continue,
+                        None => break,
+                    }
+                }
+            };
+
+            let site_path: &str = site_path.get_or_insert_with(|| {
+                site_config
+                    .slugs
+                    .strategy
+                    .page_path(dir, page.slug.as_deref())
+            });
+            let slug = site_path.rsplit('/').next().unwrap_or_default();
+            let place = listing_place(&data.true_path)
+                .and_then(|(dir, name)| Some((listings.get(dir)?, name)));
+            let listed = place.and_then(|(listing, name)| listing.entry(name));
+            let (previous, next) =
+                place.map_or((None, None), |(listing, name)| listing.neighbours(name));
+            let build_stuffs = CoreBuildStuffs {
+                tera: &tera,
+                info: &info,
+                site: site_config,
+                site_variables: &site_variables,
+                urls: config.site_url(),
+                bundles: &bundles,
+                markdown: &markdown,
+                page: &page,
+                slug,
+                pinned: listed.map_or(false, |entry| entry.pinned),
+                previous,
+                next,
+                assets: &assets,
+                plugins: &plugins,
+                hooks: &hooks,
+                social_cards: social_cards.as_ref(),
+                tags: &tag_map,
+                emitted: &emitted,
+                categories: category_links.clone(),
+                subcategories: category_subcat_map.clone(),
+                nav_links: nav_links.clone(),
+                titles: titles.clone(),
+                docs: docs.clone(),
+                versions: versions.clone(),
+                links: page_links.clone(),
+                graph: graph.clone(),
+                ebooks: ebooks.clone(),
+                language,
+                default_language: &default_language,
+                langauges: &languages,
+                content: "",
+                markup: Markup::from_path(source_path).unwrap_or_default(),
+                path: site_path,
+                site_root: site_build_path.as_ref(),
+                source_path,
+                custom: &custom,
+                report: &page_report,
+            };
+            let document = match &rendered {
+                Page::Markup(page_type, body) => match page_type.generic() {
+                    Some(generic) => build_generic(
+                        &generic,
+                        page_type,
+                        CoreBuildStuffs {
+                            content: body,
+                            ..build_stuffs
+                        },
+                    ),
+                    None => continue,
+                },
+                Page::Prebuilt(meta, body) => build_prebuilt(meta, body, build_stuffs),
+                Page::Notebook(notebook, meta) => build_notebook(notebook, meta, build_stuffs),
+            };
+            let document = match document {
+                Ok(document) => document,
+                Err(why) => {
+                    report.error(source_path, format!("failed to build: {why}"));
+                    continue;
+                }
+            };
+
+            let target = translated_path(
+                site_path,
+                language,
+                &default_language,
+                site_config.prefix_default_language,
+            );
+            let dir = site_output_path
+                .as_ref()
+                .join(target.trim_start_matches('/'));
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(dir.join("index.html"), document.document())?;
+
+            // served under a path of its own, which changes and is hidden with the page
+            if target != site_path {
+                if let Some(hash) = pages.get(site_path).cloned() {
+                    pages.insert(target.clone(), hash);
+                }
+                if let Some(page_access) = access.get(site_path).cloned() {
+                    access.insert(target, page_access);
+                }
+            }
+        }
+    }
+    report.extend(page_report.into_inner().unwrap());
+
+    for (path, html) in plugins.take_pages() {
+        let dir = site_output_path.as_ref().join(path.trim_start_matches('/'));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("index.html"), &html)?;
+        pages.insert(path, format!("{:016x}", hash_file(&html)));
+    }
 
-            // let materials =
-        } else if fs_node.data().depth == 1 {
-            
+    // next to their pages, so they change with them and aren't pages of their own
+    for file in emitted.into_inner().unwrap() {
+        let target = site_output_path.as_ref().join(file.path.trim_start_matches('/'));
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
         }
+        std::fs::write(target, file.contents)?;
+    }
+
+    // last, pages can pull in files nothing else referenced
+    deadline.check("before writing assets")?;
+    assets.write(
+        &site_output_path,
+        site_build_path.as_ref(),
+        &site_config.build.svg,
+        blobs,
+    )?;
+
+    if site_config.build.font_subsetting {
+        subset_fonts(
+            &site_output_path,
+            config.site_url(),
+            site_config.default_language().as_str(),
+            &mut report,
+        )?;
+    }
+
+    if site_config.build.anchor_check {
+        check_anchors(
+            &site_output_path,
+            config.site_url(),
+            &site_config.default_language(),
+            &site_config.expected_languages(),
+            site_config.prefix_default_language,
+            &mut report,
+        )?;
+    }
+
+    if lint_failed {
+        return Err(Report::msg(format!(
+            "build failed linting:\n{}",
+            report
+                .sorted()
+                .diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.severity == Severity::Error)
+                .map(ToString::to_string)
+                .join("\n")
+        )));
+    }
+
+    if site_config.build.html_validation == HtmlValidation::Strict && report.has_errors() {
+        return Err(Report::msg(format!(
+            "build failed strict html validation:\n{}",
+            report.sorted().diagnostics.iter().map(ToString::to_string).join("\n")
+        )));
+    }
+
+    write_dynamic_pages(&site_output_path, &dynamic_pages)?;
+    if site_config.build.link_graph {
+        write_link_graph(&site_output_path, &graph)?;
     }
 
-    Ok(())
+    // after everything else, it covers every file the build wrote
+    let manifest = build_manifest(&site_output_path)?;
+    write_manifest(&site_output_path, &manifest)?;
+
+    Ok(BuiltSite {
+        redirects,
+        pages,
+        frozen,
+        access,
+        manifest,
+        report,
+        calls,
+        nav_links: nav_links
+            .iter()
+            .map(|(path, links)| (path.clone(), links.clone()))
+            .collect(),
+        listings,
+    })
 }
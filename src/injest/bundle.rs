@@ -0,0 +1,114 @@
+use crate::injest::{
+    report::BuildReport, static_file::new_filename, templates::SiteTheme,
+};
+use color_eyre::Result;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, write};
+use std::path::Path;
+
+pub const BUNDLE_DIR: &str = "static/bundles";
+
+// declared per template in the theme metadata:
+//
+// [bundles."article.html"]
+// styles = ["base.css", "article.scss"]
+// scripts = ["copy-button.js"]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BundleSpec {
+    #[serde(default)]
+    pub styles: Vec<String>,
+    #[serde(default)]
+    pub scripts: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BundleFile {
+    // fingerprinted, relative to the site root
+    pub site_path: String,
+    pub contents: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bundle {
+    pub style: Option<BundleFile>,
+    pub script: Option<BundleFile>,
+}
+
+fn concat_assets(
+    template: &str,
+    names: &[String],
+    assets: &dashmap::DashMap<String, String>,
+    separator: &str,
+    report: &mut BuildReport,
+) -> Option<String> {
+    let mut contents = vec![];
+    for name in names.iter().unique() {
+        match assets.get(name) {
+            Some(asset) => contents.push(asset.value().clone()),
+            None => report.error(
+                template,
+                format!("bundle for \"{template}\" references \"{name}\", which the theme does not have"),
+            ),
+        }
+    }
+    if contents.is_empty() {
+        return None;
+    }
+    Some(contents.join(separator))
+}
+
+// named only after the contents, so templates sharing the same assets share one file
+fn bundle_file(ext: &str, contents: String) -> Option<BundleFile> {
+    let (_, file_name) = new_filename(contents.as_bytes(), format!("bundle.{ext}"))?;
+    Some(BundleFile {
+        site_path: format!("/{BUNDLE_DIR}/{file_name}"),
+        contents,
+    })
+}
+
+// template name -> bundle. styles and scripts are already minified when the theme is loaded
+pub fn build_bundles(theme: &SiteTheme, report: &mut BuildReport) -> HashMap<String, Bundle> {
+    let mut bundles = HashMap::new();
+
+    for (template, spec) in &theme.metadata.bundles {
+        if !theme.tera_templates.contains_key(template) {
+            report.warn(
+                template,
+                format!("bundle declared for \"{template}\", which the theme does not have"),
+            );
+        }
+
+        let style = concat_assets(template, &spec.styles, &theme.styles, "\n", report)
+            .and_then(|css| bundle_file("css", css));
+        let script = concat_assets(template, &spec.scripts, &theme.js_scripts, ";\n", report)
+            .and_then(|js| bundle_file("js", js));
+
+        bundles.insert(template.clone(), Bundle { style, script });
+    }
+
+    bundles
+}
+
+pub fn write_bundles(
+    site_output_path: impl AsRef<Path>,
+    bundles: &HashMap<String, Bundle>,
+) -> Result<()> {
+    let dir = site_output_path.as_ref().join(BUNDLE_DIR);
+    create_dir_all(&dir)?;
+
+    let files = bundles
+        .values()
+        .flat_map(|bundle| [&bundle.style, &bundle.script])
+        .flatten()
+        .unique_by(|file| &file.site_path);
+
+    for file in files {
+        let path = site_output_path
+            .as_ref()
+            .join(file.site_path.trim_start_matches('/'));
+        write(path, &file.contents)?;
+    }
+    Ok(())
+}
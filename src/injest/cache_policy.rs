@@ -0,0 +1,215 @@
+use crate::injest::{assets::ASSET_DIR, static_file::parse_filename};
+use crate::serve::search::SEARCH_PATH;
+use axum::http::{HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+// last path segments that are a feed wherever they are
+const FEED_FILES: &[&str] = &["feed.xml", "atom.xml", "rss.xml", "index.xml", "feed.json"];
+
+// what a response is, as far as caching it goes
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentClass {
+    // `/static/name-<hash>.ext`, a change in the contents is a new name
+    HashedAsset,
+    // the rest of the built site, pages and the files next to them
+    Page,
+    Feed,
+    // answered by what the viewer may see
+    Search,
+    // the rest of the api, the admin api and whatever is per viewer
+    Admin,
+}
+
+impl ContentClass {
+    pub const ALL: [ContentClass; 5] = [
+        ContentClass::HashedAsset,
+        ContentClass::Page,
+        ContentClass::Feed,
+        ContentClass::Search,
+        ContentClass::Admin,
+    ];
+
+    // by the site path, without the base path the site is deployed under
+    pub fn of(path: &str) -> ContentClass {
+        if path == SEARCH_PATH {
+            return ContentClass::Search;
+        }
+        if path.starts_with("/api/") {
+            return ContentClass::Admin;
+        }
+        if let Some(name) = path.strip_prefix(&format!("/{ASSET_DIR}/")) {
+            if !name.contains('/') && parse_filename(name).is_some() {
+                return ContentClass::HashedAsset;
+            }
+        }
+        let last = path
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        let feed = FEED_FILES.contains(&last)
+            || last.ends_with(".rss")
+            || last.ends_with(".atom")
+            || path.starts_with("/feed/")
+            || path == "/feed";
+        match feed {
+            true => ContentClass::Feed,
+            false => ContentClass::Page,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContentClass::HashedAsset => "hashed_asset",
+            ContentClass::Page => "page",
+            ContentClass::Feed => "feed",
+            ContentClass::Search => "search",
+            ContentClass::Admin => "admin",
+        }
+    }
+}
+
+// The headers one class of response goes out with. Anything left out of site.toml is the
+// default of its class, see `defaults`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeaderSet {
+    pub cache_control: Option<String>,
+    // header names, the ones the response cache keys on are taken from here too
+    pub vary: Option<Vec<String>>,
+    // how long a cdn in front keeps it, browsers only go by cache_control
+    pub surrogate_control: Option<String>,
+}
+
+impl HeaderSet {
+    fn or(self, default: HeaderSet) -> HeaderSet {
+        HeaderSet {
+            cache_control: self.cache_control.or(default.cache_control),
+            vary: self.vary.or(default.vary),
+            surrogate_control: self.surrogate_control.or(default.surrogate_control),
+        }
+    }
+
+    fn defaults(class: ContentClass) -> HeaderSet {
+        let set = |cache_control: &str, vary: &[&str], surrogate_control: Option<&str>| HeaderSet {
+            cache_control: Some(cache_control.to_string()),
+            vary: Some(vary.iter().map(ToString::to_string).collect()),
+            surrogate_control: surrogate_control.map(ToString::to_string),
+        };
+        match class {
+            ContentClass::HashedAsset => set(
+                "public, max-age=31536000, immutable",
+                &["Accept-Encoding"],
+                Some("max-age=31536000"),
+            ),
+            // browsers check back every time, a cdn keeps pages until a build purges them
+            ContentClass::Page => set(
+                "public, max-age=0, must-revalidate",
+                &["Accept-Encoding"],
+                Some("max-age=86400"),
+            ),
+            // feed readers poll, a few minutes late is fine
+            ContentClass::Feed => set(
+                "public, max-age=900",
+                &["Accept-Encoding"],
+                Some("max-age=900"),
+            ),
+            // hits are filtered by access rules, nothing shared may keep them
+            ContentClass::Search => set(
+                "private, max-age=60",
+                &["Accept-Encoding", "Cookie", "Authorization"],
+                None,
+            ),
+            ContentClass::Admin => set("private, no-store", &["Cookie", "Authorization"], None),
+        }
+    }
+}
+
+// `[cache]` in site.toml, the one place the server's response cache, the headers sent and cdn
+// purges all go by
+//
+// [cache.page]
+// cache_control = "public, max-age=300"
+// vary = ["Accept-Encoding", "Accept-Language"]
+// surrogate_control = "max-age=604800"
+//
+// [cache.feed]
+// cache_control = "public, max-age=3600"
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachePolicy {
+    #[serde(default)]
+    pub hashed_asset: HeaderSet,
+    #[serde(default)]
+    pub page: HeaderSet,
+    #[serde(default)]
+    pub feed: HeaderSet,
+    #[serde(default)]
+    pub search: HeaderSet,
+    #[serde(default)]
+    pub admin: HeaderSet,
+}
+
+impl CachePolicy {
+    fn configured(&self, class: ContentClass) -> &HeaderSet {
+        match class {
+            ContentClass::HashedAsset => &self.hashed_asset,
+            ContentClass::Page => &self.page,
+            ContentClass::Feed => &self.feed,
+            ContentClass::Search => &self.search,
+            ContentClass::Admin => &self.admin,
+        }
+    }
+
+    // what site.toml sets, the defaults for the rest
+    pub fn headers(&self, class: ContentClass) -> HeaderSet {
+        self.configured(class)
+            .clone()
+            .or(HeaderSet::defaults(class))
+    }
+
+    // whether a cache shared between viewers may keep it, the response cache or a cdn
+    pub fn shared(&self, class: ContentClass) -> bool {
+        let cache_control = self.headers(class).cache_control.unwrap_or_default();
+        !cache_control
+            .split(',')
+            .map(|directive| directive.trim().to_ascii_lowercase())
+            .any(|directive| directive == "private" || directive == "no-store")
+    }
+
+    pub fn varies_on(&self, class: ContentClass, header: &HeaderName) -> bool {
+        self.headers(class)
+            .vary
+            .unwrap_or_default()
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(header.as_str()))
+    }
+
+    // values that can't go into a header, for SiteMeta::validate
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        for class in ContentClass::ALL {
+            let headers = self.configured(class);
+            let values = headers
+                .cache_control
+                .iter()
+                .chain(&headers.surrogate_control);
+            for value in values {
+                if HeaderValue::from_str(value).is_err() {
+                    problems.push(format!(
+                        "cache.{}: \"{value}\" is not a header value",
+                        class.name()
+                    ));
+                }
+            }
+            for name in headers.vary.iter().flatten() {
+                if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                    problems.push(format!(
+                        "cache.{}: \"{name}\" is not a header name",
+                        class.name()
+                    ));
+                }
+            }
+        }
+        problems
+    }
+}
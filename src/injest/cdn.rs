@@ -0,0 +1,184 @@
+use crate::injest::{
+    cache_policy::{CachePolicy, ContentClass},
+    diff::{slugs, DiffKind, SiteContentDiffElem},
+    links::SiteUrl,
+};
+use color_eyre::{Report, Result};
+use reqwest::{header::RETRY_AFTER, Client, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env::var;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+// tries per request, waiting twice as long after each failure
+const ATTEMPTS: u32 = 4;
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// the most urls cloudflare takes in one purge
+const CLOUDFLARE_BATCH: usize = 30;
+
+// `[[cdn]]` in site.toml, purged of every changed page after a build that succeeded
+//
+// [[cdn]]
+// provider = "cloudflare"
+// zone_id = "023e105f4ecef8ad9ca31a8372d0c353"
+// token_env = "MOKLOG_CLOUDFLARE_TOKEN"
+//
+// [[cdn]]
+// provider = "fastly"
+// token_env = "MOKLOG_FASTLY_KEY"
+//
+// [[cdn]]
+// provider = "bunny"
+// token_env = "MOKLOG_BUNNY_KEY"
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum CdnConfig {
+    Cloudflare { zone_id: String, token_env: String },
+    Fastly { token_env: String },
+    Bunny { token_env: String },
+}
+
+impl CdnConfig {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CdnConfig::Cloudflare { .. } => "cloudflare",
+            CdnConfig::Fastly { .. } => "fastly",
+            CdnConfig::Bunny { .. } => "bunny",
+        }
+    }
+
+    // the content repo is no place for api tokens, only the name of the variable holding one
+    fn token(&self) -> Result<String> {
+        let name = match self {
+            CdnConfig::Cloudflare { token_env, .. }
+            | CdnConfig::Fastly { token_env }
+            | CdnConfig::Bunny { token_env } => token_env,
+        };
+        var(name).map_err(|_| Report::msg(format!("{name} is not set")))
+    }
+}
+
+// Every url a page answers on that a cdn could have cached: changed and removed pages, and added
+// ones too, their 404 might have been cached. Assets have their content in their name and never go
+// stale, and what `[cache]` keeps out of shared caches was never cached to begin with.
+pub fn purge_urls(
+    diff: &[SiteContentDiffElem],
+    urls: &SiteUrl,
+    policy: &CachePolicy,
+) -> Vec<String> {
+    slugs(
+        diff,
+        &[DiffKind::Added, DiffKind::Updated, DiffKind::Removed],
+    )
+    .iter()
+    .filter(|path| policy.shared(ContentClass::of(path)))
+    .map(|path| urls.absolute(&urls.policy().canonical_path(path)))
+    .collect()
+}
+
+// sends the request, again after a wait on anything that could go through on another try
+async fn send_with_backoff(request: impl Fn() -> RequestBuilder) -> Result<()> {
+    let mut backoff = FIRST_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let (why, retry_after) = match request().send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => {
+                let status = response.status();
+                let retryable = status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+                if !retryable {
+                    let body = response.text().await.unwrap_or_default();
+                    return Err(Report::msg(format!("answered {status}: {body}")));
+                }
+                let retry_after = response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse::<u64>().ok())
+                    .map(Duration::from_secs);
+                (format!("answered {status}"), retry_after)
+            }
+            Err(why) => (why.to_string(), None),
+        };
+        if attempt == ATTEMPTS {
+            return Err(Report::msg(format!(
+                "gave up after {ATTEMPTS} tries: {why}"
+            )));
+        }
+        tokio::time::sleep(retry_after.unwrap_or(backoff).min(MAX_BACKOFF)).await;
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+async fn purge(client: &Client, cdn: &CdnConfig, urls: &[String]) -> Result<()> {
+    let token = cdn.token()?;
+    match cdn {
+        CdnConfig::Cloudflare { zone_id, .. } => {
+            let endpoint =
+                format!("https://api.cloudflare.com/client/v4/zones/{zone_id}/purge_cache");
+            for batch in urls.chunks(CLOUDFLARE_BATCH) {
+                send_with_backoff(|| {
+                    client
+                        .post(&endpoint)
+                        .bearer_auth(&token)
+                        .json(&json!({ "files": batch }))
+                })
+                .await?;
+            }
+        }
+        // both purge a url at a time
+        CdnConfig::Fastly { .. } => {
+            for url in urls {
+                let target = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+                send_with_backoff(|| {
+                    client
+                        .post(format!("https://api.fastly.com/purge/{target}"))
+                        .header("Fastly-Key", &token)
+                })
+                .await?;
+            }
+        }
+        CdnConfig::Bunny { .. } => {
+            for url in urls {
+                send_with_backoff(|| {
+                    client
+                        .post("https://api.bunny.net/purge")
+                        .query(&[("url", url)])
+                        .header("AccessKey", &token)
+                })
+                .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// a cdn that can't be purged is logged and otherwise ignored, the build already succeeded
+pub async fn purge_cdns(
+    cdns: &[CdnConfig],
+    diff: &[SiteContentDiffElem],
+    urls: &SiteUrl,
+    policy: &CachePolicy,
+) {
+    let purged = purge_urls(diff, urls, policy);
+    if cdns.is_empty() || purged.is_empty() {
+        return;
+    }
+    let client = match Client::builder().timeout(TIMEOUT).build() {
+        Ok(client) => client,
+        Err(why) => {
+            warn!("no client to purge cdns with: {why}");
+            return;
+        }
+    };
+    for cdn in cdns {
+        match purge(&client, cdn, &purged).await {
+            Ok(()) => info!("purged {} urls from {}", purged.len(), cdn.name()),
+            Err(why) => warn!("failed to purge {}: {why}", cdn.name()),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use crate::injest::{
+    access::validate_rules,
+    config_meta::ConfigMeta,
+    listing::validate_pinned,
+    report::BuildReport,
+    sanitize,
+    site::{SiteMeta, SITE_FILE},
+};
+use crate::walker;
+use color_eyre::Result;
+use ignore::WalkBuilder;
+use std::fs::read_to_string;
+use std::path::Path;
+
+pub const MOKLOG_FILE: &str = ".moklog";
+
+// walk the content directory and explain every problem with the `.moklog` files found
+pub fn check_site(site_build_path: impl AsRef<Path>) -> Result<BuildReport> {
+    let mut report = BuildReport::new();
+
+    let site_file = site_build_path.as_ref().join(SITE_FILE);
+    match SiteMeta::load(&site_build_path) {
+        Ok(site) => {
+            site.validate(&site_file, &mut report);
+            validate_rules(&site.access, site_build_path.as_ref(), &site_file, &mut report);
+            sanitize::validate_rules(
+                &site.sanitize,
+                &site.role_paths,
+                site_build_path.as_ref(),
+                &site_file,
+                &mut report,
+            );
+        }
+        Err(why) => report.error(&site_file, format!("invalid site configuration: {why}")),
+    }
+
+    for entry in walker!(site_build_path.as_ref()).hidden(false).build() {
+        let entry = entry?;
+        if entry.file_name() != MOKLOG_FILE {
+            continue;
+        }
+
+        let path = entry.path();
+        // depth of the directory the .moklog describes, not of the file itself
+        let depth = entry.depth().saturating_sub(1);
+
+        let data = match read_to_string(path) {
+            Ok(data) => data,
+            Err(why) => {
+                report.error(path, format!("could not read: {why}"));
+                continue;
+            }
+        };
+
+        match ConfigMeta::parse(&data) {
+            Ok(config) => {
+                config.validate(path, depth, &mut report);
+                if let (Some(category), Some(dir)) = (&config.category, path.parent()) {
+                    validate_pinned(category, dir, path, &mut report);
+                }
+            }
+            Err(why) => report.error(path, format!("invalid configuration: {why}")),
+        }
+    }
+
+    Ok(report.sorted())
+}
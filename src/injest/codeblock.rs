@@ -0,0 +1,201 @@
+use html_escape::{encode_double_quoted_attribute, encode_text};
+use serde::Serialize;
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+use tera::{Context, Tera};
+use tracing::warn;
+
+// a theme can replace the built in code block markup by shipping this template
+pub const CODEBLOCK_TEMPLATE: &str = "codeblock.html";
+
+// ```rust,linenos,linenostart=10,hl_lines=3-5 8,filename=src/main.rs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FenceInfo {
+    pub language: String,
+    pub linenos: bool,
+    pub linenostart: usize,
+    pub hl_lines: Vec<RangeInclusive<usize>>,
+    pub filename: Option<String>,
+}
+
+impl FenceInfo {
+    pub fn parse(info: &str) -> FenceInfo {
+        let mut parts = info.split(',').map(str::trim);
+        let mut fence = FenceInfo {
+            language: parts.next().unwrap_or_default().to_string(),
+            linenostart: 1,
+            ..FenceInfo::default()
+        };
+
+        for part in parts {
+            match part.split_once('=') {
+                Some(("hl_lines", ranges)) => {
+                    fence.hl_lines = ranges.split_whitespace().filter_map(parse_range).collect()
+                }
+                Some(("filename", filename)) => {
+                    fence.filename = Some(filename.trim_matches('"').to_string())
+                }
+                Some(("linenostart", start)) => {
+                    fence.linenostart = start.parse::<usize>().unwrap_or(1)
+                }
+                None if part == "linenos" => fence.linenos = true,
+                _ => {}
+            }
+        }
+
+        fence
+    }
+
+    // line numbers in hl_lines are relative to the block, not to linenostart
+    pub fn is_highlighted(&self, line: usize) -> bool {
+        self.hl_lines.iter().any(|range| range.contains(&line))
+    }
+
+    pub fn needs_lines(&self) -> bool {
+        self.linenos || !self.hl_lines.is_empty()
+    }
+}
+
+fn parse_range(range: &str) -> Option<RangeInclusive<usize>> {
+    match range.split_once('-') {
+        Some((start, end)) => {
+            let start = start.parse::<usize>().ok()?;
+            let end = end.parse::<usize>().ok()?;
+            (start <= end).then_some(start..=end)
+        }
+        None => {
+            let line = range.parse::<usize>().ok()?;
+            Some(line..=line)
+        }
+    }
+}
+
+// Highlighted html has spans running across newlines. Split it up into lines, closing whatever
+// is open at the end of a line and opening it again at the start of the next.
+pub fn split_highlighted_lines(html: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut open: Vec<&str> = vec![];
+    let mut line = String::new();
+    let mut rest = html;
+
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("</i>") {
+            open.pop();
+            line.push_str("</i>");
+            rest = &rest[4..];
+        } else if rest.starts_with("<i ") {
+            let end = rest.find('>').map(|end| end + 1).unwrap_or(rest.len());
+            open.push(&rest[..end]);
+            line.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if c == '\n' {
+            for _ in &open {
+                line.push_str("</i>");
+            }
+            lines.push(std::mem::take(&mut line));
+            for tag in &open {
+                line.push_str(tag);
+            }
+            rest = &rest[1..];
+        } else {
+            line.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    // a trailing newline doesn't make another line
+    if !line.is_empty() && line != open.concat() {
+        lines.push(line);
+    }
+    lines
+}
+
+pub fn write_lines<W>(writer: &mut W, fence: &FenceInfo, highlighted: &str)
+where
+    W: Write,
+{
+    for (idx, line) in split_highlighted_lines(highlighted).iter().enumerate() {
+        let line_no = idx + 1;
+        let class = if fence.is_highlighted(line_no) {
+            "code-line hl"
+        } else {
+            "code-line"
+        };
+        write!(writer, r#"<span class="{class}">"#).ok();
+        if fence.linenos {
+            write!(
+                writer,
+                r#"<span class="line-number" aria-hidden="true">{}</span>"#,
+                fence.linenostart + idx
+            )
+            .ok();
+        }
+        write!(writer, "{line}</span>\n").ok();
+    }
+}
+
+// what `codeblock.html` gets as `block`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct CodeBlockContext<'a> {
+    pub language: &'a str,
+    pub filename: Option<&'a str>,
+    pub line_count: usize,
+    pub linenos: bool,
+    // highlighted (and line wrapped) html, safe to output as is
+    pub code: &'a str,
+    // the code as written, for copy buttons
+    pub raw: &'a str,
+}
+
+pub fn render_codeblock(tera: Option<&Tera>, fence: &FenceInfo, raw: &str, code: &str) -> String {
+    let block = CodeBlockContext {
+        language: &fence.language,
+        filename: fence.filename.as_deref(),
+        line_count: raw.lines().count(),
+        linenos: fence.linenos,
+        code,
+        raw,
+    };
+
+    if let Some(tera) = tera {
+        let mut context = Context::new();
+        context.insert("block", &block);
+        match tera.render(CODEBLOCK_TEMPLATE, &context) {
+            Ok(rendered) => return rendered,
+            Err(why) => warn!("{CODEBLOCK_TEMPLATE} failed to render, using the default: {why}"),
+        }
+    }
+
+    let mut out = String::new();
+    write!(
+        out,
+        r#"<figure class="codeblock" data-lang="{}" data-line-count="{}">"#,
+        encode_double_quoted_attribute(block.language),
+        block.line_count
+    )
+    .ok();
+    if let Some(filename) = block.filename {
+        write!(
+            out,
+            r#"<figcaption class="codeblock-filename">{}</figcaption>"#,
+            encode_text(filename)
+        )
+        .ok();
+    }
+    if !block.language.is_empty() {
+        write!(
+            out,
+            r#"<div class="codeblock-lang">{}</div>"#,
+            encode_text(block.language)
+        )
+        .ok();
+    }
+    write!(
+        out,
+        r#"<pre class="codeblock-code"><code class="language-{}">{}</code></pre></figure>"#,
+        encode_double_quoted_attribute(block.language),
+        block.code
+    )
+    .ok();
+    out
+}
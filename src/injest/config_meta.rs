@@ -0,0 +1,320 @@
+use crate::injest::{
+    build::{ConfigurationType, RESERVED_CHARS, SPLITTER},
+    generate::CategoryMeta,
+    report::BuildReport,
+};
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// A `.moklog` file describes the directory it lives in. It is plain TOML, an optional `===` and
+// anything after it is ignored (so the same splitter as pages can be used).
+//
+// type = "category"
+//
+// [category]
+// title = "Programming"
+// template = "category.html"
+// sort = "date_desc"
+// pinned_posts = ["hello-world"]
+// pinned_in_feeds = false
+// paginate = 10
+// docs = false
+// versioned = false
+// latest_version = "v2"
+// frozen_versions = ["v1"]
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConfigMeta {
+    #[serde(rename = "type")]
+    pub typ: ConfigurationType,
+    pub category: Option<CategoryMeta>,
+    pub redirect: Option<RedirectMeta>,
+    pub external: Option<ExternalMeta>,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    DateDesc,
+    DateAsc,
+    Title,
+    // `weight` in each child's front matter, lowest first
+    Weight,
+    // `compare(a, b)` from the theme's comparators/<comparator>.rhai
+    Custom,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RedirectMeta {
+    pub title: String,
+    pub to: String,
+    #[serde(default = "default_true")]
+    pub permanent: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExternalMeta {
+    pub title: String,
+    pub url: String,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+pub fn front_matter(data: &str) -> &str {
+    match data.split_once(SPLITTER) {
+        Some((cfg, _)) => cfg,
+        None => data,
+    }
+}
+
+impl ConfigMeta {
+    pub fn parse(data: &str) -> Result<ConfigMeta> {
+        Ok(toml::from_str::<ConfigMeta>(front_matter(data))?)
+    }
+
+    // depth is relative to the content root, so categories live at 1 and subcategories at 2
+    pub fn validate(&self, path: &Path, depth: usize, report: &mut BuildReport) {
+        match self.typ {
+            ConfigurationType::Category if depth != 1 => report.error(
+                path,
+                format!("categories must be top level directories, this one is {depth} deep"),
+            ),
+            ConfigurationType::SubCategory if depth != 2 => report.error(
+                path,
+                format!(
+                    "subcategories must be directly under a category, this one is {depth} deep"
+                ),
+            ),
+            _ => {}
+        }
+
+        if depth == 0 && self.typ != ConfigurationType::Page {
+            report.error(path, "the content root can only be of type \"page\"");
+        }
+
+        let wants_category = matches!(
+            self.typ,
+            ConfigurationType::Category | ConfigurationType::SubCategory
+        );
+        let wants_redirect = self.typ == ConfigurationType::Redirect;
+        let wants_external = self.typ == ConfigurationType::External;
+
+        check_section(
+            path,
+            "category",
+            self.category.is_some(),
+            wants_category,
+            report,
+        );
+        check_section(
+            path,
+            "redirect",
+            self.redirect.is_some(),
+            wants_redirect,
+            report,
+        );
+        check_section(
+            path,
+            "external",
+            self.external.is_some(),
+            wants_external,
+            report,
+        );
+
+        if let Some(category) = &self.category {
+            if category.title.trim().is_empty() {
+                report.error(path, "category.title cannot be empty");
+            }
+            if let Some(template) = &category.template {
+                if !(template.ends_with(".html") || template.ends_with(".tera")) {
+                    report.warn(
+                        path,
+                        format!(
+                            "category.template \"{template}\" is not a .html or .tera template"
+                        ),
+                    );
+                }
+            }
+            match (category.sort, &category.comparator) {
+                (SortOrder::Custom, None) => report.error(
+                    path,
+                    "sort = \"custom\" needs a category.comparator from the theme",
+                ),
+                (SortOrder::Custom, Some(_)) | (_, None) => {}
+                (_, Some(_)) => report.warn(
+                    path,
+                    "category.comparator is ignored unless sort = \"custom\"",
+                ),
+            }
+            if category.paginate == Some(0) {
+                report.error(
+                    path,
+                    "category.paginate must be at least 1, remove it to disable pagination",
+                );
+            }
+            if !category.versioned
+                && (category.latest_version.is_some() || !category.frozen_versions.is_empty())
+            {
+                report.warn(
+                    path,
+                    "category.latest_version and category.frozen_versions are ignored unless versioned = true",
+                );
+            }
+            if let Some(latest) = &category.latest_version {
+                if category.frozen_versions.contains(latest) {
+                    report.warn(
+                        path,
+                        format!("category.latest_version \"{latest}\" is built every time, it can't be frozen"),
+                    );
+                }
+            }
+            for pinned in &category.pinned_posts {
+                if pinned.is_empty() || pinned.contains(RESERVED_CHARS) {
+                    report.error(
+                        path,
+                        format!("category.pinned_posts entry \"{pinned}\" is not a valid directory name"),
+                    );
+                }
+            }
+        }
+
+        if let Some(redirect) = &self.redirect {
+            if redirect.title.trim().is_empty() {
+                report.error(path, "redirect.title cannot be empty");
+            }
+            if !redirect.to.starts_with('/') {
+                report.error(
+                    path,
+                    format!("redirect.to \"{}\" must be a site path starting with /, use type = \"external\" for other sites", redirect.to),
+                );
+            }
+        }
+
+        if let Some(external) = &self.external {
+            if external.title.trim().is_empty() {
+                report.error(path, "external.title cannot be empty");
+            }
+            match url::Url::parse(&external.url) {
+                Ok(url) => {
+                    if url.scheme() != "http" && url.scheme() != "https" {
+                        report.warn(
+                            path,
+                            format!("external.url uses the \"{}\" scheme, browsers may refuse to follow it", url.scheme()),
+                        );
+                    }
+                }
+                Err(why) => report.error(
+                    path,
+                    format!(
+                        "external.url \"{}\" is not a valid url: {why}",
+                        external.url
+                    ),
+                ),
+            }
+        }
+    }
+
+    // every option filled in, used for `moklog example-config`
+    pub fn examples() -> Vec<(&'static str, ConfigMeta)> {
+        vec![
+            (
+                "a top level category",
+                ConfigMeta {
+                    typ: ConfigurationType::Category,
+                    category: Some(CategoryMeta {
+                        title: "Programming".to_string(),
+                        template: Some("category.html".to_string()),
+                        sort: SortOrder::DateDesc,
+                        comparator: None,
+                        pinned_posts: vec!["hello-world".to_string()],
+                        pinned_in_feeds: false,
+                        paginate: Some(10),
+                        docs: false,
+                        versioned: false,
+                        latest_version: None,
+                        frozen_versions: vec![],
+                    }),
+                    redirect: None,
+                    external: None,
+                },
+            ),
+            (
+                "a subcategory, directly inside a category",
+                ConfigMeta {
+                    typ: ConfigurationType::SubCategory,
+                    category: Some(CategoryMeta {
+                        title: "Rust".to_string(),
+                        template: None,
+                        sort: SortOrder::Weight,
+                        comparator: None,
+                        pinned_posts: vec![],
+                        pinned_in_feeds: false,
+                        paginate: None,
+                        docs: false,
+                        versioned: false,
+                        latest_version: None,
+                        frozen_versions: vec![],
+                    }),
+                    redirect: None,
+                    external: None,
+                },
+            ),
+            (
+                "a redirect to another page on this site",
+                ConfigMeta {
+                    typ: ConfigurationType::Redirect,
+                    category: None,
+                    redirect: Some(RedirectMeta {
+                        title: "Old Projects".to_string(),
+                        to: "/projects".to_string(),
+                        permanent: true,
+                    }),
+                    external: None,
+                },
+            ),
+            (
+                "a link to something hosted elsewhere",
+                ConfigMeta {
+                    typ: ConfigurationType::External,
+                    category: None,
+                    redirect: None,
+                    external: Some(ExternalMeta {
+                        title: "moklog".to_string(),
+                        url: "https://github.com/l1npengtul/moklog".to_string(),
+                    }),
+                },
+            ),
+        ]
+    }
+
+    pub fn example_document() -> Result<String> {
+        let mut document = String::new();
+        for (description, example) in ConfigMeta::examples() {
+            document.push_str(&format!("# {description}\n"));
+            document.push_str(&toml::to_string_pretty(&example)?);
+            document.push('\n');
+        }
+        Ok(document)
+    }
+}
+
+fn check_section(
+    path: &Path,
+    section: &str,
+    present: bool,
+    wanted: bool,
+    report: &mut BuildReport,
+) {
+    if wanted && !present {
+        report.error(
+            path,
+            format!("missing [{section}] table required by this type"),
+        );
+    } else if !wanted && present {
+        report.warn(path, format!("[{section}] table is ignored for this type"));
+    }
+}
@@ -0,0 +1,230 @@
+use crate::injest::{
+    anchors::Section,
+    breadcrumb::Breadcrumb,
+    docs::DocsNav,
+    ebook::EbookLinks,
+    generate::CategoryThing,
+    graph::GraphStats,
+    listing::ListingEntry,
+    site::{MenuItem, SocialLink},
+    templates::SiteThemeMetadata,
+    translation::Alternate,
+    versions::VersionNav,
+    wikilinks::Backlink,
+    workflow::WorkflowState,
+};
+use chrono::{DateTime, FixedOffset, Utc};
+use color_eyre::{Report, Result};
+use schemars::{schema_for, JsonSchema};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+// The variables a theme's templates are rendered with, as json schema. Every `page.*` key is set
+// on every page, the `content.*` ones depend on `page.type`. These structs are only here to be
+// described, the build inserts the same keys one by one into their objects, see generate.rs and
+// theme_test.rs.
+
+/// Everything moklog puts into the context of a page template. `{{ content.html | safe }}` is the
+/// rendered page, the other `content.*` variables are set next to it.
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct TemplateContext {
+    page: PageContext,
+    content: ContentContext,
+    site: SiteContext,
+    auto: AutoContext,
+    /// the `[custom]` table of the page's front matter, as it is written
+    custom: BTreeMap<String, Value>,
+    /// only for `dynamic` pages, which are rendered when they're requested
+    request: Option<RequestContext>,
+    /// only for `dynamic` pages
+    viewer: Option<ViewerContext>,
+}
+
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct PageContext {
+    /// `generic`, `prebuilt`, `notebook` or `dynamic`, `article`, `series` and `category` for
+    /// themes that have templates for them
+    #[schemars(rename = "type")]
+    kind: String,
+    /// absolute url of the page
+    url: String,
+    /// language tag of this version of the page
+    language: String,
+    /// the last segment of the page's path
+    base_slug: String,
+    group: String,
+    /// every language version of the page, empty if it isn't translated
+    translations: Vec<Alternate>,
+    default_translation: Option<Alternate>,
+    this_translation: Option<Alternate>,
+    rss_enabled: bool,
+    index_enabled: bool,
+    template: Option<String>,
+    children_template: Option<String>,
+    display: String,
+    redirect_from: Vec<String>,
+    redirect_to: Option<String>,
+    /// left out of listings, feeds, the sitemap and search
+    unlisted: bool,
+    private: bool,
+    state: WorkflowState,
+    pinned: bool,
+    /// the neighbours of the page in its category
+    previous: Option<ListingEntry>,
+    next: Option<ListingEntry>,
+    categories: Vec<CategoryThing<'static>>,
+    /// from the site root down to the page itself
+    breadcrumbs: Vec<Breadcrumb>,
+    /// the sidebar and reading order of a category with `docs = true`, unset outside of one
+    docs: Option<DocsNav>,
+    /// the version switcher of a category with `versioned = true`, unset outside of one
+    version: Option<VersionNav>,
+    /// the links to and from the page, unset for pages left out of the link graph
+    graph: Option<GraphStats>,
+    /// the epub (and pdf) of a completed series, unset for every other page
+    ebook: Option<EbookLinks>,
+    /// the page's print rendition, unset unless its front matter has `print = true`
+    print: Option<String>,
+    /// the built in print rules, only in print.html
+    print_style: Option<String>,
+}
+
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct ContentContext {
+    /// the rendered page, what the template wraps
+    html: String,
+    /// the page's source, before it was rendered
+    raw: String,
+    title: String,
+    date: Option<DateTime<FixedOffset>>,
+    authors: Vec<String>,
+    tags: Vec<String>,
+    summary_html: String,
+    summary_text: String,
+    /// markdown, a list of links to the headings
+    table_of_contents: String,
+    /// the headings with an id, with a url into each
+    sections: Vec<Section>,
+    /// the pages linking to this one, with a markdown link or a wikilink
+    backlinks: Vec<Backlink>,
+    word_count: usize,
+    character_count: usize,
+    /// characters of chinese, japanese and korean, counted apart from words
+    cjk: usize,
+    whitespace: usize,
+    reading_time_seconds: u32,
+    /// `article` and `series`
+    edited_dates: Option<Vec<DateTime<FixedOffset>>>,
+    /// `article`, the summary written in the front matter
+    summary: Option<String>,
+    /// `series`
+    on_going: Option<bool>,
+    /// `series`
+    date_started: Option<DateTime<FixedOffset>>,
+    /// `series`
+    date_completed: Option<DateTime<FixedOffset>>,
+    /// `category`, the pages listed in it in their order
+    entries: Option<Vec<ListingEntry>>,
+}
+
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct SiteContext {
+    title: String,
+    base_url: String,
+    description: Option<String>,
+    author: Option<String>,
+    social: Vec<SocialLink>,
+    /// `[theme]` of site.toml, the options the theme declares with their defaults filled in
+    theme: Map<String, Value>,
+    /// the menu as seen from this page, in its language
+    menu: Vec<MenuItem>,
+}
+
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct AutoContext {
+    build_time: DateTime<Utc>,
+    /// what started the build, `admin` or a webhook for example
+    build_init: String,
+    build_id: u64,
+    /// `dynamic` pages, when this render of it started
+    render_time: Option<DateTime<Utc>>,
+}
+
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct RequestContext {
+    /// the page's path, without the base path of the site
+    path: String,
+    /// the query parameters, the last one of a name repeated. `access` and `token` are left out
+    query: BTreeMap<String, String>,
+}
+
+/// who asked for the page, the page is rendered for each of them
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct ViewerContext {
+    /// signed in with the admin key
+    admin: bool,
+    /// holds a valid role token
+    signed_in: bool,
+    /// the roles of that token
+    roles: Vec<String>,
+}
+
+// the json schema type of an option's default, options without one can be anything
+fn option_type(default: &Value) -> Option<&'static str> {
+    match default {
+        Value::Bool(_) => Some("boolean"),
+        Value::Number(number) if number.is_f64() => Some("number"),
+        Value::Number(_) => Some("integer"),
+        Value::String(_) => Some("string"),
+        Value::Array(_) => Some("array"),
+        Value::Object(_) => Some("object"),
+        Value::Null => None,
+    }
+}
+
+// The schema of a page template's context. With a theme, `site.theme` lists the options it
+// declares in theme.toml.
+pub fn context_schema(theme: Option<&SiteThemeMetadata>) -> Result<Value> {
+    let mut schema = serde_json::to_value(schema_for!(TemplateContext))?;
+    let theme = match theme {
+        Some(theme) => theme,
+        None => return Ok(schema),
+    };
+
+    let mut properties = Map::new();
+    let mut required = vec![];
+    for (name, option) in &theme.options {
+        let mut property = Map::new();
+        if let Some(description) = &option.description {
+            property.insert("description".to_string(), json!(description));
+        }
+        if let Some(default) = &option.default {
+            let default = serde_json::to_value(default)?;
+            if let Some(kind) = option_type(&default) {
+                property.insert("type".to_string(), json!(kind));
+            }
+            property.insert("default".to_string(), default);
+        } else if option.required {
+            required.push(name.clone());
+        }
+        properties.insert(name.clone(), Value::Object(property));
+    }
+
+    let theme_schema = schema
+        .pointer_mut("/definitions/SiteContext/properties/theme")
+        .ok_or_else(|| Report::msg("the context schema has no site.theme"))?;
+    *theme_schema = json!({
+        "description": format!("`[theme]` of site.toml, the options of {}", theme.name),
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    });
+    Ok(schema)
+}
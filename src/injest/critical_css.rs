@@ -0,0 +1,168 @@
+use color_eyre::{Report, Result};
+use lightningcss::{
+    printer::PrinterOptions,
+    rules::{CssRule, CssRuleList},
+    stylesheet::{MinifyOptions, ParserOptions, StyleSheet},
+    traits::ToCss,
+};
+use lol_html::{element, rewrite_str, Settings};
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+// what a page actually contains, as far as selectors are concerned
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UsedSelectors {
+    pub tags: HashSet<String>,
+    pub classes: HashSet<String>,
+    pub ids: HashSet<String>,
+}
+
+pub fn used_selectors(html: &str) -> Result<UsedSelectors> {
+    let used = RefCell::new(UsedSelectors::default());
+
+    rewrite_str(
+        html,
+        Settings {
+            element_content_handlers: vec![element!("*", |el| {
+                let mut used = used.borrow_mut();
+                used.tags.insert(el.tag_name().to_ascii_lowercase());
+                if let Some(classes) = el.get_attribute("class") {
+                    used.classes
+                        .extend(classes.split_ascii_whitespace().map(str::to_string));
+                }
+                if let Some(id) = el.get_attribute("id") {
+                    used.ids.insert(id);
+                }
+                Ok(())
+            })],
+            ..Settings::default()
+        },
+    )?;
+
+    Ok(used.into_inner())
+}
+
+// Conservative: pseudo classes, attribute selectors and combinators are ignored, so a selector is
+// kept as long as every tag/class/id it names shows up somewhere in the page.
+fn selector_used(selector: &str, used: &UsedSelectors) -> bool {
+    fn read_ident(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+        let mut ident = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '\\' {
+                chars.next();
+                if let Some(escaped) = chars.next() {
+                    ident.push(escaped);
+                }
+            } else if c.is_alphanumeric() || c == '-' || c == '_' || !c.is_ascii() {
+                ident.push(c);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        ident
+    }
+
+    let mut chars = selector.chars().peekable();
+    let mut compound_start = true;
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '.' => {
+                chars.next();
+                if !used.classes.contains(&read_ident(&mut chars)) {
+                    return false;
+                }
+                compound_start = false;
+            }
+            '#' => {
+                chars.next();
+                if !used.ids.contains(&read_ident(&mut chars)) {
+                    return false;
+                }
+                compound_start = false;
+            }
+            '[' => {
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                }
+                compound_start = false;
+            }
+            ':' => {
+                while chars.peek() == Some(&':') {
+                    chars.next();
+                }
+                read_ident(&mut chars);
+                if chars.peek() == Some(&'(') {
+                    let mut depth = 0;
+                    for c in chars.by_ref() {
+                        match c {
+                            '(' => depth += 1,
+                            ')' => {
+                                depth -= 1;
+                                if depth == 0 {
+                                    break;
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                compound_start = false;
+            }
+            c if compound_start && c.is_alphabetic() => {
+                let tag = read_ident(&mut chars).to_ascii_lowercase();
+                if !used.tags.contains(&tag) {
+                    return false;
+                }
+                compound_start = false;
+            }
+            ' ' | '>' | '+' | '~' => {
+                chars.next();
+                compound_start = true;
+            }
+            _ => {
+                chars.next();
+            }
+        }
+    }
+
+    true
+}
+
+fn filter_rules(rules: &mut CssRuleList, used: &UsedSelectors) {
+    rules.0.retain_mut(|rule| match rule {
+        CssRule::Style(style) => match style.selectors.to_css_string(PrinterOptions::default()) {
+            Ok(selectors) => selectors
+                .split(',')
+                .any(|selector| selector_used(selector.trim(), used)),
+            Err(_) => true,
+        },
+        CssRule::Media(media) => {
+            filter_rules(&mut media.rules, used);
+            !media.rules.0.is_empty()
+        }
+        CssRule::Supports(supports) => {
+            filter_rules(&mut supports.rules, used);
+            !supports.rules.0.is_empty()
+        }
+        // fonts, keyframes and friends come with the full stylesheet
+        _ => false,
+    });
+}
+
+// the subset of `css` the page needs for its first paint
+pub fn critical_css(css: &str, used: &UsedSelectors) -> Result<String> {
+    let mut stylesheet = StyleSheet::parse(css, ParserOptions::default())
+        .map_err(|why| Report::msg(why.to_string()))?;
+    filter_rules(&mut stylesheet.rules, used);
+    stylesheet.minify(MinifyOptions::default())?;
+    Ok(stylesheet
+        .to_css(PrinterOptions {
+            minify: true,
+            ..PrinterOptions::default()
+        })?
+        .code)
+}
@@ -0,0 +1,67 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone};
+use color_eyre::{Report, Result};
+use toml::Value;
+
+const NAIVE_PATTERNS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+];
+
+// a date with an optional time and an optional zone. no time means midnight, no zone means the
+// site's default timezone.
+pub fn parse_date(date: &str, offset: &FixedOffset) -> Option<DateTime<FixedOffset>> {
+    let date = date.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Some(date);
+    }
+    // toml allows a space instead of the T
+    if let Ok(date) = DateTime::parse_from_rfc3339(&date.replacen(' ', "T", 1)) {
+        return Some(date);
+    }
+
+    let naive = NAIVE_PATTERNS
+        .iter()
+        .find_map(|pattern| NaiveDateTime::parse_from_str(date, pattern).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })?;
+    offset.from_local_datetime(&naive).single()
+}
+
+// strftime, but an unknown specifier is an error instead of a panic once it's displayed
+pub fn format_date(date: &DateTime<FixedOffset>, pattern: &str) -> Result<String> {
+    let items = StrftimeItems::new(pattern).collect::<Vec<_>>();
+    if items.contains(&Item::Error) {
+        return Err(Report::msg(format!("\"{pattern}\" is not a date pattern")));
+    }
+    Ok(date.format_with_items(items.into_iter()).to_string())
+}
+
+// Front matter dates are toml datetimes, which can be a bare date, a local date time or a full
+// offset date time. Turn all of them into rfc3339 strings in the site timezone so they deserialize
+// straight into `DateTime<FixedOffset>`.
+pub fn normalize_dates(value: &mut Value, offset: &FixedOffset) {
+    match value {
+        Value::Datetime(datetime) => {
+            if let Some(date) = parse_date(&datetime.to_string(), offset) {
+                *value = Value::String(date.to_rfc3339());
+            }
+        }
+        Value::Array(array) => {
+            for value in array {
+                normalize_dates(value, offset);
+            }
+        }
+        Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                normalize_dates(value, offset);
+            }
+        }
+        _ => {}
+    }
+}
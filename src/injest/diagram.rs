@@ -0,0 +1,196 @@
+use crate::injest::static_file::hash_file;
+use color_eyre::{Report, Result};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{create_dir_all, read_to_string, write};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use tracing::warn;
+
+pub trait DiagramRenderer: Send + Sync {
+    // source in, standalone svg out
+    fn render(&self, source: &str) -> Result<String>;
+
+    // anything besides the source that changes the svg, cached diagrams are keyed by it too
+    fn cache_key(&self) -> String {
+        String::new()
+    }
+}
+
+// anything that reads the diagram on stdin and writes svg to stdout
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandRenderer {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl DiagramRenderer for CommandRenderer {
+    fn render(&self, source: &str) -> Result<String> {
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // written from its own thread, a renderer that fills its stdout pipe before it's done
+        // reading would otherwise wait on us while we wait on it
+        let writer = child.stdin.take().map(|mut stdin| {
+            let source = source.to_string();
+            thread::spawn(move || stdin.write_all(source.as_bytes()))
+        });
+
+        let output = child.wait_with_output()?;
+        let written = match writer {
+            Some(writer) => writer
+                .join()
+                .map_err(|_| Report::msg(format!("writing to {} panicked", self.command)))?,
+            None => Ok(()),
+        };
+        if !output.status.success() {
+            return Err(Report::msg(format!(
+                "{} exited with {}: {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+        written?;
+        Ok(String::from_utf8(output.stdout)?)
+    }
+
+    fn cache_key(&self) -> String {
+        std::iter::once(&self.command)
+            .chain(&self.args)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\0")
+    }
+}
+
+fn default_renderers() -> BTreeMap<String, CommandRenderer> {
+    let renderer = |command: &str, args: &[&str]| CommandRenderer {
+        command: command.to_string(),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+    };
+
+    BTreeMap::from([
+        (
+            "mermaid".to_string(),
+            renderer("mmdc", &["--input", "-", "--output", "-", "--outputFormat", "svg"]),
+        ),
+        ("graphviz".to_string(), renderer("dot", &["-Tsvg"])),
+        ("dot".to_string(), renderer("dot", &["-Tsvg"])),
+        ("plantuml".to_string(), renderer("plantuml", &["-tsvg", "-pipe"])),
+    ])
+}
+
+// `[diagrams]` in site.toml, code fence language -> renderer. the defaults can be overridden or
+// turned off by setting `enabled = false`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiagramOptions {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub renderers: BTreeMap<String, CommandRenderer>,
+}
+
+impl Default for DiagramOptions {
+    fn default() -> Self {
+        DiagramOptions {
+            enabled: true,
+            renderers: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+pub struct Diagrams {
+    renderers: HashMap<String, Box<dyn DiagramRenderer>>,
+    cache: DashMap<u64, String>,
+    cache_dir: Option<PathBuf>,
+}
+
+impl Diagrams {
+    pub fn new(options: &DiagramOptions, cache_dir: Option<PathBuf>) -> Diagrams {
+        let mut renderers: HashMap<String, Box<dyn DiagramRenderer>> = HashMap::new();
+        if options.enabled {
+            let mut configured = default_renderers();
+            configured.extend(options.renderers.clone());
+            for (language, renderer) in configured {
+                renderers.insert(language, Box::new(renderer));
+            }
+        }
+
+        Diagrams {
+            renderers,
+            cache: DashMap::new(),
+            cache_dir,
+        }
+    }
+
+    pub fn register(&mut self, language: impl Into<String>, renderer: Box<dyn DiagramRenderer>) {
+        self.renderers.insert(language.into(), renderer);
+    }
+
+    pub fn handles(&self, language: &str) -> bool {
+        self.renderers.contains_key(language)
+    }
+
+    fn cache_path(&self, hash: u64) -> Option<PathBuf> {
+        self.cache_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{hash:016x}.svg")))
+    }
+
+    // the svg, ready to be inlined. results are cached by language + renderer + source, across
+    // builds if there is a cache dir.
+    pub fn render(&self, language: &str, source: &str) -> Result<String> {
+        let renderer = match self.renderers.get(language) {
+            Some(renderer) => renderer,
+            None => return Err(Report::msg(format!("no diagram renderer for {language}"))),
+        };
+
+        let hash = hash_file(format!("{language}\0{}\0{source}", renderer.cache_key()));
+        if let Some(svg) = self.cache.get(&hash) {
+            return Ok(svg.clone());
+        }
+        if let Some(svg) = self.cache_path(hash).and_then(|path| read_to_string(path).ok()) {
+            self.cache.insert(hash, svg.clone());
+            return Ok(svg);
+        }
+
+        let svg = strip_prolog(&renderer.render(source)?).to_string();
+        if !svg.starts_with("<svg") {
+            return Err(Report::msg(format!("{language} renderer did not produce an svg")));
+        }
+
+        if let Some(path) = self.cache_path(hash) {
+            if let Err(why) = path
+                .parent()
+                .map(create_dir_all)
+                .transpose()
+                .and_then(|_| write(&path, &svg))
+            {
+                warn!("failed to cache diagram at {}: {why}", path.display());
+            }
+        }
+        self.cache.insert(hash, svg.clone());
+        Ok(svg)
+    }
+}
+
+// inline svg can't have an xml declaration or doctype in front of it
+fn strip_prolog(svg: &str) -> &str {
+    match svg.find("<svg") {
+        Some(start) => svg[start..].trim_end(),
+        None => svg.trim(),
+    }
+}
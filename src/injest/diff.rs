@@ -0,0 +1,179 @@
+use crate::models::{build_diff, page_hash};
+use color_eyre::Result;
+use schemars::JsonSchema;
+use sea_orm::{DatabaseConnection, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffKind {
+    Added,
+    Updated,
+    Removed,
+}
+
+impl DiffKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiffKind::Added => "added",
+            DiffKind::Updated => "updated",
+            DiffKind::Removed => "removed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<DiffKind> {
+        match name {
+            "added" => Some(DiffKind::Added),
+            "updated" => Some(DiffKind::Updated),
+            "removed" => Some(DiffKind::Removed),
+            _ => None,
+        }
+    }
+}
+
+// one page that is different from the build before, by the hash of its sources
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SiteContentDiffElem {
+    pub slug: String,
+    pub kind: DiffKind,
+    pub old_hash: Option<String>,
+    pub new_hash: Option<String>,
+}
+
+// what changed going from the pages of one build to the next, by slug
+pub fn diff_pages(
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+) -> Vec<SiteContentDiffElem> {
+    let mut diff = vec![];
+    for (slug, new_hash) in new {
+        let kind = match old.get(slug) {
+            None => DiffKind::Added,
+            Some(old_hash) if old_hash != new_hash => DiffKind::Updated,
+            Some(_) => continue,
+        };
+        diff.push(SiteContentDiffElem {
+            slug: slug.clone(),
+            kind,
+            old_hash: old.get(slug).cloned(),
+            new_hash: Some(new_hash.clone()),
+        });
+    }
+    for (slug, old_hash) in old {
+        if !new.contains_key(slug) {
+            diff.push(SiteContentDiffElem {
+                slug: slug.clone(),
+                kind: DiffKind::Removed,
+                old_hash: Some(old_hash.clone()),
+                new_hash: None,
+            });
+        }
+    }
+    diff.sort_by(|a, b| a.slug.cmp(&b.slug));
+    diff
+}
+
+pub fn slugs(diff: &[SiteContentDiffElem], kinds: &[DiffKind]) -> Vec<String> {
+    diff.iter()
+        .filter(|elem| kinds.contains(&elem.kind))
+        .map(|elem| elem.slug.clone())
+        .collect()
+}
+
+// Diffs the pages of a finished build against the last one, keeps the diff under the build's id and
+// the pages as the base for the next build. Everything that reacts to changed pages goes off this.
+pub async fn record_diff(
+    db: &DatabaseConnection,
+    build_id: u64,
+    pages: &BTreeMap<String, String>,
+) -> Result<Vec<SiteContentDiffElem>> {
+    let txn = db.begin().await?;
+    let previous = page_hash::all(&txn).await?;
+    let diff = diff_pages(&previous, pages);
+    build_diff::save(&txn, build_id, &diff).await?;
+    page_hash::replace(&txn, pages).await?;
+    txn.commit().await?;
+    Ok(diff)
+}
+
+// a category directory that was renamed between two builds
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CategoryRename {
+    pub from: String,
+    pub to: String,
+    // old -> new site path of every page that was under it
+    pub pages: Vec<(String, String)>,
+}
+
+// `/blog/rustlang/intro` -> `/blog/rust/intro` is `/blog/rustlang` -> `/blog/rust`, the path
+// up to the segments both end with
+fn moved_prefix<'a>(from: &'a str, to: &'a str) -> Option<(&'a str, &'a str)> {
+    let (mut from_end, mut to_end) = (from.len(), to.len());
+    while let (Some(from_start), Some(to_start)) =
+        (from[..from_end].rfind('/'), to[..to_end].rfind('/'))
+    {
+        if from[from_start..from_end] != to[to_start..to_end] {
+            break;
+        }
+        (from_end, to_end) = (from_start, to_start);
+    }
+    let (from, to) = (&from[..from_end], &to[..to_end]);
+    match from.is_empty() || to.is_empty() {
+        true => None,
+        false => Some((from, to)),
+    }
+}
+
+// Categories renamed from one build to the next, found by the pages that disappeared under one
+// path and showed up under another with the same sources. A category only counts as renamed when
+// nothing is left under its old path, a few pages moved out of one is left alone. A page directory
+// that was renamed on its own comes out as a category of one page.
+pub fn category_renames(
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+) -> Vec<CategoryRename> {
+    let mut added: HashMap<&String, Vec<&String>> = HashMap::new();
+    for (slug, hash) in new.iter().filter(|(slug, _)| !old.contains_key(*slug)) {
+        added.entry(hash).or_default().push(slug);
+    }
+    let removed = old
+        .iter()
+        .filter(|(slug, _)| !new.contains_key(*slug))
+        .collect::<Vec<_>>();
+
+    let mut candidates = BTreeSet::new();
+    for (slug, hash) in &removed {
+        if let Some([moved]) = added.get(hash).map(Vec::as_slice) {
+            if let Some((from, to)) = moved_prefix(slug, moved) {
+                candidates.insert((from.to_string(), to.to_string()));
+            }
+        }
+    }
+
+    let under = |slug: &str, prefix: &str| {
+        slug == prefix
+            || slug
+                .strip_prefix(prefix)
+                .map_or(false, |rest| rest.starts_with('/'))
+    };
+    let mut renames: Vec<CategoryRename> = vec![];
+    for (from, to) in candidates {
+        if new.keys().any(|slug| under(slug, &from))
+            || renames.iter().any(|rename| under(&from, &rename.from))
+        {
+            continue;
+        }
+        // pages edited in the same commit as the rename don't match by hash, but still moved
+        let pages = removed
+            .iter()
+            .filter(|(slug, _)| under(slug, &from))
+            .map(|(slug, _)| (slug.to_string(), format!("{to}{}", &slug[from.len()..])))
+            .filter(|(_, moved)| new.contains_key(moved))
+            .collect::<Vec<_>>();
+        if !pages.is_empty() {
+            renames.push(CategoryRename { from, to, pages });
+        }
+    }
+    renames
+}
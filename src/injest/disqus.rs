@@ -0,0 +1,253 @@
+use crate::injest::links::SiteUrl;
+use crate::models::{comment, page_hash, redirect};
+use chrono::{DateTime, Utc};
+use color_eyre::{Report, Result};
+use roxmltree::{Document, Node};
+use sea_orm::DatabaseConnection;
+use std::collections::{BTreeMap, BTreeSet};
+
+// the namespace of `dsq:id`, which is what posts point at their thread and parent with
+const DISQUS_INTERNALS: &str = "http://disqus.com/disqus-internals";
+
+// redirects are followed this far before a thread counts as unmatched
+const MAX_REDIRECTS: usize = 8;
+
+// a <thread> of a Disqus export, one for every page that was ever embedded on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisqusThread {
+    pub id: String,
+    // what the page was embedded with, often its path on the old site
+    pub identifier: Option<String>,
+    pub link: String,
+    pub title: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisqusPost {
+    pub id: String,
+    pub thread: String,
+    // the post this one replies to
+    pub parent: Option<String>,
+    pub author: String,
+    pub email: Option<String>,
+    // html, as Disqus stored it
+    pub message: String,
+    pub created_at: DateTime<Utc>,
+    // neither deleted nor spam
+    pub approved: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DisqusExport {
+    pub threads: Vec<DisqusThread>,
+    pub posts: Vec<DisqusPost>,
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child(node, name)
+        .and_then(|child| child.text())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+}
+
+fn disqus_id(node: Node) -> Option<String> {
+    node.attribute((DISQUS_INTERNALS, "id"))
+        .map(ToString::to_string)
+}
+
+fn thread(node: Node) -> Result<DisqusThread> {
+    Ok(DisqusThread {
+        id: disqus_id(node).ok_or_else(|| Report::msg("a <thread> has no dsq:id"))?,
+        identifier: child_text(node, "id").map(ToString::to_string),
+        link: child_text(node, "link").unwrap_or_default().to_string(),
+        title: child_text(node, "title").unwrap_or_default().to_string(),
+    })
+}
+
+fn post(node: Node) -> Result<DisqusPost> {
+    let id = disqus_id(node).ok_or_else(|| Report::msg("a <post> has no dsq:id"))?;
+    let thread = child(node, "thread")
+        .and_then(disqus_id)
+        .ok_or_else(|| Report::msg(format!("post {id} isn't in a thread")))?;
+    let created_at = match child_text(node, "createdAt") {
+        Some(date) => DateTime::parse_from_rfc3339(date)?.with_timezone(&Utc),
+        None => return Err(Report::msg(format!("post {id} has no createdAt"))),
+    };
+    let author = child(node, "author")
+        .and_then(|author| child_text(author, "name").or_else(|| child_text(author, "username")))
+        .unwrap_or("Anonymous")
+        .to_string();
+    let email = child(node, "author")
+        .and_then(|author| child_text(author, "email"))
+        .map(ToString::to_string);
+    let flagged = |name: &str| child_text(node, name) == Some("true");
+    Ok(DisqusPost {
+        thread,
+        parent: child(node, "parent").and_then(disqus_id),
+        author,
+        email,
+        message: child_text(node, "message").unwrap_or_default().to_string(),
+        created_at,
+        approved: !flagged("isDeleted") && !flagged("isSpam"),
+        id,
+    })
+}
+
+// The threads and posts of a Disqus xml export, categories and everything else are left out.
+pub fn parse_export(xml: &str) -> Result<DisqusExport> {
+    let document = Document::parse(xml)?;
+    let mut export = DisqusExport::default();
+    for node in document.root_element().children().filter(Node::is_element) {
+        match node.tag_name().name() {
+            "thread" => export.threads.push(thread(node)?),
+            "post" => export.posts.push(post(node)?),
+            _ => {}
+        }
+    }
+    Ok(export)
+}
+
+// `/2019/01/post/?utm_source=x` and `/2019/01/post/index.html` are both `/2019/01/post`
+fn normalize(path: &str) -> String {
+    let path = path
+        .split(|c| c == '?' || c == '#')
+        .next()
+        .unwrap_or_default();
+    let path = path.strip_suffix("index.html").unwrap_or(path);
+    match url_escape::decode(path).trim_end_matches('/') {
+        "" => "/".to_string(),
+        path => path.to_string(),
+    }
+}
+
+// where a redirect goes, as a site path, None when it leaves the site
+fn redirect_target(to: &str, urls: &SiteUrl) -> Option<String> {
+    let path = to.strip_prefix(&urls.origin()).unwrap_or(to);
+    if !path.starts_with('/') || path.starts_with("//") {
+        return None;
+    }
+    urls.strip_base(path).map(normalize)
+}
+
+// The page a thread's comments belong on, by the path of its link (on whatever site it was) and
+// then its identifier, through the site's redirects from old urls. None if neither gets to a page.
+pub fn match_thread(
+    thread: &DisqusThread,
+    pages: &BTreeSet<String>,
+    redirects: &BTreeMap<String, String>,
+    urls: &SiteUrl,
+) -> Option<String> {
+    let from_link = url::Url::parse(&thread.link).ok().map(|link| {
+        let path = match link.origin() == urls.base().origin() {
+            true => urls.strip_base(link.path()).unwrap_or(link.path()),
+            false => link.path(),
+        };
+        normalize(path)
+    });
+    let from_identifier = thread
+        .identifier
+        .as_deref()
+        .filter(|identifier| identifier.starts_with('/'))
+        .map(normalize);
+
+    from_link
+        .into_iter()
+        .chain(from_identifier)
+        .find_map(|mut path| {
+            for _ in 0..=MAX_REDIRECTS {
+                if pages.contains(&path) {
+                    return Some(path);
+                }
+                path = redirect_target(redirects.get(&path)?, urls)?;
+            }
+            None
+        })
+}
+
+// a thread with approved comments, and where they went
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThreadOutcome {
+    pub thread: DisqusThread,
+    pub comments: usize,
+    // None for a thread that matched no page, its comments aren't imported
+    pub page: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DisqusImport {
+    pub threads: Vec<ThreadOutcome>,
+    // comments inserted, the ones an earlier import brought over already don't count
+    pub imported: u64,
+}
+
+impl DisqusImport {
+    pub fn unmatched(&self) -> impl Iterator<Item = &ThreadOutcome> {
+        self.threads.iter().filter(|outcome| outcome.page.is_none())
+    }
+}
+
+// Imports the approved comments of every thread that matches a page of the last build, with
+// their authors and times as Disqus had them. `dry_run` only matches threads.
+pub async fn import_disqus(
+    db: &DatabaseConnection,
+    export: &DisqusExport,
+    urls: &SiteUrl,
+    dry_run: bool,
+) -> Result<DisqusImport> {
+    let pages = page_hash::all(db)
+        .await?
+        .into_keys()
+        .collect::<BTreeSet<_>>();
+    let redirects = redirect::all(db).await?;
+
+    let mut counts = BTreeMap::<&str, usize>::new();
+    for post in export.posts.iter().filter(|post| post.approved) {
+        *counts.entry(post.thread.as_str()).or_default() += 1;
+    }
+    let mut threads = vec![];
+    let mut pages_of = BTreeMap::new();
+    for thread in &export.threads {
+        let comments = match counts.get(thread.id.as_str()) {
+            Some(comments) => *comments,
+            None => continue,
+        };
+        let page = match_thread(thread, &pages, &redirects, urls);
+        if let Some(page) = &page {
+            pages_of.insert(thread.id.as_str(), page.clone());
+        }
+        threads.push(ThreadOutcome {
+            thread: thread.clone(),
+            comments,
+            page,
+        });
+    }
+
+    let comments = export
+        .posts
+        .iter()
+        .filter(|post| post.approved)
+        .filter_map(|post| {
+            Some(comment::ImportedComment {
+                source: format!("disqus:{}", post.id),
+                parent: post
+                    .parent
+                    .as_ref()
+                    .map(|parent| format!("disqus:{parent}")),
+                page: pages_of.get(post.thread.as_str())?.clone(),
+                author: post.author.clone(),
+                email: post.email.clone(),
+                body: post.message.clone(),
+                created_at: post.created_at,
+            })
+        })
+        .collect::<Vec<_>>();
+    let imported = match dry_run {
+        true => 0,
+        false => comment::import(db, comments).await?,
+    };
+    Ok(DisqusImport { threads, imported })
+}
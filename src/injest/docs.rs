@@ -0,0 +1,178 @@
+use crate::injest::links::SiteUrl;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// a page of a docs category, as the build found it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DocsPage {
+    pub path: String,
+    pub title: String,
+    pub weight: i64,
+}
+
+// a page the reader can go to from the sidebar or the previous and next links
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct DocsLink {
+    pub title: String,
+    pub url: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct SidebarItem {
+    pub title: String,
+    pub url: String,
+    // the page being rendered
+    pub current: bool,
+    // the page being rendered or one of the pages above it, for keeping its branch unfolded
+    pub open: bool,
+    pub children: Vec<SidebarItem>,
+}
+
+// what a page of a docs category gets as `page.docs`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct DocsNav {
+    // the category itself, what the sidebar is the contents of
+    pub root: DocsLink,
+    pub sidebar: Vec<SidebarItem>,
+    // the neighbours in reading order, the sidebar read from top to bottom
+    pub previous: Option<DocsLink>,
+    pub next: Option<DocsLink>,
+}
+
+// One category with `docs = true`. Its pages nest like their directories do, a page's parent is
+// the closest page above it, and siblings are ordered by `weight` then title.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DocsTree {
+    root: String,
+    title: String,
+    pages: BTreeMap<String, DocsPage>,
+    // site path -> its children in order, the root's are the top level of the sidebar
+    children: BTreeMap<String, Vec<String>>,
+    reading_order: Vec<String>,
+}
+
+fn parent_path(path: &str) -> Option<&str> {
+    match path.trim_end_matches('/').rsplit_once('/') {
+        Some(("", _)) => Some("/"),
+        Some((parent, _)) => Some(parent),
+        None => None,
+    }
+}
+
+impl DocsTree {
+    pub fn new(root: &str, title: &str, pages: Vec<DocsPage>) -> DocsTree {
+        let pages = pages
+            .into_iter()
+            .filter(|page| page.path != root)
+            .map(|page| (page.path.clone(), page))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut children = BTreeMap::<String, Vec<String>>::new();
+        for path in pages.keys() {
+            // a directory without a page of its own is skipped over
+            let mut parent = parent_path(path).unwrap_or(root);
+            while parent != root && !pages.contains_key(parent) {
+                parent = parent_path(parent).unwrap_or(root);
+            }
+            children
+                .entry(parent.to_string())
+                .or_default()
+                .push(path.clone());
+        }
+        for siblings in children.values_mut() {
+            siblings.sort_by(|a, b| {
+                let (a, b) = (&pages[a], &pages[b]);
+                (a.weight, &a.title, &a.path).cmp(&(b.weight, &b.title, &b.path))
+            });
+        }
+
+        let mut tree = DocsTree {
+            root: root.to_string(),
+            title: title.to_string(),
+            pages,
+            children,
+            reading_order: vec![],
+        };
+        let mut reading_order = vec![];
+        tree.walk(root, &mut reading_order);
+        tree.reading_order = reading_order;
+        tree
+    }
+
+    fn walk(&self, path: &str, into: &mut Vec<String>) {
+        for child in self.children.get(path).into_iter().flatten() {
+            into.push(child.clone());
+            self.walk(child, into);
+        }
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.pages.contains_key(path)
+    }
+
+    fn link(&self, path: &str, urls: &SiteUrl) -> DocsLink {
+        DocsLink {
+            title: self.pages[path].title.clone(),
+            url: urls.absolute(path),
+        }
+    }
+
+    fn items(&self, parent: &str, current: &str, urls: &SiteUrl) -> Vec<SidebarItem> {
+        self.children
+            .get(parent)
+            .into_iter()
+            .flatten()
+            .map(|path| SidebarItem {
+                title: self.pages[path].title.clone(),
+                url: urls.absolute(path),
+                current: path == current,
+                open: current == path || current.starts_with(&format!("{path}/")),
+                children: self.items(path, current, urls),
+            })
+            .collect()
+    }
+
+    // the root of the category gets the sidebar too, and the first page as its next
+    pub fn nav(&self, path: &str, urls: &SiteUrl) -> DocsNav {
+        let position = self.reading_order.iter().position(|page| page == path);
+        let (previous, next) = match position {
+            Some(position) => (
+                position
+                    .checked_sub(1)
+                    .map(|before| &self.reading_order[before]),
+                self.reading_order.get(position + 1),
+            ),
+            None => (None, self.reading_order.first()),
+        };
+        DocsNav {
+            root: DocsLink {
+                title: self.title.clone(),
+                url: urls.absolute(&self.root),
+            },
+            sidebar: self.items(&self.root, path, urls),
+            previous: previous.map(|previous| self.link(previous, urls)),
+            next: next.map(|next| self.link(next, urls)),
+        }
+    }
+}
+
+// every docs category of the site, by the site path of the category
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DocsTrees {
+    trees: BTreeMap<String, DocsTree>,
+}
+
+impl DocsTrees {
+    pub fn insert(&mut self, tree: DocsTree) {
+        self.trees.insert(tree.root.clone(), tree);
+    }
+
+    // None for pages outside of docs categories
+    pub fn nav(&self, path: &str, urls: &SiteUrl) -> Option<DocsNav> {
+        self.trees
+            .values()
+            .find(|tree| tree.root == path || tree.contains(path))
+            .map(|tree| tree.nav(path, urls))
+    }
+}
@@ -0,0 +1,148 @@
+use crate::injest::{
+    generate::insert_nested, links::SiteUrl, report::BuildReport, site::SiteVariables,
+};
+use crate::util::stream_file;
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{copy, create_dir_all, read_dir, read_to_string, write};
+use std::path::Path;
+use tera::{Context, Tera};
+
+// files in here are offered as downloads, each one can have a `<file>.toml` next to it
+pub const DOWNLOADS_DIR: &str = "downloads";
+// rendered to /downloads/ if the theme has it
+pub const DOWNLOADS_TEMPLATE: &str = "downloads.html";
+
+// download.zip.toml
+//
+// title = "moklog 0.1"
+// description = "the source, as released"
+// version = "0.1.0"
+// show_checksum = true
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadMeta {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub version: Option<String>,
+    #[serde(default)]
+    pub show_checksum: bool,
+}
+
+// what `downloads.html` gets as `downloads`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Download {
+    pub file_name: String,
+    pub url: String,
+    pub size: u64,
+    // lowercase hex sha256 of the file
+    pub sha256: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub version: Option<String>,
+    pub show_checksum: bool,
+}
+
+fn sidecar_name(file_name: &str) -> String {
+    format!("{file_name}.toml")
+}
+
+pub fn collect_downloads(
+    site_build_path: impl AsRef<Path>,
+    urls: &SiteUrl,
+    report: &mut BuildReport,
+) -> Result<Vec<Download>> {
+    let dir = site_build_path.as_ref().join(DOWNLOADS_DIR);
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut names = vec![];
+    for entry in read_dir(&dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+
+    let mut downloads = vec![];
+    for name in &names {
+        if let Some(described) = name.strip_suffix(".toml") {
+            if !names.iter().any(|other| other == described) {
+                report.warn(
+                    dir.join(name),
+                    format!("describes {described}, which does not exist"),
+                );
+            }
+            continue;
+        }
+
+        let path = dir.join(name);
+        let sidecar = dir.join(sidecar_name(name));
+        let meta = match sidecar.exists() {
+            true => match toml::from_str::<DownloadMeta>(&read_to_string(&sidecar)?) {
+                Ok(meta) => meta,
+                Err(why) => {
+                    report.error(&sidecar, format!("invalid download description: {why}"));
+                    DownloadMeta::default()
+                }
+            },
+            false => DownloadMeta::default(),
+        };
+
+        let mut checksum = Sha256::new();
+        let size = stream_file(&path, |chunk| checksum.update(chunk))?;
+        downloads.push(Download {
+            file_name: name.clone(),
+            url: urls.link(&format!("/{DOWNLOADS_DIR}/{name}")),
+            size,
+            sha256: format!("{:x}", checksum.finalize()),
+            title: meta.title.unwrap_or_else(|| name.clone()),
+            description: meta.description,
+            version: meta.version,
+            show_checksum: meta.show_checksum,
+        });
+    }
+
+    Ok(downloads)
+}
+
+// copies the files over and renders the index, if the theme has a template for it
+pub fn write_downloads(
+    site_build_path: impl AsRef<Path>,
+    site_output_path: impl AsRef<Path>,
+    downloads: &[Download],
+    tera: &Tera,
+    site: &SiteVariables,
+) -> Result<()> {
+    if downloads.is_empty() {
+        return Ok(());
+    }
+
+    let from = site_build_path.as_ref().join(DOWNLOADS_DIR);
+    let to = site_output_path.as_ref().join(DOWNLOADS_DIR);
+    create_dir_all(&to)?;
+    for download in downloads {
+        copy(from.join(&download.file_name), to.join(&download.file_name))?;
+    }
+
+    if tera
+        .get_template_names()
+        .any(|name| name == DOWNLOADS_TEMPLATE)
+    {
+        let mut context = Context::new();
+        insert_nested(&mut context, "site.title", &site.title);
+        insert_nested(&mut context, "site.base_url", &site.base_url);
+        insert_nested(&mut context, "site.theme", &site.theme);
+        context.insert("downloads", downloads);
+        write(
+            to.join("index.html"),
+            tera.render(DOWNLOADS_TEMPLATE, &context)?,
+        )?;
+    }
+    Ok(())
+}
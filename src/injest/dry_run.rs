@@ -0,0 +1,90 @@
+use crate::config::Config;
+use crate::injest::{
+    build::build_site,
+    check::check_site,
+    diff::{diff_pages, SiteContentDiffElem},
+    report::{Diagnostic, Severity},
+    site::{SiteMeta, SITE_FILE},
+    templates::build_site_theme,
+    translation::TranslationStatus,
+};
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{read_to_string, remove_dir_all};
+use std::path::Path;
+
+// what `moklog check --json` prints, made to be read by ci
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DryRunReport {
+    pub ok: bool,
+    pub errors: Vec<Diagnostic>,
+    pub warnings: Vec<Diagnostic>,
+    #[serde(default)]
+    pub translations: Vec<TranslationStatus>,
+    // only with a theme to build against, empty otherwise
+    #[serde(default)]
+    pub pages: BTreeMap<String, String>,
+    // against the pages of `base`, every page counts as added without one
+    #[serde(default)]
+    pub diff: Vec<SiteContentDiffElem>,
+}
+
+// The checks of `check`, and with a theme the whole build as well. The build goes to a throwaway
+// directory and nothing touches the serve dir, the database or the search index. `base` is the
+// report of an earlier run, the diff is against its pages.
+pub async fn dry_run(
+    site_build_path: &Path,
+    theme: Option<&str>,
+    base: Option<&Path>,
+) -> Result<DryRunReport> {
+    let mut report = check_site(site_build_path)?;
+    let mut pages = BTreeMap::new();
+
+    if let Some(theme) = theme {
+        let output = std::env::temp_dir().join(format!("moklog-dry-run-{}", std::process::id()));
+        let built = async {
+            let config = Config::offline()?;
+            let site = SiteMeta::load(site_build_path)?;
+            let theme = build_site_theme(theme).await?;
+            let site_build_path = site_build_path.to_path_buf();
+            let output = output.clone();
+            tokio::task::spawn_blocking(move || {
+                build_site(&site_build_path, &output, &config, &site, &theme, None)
+            })
+            .await?
+        }
+        .await;
+        let _ = remove_dir_all(&output);
+
+        match built {
+            Ok(built) => {
+                report.extend(built.report);
+                pages = built.pages;
+            }
+            Err(why) => report.error(
+                site_build_path.join(SITE_FILE),
+                format!("build failed: {why}"),
+            ),
+        }
+    }
+
+    let base_pages = match base {
+        Some(base) => serde_json::from_str::<DryRunReport>(&read_to_string(base)?)?.pages,
+        None => BTreeMap::new(),
+    };
+    let report = report.sorted();
+    let (errors, warnings) = report
+        .diagnostics
+        .into_iter()
+        .partition::<Vec<_>, _>(|diagnostic| diagnostic.severity == Severity::Error);
+
+    Ok(DryRunReport {
+        ok: errors.is_empty(),
+        errors,
+        warnings,
+        translations: report.translations,
+        diff: diff_pages(&base_pages, &pages),
+        pages,
+    })
+}
@@ -0,0 +1,98 @@
+use crate::injest::{
+    build::SPLITTER,
+    generate::{toml_v_to_json_v, PageHeader},
+};
+use color_eyre::Result;
+use pulldown_cmark::{html, Parser};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{read_to_string, write};
+use std::path::Path;
+
+// written next to the manifest, what the server renders per request instead of serving a file
+pub const DYNAMIC_FILE: &str = "dynamic.json";
+// the theme's template for dynamic pages that don't name their own
+pub const DYNAMIC_TEMPLATE: &str = "dynamic.html";
+// seconds a render is kept for the same request
+pub const DEFAULT_TTL: u64 = 60;
+
+// `[page_type.DynamicMeta]`, a page whose template renders when it's requested, with the query
+// and the viewer in its context
+//
+// [page_type.DynamicMeta]
+// title = "Latest comments"
+// template = "comments.html"
+// ttl = 10
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DynamicMeta {
+    pub title: String,
+    pub template: Option<String>,
+    pub ttl: Option<u64>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+// everything about a dynamic page that is known at build time
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DynamicPage {
+    pub template: String,
+    pub ttl: u64,
+    pub title: String,
+    pub authors: Vec<String>,
+    pub tags: Vec<String>,
+    // the body below the front matter, and it rendered as markdown
+    pub raw: String,
+    pub html: String,
+    pub custom: BTreeMap<String, serde_json::Value>,
+}
+
+impl DynamicPage {
+    pub fn new(meta: &DynamicMeta, header: &PageHeader, source: &str) -> DynamicPage {
+        let raw = source
+            .split_once(SPLITTER)
+            .map_or("", |(_, body)| body)
+            .to_string();
+        let mut rendered = String::with_capacity(raw.len());
+        html::push_html(&mut rendered, Parser::new(&raw));
+        DynamicPage {
+            template: meta
+                .template
+                .clone()
+                .unwrap_or_else(|| DYNAMIC_TEMPLATE.to_string()),
+            ttl: meta.ttl.unwrap_or(DEFAULT_TTL),
+            title: meta.title.clone(),
+            authors: meta.authors.clone(),
+            tags: meta.tags.clone(),
+            raw,
+            html: rendered,
+            custom: header
+                .custom
+                .data
+                .iter()
+                .map(|(key, value)| (key.clone(), toml_v_to_json_v(value.clone())))
+                .collect(),
+        }
+    }
+}
+
+// site path -> page
+pub type DynamicPages = BTreeMap<String, DynamicPage>;
+
+pub fn write_dynamic_pages(output: impl AsRef<Path>, pages: &DynamicPages) -> Result<()> {
+    write(
+        output.as_ref().join(DYNAMIC_FILE),
+        serde_json::to_string(pages)?,
+    )?;
+    Ok(())
+}
+
+// none before the first build that knows about them
+pub fn read_dynamic_pages(serve_dir: impl AsRef<Path>) -> Result<DynamicPages> {
+    let path = serve_dir.as_ref().join(DYNAMIC_FILE);
+    if !path.is_file() {
+        return Ok(DynamicPages::new());
+    }
+    Ok(serde_json::from_str(&read_to_string(path)?)?)
+}
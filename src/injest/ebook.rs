@@ -0,0 +1,443 @@
+use crate::injest::{
+    assets::{resolve_asset, AssetStore},
+    generate::MarkdownOptions,
+    include::expand_includes,
+    links::SiteUrl,
+    markup::{render_markup, Markup},
+    static_file::hash_file,
+};
+use crate::CACHE_DIR;
+use chrono::{DateTime, Datelike, FixedOffset, Utc};
+use color_eyre::{Report, Result};
+use html_escape::{encode_double_quoted_attribute, encode_text};
+use lol_html::{element, rewrite_str, Settings};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{copy, create_dir_all, read, write};
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+// `[build.ebooks]`, an epub of every completed series
+//
+// [build.ebooks]
+// pdf = { command = "ebook-convert", args = ["{input}", "{output}"] }
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EbookOptions {
+    // turns the epub into a pdf as well
+    pub pdf: Option<PdfCommand>,
+}
+
+// `{input}` and `{output}` in the args are the epub and the pdf it should write
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PdfCommand {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl PdfCommand {
+    fn convert(&self, epub: &Path, pdf: &Path) -> Result<()> {
+        let args = self.args.iter().map(|arg| {
+            arg.replace("{input}", &epub.to_string_lossy())
+                .replace("{output}", &pdf.to_string_lossy())
+        });
+        let output = Command::new(&self.command).args(args).output()?;
+        if !output.status.success() {
+            return Err(Report::msg(format!(
+                "{} exited with {}: {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+        if !pdf.is_file() {
+            return Err(Report::msg(format!("{} wrote no pdf", self.command)));
+        }
+        Ok(())
+    }
+}
+
+// a page under a series, as the build found it
+#[derive(Clone, Debug, PartialEq)]
+pub struct BookPart {
+    pub path: String,
+    // relative to the content root
+    pub source: PathBuf,
+    pub markup: Markup,
+    pub title: String,
+    pub date: Option<DateTime<FixedOffset>>,
+    pub weight: i64,
+    // the source without its front matter
+    pub body: String,
+}
+
+// a completed series and its parts, in reading order
+pub struct Book<'a> {
+    pub path: &'a str,
+    pub title: &'a str,
+    pub authors: &'a [String],
+    pub date_started: DateTime<FixedOffset>,
+    pub date_completed: Option<DateTime<FixedOffset>>,
+    pub language: &'a str,
+    pub parts: Vec<&'a BookPart>,
+}
+
+// what a series page gets as `page.ebook`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct EbookLinks {
+    pub epub: String,
+    // unset without `[build.ebooks] pdf`
+    pub pdf: Option<String>,
+}
+
+// the parts of a series are the pages under it, by weight, then date
+pub fn book_parts<'a>(series: &str, pages: &'a [BookPart]) -> Vec<&'a BookPart> {
+    let prefix = format!("{}/", series.trim_end_matches('/'));
+    let mut parts = pages
+        .iter()
+        .filter(|part| part.path.starts_with(&prefix))
+        .collect::<Vec<_>>();
+    parts.sort_by(|a, b| (a.weight, a.date, &a.path).cmp(&(b.weight, b.date, &b.path)));
+    parts
+}
+
+// epub readers only have to show these
+fn image_type(name: &str) -> Option<&'static str> {
+    match name.rsplit('.').next()?.to_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        "svg" => Some("image/svg+xml"),
+        _ => None,
+    }
+}
+
+struct Chapter {
+    file: String,
+    title: String,
+    xhtml: String,
+}
+
+fn xhtml_document(title: &str, language: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" lang="{language}" xml:lang="{language}">
+<head><meta charset="UTF-8"/><title>{title}</title></head>
+<body>
+{body}
+</body>
+</html>
+"#,
+        language = encode_double_quoted_attribute(language),
+        title = encode_text(title),
+    )
+}
+
+// A part rendered the way its page is, with the images of the content repo it shows carried into
+// the book under the names the site serves them by. Images from other sites are left as links,
+// and raw html in the source has to be xhtml already.
+fn chapter(
+    part: &BookPart,
+    number: usize,
+    language: &str,
+    site_root: &Path,
+    markdown: &MarkdownOptions,
+    assets: &AssetStore,
+    images: &mut BTreeMap<String, PathBuf>,
+) -> Result<Chapter> {
+    let body = expand_includes(&part.body, site_root, &part.source)?;
+    let html = render_markup(part.markup, &body, markdown)?;
+
+    let found = RefCell::new(BTreeMap::new());
+    let html = rewrite_str(
+        &html,
+        Settings {
+            element_content_handlers: vec![element!("img[src]", |el| {
+                let src = el.get_attribute("src").unwrap_or_default();
+                let file = match resolve_asset(site_root, &part.source, &src) {
+                    Some(file) if file.is_file() => file,
+                    _ => return Ok(()),
+                };
+                let asset = assets.add(&file).ok().and_then(|hash| assets.get(hash));
+                if let Some(asset) = asset {
+                    if image_type(&asset.output_name).is_some() {
+                        el.set_attribute("src", &format!("images/{}", asset.output_name))?;
+                        found.borrow_mut().insert(asset.output_name, asset.source);
+                    }
+                }
+                Ok(())
+            })],
+            ..Settings::default()
+        },
+    )?;
+    images.extend(found.into_inner());
+
+    let body = format!("<h1>{}</h1>\n{html}", encode_text(&part.title));
+    Ok(Chapter {
+        file: format!("chapter-{number:03}.xhtml"),
+        title: part.title.clone(),
+        xhtml: xhtml_document(&part.title, language, &body),
+    })
+}
+
+// the title, who wrote it and when, as a portrait svg
+fn cover(book: &Book) -> String {
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in book.title.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + word.chars().count() > 18 {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    lines.push(line);
+
+    let mut svg = String::from(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 600 800" width="600" height="800">
+<rect width="600" height="800" fill="#1f2430"/>
+<rect width="16" height="800" fill="#ff7a59"/>
+"##,
+    );
+    for (at, line) in lines.iter().enumerate() {
+        svg.push_str(&format!(
+            r##"<text x="64" y="{}" font-family="serif" font-size="48" fill="#f0f0f0">{}</text>
+"##,
+            200 + at * 64,
+            encode_text(line)
+        ));
+    }
+    let years = match book.date_completed {
+        Some(completed) if completed.year() != book.date_started.year() => {
+            format!("{}–{}", book.date_started.year(), completed.year())
+        }
+        _ => book.date_started.year().to_string(),
+    };
+    for (at, text) in [book.authors.join(", "), years].iter().enumerate() {
+        svg.push_str(&format!(
+            r##"<text x="64" y="{}" font-family="serif" font-size="28" fill="#f0f0f0">{}</text>
+"##,
+            680 + at * 44,
+            encode_text(text)
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn package(
+    book: &Book,
+    identifier: &str,
+    chapters: &[Chapter],
+    images: &BTreeMap<String, PathBuf>,
+) -> String {
+    let modified = book
+        .date_completed
+        .unwrap_or(book.date_started)
+        .with_timezone(&Utc)
+        .format("%Y-%m-%dT%H:%M:%SZ");
+    let creators = book
+        .authors
+        .iter()
+        .map(|author| format!("<dc:creator>{}</dc:creator>", encode_text(author)))
+        .collect::<String>();
+    let mut manifest = String::from(
+        r#"<item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+<item id="cover" href="cover.svg" media-type="image/svg+xml" properties="cover-image"/>
+<item id="cover-page" href="cover.xhtml" media-type="application/xhtml+xml"/>
+"#,
+    );
+    let mut spine = String::from("<itemref idref=\"cover-page\"/>\n<itemref idref=\"nav\"/>\n");
+    for (at, chapter) in chapters.iter().enumerate() {
+        manifest.push_str(&format!(
+            "<item id=\"chapter-{at}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+            chapter.file
+        ));
+        spine.push_str(&format!("<itemref idref=\"chapter-{at}\"/>\n"));
+    }
+    for (at, name) in images.keys().enumerate() {
+        if let Some(media_type) = image_type(name) {
+            manifest.push_str(&format!(
+                "<item id=\"image-{at}\" href=\"images/{}\" media-type=\"{media_type}\"/>\n",
+                encode_double_quoted_attribute(name)
+            ));
+        }
+    }
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id" xml:lang="{language}">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+<dc:identifier id="id">{identifier}</dc:identifier>
+<dc:title>{title}</dc:title>
+<dc:language>{language}</dc:language>
+{creators}
+<meta property="dcterms:modified">{modified}</meta>
+</metadata>
+<manifest>
+{manifest}</manifest>
+<spine>
+{spine}</spine>
+</package>
+"#,
+        language = encode_text(book.language),
+        identifier = encode_text(identifier),
+        title = encode_text(book.title),
+    )
+}
+
+fn navigation(book: &Book, chapters: &[Chapter]) -> String {
+    let items = chapters
+        .iter()
+        .map(|chapter| {
+            format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                chapter.file,
+                encode_text(&chapter.title)
+            )
+        })
+        .collect::<String>();
+    let body = format!(
+        "<nav epub:type=\"toc\" id=\"toc\">\n<h1>{}</h1>\n<ol>\n{items}</ol>\n</nav>",
+        encode_text(book.title)
+    );
+    xhtml_document(book.title, book.language, &body)
+}
+
+fn archive(files: Vec<(String, Vec<u8>)>) -> Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    // first and stored as is, it's how readers tell an epub from any other zip
+    zip.start_file(
+        "mimetype",
+        FileOptions::default().compression_method(CompressionMethod::Stored),
+    )?;
+    zip.write_all(b"application/epub+zip")?;
+    for (name, contents) in files {
+        zip.start_file(name, FileOptions::default())?;
+        zip.write_all(&contents)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+// the file name of a series' books, `/novels/the-long-way` has `the-long-way.epub`
+fn file_stem(path: &str) -> &str {
+    match path.trim_end_matches('/').rsplit('/').next() {
+        Some(stem) if !stem.is_empty() => stem,
+        _ => "book",
+    }
+}
+
+// Writes the epub of a series next to its page, and the pdf of it with `[build.ebooks] pdf`.
+// Both are cached by everything that goes into them, a series none of whose parts changed isn't
+// zipped or converted again.
+pub fn write_ebook(
+    book: &Book,
+    options: &EbookOptions,
+    site_root: &Path,
+    site_output_path: &Path,
+    markdown: &MarkdownOptions,
+    assets: &AssetStore,
+    urls: &SiteUrl,
+) -> Result<EbookLinks> {
+    let mut images = BTreeMap::new();
+    let mut chapters = Vec::with_capacity(book.parts.len());
+    for (number, part) in book.parts.iter().enumerate() {
+        chapters.push(chapter(
+            part,
+            number + 1,
+            book.language,
+            site_root,
+            markdown,
+            assets,
+            &mut images,
+        )?);
+    }
+    let identifier = urls.absolute(book.path);
+    let package = package(book, &identifier, &chapters, &images);
+    let cover_page = xhtml_document(
+        book.title,
+        book.language,
+        &format!(
+            "<img src=\"cover.svg\" alt=\"{}\"/>",
+            encode_double_quoted_attribute(book.title)
+        ),
+    );
+
+    let mut files = vec![
+        (
+            "META-INF/container.xml".to_string(),
+            br#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>
+"#
+            .to_vec(),
+        ),
+        ("OEBPS/content.opf".to_string(), package.into_bytes()),
+        (
+            "OEBPS/nav.xhtml".to_string(),
+            navigation(book, &chapters).into_bytes(),
+        ),
+        ("OEBPS/cover.svg".to_string(), cover(book).into_bytes()),
+        ("OEBPS/cover.xhtml".to_string(), cover_page.into_bytes()),
+    ];
+    for chapter in chapters {
+        files.push((
+            format!("OEBPS/{}", chapter.file),
+            chapter.xhtml.into_bytes(),
+        ));
+    }
+    for (name, source) in &images {
+        files.push((format!("OEBPS/images/{name}"), read(source)?));
+    }
+
+    // image names carry the hash of the image, so the names are enough
+    let key = files
+        .iter()
+        .flat_map(|(name, contents)| [name.as_bytes(), contents.as_slice()])
+        .chain([toml::to_string(options)?.as_bytes()])
+        .flatten()
+        .copied()
+        .collect::<Vec<u8>>();
+    let key = format!("{:016x}", hash_file(&key));
+    let cache = Path::new(CACHE_DIR).join("ebooks");
+    create_dir_all(&cache)?;
+    let cached_epub = cache.join(format!("{key}.epub"));
+    if !cached_epub.is_file() {
+        write(&cached_epub, archive(files)?)?;
+    }
+
+    let stem = file_stem(book.path);
+    let dir = site_output_path.join(book.path.trim_start_matches('/'));
+    create_dir_all(&dir)?;
+    copy(&cached_epub, dir.join(format!("{stem}.epub")))?;
+    let link = |extension: &str| {
+        urls.link(&format!(
+            "{}/{stem}.{extension}",
+            book.path.trim_end_matches('/')
+        ))
+    };
+
+    let pdf = match &options.pdf {
+        Some(command) => {
+            let cached_pdf = cache.join(format!("{key}.pdf"));
+            if !cached_pdf.is_file() {
+                command.convert(&cached_epub, &cached_pdf)?;
+            }
+            copy(&cached_pdf, dir.join(format!("{stem}.pdf")))?;
+            Some(link("pdf"))
+        }
+        None => None,
+    };
+    Ok(EbookLinks {
+        epub: link("epub"),
+        pdf,
+    })
+}
@@ -0,0 +1,43 @@
+use crate::injest::{generate::insert_nested, site::SiteVariables};
+use color_eyre::Result;
+use std::fs::{create_dir_all, write};
+use std::path::Path;
+use tera::{Context, Tera};
+
+// the theme's page for errors the server answers with, gets `error.status` and `error.title`
+pub const ERROR_TEMPLATE: &str = "error.html";
+// `/error/<status>.html` in the output
+pub const ERROR_DIR: &str = "error";
+
+pub const ERROR_PAGES: &[(u16, &str)] = &[
+    (401, "Sign in required"),
+    (403, "Not allowed"),
+    (404, "Not found"),
+];
+
+// nothing to write if the theme has no error.html, the server falls back to plain text
+pub fn write_error_pages(
+    site_output_path: impl AsRef<Path>,
+    tera: &Tera,
+    site: &SiteVariables,
+) -> Result<()> {
+    if !tera.get_template_names().any(|name| name == ERROR_TEMPLATE) {
+        return Ok(());
+    }
+
+    let dir = site_output_path.as_ref().join(ERROR_DIR);
+    create_dir_all(&dir)?;
+    for (status, title) in ERROR_PAGES {
+        let mut context = Context::new();
+        insert_nested(&mut context, "site.title", &site.title);
+        insert_nested(&mut context, "site.base_url", &site.base_url);
+        insert_nested(&mut context, "site.theme", &site.theme);
+        insert_nested(&mut context, "error.status", status);
+        insert_nested(&mut context, "error.title", title);
+        write(
+            dir.join(format!("{status}.html")),
+            tera.render(ERROR_TEMPLATE, &context)?,
+        )?;
+    }
+    Ok(())
+}
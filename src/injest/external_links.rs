@@ -0,0 +1,129 @@
+use crate::injest::links::SiteUrl;
+use lol_html::html_content::Element;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use url::Url;
+
+// what one domain gets instead of the defaults, anything not set is left as the defaults say
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkOverride {
+    pub rel: Option<Vec<String>>,
+    pub new_tab: Option<bool>,
+    pub class: Option<String>,
+}
+
+// `[build.external_links]` in site.toml, what links off the site get
+//
+// [build.external_links]
+// rel = ["nofollow", "noopener"]
+// new_tab = true
+// class = "external"
+// internal = ["example.org", "docs.example.org"]
+//
+// [build.external_links.domains."github.com"]
+// rel = ["noopener"]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExternalLinkOptions {
+    #[serde(default)]
+    pub rel: Vec<String>,
+    // `target="_blank"`
+    #[serde(default)]
+    pub new_tab: bool,
+    // added to the link's own classes, for an icon in the theme's css
+    pub class: Option<String>,
+    // domains that count as the site itself, along with the host of the base url. a domain covers
+    // its subdomains.
+    #[serde(default)]
+    pub internal: Vec<String>,
+    #[serde(default)]
+    pub domains: BTreeMap<String, LinkOverride>,
+}
+
+fn covers(domain: &str, host: &str) -> bool {
+    let domain = domain.trim_start_matches("*.").to_lowercase();
+    host == domain
+        || host
+            .strip_suffix(&domain)
+            .map_or(false, |rest| rest.ends_with('.'))
+}
+
+impl ExternalLinkOptions {
+    // the most specific override for `host`
+    fn override_for(&self, host: &str) -> Option<&LinkOverride> {
+        self.domains
+            .iter()
+            .filter(|(domain, _)| covers(domain, host))
+            .max_by_key(|(domain, _)| domain.len())
+            .map(|(_, link)| link)
+    }
+
+    fn is_internal(&self, urls: &SiteUrl, host: &str) -> bool {
+        urls.base()
+            .host_str()
+            .map_or(false, |own| covers(own, host))
+            || self.internal.iter().any(|domain| covers(domain, host))
+    }
+}
+
+fn add_tokens(element: &mut Element, attribute: &str, tokens: &[String]) {
+    if tokens.is_empty() {
+        return;
+    }
+    let mut values = element
+        .get_attribute(attribute)
+        .map(|value| {
+            value
+                .split_whitespace()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    for token in tokens {
+        if !values.contains(token) {
+            values.push(token.clone());
+        }
+    }
+    element.set_attribute(attribute, &values.join(" ")).unwrap();
+}
+
+// Links to other http(s) sites get the rel, target and class of the options, merged with whatever
+// the link already has. Links the source already gave a target keep it.
+pub fn annotate_external_link(
+    options: &ExternalLinkOptions,
+    urls: &SiteUrl,
+    element: &mut Element,
+) {
+    let href = match element.get_attribute("href") {
+        Some(href) => href,
+        None => return,
+    };
+    let host = match Url::parse(&href) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => match url.host_str() {
+            Some(host) => host.to_lowercase(),
+            None => return,
+        },
+        _ => return,
+    };
+    if options.is_internal(urls, &host) {
+        return;
+    }
+
+    let link = options.override_for(&host);
+    let rel = link
+        .and_then(|link| link.rel.as_ref())
+        .unwrap_or(&options.rel);
+    let new_tab = link
+        .and_then(|link| link.new_tab)
+        .unwrap_or(options.new_tab);
+    let class = link
+        .and_then(|link| link.class.as_ref())
+        .or(options.class.as_ref());
+
+    add_tokens(element, "rel", rel);
+    if new_tab && !element.has_attribute("target") {
+        element.set_attribute("target", "_blank").unwrap();
+    }
+    if let Some(class) = class {
+        add_tokens(element, "class", &[class.clone()]);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::injest::assets::AssetStore;
+use crate::util::load_file;
+use crate::CACHE_DIR;
+use color_eyre::{Report, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use seahash::SeaHasher;
+use std::fs::{create_dir_all, write};
+use std::hash::Hasher;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// What to do with a file of the content repo that is neither a page nor a known static file, by
+// extension under `[files]` in site.toml:
+//
+// [files]
+// pdf = "copy"
+// ipynb = "ignore"
+// log = "text"
+// csv = { command = "csv2html", args = ["--table"], extension = "html" }
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FileHandler {
+    Strategy(FileStrategy),
+    Command(CommandHandler),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStrategy {
+    // copied as it is, like any static file
+    Copy,
+    // left out of the site without a warning
+    Ignore,
+    // copied as utf-8 `.txt`, so it is shown in the browser instead of downloaded
+    Text,
+}
+
+// the file on stdin, what ends up on the site on stdout
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandHandler {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    // of the output, the file's own if not set
+    pub extension: Option<String>,
+}
+
+impl CommandHandler {
+    fn run(&self, input: &[u8]) -> Result<Vec<u8>> {
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input)?;
+        }
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(Report::msg(format!(
+                "{} exited with {}: {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+        Ok(output.stdout)
+    }
+}
+
+pub type FileHandlers = BTreeMap<String, FileHandler>;
+
+// Converted output is kept in the cache by the content it came from and the handler that made it,
+// that file is what goes into the asset store. Named after the original so the hashed name on the
+// site still says what it is.
+fn cached_output(
+    path: &Path,
+    contents: &[u8],
+    handler: &FileHandler,
+    extension: &str,
+) -> Result<PathBuf> {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| Report::msg(format!("{} has no file name", path.display())))?;
+    // hashed in two writes, the same as hashing them concatenated without making the copy
+    let mut hasher = SeaHasher::new();
+    hasher.write(contents);
+    hasher.write(format!("{handler:?}").as_bytes());
+    let dir = Path::new(CACHE_DIR)
+        .join("files")
+        .join(format!("{:016x}", hasher.finish()));
+    create_dir_all(&dir)?;
+    Ok(dir.join(format!("{stem}.{extension}")))
+}
+
+// Ok(false) if there is no handler for the extension
+pub fn handle_file(handlers: &FileHandlers, path: &Path, assets: &AssetStore) -> Result<bool> {
+    let extension = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => extension.to_ascii_lowercase(),
+        None => return Ok(false),
+    };
+    let handler = match handlers.get(&extension) {
+        Some(handler) => handler,
+        None => return Ok(false),
+    };
+
+    match handler {
+        FileHandler::Strategy(FileStrategy::Ignore) => {}
+        FileHandler::Strategy(FileStrategy::Copy) => {
+            assets.add(path)?;
+        }
+        FileHandler::Strategy(FileStrategy::Text) => {
+            let contents = load_file(path)?;
+            let output = cached_output(path, &contents, handler, "txt")?;
+            if !output.is_file() {
+                write(&output, String::from_utf8_lossy(&contents).as_bytes())?;
+            }
+            assets.add_converted(path, output)?;
+        }
+        FileHandler::Command(command) => {
+            let contents = load_file(path)?;
+            let output = cached_output(
+                path,
+                &contents,
+                handler,
+                command.extension.as_deref().unwrap_or(&extension),
+            )?;
+            if !output.is_file() {
+                write(&output, command.run(&contents)?)?;
+            }
+            assets.add_converted(path, output)?;
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,287 @@
+use crate::injest::{
+    assets::ASSET_DIR, links::SiteUrl, report::BuildReport, static_file::new_filename, url_path,
+};
+use crate::walker;
+use color_eyre::{Report, Result};
+use ignore::WalkBuilder;
+use lol_html::{element, rewrite_str, text, Settings};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{read, read_to_string, remove_file, write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// fonttools, `pip install fonttools brotli`
+pub const SUBSET_COMMAND: &str = "pyftsubset";
+
+const FONT_EXTENSIONS: &[&str] = &["woff2", "woff", "ttf", "otf"];
+
+// an @font-face rule of a built stylesheet and the font file it loads
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FontFace {
+    // the whole rule, `@font-face{...}`
+    pub rule: String,
+    // the url as written in the rule
+    pub url: String,
+    // file name of the font, under ASSET_DIR
+    pub file_name: String,
+    // site path of the stylesheet the rule is in
+    pub stylesheet: String,
+}
+
+pub fn font_faces(css: &str, stylesheet: &str) -> Vec<FontFace> {
+    let mut faces = vec![];
+    let mut rest = css;
+    while let Some(start) = rest.find("@font-face") {
+        let after = &rest[start..];
+        let end = match after.find('}') {
+            Some(end) => end + 1,
+            None => break,
+        };
+        let rule = &after[..end];
+        rest = &after[end..];
+
+        // the first url with a font extension, a rule can list several formats
+        let url = rule
+            .split("url(")
+            .skip(1)
+            .filter_map(|part| part.split(')').next())
+            .map(|url| url.trim().trim_matches(['"', '\'']))
+            .find(|url| {
+                FONT_EXTENSIONS.iter().any(|extension| {
+                    url.split(['?', '#'])
+                        .next()
+                        .unwrap_or_default()
+                        .ends_with(extension)
+                })
+            });
+        if let Some(url) = url {
+            let file_name = url
+                .split(['?', '#'])
+                .next()
+                .unwrap_or_default()
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            faces.push(FontFace {
+                rule: rule.to_string(),
+                url: url.to_string(),
+                file_name,
+                stylesheet: stylesheet.to_string(),
+            });
+        }
+    }
+    faces
+}
+
+// what a built page needs from the fonts
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PageText {
+    pub language: Option<String>,
+    pub characters: BTreeSet<char>,
+    // hrefs of <link rel=stylesheet>
+    pub stylesheets: Vec<String>,
+}
+
+pub fn page_text(html: &str) -> Result<PageText> {
+    let page = RefCell::new(PageText::default());
+
+    rewrite_str(
+        html,
+        Settings {
+            element_content_handlers: vec![
+                element!("html[lang]", |el| {
+                    page.borrow_mut().language = el.get_attribute("lang");
+                    Ok(())
+                }),
+                element!("link[rel=stylesheet][href]", |el| {
+                    page.borrow_mut()
+                        .stylesheets
+                        .extend(el.get_attribute("href"));
+                    Ok(())
+                }),
+                // alt and title text is rendered in the page's fonts too
+                element!("[alt], [title], [placeholder]", |el| {
+                    let mut page = page.borrow_mut();
+                    for attribute in ["alt", "title", "placeholder"] {
+                        if let Some(value) = el.get_attribute(attribute) {
+                            page.characters.extend(value.chars());
+                        }
+                    }
+                    Ok(())
+                }),
+                text!("body :not(script):not(style), title", |txt| {
+                    page.borrow_mut().characters.extend(txt.as_str().chars());
+                    Ok(())
+                }),
+            ],
+            ..Settings::default()
+        },
+    )?;
+
+    let mut page = page.into_inner();
+    page.characters.retain(|c| !c.is_control());
+    Ok(page)
+}
+
+// the subset of `font` with only `characters`, as woff2
+fn subset_font(font: &Path, characters: &BTreeSet<char>, output: &Path) -> Result<()> {
+    let text = characters.iter().collect::<String>();
+    let status = Command::new(SUBSET_COMMAND)
+        .arg(font)
+        .arg(format!("--text={text}"))
+        .arg("--flavor=woff2")
+        .arg("--layout-features=*")
+        .arg(format!("--output-file={}", output.display()))
+        .status()?;
+    if !status.success() {
+        return Err(Report::msg(format!(
+            "{SUBSET_COMMAND} exited with {status} for {}",
+            font.display()
+        )));
+    }
+    Ok(())
+}
+
+// the site path of a stylesheet link, however the theme wrote it
+fn stylesheet_path<'a>(urls: &SiteUrl, href: &'a str) -> Option<&'a str> {
+    let origin = urls.origin();
+    let href = href.strip_prefix(origin.as_str()).unwrap_or(href);
+    urls.strip_base(href.split(['?', '#']).next()?)
+}
+
+fn preload_hints(subsets: &[(FontFace, String)]) -> String {
+    let mut head = String::new();
+    for (_, url) in subsets {
+        head.push_str(&format!(
+            r#"<link rel="preload" href="{url}" as="font" type="font/woff2" crossorigin>"#
+        ));
+    }
+    head.push_str("<style>");
+    for (face, url) in subsets {
+        // declared after the theme's stylesheets, so the subset wins for the same family/style
+        let src = format!(r#"src:url("{url}") format("woff2")"#);
+        let rule = match (face.rule.find("src:"), face.rule.find("src :")) {
+            (Some(start), _) | (None, Some(start)) => {
+                let end = face.rule[start..]
+                    .find([';', '}'])
+                    .map(|end| start + end)
+                    .unwrap_or(face.rule.len());
+                format!("{}{src}{}", &face.rule[..start], &face.rule[end..])
+            }
+            (None, None) => continue,
+        };
+        head.push_str(&rule);
+    }
+    head.push_str("</style>");
+    head
+}
+
+// Subsets every webfont the built stylesheets load to the characters the pages of each language
+// use, writes the subsets as woff2 next to the other assets and points the pages of that language
+// at them with preload hints. Runs over the finished output, so it sees exactly what was built.
+pub fn subset_fonts(
+    site_output_path: impl AsRef<Path>,
+    urls: &SiteUrl,
+    default_language: &str,
+    report: &mut BuildReport,
+) -> Result<()> {
+    let output = site_output_path.as_ref();
+    let asset_dir = output.join(ASSET_DIR);
+
+    let mut faces = vec![];
+    let mut pages: Vec<(PathBuf, PageText)> = vec![];
+    for entry in walker!(output).build() {
+        let entry = entry?;
+        let path = entry.path();
+        let relative = path.strip_prefix(output)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("css") => faces.extend(font_faces(
+                &read_to_string(path)?,
+                &format!("/{}", url_path(relative)),
+            )),
+            Some("html") => pages.push((path.to_path_buf(), page_text(&read_to_string(path)?)?)),
+            _ => {}
+        }
+    }
+    if faces.is_empty() {
+        return Ok(());
+    }
+
+    let mut characters: BTreeMap<String, BTreeSet<char>> = BTreeMap::new();
+    for (_, page) in &pages {
+        let language = page.language.as_deref().unwrap_or(default_language);
+        characters
+            .entry(language.to_string())
+            .or_default()
+            .extend(&page.characters);
+    }
+
+    // (font file, language) -> site path of the subset
+    let mut subsets: HashMap<(String, String), String> = HashMap::new();
+    for face in &faces {
+        let font = asset_dir.join(&face.file_name);
+        if !font.is_file() {
+            report.warn(
+                &font,
+                format!("{} loads a font that was not built", face.stylesheet),
+            );
+            continue;
+        }
+        for (language, characters) in &characters {
+            let key = (face.file_name.clone(), language.clone());
+            if subsets.contains_key(&key) {
+                continue;
+            }
+
+            let stem = face.file_name.split('.').next().unwrap_or_default();
+            let temporary = asset_dir.join(format!("{stem}.{language}.subset.woff2"));
+            if let Err(why) = subset_font(&font, characters, &temporary) {
+                report.warn(&font, format!("could not subset for {language}: {why}"));
+                continue;
+            }
+            let contents = read(&temporary)?;
+            remove_file(&temporary)?;
+            let (_, name) = new_filename(&contents, format!("{stem}-{language}.woff2"))
+                .ok_or_else(|| Report::msg("subset has no file name"))?;
+            write(asset_dir.join(&name), contents)?;
+            subsets.insert(key, urls.link(&format!("/{ASSET_DIR}/{name}")));
+        }
+    }
+
+    for (path, page) in &pages {
+        let language = page.language.as_deref().unwrap_or(default_language);
+        let used = faces
+            .iter()
+            .filter(|face| {
+                page.stylesheets
+                    .iter()
+                    .any(|href| stylesheet_path(urls, href) == Some(face.stylesheet.as_str()))
+            })
+            .filter_map(|face| {
+                subsets
+                    .get(&(face.file_name.clone(), language.to_string()))
+                    .map(|url| (face.clone(), url.clone()))
+            })
+            .collect::<Vec<_>>();
+        if used.is_empty() {
+            continue;
+        }
+
+        let hints = preload_hints(&used);
+        let html = rewrite_str(
+            &read_to_string(path)?,
+            Settings {
+                element_content_handlers: vec![element!("head", |el| {
+                    el.append(&hints, lol_html::html_content::ContentType::Html);
+                    Ok(())
+                })],
+                ..Settings::default()
+            },
+        )?;
+        write(path, html)?;
+    }
+
+    Ok(())
+}
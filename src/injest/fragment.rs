@@ -0,0 +1,278 @@
+use color_eyre::{Report, Result};
+use moka::sync::Cache;
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tera::{Context, Function, Tera, Value};
+
+// `{% cache "recent-posts", 300 %} ... {% endcache %}`, tera has no custom tags so the block is cut
+// out into a template of its own and replaced by a call to this
+const CACHE_FUNCTION: &str = "cache_fragment";
+// the fragments of `page.html` are `__fragment0/page.html`, `__fragment1/page.html`, keeping the
+// extension so they're escaped like the template they came from
+const FRAGMENT_PREFIX: &str = "__fragment";
+const MAX_FRAGMENTS: u64 = 1024;
+
+struct Fragment {
+    at: Instant,
+    ttl: Option<Duration>,
+    html: String,
+}
+
+// Rendered fragments by their key. Every page of a build shares one, so a fragment on every page
+// is rendered once per build, and a dynamic page's is kept across requests for its ttl. A fragment
+// without a ttl lives as long as the cache.
+#[derive(Clone)]
+pub struct FragmentCache {
+    entries: Cache<String, Arc<Fragment>>,
+}
+
+impl FragmentCache {
+    pub fn new() -> FragmentCache {
+        FragmentCache {
+            entries: Cache::new(MAX_FRAGMENTS),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        let fragment = self.entries.get(key)?;
+        match fragment.ttl {
+            Some(ttl) if fragment.at.elapsed() >= ttl => None,
+            _ => Some(fragment.html.clone()),
+        }
+    }
+
+    fn insert(&self, key: String, ttl: Option<Duration>, html: String) {
+        let fragment = Fragment {
+            at: Instant::now(),
+            ttl,
+            html,
+        };
+        self.entries.insert(key, Arc::new(fragment));
+    }
+}
+
+impl Default for FragmentCache {
+    fn default() -> Self {
+        FragmentCache::new()
+    }
+}
+
+// the template a fragment was cut out of, for telling the theme where a problem is
+pub fn source_template(name: &str) -> &str {
+    match name.strip_prefix(FRAGMENT_PREFIX) {
+        Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => {
+            rest.split_once('/').map_or(name, |(_, source)| source)
+        }
+        _ => name,
+    }
+}
+
+// `{% name args %}` at the start of `source`, with its whitespace control
+struct Tag<'a> {
+    args: &'a str,
+    trim_before: bool,
+    trim_after: bool,
+    len: usize,
+}
+
+fn tag<'a>(source: &'a str, name: &str) -> Option<Tag<'a>> {
+    let rest = source.strip_prefix("{%")?;
+    let (trim_before, rest) = match rest.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let rest = rest.trim_start().strip_prefix(name)?;
+    if rest.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    let end = rest.find("%}")?;
+    let (args, trim_after) = match rest[..end].strip_suffix('-') {
+        Some(args) => (args, true),
+        None => (&rest[..end], false),
+    };
+    Some(Tag {
+        args: args.trim(),
+        trim_before,
+        trim_after,
+        len: source.len() - rest.len() + end + 2,
+    })
+}
+
+// `"recent-" ~ page.language, 300` into the key and the ttl, split at the last comma outside of
+// strings and parentheses
+fn split_args(args: &str) -> (&str, Option<&str>) {
+    let mut quote = None;
+    let mut depth = 0usize;
+    let mut last = None;
+    for (at, c) in args.char_indices() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'' | '`') => quote = Some(c),
+            (None, '(' | '[') => depth += 1,
+            (None, ')' | ']') => depth = depth.saturating_sub(1),
+            (None, ',') if depth == 0 => last = Some(at),
+            _ => {}
+        }
+    }
+    match last {
+        Some(at) => (args[..at].trim(), Some(args[at + 1..].trim())),
+        None => (args.trim(), None),
+    }
+}
+
+fn rewrite(
+    name: &str,
+    source: &str,
+    imports: &str,
+    fragments: &mut Vec<(String, String)>,
+) -> Result<String> {
+    let mut rewritten = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(at) = rest.find("{%") {
+        rewritten.push_str(&rest[..at]);
+        let open = match tag(&rest[at..], "cache") {
+            Some(open) => open,
+            None => {
+                rewritten.push_str("{%");
+                rest = &rest[at + 2..];
+                continue;
+            }
+        };
+
+        let body_start = at + open.len;
+        let mut depth = 1;
+        let mut cursor = body_start;
+        let (body_end, close) = loop {
+            let next = match rest[cursor..].find("{%") {
+                Some(next) => cursor + next,
+                None => {
+                    return Err(Report::msg(format!(
+                        "{name}: {{% cache {} %}} has no {{% endcache %}}",
+                        open.args
+                    )))
+                }
+            };
+            if let Some(nested) = tag(&rest[next..], "cache") {
+                depth += 1;
+                cursor = next + nested.len;
+            } else if let Some(end) = tag(&rest[next..], "endcache") {
+                depth -= 1;
+                if depth == 0 {
+                    break (next, end);
+                }
+                cursor = next + end.len;
+            } else {
+                cursor = next + 2;
+            }
+        };
+
+        let (key, ttl) = split_args(open.args);
+        if key.is_empty() {
+            return Err(Report::msg(format!("{name}: {{% cache %}} needs a key")));
+        }
+        let mut body = &rest[body_start..body_end];
+        if open.trim_after {
+            body = body.trim_start();
+        }
+        if close.trim_before {
+            body = body.trim_end();
+        }
+        let fragment = format!("{FRAGMENT_PREFIX}{}/{name}", fragments.len());
+        let index = fragments.len();
+        fragments.push((fragment.clone(), String::new()));
+        let body = rewrite(name, body, imports, fragments)?;
+        fragments[index].1 = format!("{imports}{body}");
+
+        let ttl = ttl.map(|ttl| format!("ttl={ttl}, ")).unwrap_or_default();
+        rewritten.push_str(match open.trim_before {
+            true => "{{-",
+            false => "{{",
+        });
+        // the whole context goes along, it's what the fragment is rendered with on a miss
+        rewritten.push_str(&format!(
+            " {CACHE_FUNCTION}(key={key}, {ttl}fragment=\"{fragment}\", context=__tera_context) | safe "
+        ));
+        rewritten.push_str(match close.trim_after {
+            true => "-}}",
+            false => "}}",
+        });
+        rest = &rest[body_end + close.len..];
+    }
+    rewritten.push_str(rest);
+    Ok(rewritten)
+}
+
+// The template with its `{% cache %}` blocks replaced, followed by the blocks as templates of their
+// own. A fragment gets the `{% import %}`s of its template, so the macros it calls are there.
+pub fn cache_blocks(name: &str, source: &str) -> Result<Vec<(String, String)>> {
+    let mut imports = String::new();
+    for (at, _) in source.match_indices("{%") {
+        if let Some(import) = tag(&source[at..], "import") {
+            imports.push_str(&source[at..at + import.len]);
+        }
+    }
+    let mut fragments = vec![];
+    let rewritten = rewrite(name, source, &imports, &mut fragments)?;
+    fragments.insert(0, (name.to_string(), rewritten));
+    Ok(fragments)
+}
+
+struct CacheFragment {
+    // the tera this is registered with, set once everything else is
+    tera: Arc<OnceCell<Tera>>,
+    fragments: FragmentCache,
+}
+
+impl Function for CacheFragment {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        // anything can be a key, `"recent-" ~ page.language` or a number
+        let key = match args.get("key") {
+            Some(Value::String(key)) => key.clone(),
+            Some(key) => key.to_string(),
+            None => return Err(tera::Error::msg("cache: no key")),
+        };
+        if let Some(html) = self.fragments.get(&key) {
+            return Ok(Value::String(html));
+        }
+
+        let ttl = match args.get("ttl") {
+            Some(ttl) => Some(Duration::from_secs(ttl.as_u64().ok_or_else(|| {
+                tera::Error::msg(format!("cache {key}: the ttl {ttl} is not seconds"))
+            })?)),
+            None => None,
+        };
+        let fragment = args
+            .get("fragment")
+            .and_then(Value::as_str)
+            .ok_or_else(|| tera::Error::msg(format!("cache {key}: no fragment")))?;
+        let tera = self
+            .tera
+            .get()
+            .ok_or_else(|| tera::Error::msg(format!("cache {key}: rendered too early")))?;
+        // `__tera_context` is the context as pretty printed json
+        let context = match args.get("context") {
+            Some(Value::String(json)) => serde_json::from_str(json)?,
+            Some(context) => context.clone(),
+            None => Value::Null,
+        };
+        let html = tera.render(fragment, &Context::from_value(context)?)?;
+        self.fragments.insert(key, ttl, html.clone());
+        Ok(Value::String(html))
+    }
+}
+
+// last, the fragments are rendered with everything else the theme registers
+pub fn register_fragment_cache(tera: &mut Tera, fragments: &FragmentCache) {
+    let registered = Arc::new(OnceCell::new());
+    tera.register_function(
+        CACHE_FUNCTION,
+        CacheFragment {
+            tera: registered.clone(),
+            fragments: fragments.clone(),
+        },
+    );
+    let _ = registered.set(tera.clone());
+}
@@ -1,11 +1,13 @@
-use chrono::{Date, Utc};
+use chrono::{DateTime, FixedOffset};
 use color_eyre::{Report, Result};
 use once_cell::sync::Lazy;
 use pulldown_cmark::{html, CodeBlockKind, Event, Parser, Tag};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
-use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
 use bidirectional_map::Bimap;
 use dashmap::DashMap;
 use language_tags::LanguageTag;
@@ -16,8 +18,45 @@ use tracing::log::warn;
 use tree_sitter_highlight::{HighlightConfiguration, HighlightEvent, Highlighter};
 use tera::Context;
 use toml::Value;
-use crate::injest::build::BuildInformation;
-use crate::injest::processor::{html_post_processor, ProcessedDocument};
+use crate::injest::assets::AssetStore;
+use crate::injest::breadcrumb::breadcrumbs;
+use crate::injest::build::{BuildInformation, SPLITTER};
+use crate::injest::config_meta::{front_matter, SortOrder};
+use crate::injest::dates::normalize_dates;
+use crate::injest::anchors::page_sections;
+use crate::injest::bundle::Bundle;
+use crate::injest::codeblock::{render_codeblock, write_lines, FenceInfo};
+use crate::injest::diagram::Diagrams;
+use crate::injest::docs::DocsTrees;
+use crate::injest::versions::VersionTrees;
+use crate::injest::wikilinks::{PageLinks, WikiIndex};
+use crate::injest::graph::LinkGraph;
+use crate::injest::ebook::EbookLinks;
+use crate::injest::dynamic::DynamicMeta;
+use crate::injest::hooks::{EmittedFile, PageHooks};
+use crate::injest::social_card::{CardText, SocialCards};
+use crate::injest::summary::{prose_count, summarize, Summary, DEFAULT_SUMMARY_LENGTH};
+use crate::injest::svg::{SvgOptions, TRUSTED_DIAGRAMS};
+use crate::injest::include::expand_includes;
+use crate::injest::picture::dark_mode_images;
+use crate::injest::processor::{html_post_processor, PostProcessContext, ProcessedDocument};
+use crate::injest::links::SiteUrl;
+use crate::injest::listing::ListingEntry;
+use crate::injest::markup::{render_markup, Markup, MarkupOptions};
+use crate::injest::notebook::{render_notebook, Notebook, NotebookMeta, NOTEBOOK_TEMPLATE};
+use crate::injest::print::{
+    default_print_page, expand_link_urls, print_path, PRINT_STYLE, PRINT_TEMPLATE,
+};
+use crate::injest::redirect::NavLink;
+use crate::injest::report::BuildReport;
+use crate::injest::sanitize::{allowed, path_roles, sanitize};
+use crate::plugin::wasm::WasmPlugins;
+use crate::injest::site::{MenuItem, SiteMeta, SiteVariables};
+use crate::injest::slug::{heading_ids, SlugStrategy};
+use crate::injest::structured_data::{structured_data, PageKind};
+use crate::injest::tags::TagMap;
+use crate::injest::translation::{alternates, translated_path, Alternate};
+use crate::injest::workflow::{WorkflowOptions, WorkflowState};
 
 // A root page (index.md) contains a PageMeta + some other Meta
 // A translation page (ko.md, ja.md, es.md, etc etc) contains a some other Meta other than ArticleMeta
@@ -30,22 +69,92 @@ pub struct PageHeader {
     pub custom: Custom,
 }
 
+impl PageHeader {
+    // dates without a zone are in `offset`, the site's default timezone
+    pub fn parse(data: &str, offset: &FixedOffset) -> Result<PageHeader> {
+        let mut header = toml::from_str::<Value>(front_matter(data))?;
+        normalize_dates(&mut header, offset);
+        Ok(header.try_into::<PageHeader>()?)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum PageTypeMeta {
     SeriesMeta(SeriesMeta),
     ArticleMeta(ArticleMeta),
     GenericMeta(GenericMeta),
     CategoryMeta(GenericMeta),
+    DynamicMeta(DynamicMeta),
     None,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+impl PageTypeMeta {
+    pub fn tags(&self) -> &[String] {
+        match self {
+            PageTypeMeta::SeriesMeta(series) => &series.tags,
+            PageTypeMeta::ArticleMeta(article) => &article.tags,
+            PageTypeMeta::GenericMeta(generic) | PageTypeMeta::CategoryMeta(generic) => {
+                &generic.tags
+            }
+            PageTypeMeta::DynamicMeta(dynamic) => &dynamic.tags,
+            PageTypeMeta::None => &[],
+        }
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        match self {
+            PageTypeMeta::SeriesMeta(series) => Some(&series.title),
+            PageTypeMeta::ArticleMeta(article) => Some(&article.title),
+            PageTypeMeta::GenericMeta(generic) | PageTypeMeta::CategoryMeta(generic) => {
+                Some(&generic.title)
+            }
+            PageTypeMeta::DynamicMeta(dynamic) => Some(&dynamic.title),
+            PageTypeMeta::None => None,
+        }
+    }
+
+    pub fn date(&self) -> Option<DateTime<FixedOffset>> {
+        match self {
+            PageTypeMeta::SeriesMeta(series) => Some(series.date_started),
+            PageTypeMeta::ArticleMeta(article) => Some(article.date),
+            PageTypeMeta::GenericMeta(generic) | PageTypeMeta::CategoryMeta(generic) => {
+                Some(generic.date)
+            }
+            PageTypeMeta::DynamicMeta(_) | PageTypeMeta::None => None,
+        }
+    }
+
+    // what build_generic renders a page of this type with, None for the ones it doesn't
+    pub fn generic(&self) -> Option<GenericMeta> {
+        match self {
+            PageTypeMeta::SeriesMeta(series) => Some(GenericMeta {
+                date: series.date_started,
+                title: series.title.clone(),
+                authors: series.authors.clone(),
+                tags: series.tags.clone(),
+            }),
+            PageTypeMeta::ArticleMeta(article) => Some(GenericMeta {
+                date: article.date,
+                title: article.title.clone(),
+                authors: article.authors.clone(),
+                tags: article.tags.clone(),
+            }),
+            PageTypeMeta::GenericMeta(generic) | PageTypeMeta::CategoryMeta(generic) => {
+                Some(generic.clone())
+            }
+            // rendered by the server when they're requested
+            PageTypeMeta::DynamicMeta(_) | PageTypeMeta::None => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Custom {
     #[serde(flatten)]
     pub data: BTreeMap<String, Value>
 }
 
-fn toml_v_to_json_v(toml: Value) -> serde_json::Value {
+pub fn toml_v_to_json_v(toml: Value) -> serde_json::Value {
     match toml {
         Value::String(n) => {
             serde_json::Value::String(n)
@@ -53,8 +162,9 @@ fn toml_v_to_json_v(toml: Value) -> serde_json::Value {
         Value::Integer(n) => {
             serde_json::Value::Number(Number::from(n))
         }
+        // json has no nan or infinity
         Value::Float(n) => {
-            serde_json::Value::Number(Number::from(n))
+            Number::from_f64(n).map_or(serde_json::Value::Null, serde_json::Value::Number)
         }
         Value::Boolean(n) => {
             serde_json::Value::Bool(n)
@@ -76,7 +186,7 @@ fn toml_v_to_json_v(toml: Value) -> serde_json::Value {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct PageMeta {
     pub group: Option<String>,
     pub translations: BTreeSet<String>,
@@ -87,28 +197,172 @@ pub struct PageMeta {
     pub display: String,
     pub children_template: Option<String>,
     pub template: Option<String>,
+    // position in a category with `sort = "weight"`, lowest first
+    #[serde(default)]
+    pub weight: i64,
+    // merged over the generated json-ld, `false` to leave it out for this page
+    pub structured_data: Option<Value>,
+    // theme functions with an `after_render`, run in order once the page is rendered
+    #[serde(default)]
+    pub hooks: Vec<String>,
+    // served, but left out of listings, feeds, the sitemap and search
+    #[serde(default)]
+    pub unlisted: bool,
+    // unlisted, and only served with a signed token or to an admin
+    #[serde(default)]
+    pub private: bool,
+    // draft and review pages are built, but only served like private ones, see workflow.rs
+    #[serde(default)]
+    pub state: WorkflowState,
+    // roles that may see the page while it's in review
+    #[serde(default)]
+    pub reviewers: BTreeSet<String>,
+    // the last segment of the page's url instead of its directory name
+    pub slug: Option<String>,
+    // a print rendition at print.html next to the page, see print.rs
+    #[serde(default)]
+    pub print: bool,
+}
+
+impl PageMeta {
+    pub fn is_listed(&self) -> bool {
+        !self.unlisted && !self.private && self.state == WorkflowState::Published
+    }
+
+    pub fn access(&self, workflow: &WorkflowOptions) -> PageAccess {
+        PageAccess {
+            unlisted: !self.is_listed(),
+            private: self.private,
+            state: self.state,
+            reviewers: self.reviewers.clone(),
+            roles: workflow.roles(self.state, &self.reviewers),
+        }
+    }
+}
+
+// what the server has to know about a page that isn't public, persisted after every build
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageAccess {
+    pub unlisted: bool,
+    pub private: bool,
+    pub state: WorkflowState,
+    pub reviewers: BTreeSet<String>,
+    // who besides an admin may see it before it's published
+    pub roles: BTreeSet<String>,
+}
+
+impl PageAccess {
+    // served to admins and signed links only, and to `roles` until it's published
+    pub fn is_hidden(&self) -> bool {
+        self.private || self.state != WorkflowState::Published
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct GenericMeta {
-    pub date: Date<Utc>,
+    pub date: DateTime<FixedOffset>,
     pub title: String,
     pub authors: Vec<String>,
     pub tags: Vec<String>,
 }
 
+// the optional header of a hand written .html page
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PrebuiltMeta {
+    pub title: Option<String>,
+    pub date: Option<DateTime<FixedOffset>>,
+    // render the page itself as a Tera template, off so `{{` in scripts survives
+    #[serde(default)]
+    pub tera: bool,
+    // wrap the page in a theme template, which gets it as `content.html`
+    pub template: Option<String>,
+    #[serde(default = "default_true")]
+    pub index: bool,
+    #[serde(default = "default_true")]
+    pub feed: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for PrebuiltMeta {
+    fn default() -> Self {
+        PrebuiltMeta {
+            title: None,
+            date: None,
+            tera: false,
+            template: None,
+            index: true,
+            feed: true,
+        }
+    }
+}
+
+impl PrebuiltMeta {
+    // the page's header and body, a default header if it has none
+    pub fn parse<'a>(data: &'a str, offset: &FixedOffset) -> Result<(PrebuiltMeta, &'a str)> {
+        match split_prebuilt(data) {
+            (Some(header), body) => {
+                let mut header = toml::from_str::<Value>(header)?;
+                normalize_dates(&mut header, offset);
+                Ok((header.try_into::<PrebuiltMeta>()?, body))
+            }
+            (None, body) => Ok((PrebuiltMeta::default(), body)),
+        }
+    }
+}
+
+// A .html page may start with a toml header and a `===` line. Anything that starts with markup is
+// all body, so plain html files work untouched.
+pub fn split_prebuilt(data: &str) -> (Option<&str>, &str) {
+    if data.trim_start().starts_with('<') {
+        return (None, data);
+    }
+    let mut offset = 0;
+    for line in data.split_inclusive('\n') {
+        if line.trim() == SPLITTER {
+            return (Some(&data[..offset]), &data[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+    (None, data)
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct CategoryMeta {
     pub title: String,
+    pub template: Option<String>,
+    #[serde(default)]
+    pub sort: SortOrder,
+    // name of the theme comparator used by `sort = "custom"`
+    pub comparator: Option<String>,
+    #[serde(default)]
     pub pinned_posts: Vec<String>,
+    // pinned posts go first in the category listing, and in its feed too if this is set
+    #[serde(default)]
+    pub pinned_in_feeds: bool,
+    pub paginate: Option<usize>,
+    // a sidebar of its pages nested like their directories, and previous and next in the order
+    // of the sidebar, see docs.rs
+    #[serde(default)]
+    pub docs: bool,
+    // every directory of the category is a version of it, see versions.rs
+    #[serde(default)]
+    pub versioned: bool,
+    // the version that is canonical, the newest one when unset
+    pub latest_version: Option<String>,
+    // old versions that builds leave alone unless they're forced
+    #[serde(default)]
+    pub frozen_versions: Vec<String>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct SeriesMeta {
     pub on_going: bool,
-    pub date_started: Date<Utc>,
-    pub date_completed: Option<Date<Utc>>,
-    pub edited_dates: Vec<Date<Utc>>,
+    pub date_started: DateTime<FixedOffset>,
+    pub date_completed: Option<DateTime<FixedOffset>>,
+    pub edited_dates: Vec<DateTime<FixedOffset>>,
     pub title: String,
     pub authors: Vec<String>,
     pub tags: Vec<String>,
@@ -119,109 +373,363 @@ pub struct ArticleMeta {
     pub title: String,
     pub tags: Vec<String>,
     pub authors: Vec<String>,
-    pub date: Date<Utc>,
-    pub edited_dates: Vec<Date<Utc>>,
+    pub date: DateTime<FixedOffset>,
+    pub edited_dates: Vec<DateTime<FixedOffset>>,
     pub summary: Option<String>,
 }
 
 // all of this expects a pre-propagated config!
 // page type is exported into the template under "content"
 
+// Tera looks `page.url` up as `url` in the `page` object, a key with the dot in it is never found.
+// This puts the value there, next to whatever the object has already.
+pub fn insert_nested<T: Serialize + ?Sized>(context: &mut Context, key: &str, value: &T) {
+    let (top, rest) = match key.split_once('.') {
+        Some(split) => split,
+        None => return context.insert(key, value),
+    };
+    let mut object = match context.get(top) {
+        Some(serde_json::Value::Object(object)) => object.clone(),
+        _ => serde_json::Map::new(),
+    };
+    let value = serde_json::to_value(value).unwrap_or(serde_json::Value::Null);
+    nest_value(&mut object, rest, value);
+    context.insert(top, &object);
+}
+
+// the same for a context that's json already, `content.title` into `{"content": {"title": ..}}`
+pub fn nest_value(
+    object: &mut serde_json::Map<String, serde_json::Value>,
+    key: &str,
+    value: serde_json::Value,
+) {
+    match key.split_once('.') {
+        Some((top, rest)) => {
+            let inner = object
+                .entry(top)
+                .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+            if !inner.is_object() {
+                *inner = serde_json::Value::Object(serde_json::Map::new());
+            }
+            if let serde_json::Value::Object(inner) = inner {
+                nest_value(inner, rest, value);
+            }
+        }
+        None => {
+            object.insert(key.to_string(), value);
+        }
+    }
+}
+
 fn populate_page_meta(context: &mut Context, page: &PageMeta) {
-    context.insert("page.group", &page.group.unwrap_or("default".into()));
-    context.insert("page.translations", &page.translations);
-    context.insert("page.rss_enabled", &page.rss);
-    context.insert("page.index_enabled", &page.index);
-    context.insert("page.template", &page.template);
-    context.insert("page.children_template", &page.children_template);
-    context.insert("page.display", &page.display);
-    context.insert("page.redirect_from", &page.redirect_from);
-    context.insert("page.redirect_to", &page.redirect_to);
-    context.insert("page.display", &page.display);
+    insert_nested(
+        context,
+        "page.group",
+        &page.group.unwrap_or("default".into()),
+    );
+    insert_nested(context, "page.translations", &page.translations);
+    insert_nested(context, "page.rss_enabled", &page.rss);
+    insert_nested(context, "page.index_enabled", &page.index);
+    insert_nested(context, "page.template", &page.template);
+    insert_nested(context, "page.children_template", &page.children_template);
+    insert_nested(context, "page.display", &page.display);
+    insert_nested(context, "page.redirect_from", &page.redirect_from);
+    insert_nested(context, "page.redirect_to", &page.redirect_to);
+    insert_nested(context, "page.display", &page.display);
+    insert_nested(context, "page.unlisted", &!page.is_listed());
+    insert_nested(context, "page.private", &page.private);
+    insert_nested(context, "page.state", &page.state);
 }
 
 fn populate_counts(context: &mut Context, content: &str) {
     const READING_WPM: f64 = 150.0;
 
-    let word_count = words_count::count(content);
+    let word_count = prose_count(content);
     let reading_time_seconds = (word_count.words as f64 / READING_WPM).round() as u32;
     let table_of_contents = pulldown_cmark_toc::TableOfContents::new(content).to_cmark();
 
-    context.insert("content.reading_time_seconds", &reading_time_seconds);
-    context.insert("content.table_of_contents", &table_of_contents);
-    context.insert("content.word_count", &word_count.words);
-    context.insert("content.character_count", &word_count.characters);
-    context.insert("content.cjk", &word_count.cjk);
-    context.insert("content.whitespace", &word_count.whitespaces);
+    insert_nested(
+        context,
+        "content.reading_time_seconds",
+        &reading_time_seconds,
+    );
+    insert_nested(context, "content.table_of_contents", &table_of_contents);
+    insert_nested(context, "content.word_count", &word_count.words);
+    insert_nested(context, "content.character_count", &word_count.characters);
+    insert_nested(context, "content.cjk", &word_count.cjk);
+    insert_nested(context, "content.whitespace", &word_count.whitespaces);
 }
 
 fn populate_autos(context: &mut Context, build_info: &BuildInformation) {
     // populate autogenerated data
     // TODO: moklog information (version, etc)
-    context.insert("auto.build_time", &build_info.start_time);
-    context.insert("auto.build_init", &build_info.initiated);
-    context.insert("auto.build_id", &build_info.id);
+    insert_nested(context, "auto.build_time", &build_info.start_time);
+    insert_nested(context, "auto.build_init", &build_info.initiated);
+    insert_nested(context, "auto.build_id", &build_info.id);
 }
 
-struct CategoryThing<'a> {
+// the summary of the rendered content, before the theme wraps it
+fn populate_summary(context: &mut Context, html: &str, site: &SiteMeta) -> Result<Summary> {
+    let length = site.build.summary_length.unwrap_or(DEFAULT_SUMMARY_LENGTH);
+    let summary = summarize(html, length)?;
+    insert_nested(context, "content.summary_html", &summary.html);
+    insert_nested(context, "content.summary_text", &summary.text);
+    Ok(summary)
+}
+
+// `content.sections`, the headings of the rendered content with a url into each, for a table of
+// contents with links or a feed item that links past the top of a long article
+fn populate_sections(context: &mut Context, html: &str, urls: &SiteUrl, canonical: &str) -> Result<()> {
+    let page_url = urls.absolute(&urls.policy().canonical_path(canonical));
+    insert_nested(context, "content.sections", &page_sections(html, &page_url)?);
+    Ok(())
+}
+
+// what a template sees under `page.categories`
+#[derive(Serialize, JsonSchema)]
+pub struct CategoryThing<'a> {
     pub display: &'a str,
-    pub link: &'a str,
-    pub subcategories: &'a HashSet<String>
+    // the category's path, what `nav_links` are keyed by
+    pub link: String,
+    pub subcategories: &'a HashSet<String>,
+    pub links: &'a [NavLink],
 }
 
-fn populate_categories_subcategories<'a>(context: &'a mut Context, categories: &'a Arc<HashMap<String, String>>, subcategories: &'a Arc<HashMap<String, HashSet<String>>>) {
-    let thing = categories.iter().map(|(display, link)| {
+fn populate_categories_subcategories<'a>(context: &'a mut Context, categories: &'a Arc<HashMap<String, String>>, subcategories: &'a Arc<HashMap<String, HashSet<String>>>, nav_links: &'a Arc<HashMap<String, Vec<NavLink>>>) {
+    let thing = categories.iter().map(|(display, dir)| {
+        let link = format!("/{dir}");
         CategoryThing {
             display,
+            subcategories: subcategories.get(dir).unwrap(),
+            links: nav_links.get(&link).map(|x| x.as_slice()).unwrap_or_default(),
             link,
-            subcategories: subcategories.get(link).unwrap(),
         }
-    });
-    context.insert("page.categories", &thing);
+    }).collect::<Vec<_>>();
+    insert_nested(context, "page.categories", &thing);
 }
 
-fn populate_translations(context: &mut Context, languages: &[&LanguageTag], this_lang: &LanguageTag, default_lang: &LanguageTag, path: &str) {
-    context.insert("page.translations", languages.iter().filter(|x| x == this_lang).filter(|x| x == default_lang).map(|x| {
-        (x.clone().clone(),)
-    }).collect());
+fn populate_translations(context: &mut Context, alternates: &[Alternate], this_lang: &LanguageTag) {
+    insert_nested(context, "page.translations", alternates);
+    insert_nested(context, "page.default_translation", &alternates.iter().find(|alternate| alternate.default));
+    insert_nested(context, "page.this_translation", &alternates.iter().find(|alternate| alternate.language == this_lang.as_str()));
+}
 
-    context.insert("page.default_translation", &(default_lang, path));
-    if this_lang == default_lang {
-        context.insert("page.this_translation", &(this_lang, path));
-    } else {
-        context.insert("page.this_translation", &(this_lang,  format!("/{}{path}", this_lang.as_str())));
-    }
+// what a template sees under `site`, the same everywhere but for the menu
+#[derive(Serialize)]
+struct SiteContext<'a> {
+    #[serde(flatten)]
+    variables: &'a SiteVariables,
+    menu: Vec<MenuItem>,
 }
 
-fn populate_core_build_stuffs(context: &mut Context, core: CoreBuildStuffs) {
+pub fn populate_site(context: &mut Context, site: &SiteMeta, variables: &SiteVariables, urls: &SiteUrl, path: &str, language: &LanguageTag) {
+    context.insert("site", &SiteContext {
+        variables,
+        menu: site.menu_for(path, language, urls),
+    });
+    insert_nested(context, "page.url", &urls.absolute(path));
+    insert_nested(context, "page.language", language.as_str());
+}
+
+fn populate_core_build_stuffs(context: &mut Context, core: &CoreBuildStuffs) {
     populate_page_meta(context, core.page);
     populate_counts(context, core.content);
-    context.insert("page.base_slug", core.slug);
+    insert_nested(context, "page.base_slug", core.slug);
+    insert_nested(context, "page.pinned", &core.pinned);
+    insert_nested(context, "page.previous", &core.previous);
+    insert_nested(context, "page.next", &core.next);
+    insert_nested(context, "page.docs", &core.docs.nav(core.path, core.urls));
+    insert_nested(context, "page.version", &core.versions.nav(core.path, core.urls));
+    insert_nested(context, "content.backlinks", &core.links.backlinks(core.path, core.urls));
+    insert_nested(context, "page.graph", &core.graph.stats(core.path, core.urls));
+    insert_nested(context, "page.ebook", &core.ebooks.get(core.path));
+    insert_nested(context, "page.print", &core.print_path().map(|print| core.urls.link(&print)));
     populate_autos(context, core.info);
-    populate_categories_subcategories(context, &core.categories, &core.subcategories);
-    populate_translations(context, core.langauges, core.language, core.default_language, core.path);
-    tera_context.insert("content.raw", core.content);
+    populate_categories_subcategories(context, &core.categories, &core.subcategories, &core.nav_links);
+    populate_translations(context, &core.alternates(), core.language);
+    populate_site(context, core.site, core.site_variables, core.urls, core.path, core.language);
+    insert_nested(context, "content.raw", core.content);
+
+    context.insert("custom", &core.custom.data);
+}
+
+impl CoreBuildStuffs<'_> {
+    fn alternates(&self) -> Vec<Alternate> {
+        alternates(
+            self.path,
+            self.langauges,
+            self.default_language,
+            self.site.prefix_default_language,
+            self.urls,
+        )
+    }
+
+    fn print_path(&self) -> Option<String> {
+        self.page.print.then(|| print_path(self.path))
+    }
+
+    // The page's print rendition, `content.html` with every link followed by its url, in the theme's
+    // print.html or a plain page. It's post processed like the page and written next to it.
+    fn print_rendition(
+        &self,
+        context: &Context,
+        title: &str,
+        content: &str,
+        post: &PostProcessContext,
+    ) -> Result<()> {
+        let path = match self.print_path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let content = expand_link_urls(content, self.urls)?;
+        let rendered = match self.tera.get_template_names().any(|name| name == PRINT_TEMPLATE) {
+            true => {
+                let mut context = context.clone();
+                insert_nested(&mut context, "content.html", &content);
+                insert_nested(&mut context, "page.print_style", PRINT_STYLE);
+                self.tera.render(PRINT_TEMPLATE, &context)?
+            }
+            false => default_print_page(title, self.language.as_str(), &content),
+        };
+        let post = PostProcessContext {
+            bundle: None,
+            breadcrumbs: &[],
+            structured_data: None,
+            og_image: None,
+            summary: post.summary.clone(),
+            noindex: true,
+            print: None,
+            ..*post
+        };
+        let document = html_post_processor(self.path, self.assets, &post, &rendered)?;
+        self.emitted.lock().unwrap().push(EmittedFile {
+            path,
+            contents: document.document().to_string(),
+        });
+        Ok(())
+    }
+
+    // only the latest version of a versioned category is for search engines
+    fn noindex(&self) -> bool {
+        !self.page.is_listed() || self.versions.is_outdated(self.path)
+    }
+
+    // rendered content cleaned by the `[[sanitize]]` rules covering the page, as it is otherwise
+    fn sanitize(&self, content: String) -> String {
+        let roles = path_roles(&self.site.role_paths, self.source_path);
+        match allowed(&self.site.sanitize, self.source_path, &roles) {
+            Some(allow) => sanitize(&content, &allow),
+            None => content,
+        }
+    }
+
+    // where this language version of the page lives
+    fn translated_path(&self) -> String {
+        translated_path(
+            self.path,
+            self.language,
+            self.default_language,
+            self.site.prefix_default_language,
+        )
+    }
 
-    for (key, value) in core.custom.data.iter() {
-        let ins_key = format!("custom.{}", key);
-        context.insert(&ins_key, &value);
+    // The page's hooks over what `template` rendered. Context they change means one more render,
+    // without a template (a bare prebuilt page) there is nothing to render again.
+    fn run_hooks(
+        &self,
+        template: Option<&str>,
+        context: &mut Context,
+        rendered: String,
+    ) -> Result<String> {
+        if self.page.hooks.is_empty() {
+            return Ok(rendered);
+        }
+        let outcome = self.hooks.run(&self.page.hooks, context, &rendered, self.path)?;
+
+        let mut rendered = rendered;
+        if !outcome.context.is_empty() {
+            for (key, value) in outcome.context.iter() {
+                insert_nested(context, key, value);
+            }
+            if let Some(template) = template {
+                rendered = self.tera.render(template, context)?;
+            }
+        }
+        if let Some(html) = outcome.html {
+            rendered = html;
+        }
+        self.emitted.lock().unwrap().extend(outcome.files);
+        Ok(rendered)
+    }
+
+    // site path of the page's generated preview image, a card that fails is reported and left out
+    fn social_card(&self, title: &str, authors: &[String], date: Option<String>) -> Option<String> {
+        let cards = self.social_cards?;
+        let text = CardText {
+            title,
+            authors,
+            date,
+        };
+        match cards.card(&text, self.assets) {
+            Ok(site_path) => Some(site_path),
+            Err(why) => {
+                self.report
+                    .lock()
+                    .unwrap()
+                    .warn(self.source_path, format!("no social card: {why}"));
+                None
+            }
+        }
     }
 }
 
 pub struct CoreBuildStuffs<'a> {
-    tera: &'a Tera,
-    info: &'a BuildInformation,
-    page: &'a PageMeta,
-    slug: &'a str,
-    files: Arc<DashMap<u64, PathBuf>>,
-    categories: Arc<HashMap<String, String>>,
-    subcategories: Arc<HashMap<String, HashSet<String>>>,
-    language: &'a LanguageTag,
-    default_language: &'a LanguageTag,
-    langauges: &'a [&'a LanguageTag],
-    content: &'a str,
-    path: &'a str,
-    custom: &'a Custom,
+    pub tera: &'a Tera,
+    pub info: &'a BuildInformation,
+    pub site: &'a SiteMeta,
+    pub site_variables: &'a SiteVariables,
+    pub urls: &'a SiteUrl,
+    pub bundles: &'a HashMap<String, Bundle>,
+    pub markdown: &'a MarkdownOptions<'a>,
+    pub page: &'a PageMeta,
+    pub slug: &'a str,
+    // pinned in its category
+    pub pinned: bool,
+    // neighbours in the parent category's listing
+    pub previous: Option<&'a ListingEntry>,
+    pub next: Option<&'a ListingEntry>,
+    pub assets: &'a AssetStore,
+    pub plugins: &'a WasmPlugins,
+    pub hooks: &'a PageHooks,
+    // None unless `[build.social_cards]` is set
+    pub social_cards: Option<&'a SocialCards>,
+    // `[tags]` aliases, applied to the tags of the page before anything sees them
+    pub tags: &'a TagMap,
+    // files the page's hooks want written next to it
+    pub emitted: &'a Mutex<Vec<EmittedFile>>,
+    pub categories: Arc<HashMap<String, String>>,
+    pub subcategories: Arc<HashMap<String, HashSet<String>>>,
+    pub nav_links: Arc<HashMap<String, Vec<NavLink>>>,
+    // site path -> title of every category and the root, for breadcrumbs
+    pub titles: Arc<HashMap<String, String>>,
+    pub docs: Arc<DocsTrees>,
+    pub versions: Arc<VersionTrees>,
+    pub links: Arc<PageLinks>,
+    pub graph: Arc<LinkGraph>,
+    // series path -> its books, for completed series with `[build.ebooks]`
+    pub ebooks: Arc<BTreeMap<String, EbookLinks>>,
+    pub language: &'a LanguageTag,
+    pub default_language: &'a LanguageTag,
+    pub langauges: &'a [&'a LanguageTag],
+    // the markdown (or org, or rst) as written, includes get expanded when the page is built
+    pub content: &'a str,
+    pub markup: Markup,
+    pub path: &'a str,
+    pub site_root: &'a Path,
+    // relative to site_root
+    pub source_path: &'a Path,
+    pub custom: &'a Custom,
+    pub report: &'a Mutex<BuildReport>,
 }
 
 // TODO: PAM + Permission System
@@ -230,52 +738,317 @@ pub struct CoreBuildStuffs<'a> {
 // TODO: backfill logic by recursively parent tree, then go forward down the backfills until a consistant thing forms
 pub fn build() {}
 
+// Articles and series are rendered like generic pages, `page_type` is what they are to schema.org.
 pub fn build_generic(
     generic: &GenericMeta,
+    page_type: &PageTypeMeta,
     build_stuffs: CoreBuildStuffs
 ) -> Result<ProcessedDocument> {
-    let mut parser = Parser::new(content);
+    let expanded = expand_includes(build_stuffs.content, build_stuffs.site_root, build_stuffs.source_path)?;
+    let build_stuffs = CoreBuildStuffs { content: &expanded, ..build_stuffs };
+    let generic = &GenericMeta {
+        tags: build_stuffs.tags.canonical_list(&generic.tags),
+        ..generic.clone()
+    };
+    let content = build_stuffs.content;
+
     let mut output = String::with_capacity(content.len());
     let mut tera_context = Context::new();
 
-    populate_core_build_stuffs(&mut tera_context, build_stuffs);
-    tera_context.insert("page.type", "generic");
-    tera_context.insert("content.date", &generic.date);
-    tera_context.insert("content.title", &generic.title);
-    tera_context.insert("content.authors", &generic.authors);
-    tera_context.insert("content.tags", &generic.tags);
+    populate_core_build_stuffs(&mut tera_context, &build_stuffs);
+    insert_nested(&mut tera_context, "page.type", "generic");
+    insert_nested(&mut tera_context, "content.date", &generic.date);
+    insert_nested(&mut tera_context, "content.title", &generic.title);
+    insert_nested(&mut tera_context, "content.authors", &generic.authors);
+    insert_nested(&mut tera_context, "content.tags", &generic.tags);
+
+    let crumbs = breadcrumbs(
+        build_stuffs.path,
+        &generic.title,
+        &build_stuffs.titles,
+        build_stuffs.urls,
+    );
+    insert_nested(&mut tera_context, "page.breadcrumbs", &crumbs);
+
+    let canonical = build_stuffs.translated_path();
+    let alternates = match build_stuffs.langauges.len() {
+        0 | 1 => vec![],
+        _ => build_stuffs.alternates(),
+    };
+
+    let kind = match page_type {
+        PageTypeMeta::ArticleMeta(article) => PageKind::Article(article),
+        PageTypeMeta::SeriesMeta(series) => PageKind::Series(series),
+        _ => PageKind::Generic(generic),
+    };
+    let data = match build_stuffs.site.build.structured_data {
+        true => structured_data(
+            &kind,
+            build_stuffs.path,
+            build_stuffs.site_variables,
+            build_stuffs.urls,
+            build_stuffs.page.structured_data.clone().map(toml_v_to_json_v).as_ref(),
+        ),
+        false => None,
+    };
 
-    parser_to_writer(&mut output, parser)?;
-    tera_context.insert("content", &output);
+    output.push_str(&render_markup(build_stuffs.markup, content, build_stuffs.markdown)?);
+    let output = build_stuffs.sanitize(output);
+    insert_nested(&mut tera_context, "content.html", &output);
+    let summary = populate_summary(&mut tera_context, &output, build_stuffs.site)?;
+    populate_sections(&mut tera_context, &output, build_stuffs.urls, &canonical)?;
 
-    // insert tera templates
+    // insert tera templates, the page's own if its header names one
+    let template = build_stuffs.page.template.as_deref().unwrap_or("generic.html");
     let mut rendered = String::with_capacity(output.len());
-    build_stuffs.tera.render_to("generic.html", &tera_context, &mut rendered)?;
+    build_stuffs.tera.render_to(template, &tera_context, &mut rendered)?;
+    let rendered = build_stuffs.run_hooks(Some(template), &mut tera_context, rendered)?;
 
     // html stuffs
+    let og_image = build_stuffs.social_card(
+        &generic.title,
+        &generic.authors,
+        Some(generic.date.format("%Y-%m-%d").to_string()),
+    );
+
+    let print = build_stuffs.print_path();
+    let post = PostProcessContext {
+        urls: build_stuffs.urls,
+        bundle: build_stuffs.bundles.get(template),
+        options: &build_stuffs.site.build,
+        breadcrumbs: &crumbs,
+        structured_data: data.as_ref(),
+        canonical: &canonical,
+        alternates: &alternates,
+        site_root: build_stuffs.site_root,
+        source: build_stuffs.source_path,
+        report: build_stuffs.report,
+        og_image: og_image.as_deref(),
+        summary,
+        noindex: build_stuffs.noindex(),
+        language: build_stuffs.language,
+        print: print.as_deref(),
+    };
+    build_stuffs.print_rendition(&tera_context, &generic.title, &output, &post)?;
+    let rendered = build_stuffs.plugins.transform(build_stuffs.path, rendered)?;
+    Ok(html_post_processor(
+        build_stuffs.path,
+        build_stuffs.assets,
+        &post,
+        &rendered,
+    )?)
+}
+
+// Hand written html: no markdown, Tera only if the page asks for it, but the same post processing
+// as every other page so links, static files and the head all come out the same.
+pub fn build_prebuilt(
+    prebuilt: &PrebuiltMeta,
+    body: &str,
+    build_stuffs: CoreBuildStuffs,
+) -> Result<ProcessedDocument> {
+    let title = prebuilt.title.as_deref().unwrap_or(build_stuffs.slug);
+    let mut tera_context = Context::new();
+
+    populate_core_build_stuffs(&mut tera_context, &build_stuffs);
+    insert_nested(&mut tera_context, "page.type", "prebuilt");
+    // hand written pages print as they are, they don't get a print rendition
+    insert_nested(&mut tera_context, "page.print", &Option::<String>::None);
+    insert_nested(&mut tera_context, "content.title", title);
+    insert_nested(&mut tera_context, "content.date", &prebuilt.date);
+
+    let crumbs = breadcrumbs(build_stuffs.path, title, &build_stuffs.titles, build_stuffs.urls);
+    insert_nested(&mut tera_context, "page.breadcrumbs", &crumbs);
+
+    let canonical = build_stuffs.translated_path();
+    let alternates = match build_stuffs.langauges.len() {
+        0 | 1 => vec![],
+        _ => build_stuffs.alternates(),
+    };
+
+    let body = match prebuilt.tera {
+        true => {
+            // render_str needs the templates and filters, and a &mut
+            let mut tera = build_stuffs.tera.clone();
+            tera.render_str(body, &tera_context)?
+        }
+        false => body.to_string(),
+    };
+    // one without a template loses its head along with everything else outside the allowlist
+    let body = build_stuffs.sanitize(body);
+    let summary = populate_summary(&mut tera_context, &body, build_stuffs.site)?;
+
+    let rendered = match &prebuilt.template {
+        Some(template) => {
+            insert_nested(&mut tera_context, "content.html", &body);
+            build_stuffs.tera.render(template, &tera_context)?
+        }
+        None => body,
+    };
+    let rendered = build_stuffs.run_hooks(
+        prebuilt.template.as_deref(),
+        &mut tera_context,
+        rendered,
+    )?;
+    let og_image = build_stuffs.social_card(
+        title,
+        &[],
+        prebuilt.date.map(|date| date.format("%Y-%m-%d").to_string()),
+    );
+
+    let post = PostProcessContext {
+        urls: build_stuffs.urls,
+        bundle: prebuilt
+            .template
+            .as_ref()
+            .and_then(|template| build_stuffs.bundles.get(template)),
+        options: &build_stuffs.site.build,
+        breadcrumbs: &crumbs,
+        structured_data: None,
+        canonical: &canonical,
+        alternates: &alternates,
+        site_root: build_stuffs.site_root,
+        source: build_stuffs.source_path,
+        report: build_stuffs.report,
+        og_image: og_image.as_deref(),
+        summary,
+        noindex: build_stuffs.noindex(),
+        language: build_stuffs.language,
+        print: None,
+    };
+    let rendered = build_stuffs.plugins.transform(build_stuffs.path, rendered)?;
+    Ok(html_post_processor(
+        build_stuffs.path,
+        build_stuffs.assets,
+        &post,
+        &rendered,
+    )?)
+}
+
+// Jupyter notebooks, rendered into notebook.html (or generic.html if the theme has no notebook
+// template) as `content.html`.
+pub fn build_notebook(
+    notebook: &Notebook,
+    meta: &NotebookMeta,
+    build_stuffs: CoreBuildStuffs,
+) -> Result<ProcessedDocument> {
+    let meta = &NotebookMeta {
+        tags: build_stuffs.tags.canonical_list(&meta.tags),
+        ..meta.clone()
+    };
+    let title = meta.title.as_deref().unwrap_or(build_stuffs.slug);
+    let mut tera_context = Context::new();
+
+    populate_core_build_stuffs(&mut tera_context, &build_stuffs);
+    insert_nested(&mut tera_context, "page.type", "notebook");
+    insert_nested(&mut tera_context, "content.title", title);
+    insert_nested(&mut tera_context, "content.date", &meta.date);
+    insert_nested(&mut tera_context, "content.authors", &meta.authors);
+    insert_nested(&mut tera_context, "content.tags", &meta.tags);
+
+    let crumbs = breadcrumbs(build_stuffs.path, title, &build_stuffs.titles, build_stuffs.urls);
+    insert_nested(&mut tera_context, "page.breadcrumbs", &crumbs);
+
+    let canonical = build_stuffs.translated_path();
+    let alternates = match build_stuffs.langauges.len() {
+        0 | 1 => vec![],
+        _ => build_stuffs.alternates(),
+    };
+
+    let output = render_notebook(
+        notebook,
+        meta,
+        build_stuffs.markdown,
+        build_stuffs.assets,
+        &build_stuffs.source_path.to_string_lossy(),
+    )?;
+    let output = build_stuffs.sanitize(output);
+    insert_nested(&mut tera_context, "content.html", &output);
+    let summary = populate_summary(&mut tera_context, &output, build_stuffs.site)?;
 
-    Ok(html_post_processor(path, files.clone(), &rendered)?)
+    let template = match &meta.template {
+        Some(template) => template.as_str(),
+        None if build_stuffs
+            .tera
+            .get_template_names()
+            .any(|name| name == NOTEBOOK_TEMPLATE) =>
+        {
+            NOTEBOOK_TEMPLATE
+        }
+        None => "generic.html",
+    };
+    let mut rendered = String::with_capacity(output.len());
+    build_stuffs.tera.render_to(template, &tera_context, &mut rendered)?;
+    let rendered = build_stuffs.run_hooks(Some(template), &mut tera_context, rendered)?;
+    let og_image = build_stuffs.social_card(
+        title,
+        &meta.authors,
+        meta.date.map(|date| date.format("%Y-%m-%d").to_string()),
+    );
+
+    let print = build_stuffs.print_path();
+    let post = PostProcessContext {
+        urls: build_stuffs.urls,
+        bundle: build_stuffs.bundles.get(template),
+        options: &build_stuffs.site.build,
+        breadcrumbs: &crumbs,
+        structured_data: None,
+        canonical: &canonical,
+        alternates: &alternates,
+        site_root: build_stuffs.site_root,
+        source: build_stuffs.source_path,
+        report: build_stuffs.report,
+        og_image: og_image.as_deref(),
+        summary,
+        noindex: build_stuffs.noindex(),
+        language: build_stuffs.language,
+        print: print.as_deref(),
+    };
+    build_stuffs.print_rendition(&tera_context, title, &output, &post)?;
+    let rendered = build_stuffs.plugins.transform(build_stuffs.path, rendered)?;
+    Ok(html_post_processor(
+        build_stuffs.path,
+        build_stuffs.assets,
+        &post,
+        &rendered,
+    )?)
 }
 
 struct Code {
-    pub language: String,
+    pub fence: FenceInfo,
     pub code: String,
 }
 
-pub fn parser_to_writer<W>(writer: W, parser: Parser) -> Result<()>
+// everything markdown rendering needs besides the markdown itself
+pub struct MarkdownOptions<'a> {
+    pub diagrams: &'a Diagrams,
+    // rendered diagrams are inlined, so they are sanitized like any other svg
+    pub svg: &'a SvgOptions,
+    // renderers for the markup languages that aren't markdown
+    pub markup: &'a MarkupOptions,
+    // set when the theme has a codeblock.html
+    pub codeblock_template: Option<&'a Tera>,
+    // for heading ids
+    pub slugs: SlugStrategy,
+    // set with `[markup] wikilinks = true`
+    pub wikilinks: Option<&'a WikiIndex>,
+}
+
+pub fn parser_to_writer<W>(writer: W, parser: Parser, options: &MarkdownOptions) -> Result<()>
 where
     W: std::fmt::Write,
 {
     let mut code = None;
 
-    let iter = parser.map(|event| {
+    let events = heading_ids(dark_mode_images(parser.collect()), options.slugs);
+    let iter = events.into_iter().map(|event| {
         match &event {
             Event::Start(start) => match start {
-                Tag::CodeBlock(CodeBlockKind::Fenced(lang)) => {
+                Tag::CodeBlock(CodeBlockKind::Fenced(info)) => {
                     code = Some(Code {
-                        language: lang.to_string(),
+                        fence: FenceInfo::parse(info),
                         code: "".to_string(),
                     });
+                    return Event::Html("".into());
                 }
                 _ => {}
             },
@@ -283,29 +1056,49 @@ where
                 Tag::CodeBlock(CodeBlockKind::Fenced(_)) => {
                     if let Some(code) = code.take() {
                         let mut out = String::new();
-                        //
-                        write!(out, r#"<pre>"#).ok();
+                        let language = &code.fence.language;
 
-                        if code.language != "" {
-                            write!(out, r#"<div class="lang-tag">{}</div>"#, code.language).ok();
+                        if options.diagrams.handles(language) {
+                            let rendered = options
+                                .diagrams
+                                .render(language, &code.code)
+                                .and_then(|svg| options.svg.process(&svg, TRUSTED_DIAGRAMS));
+                            match rendered {
+                                Ok(svg) => {
+                                    write!(out, r#"<figure class="diagram diagram-{language}">{svg}</figure>"#).ok();
+                                    return Event::Html(out.into());
+                                }
+                                Err(why) => warn!("failed to render {language} diagram: {why}"),
+                            }
                         }
-                        write!(out, r#"<div class="code-block"><code>"#).ok();
 
+                        let mut highlighted = String::new();
                         if let Err(why) =
-                            parse_highlight_write_code(&mut out, &code.code, Some(&code.language))
+                            parse_highlight_write_code(&mut highlighted, &code.code, Some(language))
                         {
                             warn!(why);
-                            escape_to_writer(&mut out, &code.code).ok();
+                            highlighted.clear();
+                            escape_to_writer(&mut highlighted, &code.code).ok();
                         }
-                        write!(&mut out, "</div></code></pre>").ok();
+
+                        let body = if code.fence.needs_lines() {
+                            let mut lines = String::new();
+                            write_lines(&mut lines, &code.fence, &highlighted);
+                            lines
+                        } else {
+                            highlighted
+                        };
+
+                        out.push_str(&render_codeblock(options.codeblock_template, &code.fence, &code.code, &body));
                         return Event::Html(out.into());
                     }
                 }
                 _ => {}
             },
             Event::Text(txt) => {
-                if let Some(mut code) = code {
+                if let Some(code) = code.as_mut() {
                     code.code.push_str(txt);
+                    return Event::Html("".into());
                 }
             }
             _ => {}
@@ -0,0 +1,111 @@
+use crate::injest::{links::SiteUrl, wikilinks::PageLinks};
+use color_eyre::Result;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::write;
+use std::path::Path;
+
+// at the root of the site, `https://example.com/graph.json`
+pub const GRAPH_FILE: &str = "graph.json";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct GraphNode {
+    // the site path of the page, what edges name it by
+    pub id: String,
+    pub title: String,
+    pub url: String,
+    pub tags: Vec<String>,
+    pub incoming: usize,
+    pub outgoing: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+}
+
+// what a page gets as `page.graph`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct GraphStats {
+    // the graph of the whole site, unset unless `[build] link_graph = true`
+    pub url: Option<String>,
+    // pages linking to this one
+    pub incoming: usize,
+    // pages this one links to
+    pub outgoing: usize,
+    pub degree: usize,
+}
+
+// Every page and the links between them, for a theme to draw a graph view of. Pages that aren't
+// listed or aren't public are left out, and so are the links to and from them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct LinkGraph {
+    // by id
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+    #[serde(skip)]
+    published: bool,
+}
+
+impl LinkGraph {
+    pub fn new(
+        links: &PageLinks,
+        tags: &BTreeMap<String, Vec<String>>,
+        hidden: &BTreeSet<String>,
+        urls: &SiteUrl,
+        published: bool,
+    ) -> LinkGraph {
+        let edges = links
+            .links()
+            .filter(|(from, to)| !hidden.contains(*from) && !hidden.contains(*to))
+            .map(|(from, to)| GraphEdge {
+                source: from.to_string(),
+                target: to.to_string(),
+            })
+            .collect::<Vec<_>>();
+        let nodes = links
+            .pages()
+            .filter(|(path, _)| !hidden.contains(*path))
+            .map(|(path, title)| GraphNode {
+                id: path.to_string(),
+                title: title.to_string(),
+                url: urls.absolute(path),
+                tags: tags.get(path).cloned().unwrap_or_default(),
+                incoming: edges.iter().filter(|edge| edge.target == path).count(),
+                outgoing: edges.iter().filter(|edge| edge.source == path).count(),
+            })
+            .collect();
+        LinkGraph {
+            nodes,
+            edges,
+            published,
+        }
+    }
+
+    // None for pages that aren't in the graph
+    pub fn stats(&self, path: &str, urls: &SiteUrl) -> Option<GraphStats> {
+        let node = self
+            .nodes
+            .binary_search_by(|node| node.id.as_str().cmp(path))
+            .ok()
+            .map(|at| &self.nodes[at])?;
+        Some(GraphStats {
+            url: self
+                .published
+                .then(|| urls.absolute(&format!("/{GRAPH_FILE}"))),
+            incoming: node.incoming,
+            outgoing: node.outgoing,
+            degree: node.incoming + node.outgoing,
+        })
+    }
+}
+
+pub fn write_link_graph(output: impl AsRef<Path>, graph: &LinkGraph) -> Result<()> {
+    write(
+        output.as_ref().join(GRAPH_FILE),
+        serde_json::to_string(graph)?,
+    )?;
+    Ok(())
+}
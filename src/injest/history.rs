@@ -0,0 +1,38 @@
+use chrono::{DateTime, TimeZone, Utc};
+use color_eyre::Result;
+use git2::{Repository, Sort};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// path (relative to the repo root) -> time of the last commit that touched it.
+// walks the whole history once, newest first, so the first time a path shows up is its last edit.
+pub fn file_edit_times(repo_path: impl AsRef<Path>) -> Result<HashMap<PathBuf, DateTime<Utc>>> {
+    let repo = Repository::open(repo_path)?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+    revwalk.set_sorting(Sort::TIME)?;
+
+    let mut times = HashMap::new();
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        let time = match Utc.timestamp_opt(commit.time().seconds(), 0).single() {
+            Some(time) => time,
+            None => continue,
+        };
+
+        let tree = commit.tree()?;
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
+
+        for delta in diff.deltas() {
+            if let Some(path) = delta.new_file().path() {
+                times.entry(path.to_path_buf()).or_insert(time);
+            }
+        }
+    }
+
+    Ok(times)
+}
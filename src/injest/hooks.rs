@@ -0,0 +1,148 @@
+use crate::injest::generate::nest_value;
+use crate::injest::templates::SiteTheme;
+use crate::plugin::rhai::stdlib::ScriptStdlib;
+use color_eyre::{Report, Result};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::collections::HashMap;
+use std::path::{Component, Path};
+use tera::Context;
+use tracing::warn;
+
+// what a theme function has to define to be usable as a page hook
+pub const HOOK_FUNCTION: &str = "after_render";
+
+// a file a hook wants next to its page
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmittedFile {
+    // site path, `/blog/post/card.html`
+    pub path: String,
+    pub contents: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HookOutcome {
+    pub context: Vec<(String, serde_json::Value)>,
+    pub html: Option<String>,
+    pub files: Vec<EmittedFile>,
+}
+
+// Theme functions that define `fn after_render(page, html)`, run for the pages that list them
+// under `hooks` in their front matter. A hook returns nothing, or a map with any of
+//
+// #{
+//     context: #{ "content.title": "..." },   // set and render the page again
+//     html: "...",                           // replaces the rendered page
+//     files: #{ "card.html": "..." },         // written next to the page
+// }
+pub struct PageHooks {
+    engine: Engine,
+    scripts: HashMap<String, AST>,
+}
+
+impl PageHooks {
+    pub fn new(theme: &SiteTheme, stdlib: &ScriptStdlib) -> PageHooks {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(10_000_000)
+            .set_max_call_levels(32)
+            .set_max_string_size(16 * 1024 * 1024)
+            .set_max_array_size(100_000)
+            .set_max_map_size(100_000);
+        stdlib.register(&mut engine);
+
+        let mut scripts = HashMap::new();
+        for function in theme.functions.iter() {
+            match engine.compile(function.value()) {
+                Ok(ast) if ast.iter_functions().any(|f| f.name == HOOK_FUNCTION) => {
+                    scripts.insert(function.key().clone(), ast);
+                }
+                Ok(_) => {}
+                Err(why) => warn!("theme function {} does not compile: {why}", function.key()),
+            }
+        }
+        PageHooks { engine, scripts }
+    }
+
+    fn run_one(&self, name: &str, page: Dynamic, html: &str) -> Result<Dynamic> {
+        let script = self.scripts.get(name).ok_or_else(|| {
+            Report::msg(format!(
+                "the theme has no function {name} with an {HOOK_FUNCTION}(page, html)"
+            ))
+        })?;
+        self.engine
+            .call_fn::<Dynamic>(
+                &mut Scope::new(),
+                script,
+                HOOK_FUNCTION,
+                (page, html.to_string()),
+            )
+            .map_err(|why| Report::msg(format!("hook {name}: {why}")))
+    }
+
+    // every hook of the page in order, each one seeing what the ones before it did
+    pub fn run(
+        &self,
+        hooks: &[String],
+        context: &Context,
+        html: &str,
+        page_path: &str,
+    ) -> Result<HookOutcome> {
+        let mut page = context.clone().into_json();
+        let mut outcome = HookOutcome::default();
+
+        for name in hooks {
+            let result = self.run_one(
+                name,
+                rhai::serde::to_dynamic(&page).map_err(|why| Report::msg(why.to_string()))?,
+                outcome.html.as_deref().unwrap_or(html),
+            )?;
+            if result.is_unit() {
+                continue;
+            }
+            let result = result.try_cast::<Map>().ok_or_else(|| {
+                Report::msg(format!("hook {name} has to return a map or nothing"))
+            })?;
+
+            if let Some(values) = result.get("context") {
+                let values =
+                    rhai::serde::from_dynamic::<serde_json::Map<String, serde_json::Value>>(values)
+                        .map_err(|why| Report::msg(format!("hook {name}: {why}")))?;
+                for (key, value) in values {
+                    if let Some(object) = page.as_object_mut() {
+                        nest_value(object, &key, value.clone());
+                    }
+                    outcome.context.push((key, value));
+                }
+            }
+            if let Some(html) = result.get("html") {
+                outcome.html = Some(html.to_string());
+            }
+            if let Some(files) = result
+                .get("files")
+                .and_then(|files| files.read_lock::<Map>())
+            {
+                for (file, contents) in files.iter() {
+                    outcome
+                        .files
+                        .push(emitted_file(page_path, file, contents.to_string())?);
+                }
+            }
+        }
+        Ok(outcome)
+    }
+}
+
+fn emitted_file(page_path: &str, file: &str, contents: String) -> Result<EmittedFile> {
+    if !Path::new(file)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(Report::msg(format!(
+            "hooks can only write files next to their page, not {file}"
+        )));
+    }
+    Ok(EmittedFile {
+        path: format!("{}/{file}", page_path.trim_end_matches('/')),
+        contents,
+    })
+}
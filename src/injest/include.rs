@@ -0,0 +1,112 @@
+use color_eyre::{Report, Result};
+use std::fs::read_to_string;
+use std::path::{Component, Path, PathBuf};
+
+pub const MAX_INCLUDE_DEPTH: usize = 8;
+
+// `{{ include "snippets/setup.md" }}`, the inside of the braces
+fn include_target(directive: &str) -> Option<&str> {
+    let rest = directive.trim().strip_prefix("include")?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let rest = rest.trim();
+    rest.strip_prefix('"')?.strip_suffix('"')
+}
+
+fn resolve(site_build_path: &Path, target: &str) -> Result<PathBuf> {
+    let relative = Path::new(target.trim_start_matches('/'));
+    if relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return Err(Report::msg(format!(
+            "include \"{target}\" has to be a plain path inside the content repo"
+        )));
+    }
+    Ok(site_build_path.join(relative))
+}
+
+fn is_fence(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("```") || line.starts_with("~~~")
+}
+
+fn expand(
+    content: &str,
+    site_build_path: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Result<String> {
+    if stack.len() > MAX_INCLUDE_DEPTH {
+        return Err(Report::msg(format!(
+            "includes nested deeper than {MAX_INCLUDE_DEPTH}: {}",
+            stack
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<String>>()
+                .join(" -> ")
+        )));
+    }
+
+    let mut out = String::with_capacity(content.len());
+    let mut in_fence = false;
+
+    for line in content.split_inclusive('\n') {
+        if is_fence(line) {
+            in_fence = !in_fence;
+        }
+        if in_fence {
+            out.push_str(line);
+            continue;
+        }
+
+        let mut rest = line;
+        while let Some(start) = rest.find("{{") {
+            let end = match rest[start..].find("}}") {
+                Some(end) => start + end,
+                None => break,
+            };
+
+            let target = match include_target(&rest[start + 2..end]) {
+                Some(target) => target,
+                None => {
+                    out.push_str(&rest[..end + 2]);
+                    rest = &rest[end + 2..];
+                    continue;
+                }
+            };
+
+            let path = resolve(site_build_path, target)?;
+            if stack.contains(&path) {
+                return Err(Report::msg(format!(
+                    "include cycle: {} includes itself",
+                    path.display()
+                )));
+            }
+
+            let included = read_to_string(&path)
+                .map_err(|why| Report::msg(format!("include \"{target}\": {why}")))?;
+            stack.push(path);
+            let expanded = expand(&included, site_build_path, stack)?;
+            stack.pop();
+
+            out.push_str(&rest[..start]);
+            out.push_str(expanded.trim_end_matches('\n'));
+            rest = &rest[end + 2..];
+        }
+        out.push_str(rest);
+    }
+
+    Ok(out)
+}
+
+// Expands every include in a page's markdown. Done before anything else looks at the content,
+// so word counts and the search index see the included text as part of the page.
+pub fn expand_includes(
+    content: &str,
+    site_build_path: impl AsRef<Path>,
+    page_path: impl AsRef<Path>,
+) -> Result<String> {
+    let mut stack = vec![site_build_path.as_ref().join(page_path.as_ref())];
+    expand(content, site_build_path.as_ref(), &mut stack)
+}
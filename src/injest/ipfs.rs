@@ -0,0 +1,163 @@
+use crate::injest::{
+    access::{rule_for, AccessRule},
+    generate::PageAccess,
+    manifest::MANIFEST_FILE,
+    url_path,
+};
+use color_eyre::{Report, Result};
+use ignore::WalkBuilder;
+use reqwest::{
+    multipart::{Form, Part},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::env::var;
+use std::path::Path;
+use std::time::Duration;
+
+// adding a large site takes a while, pinning one remotely takes longer
+const TIMEOUT: Duration = Duration::from_secs(600);
+// the directory the site is added as, its cid is the one published
+const ROOT_NAME: &str = "site";
+
+// `[ipfs]` in site.toml, a mirror of every build on ipfs
+//
+// [ipfs]
+// api = "http://127.0.0.1:5001"
+// pin_service = "https://api.pinata.cloud/psa"
+// pin_token_env = "MOKLOG_PIN_TOKEN"
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IpfsOptions {
+    // rpc api of the node the site is added to
+    pub api: String,
+    // an IPFS Pinning Service API endpoint to pin every build with as well, so the mirror doesn't
+    // depend on the node staying up
+    pub pin_service: Option<String>,
+    pub pin_token_env: Option<String>,
+}
+
+// the value of the `_dnslink` TXT record pointing at a cid
+pub fn dnslink(cid: &str) -> String {
+    format!("dnslink=/ipfs/{cid}")
+}
+
+// IPFS is public for good, so nothing goes there that the server wouldn't serve to anyone: no
+// private or unpublished pages, nothing behind an access rule. Everything under them is left out
+// with them.
+fn is_public(page: &str, access: &BTreeMap<String, PageAccess>, rules: &[AccessRule]) -> bool {
+    !access.get(page).map_or(false, PageAccess::is_hidden) && rule_for(rules, page).is_none()
+}
+
+#[derive(Deserialize)]
+struct AddedEntry {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+// Adds the public part of the output to the node, pinned, and answers its cid. Files are streamed
+// from disk, the site is never held in memory.
+pub async fn add_site(
+    options: &IpfsOptions,
+    site_output_path: impl AsRef<Path>,
+    access: &BTreeMap<String, PageAccess>,
+    rules: &[AccessRule],
+) -> Result<String> {
+    let root = site_output_path.as_ref().to_path_buf();
+    let walker = {
+        let (root, access, rules) = (root.clone(), access.clone(), rules.to_vec());
+        WalkBuilder::new(&root)
+            .standard_filters(false)
+            .filter_entry(move |entry| {
+                let is_dir = entry.file_type().map_or(false, |kind| kind.is_dir());
+                match entry.path().strip_prefix(&root).ok().map(url_path) {
+                    Some(relative) if is_dir => is_public(&format!("/{relative}"), &access, &rules),
+                    Some(relative) => relative != MANIFEST_FILE,
+                    None => false,
+                }
+            })
+            .build()
+    };
+    let mut form = Form::new().part(
+        "file",
+        Part::bytes(Vec::new())
+            .file_name(ROOT_NAME)
+            .mime_str("application/x-directory")?,
+    );
+    for entry in walker {
+        let entry = entry?;
+        let relative = match entry.path().strip_prefix(&root).ok().map(url_path) {
+            Some(relative) if !relative.is_empty() => relative,
+            _ => continue,
+        };
+        let name = format!("{ROOT_NAME}/{relative}");
+        let part = if entry.file_type().map_or(false, |kind| kind.is_dir()) {
+            Part::bytes(Vec::new()).mime_str("application/x-directory")?
+        } else {
+            let file = tokio::fs::File::open(entry.path()).await?;
+            Part::stream(file).mime_str("application/octet-stream")?
+        };
+        // kubo takes the path from the file name, escaped
+        form = form.part(
+            "file",
+            part.file_name(
+                url::form_urlencoded::byte_serialize(name.as_bytes()).collect::<String>(),
+            ),
+        );
+    }
+
+    let client = Client::builder().timeout(TIMEOUT).build()?;
+    let response = client
+        .post(format!("{}/api/v0/add", options.api.trim_end_matches('/')))
+        .query(&[("recursive", "true"), ("pin", "true"), ("cid-version", "1")])
+        .multipart(form)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(Report::msg(format!(
+            "ipfs node answered {}: {}",
+            response.status(),
+            response.text().await.unwrap_or_default()
+        )));
+    }
+    // one json object per line, one for every file and directory added
+    response
+        .text()
+        .await?
+        .lines()
+        .filter_map(|line| serde_json::from_str::<AddedEntry>(line).ok())
+        .find(|entry| entry.name == ROOT_NAME)
+        .map(|entry| entry.hash)
+        .ok_or_else(|| Report::msg("the ipfs node did not answer a cid for the site"))
+}
+
+// asks the pinning service to pin `cid` too, it fetches it from the network on its own time
+pub async fn pin_remote(options: &IpfsOptions, cid: &str, name: &str) -> Result<()> {
+    let service = match &options.pin_service {
+        Some(service) => service.trim_end_matches('/'),
+        None => return Ok(()),
+    };
+    let token = match &options.pin_token_env {
+        Some(name) => var(name).map_err(|_| Report::msg(format!("{name} is not set")))?,
+        None => return Err(Report::msg("pin_service is set without pin_token_env")),
+    };
+    let response = Client::builder()
+        .timeout(TIMEOUT)
+        .build()?
+        .post(format!("{service}/pins"))
+        .bearer_auth(token)
+        .json(&json!({ "cid": cid, "name": name }))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(Report::msg(format!(
+            "pinning service answered {}: {}",
+            response.status(),
+            response.text().await.unwrap_or_default()
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use crate::util::MAX_SOURCE_SIZE;
+use color_eyre::{Report, Result};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+// `[limits]` in site.toml, what one build may use. A content repo that's broken (or hostile) fails
+// its build with a report of which limit it hit instead of hanging or eating the server's memory.
+//
+// [limits]
+// build_seconds = 1800
+// file_bytes = 67108864
+// pages = 100000
+// script_operations = 10000000
+// plugin_fuel = 100000000
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildLimits {
+    // the whole build, checked between stages and pages and while theme scripts run
+    #[serde(default = "default_build_seconds")]
+    pub build_seconds: u64,
+    // of one page source, everything in it is held in memory at once
+    #[serde(default = "default_file_bytes")]
+    pub file_bytes: u64,
+    // page sources read, translations included
+    #[serde(default = "default_pages")]
+    pub pages: usize,
+    // of one run of a theme script or hook, about one per expression it evaluates
+    #[serde(default = "default_script_operations")]
+    pub script_operations: u64,
+    // per call into a wasm plugin, lowers what a plugin's manifest asks for but never raises it
+    pub plugin_fuel: Option<u64>,
+}
+
+fn default_build_seconds() -> u64 {
+    30 * 60
+}
+
+fn default_file_bytes() -> u64 {
+    MAX_SOURCE_SIZE
+}
+
+fn default_pages() -> usize {
+    100_000
+}
+
+fn default_script_operations() -> u64 {
+    10_000_000
+}
+
+impl Default for BuildLimits {
+    fn default() -> Self {
+        BuildLimits {
+            build_seconds: default_build_seconds(),
+            file_bytes: default_file_bytes(),
+            pages: default_pages(),
+            script_operations: default_script_operations(),
+            plugin_fuel: None,
+        }
+    }
+}
+
+impl BuildLimits {
+    pub fn check_pages(&self, pages: usize) -> Result<()> {
+        match pages > self.pages {
+            true => Err(Report::msg(format!(
+                "the content has more than {} pages, raise [limits] pages if that's right",
+                self.pages
+            ))),
+            false => Ok(()),
+        }
+    }
+}
+
+// when a build has to be done by, from `build_seconds`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Deadline {
+    started: Instant,
+    limit: Duration,
+}
+
+impl Deadline {
+    pub fn start(limits: &BuildLimits) -> Deadline {
+        Deadline {
+            started: Instant::now(),
+            limit: Duration::from_secs(limits.build_seconds),
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.started.elapsed() > self.limit
+    }
+
+    // `stage` is what the build was doing, `while rendering pages`
+    pub fn check(&self, stage: &str) -> Result<()> {
+        match self.passed() {
+            true => Err(Report::msg(format!(
+                "the build ran past its limit of {} seconds {stage}, raise [limits] build_seconds \
+                 if the site needs longer",
+                self.limit.as_secs()
+            ))),
+            false => Ok(()),
+        }
+    }
+}
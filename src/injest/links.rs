@@ -0,0 +1,194 @@
+use color_eyre::{Report, Result};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use url::Url;
+
+// how internal links are written into the generated html
+#[derive(Copy, Clone, Debug, Default, PartialOrd, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkStyle {
+    // `/blog/programming/rust`
+    #[default]
+    RootRelative,
+    // `https://example.com/blog/programming/rust`
+    Absolute,
+}
+
+impl FromStr for LinkStyle {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "root_relative" | "root" | "relative" => Ok(LinkStyle::RootRelative),
+            "absolute" => Ok(LinkStyle::Absolute),
+            other => Err(Report::msg(format!(
+                "unknown link style \"{other}\", expected root_relative or absolute"
+            ))),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialOrd, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrailingSlash {
+    // `/programming/rust/`
+    #[default]
+    Always,
+    // `/programming/rust`
+    Never,
+}
+
+impl FromStr for TrailingSlash {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "always" => Ok(TrailingSlash::Always),
+            "never" => Ok(TrailingSlash::Never),
+            other => Err(Report::msg(format!(
+                "unknown trailing slash policy \"{other}\", expected always or never"
+            ))),
+        }
+    }
+}
+
+// The canonical form of a page path. Only pages are touched, anything with a file extension in
+// its last segment (hashed static files, feeds) is served exactly as named.
+#[derive(Copy, Clone, Debug, Default, PartialOrd, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutePolicy {
+    pub trailing_slash: TrailingSlash,
+    pub lowercase: bool,
+}
+
+impl RoutePolicy {
+    pub fn canonical_path(&self, path: &str) -> String {
+        let split_at = path.find(['?', '#']).unwrap_or(path.len());
+        let (path, rest) = path.split_at(split_at);
+
+        if !is_page_path(path) {
+            return format!("{path}{rest}");
+        }
+
+        let mut canonical = if self.lowercase {
+            path.to_lowercase()
+        } else {
+            path.to_string()
+        };
+
+        let trimmed_len = canonical.trim_end_matches('/').len();
+        canonical.truncate(trimmed_len);
+        match self.trailing_slash {
+            TrailingSlash::Always => canonical.push('/'),
+            TrailingSlash::Never if canonical.is_empty() => canonical.push('/'),
+            TrailingSlash::Never => {}
+        }
+
+        canonical.push_str(rest);
+        canonical
+    }
+}
+
+pub fn is_page_path(path: &str) -> bool {
+    match path.trim_end_matches('/').rsplit('/').next() {
+        Some(last) => !last.contains('.'),
+        None => true,
+    }
+}
+
+// All generated urls go through here. "site paths" are paths as the content repo sees them
+// (`/programming/rust`), without whatever subpath the site is deployed under.
+#[derive(Clone, Debug, PartialOrd, PartialEq, Eq)]
+pub struct SiteUrl {
+    base: Url,
+    style: LinkStyle,
+    policy: RoutePolicy,
+}
+
+impl SiteUrl {
+    pub fn new(base_url: &str, style: LinkStyle, policy: RoutePolicy) -> Result<SiteUrl> {
+        let mut base = Url::parse(base_url)?;
+        if base.cannot_be_a_base() {
+            return Err(Report::msg(format!("{base_url} cannot be used as a base url")));
+        }
+        if !base.path().ends_with('/') {
+            let path = format!("{}/", base.path());
+            base.set_path(&path);
+        }
+        base.set_query(None);
+        base.set_fragment(None);
+        Ok(SiteUrl {
+            base,
+            style,
+            policy,
+        })
+    }
+
+    pub fn base(&self) -> &Url {
+        &self.base
+    }
+
+    pub fn style(&self) -> LinkStyle {
+        self.style
+    }
+
+    pub fn policy(&self) -> RoutePolicy {
+        self.policy
+    }
+
+    // `/blog/` for a site deployed at `https://example.com/blog/`, `/` otherwise
+    pub fn base_path(&self) -> &str {
+        self.base.path()
+    }
+
+    // `/blog/programming/rust`
+    pub fn root_relative(&self, site_path: &str) -> String {
+        format!("{}{}", self.base_path(), site_path.trim_start_matches('/'))
+    }
+
+    // `https://example.com/blog/programming/rust`
+    pub fn absolute(&self, site_path: &str) -> String {
+        format!("{}{}", self.origin(), self.root_relative(site_path))
+    }
+
+    // `https://example.com`
+    pub fn origin(&self) -> String {
+        self.base.origin().ascii_serialization()
+    }
+
+    // a link written according to the configured style, in its canonical form so that internal
+    // links never have to bounce through a redirect
+    pub fn link(&self, site_path: &str) -> String {
+        let site_path = self.policy.canonical_path(site_path);
+        match self.style {
+            LinkStyle::RootRelative => self.root_relative(&site_path),
+            LinkStyle::Absolute => self.absolute(&site_path),
+        }
+    }
+
+    // turns a request path back into a site path, None if it is outside of the site
+    pub fn strip_base<'a>(&self, request_path: &'a str) -> Option<&'a str> {
+        let base = self.base_path().trim_end_matches('/');
+        match request_path.strip_prefix(base) {
+            Some("") => Some("/"),
+            Some(rest) if rest.starts_with('/') => Some(rest),
+            _ => None,
+        }
+    }
+
+    // rewrites an href/src found in generated html. only site paths (`/...`) are touched,
+    // relative links, anchors, and other sites are left alone.
+    pub fn rewrite_internal(&self, link: &str) -> Option<String> {
+        if !link.starts_with('/') || link.starts_with("//") {
+            return None;
+        }
+        if self.base_path() != "/" && self.strip_base(link).is_some() {
+            // already has the base, don't double it up
+            let link = self.policy.canonical_path(link);
+            return match self.style {
+                LinkStyle::RootRelative => Some(link),
+                LinkStyle::Absolute => Some(format!("{}{link}", self.origin())),
+            };
+        }
+        Some(self.link(link))
+    }
+}
@@ -0,0 +1,227 @@
+use crate::injest::{build::SPLITTER, report::Severity};
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use toml::Value;
+
+// what a rule that's broken does to the build, `warn` unless a rule says otherwise
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LintSeverity {
+    #[default]
+    Warn,
+    Error,
+}
+
+impl From<LintSeverity> for Severity {
+    fn from(severity: LintSeverity) -> Self {
+        match severity {
+            LintSeverity::Warn => Severity::Warning,
+            LintSeverity::Error => Severity::Error,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LintRule<T> {
+    pub value: T,
+    #[serde(default)]
+    pub severity: LintSeverity,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForbiddenPhrase {
+    // matched as whole words, ignoring case
+    pub phrase: String,
+    // what to write instead, shown with the finding
+    pub instead: Option<String>,
+    #[serde(default)]
+    pub severity: LintSeverity,
+}
+
+// `[build.lint]` in site.toml, a house style for markdown sources. Every rule is off unless set.
+//
+// [build.lint]
+// max_heading_depth = { value = 3 }
+// image_alt = "error"
+// code_line_length = { value = 100, severity = "warn" }
+// required_fields = { blog = { value = ["ArticleMeta.tags"], severity = "error" } }
+//
+// [[build.lint.forbidden]]
+// phrase = "utilize"
+// instead = "use"
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LintOptions {
+    // `#` is 1, so 3 allows down to `###`
+    pub max_heading_depth: Option<LintRule<usize>>,
+    // front matter keys every page of a category has, by category directory. `ArticleMeta.tags`
+    // is `tags` under `[ArticleMeta]`.
+    #[serde(default)]
+    pub required_fields: BTreeMap<String, LintRule<Vec<String>>>,
+    #[serde(default)]
+    pub forbidden: Vec<ForbiddenPhrase>,
+    pub image_alt: Option<LintSeverity>,
+    // in characters, per line of a fenced or indented code block
+    pub code_line_length: Option<LintRule<usize>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LintFinding {
+    pub line: usize,
+    pub severity: Severity,
+    pub rule: &'static str,
+    pub message: String,
+}
+
+impl LintFinding {
+    pub fn describe(&self) -> String {
+        format!("line {}: {} ({})", self.line, self.message, self.rule)
+    }
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+fn has_field(header: &Value, field: &str) -> bool {
+    let mut value = header;
+    for key in field.split('.') {
+        value = match value.get(key) {
+            Some(value) => value,
+            None => return false,
+        };
+    }
+    match value {
+        Value::String(text) => !text.trim().is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        _ => true,
+    }
+}
+
+// the line within `text` of every place `phrase` appears on its own, not inside a longer word
+fn whole_word_matches(text: &str, phrase: &str) -> Vec<usize> {
+    let (text, phrase) = (text.to_lowercase(), phrase.to_lowercase());
+    if phrase.is_empty() {
+        return vec![];
+    }
+    text.match_indices(&phrase)
+        .filter(|(start, _)| {
+            let before = text[..*start].chars().next_back();
+            let after = text[start + phrase.len()..].chars().next();
+            !before.map_or(false, char::is_alphanumeric)
+                && !after.map_or(false, char::is_alphanumeric)
+        })
+        .map(|(start, _)| text[..start].matches('\n').count())
+        .collect()
+}
+
+// Everything in `source`, a markdown page with its front matter, that breaks a rule. `category`
+// is the top level directory the page is in, None for pages at the root.
+pub fn lint_markdown(
+    options: &LintOptions,
+    source: &str,
+    category: Option<&str>,
+) -> Vec<LintFinding> {
+    let mut findings = vec![];
+    let (header, body_start) = match source.split_once(SPLITTER) {
+        Some((header, _)) => (header, header.len() + SPLITTER.len()),
+        None => ("", 0),
+    };
+
+    if let Some(rule) = category.and_then(|category| options.required_fields.get(category)) {
+        let header = toml::from_str::<Value>(header).unwrap_or(Value::Table(Default::default()));
+        for field in rule.value.iter().filter(|field| !has_field(&header, field)) {
+            findings.push(LintFinding {
+                line: 1,
+                severity: rule.severity.into(),
+                rule: "required_fields",
+                message: format!("front matter has no `{field}`"),
+            });
+        }
+    }
+
+    let body = &source[body_start..];
+    let line = |offset: usize| line_of(source, body_start + offset);
+    let mut in_code = false;
+    // alt text of the image being read, and where it starts
+    let mut image: Option<(usize, String)> = None;
+    for (event, range) in Parser::new_ext(body, Options::all()).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Heading(level, ..)) => {
+                let depth = level as usize;
+                if let Some(rule) = options
+                    .max_heading_depth
+                    .as_ref()
+                    .filter(|rule| depth > rule.value)
+                {
+                    findings.push(LintFinding {
+                        line: line(range.start),
+                        severity: rule.severity.into(),
+                        rule: "max_heading_depth",
+                        message: format!(
+                            "heading of depth {depth}, at most {} is allowed",
+                            rule.value
+                        ),
+                    });
+                }
+            }
+            Event::Start(Tag::Image(..)) => image = Some((range.start, String::new())),
+            Event::End(Tag::Image(..)) => {
+                if let (Some((start, alt)), Some(severity)) = (image.take(), options.image_alt) {
+                    if alt.trim().is_empty() {
+                        findings.push(LintFinding {
+                            line: line(start),
+                            severity: severity.into(),
+                            rule: "image_alt",
+                            message: "image without alt text".to_string(),
+                        });
+                    }
+                }
+            }
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::End(Tag::CodeBlock(_)) => in_code = false,
+            Event::Text(text) if in_code => {
+                let rule = match &options.code_line_length {
+                    Some(rule) => rule,
+                    None => continue,
+                };
+                let first_line = line(range.start);
+                for (index, code_line) in text.lines().enumerate() {
+                    let length = code_line.chars().count();
+                    if length > rule.value {
+                        findings.push(LintFinding {
+                            line: first_line + index,
+                            severity: rule.severity.into(),
+                            rule: "code_line_length",
+                            message: format!(
+                                "code line of {length} characters, at most {} is allowed",
+                                rule.value
+                            ),
+                        });
+                    }
+                }
+            }
+            Event::Text(text) => {
+                if let Some((_, alt)) = &mut image {
+                    alt.push_str(&text);
+                }
+                for forbidden in &options.forbidden {
+                    for within in whole_word_matches(&text, &forbidden.phrase) {
+                        let instead = match &forbidden.instead {
+                            Some(instead) => format!(", write \"{instead}\" instead"),
+                            None => String::new(),
+                        };
+                        findings.push(LintFinding {
+                            line: line(range.start) + within,
+                            severity: forbidden.severity.into(),
+                            rule: "forbidden",
+                            message: format!("\"{}\"{instead}", forbidden.phrase),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    findings
+}
@@ -0,0 +1,203 @@
+use crate::injest::{config_meta::SortOrder, generate::CategoryMeta, report::BuildReport};
+use chrono::{DateTime, FixedOffset};
+use color_eyre::{Report, Result};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::Path;
+
+// one child of a category, as its listing and feeds see it
+#[derive(Clone, Debug, PartialEq, Serialize, JsonSchema)]
+pub struct ListingEntry {
+    // the directory name
+    pub slug: String,
+    pub title: String,
+    pub url: String,
+    pub date: Option<DateTime<FixedOffset>>,
+    // `weight` from the front matter, 0 if not set
+    pub weight: i64,
+    pub pinned: bool,
+}
+
+impl ListingEntry {
+    fn to_rhai(&self) -> Map {
+        let mut map = Map::new();
+        map.insert("slug".into(), self.slug.clone().into());
+        map.insert("title".into(), self.title.clone().into());
+        map.insert("url".into(), self.url.clone().into());
+        map.insert(
+            "date".into(),
+            self.date
+                .map(|date| Dynamic::from(date.to_rfc3339()))
+                .unwrap_or(Dynamic::UNIT),
+        );
+        map.insert("weight".into(), self.weight.into());
+        map.insert("pinned".into(), self.pinned.into());
+        map
+    }
+}
+
+// `fn compare(a, b)` from the theme's comparators/, returning a negative number, 0 or a positive
+// number like every other comparator. entries come in as maps with the fields of ListingEntry.
+pub struct Comparator {
+    engine: Engine,
+    script: AST,
+}
+
+impl Comparator {
+    pub fn new(source: &str) -> Result<Comparator> {
+        let engine = Engine::new();
+        let script = engine.compile(source)?;
+        Ok(Comparator { engine, script })
+    }
+
+    pub fn compare(&self, a: &ListingEntry, b: &ListingEntry) -> Result<Ordering> {
+        let mut scope = Scope::new();
+        let result = self
+            .engine
+            .call_fn::<i64>(
+                &mut scope,
+                &self.script,
+                "compare",
+                (a.to_rhai(), b.to_rhai()),
+            )
+            .map_err(|why| Report::msg(format!("comparator failed: {why}")))?;
+        Ok(result.cmp(&0))
+    }
+}
+
+pub fn mark_pinned(entries: &mut [ListingEntry], category: &CategoryMeta) {
+    for entry in entries.iter_mut() {
+        entry.pinned = category.pinned_posts.contains(&entry.slug);
+    }
+}
+
+// Sorts by the category's order. With `pin`, pinned entries go first, in the order they are listed
+// in `pinned_posts`.
+pub fn sort_listing(
+    entries: &mut [ListingEntry],
+    category: &CategoryMeta,
+    comparators: &HashMap<String, Comparator>,
+    pin: bool,
+) -> Result<()> {
+    let comparator = match (category.sort, &category.comparator) {
+        (SortOrder::Custom, Some(name)) => match comparators.get(name) {
+            Some(comparator) => Some(comparator),
+            None => {
+                return Err(Report::msg(format!(
+                    "the theme has no comparator named {name}"
+                )))
+            }
+        },
+        (SortOrder::Custom, None) => {
+            return Err(Report::msg("sort = \"custom\" without a comparator"))
+        }
+        _ => None,
+    };
+
+    // the comparator can fail halfway through a sort, keep the first error and finish sorting
+    let mut failed = None;
+    entries.sort_by(|a, b| match category.sort {
+        SortOrder::DateDesc => b.date.cmp(&a.date),
+        SortOrder::DateAsc => a.date.cmp(&b.date),
+        SortOrder::Title => a.title.cmp(&b.title),
+        SortOrder::Weight => a.weight.cmp(&b.weight).then(b.date.cmp(&a.date)),
+        SortOrder::Custom => match comparator.map(|comparator| comparator.compare(a, b)) {
+            Some(Ok(ordering)) => ordering,
+            Some(Err(why)) => {
+                failed.get_or_insert(why);
+                Ordering::Equal
+            }
+            None => Ordering::Equal,
+        },
+    });
+    if let Some(why) = failed {
+        return Err(why);
+    }
+
+    if pin {
+        let pin_position = |entry: &ListingEntry| {
+            category
+                .pinned_posts
+                .iter()
+                .position(|slug| slug == &entry.slug)
+                .unwrap_or(usize::MAX)
+        };
+        // stable, so unpinned entries keep the order from above
+        entries.sort_by_key(pin_position);
+    }
+    Ok(())
+}
+
+// A category's children in the order everything uses: the listing page, its pagination and the
+// prev/next links of each child. The feed has the same order, with pinned entries first only if
+// the category has `pinned_in_feeds`.
+pub struct Listing {
+    pub entries: Vec<ListingEntry>,
+    pub feed: Vec<ListingEntry>,
+}
+
+impl Listing {
+    pub fn new(
+        mut entries: Vec<ListingEntry>,
+        category: &CategoryMeta,
+        comparators: &HashMap<String, Comparator>,
+    ) -> Result<Listing> {
+        mark_pinned(&mut entries, category);
+        let mut feed = entries.clone();
+        sort_listing(&mut feed, category, comparators, category.pinned_in_feeds)?;
+        sort_listing(&mut entries, category, comparators, true)?;
+        Ok(Listing { entries, feed })
+    }
+
+    pub fn entry(&self, slug: &str) -> Option<&ListingEntry> {
+        self.entries.iter().find(|entry| entry.slug == slug)
+    }
+
+    // everything on one page if the category doesn't paginate
+    pub fn pages(&self, paginate: Option<usize>) -> Vec<&[ListingEntry]> {
+        match paginate {
+            Some(per_page) if per_page > 0 => self.entries.chunks(per_page).collect(),
+            _ => vec![self.entries.as_slice()],
+        }
+    }
+
+    // (previous, next) of the entry with this slug
+    pub fn neighbours(&self, slug: &str) -> (Option<&ListingEntry>, Option<&ListingEntry>) {
+        match self.entries.iter().position(|entry| entry.slug == slug) {
+            Some(idx) => (
+                idx.checked_sub(1).and_then(|prev| self.entries.get(prev)),
+                self.entries.get(idx + 1),
+            ),
+            None => (None, None),
+        }
+    }
+}
+
+// The directory a page is listed in and its name there, the one `pinned_posts` uses:
+// `blog/post/index.md` and `blog/post.md` are both `post` in `blog`.
+pub fn listing_place(source: &Path) -> Option<(&Path, &str)> {
+    let stem = source.file_stem()?.to_str()?;
+    let dir = source.parent()?;
+    match stem {
+        "index" => Some((dir.parent()?, dir.file_name()?.to_str()?)),
+        _ => Some((dir, stem)),
+    }
+}
+
+// `dir` is the category directory the .moklog at `path` describes
+pub fn validate_pinned(category: &CategoryMeta, dir: &Path, path: &Path, report: &mut BuildReport) {
+    for pinned in &category.pinned_posts {
+        if !dir.join(pinned).is_dir() {
+            report.warn(
+                path,
+                format!(
+                    "pinned post \"{pinned}\" does not exist in {}",
+                    dir.display()
+                ),
+            );
+        }
+    }
+}
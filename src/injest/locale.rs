@@ -0,0 +1,474 @@
+use crate::injest::dates;
+use chrono::{DateTime, Datelike, FixedOffset, TimeZone, Utc};
+use color_eyre::Result;
+use language_tags::LanguageTag;
+use std::collections::HashMap;
+use tera::{Filter, Tera, Value};
+
+// names and separators for the languages we know how to format. anything else is formatted as
+// english, better than failing the build over a date.
+pub struct Locale {
+    months: [&'static str; 12],
+    months_short: [&'static str; 12],
+    weekdays: [&'static str; 7],
+    weekdays_short: [&'static str; 7],
+    date_pattern: &'static str,
+    decimal: &'static str,
+    group: &'static str,
+    // second, minute, hour, day, month, year as (one, many)
+    units: [(&'static str, &'static str); 6],
+    unit_space: bool,
+    past: &'static str,
+    future: &'static str,
+}
+
+const EN: Locale = Locale {
+    months: [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ],
+    months_short: [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ],
+    weekdays: [
+        "Monday",
+        "Tuesday",
+        "Wednesday",
+        "Thursday",
+        "Friday",
+        "Saturday",
+        "Sunday",
+    ],
+    weekdays_short: ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"],
+    date_pattern: "%B %-d, %Y",
+    decimal: ".",
+    group: ",",
+    units: [
+        ("second", "seconds"),
+        ("minute", "minutes"),
+        ("hour", "hours"),
+        ("day", "days"),
+        ("month", "months"),
+        ("year", "years"),
+    ],
+    unit_space: true,
+    past: "{} ago",
+    future: "in {}",
+};
+
+const KO: Locale = Locale {
+    months: [
+        "1월", "2월", "3월", "4월", "5월", "6월", "7월", "8월", "9월", "10월", "11월", "12월",
+    ],
+    months_short: [
+        "1월", "2월", "3월", "4월", "5월", "6월", "7월", "8월", "9월", "10월", "11월", "12월",
+    ],
+    weekdays: [
+        "월요일",
+        "화요일",
+        "수요일",
+        "목요일",
+        "금요일",
+        "토요일",
+        "일요일",
+    ],
+    weekdays_short: ["월", "화", "수", "목", "금", "토", "일"],
+    date_pattern: "%Y년 %-m월 %-d일",
+    decimal: ".",
+    group: ",",
+    units: [
+        ("초", "초"),
+        ("분", "분"),
+        ("시간", "시간"),
+        ("일", "일"),
+        ("개월", "개월"),
+        ("년", "년"),
+    ],
+    unit_space: false,
+    past: "{} 전",
+    future: "{} 후",
+};
+
+const JA: Locale = Locale {
+    months: [
+        "1月", "2月", "3月", "4月", "5月", "6月", "7月", "8月", "9月", "10月", "11月", "12月",
+    ],
+    months_short: [
+        "1月", "2月", "3月", "4月", "5月", "6月", "7月", "8月", "9月", "10月", "11月", "12月",
+    ],
+    weekdays: [
+        "月曜日",
+        "火曜日",
+        "水曜日",
+        "木曜日",
+        "金曜日",
+        "土曜日",
+        "日曜日",
+    ],
+    weekdays_short: ["月", "火", "水", "木", "金", "土", "日"],
+    date_pattern: "%Y年%-m月%-d日",
+    decimal: ".",
+    group: ",",
+    units: [
+        ("秒", "秒"),
+        ("分", "分"),
+        ("時間", "時間"),
+        ("日", "日"),
+        ("か月", "か月"),
+        ("年", "年"),
+    ],
+    unit_space: false,
+    past: "{}前",
+    future: "{}後",
+};
+
+const DE: Locale = Locale {
+    months: [
+        "Januar",
+        "Februar",
+        "März",
+        "April",
+        "Mai",
+        "Juni",
+        "Juli",
+        "August",
+        "September",
+        "Oktober",
+        "November",
+        "Dezember",
+    ],
+    months_short: [
+        "Jan.", "Feb.", "März", "Apr.", "Mai", "Juni", "Juli", "Aug.", "Sept.", "Okt.", "Nov.",
+        "Dez.",
+    ],
+    weekdays: [
+        "Montag",
+        "Dienstag",
+        "Mittwoch",
+        "Donnerstag",
+        "Freitag",
+        "Samstag",
+        "Sonntag",
+    ],
+    weekdays_short: ["Mo.", "Di.", "Mi.", "Do.", "Fr.", "Sa.", "So."],
+    date_pattern: "%-d. %B %Y",
+    decimal: ",",
+    group: ".",
+    units: [
+        ("Sekunde", "Sekunden"),
+        ("Minute", "Minuten"),
+        ("Stunde", "Stunden"),
+        ("Tag", "Tagen"),
+        ("Monat", "Monaten"),
+        ("Jahr", "Jahren"),
+    ],
+    unit_space: true,
+    past: "vor {}",
+    future: "in {}",
+};
+
+const FR: Locale = Locale {
+    months: [
+        "janvier",
+        "février",
+        "mars",
+        "avril",
+        "mai",
+        "juin",
+        "juillet",
+        "août",
+        "septembre",
+        "octobre",
+        "novembre",
+        "décembre",
+    ],
+    months_short: [
+        "janv.", "févr.", "mars", "avr.", "mai", "juin", "juil.", "août", "sept.", "oct.", "nov.",
+        "déc.",
+    ],
+    weekdays: [
+        "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi", "dimanche",
+    ],
+    weekdays_short: ["lun.", "mar.", "mer.", "jeu.", "ven.", "sam.", "dim."],
+    date_pattern: "%-d %B %Y",
+    decimal: ",",
+    group: "\u{202f}",
+    units: [
+        ("seconde", "secondes"),
+        ("minute", "minutes"),
+        ("heure", "heures"),
+        ("jour", "jours"),
+        ("mois", "mois"),
+        ("an", "ans"),
+    ],
+    unit_space: true,
+    past: "il y a {}",
+    future: "dans {}",
+};
+
+const ES: Locale = Locale {
+    months: [
+        "enero",
+        "febrero",
+        "marzo",
+        "abril",
+        "mayo",
+        "junio",
+        "julio",
+        "agosto",
+        "septiembre",
+        "octubre",
+        "noviembre",
+        "diciembre",
+    ],
+    months_short: [
+        "ene", "feb", "mar", "abr", "may", "jun", "jul", "ago", "sept", "oct", "nov", "dic",
+    ],
+    weekdays: [
+        "lunes",
+        "martes",
+        "miércoles",
+        "jueves",
+        "viernes",
+        "sábado",
+        "domingo",
+    ],
+    weekdays_short: ["lun", "mar", "mié", "jue", "vie", "sáb", "dom"],
+    date_pattern: "%-d de %B de %Y",
+    decimal: ",",
+    group: ".",
+    units: [
+        ("segundo", "segundos"),
+        ("minuto", "minutos"),
+        ("hora", "horas"),
+        ("día", "días"),
+        ("mes", "meses"),
+        ("año", "años"),
+    ],
+    unit_space: true,
+    past: "hace {}",
+    future: "dentro de {}",
+};
+
+impl Locale {
+    pub fn for_language(language: &LanguageTag) -> &'static Locale {
+        match language.primary_language() {
+            "ko" => &KO,
+            "ja" => &JA,
+            "de" => &DE,
+            "fr" => &FR,
+            "es" => &ES,
+            _ => &EN,
+        }
+    }
+
+    // chrono's strftime with the name specifiers (%B %b %A %a) in this locale
+    pub fn format_date(
+        &self,
+        date: &DateTime<FixedOffset>,
+        pattern: Option<&str>,
+    ) -> Result<String> {
+        let pattern = pattern.unwrap_or(self.date_pattern);
+        let month = date.month0() as usize;
+        let weekday = date.weekday().num_days_from_monday() as usize;
+
+        let mut localized = String::with_capacity(pattern.len());
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                localized.push(c);
+                continue;
+            }
+            let name = match chars.next() {
+                Some('B') => self.months[month],
+                Some('b') | Some('h') => self.months_short[month],
+                Some('A') => self.weekdays[weekday],
+                Some('a') => self.weekdays_short[weekday],
+                Some(other) => {
+                    localized.push('%');
+                    localized.push(other);
+                    continue;
+                }
+                None => {
+                    localized.push('%');
+                    continue;
+                }
+            };
+            localized.push_str(&name.replace('%', "%%"));
+        }
+
+        dates::format_date(date, &localized)
+    }
+
+    pub fn relative_time(&self, date: &DateTime<FixedOffset>, now: &DateTime<Utc>) -> String {
+        let seconds = now.signed_duration_since(*date).num_seconds();
+        let elapsed = seconds.unsigned_abs();
+        let (amount, unit) = match elapsed {
+            0..=59 => (elapsed, 0),
+            60..=3_599 => (elapsed / 60, 1),
+            3_600..=86_399 => (elapsed / 3_600, 2),
+            86_400..=2_591_999 => (elapsed / 86_400, 3),
+            2_592_000..=31_535_999 => (elapsed / 2_592_000, 4),
+            _ => (elapsed / 31_536_000, 5),
+        };
+
+        let (one, many) = self.units[unit];
+        let unit = if amount == 1 { one } else { many };
+        let span = if self.unit_space {
+            format!("{amount} {unit}")
+        } else {
+            format!("{amount}{unit}")
+        };
+
+        if seconds >= 0 {
+            self.past.replace("{}", &span)
+        } else {
+            self.future.replace("{}", &span)
+        }
+    }
+
+    pub fn format_number(&self, number: f64, decimals: Option<usize>) -> String {
+        let decimals = decimals.unwrap_or(if number.fract() == 0.0 { 0 } else { 2 });
+        let formatted = format!("{:.*}", decimals, number.abs());
+        let (whole, fraction) = match formatted.split_once('.') {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (formatted.as_str(), None),
+        };
+
+        let mut out = String::new();
+        if number < 0.0 && formatted.chars().any(|c| c.is_ascii_digit() && c != '0') {
+            out.push('-');
+        }
+        for (idx, digit) in whole.chars().enumerate() {
+            if idx != 0 && (whole.len() - idx) % 3 == 0 {
+                out.push_str(self.group);
+            }
+            out.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            out.push_str(self.decimal);
+            out.push_str(fraction);
+        }
+        out
+    }
+}
+
+// dates in templates are what front matter dates serialize to (rfc3339) or anything else
+// `dates::parse_date` understands, or a unix timestamp
+pub fn parse_date(value: &Value, offset: &FixedOffset) -> Option<DateTime<FixedOffset>> {
+    match value {
+        Value::Number(timestamp) => offset.timestamp_opt(timestamp.as_i64()?, 0).single(),
+        Value::String(date) => dates::parse_date(date, offset),
+        _ => None,
+    }
+}
+
+fn locale_arg(
+    args: &HashMap<String, Value>,
+    default: &LanguageTag,
+) -> tera::Result<&'static Locale> {
+    match args.get("locale") {
+        Some(Value::String(locale)) => match LanguageTag::parse(locale) {
+            Ok(language) => Ok(Locale::for_language(&language)),
+            Err(why) => Err(tera::Error::msg(format!("bad locale \"{locale}\": {why}"))),
+        },
+        Some(other) => Err(tera::Error::msg(format!(
+            "locale has to be a string, got {other}"
+        ))),
+        None => Ok(Locale::for_language(default)),
+    }
+}
+
+// `{{ page.date | format_date(locale=page.language, pattern="%A, %B %-d") }}`
+struct FormatDate {
+    default_language: LanguageTag,
+    offset: FixedOffset,
+}
+
+impl Filter for FormatDate {
+    fn filter(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let locale = locale_arg(args, &self.default_language)?;
+        let date = parse_date(value, &self.offset)
+            .ok_or_else(|| tera::Error::msg(format!("format_date: {value} is not a date")))?;
+        let pattern = args.get("pattern").and_then(Value::as_str);
+        let formatted = locale
+            .format_date(&date, pattern)
+            .map_err(|why| tera::Error::msg(format!("format_date: {why}")))?;
+        Ok(Value::String(formatted))
+    }
+}
+
+// `{{ page.date | relative_time(locale=page.language) }}`, relative to when the build started
+struct RelativeTime {
+    default_language: LanguageTag,
+    offset: FixedOffset,
+    now: DateTime<Utc>,
+}
+
+impl Filter for RelativeTime {
+    fn filter(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let locale = locale_arg(args, &self.default_language)?;
+        let date = parse_date(value, &self.offset)
+            .ok_or_else(|| tera::Error::msg(format!("relative_time: {value} is not a date")))?;
+        Ok(Value::String(locale.relative_time(&date, &self.now)))
+    }
+}
+
+// `{{ content.word_count | format_number(locale=page.language, decimals=0) }}`
+struct FormatNumber {
+    default_language: LanguageTag,
+}
+
+impl Filter for FormatNumber {
+    fn filter(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let locale = locale_arg(args, &self.default_language)?;
+        let number = value
+            .as_f64()
+            .ok_or_else(|| tera::Error::msg(format!("format_number: {value} is not a number")))?;
+        let decimals = args
+            .get("decimals")
+            .and_then(Value::as_u64)
+            .map(|decimals| decimals as usize);
+        Ok(Value::String(locale.format_number(number, decimals)))
+    }
+}
+
+// registered before the theme's own filters, so a theme can still replace them
+pub fn register_locale_filters(
+    tera: &mut Tera,
+    default_language: &LanguageTag,
+    offset: FixedOffset,
+) {
+    let now = Utc::now();
+    tera.register_filter(
+        "format_date",
+        FormatDate {
+            default_language: default_language.clone(),
+            offset,
+        },
+    );
+    tera.register_filter(
+        "relative_time",
+        RelativeTime {
+            default_language: default_language.clone(),
+            offset,
+            now,
+        },
+    );
+    tera.register_filter(
+        "format_number",
+        FormatNumber {
+            default_language: default_language.clone(),
+        },
+    );
+}
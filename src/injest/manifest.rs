@@ -0,0 +1,87 @@
+use crate::injest::url_path;
+use crate::util::stream_file;
+use chrono::{DateTime, Utc};
+use color_eyre::{Report, Result};
+use ignore::WalkBuilder;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{read_to_string, write};
+use std::path::Path;
+
+// at the root of the output, only ever served to admins
+pub const MANIFEST_FILE: &str = "moklog-manifest.json";
+
+// Every file of a finished build by its site path, for CDNs and deploy scripts to check what they
+// got and purge only what changed.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub generated: DateTime<Utc>,
+    // sha256 hex, what `sha256sum` prints
+    pub files: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    // paths whose content differs from `previous`, or that are new or gone
+    pub fn changed_since(&self, previous: &Manifest) -> Vec<String> {
+        let mut changed = self
+            .files
+            .iter()
+            .filter(|(path, hash)| previous.files.get(*path) != Some(hash))
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        changed.extend(
+            previous
+                .files
+                .keys()
+                .filter(|path| !self.files.contains_key(*path))
+                .cloned(),
+        );
+        changed.sort();
+        changed
+    }
+}
+
+pub fn build_manifest(site_output_path: impl AsRef<Path>) -> Result<Manifest> {
+    let root = site_output_path.as_ref();
+    let mut paths = Vec::new();
+    for entry in WalkBuilder::new(root).standard_filters(false).build() {
+        let entry = entry?;
+        if entry.file_type().map_or(false, |kind| kind.is_file()) {
+            paths.push(entry.into_path());
+        }
+    }
+
+    let files = paths
+        .par_iter()
+        .filter_map(|path| {
+            let site_path = url_path(path.strip_prefix(root).ok()?);
+            (site_path != MANIFEST_FILE).then_some((path, site_path))
+        })
+        .map(|(path, site_path)| {
+            let mut hash = Sha256::new();
+            stream_file(path, |chunk| hash.update(chunk))?;
+            Ok((format!("/{site_path}"), hex::encode(hash.finalize())))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+
+    Ok(Manifest {
+        generated: Utc::now(),
+        files,
+    })
+}
+
+pub fn write_manifest(site_output_path: impl AsRef<Path>, manifest: &Manifest) -> Result<()> {
+    write(
+        site_output_path.as_ref().join(MANIFEST_FILE),
+        serde_json::to_string_pretty(manifest)?,
+    )?;
+    Ok(())
+}
+
+pub fn read_manifest(site_output_path: impl AsRef<Path>) -> Result<Manifest> {
+    let path = site_output_path.as_ref().join(MANIFEST_FILE);
+    serde_json::from_str(&read_to_string(&path)?)
+        .map_err(|why| Report::msg(format!("{}: {why}", path.display())))
+}
@@ -0,0 +1,88 @@
+use crate::injest::asciidoc::AsciiDocOptions;
+use crate::injest::diagram::{CommandRenderer, DiagramRenderer};
+use crate::injest::generate::{parser_to_writer, MarkdownOptions};
+use color_eyre::Result;
+use orgize::Org;
+use pulldown_cmark::Parser;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// the page sources besides prebuilt html and notebooks, all with the same toml front matter
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Markup {
+    #[default]
+    Markdown,
+    Org,
+    Rst,
+    AsciiDoc,
+}
+
+impl Markup {
+    pub fn from_path(path: &Path) -> Option<Markup> {
+        match path.extension()?.to_str()? {
+            "md" => Some(Markup::Markdown),
+            "org" => Some(Markup::Org),
+            "rst" => Some(Markup::Rst),
+            "adoc" => Some(Markup::AsciiDoc),
+            _ => None,
+        }
+    }
+}
+
+// `[markup]` in site.toml
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarkupOptions {
+    // reads restructuredtext on stdin and writes an html fragment to stdout
+    #[serde(default = "default_rst")]
+    pub rst: CommandRenderer,
+    #[serde(default)]
+    pub asciidoc: AsciiDocOptions,
+    // `[[Page title]]` in markdown links to that page, see wikilinks.rs
+    #[serde(default)]
+    pub wikilinks: bool,
+}
+
+impl Default for MarkupOptions {
+    fn default() -> Self {
+        MarkupOptions {
+            rst: default_rst(),
+            asciidoc: AsciiDocOptions::default(),
+            wikilinks: false,
+        }
+    }
+}
+
+fn default_rst() -> CommandRenderer {
+    CommandRenderer {
+        command: "pandoc".to_string(),
+        args: ["--from", "rst", "--to", "html5"]
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+    }
+}
+
+// The body of a page (front matter already split off) as html. Only markdown goes through the
+// markdown pipeline, the rest come out of their own renderers and meet the others again in post
+// processing.
+pub fn render_markup(markup: Markup, body: &str, options: &MarkdownOptions) -> Result<String> {
+    let mut output = String::with_capacity(body.len());
+    match markup {
+        Markup::Markdown => match options.wikilinks {
+            Some(index) => {
+                let expanded = index.expand(body);
+                parser_to_writer(&mut output, Parser::new(&expanded), options)?
+            }
+            None => parser_to_writer(&mut output, Parser::new(body), options)?,
+        },
+        Markup::Org => {
+            let mut html = Vec::with_capacity(body.len());
+            Org::parse(body).write_html(&mut html)?;
+            output.push_str(&String::from_utf8(html)?);
+        }
+        Markup::Rst => output.push_str(&options.markup.rst.render(body)?),
+        Markup::AsciiDoc => output.push_str(&options.markup.asciidoc.command.render(body)?),
+    }
+    Ok(output)
+}
@@ -0,0 +1,78 @@
+use crate::util::hash_path;
+use crate::CACHE_DIR;
+use color_eyre::{Report, Result};
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+pub const VIDEO_EXTENSIONS: &[&str] = &["mp4", "webm", "ogv"];
+// .ogg is usually audio, a video in one still works, it just doesn't count as video here
+pub const AUDIO_EXTENSIONS: &[&str] = &["mp3", "flac", "ogg", "oga", "wav"];
+
+pub const POSTER_COMMAND: &str = "ffmpeg";
+
+// how far into the video the poster frame is taken, the very first frame is often black
+const POSTER_OFFSET: &str = "00:00:01";
+
+pub fn is_media(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => {
+            VIDEO_EXTENSIONS.contains(&extension) || AUDIO_EXTENSIONS.contains(&extension)
+        }
+        None => false,
+    }
+}
+
+// poster generation is optional, a build without ffmpeg just doesn't get posters
+pub fn posters_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        Command::new(POSTER_COMMAND)
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+    })
+}
+
+// A jpeg of a frame near the start of `video`. Kept in the cache by the video's content, so a
+// video is only ever decoded once.
+pub fn poster(video: &Path) -> Result<PathBuf> {
+    let dir = Path::new(CACHE_DIR).join("posters");
+    let output = dir.join(format!("{:016x}.jpg", hash_path(video)?));
+    if output.is_file() {
+        return Ok(output);
+    }
+    create_dir_all(&dir)?;
+
+    let attempt = |offset: Option<&str>| {
+        let mut command = Command::new(POSTER_COMMAND);
+        command.args(["-y", "-loglevel", "error"]);
+        if let Some(offset) = offset {
+            command.args(["-ss", offset]);
+        }
+        command
+            .arg("-i")
+            .arg(video)
+            .args(["-frames:v", "1", "-q:v", "3"])
+            .arg(&output)
+            .output()
+    };
+
+    // videos shorter than the offset have no frame there, fall back to the first one
+    let mut result = attempt(Some(POSTER_OFFSET))?;
+    if !result.status.success() || !output.is_file() {
+        result = attempt(None)?;
+    }
+    if !result.status.success() || !output.is_file() {
+        return Err(Report::msg(format!(
+            "{POSTER_COMMAND} could not take a poster from {}: {}",
+            video.display(),
+            String::from_utf8_lossy(&result.stderr)
+        )));
+    }
+    Ok(output)
+}
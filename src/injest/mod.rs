@@ -1,25 +1,103 @@
 use color_eyre::Result;
-use relative_path::RelativePath;
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
+pub mod access;
+pub mod anchors;
+pub mod asciidoc;
+pub mod assets;
+pub mod audit;
+pub mod blobs;
+pub mod breadcrumb;
 pub mod build;
+pub mod bundle;
+pub mod cache_policy;
+pub mod cdn;
+pub mod check;
+pub mod codeblock;
+pub mod config_meta;
+pub mod context_schema;
+pub mod critical_css;
+pub mod dates;
+pub mod diagram;
+pub mod diff;
+pub mod disqus;
+pub mod docs;
+pub mod downloads;
+pub mod dry_run;
+pub mod dynamic;
+pub mod ebook;
+pub mod errors;
+pub mod external_links;
+pub mod file_handler;
+pub mod fonts;
+pub mod fragment;
 pub mod generate;
+pub mod graph;
+pub mod history;
+pub mod hooks;
+pub mod include;
+pub mod ipfs;
+pub mod limits;
+pub mod links;
+pub mod lint;
+pub mod listing;
+pub mod locale;
+pub mod manifest;
+pub mod markup;
+pub mod media;
+pub mod notebook;
+pub mod notify;
+pub mod picture;
+pub mod print;
 pub mod processor;
+pub mod redirect;
+pub mod report;
+pub mod retention;
+pub mod sanitize;
+pub mod search;
+pub mod security_headers;
+pub mod site;
+pub mod slug;
+pub mod social_card;
+pub mod spellcheck;
 pub mod static_file;
+pub mod structured_data;
 pub mod stylesheet;
+pub mod suggestion;
+pub mod summary;
+pub mod svg;
+pub mod tags;
+pub mod template_check;
 pub mod templates;
+pub mod theme_docs;
+pub mod theme_test;
+pub mod translation;
+pub mod typography;
+pub mod validate;
+pub mod versions;
+pub mod webhook;
+pub mod wikilinks;
+pub mod workflow;
+
+// A relative path of the file system as the path of a url, `blog\\post` on windows is `blog/post`
+// too. Anything that isn't a plain name (`.`, `..`, a drive) is left out.
+pub fn url_path(relative: impl AsRef<Path>) -> String {
+    relative
+        .as_ref()
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
 
 pub fn path_relativizie(base: impl AsRef<Path>, item: impl AsRef<Path>) -> Result<String> {
-    let base = RelativePath::new(base.as_ref());
-    let item = RelativePath::new(item);
-    let new = item.strip_prefix(base)?;
-    Ok(new.to_string())
+    Ok(url_path(item.as_ref().strip_prefix(base.as_ref())?))
 }
 
 pub fn path_relativizie_path(base: impl AsRef<Path>, item: impl AsRef<Path>) -> Result<PathBuf> {
-    let base = RelativePath::new(base.as_ref());
-    let item = RelativePath::new(item);
-    let new = item.strip_prefix(base)?;
-    Ok(new.into())
+    Ok(item.as_ref().strip_prefix(base.as_ref())?.to_path_buf())
 }
@@ -0,0 +1,318 @@
+use crate::injest::{
+    assets::AssetStore,
+    codeblock::{render_codeblock, FenceInfo},
+    dates::{normalize_dates, parse_date},
+    generate::{escape_to_writer, parse_highlight_write_code, parser_to_writer, MarkdownOptions},
+    static_file::hash_file,
+};
+use crate::CACHE_DIR;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, FixedOffset};
+use color_eyre::{Report, Result};
+use pulldown_cmark::Parser;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::{create_dir_all, write};
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+// wraps the rendered notebook if the theme has it, generic.html otherwise
+pub const NOTEBOOK_TEMPLATE: &str = "notebook.html";
+
+// nbformat 4 stores text either as one string or as a list of lines
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MultilineString {
+    One(String),
+    Lines(Vec<String>),
+}
+
+impl MultilineString {
+    pub fn text(&self) -> String {
+        match self {
+            MultilineString::One(text) => text.clone(),
+            MultilineString::Lines(lines) => lines.concat(),
+        }
+    }
+}
+
+impl Default for MultilineString {
+    fn default() -> Self {
+        MultilineString::One(String::new())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Notebook {
+    pub cells: Vec<Cell>,
+    #[serde(default)]
+    pub metadata: NotebookMetadata,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NotebookMetadata {
+    pub kernelspec: Option<KernelSpec>,
+    pub language_info: Option<LanguageInfo>,
+    // front matter kept inside the notebook, used when there is no sidecar .toml
+    pub moklog: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KernelSpec {
+    pub language: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LanguageInfo {
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cell {
+    pub cell_type: String,
+    #[serde(default)]
+    pub source: MultilineString,
+    pub execution_count: Option<u64>,
+    #[serde(default)]
+    pub outputs: Vec<Output>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Output {
+    pub output_type: String,
+    // streams
+    pub name: Option<String>,
+    pub text: Option<MultilineString>,
+    // execute_result and display_data, by mime type
+    #[serde(default)]
+    pub data: BTreeMap<String, MultilineString>,
+    // errors
+    pub ename: Option<String>,
+    pub evalue: Option<String>,
+    #[serde(default)]
+    pub traceback: Vec<String>,
+}
+
+impl Notebook {
+    pub fn parse(data: &str) -> Result<Notebook> {
+        Ok(serde_json::from_str(data)?)
+    }
+
+    // what the code cells are written in, for highlighting
+    pub fn language(&self) -> &str {
+        self.metadata
+            .language_info
+            .as_ref()
+            .and_then(|info| info.name.as_deref())
+            .or_else(|| {
+                self.metadata
+                    .kernelspec
+                    .as_ref()
+                    .and_then(|spec| spec.language.as_deref())
+            })
+            .unwrap_or("python")
+    }
+}
+
+// `post.ipynb` -> `post.toml`
+pub fn sidecar_path(notebook: &Path) -> PathBuf {
+    notebook.with_extension("toml")
+}
+
+pub fn is_sidecar(path: &Path) -> bool {
+    path.extension().unwrap_or_default() == "toml" && path.with_extension("ipynb").is_file()
+}
+
+// the front matter of a notebook, from `<name>.toml` next to it or `metadata.moklog` inside it
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NotebookMeta {
+    pub title: Option<String>,
+    pub date: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // instead of notebook.html
+    pub template: Option<String>,
+    // leave the outputs out and only show the code
+    #[serde(default)]
+    pub hide_outputs: bool,
+}
+
+impl NotebookMeta {
+    // the sidecar wins if there is one
+    pub fn load(
+        notebook: &Notebook,
+        sidecar: Option<&str>,
+        offset: &FixedOffset,
+    ) -> Result<NotebookMeta> {
+        let mut header = match (sidecar, &notebook.metadata.moklog) {
+            (Some(sidecar), _) => toml::from_str::<Value>(sidecar)?,
+            (None, Some(moklog)) => Value::try_from(moklog)?,
+            (None, None) => return Ok(NotebookMeta::default()),
+        };
+        normalize_dates(&mut header, offset);
+
+        // json has no dates, only strings that look like one
+        if let Some(Value::String(date)) = header.get("date") {
+            let date = parse_date(date, offset)
+                .ok_or_else(|| Report::msg(format!("\"{date}\" is not a date")))?;
+            header
+                .as_table_mut()
+                .unwrap()
+                .insert("date".to_string(), Value::String(date.to_rfc3339()));
+        }
+        Ok(header.try_into::<NotebookMeta>()?)
+    }
+}
+
+// terminal colours in tracebacks
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' && chars.peek() == Some(&'[') {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
+        stripped.push(c);
+    }
+    stripped
+}
+
+// Embedded images go through the static pipeline like any other file: written to the cache by
+// content and hashed into the asset store. Returns the site path.
+fn extract_image(data: &str, extension: &str, assets: &AssetStore) -> Result<String> {
+    let cleaned = data
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    let bytes = STANDARD.decode(cleaned)?;
+
+    let dir = Path::new(CACHE_DIR).join("notebooks");
+    create_dir_all(&dir)?;
+    let path = dir.join(format!("output-{:016x}.{extension}", hash_file(&bytes)));
+    if !path.is_file() {
+        write(&path, bytes)?;
+    }
+    assets.site_path(path)
+}
+
+fn write_pre(out: &mut String, class: &str, text: &str) -> Result<()> {
+    write!(out, r#"<pre class="{class}">"#)?;
+    escape_to_writer(out, text)?;
+    out.push_str("</pre>");
+    Ok(())
+}
+
+fn write_output(
+    out: &mut String,
+    output: &Output,
+    markdown: &MarkdownOptions,
+    assets: &AssetStore,
+    source: &str,
+) -> Result<()> {
+    match output.output_type.as_str() {
+        "stream" => {
+            let name = output.name.as_deref().unwrap_or("stdout");
+            let text = output
+                .text
+                .as_ref()
+                .map(MultilineString::text)
+                .unwrap_or_default();
+            write_pre(out, &format!("notebook-stream notebook-{name}"), &text)?;
+        }
+        "error" => {
+            let traceback = match output.traceback.is_empty() {
+                true => format!(
+                    "{}: {}",
+                    output.ename.as_deref().unwrap_or_default(),
+                    output.evalue.as_deref().unwrap_or_default()
+                ),
+                false => strip_ansi(&output.traceback.join("\n")),
+            };
+            write_pre(out, "notebook-error", &traceback)?;
+        }
+        "execute_result" | "display_data" => {
+            let data = &output.data;
+            // richest first, text/plain is always there as the fallback
+            if let Some(svg) = data.get("image/svg+xml") {
+                out.push_str(&markdown.svg.process(&svg.text(), source)?);
+            } else if let Some((mime, image)) = ["image/png", "image/jpeg", "image/gif"]
+                .iter()
+                .find_map(|mime| data.get(*mime).map(|image| (mime, image)))
+            {
+                let extension = mime.trim_start_matches("image/").replace("jpeg", "jpg");
+                let site_path = extract_image(&image.text(), &extension, assets)?;
+                write!(out, r#"<img src="{site_path}" alt="">"#)?;
+            } else if let Some(html) = data.get("text/html") {
+                write!(out, r#"<div class="notebook-html">{}</div>"#, html.text())?;
+            } else if let Some(text) = data.get("text/markdown") {
+                parser_to_writer(&mut *out, Parser::new(&text.text()), markdown)?;
+            } else if let Some(text) = data.get("text/plain") {
+                write_pre(out, "notebook-text", &text.text())?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// The notebook as html: markdown cells through the markdown pipeline, code cells highlighted like
+// any other code block, outputs below their cell. `source` is the notebook's path in the content
+// repo, for the svg allowlist.
+pub fn render_notebook(
+    notebook: &Notebook,
+    meta: &NotebookMeta,
+    markdown: &MarkdownOptions,
+    assets: &AssetStore,
+    source: &str,
+) -> Result<String> {
+    let language = notebook.language();
+    let fence = FenceInfo::parse(language);
+    let mut out = String::new();
+
+    for cell in &notebook.cells {
+        let text = cell.source.text();
+        match cell.cell_type.as_str() {
+            "markdown" => {
+                out.push_str(r#"<div class="notebook-cell notebook-markdown">"#);
+                parser_to_writer(&mut out, Parser::new(&text), markdown)?;
+                out.push_str("</div>");
+            }
+            "code" => {
+                out.push_str(r#"<div class="notebook-cell notebook-code">"#);
+                if let Some(count) = cell.execution_count {
+                    write!(out, r#"<span class="notebook-prompt">[{count}]</span>"#)?;
+                }
+                let mut highlighted = String::new();
+                if parse_highlight_write_code(&mut highlighted, &text, Some(language)).is_err() {
+                    highlighted.clear();
+                    escape_to_writer(&mut highlighted, &text)?;
+                }
+                out.push_str(&render_codeblock(
+                    markdown.codeblock_template,
+                    &fence,
+                    &text,
+                    &highlighted,
+                ));
+                if !meta.hide_outputs && !cell.outputs.is_empty() {
+                    out.push_str(r#"<div class="notebook-outputs">"#);
+                    for output in &cell.outputs {
+                        write_output(&mut out, output, markdown, assets, source)?;
+                    }
+                    out.push_str("</div>");
+                }
+                out.push_str("</div>");
+            }
+            // raw cells are for nbconvert, not for us
+            _ => {}
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,234 @@
+use crate::injest::webhook::{sign, EVENT_HEADER, SIGNATURE_HEADER};
+use color_eyre::{Report, Result};
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::env::var;
+use std::time::Duration;
+use tracing::warn;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyEvent {
+    BuildFailed,
+    // something a reader sent in waits on an admin, translation suggestions for now
+    AwaitingModeration,
+    // a page is in review, only told to roles that may review it
+    EnteredReview,
+}
+
+impl NotifyEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NotifyEvent::BuildFailed => "build_failed",
+            NotifyEvent::AwaitingModeration => "awaiting_moderation",
+            NotifyEvent::EnteredReview => "entered_review",
+        }
+    }
+}
+
+// where a notification goes, secrets only ever by the name of the variable holding them
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "channel", rename_all = "snake_case")]
+pub enum Channel {
+    Smtp(SmtpChannel),
+    Matrix(MatrixChannel),
+    Webhook(WebhookChannel),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmtpChannel {
+    pub host: String,
+    // 587 with STARTTLS if not set
+    pub port: Option<u16>,
+    pub from: String,
+    pub to: Vec<String>,
+    pub username_env: Option<String>,
+    pub password_env: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatrixChannel {
+    pub homeserver: String,
+    // the room id, `!abc:example.org`, not an alias
+    pub room: String,
+    pub token_env: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookChannel {
+    pub url: String,
+    pub secret_env: Option<String>,
+}
+
+// `[[notify]]` in site.toml, one person's (or one team's) subscription. There are no accounts, a
+// role is who someone is, the same one their role token carries.
+//
+// [[notify]]
+// role = "alice"
+// events = ["entered_review"]
+// channel = "matrix"
+// homeserver = "https://matrix.org"
+// room = "!editorial:matrix.org"
+// token_env = "MOKLOG_MATRIX_TOKEN"
+//
+// [[notify]]
+// role = "editor"
+// events = ["build_failed", "awaiting_moderation"]
+// channel = "smtp"
+// host = "smtp.example.org"
+// from = "moklog <moklog@example.org>"
+// to = ["editors@example.org"]
+// username_env = "MOKLOG_SMTP_USER"
+// password_env = "MOKLOG_SMTP_PASSWORD"
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subscription {
+    pub role: String,
+    // every event if empty
+    #[serde(default)]
+    pub events: Vec<NotifyEvent>,
+    #[serde(flatten)]
+    pub channel: Channel,
+}
+
+impl Subscription {
+    // `audience` is who the event is about, everyone subscribed if None
+    pub fn wants(&self, event: NotifyEvent, audience: Option<&BTreeSet<String>>) -> bool {
+        (self.events.is_empty() || self.events.contains(&event))
+            && audience.map_or(true, |roles| roles.contains(&self.role))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    pub event: NotifyEvent,
+    pub site: String,
+    pub subject: String,
+    pub body: String,
+    pub url: Option<String>,
+}
+
+impl Notification {
+    fn text(&self) -> String {
+        match &self.url {
+            Some(url) => format!("{}\n\n{url}", self.body),
+            None => self.body.clone(),
+        }
+    }
+}
+
+fn env(name: &str) -> Result<String> {
+    var(name).map_err(|_| Report::msg(format!("{name} is not set")))
+}
+
+impl SmtpChannel {
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        let mut message = Message::builder()
+            .from(self.from.parse::<Mailbox>()?)
+            .subject(format!("[{}] {}", notification.site, notification.subject));
+        for to in &self.to {
+            message = message.to(to.parse::<Mailbox>()?);
+        }
+        let message = message.body(notification.text())?;
+
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.host)?
+            .port(self.port.unwrap_or(587))
+            .timeout(Some(TIMEOUT));
+        match (&self.username_env, &self.password_env) {
+            (Some(username), Some(password)) => {
+                transport = transport.credentials(Credentials::new(env(username)?, env(password)?))
+            }
+            (None, None) => {}
+            _ => return Err(Report::msg("smtp needs both username_env and password_env")),
+        }
+        transport.build().send(message).await?;
+        Ok(())
+    }
+}
+
+impl MatrixChannel {
+    async fn send(&self, client: &Client, notification: &Notification) -> Result<()> {
+        // unique per message, the homeserver drops a retried one with the same id
+        let transaction = format!("moklog-{}", rand::random::<u64>());
+        let room = url::form_urlencoded::byte_serialize(self.room.as_bytes()).collect::<String>();
+        let response = client
+            .put(format!(
+                "{}/_matrix/client/v3/rooms/{room}/send/m.room.message/{transaction}",
+                self.homeserver.trim_end_matches('/')
+            ))
+            .bearer_auth(env(&self.token_env)?)
+            .json(&json!({
+                "msgtype": "m.text",
+                "body": format!("{}\n{}", notification.subject, notification.text()),
+            }))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(Report::msg(format!("answered {}", response.status())));
+        }
+        Ok(())
+    }
+}
+
+impl WebhookChannel {
+    // the notification as json, signed the way webhooks are
+    async fn send(&self, client: &Client, notification: &Notification) -> Result<()> {
+        let body = serde_json::to_string(notification)?;
+        let mut request = client
+            .post(&self.url)
+            .header("content-type", "application/json")
+            .header(EVENT_HEADER, notification.event.name());
+        if let Some(name) = &self.secret_env {
+            request = request.header(SIGNATURE_HEADER, sign(&env(name)?, &body)?);
+        }
+        let response = request.body(body).send().await?;
+        if !response.status().is_success() {
+            return Err(Report::msg(format!("answered {}", response.status())));
+        }
+        Ok(())
+    }
+}
+
+// Sends to every subscription that wants it. A channel that fails is logged and otherwise
+// ignored, like a webhook it never fails whatever it's about.
+pub async fn notify(
+    subscriptions: &[Subscription],
+    notification: &Notification,
+    audience: Option<&BTreeSet<String>>,
+) {
+    let subscriptions = subscriptions
+        .iter()
+        .filter(|subscription| subscription.wants(notification.event, audience))
+        .collect::<Vec<_>>();
+    if subscriptions.is_empty() {
+        return;
+    }
+    let client = match Client::builder().timeout(TIMEOUT).build() {
+        Ok(client) => client,
+        Err(why) => {
+            warn!("no notifications for {}: {why}", notification.event.name());
+            return;
+        }
+    };
+    for subscription in subscriptions {
+        let sent = match &subscription.channel {
+            Channel::Smtp(smtp) => smtp.send(notification).await,
+            Channel::Matrix(matrix) => matrix.send(&client, notification).await,
+            Channel::Webhook(webhook) => webhook.send(&client, notification).await,
+        };
+        if let Err(why) = sent {
+            warn!(
+                "notifying {} of {}: {why}",
+                subscription.role,
+                notification.event.name()
+            );
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use crate::injest::report::BuildReport;
+use html_escape::encode_double_quoted_attribute;
+use pulldown_cmark::{CowStr, Event, Tag};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const LIGHT_FRAGMENT: &str = "#light";
+const DARK_FRAGMENT: &str = "#dark";
+
+// custom properties declared by the theme, compiled into `colors.css`:
+//
+// [colors.light]
+// background = "#ffffff"
+// [colors.dark]
+// background = "#1e1e1e"
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColorSchemes {
+    #[serde(default)]
+    pub light: BTreeMap<String, String>,
+    #[serde(default)]
+    pub dark: BTreeMap<String, String>,
+}
+
+pub const COLOR_SCHEME_STYLESHEET: &str = "colors.css";
+
+impl ColorSchemes {
+    pub fn is_empty(&self) -> bool {
+        self.light.is_empty() && self.dark.is_empty()
+    }
+
+    pub fn validate(&self, theme: &str, report: &mut BuildReport) {
+        for name in self.dark.keys() {
+            if !self.light.contains_key(name) {
+                report.warn(
+                    theme,
+                    format!("color \"{name}\" only has a dark value, light mode will not set it"),
+                );
+            }
+        }
+        for name in self.light.keys() {
+            if !self.dark.is_empty() && !self.dark.contains_key(name) {
+                report.warn(
+                    theme,
+                    format!("color \"{name}\" has no dark value, dark mode will use the light one"),
+                );
+            }
+        }
+    }
+
+    fn properties(colors: &BTreeMap<String, String>) -> String {
+        let mut out = String::new();
+        for (name, value) in colors {
+            write!(out, "--{}:{value};", name.trim_start_matches("--")).ok();
+        }
+        out
+    }
+
+    pub fn to_css(&self) -> String {
+        let mut css = format!(":root{{color-scheme:light dark;{}}}", Self::properties(&self.light));
+        if !self.dark.is_empty() {
+            write!(
+                css,
+                "@media (prefers-color-scheme: dark){{:root{{{}}}}}",
+                Self::properties(&self.dark)
+            )
+            .ok();
+        }
+        css
+    }
+}
+
+fn picture_html(alt: &str, title: &str, light: &str, dark: &str) -> String {
+    let mut out = String::from("<picture>");
+    write!(
+        out,
+        r#"<source srcset="{}" media="(prefers-color-scheme: dark)">"#,
+        encode_double_quoted_attribute(dark)
+    )
+    .ok();
+    write!(
+        out,
+        r#"<img src="{}" alt="{}""#,
+        encode_double_quoted_attribute(light),
+        encode_double_quoted_attribute(alt)
+    )
+    .ok();
+    if !title.is_empty() {
+        write!(out, r#" title="{}""#, encode_double_quoted_attribute(title)).ok();
+    }
+    out.push_str("></picture>");
+    out
+}
+
+// `(dark.png#dark)` at the start of the text right after an image, returns the source and the rest
+fn split_dark_source(text: &str) -> Option<(&str, &str)> {
+    let inner = text.strip_prefix('(')?;
+    let (source, rest) = inner.split_once(')')?;
+    let source = source.trim().strip_suffix(DARK_FRAGMENT)?;
+    if source.is_empty() || source.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((source, rest))
+}
+
+// `![alt](light.png#light)(dark.png#dark)` becomes a <picture> that follows the color scheme.
+// anything else passes through untouched.
+pub fn dark_mode_images<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    let mut out = Vec::with_capacity(events.len());
+    let mut iter = events.into_iter().peekable();
+
+    while let Some(event) = iter.next() {
+        let (dest, title) = match &event {
+            Event::Start(Tag::Image(_, dest, title)) if dest.ends_with(LIGHT_FRAGMENT) => {
+                (dest.clone(), title.clone())
+            }
+            _ => {
+                out.push(event);
+                continue;
+            }
+        };
+
+        // alt text and the end of the image
+        let mut image = vec![event];
+        let mut alt = String::new();
+        for inner in iter.by_ref() {
+            let end = matches!(inner, Event::End(Tag::Image(..)));
+            if let Event::Text(text) | Event::Code(text) = &inner {
+                alt.push_str(text);
+            }
+            image.push(inner);
+            if end {
+                break;
+            }
+        }
+
+        // text may come in more than one piece
+        let mut following = String::new();
+        while let Some(Event::Text(text)) = iter.peek() {
+            following.push_str(text);
+            iter.next();
+        }
+
+        match split_dark_source(&following) {
+            Some((dark, rest)) => {
+                let light = dest.trim_end_matches(LIGHT_FRAGMENT);
+                out.push(Event::Html(CowStr::from(picture_html(&alt, &title, light, dark))));
+                if !rest.is_empty() {
+                    out.push(Event::Text(CowStr::from(rest.to_string())));
+                }
+            }
+            None => {
+                out.extend(image);
+                if !following.is_empty() {
+                    out.push(Event::Text(CowStr::from(following)));
+                }
+            }
+        }
+    }
+
+    out
+}
@@ -0,0 +1,78 @@
+use crate::injest::links::SiteUrl;
+use color_eyre::Result;
+use html_escape::{encode_double_quoted_attribute, encode_text};
+use lol_html::{element, html_content::ContentType, rewrite_str, Settings};
+
+// wraps a page's print rendition if the theme has it, a plain page with PRINT_STYLE otherwise
+pub const PRINT_TEMPLATE: &str = "print.html";
+
+// next to the page's index.html, so relative links and images work the same from both
+pub const PRINT_FILE: &str = "print.html";
+
+// Page breaks kept away from headings and out of code, figures and tables, and everything that
+// only makes sense on a screen hidden. print.html gets it as `page.print_style`.
+pub const PRINT_STYLE: &str = "@page { margin: 2cm; }
+body { font: 11pt/1.5 Georgia, serif; color: #000; background: #fff; margin: 0; }
+h1, h2, h3, h4, h5, h6 { break-after: avoid; page-break-after: avoid; }
+pre, blockquote, figure, table, img, svg { break-inside: avoid; page-break-inside: avoid; }
+p { orphans: 3; widows: 3; }
+pre { white-space: pre-wrap; }
+img, svg { max-width: 100%; }
+a { color: inherit; }
+.print-url { font-size: 0.85em; word-break: break-all; }
+.footnote-definition { font-size: 0.9em; }
+nav, video, audio, iframe, .no-print { display: none; }
+";
+
+// `/blog/post` -> `/blog/post/print.html`
+pub fn print_path(page: &str) -> String {
+    format!("{}/{PRINT_FILE}", page.trim_end_matches('/'))
+}
+
+// what a link prints as after its text, None for links within the page and relative ones
+fn printed_url(href: &str, urls: &SiteUrl) -> Option<String> {
+    if let Some(rest) = href.strip_prefix("//") {
+        return Some(format!("https://{rest}"));
+    }
+    if href.starts_with('/') {
+        return Some(urls.absolute(href));
+    }
+    match url::Url::parse(href) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Some(href.to_string()),
+        Ok(url) if url.scheme() == "mailto" => Some(url.path().to_string()),
+        _ => None,
+    }
+}
+
+// Every link followed by where it goes, ` (https://example.com/)`, for paper and for readers that
+// don't do `a[href]:after` in css. Footnote references point within the page and are left alone.
+pub fn expand_link_urls(content: &str, urls: &SiteUrl) -> Result<String> {
+    Ok(rewrite_str(
+        content,
+        Settings {
+            element_content_handlers: vec![element!("a[href]", |el| {
+                let printed = el
+                    .get_attribute("href")
+                    .and_then(|href| printed_url(&href, urls));
+                if let Some(url) = printed {
+                    el.after(
+                        &format!(r#" <span class="print-url">({})</span>"#, encode_text(&url)),
+                        ContentType::Html,
+                    );
+                }
+                Ok(())
+            })],
+            ..Settings::default()
+        },
+    )?)
+}
+
+// the print rendition of a theme without a print.html
+pub fn default_print_page(title: &str, language: &str, content: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html><html lang="{}"><head><meta charset="utf-8"><title>{}</title><style>{PRINT_STYLE}</style></head><body><article><h1>{}</h1>{content}</article></body></html>"#,
+        encode_double_quoted_attribute(language),
+        encode_text(title),
+        encode_text(title),
+    )
+}
@@ -1,18 +1,27 @@
-use crate::injest::static_file::new_filename;
+use crate::injest::breadcrumb::{breadcrumb_json_ld, Breadcrumb};
+use crate::injest::assets::{resolve_asset, AssetStore};
+use crate::injest::audit::accessibility_audit;
+use crate::injest::bundle::Bundle;
+use crate::injest::structured_data::json_ld_script;
+use crate::injest::translation::Alternate;
+use crate::injest::validate::{validate_html, HtmlValidation};
+use crate::injest::critical_css::{critical_css, used_selectors};
+use crate::injest::external_links::annotate_external_link;
+use crate::injest::media::{poster, posters_available};
+use crate::injest::site::BuildOptions;
+use crate::injest::links::SiteUrl;
+use crate::injest::report::BuildReport;
+use crate::injest::social_card::{CARD_HEIGHT, CARD_WIDTH};
+use crate::injest::summary::Summary;
+use crate::injest::typography::{rules_for, typeset_document};
 use color_eyre::Result;
-use dashmap::DashMap;
-use lol_html::html_content::{Element, TextType};
-use lol_html::{element, rewrite_str, text, HtmlRewriter, Settings};
+use language_tags::LanguageTag;
+use lol_html::html_content::{ContentType, Element};
+use lol_html::{element, rewrite_str, HtmlRewriter, Settings};
 use std::io::Write;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
-use crate::mmap_load;
-
-pub fn title_make_url_safe(title: &str) -> String {
-    let mut no_whitespace = title.replace(" ", "-");
-    url_escape::encode(&no_whitespace, &url_escape::PATH).to_string()
-}
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::warn;
 
 pub struct DocumentStatistics {
     pub characters: u64,
@@ -20,15 +29,17 @@ pub struct DocumentStatistics {
 }
 
 pub fn static_file_rewriter(
-    path: String,
-    files: Arc<DashMap<String, String>>,
+    site_root: &Path,
+    source: &Path,
+    assets: &AssetStore,
     out: &mut impl Write,
     data_in: impl AsRef<[u8]>,
 ) -> Result<()> {
     let mut rewriter = HtmlRewriter::new(
         Settings {
             element_content_handlers: vec![element!("[href]", |el| {
-                static_file_rewrite_element(path.as_str(), files.clone(), el)
+                static_file_rewrite_element(site_root, source, assets, el);
+                Ok(())
             })],
             document_content_handlers: vec![],
             ..Default::default()
@@ -40,87 +51,319 @@ pub fn static_file_rewriter(
     Ok(())
 }
 
+// points links to files of the content repo at their single hashed copy
 fn static_file_rewrite_element(
-    path: &str,
-    files: Arc<DashMap<u64, PathBuf>>,
+    site_root: &Path,
+    source: &Path,
+    assets: &AssetStore,
     element: &mut Element,
 ) {
-    let (da_linkie, attr) = match (element.get_attribute("href"), element.get_attribute("src")) {
-        (Some(linkie), None) => (linkie, "href"),
-        (None, Some(linkie)) => (linkie, "src"),
-        (_, _) => return,
+    let (da_linkie, attr) = match (element.get_attribute("href"), element.get_attribute("src"), element.get_attribute("srcset")) {
+        (Some(linkie), None, None) => (linkie, "href"),
+        (None, Some(linkie), None) => (linkie, "src"),
+        // only a lone source, not a list of candidates
+        (None, None, Some(linkie)) if !linkie.contains([' ', ',']) => (linkie, "srcset"),
+        (_, _, _) => return,
     };
 
     if let Ok(_) = url::Url::parse(&da_linkie) {
         return;
     }
 
-    let file_read = mmap_load!(&da_linkie);
+    let file = match resolve_asset(site_root, source, &da_linkie) {
+        Some(file) if file.is_file() => file,
+        _ => return,
+    };
+    // other pages are links, not assets
+    if matches!(file.extension().and_then(|ext| ext.to_str()), Some("md" | "org" | "rst" | "adoc" | "html" | "ipynb" | "moklog")) {
+        return;
+    }
+
+    match assets.site_path(&file) {
+        Ok(site_path) => element.set_attribute(attr, &site_path).unwrap(),
+        Err(why) => warn!("failed to add {} as an asset: {why}", file.display()),
+    }
+}
+
+// has to run before the src is rewritten, it needs the file in the content repo
+fn add_poster(post: &PostProcessContext, assets: &AssetStore, element: &mut Element) {
+    let src = match element.get_attribute("src") {
+        Some(src) => src,
+        None => return,
+    };
+    let video = match resolve_asset(post.site_root, post.source, &src) {
+        Some(video) if video.is_file() => video,
+        _ => return,
+    };
+
+    let poster = poster(&video).and_then(|poster| assets.site_path(poster));
+    match poster {
+        Ok(site_path) => element
+            .set_attribute("poster", &post.urls.link(&site_path))
+            .unwrap(),
+        Err(why) => post.report.lock().unwrap().warn(post.source, format!("no poster: {why}")),
+    }
+}
 
-    let (_, filename) = match new_filename(file_read, &da_linkie) {
-        Some(h) => h,
+fn rewrite_internal_link(urls: &SiteUrl, element: &mut Element) {
+    let attr = ["href", "src", "srcset"]
+        .into_iter()
+        .find(|attr| element.has_attribute(attr))
+        .unwrap_or("href");
+    let link = match element.get_attribute(attr) {
+        Some(link) => link,
         None => return,
     };
 
-    let filename = format!("/{filename}");
+    if let Some(new_link) = urls.rewrite_internal(&link) {
+        element.set_attribute(attr, &new_link).unwrap();
+    }
+}
 
-    element.set_attribute(attr, &filename).unwrap();
+// the same url `content.sections` gives a feed, relative so it works on any copy of the page
+fn add_section_permalink(element: &mut Element) {
+    let id = match element.get_attribute("id") {
+        Some(id) if !id.is_empty() => id,
+        _ => return,
+    };
+    element.append(
+        &format!(
+            r##"<a class="section-permalink" href="#{}" aria-label="link to this section">#</a>"##,
+            html_escape::encode_double_quoted_attribute(&url_escape::encode_fragment(&id))
+        ),
+        ContentType::Html,
+    );
 }
 
 pub struct ProcessedDocument {
     document: String,
-    summary: String,
+    summary: Summary,
     full_title: String,
 }
 
+impl ProcessedDocument {
+    pub fn document(&self) -> &str {
+        &self.document
+    }
+}
+
+// everything post processing needs besides the document itself
+pub struct PostProcessContext<'a> {
+    pub urls: &'a SiteUrl,
+    pub bundle: Option<&'a Bundle>,
+    pub options: &'a BuildOptions,
+    pub breadcrumbs: &'a [Breadcrumb],
+    // json-ld describing the page itself, if turned on
+    pub structured_data: Option<&'a serde_json::Value>,
+    // site path of the page this document is, translations included
+    pub canonical: &'a str,
+    // every language version of the page, empty if it isn't translated
+    pub alternates: &'a [Alternate],
+    pub site_root: &'a Path,
+    // the file the page was built from (relative to site_root), findings of the optional stages
+    // are reported against it
+    pub source: &'a Path,
+    pub report: &'a Mutex<BuildReport>,
+    // site path of the generated social card, the theme's own og:image wins over it
+    pub og_image: Option<&'a str>,
+    // of the content the page was rendered from, see summary.rs
+    pub summary: Summary,
+    // unlisted and private pages ask search engines to stay away too
+    pub noindex: bool,
+    // of the page, picks the typography rules
+    pub language: &'a LanguageTag,
+    // site path of the page's print rendition, see print.rs
+    pub print: Option<&'a str>,
+}
+
+// whether the theme already put an og:image in the page
+fn has_og_image(data_in: &str) -> Result<bool> {
+    let found = std::cell::Cell::new(false);
+    rewrite_str(
+        data_in,
+        Settings {
+            element_content_handlers: vec![element!(r#"meta[property="og:image"]"#, |_| {
+                found.set(true);
+                Ok(())
+            })],
+            ..Settings::default()
+        },
+    )?;
+    Ok(found.get())
+}
+
 pub fn html_post_processor(
     path: &str,
-    files: Arc<DashMap<u64, PathBuf>>,
+    assets: &AssetStore,
+    post: &PostProcessContext,
     data_in: &str,
 ) -> Result<ProcessedDocument> {
-    let character_count = AtomicU64::new(0);
-    let mut skip: bool = false;
+    let urls = post.urls;
 
-    let summary_generator = Settings {
-        element_content_handlers: vec![
-            element!("*", |el| {
-                if character_count.load(Ordering::SeqCst) > 200 {
-                    if el.tag_name() == "p" {
-                        skip = true;
-                    }
-                }
+    let critical = match post.bundle.and_then(|bundle| bundle.style.as_ref()) {
+        Some(style) if post.options.critical_css => {
+            Some(critical_css(&style.contents, &used_selectors(data_in)?)?)
+        }
+        _ => None,
+    };
 
-                if skip {
-                    el.remove();
-                }
-            }),
-            text!("*", |txt| {
-                if TextType::Data == txt.text_type() {
-                    character_count.fetch_add(txt.as_str().len() as u64, Ordering::SeqCst);
-                }
-            }),
-        ],
-        ..Settings::default()
+    let og_image = match post.og_image {
+        Some(site_path) if !has_og_image(data_in)? => Some(urls.absolute(site_path)),
+        _ => None,
     };
 
-    let fc = files.clone();
     let settings = Settings {
         element_content_handlers: vec![
-            element!("a[href]|img[src]", |el| {
-                static_file_rewrite_element(path, fc, el)
+            element!("video[src]:not([poster])", |el| {
+                if post.options.video_posters && posters_available() {
+                    add_poster(post, assets, el);
+                }
+                Ok(())
+            }),
+            element!("a[href]|img[src]|source[srcset]|video[src]|audio[src]|source[src]", |el| {
+                static_file_rewrite_element(post.site_root, post.source, assets, el);
+                Ok(())
+            }),
+            element!("a[href]|link[href]|img[src]|script[src]|source[src]|source[srcset]|video[src]|audio[src]", |el| {
+                rewrite_internal_link(urls, el);
+                Ok(())
+            }),
+            element!("a[href]", |el| {
+                if let Some(external) = &post.options.external_links {
+                    annotate_external_link(external, urls, el);
+                }
+                Ok(())
+            }),
+            element!("h1[id]|h2[id]|h3[id]|h4[id]|h5[id]|h6[id]", |el| {
+                if post.options.section_permalinks {
+                    add_section_permalink(el);
+                }
+                Ok(())
             }),
             element!("img|iframe|audio|video", |el| {
                 el.set_attribute("loading", "lazy")
             }),
             element!("video", |el| { el.set_attribute("preload", "metadata") }),
+            // the build knows the canonical url and translations better than the theme does
+            element!(r#"link[rel="canonical"]|link[rel="alternate"][hreflang]"#, |el| {
+                el.remove();
+                Ok(())
+            }),
+            element!("head", |el| {
+                el.append(
+                    &format!(
+                        r#"<link rel="canonical" href="{}">"#,
+                        urls.absolute(&urls.policy().canonical_path(post.canonical))
+                    ),
+                    ContentType::Html,
+                );
+                for alternate in post.alternates {
+                    let href = urls.absolute(&urls.policy().canonical_path(&alternate.path));
+                    el.append(
+                        &format!(
+                            r#"<link rel="alternate" hreflang="{}" href="{href}">"#,
+                            alternate.language
+                        ),
+                        ContentType::Html,
+                    );
+                    if alternate.default {
+                        el.append(
+                            &format!(r#"<link rel="alternate" hreflang="x-default" href="{href}">"#),
+                            ContentType::Html,
+                        );
+                    }
+                }
+                if post.noindex {
+                    el.append(r#"<meta name="robots" content="noindex">"#, ContentType::Html);
+                }
+                if let Some(print) = post.print {
+                    el.append(
+                        &format!(
+                            r#"<link rel="alternate" media="print" href="{}">"#,
+                            urls.link(print)
+                        ),
+                        ContentType::Html,
+                    );
+                }
+                if let Some(image) = &og_image {
+                    el.append(
+                        &format!(
+                            r#"<meta property="og:image" content="{image}"><meta property="og:image:width" content="{CARD_WIDTH}"><meta property="og:image:height" content="{CARD_HEIGHT}"><meta name="twitter:card" content="summary_large_image"><meta name="twitter:image" content="{image}">"#
+                        ),
+                        ContentType::Html,
+                    );
+                }
+                if let Some(data) = post.structured_data {
+                    el.append(&json_ld_script(data), ContentType::Html);
+                }
+                if !post.breadcrumbs.is_empty() {
+                    el.append(
+                        &json_ld_script(&breadcrumb_json_ld(post.breadcrumbs, urls)),
+                        ContentType::Html,
+                    );
+                }
+                if let Some(style) = post.bundle.and_then(|bundle| bundle.style.as_ref()) {
+                    let href = urls.link(&style.site_path);
+                    match &critical {
+                        Some(critical) => {
+                            el.prepend(&format!("<style>{critical}</style>"), ContentType::Html);
+                            el.append(
+                                &format!(r#"<link rel="preload" as="style" href="{href}" onload="this.onload=null;this.rel='stylesheet'"><noscript><link rel="stylesheet" href="{href}"></noscript>"#),
+                                ContentType::Html,
+                            );
+                        }
+                        None => el.append(
+                            &format!(r#"<link rel="stylesheet" href="{href}">"#),
+                            ContentType::Html,
+                        ),
+                    }
+                }
+                Ok(())
+            }),
+            element!("body", |el| {
+                if let Some(script) = post.bundle.and_then(|bundle| bundle.script.as_ref()) {
+                    el.append(
+                        &format!(r#"<script src="{}" defer></script>"#, urls.link(&script.site_path)),
+                        ContentType::Html,
+                    );
+                }
+                Ok(())
+            }),
         ],
         ..Default::default()
     };
 
+    let mut document = rewrite_str(data_in, settings)?;
+    if let Some(rules) = rules_for(&post.options.typography, post.language) {
+        document = typeset_document(&document, rules, post.language)?;
+    }
     let new_document = ProcessedDocument {
-        document: rewrite_str(data_in, settings)?,
-        summary: rewrite_str(data_in, summary_generator)?,
+        document,
+        summary: post.summary.clone(),
     };
 
+    if post.options.accessibility_audit {
+        let findings = accessibility_audit(&new_document.document)?;
+        if !findings.is_empty() {
+            let mut report = post.report.lock().unwrap();
+            for finding in findings {
+                report.warn(post.source, finding);
+            }
+        }
+    }
+
+    if post.options.html_validation != HtmlValidation::Off {
+        let problems = validate_html(&new_document.document);
+        if !problems.is_empty() {
+            let mut report = post.report.lock().unwrap();
+            for problem in problems {
+                match post.options.html_validation {
+                    HtmlValidation::Strict => report.error(post.source, problem),
+                    _ => report.warn(post.source, problem),
+                }
+            }
+        }
+    }
+
     Ok(new_document)
 }
@@ -0,0 +1,107 @@
+use crate::injest::{
+    build::ConfigurationType,
+    config_meta::{ConfigMeta, ExternalMeta, RedirectMeta},
+    generate::PageMeta,
+    links::SiteUrl,
+};
+use color_eyre::Result;
+use html_escape::{encode_double_quoted_attribute, encode_text};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, write};
+use std::path::Path;
+
+// an entry that shows up in listings/menus but does not have a page of its own
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct NavLink {
+    pub title: String,
+    pub url: String,
+    pub external: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedirectEntry {
+    pub from: String,
+    pub to: String,
+    pub permanent: bool,
+}
+
+// `from` is the site path of the directory the .moklog is in, e.g. `/projects/moklog`
+pub fn redirect_from_config(
+    config: &ConfigMeta,
+    from: &str,
+    urls: &SiteUrl,
+) -> Option<(NavLink, RedirectEntry)> {
+    match (config.typ, &config.redirect, &config.external) {
+        (
+            ConfigurationType::Redirect,
+            Some(RedirectMeta {
+                title,
+                to,
+                permanent,
+            }),
+            _,
+        ) => Some((
+            NavLink {
+                title: title.clone(),
+                url: urls.link(to),
+                external: false,
+            },
+            RedirectEntry {
+                from: from.to_string(),
+                to: urls.link(to),
+                permanent: *permanent,
+            },
+        )),
+        (ConfigurationType::External, _, Some(ExternalMeta { title, url })) => Some((
+            NavLink {
+                title: title.clone(),
+                url: url.clone(),
+                external: true,
+            },
+            RedirectEntry {
+                from: from.to_string(),
+                to: url.clone(),
+                permanent: true,
+            },
+        )),
+        _ => None,
+    }
+}
+
+// `redirect_from` of a page, all pointing straight at its canonical url so that search engines
+// never see the old paths as copies of it
+pub fn page_redirects(page: &PageMeta, canonical_path: &str, urls: &SiteUrl) -> Vec<RedirectEntry> {
+    let to = urls.absolute(&urls.policy().canonical_path(canonical_path));
+    page.redirect_from
+        .iter()
+        .map(|from| RedirectEntry {
+            from: from.clone(),
+            to: to.clone(),
+            permanent: true,
+        })
+        .collect()
+}
+
+// fallback for when the page is served without moklog in front of it (static export, mirrors),
+// the server answers these paths with a real 301/302 before this is ever read.
+pub fn redirect_page(title: &str, to: &str) -> String {
+    let to_attr = encode_double_quoted_attribute(to);
+    let title = encode_text(title);
+    format!(
+        r#"<!DOCTYPE html><html><head><meta charset="utf-8"><title>{title}</title><meta name="robots" content="noindex"><link rel="canonical" href="{to_attr}"><meta http-equiv="refresh" content="0; url={to_attr}"></head><body><p>This page has moved to <a href="{to_attr}">{title}</a>.</p></body></html>"#
+    )
+}
+
+pub fn write_redirect_page(
+    site_output_path: impl AsRef<Path>,
+    title: &str,
+    entry: &RedirectEntry,
+) -> Result<()> {
+    let dir = site_output_path
+        .as_ref()
+        .join(entry.from.trim_start_matches('/'));
+    create_dir_all(&dir)?;
+    write(dir.join("index.html"), redirect_page(title, &entry.to))?;
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use crate::injest::translation::TranslationStatus;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub path: PathBuf,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {}: {}",
+            self.path.display(),
+            self.severity,
+            self.message
+        )
+    }
+}
+
+// collected problems of a build/check run. nothing in here stops a build by itself,
+// the caller decides what to do with errors.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BuildReport {
+    pub diagnostics: Vec<Diagnostic>,
+    // pages that are missing translations or have stale ones
+    #[serde(default)]
+    pub translations: Vec<TranslationStatus>,
+}
+
+impl BuildReport {
+    pub fn new() -> Self {
+        BuildReport::default()
+    }
+
+    pub fn push(&mut self, path: impl AsRef<Path>, severity: Severity, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic {
+            path: path.as_ref().to_path_buf(),
+            severity,
+            message: message.into(),
+        })
+    }
+
+    pub fn warn(&mut self, path: impl AsRef<Path>, message: impl Into<String>) {
+        self.push(path, Severity::Warning, message)
+    }
+
+    pub fn error(&mut self, path: impl AsRef<Path>, message: impl Into<String>) {
+        self.push(path, Severity::Error, message)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diag| diag.severity == Severity::Error)
+    }
+
+    pub fn extend(&mut self, other: BuildReport) {
+        self.diagnostics.extend(other.diagnostics);
+        self.translations.extend(other.translations)
+    }
+
+    pub fn translation(&mut self, status: TranslationStatus) {
+        for language in &status.missing {
+            self.warn(&status.path, format!("no {language} translation"));
+        }
+        for language in &status.stale {
+            self.warn(
+                &status.path,
+                format!("{language} translation is older than the last edit of the page"),
+            );
+        }
+        if !status.is_complete() {
+            self.translations.push(status);
+        }
+    }
+
+    pub fn sorted(mut self) -> Self {
+        self.diagnostics
+            .sort_by(|a, b| a.path.cmp(&b.path).then(b.severity.cmp(&a.severity)));
+        self.translations.sort_by(|a, b| a.path.cmp(&b.path));
+        self
+    }
+}
@@ -0,0 +1,159 @@
+use crate::injest::{assets::ASSET_DIR, manifest::MANIFEST_FILE};
+use color_eyre::Result;
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use std::fs::{metadata, read_dir, remove_dir_all, remove_file};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// the directories under CACHE_DIR that only hold what a build can make again. `kv` is plugin data
+// and never pruned.
+pub const PRUNABLE_CACHES: &[&str] = &["files", "diagrams", "social-cards", "notebooks", "posters"];
+
+// `[retention]` in site.toml, nothing is pruned unless set
+//
+// [retention]
+// keep_builds = 10
+// max_cache_bytes = 2000000000
+// ip_days = 30
+// email_days = 365
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionOptions {
+    // builds whose diffs and ipfs cids are kept, and whose files stay in the serve dir for pages
+    // still open in a browser after the site moved on
+    pub keep_builds: Option<usize>,
+    // the build caches past this are pruned, least recently written first
+    pub max_cache_bytes: Option<u64>,
+    // days the addresses of sign in attempts and translation suggestions are kept for
+    pub ip_days: Option<u32>,
+    // days the emails of commenters and of translation suggesters are kept for, the comments and
+    // suggestions stay
+    pub email_days: Option<u32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PruneReason {
+    // in the serve dir, not written by any of the builds kept
+    OldBuild,
+    // in a cache that is over `max_cache_bytes`
+    CacheSize,
+    // a stored static file, or a link to one, that no kept build has
+    Unreferenced,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Prunable {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub reason: PruneReason,
+}
+
+// everything a pruning run removes, or would remove for a dry run
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrunePlan {
+    // ids of the builds whose records go
+    pub builds: Vec<u64>,
+    pub files: Vec<Prunable>,
+    pub bytes: u64,
+}
+
+impl PrunePlan {
+    pub fn add(&mut self, files: Vec<Prunable>) {
+        self.bytes += files.iter().map(|file| file.bytes).sum::<u64>();
+        self.files.extend(files);
+    }
+}
+
+fn size(path: &Path) -> Result<u64> {
+    let metadata = metadata(path)?;
+    if metadata.is_file() {
+        return Ok(metadata.len());
+    }
+    let mut total = 0;
+    for entry in WalkBuilder::new(path).standard_filters(false).build() {
+        let entry = entry?;
+        if entry.file_type().map_or(false, |kind| kind.is_file()) {
+            total += entry.metadata()?.len();
+        }
+    }
+    Ok(total)
+}
+
+// Files of the serve dir last written before `cutoff`, the start of the oldest build that's kept.
+// Every build writes every file of the site again, so one that no kept build wrote is left over
+// from a page that's gone. Assets are links into the blob store, as old as the blob, and pruned
+// with it instead.
+pub fn serve_leftovers(serve_dir: impl AsRef<Path>, cutoff: SystemTime) -> Result<Vec<Prunable>> {
+    let serve_dir = serve_dir.as_ref();
+    let mut leftovers = vec![];
+    if !serve_dir.is_dir() {
+        return Ok(leftovers);
+    }
+    for entry in WalkBuilder::new(serve_dir).standard_filters(false).build() {
+        let entry = entry?;
+        if !entry.file_type().map_or(false, |kind| kind.is_file())
+            || entry.path() == serve_dir.join(MANIFEST_FILE)
+            || entry.path().parent() == Some(&serve_dir.join(ASSET_DIR))
+        {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if metadata.modified()? < cutoff {
+            leftovers.push(Prunable {
+                path: entry.into_path(),
+                bytes: metadata.len(),
+                reason: PruneReason::OldBuild,
+            });
+        }
+    }
+    Ok(leftovers)
+}
+
+// Entries of the build caches, oldest first, until what's left fits in `max_bytes`. An entry is
+// whatever a cache keeps per input, a file or the directory of one hash.
+pub fn cache_overflow(cache_dir: impl AsRef<Path>, max_bytes: u64) -> Result<Vec<Prunable>> {
+    let mut entries = vec![];
+    for cache in PRUNABLE_CACHES {
+        let dir = cache_dir.as_ref().join(cache);
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in read_dir(dir)? {
+            let path = entry?.path();
+            let written = metadata(&path)?.modified()?;
+            entries.push((written, size(&path)?, path));
+        }
+    }
+    entries.sort();
+
+    let mut total = entries.iter().map(|(_, bytes, _)| bytes).sum::<u64>();
+    let mut overflow = vec![];
+    for (_, bytes, path) in entries {
+        if total <= max_bytes {
+            break;
+        }
+        total -= bytes;
+        overflow.push(Prunable {
+            path,
+            bytes,
+            reason: PruneReason::CacheSize,
+        });
+    }
+    Ok(overflow)
+}
+
+// a file that's already gone is fine, something else pruned it
+pub fn remove_files(files: &[Prunable]) -> Result<()> {
+    for file in files {
+        let removed = match file.path.is_dir() {
+            true => remove_dir_all(&file.path),
+            false => remove_file(&file.path),
+        };
+        match removed {
+            Err(why) if why.kind() != std::io::ErrorKind::NotFound => return Err(why.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
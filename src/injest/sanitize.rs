@@ -0,0 +1,174 @@
+use crate::injest::report::BuildReport;
+use ammonia::Builder;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+// `[[sanitize]]` in site.toml, pages written by people who aren't trusted with raw html. Exactly
+// one of `category` and `role`, a role is held by the pages under the directories `[role_paths]`
+// gives it to. Not by front matter, a page's authors are whatever the page says they are.
+//
+// [[sanitize]]
+// category = "blog/guest"
+//
+// [[sanitize]]
+// role = "contributor"
+// allow = ["iframe"]
+//
+// [role_paths]
+// contributor = ["wiki", "blog/community"]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SanitizeRule {
+    // a category directory of the content repo, covering everything under it
+    pub category: Option<String>,
+    // pages holding it
+    pub role: Option<String>,
+    // tags of ALLOWABLE kept anyway
+    #[serde(default)]
+    pub allow: BTreeSet<String>,
+}
+
+// Left out of the allowlist, and what they need to work when a rule lets them back in. Scripts and
+// styles take their content with them when they go.
+pub const ALLOWABLE: &[(&str, &[&str])] = &[
+    ("script", &["src", "type", "async", "defer"]),
+    ("style", &["media"]),
+    (
+        "iframe",
+        &[
+            "src",
+            "width",
+            "height",
+            "title",
+            "allow",
+            "allowfullscreen",
+            "loading",
+        ],
+    ),
+    (
+        "video",
+        &[
+            "src",
+            "poster",
+            "width",
+            "height",
+            "controls",
+            "loop",
+            "muted",
+            "playsinline",
+            "preload",
+        ],
+    ),
+    ("audio", &["src", "controls", "loop", "muted", "preload"]),
+    ("source", &["src", "type", "media"]),
+];
+
+impl SanitizeRule {
+    pub fn covers(&self, source_path: &Path, roles: &BTreeSet<String>) -> bool {
+        match (&self.category, &self.role) {
+            (Some(category), None) => source_path.starts_with(category.trim_matches('/')),
+            (None, Some(role)) => roles.contains(role),
+            _ => false,
+        }
+    }
+}
+
+// the roles of a page, from the directories it is under
+pub fn path_roles(
+    role_paths: &BTreeMap<String, BTreeSet<String>>,
+    source_path: &Path,
+) -> BTreeSet<String> {
+    role_paths
+        .iter()
+        .filter(|(_, dirs)| {
+            dirs.iter()
+                .any(|dir| source_path.starts_with(dir.trim_matches('/')))
+        })
+        .map(|(role, _)| role.clone())
+        .collect()
+}
+
+// What the rules covering a page allow, only what every one of them does. None when no rule
+// covers it and the page is left as it is.
+pub fn allowed(
+    rules: &[SanitizeRule],
+    source_path: &Path,
+    roles: &BTreeSet<String>,
+) -> Option<BTreeSet<String>> {
+    rules
+        .iter()
+        .filter(|rule| rule.covers(source_path, roles))
+        .map(|rule| rule.allow.clone())
+        .reduce(|allow, other| allow.intersection(&other).cloned().collect())
+}
+
+// Rendered content down to the allowlist: formatting, links, images, tables and code, with their
+// ids and classes so footnotes and highlighting keep working. Event handlers, inline styles and
+// `javascript:` links go everywhere.
+pub fn sanitize(html: &str, allow: &BTreeSet<String>) -> String {
+    let mut builder = Builder::default();
+    builder.add_generic_attributes(["id", "class"]);
+    for (tag, attributes) in ALLOWABLE.iter().filter(|(tag, _)| allow.contains(*tag)) {
+        builder
+            .rm_clean_content_tags([*tag])
+            .add_tags([*tag])
+            .add_tag_attributes(*tag, attributes.iter());
+    }
+    builder.clean(html).to_string()
+}
+
+pub fn validate_rules(
+    rules: &[SanitizeRule],
+    role_paths: &BTreeMap<String, BTreeSet<String>>,
+    site_root: &Path,
+    path: &Path,
+    report: &mut BuildReport,
+) {
+    for rule in rules {
+        match (&rule.category, &rule.role) {
+            (Some(_), Some(_)) | (None, None) => report.error(
+                path,
+                "a sanitize rule needs exactly one of `category` and `role`",
+            ),
+            (Some(category), None) if !site_root.join(category.trim_matches('/')).is_dir() => {
+                report.error(
+                    path,
+                    format!("sanitize rule for category {category}, which does not exist"),
+                )
+            }
+            (None, Some(role)) if !role_paths.contains_key(role) => report.warn(
+                path,
+                format!("sanitize rule for role {role}, which [role_paths] gives to no directory"),
+            ),
+            _ => {}
+        }
+        for tag in rule.allow.iter() {
+            if !ALLOWABLE
+                .iter()
+                .any(|(allowable, _)| *allowable == tag.as_str())
+            {
+                report.error(
+                    path,
+                    format!(
+                        "\"{tag}\" can't be allowed, only {}",
+                        ALLOWABLE
+                            .iter()
+                            .map(|(tag, _)| *tag)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                );
+            }
+        }
+    }
+    for (role, dirs) in role_paths {
+        for dir in dirs {
+            if !site_root.join(dir.trim_matches('/')).is_dir() {
+                report.warn(
+                    path,
+                    format!("[role_paths] gives {role} to {dir}, which does not exist"),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,802 @@
+use crate::injest::summary::{summarize, Summary, DEFAULT_SUMMARY_LENGTH};
+use crate::injest::url_path;
+use crate::walker;
+use chrono::Utc;
+use color_eyre::{Report, Result};
+use ignore::WalkBuilder;
+use lindera_tantivy::mode::Mode;
+use lindera_tantivy::tokenizer::{
+    DictionaryConfig, DictionaryKind, LinderaTokenizer, TokenizerConfig,
+};
+use lol_html::{element, rewrite_str, text, Settings};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_dir_all, rename, write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tantivy::collector::TopDocs;
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{
+    BooleanQuery, BoostQuery, FuzzyTermQuery, Occur, Query, QueryParser, TermQuery,
+};
+use tantivy::schema::{
+    Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, STORED, STRING, TEXT,
+};
+use tantivy::tokenizer::TokenStream;
+use tantivy::{Document, Index, IndexReader, IndexSettings, ReloadPolicy, SnippetGenerator, Term};
+use tantivy_jieba::JiebaTokenizer;
+use tracing::warn;
+
+pub const DEFAULT_SNIPPET_LENGTH: usize = 160;
+pub const MAX_SNIPPET_LENGTH: usize = 1000;
+
+const WRITER_MEMORY: usize = 50_000_000;
+
+// not what a page is about, left out of the indexed body
+const NOT_CONTENT: &str = "script, style, nav, header, footer, noscript, template";
+
+const JAPANESE_TOKENIZER: &str = "lindera_ja";
+const KOREAN_TOKENIZER: &str = "lindera_ko";
+const CHINESE_TOKENIZER: &str = "jieba";
+
+// Languages the default tokenizer can't split into words, korean sticks particles to them and
+// japanese and chinese don't space them at all. Their pages are indexed a second time into a
+// field of their own language, split by a tokenizer that knows the language.
+const CJK_TOKENIZERS: [(&str, &str); 3] = [
+    ("ja", JAPANESE_TOKENIZER),
+    ("ko", KOREAN_TOKENIZER),
+    ("zh", CHINESE_TOKENIZER),
+];
+
+#[derive(Clone, Copy, Debug)]
+pub struct SearchFields {
+    pub path: Field,
+    pub title: Field,
+    pub language: Field,
+    // the tag and its primary language, lowercase, what `lang` is matched against
+    pub languages: Field,
+    // `hreflang href` of every other language version of the page
+    pub translations: Field,
+    // `text_ja`, `text_ko` and `text_zh` in the order of CJK_TOKENIZERS
+    pub cjk: [Field; 3],
+    pub summary: Field,
+    // one value per heading, in document order
+    pub headings: Field,
+    pub body: Field,
+}
+
+pub fn search_schema() -> (Schema, SearchFields) {
+    let mut builder = Schema::builder();
+    let fields = SearchFields {
+        path: builder.add_text_field("path", STRING | STORED),
+        title: builder.add_text_field("title", TEXT | STORED),
+        language: builder.add_text_field("language", STRING | STORED),
+        languages: builder.add_text_field("languages", STRING),
+        translations: builder.add_text_field("translations", STORED),
+        cjk: CJK_TOKENIZERS.map(|(language, tokenizer)| {
+            builder.add_text_field(
+                &format!("text_{language}"),
+                TextOptions::default().set_indexing_options(
+                    TextFieldIndexing::default()
+                        .set_tokenizer(tokenizer)
+                        .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+                ),
+            )
+        }),
+        summary: builder.add_text_field("summary", TEXT | STORED),
+        headings: builder.add_text_field("headings", TEXT | STORED),
+        // stored, snippets are cut from it
+        body: builder.add_text_field("body", TEXT | STORED),
+    };
+    (builder.build(), fields)
+}
+
+impl SearchFields {
+    // the field split by the tokenizer of `language`, if it has one of its own
+    pub fn cjk_field(&self, language: &str) -> Option<Field> {
+        let primary = language.split('-').next()?.to_ascii_lowercase();
+        CJK_TOKENIZERS
+            .iter()
+            .position(|(cjk, _)| *cjk == primary)
+            .map(|i| self.cjk[i])
+    }
+}
+
+// the tokenizers the cjk fields name, every index opened or created needs them
+fn register_tokenizers(index: &Index) -> Result<()> {
+    for (kind, name) in [
+        (DictionaryKind::IPADIC, JAPANESE_TOKENIZER),
+        (DictionaryKind::KoDic, KOREAN_TOKENIZER),
+    ] {
+        let config = TokenizerConfig {
+            dictionary: DictionaryConfig {
+                kind: Some(kind),
+                path: None,
+            },
+            user_dictionary: None,
+            mode: Mode::Normal,
+        };
+        let tokenizer =
+            LinderaTokenizer::with_config(config).map_err(|why| Report::msg(why.to_string()))?;
+        index.tokenizers().register(name, tokenizer);
+    }
+    index
+        .tokenizers()
+        .register(CHINESE_TOKENIZER, JiebaTokenizer {});
+    Ok(())
+}
+
+// one built page, as the index sees it
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchDocument {
+    pub path: String,
+    pub title: String,
+    pub language: String,
+    // hreflang -> url of the page's other language versions
+    pub translations: BTreeMap<String, String>,
+    pub summary: String,
+    pub headings: Vec<String>,
+    pub body: String,
+}
+
+impl SearchDocument {
+    // `html` is the finished page, the theme's navigation and scripts don't end up in the body
+    pub fn new(
+        path: &str,
+        title: &str,
+        language: &str,
+        html: &str,
+        summary: &Summary,
+    ) -> Result<SearchDocument> {
+        let headings = RefCell::new(Vec::<String>::new());
+        let in_heading = RefCell::new(false);
+        let body = RefCell::new(String::new());
+
+        let content = rewrite_str(
+            html,
+            Settings {
+                element_content_handlers: vec![element!(NOT_CONTENT, |el| {
+                    el.remove();
+                    Ok(())
+                })],
+                ..Settings::default()
+            },
+        )?;
+        rewrite_str(
+            &content,
+            Settings {
+                element_content_handlers: vec![
+                    element!("h1, h2, h3, h4, h5, h6", |_| {
+                        headings.borrow_mut().push(String::new());
+                        *in_heading.borrow_mut() = true;
+                        Ok(())
+                    }),
+                    // a heading is over at the next element that isn't inside it, close enough
+                    // for headings that are text and maybe an anchor
+                    element!("p, li, pre, blockquote, div, section, table", |_| {
+                        *in_heading.borrow_mut() = false;
+                        Ok(())
+                    }),
+                    text!("body *", |chunk| {
+                        let text = html_escape::decode_html_entities(chunk.as_str()).to_string();
+                        if *in_heading.borrow() {
+                            if let Some(heading) = headings.borrow_mut().last_mut() {
+                                heading.push_str(&text);
+                            }
+                        }
+                        body.borrow_mut().push_str(&text);
+                        if chunk.last_in_text_node() {
+                            body.borrow_mut().push(' ');
+                        }
+                        Ok(())
+                    }),
+                ],
+                ..Settings::default()
+            },
+        )?;
+
+        Ok(SearchDocument {
+            path: path.to_string(),
+            title: title.to_string(),
+            language: language.to_string(),
+            translations: BTreeMap::new(),
+            summary: summary.text.clone(),
+            headings: headings
+                .into_inner()
+                .into_iter()
+                .map(|heading| heading.split_whitespace().collect::<Vec<_>>().join(" "))
+                .filter(|heading| !heading.is_empty())
+                .collect(),
+            body: body
+                .into_inner()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+        })
+    }
+}
+
+// the name of the snapshot being served, in the index directory
+const CURRENT_FILE: &str = "CURRENT";
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const BUILDING_PREFIX: &str = ".building-";
+// word -> how often it's used, next to the index of a snapshot
+const TERMS_FILE: &str = "terms.json";
+
+// the words of `text` the way the default tokenizer splits them, lowercase
+fn index_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+// hangul, kana and han, a query in them without a language is looked for in every cjk field
+fn is_cjk(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1100..=0x11FF
+            | 0x3040..=0x30FF
+            | 0x3130..=0x318F
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xAC00..=0xD7AF
+            | 0xF900..=0xFAFF
+    )
+}
+
+// `ko-KR` is found as `ko-kr` and as `ko`
+fn language_terms(language: &str) -> Vec<String> {
+    let language = language.to_ascii_lowercase();
+    match language.split_once('-') {
+        Some((primary, _)) => vec![primary.to_string(), language],
+        None => vec![language],
+    }
+}
+
+// One finished index, never written to again. A rebuild makes a new snapshot next to it, so
+// searches already running keep the one they started with. A snapshot that was replaced removes
+// its directory once the last search holding it is done.
+pub struct SearchSnapshot {
+    pub dir: PathBuf,
+    pub index: Index,
+    pub reader: IndexReader,
+    pub fields: SearchFields,
+    // how often every word is used across the site, for suggestions
+    pub terms: HashMap<String, u64>,
+    retired: AtomicBool,
+}
+
+impl SearchSnapshot {
+    pub fn open(dir: impl AsRef<Path>) -> Result<SearchSnapshot> {
+        let (schema, fields) = search_schema();
+        let directory =
+            MmapDirectory::open(dir.as_ref()).map_err(|why| Report::msg(why.to_string()))?;
+        let index = Index::open_or_create(directory, schema)?;
+        register_tokenizers(&index)?;
+        // it doesn't change, there's nothing to reload
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+        let terms = match read_to_string(dir.as_ref().join(TERMS_FILE)) {
+            Ok(terms) => serde_json::from_str(&terms)?,
+            // a snapshot from before there were suggestions, it searches without them
+            Err(_) => HashMap::new(),
+        };
+        Ok(SearchSnapshot {
+            dir: dir.as_ref().to_path_buf(),
+            index,
+            reader,
+            fields,
+            terms,
+            retired: AtomicBool::new(false),
+        })
+    }
+
+    // removed from disk when dropped, once a newer snapshot took its place
+    pub fn retire(&self) {
+        self.retired.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for SearchSnapshot {
+    fn drop(&mut self) {
+        if self.retired.load(Ordering::SeqCst) {
+            if let Err(why) = remove_dir_all(&self.dir) {
+                warn!("failed to remove old index {}: {why}", self.dir.display());
+            }
+        }
+    }
+}
+
+// the snapshot `CURRENT` names, None before the first index was built
+pub fn current_snapshot(index_dir: impl AsRef<Path>) -> Result<Option<PathBuf>> {
+    let current = index_dir.as_ref().join(CURRENT_FILE);
+    if !current.is_file() {
+        return Ok(None);
+    }
+    let name = read_to_string(current)?;
+    Ok(Some(index_dir.as_ref().join(name.trim())))
+}
+
+// Snapshots a crash or a swap left behind, everything but the current one. Only for startup,
+// before anything searches.
+pub fn remove_stale_snapshots(index_dir: impl AsRef<Path>) -> Result<()> {
+    let index_dir = index_dir.as_ref();
+    if !index_dir.is_dir() {
+        return Ok(());
+    }
+    let current = current_snapshot(index_dir)?;
+    for entry in read_dir(index_dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let is_snapshot = name.starts_with(SNAPSHOT_PREFIX) || name.starts_with(BUILDING_PREFIX);
+        if is_snapshot && path.is_dir() && current.as_ref() != Some(&path) {
+            remove_dir_all(&path)?;
+        }
+    }
+    Ok(())
+}
+
+// Indexes the pages of a build into a new snapshot and makes it the current one, returns its
+// directory. The index is written under a temporary name and renamed when it's complete, then
+// `CURRENT` is replaced the same way, so what it names is always a whole index.
+pub fn index_pages(index_dir: impl AsRef<Path>, documents: &[SearchDocument]) -> Result<PathBuf> {
+    let index_dir = index_dir.as_ref();
+    create_dir_all(index_dir)?;
+    let name = format!("{SNAPSHOT_PREFIX}{}", Utc::now().timestamp_millis());
+    let building = index_dir.join(format!("{BUILDING_PREFIX}{name}"));
+    create_dir_all(&building)?;
+
+    {
+        let (schema, fields) = search_schema();
+        let directory =
+            MmapDirectory::open(&building).map_err(|why| Report::msg(why.to_string()))?;
+        let index = Index::create(directory, schema, IndexSettings::default())?;
+        register_tokenizers(&index)?;
+        let mut writer = index.writer(WRITER_MEMORY)?;
+        let mut terms = HashMap::<String, u64>::new();
+        for document in documents {
+            let texts = [&document.title, &document.summary, &document.body]
+                .into_iter()
+                .chain(&document.headings)
+                .collect::<Vec<_>>();
+            for word in texts.iter().flat_map(|text| index_words(text)) {
+                *terms.entry(word).or_default() += 1;
+            }
+            let mut doc = Document::default();
+            doc.add_text(fields.path, &document.path);
+            doc.add_text(fields.title, &document.title);
+            doc.add_text(fields.language, &document.language);
+            for language in language_terms(&document.language) {
+                doc.add_text(fields.languages, &language);
+            }
+            for (language, url) in &document.translations {
+                doc.add_text(fields.translations, format!("{language} {url}"));
+            }
+            if let Some(field) = fields.cjk_field(&document.language) {
+                for text in &texts {
+                    doc.add_text(field, text);
+                }
+            }
+            doc.add_text(fields.summary, &document.summary);
+            for heading in document.headings.iter() {
+                doc.add_text(fields.headings, heading);
+            }
+            doc.add_text(fields.body, &document.body);
+            writer.add_document(doc)?;
+        }
+        writer.commit()?;
+        writer.wait_merging_threads()?;
+        write(building.join(TERMS_FILE), serde_json::to_string(&terms)?)?;
+    }
+
+    let snapshot = index_dir.join(&name);
+    rename(&building, &snapshot)?;
+    let current = index_dir.join(format!("{CURRENT_FILE}.tmp"));
+    write(&current, &name)?;
+    rename(&current, index_dir.join(CURRENT_FILE))?;
+    Ok(snapshot)
+}
+
+// Every page of a built site worth finding, read back from its html. Redirects, error pages and
+// the pages in `hidden` (drafts, private pages) are left out.
+pub fn collect_documents(
+    site_output_path: impl AsRef<Path>,
+    hidden: &BTreeSet<String>,
+    default_language: &str,
+) -> Result<Vec<SearchDocument>> {
+    let output = site_output_path.as_ref();
+    let mut documents = vec![];
+    for entry in walker!(output).build() {
+        let entry = entry?;
+        let path = entry.path();
+        if path.file_name().and_then(|name| name.to_str()) != Some("index.html") {
+            continue;
+        }
+        let site_path = match path.parent().and_then(|dir| dir.strip_prefix(output).ok()) {
+            Some(dir) if dir.as_os_str().is_empty() => "/".to_string(),
+            Some(dir) => format!("/{}", url_path(dir)),
+            None => continue,
+        };
+        if hidden.contains(&site_path) {
+            continue;
+        }
+        let html = read_to_string(path)?;
+        let head = RefCell::new(PageHead::default());
+        rewrite_str(
+            &html,
+            Settings {
+                element_content_handlers: vec![
+                    element!("html[lang]", |el| {
+                        head.borrow_mut().language = el.get_attribute("lang");
+                        Ok(())
+                    }),
+                    element!(r#"link[rel="canonical"][href]"#, |el| {
+                        head.borrow_mut().canonical = el.get_attribute("href");
+                        Ok(())
+                    }),
+                    element!(r#"link[rel="alternate"][hreflang][href]"#, |el| {
+                        if let (Some(language), Some(href)) =
+                            (el.get_attribute("hreflang"), el.get_attribute("href"))
+                        {
+                            head.borrow_mut().alternates.insert(language, href);
+                        }
+                        Ok(())
+                    }),
+                    element!("meta[http-equiv=refresh]", |_| {
+                        head.borrow_mut().redirect = true;
+                        Ok(())
+                    }),
+                    element!("meta[name=description][content]", |el| {
+                        head.borrow_mut().description = el.get_attribute("content");
+                        Ok(())
+                    }),
+                    text!("head > title", |chunk| {
+                        head.borrow_mut().title.push_str(chunk.as_str());
+                        Ok(())
+                    }),
+                ],
+                ..Settings::default()
+            },
+        )?;
+        let head = head.into_inner();
+        if head.redirect {
+            continue;
+        }
+        let summary = Summary {
+            html: String::new(),
+            text: head.description.clone().unwrap_or_default(),
+            explicit: false,
+        };
+        // a page without a lang is in the language its hreflang links give its own url
+        let own_hreflang = head.alternates.iter().find_map(|(language, href)| {
+            (language != "x-default" && Some(href) == head.canonical.as_ref())
+                .then_some(language.as_str())
+        });
+        let mut document = SearchDocument::new(
+            &site_path,
+            html_escape::decode_html_entities(head.title.trim()).as_ref(),
+            head.language
+                .as_deref()
+                .or(own_hreflang)
+                .unwrap_or(default_language),
+            &html,
+            &summary,
+        )?;
+        document.translations = head
+            .alternates
+            .iter()
+            .filter(|(language, href)| {
+                *language != "x-default" && Some(*href) != head.canonical.as_ref()
+            })
+            .map(|(language, href)| (language.clone(), href.clone()))
+            .collect();
+        // the start of the body, the way a page's summary is cut
+        if head.description.is_none() {
+            let body = html_escape::encode_text(&document.body).to_string();
+            document.summary = summarize(&body, DEFAULT_SUMMARY_LENGTH)?.text;
+        }
+        documents.push(document);
+    }
+    Ok(documents)
+}
+
+#[derive(Default)]
+struct PageHead {
+    title: String,
+    language: Option<String>,
+    canonical: Option<String>,
+    // hreflang -> href
+    alternates: BTreeMap<String, String>,
+    description: Option<String>,
+    redirect: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SearchHit {
+    pub path: String,
+    pub title: String,
+    pub language: String,
+    // hreflang -> url of the same page in other languages
+    pub translations: BTreeMap<String, String>,
+    pub summary: String,
+    pub headings: Vec<String>,
+    // html, the matched words in <b>, the summary if only the title matched
+    pub snippet: String,
+    pub score: f32,
+}
+
+fn first_text(doc: &Document, field: Field) -> String {
+    doc.get_first(field)
+        .and_then(|value| value.as_text())
+        .unwrap_or_default()
+        .to_string()
+}
+
+// `query` kept to pages in `language`, `ko` is any korean and `ko-KR` only that
+fn in_language(
+    fields: &SearchFields,
+    query: Box<dyn Query>,
+    language: Option<&str>,
+) -> Box<dyn Query> {
+    match language {
+        Some(language) => Box::new(BooleanQuery::new(vec![
+            (Occur::Must, query),
+            (
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(fields.languages, &language.to_ascii_lowercase()),
+                    IndexRecordOption::Basic,
+                )),
+            ),
+        ])),
+        None => query,
+    }
+}
+
+// `query`, or the words of `text` split by the tokenizer of `language`, any of them in its
+// field. Without a language a query in cjk scripts is split by every cjk tokenizer. A page with
+// more of the words ranks higher, particles and all are words to these tokenizers and requiring
+// every one would miss a word with another particle.
+fn with_cjk(
+    snapshot: &SearchSnapshot,
+    query: Box<dyn Query>,
+    text: &str,
+    language: Option<&str>,
+) -> Result<Box<dyn Query>> {
+    let fields = &snapshot.fields;
+    let cjk_fields = match language {
+        Some(language) => fields.cjk_field(language).into_iter().collect(),
+        None if text.chars().any(is_cjk) => fields.cjk.to_vec(),
+        None => vec![],
+    };
+    let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Should, query)];
+    for field in cjk_fields {
+        let mut words = vec![];
+        snapshot
+            .index
+            .tokenizer_for_field(field)?
+            .token_stream(text)
+            .process(&mut |token| words.push(token.text.clone()));
+        for word in words {
+            clauses.push((
+                Occur::Should,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(field, &word),
+                    IndexRecordOption::WithFreqs,
+                )),
+            ));
+        }
+    }
+    Ok(Box::new(BooleanQuery::new(clauses)))
+}
+
+// the edits a word of this length may be off by and still match, none for short words where
+// one edit is a different word
+fn typo_distance(word: &str) -> u8 {
+    match word.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+// levenshtein distance, in characters
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// Any word of `query` the site never uses swapped for the most common one that's close to it,
+// None if every word is known or nothing is close.
+pub fn suggest(terms: &HashMap<String, u64>, query: &str) -> Option<String> {
+    let mut changed = false;
+    let suggestion = query
+        .split_whitespace()
+        .map(|word| {
+            let lowercase = word.to_lowercase();
+            let distance = typo_distance(&lowercase) as usize;
+            if distance == 0
+                || terms.contains_key(&lowercase)
+                || !lowercase.chars().all(char::is_alphanumeric)
+            {
+                return word.to_string();
+            }
+            let length = lowercase.chars().count();
+            let closest = terms
+                .iter()
+                .filter(|(term, _)| term.chars().count().abs_diff(length) <= distance)
+                .map(|(term, count)| (edit_distance(&lowercase, term), term, count))
+                .filter(|(edits, ..)| *edits <= distance)
+                .min_by(|(a_edits, a, a_count), (b_edits, b, b_count)| {
+                    a_edits
+                        .cmp(b_edits)
+                        .then(b_count.cmp(a_count))
+                        .then(a.cmp(b))
+                });
+            match closest {
+                Some((_, term, _)) => {
+                    changed = true;
+                    term.clone()
+                }
+                None => word.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    changed.then_some(suggestion)
+}
+
+// every word of a query as a fuzzy term, any of them matching in any text field
+fn fuzzy_query(fields: &SearchFields, query: &str) -> Option<Box<dyn Query>> {
+    let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![];
+    for word in index_words(query) {
+        let distance = typo_distance(&word);
+        for (field, boost) in [
+            (fields.title, 3.0),
+            (fields.headings, 2.0),
+            (fields.summary, 1.0),
+            (fields.body, 1.0),
+        ] {
+            let term = Term::from_field_text(field, &word);
+            clauses.push((
+                Occur::Should,
+                Box::new(BoostQuery::new(
+                    Box::new(FuzzyTermQuery::new(term, distance, true)),
+                    boost,
+                )),
+            ));
+        }
+    }
+    match clauses.is_empty() {
+        true => None,
+        false => Some(Box::new(BooleanQuery::new(clauses))),
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+    // the query with its typos fixed, when some word of it isn't on the site
+    pub suggestion: Option<String>,
+    // nothing matched as written, the hits are for words close to the query's
+    pub fuzzy: bool,
+}
+
+fn collect_hits(
+    snapshot: &SearchSnapshot,
+    query: &dyn Query,
+    snippet_query: &dyn Query,
+    limit: usize,
+    snippet_length: usize,
+) -> Result<Vec<SearchHit>> {
+    let (fields, searcher) = (&snapshot.fields, snapshot.reader.searcher());
+    let mut snippets = SnippetGenerator::create(&searcher, snippet_query, fields.body)?;
+    snippets.set_max_num_chars(snippet_length.clamp(1, MAX_SNIPPET_LENGTH));
+
+    let mut hits = vec![];
+    for (score, address) in searcher.search(query, &TopDocs::with_limit(limit))? {
+        let doc = searcher.doc(address)?;
+        let summary = first_text(&doc, fields.summary);
+        let snippet = snippets.snippet_from_doc(&doc);
+        let snippet = match snippet.fragment().is_empty() || snippet.highlighted().is_empty() {
+            true => html_escape::encode_text(&summary).to_string(),
+            false => snippet.to_html(),
+        };
+        hits.push(SearchHit {
+            path: first_text(&doc, fields.path),
+            title: first_text(&doc, fields.title),
+            language: first_text(&doc, fields.language),
+            translations: doc
+                .get_all(fields.translations)
+                .filter_map(|value| value.as_text()?.split_once(' '))
+                .map(|(language, url)| (language.to_string(), url.to_string()))
+                .collect(),
+            headings: doc
+                .get_all(fields.headings)
+                .filter_map(|value| value.as_text())
+                .map(ToString::to_string)
+                .collect(),
+            summary,
+            snippet,
+            score,
+        });
+    }
+    Ok(hits)
+}
+
+// The best `limit` pages for `query`, with snippets of about `snippet_length` characters.
+// `language` keeps it to pages in that language. When nothing matches as written, the words of
+// the query are matched with a typo or two instead.
+pub fn search(
+    snapshot: &SearchSnapshot,
+    query: &str,
+    language: Option<&str>,
+    limit: usize,
+    snippet_length: usize,
+) -> Result<SearchResults> {
+    let fields = &snapshot.fields;
+    let mut parser = QueryParser::for_index(
+        &snapshot.index,
+        vec![fields.title, fields.headings, fields.summary, fields.body],
+    );
+    parser.set_field_boost(fields.title, 3.0);
+    parser.set_field_boost(fields.headings, 2.0);
+    // kept as a QueryParserError, it's the user's mistake and not the index's
+    let parsed = parser.parse_query(query).map_err(Report::new)?;
+    let matched = in_language(
+        fields,
+        with_cjk(snapshot, parsed.box_clone(), query, language)?,
+        language,
+    );
+    let suggestion = suggest(&snapshot.terms, query);
+
+    let hits = collect_hits(snapshot, &*matched, &*parsed, limit, snippet_length)?;
+    if !hits.is_empty() {
+        return Ok(SearchResults {
+            hits,
+            suggestion,
+            fuzzy: false,
+        });
+    }
+
+    let fuzzy = match fuzzy_query(fields, query) {
+        Some(fuzzy) => in_language(fields, fuzzy, language),
+        None => {
+            return Ok(SearchResults {
+                hits,
+                suggestion,
+                fuzzy: false,
+            })
+        }
+    };
+    // fuzzy terms have nothing to highlight, the words of the suggestion do
+    let snippet_query = match suggestion
+        .as_deref()
+        .and_then(|suggestion| parser.parse_query(suggestion).ok())
+    {
+        Some(snippet_query) => snippet_query,
+        None => parsed,
+    };
+    Ok(SearchResults {
+        hits: collect_hits(snapshot, &*fuzzy, &*snippet_query, limit, snippet_length)?,
+        suggestion,
+        fuzzy: true,
+    })
+}
@@ -0,0 +1,158 @@
+use crate::injest::cache_policy::ContentClass;
+use axum::http::{header, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+const PERMISSIONS_POLICY: &str = "permissions-policy";
+
+// The security headers of a response. An empty value leaves that header out, anything not set is
+// the default of the response's class, see `defaults`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecurityHeaderSet {
+    // Strict-Transport-Security, only ever sent when the site is served over https
+    pub hsts: Option<String>,
+    pub content_type_options: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub permissions_policy: Option<String>,
+    pub frame_options: Option<String>,
+    // the sources of `frame-ancestors`, sent as a Content-Security-Policy of its own
+    pub frame_ancestors: Option<String>,
+}
+
+impl SecurityHeaderSet {
+    fn or(self, default: SecurityHeaderSet) -> SecurityHeaderSet {
+        SecurityHeaderSet {
+            hsts: self.hsts.or(default.hsts),
+            content_type_options: self.content_type_options.or(default.content_type_options),
+            referrer_policy: self.referrer_policy.or(default.referrer_policy),
+            permissions_policy: self.permissions_policy.or(default.permissions_policy),
+            frame_options: self.frame_options.or(default.frame_options),
+            frame_ancestors: self.frame_ancestors.or(default.frame_ancestors),
+        }
+    }
+
+    fn defaults(class: ContentClass) -> SecurityHeaderSet {
+        let set =
+            |referrer_policy: &str, frame_options: &str, frame_ancestors: &str| SecurityHeaderSet {
+                hsts: Some("max-age=31536000".to_string()),
+                content_type_options: Some("nosniff".to_string()),
+                referrer_policy: Some(referrer_policy.to_string()),
+                permissions_policy: Some(
+                    "camera=(), microphone=(), geolocation=(), interest-cohort=()".to_string(),
+                ),
+                frame_options: Some(frame_options.to_string()),
+                frame_ancestors: Some(frame_ancestors.to_string()),
+            };
+        match class {
+            // the admin api and the pages it serves are never framed or named in a referrer
+            ContentClass::Admin => set("no-referrer", "DENY", "'none'"),
+            _ => set("strict-origin-when-cross-origin", "SAMEORIGIN", "'self'"),
+        }
+    }
+
+    // the headers as they go out, without the empty ones
+    pub fn headers(&self, https: bool) -> Vec<(HeaderName, String)> {
+        let hsts = self.hsts.clone().filter(|_| https);
+        let frame_ancestors = self
+            .frame_ancestors
+            .as_ref()
+            .filter(|sources| !sources.is_empty())
+            .map(|sources| format!("frame-ancestors {sources}"));
+        [
+            (header::STRICT_TRANSPORT_SECURITY, hsts),
+            (
+                header::X_CONTENT_TYPE_OPTIONS,
+                self.content_type_options.clone(),
+            ),
+            (header::REFERRER_POLICY, self.referrer_policy.clone()),
+            (
+                HeaderName::from_static(PERMISSIONS_POLICY),
+                self.permissions_policy.clone(),
+            ),
+            (header::X_FRAME_OPTIONS, self.frame_options.clone()),
+            (header::CONTENT_SECURITY_POLICY, frame_ancestors),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value.filter(|value| !value.is_empty())?)))
+        .collect()
+    }
+}
+
+// `[[headers.paths]]`, different headers for a part of the site
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathHeaders {
+    // a site path prefix, `/embed` covers `/embed` and everything under it
+    pub path: String,
+    #[serde(flatten)]
+    pub headers: SecurityHeaderSet,
+}
+
+impl PathHeaders {
+    fn prefix(&self) -> String {
+        format!("/{}", self.path.trim_matches('/'))
+    }
+
+    fn covers(&self, path: &str) -> bool {
+        let prefix = self.prefix();
+        prefix == "/"
+            || path == prefix
+            || path
+                .strip_prefix(&prefix)
+                .map_or(false, |rest| rest.starts_with('/'))
+    }
+}
+
+// `[headers]` in site.toml, the security headers of every response. Set on responses that don't
+// have them already, a plugin route can send its own.
+//
+// [headers]
+// hsts = "max-age=63072000; includeSubDomains; preload"
+// permissions_policy = ""
+//
+// [[headers.paths]]
+// path = "/embed"
+// frame_options = ""
+// frame_ancestors = "https://example.org"
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecurityHeaders {
+    #[serde(flatten)]
+    pub site: SecurityHeaderSet,
+    #[serde(default)]
+    pub paths: Vec<PathHeaders>,
+}
+
+impl SecurityHeaders {
+    // the most specific `[[headers.paths]]` over `[headers]` over the defaults of the class
+    pub fn for_path(&self, path: &str) -> SecurityHeaderSet {
+        let site = self
+            .site
+            .clone()
+            .or(SecurityHeaderSet::defaults(ContentClass::of(path)));
+        match self
+            .paths
+            .iter()
+            .filter(|paths| paths.covers(path))
+            .max_by_key(|paths| paths.prefix().len())
+        {
+            Some(paths) => paths.headers.clone().or(site),
+            None => site,
+        }
+    }
+
+    // values that can't go into a header, for SiteMeta::validate
+    pub fn problems(&self) -> Vec<String> {
+        let sets = std::iter::once(("headers".to_string(), &self.site)).chain(
+            self.paths
+                .iter()
+                .map(|paths| (format!("headers for {}", paths.prefix()), &paths.headers)),
+        );
+        let mut problems = vec![];
+        for (at, set) in sets {
+            for (name, value) in set.headers(true) {
+                if HeaderValue::from_str(&value).is_err() {
+                    problems.push(format!("{at}: \"{value}\" is not a valid {name}"));
+                }
+            }
+        }
+        problems
+    }
+}
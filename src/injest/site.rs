@@ -0,0 +1,392 @@
+use crate::config::Config;
+use crate::plugin::{rhai::stdlib::ScriptOptions, route::RouteConfig};
+use crate::injest::{
+    access::AccessRule, cache_policy::CachePolicy, cdn::CdnConfig, diagram::DiagramOptions,
+    ebook::EbookOptions,
+    external_links::ExternalLinkOptions, file_handler::FileHandlers, ipfs::IpfsOptions,
+    limits::BuildLimits, links::SiteUrl, lint::LintOptions, markup::MarkupOptions,
+    notify::Subscription,
+    report::BuildReport, retention::RetentionOptions, sanitize::SanitizeRule,
+    security_headers::SecurityHeaders,
+    slug::SlugOptions,
+    social_card::SocialCardOptions,
+    spellcheck::SpellcheckOptions, svg::SvgOptions, tags::TagOptions,
+    template_check::TemplateVariables, templates::SiteThemeMetadata,
+    typography::TypographyOptions, validate::HtmlValidation, webhook::WebhookConfig,
+    workflow::WorkflowOptions,
+};
+use color_eyre::Result;
+use language_tags::LanguageTag;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::read_to_string;
+use std::path::Path;
+use toml::Value;
+
+pub const SITE_FILE: &str = "site.toml";
+
+// site wide configuration that lives in the content repo, next to the content itself.
+// anything that belongs to the server (secrets, database, ports) stays in `Config`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SiteMeta {
+    // falls back to the SITENAME the server was started with
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    #[serde(default)]
+    pub social: Vec<SocialLink>,
+    #[serde(default)]
+    pub menu: Vec<MenuEntry>,
+    // overrides for the options the theme declares
+    #[serde(default)]
+    pub theme: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub build: BuildOptions,
+    #[serde(default)]
+    pub diagrams: DiagramOptions,
+    #[serde(default)]
+    pub markup: MarkupOptions,
+    // what to do with files that are neither pages nor known static files, by extension
+    #[serde(default)]
+    pub files: FileHandlers,
+    // the language of index.md, english if not set
+    pub default_language: Option<String>,
+    // languages besides the default every page is expected to be translated into
+    #[serde(default)]
+    pub languages: Vec<String>,
+    // what theme scripts may do besides computing things
+    #[serde(default)]
+    pub scripts: ScriptOptions,
+    // directories with a plugin.toml, wasm plugins that run inside the build
+    #[serde(default)]
+    pub wasm_plugins: Vec<String>,
+    // url prefixes served by plugin scripts instead of the built site
+    #[serde(default)]
+    pub routes: BTreeMap<String, RouteConfig>,
+    // told about builds and newly published pages
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    // parts of the site only some roles may see
+    #[serde(default)]
+    pub access: Vec<AccessRule>,
+    // pages whose content is cleaned of anything that runs, see sanitize.rs
+    #[serde(default)]
+    pub sanitize: Vec<SanitizeRule>,
+    // role -> the content directories whose pages hold it, for sanitize rules by role
+    #[serde(default)]
+    pub role_paths: BTreeMap<String, BTreeSet<String>>,
+    // purged of changed pages after every build
+    #[serde(default)]
+    pub cdn: Vec<CdnConfig>,
+    // the caching headers of every kind of response, see cache_policy.rs
+    #[serde(default)]
+    pub cache: CachePolicy,
+    // hsts, framing, referrers and the like of every response, see security_headers.rs
+    #[serde(default)]
+    pub headers: SecurityHeaders,
+    // every build mirrored to ipfs
+    pub ipfs: Option<IpfsOptions>,
+    // who may see draft and review pages
+    #[serde(default)]
+    pub workflow: WorkflowOptions,
+    // who is told about what, by email, matrix or webhook
+    #[serde(default)]
+    pub notify: Vec<Subscription>,
+    // aliases every page's tags are mapped through, see tags.rs
+    #[serde(default)]
+    pub tags: TagOptions,
+    // how directory names and headings become urls and ids, see slug.rs
+    #[serde(default)]
+    pub slugs: SlugOptions,
+    // how much of old builds and the build caches is kept, see retention.rs
+    #[serde(default)]
+    pub retention: RetentionOptions,
+    // what one build may use before it fails, see limits.rs
+    #[serde(default)]
+    pub limits: BuildLimits,
+    // serve the default language under /{lang}/ like every other language instead of at /
+    #[serde(default)]
+    pub prefix_default_language: bool,
+}
+
+// optional build stages, all off unless turned on under `[build]` (except svg handling)
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BuildOptions {
+    // inline the css a page uses into its <head> and load the full stylesheet afterwards
+    #[serde(default)]
+    pub critical_css: bool,
+    // schema.org json-ld for every page, see structured_data.rs
+    #[serde(default)]
+    pub structured_data: bool,
+    // report missing alt text, empty links, skipped headings and a missing lang per page
+    #[serde(default)]
+    pub accessibility_audit: bool,
+    // check the markup of every finished page, `strict` fails the build on any problem
+    #[serde(default)]
+    pub html_validation: HtmlValidation,
+    // subset the theme's webfonts to the characters each language uses and preload them, needs
+    // pyftsubset from fonttools
+    #[serde(default)]
+    pub font_subsetting: bool,
+    // a poster frame for every <video> without one, needs ffmpeg
+    #[serde(default)]
+    pub video_posters: bool,
+    // sanitizing and minifying of svgs, on by default
+    #[serde(default)]
+    pub svg: SvgOptions,
+    // characters of a generated summary before it ends at the next sentence, 200 if not set
+    pub summary_length: Option<usize>,
+    // a generated og:image for every page, see social_card.rs
+    pub social_cards: Option<SocialCardOptions>,
+    // style rules for markdown sources, a rule at `error` fails the build, see lint.rs
+    pub lint: Option<LintOptions>,
+    // misspellings in markdown sources, checked against the dictionary of their language
+    pub spellcheck: Option<SpellcheckOptions>,
+    // quotes, spacing, widows and hyphenation by language, see typography.rs
+    #[serde(default)]
+    pub typography: TypographyOptions,
+    // rel, target and a class for links to other sites
+    pub external_links: Option<ExternalLinkOptions>,
+    // report `#fragment` links between pages whose target page has no such id, see anchors.rs
+    #[serde(default)]
+    pub anchor_check: bool,
+    // a `#` link at the end of every heading with an id, for copying a link to that section
+    #[serde(default)]
+    pub section_permalinks: bool,
+    // report variables templates read that no page has and theme options nothing reads, `strict`
+    // fails the build on any, see template_check.rs
+    #[serde(default)]
+    pub template_variables: TemplateVariables,
+    // every page and the links between them as /graph.json, for graph views, see graph.rs
+    #[serde(default)]
+    pub link_graph: bool,
+    // an epub (and a pdf) of every completed series, next to its page, see ebook.rs
+    pub ebooks: Option<EbookOptions>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SocialLink {
+    pub name: String,
+    pub url: String,
+}
+
+// everything under `site.*` that is the same for every page
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SiteVariables {
+    pub title: String,
+    pub base_url: String,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub social: Vec<SocialLink>,
+    pub theme: BTreeMap<String, Value>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MenuEntry {
+    pub label: String,
+    // language tag -> label, falls back to `label`
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    pub url: Option<String>,
+    #[serde(default)]
+    pub children: Vec<MenuEntry>,
+}
+
+// what a template sees under `site.menu`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct MenuItem {
+    pub label: String,
+    pub url: Option<String>,
+    pub external: bool,
+    pub current: bool,
+    pub ancestor: bool,
+    pub children: Vec<MenuItem>,
+}
+
+impl SiteMeta {
+    pub fn load(site_build_path: impl AsRef<Path>) -> Result<SiteMeta> {
+        let path = site_build_path.as_ref().join(SITE_FILE);
+        if !path.exists() {
+            return Ok(SiteMeta::default());
+        }
+        Ok(toml::from_str::<SiteMeta>(&read_to_string(path)?)?)
+    }
+
+    pub fn validate(&self, path: &Path, report: &mut BuildReport) {
+        fn validate_entries(entries: &[MenuEntry], path: &Path, report: &mut BuildReport) {
+            for entry in entries {
+                if entry.url.is_none() && entry.children.is_empty() {
+                    report.warn(
+                        path,
+                        format!("menu entry \"{}\" has no url and no children", entry.label),
+                    );
+                }
+                for language in entry.labels.keys() {
+                    if LanguageTag::parse(language).is_err() {
+                        report.error(
+                            path,
+                            format!(
+                                "menu entry \"{}\" has a label for \"{language}\", which is not a language tag",
+                                entry.label
+                            ),
+                        );
+                    }
+                }
+                validate_entries(&entry.children, path, report);
+            }
+        }
+
+        validate_entries(&self.menu, path, report);
+
+        for language in self.default_language.iter().chain(&self.languages) {
+            if LanguageTag::parse(language).is_err() {
+                report.error(
+                    path,
+                    format!("\"{language}\" is not a language tag"),
+                );
+            }
+        }
+
+        for problem in self.cache.problems() {
+            report.error(path, problem);
+        }
+        for problem in self.headers.problems() {
+            report.error(path, problem);
+        }
+    }
+
+    pub fn default_language(&self) -> LanguageTag {
+        self.default_language
+            .as_deref()
+            .and_then(|language| LanguageTag::parse(language).ok())
+            .unwrap_or_else(|| LanguageTag::parse("en").unwrap())
+    }
+
+    pub fn expected_languages(&self) -> Vec<LanguageTag> {
+        self.languages
+            .iter()
+            .filter_map(|language| LanguageTag::parse(language).ok())
+            .collect()
+    }
+
+    pub fn variables(
+        &self,
+        config: &Config,
+        theme: &SiteThemeMetadata,
+        path: &Path,
+        report: &mut BuildReport,
+    ) -> SiteVariables {
+        let mut options = BTreeMap::new();
+        for (name, option) in &theme.options {
+            match (self.theme.get(name), &option.default) {
+                (Some(value), _) | (None, Some(value)) => {
+                    options.insert(name.clone(), value.clone());
+                }
+                (None, None) if option.required => report.error(
+                    path,
+                    format!(
+                        "theme \"{}\" requires the option \"{name}\" to be set under [theme]{}",
+                        theme.name,
+                        option
+                            .description
+                            .as_ref()
+                            .map(|desc| format!(" ({desc})"))
+                            .unwrap_or_default()
+                    ),
+                ),
+                (None, None) => {}
+            }
+        }
+
+        for name in self.theme.keys() {
+            if !theme.options.contains_key(name) {
+                report.warn(
+                    path,
+                    format!(
+                        "[theme] sets \"{name}\", which theme \"{}\" does not declare",
+                        theme.name
+                    ),
+                );
+            }
+        }
+
+        SiteVariables {
+            title: self
+                .title
+                .clone()
+                .unwrap_or_else(|| config.sitename().to_string()),
+            base_url: config.site_url().base().to_string(),
+            description: self.description.clone(),
+            author: self.author.clone(),
+            social: self.social.clone(),
+            theme: options,
+        }
+    }
+
+    // `page_path` is the site path of the page being rendered, e.g. `/programming/rust`
+    pub fn menu_for(&self, page_path: &str, language: &LanguageTag, urls: &SiteUrl) -> Vec<MenuItem> {
+        self.menu
+            .iter()
+            .map(|entry| entry.to_item(page_path, language, urls))
+            .collect()
+    }
+}
+
+impl MenuEntry {
+    pub fn label_for(&self, language: &LanguageTag) -> &str {
+        self.labels
+            .get(language.as_str())
+            .or_else(|| self.labels.get(language.primary_language()))
+            .unwrap_or(&self.label)
+    }
+
+    fn to_item(&self, page_path: &str, language: &LanguageTag, urls: &SiteUrl) -> MenuItem {
+        let children = self
+            .children
+            .iter()
+            .map(|child| child.to_item(page_path, language, urls))
+            .collect::<Vec<MenuItem>>();
+
+        let external = self
+            .url
+            .as_ref()
+            .map(|url| url::Url::parse(url).is_ok())
+            .unwrap_or(false);
+
+        let page_path = normalize_path(page_path);
+        let (current, under) = match (&self.url, external) {
+            (Some(url), false) => {
+                let url = normalize_path(url);
+                let under = url != "/" && page_path.starts_with(&format!("{url}/"));
+                (url == page_path, under)
+            }
+            _ => (false, false),
+        };
+
+        let ancestor = under
+            || children
+                .iter()
+                .any(|child| child.current || child.ancestor);
+
+        MenuItem {
+            label: self.label_for(language).to_string(),
+            url: match (&self.url, external) {
+                (Some(url), false) => Some(urls.rewrite_internal(url).unwrap_or_else(|| url.clone())),
+                (url, _) => url.clone(),
+            },
+            external,
+            current,
+            ancestor,
+            children,
+        }
+    }
+}
+
+fn normalize_path(path: &str) -> &str {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    match path.trim_end_matches('/') {
+        "" => "/",
+        p => p,
+    }
+}
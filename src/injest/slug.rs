@@ -0,0 +1,162 @@
+use any_ascii::any_ascii;
+use pulldown_cmark::{Event, Tag};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path};
+use tera::{Filter, Tera, Value};
+
+// `[slugs]` in site.toml, how names and titles become urls and heading ids
+//
+// [slugs]
+// strategy = "transliterate"
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlugOptions {
+    #[serde(default)]
+    pub strategy: SlugStrategy,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlugStrategy {
+    // the words as they are written, percent-encoded once they're in a url: `/blog/안녕하세요`
+    // is requested as `/blog/%EC%95%88...`, browsers show it in hangul
+    #[default]
+    Unicode,
+    // spelled in ascii: `/blog/annyeonghaseyo`. kanji come out in their chinese reading
+    Transliterate,
+}
+
+impl SlugStrategy {
+    // `text` as one segment of a url or an id: lowercase words joined by `-`, everything but
+    // letters, digits and `_` dropped. Empty if that leaves nothing.
+    pub fn slugify(&self, text: &str) -> String {
+        let text = match self {
+            SlugStrategy::Unicode => text.to_string(),
+            SlugStrategy::Transliterate => any_ascii(text),
+        };
+        text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+
+    // A directory name as its segment of the url. Directory names were urls before there were
+    // strategies, so unicode leaves them exactly as they are.
+    pub fn segment(&self, name: &str) -> String {
+        match self {
+            SlugStrategy::Unicode => name.to_string(),
+            SlugStrategy::Transliterate => match self.slugify(name) {
+                slug if slug.is_empty() => name.to_string(),
+                slug => slug,
+            },
+        }
+    }
+
+    // The site path of the page in `dir`, relative to the content root. `slug` from the front
+    // matter replaces the page's own segment, the directories above it keep theirs.
+    pub fn page_path(&self, dir: &Path, slug: Option<&str>) -> String {
+        let mut segments = dir
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => name.to_str(),
+                _ => None,
+            })
+            .map(|name| self.segment(name))
+            .collect::<Vec<_>>();
+        if let Some(slug) = slug.map(|slug| slug.trim_matches('/')) {
+            match segments.last_mut() {
+                Some(last) if !slug.is_empty() => *last = slug.to_string(),
+                _ => {}
+            }
+        }
+        format!("/{}", segments.join("/"))
+    }
+}
+
+// Every markdown heading without an id of its own (`# Title {#id}`) gets one from its text. The
+// second heading with the same text gets `-1` after it, the third `-2`, in document order.
+pub fn heading_ids<'a>(events: Vec<Event<'a>>, strategy: SlugStrategy) -> Vec<Event<'a>> {
+    let mut taken = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Start(Tag::Heading(_, Some(id), _)) => Some(id.to_string()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    let mut counts = HashMap::<String, usize>::new();
+
+    let mut with_ids = Vec::with_capacity(events.len());
+    let mut events = events.into_iter();
+    while let Some(event) = events.next() {
+        let (level, classes) = match event {
+            Event::Start(Tag::Heading(level, None, classes)) => (level, classes),
+            event => {
+                with_ids.push(event);
+                continue;
+            }
+        };
+        let mut inner = vec![];
+        let mut text = String::new();
+        for event in events.by_ref() {
+            match &event {
+                Event::End(Tag::Heading(..)) => {
+                    inner.push(event);
+                    break;
+                }
+                Event::Text(chunk) | Event::Code(chunk) => text.push_str(chunk),
+                _ => {}
+            }
+            inner.push(event);
+        }
+
+        let base = match strategy.slugify(&text) {
+            slug if slug.is_empty() => "section".to_string(),
+            slug => slug,
+        };
+        let mut id = base.clone();
+        while taken.contains(&id) {
+            let count = counts.entry(base.clone()).or_default();
+            *count += 1;
+            id = format!("{base}-{count}");
+        }
+        taken.insert(id.clone());
+
+        let class = match classes.is_empty() {
+            true => String::new(),
+            false => format!(
+                r#" class="{}""#,
+                html_escape::encode_double_quoted_attribute(&classes.join(" "))
+            ),
+        };
+        with_ids.push(Event::Html(
+            format!(
+                r#"<{level} id="{}"{class}>"#,
+                html_escape::encode_double_quoted_attribute(&id)
+            )
+            .into(),
+        ));
+        // the end tag goes out the way pulldown-cmark writes it
+        with_ids.extend(inner);
+    }
+    with_ids
+}
+
+// `{{ tag | slug }}`, the site's strategy for links a theme makes itself, tag pages for one
+struct Slug {
+    strategy: SlugStrategy,
+}
+
+impl Filter for Slug {
+    fn filter(&self, value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+        let text = value
+            .as_str()
+            .ok_or_else(|| tera::Error::msg(format!("slug: {value} is not a string")))?;
+        Ok(Value::String(self.strategy.slugify(text)))
+    }
+}
+
+// registered before the theme's own filters, like the locale ones
+pub fn register_slug_filter(tera: &mut Tera, strategy: SlugStrategy) {
+    tera.register_filter("slug", Slug { strategy });
+}
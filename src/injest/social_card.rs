@@ -0,0 +1,275 @@
+use crate::injest::assets::AssetStore;
+use crate::injest::static_file::hash_file;
+use crate::CACHE_DIR;
+use color_eyre::{Report, Result};
+use fontdue::layout::{CoordinateSystem, Layout, LayoutSettings, TextStyle};
+use fontdue::{Font, FontSettings};
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, read, write};
+use std::path::{Path, PathBuf};
+use tera::Context;
+use tiny_skia::{Color, Paint, Pixmap, Rect, Transform};
+
+// what og:image readers expect, 1.91:1
+pub const CARD_WIDTH: u32 = 1200;
+pub const CARD_HEIGHT: u32 = 630;
+
+const PADDING: f32 = 80.0;
+const ACCENT_WIDTH: f32 = 16.0;
+const LINE_GAP: f32 = 24.0;
+
+// `[build.social_cards]`, a preview image for every page without one of its own
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SocialCardOptions {
+    // a ttf or otf in the content repo
+    pub font: String,
+    #[serde(default = "default_background")]
+    pub background: String,
+    #[serde(default = "default_foreground")]
+    pub foreground: String,
+    // the bar along the left edge
+    #[serde(default = "default_accent")]
+    pub accent: String,
+    #[serde(default = "default_lines")]
+    pub lines: Vec<CardLine>,
+}
+
+// One line (wrapped if it has to) of the card. `text` is a Tera template that gets `title`,
+// `authors`, `author`, `date` and `site`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CardLine {
+    pub text: String,
+    pub size: f32,
+    // the foreground if not set
+    pub color: Option<String>,
+    // stacked up from the bottom edge instead of down from the top
+    #[serde(default)]
+    pub bottom: bool,
+}
+
+fn default_background() -> String {
+    "#1f2430".to_string()
+}
+
+fn default_foreground() -> String {
+    "#f0f0f0".to_string()
+}
+
+fn default_accent() -> String {
+    "#ff7a59".to_string()
+}
+
+fn default_lines() -> Vec<CardLine> {
+    vec![
+        CardLine {
+            text: "{{ title }}".to_string(),
+            size: 72.0,
+            color: None,
+            bottom: false,
+        },
+        CardLine {
+            text: "{{ author }}".to_string(),
+            size: 36.0,
+            color: None,
+            bottom: false,
+        },
+        CardLine {
+            text: "{{ site }}".to_string(),
+            size: 32.0,
+            color: None,
+            bottom: true,
+        },
+    ]
+}
+
+// `#rgb` or `#rrggbb`, cards are always opaque
+fn parse_color(color: &str) -> Result<Color> {
+    let hex = color.trim().trim_start_matches('#');
+    let hex = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect::<String>(),
+        6 => hex.to_string(),
+        _ => return Err(Report::msg(format!("{color} is not a #rrggbb color"))),
+    };
+    let channel = |at: usize| {
+        u8::from_str_radix(&hex[at..at + 2], 16)
+            .map_err(|_| Report::msg(format!("{color} is not a #rrggbb color")))
+    };
+    Ok(Color::from_rgba8(
+        channel(0)?,
+        channel(2)?,
+        channel(4)?,
+        255,
+    ))
+}
+
+// what a card says about its page
+pub struct CardText<'a> {
+    pub title: &'a str,
+    pub authors: &'a [String],
+    pub date: Option<String>,
+}
+
+// Renders the social cards of a build. Cards are cached by everything that goes into them, so an
+// unchanged page doesn't get rasterized again, and go out through the AssetStore like any other
+// static file.
+pub struct SocialCards {
+    options: SocialCardOptions,
+    font: Font,
+    // hash of the font file, a new font means new cards
+    font_hash: u64,
+    site: String,
+    cache: PathBuf,
+}
+
+impl SocialCards {
+    pub fn new(options: &SocialCardOptions, site_root: &Path, site: &str) -> Result<SocialCards> {
+        let font_bytes = read(site_root.join(options.font.trim_start_matches('/')))?;
+        let font = Font::from_bytes(font_bytes.as_slice(), FontSettings::default())
+            .map_err(|why| Report::msg(format!("{}: {why}", options.font)))?;
+        // fail on bad colors now and not once per page
+        for color in [&options.background, &options.foreground, &options.accent]
+            .into_iter()
+            .chain(options.lines.iter().filter_map(|line| line.color.as_ref()))
+        {
+            parse_color(color)?;
+        }
+
+        let cache = Path::new(CACHE_DIR).join("social-cards");
+        create_dir_all(&cache)?;
+        Ok(SocialCards {
+            options: options.clone(),
+            font,
+            font_hash: hash_file(&font_bytes),
+            site: site.to_string(),
+            cache,
+        })
+    }
+
+    // the site path of the page's card
+    pub fn card(&self, text: &CardText, assets: &AssetStore) -> Result<String> {
+        let mut context = Context::new();
+        context.insert("title", text.title);
+        context.insert("authors", text.authors);
+        context.insert("author", &text.authors.join(", "));
+        context.insert("date", &text.date);
+        context.insert("site", &self.site);
+
+        let mut lines = Vec::with_capacity(self.options.lines.len());
+        for line in self.options.lines.iter() {
+            let rendered = tera::Tera::one_off(&line.text, &context, false)?;
+            let rendered = rendered.trim();
+            if !rendered.is_empty() {
+                lines.push((line, rendered.to_string()));
+            }
+        }
+
+        let key = format!(
+            "{}{:016x}{}",
+            toml::to_string(&self.options)?,
+            self.font_hash,
+            lines
+                .iter()
+                .map(|(_, text)| text.as_str())
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+        let file = self.cache.join(format!("{:016x}.png", hash_file(&key)));
+        if !file.is_file() {
+            write(&file, self.render(&lines)?)?;
+        }
+        assets.site_path(&file)
+    }
+
+    fn render(&self, lines: &[(&CardLine, String)]) -> Result<Vec<u8>> {
+        let mut pixmap = Pixmap::new(CARD_WIDTH, CARD_HEIGHT)
+            .ok_or_else(|| Report::msg("failed to allocate the social card"))?;
+        pixmap.fill(parse_color(&self.options.background)?);
+
+        let mut paint = Paint::default();
+        paint.set_color(parse_color(&self.options.accent)?);
+        if let Some(bar) = Rect::from_xywh(0.0, 0.0, ACCENT_WIDTH, CARD_HEIGHT as f32) {
+            pixmap.fill_rect(bar, &paint, Transform::identity(), None);
+        }
+
+        let max_width = CARD_WIDTH as f32 - PADDING * 2.0;
+        let mut top = PADDING;
+        let mut bottom = CARD_HEIGHT as f32 - PADDING;
+        // bottom lines stack upwards, the last one sits on the edge
+        for (line, text) in lines.iter().filter(|(line, _)| !line.bottom) {
+            let layout = self.layout(text, line.size, max_width);
+            self.draw(&mut pixmap, &layout, PADDING, top, self.line_color(line)?);
+            top += layout.height() + LINE_GAP;
+        }
+        for (line, text) in lines.iter().rev().filter(|(line, _)| line.bottom) {
+            let layout = self.layout(text, line.size, max_width);
+            bottom -= layout.height();
+            self.draw(
+                &mut pixmap,
+                &layout,
+                PADDING,
+                bottom,
+                self.line_color(line)?,
+            );
+            bottom -= LINE_GAP;
+        }
+
+        pixmap
+            .encode_png()
+            .map_err(|why| Report::msg(why.to_string()))
+    }
+
+    fn line_color(&self, line: &CardLine) -> Result<Color> {
+        parse_color(line.color.as_deref().unwrap_or(&self.options.foreground))
+    }
+
+    fn layout(&self, text: &str, size: f32, max_width: f32) -> Layout {
+        let mut layout = Layout::new(CoordinateSystem::PositiveYDown);
+        layout.reset(&LayoutSettings {
+            max_width: Some(max_width),
+            ..LayoutSettings::default()
+        });
+        layout.append(&[&self.font], &TextStyle::new(text, size, 0));
+        layout
+    }
+
+    // blends the coverage of every glyph over what is already there, anything off the card is cut
+    fn draw(&self, pixmap: &mut Pixmap, layout: &Layout, x: f32, y: f32, color: Color) {
+        let color = color.to_color_u8();
+        let (width, height) = (pixmap.width() as i64, pixmap.height() as i64);
+        let pixels = pixmap.pixels_mut();
+
+        for glyph in layout.glyphs() {
+            if glyph.width == 0 || glyph.height == 0 {
+                continue;
+            }
+            let (metrics, coverage) = self.font.rasterize_config(glyph.key);
+            let left = (x + glyph.x) as i64;
+            let top = (y + glyph.y) as i64;
+            for row in 0..metrics.height {
+                for column in 0..metrics.width {
+                    let (px, py) = (left + column as i64, top + row as i64);
+                    if px < 0 || py < 0 || px >= width || py >= height {
+                        continue;
+                    }
+                    let alpha = coverage[row * metrics.width + column] as u32;
+                    if alpha == 0 {
+                        continue;
+                    }
+                    let pixel = &mut pixels[(py * width + px) as usize];
+                    let blend = |over: u8, under: u8| {
+                        ((over as u32 * alpha + under as u32 * (255 - alpha)) / 255) as u8
+                    };
+                    // opaque background, so premultiplied is the same as straight
+                    if let Some(blended) = tiny_skia::PremultipliedColorU8::from_rgba(
+                        blend(color.red(), pixel.red()),
+                        blend(color.green(), pixel.green()),
+                        blend(color.blue(), pixel.blue()),
+                        255,
+                    ) {
+                        *pixel = blended;
+                    }
+                }
+            }
+        }
+    }
+}
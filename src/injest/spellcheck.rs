@@ -0,0 +1,222 @@
+use crate::injest::{
+    build::SPLITTER,
+    lint::LintSeverity,
+    report::{BuildReport, Severity},
+};
+use color_eyre::{Report, Result};
+use language_tags::LanguageTag;
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use zspell::Dictionary;
+
+// where hunspell dictionaries are usually installed, looked in after the content repo
+const SYSTEM_DICTIONARIES: &str = "/usr/share/hunspell";
+// characters of the line shown around a misspelling
+const CONTEXT_CHARS: usize = 30;
+
+// `[build.spellcheck]` in site.toml, off unless set
+//
+// [build.spellcheck]
+// dictionaries = "dictionaries"
+// allowlist = "spelling.txt"
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpellcheckOptions {
+    // directory in the content repo with a `<language>.aff` and `<language>.dic` per language,
+    // `en-US`, `en_US` and `en` are all tried for en-US
+    #[serde(default = "default_dictionaries")]
+    pub dictionaries: String,
+    // file in the content repo with words that are spelled right, one per line, `#` comments
+    pub allowlist: Option<String>,
+    #[serde(default)]
+    pub severity: LintSeverity,
+}
+
+fn default_dictionaries() -> String {
+    "dictionaries".to_string()
+}
+
+// the names a dictionary for `language` can have, most specific first
+fn dictionary_names(language: &LanguageTag) -> Vec<String> {
+    let mut names = vec![language.as_str().to_string()];
+    if let Some(region) = language.region() {
+        names.push(format!("{}_{region}", language.primary_language()));
+    }
+    names.push(language.primary_language().to_string());
+    names.dedup();
+    names
+}
+
+fn load_dictionary(dirs: &[PathBuf], language: &LanguageTag) -> Result<Option<Dictionary>> {
+    for dir in dirs {
+        for name in dictionary_names(language) {
+            let (aff, dic) = (
+                dir.join(format!("{name}.aff")),
+                dir.join(format!("{name}.dic")),
+            );
+            if !aff.is_file() || !dic.is_file() {
+                continue;
+            }
+            let dictionary = zspell::builder()
+                .config_str(&read_to_string(&aff)?)
+                .dict_str(&read_to_string(&dic)?)
+                .build()
+                .map_err(|why| Report::msg(format!("{}: {why}", dic.display())))?;
+            return Ok(Some(dictionary));
+        }
+    }
+    Ok(None)
+}
+
+fn is_quote(c: char) -> bool {
+    c == '\'' || c == '’'
+}
+
+// the words of a line worth checking, with their byte offset. numbers, single letters and
+// anything with digits in it are not words.
+fn words(line: &str) -> Vec<(usize, &str)> {
+    let mut words = vec![];
+    let mut start = None;
+    for (index, c) in line.char_indices().chain([(line.len(), ' ')]) {
+        match (start, c.is_alphanumeric() || is_quote(c)) {
+            (None, true) => start = Some(index),
+            (Some(from), false) => {
+                let word = &line[from..index];
+                let trimmed = word.trim_start_matches(is_quote);
+                words.push((
+                    from + word.len() - trimmed.len(),
+                    trimmed.trim_end_matches(is_quote),
+                ));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    words.retain(|(_, word)| word.chars().count() > 1 && !word.chars().any(char::is_numeric));
+    words
+}
+
+fn context(line: &str, offset: usize, word: &str) -> String {
+    let start = line[..offset]
+        .char_indices()
+        .rev()
+        .nth(CONTEXT_CHARS)
+        .map_or(0, |(index, _)| index);
+    let end = line[offset + word.len()..]
+        .char_indices()
+        .nth(CONTEXT_CHARS)
+        .map_or(line.len(), |(index, _)| offset + word.len() + index);
+    line[start..end].trim().to_string()
+}
+
+// Checks markdown sources against the dictionary of their language. Dictionaries are loaded the
+// first time a language comes up, a language without one is reported once and not checked.
+pub struct SpellChecker<'a> {
+    options: &'a SpellcheckOptions,
+    dirs: Vec<PathBuf>,
+    dictionaries: HashMap<LanguageTag, Option<Dictionary>>,
+    allowed: HashSet<String>,
+}
+
+impl<'a> SpellChecker<'a> {
+    pub fn new(options: &'a SpellcheckOptions, site_root: &Path) -> Result<SpellChecker<'a>> {
+        let allowed = match &options.allowlist {
+            Some(allowlist) => read_to_string(site_root.join(allowlist))
+                .map_err(|why| Report::msg(format!("allowlist {allowlist}: {why}")))?
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or_default().trim())
+                .filter(|word| !word.is_empty())
+                .map(str::to_lowercase)
+                .collect(),
+            None => HashSet::new(),
+        };
+        Ok(SpellChecker {
+            options,
+            dirs: vec![
+                site_root.join(&options.dictionaries),
+                PathBuf::from(SYSTEM_DICTIONARIES),
+            ],
+            dictionaries: HashMap::new(),
+            allowed,
+        })
+    }
+
+    fn is_spelled_right(dictionary: &Dictionary, allowed: &HashSet<String>, word: &str) -> bool {
+        let lowercase = word.to_lowercase();
+        allowed.contains(&lowercase)
+            || dictionary.check_word(word)
+            // sentence case, or a heading in title case
+            || (word.chars().next().map_or(false, char::is_uppercase)
+                && dictionary.check_word(&lowercase))
+    }
+
+    // `source` is a markdown page with its front matter, reported against `path`
+    pub fn check(
+        &mut self,
+        path: &Path,
+        source: &str,
+        language: &LanguageTag,
+        report: &mut BuildReport,
+    ) {
+        if !self.dictionaries.contains_key(language) {
+            let dictionary = match load_dictionary(&self.dirs, language) {
+                Ok(Some(dictionary)) => Some(dictionary),
+                Ok(None) => {
+                    report.warn(
+                        path,
+                        format!("no dictionary for {language}, not spell checking it"),
+                    );
+                    None
+                }
+                Err(why) => {
+                    report.warn(
+                        path,
+                        format!("dictionary for {language} failed to load: {why}"),
+                    );
+                    None
+                }
+            };
+            self.dictionaries.insert(language.clone(), dictionary);
+        }
+        let dictionary = match self.dictionaries.get(language) {
+            Some(Some(dictionary)) => dictionary,
+            _ => return,
+        };
+
+        let body_start = source
+            .split_once(SPLITTER)
+            .map_or(0, |(header, _)| header.len() + SPLITTER.len());
+        let body = &source[body_start..];
+        let severity = Severity::from(self.options.severity);
+        let mut in_code = false;
+        for (event, range) in Parser::new_ext(body, Options::all()).into_offset_iter() {
+            match event {
+                // code isn't prose
+                Event::Start(Tag::CodeBlock(_)) => in_code = true,
+                Event::End(Tag::CodeBlock(_)) => in_code = false,
+                Event::Text(text) if !in_code => {
+                    let first_line = source[..body_start + range.start].matches('\n').count() + 1;
+                    for (index, line) in text.lines().enumerate() {
+                        for (offset, word) in words(line) {
+                            if Self::is_spelled_right(dictionary, &self.allowed, word) {
+                                continue;
+                            }
+                            report.push(
+                                path,
+                                severity,
+                                format!(
+                                    "line {}: \"{word}\" is misspelled ({language}): {}",
+                                    first_line + index,
+                                    context(line, offset, word)
+                                ),
+                            );
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -6,7 +6,7 @@ use base64::alphabet::URL_SAFE;
 use base64::engine::{GeneralPurpose, GeneralPurposeConfig};
 use tracing::instrument;
 use color_eyre::Result;
-use memmap2::Mmap;
+use crate::util::hash_path;
 use crate::injest::path_relativizie;
 
 #[derive(Clone, Debug, Default, PartialOrd, PartialEq, Serialize, Deserialize)]
@@ -32,43 +32,68 @@ pub fn hash_file(file: impl AsRef<[u8]>) -> u64 {
 }
 
 pub fn new_filename(file: impl AsRef<[u8]>, filename: impl AsRef<Path>) -> Option<(u64, String)> {
-    let hash = hash_file(file);
-    let engine = GeneralPurpose::new(
+    fingerprinted_name(hash_file(file), filename)
+}
+
+// the hash of a fingerprinted name, in hex
+const FINGERPRINT_LENGTH: usize = 16;
+// Names fingerprinted before the hex ones, 8 bytes of url safe base64 with its `=` padding. Only
+// parsed, for the files older builds left in the serve dir.
+const LEGACY_FINGERPRINT_LENGTH: usize = 12;
+
+fn legacy_engine() -> GeneralPurpose {
+    GeneralPurpose::new(
         &URL_SAFE,
-        GeneralPurposeConfig::new().with_encode_padding(true)
-    );
-    let base64 = engine.encode(hash.to_le_bytes());
+        GeneralPurposeConfig::new().with_encode_padding(true),
+    )
+}
+
+pub fn fingerprint(hash: u64) -> String {
+    format!("{hash:016x}")
+}
+
+// `name-<hash>.ext` for a hash worked out some other way, streamed for a large file
+pub fn fingerprinted_name(hash: u64, filename: impl AsRef<Path>) -> Option<(u64, String)> {
     let file_name = filename.as_ref().file_name()?.to_str()?;
     let split = file_name.split_once(".");
     match split {
-        Some((fname, ext)) => Some((hash, format!("{fname}-{base64}.{ext}"))),
+        Some((fname, ext)) => Some((hash, format!("{fname}-{}.{ext}", fingerprint(hash)))),
         None => None,
     }
 }
 
+// `name-<fingerprint>` -> `name` and the fingerprint, if the stem is long enough to have one
+fn split_fingerprint(stem: &str, length: usize) -> Option<(&str, &str)> {
+    let cut = stem.len().checked_sub(length)?;
+    Some((stem.get(..cut)?.strip_suffix('-')?, stem.get(cut..)?))
+}
+
+// The hash and original name of a fingerprinted one, `name-<hash>.ext` back to `name.ext`. The
+// hash is always the same length and a legacy one can have a `-` in it, so it's cut off the end
+// rather than split at a `-`. None for anything that isn't a fingerprinted name.
 pub fn parse_filename(filename: impl AsRef<str>) -> Option<(u64, String)> {
-    match filename.split_once(".") {
-        Some((_, hash_and_ext)) => match hash_and_ext.split_once(".") {
-            Some((hash, _)) => match base64::decode(hash) {
-                Ok(data) => {
-                    let fh = u64::from_le_bytes(data.into());
-                    Some((fh, format!()))
-                }
-                Err(_) => None,
-            },
-            None => None,
-        },
-        None => None,
-    }
+    let (stem, ext) = filename.as_ref().split_once('.')?;
+    let (name, hash) = match split_fingerprint(stem, FINGERPRINT_LENGTH) {
+        Some((name, hex)) if hex.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f')) => {
+            (name, u64::from_str_radix(hex, 16).ok()?)
+        }
+        // a legacy fingerprint ends in `=`, which is never hex
+        _ => {
+            let (name, base64) = split_fingerprint(stem, LEGACY_FINGERPRINT_LENGTH)?;
+            let data = legacy_engine().decode(base64).ok()?;
+            (name, u64::from_le_bytes(data.try_into().ok()?))
+        }
+    };
+    Some((hash, format!("{name}.{ext}")))
 }
 
 pub fn process_static_file(file: impl AsRef<Path>) -> Option<(u64, StaticFile)> {
     let file = file.as_ref();
     if file.metadata()?.len() != 0 {
-        let data = unsafe { Mmap::map(file.path())? };
+        let hash = hash_path(file).ok()?;
         let mut filename = file.into_path();
         let last = filename.file_name().unwrap().to_str().unwrap_or_default();
-        if let Some((hash, newfname)) = new_filename(data.as_ref(), last) {
+        if let Some((hash, newfname)) = fingerprinted_name(hash, last) {
             let filename = filename.with_file_name(newfname);
             let new_filename = path_relativizie(file, filename)?;
             Some((
@@ -0,0 +1,144 @@
+use crate::injest::generate::{ArticleMeta, CategoryMeta, GenericMeta, SeriesMeta};
+use crate::injest::links::SiteUrl;
+use crate::injest::listing::ListingEntry;
+use crate::injest::site::SiteVariables;
+use serde_json::{json, Map, Value};
+
+// what a page is, as far as schema.org cares
+pub enum PageKind<'a> {
+    Article(&'a ArticleMeta),
+    Series(&'a SeriesMeta),
+    Collection {
+        category: &'a CategoryMeta,
+        entries: &'a [ListingEntry],
+    },
+    Generic(&'a GenericMeta),
+}
+
+fn people(names: &[String]) -> Value {
+    Value::Array(
+        names
+            .iter()
+            .map(|name| json!({ "@type": "Person", "name": name }))
+            .collect(),
+    )
+}
+
+fn publisher(site: &SiteVariables) -> Value {
+    json!({ "@type": "Organization", "name": site.title, "url": site.base_url })
+}
+
+// The generated object for a page, with `overrides` (the page's `structured_data` front matter)
+// merged on top. `overrides = false` turns it off for the page.
+pub fn structured_data(
+    kind: &PageKind,
+    path: &str,
+    site: &SiteVariables,
+    urls: &SiteUrl,
+    overrides: Option<&Value>,
+) -> Option<Value> {
+    if overrides == Some(&Value::Bool(false)) {
+        return None;
+    }
+
+    let url = urls.absolute(&urls.policy().canonical_path(path));
+    let generated = match kind {
+        PageKind::Article(article) => json!({
+            "@context": "https://schema.org",
+            "@type": "BlogPosting",
+            "headline": article.title,
+            "url": url,
+            "mainEntityOfPage": url,
+            "datePublished": article.date.to_rfc3339(),
+            "dateModified": article.edited_dates.iter().max().unwrap_or(&article.date).to_rfc3339(),
+            "author": people(&article.authors),
+            "keywords": article.tags,
+            "description": article.summary,
+            "publisher": publisher(site),
+        }),
+        PageKind::Series(series) => json!({
+            "@context": "https://schema.org",
+            "@type": "CreativeWorkSeries",
+            "name": series.title,
+            "url": url,
+            "startDate": series.date_started.to_rfc3339(),
+            "endDate": series.date_completed.map(|date| date.to_rfc3339()),
+            "author": people(&series.authors),
+            "keywords": series.tags,
+            "publisher": publisher(site),
+        }),
+        PageKind::Collection { category, entries } => json!({
+            "@context": "https://schema.org",
+            "@type": "CollectionPage",
+            "name": category.title,
+            "url": url,
+            "mainEntity": {
+                "@type": "ItemList",
+                "itemListElement": entries
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, entry)| json!({
+                        "@type": "ListItem",
+                        "position": idx + 1,
+                        "name": entry.title,
+                        "url": entry.url,
+                    }))
+                    .collect::<Vec<_>>(),
+            },
+        }),
+        PageKind::Generic(generic) => json!({
+            "@context": "https://schema.org",
+            "@type": "WebPage",
+            "name": generic.title,
+            "url": url,
+            "datePublished": generic.date.to_rfc3339(),
+            "author": people(&generic.authors),
+            "keywords": generic.tags,
+        }),
+    };
+
+    let mut generated = without_nulls(generated);
+    if let Some(overrides) = overrides {
+        merge(&mut generated, overrides);
+    }
+    Some(generated)
+}
+
+fn without_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, without_nulls(value)))
+                .collect::<Map<String, Value>>(),
+        ),
+        other => other,
+    }
+}
+
+// objects merge key by key, anything else replaces what was generated
+fn merge(base: &mut Value, overrides: &Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides.clone(),
+    }
+}
+
+// a `<script type="application/ld+json">` for the <head>
+pub fn json_ld_script(value: &Value) -> String {
+    // a string containing </script> must not end the element
+    format!(
+        r#"<script type="application/ld+json">{}</script>"#,
+        value.to_string().replace("</", "<\\/")
+    )
+}
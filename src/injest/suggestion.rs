@@ -0,0 +1,182 @@
+use color_eyre::{Report, Result};
+use itertools::Itertools;
+use language_tags::LanguageTag;
+use std::fs::read_to_string;
+use std::path::{Component, Path, PathBuf};
+
+// what a translation leaf can be written in, `<dir>/<lang>.<ext>` next to the page's index
+pub const TRANSLATION_EXTENSIONS: &[&str] = &["md", "org", "rst", "adoc", "html"];
+// lines of the translation kept on either side of a suggestion, and around a hunk of the patch
+pub const CONTEXT_LINES: usize = 3;
+
+// the site path of a page without the `/<language>` prefix of its translation
+pub fn untranslated_path(page: &str, language: &LanguageTag) -> String {
+    let page = page.trim_matches('/');
+    let page = page
+        .strip_prefix(language.as_str())
+        .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+        .unwrap_or(page);
+    format!("/{}", page.trim_start_matches('/'))
+}
+
+// The translation leaf of `page` in `language`, relative to the content root. The page is a site
+// path, with or without the language prefix. None if the page has no translation of its own, a
+// page falling back to the default language has nothing to correct.
+pub fn translation_source(site_root: &Path, page: &str, language: &LanguageTag) -> Option<PathBuf> {
+    let dir = PathBuf::from(untranslated_path(page, language).trim_start_matches('/'));
+    // a page path reaching out of the content repo is no page
+    if dir
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    TRANSLATION_EXTENSIONS
+        .iter()
+        .map(|extension| dir.join(format!("{}.{extension}", language.as_str())))
+        .find(|source| site_root.join(source).is_file())
+}
+
+// where a suggestion sits in the translation as it was when it was made
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SuggestionContext {
+    pub before: String,
+    pub after: String,
+}
+
+// The text around the one place `original` appears in `source`. An excerpt that appears more than
+// once is ambiguous, the reader has to quote more of it.
+pub fn suggestion_context(source: &str, original: &str) -> Result<SuggestionContext> {
+    if original.trim().is_empty() {
+        return Err(Report::msg("nothing quoted to correct"));
+    }
+    let mut found = source.match_indices(original);
+    let start = match (found.next(), found.next()) {
+        (Some((start, _)), None) => start,
+        (None, _) => return Err(Report::msg("the quoted text is not in the translation")),
+        (Some(_), Some(_)) => {
+            return Err(Report::msg(
+                "the quoted text appears more than once, quote more of it",
+            ))
+        }
+    };
+    let end = start + original.len();
+
+    let before = &source[..start];
+    let before_start = before
+        .rmatch_indices('\n')
+        .nth(CONTEXT_LINES)
+        .map_or(0, |(index, _)| index + 1);
+    let after = &source[end..];
+    let after_end = after
+        .match_indices('\n')
+        .nth(CONTEXT_LINES)
+        .map_or(after.len(), |(index, _)| index);
+    Ok(SuggestionContext {
+        before: before[before_start..].to_string(),
+        after: after[..after_end].to_string(),
+    })
+}
+
+// `source` with every `(original, suggested)` applied, or the excerpts that are no longer there
+pub fn apply_suggestions(
+    source: &str,
+    suggestions: &[(&str, &str)],
+) -> Result<String, Vec<String>> {
+    let mut text = source.to_string();
+    let mut missing = vec![];
+    for (original, suggested) in suggestions {
+        match text.matches(original).count() {
+            1 => text = text.replacen(original, suggested, 1),
+            _ => missing.push(original.to_string()),
+        }
+    }
+    match missing.is_empty() {
+        true => Ok(text),
+        false => Err(missing),
+    }
+}
+
+// A unified diff of one file, what `git apply` takes. The whole change is one hunk, from the first
+// line that differs to the last.
+pub fn unified_diff(path: &str, old: &str, new: &str) -> String {
+    let old_lines = old.split_inclusive('\n').collect::<Vec<_>>();
+    let new_lines = new.split_inclusive('\n').collect::<Vec<_>>();
+    let prefix = old_lines
+        .iter()
+        .zip(&new_lines)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = old_lines[prefix..]
+        .iter()
+        .rev()
+        .zip(new_lines[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    if prefix == old_lines.len() && prefix == new_lines.len() {
+        return String::new();
+    }
+
+    let start = prefix.saturating_sub(CONTEXT_LINES);
+    let old_end = (old_lines.len() - suffix + CONTEXT_LINES).min(old_lines.len());
+    let new_end = (new_lines.len() - suffix + CONTEXT_LINES).min(new_lines.len());
+    let line = |marker: char, text: &str| match text.ends_with('\n') {
+        true => format!("{marker}{text}"),
+        false => format!("{marker}{text}\n\\ No newline at end of file\n"),
+    };
+
+    let mut diff = format!("--- a/{path}\n+++ b/{path}\n");
+    diff.push_str(&format!(
+        "@@ -{},{} +{},{} @@\n",
+        start + 1,
+        old_end - start,
+        start + 1,
+        new_end - start
+    ));
+    diff.extend(old_lines[start..prefix].iter().map(|text| line(' ', text)));
+    diff.extend(
+        old_lines[prefix..old_lines.len() - suffix]
+            .iter()
+            .map(|text| line('-', text)),
+    );
+    diff.extend(
+        new_lines[prefix..new_lines.len() - suffix]
+            .iter()
+            .map(|text| line('+', text)),
+    );
+    diff.extend(
+        old_lines[old_lines.len() - suffix..old_end]
+            .iter()
+            .map(|text| line(' ', text)),
+    );
+    diff
+}
+
+// A patch of every file in `changes` (path, suggestions), in the mbox format of `git format-patch`
+// so `git am` turns it into a commit.
+pub fn suggestion_patch(
+    site_root: &Path,
+    subject: &str,
+    author: &str,
+    changes: &[(String, Vec<(&str, &str)>)],
+) -> Result<String> {
+    let mut diffs = vec![];
+    for (path, suggestions) in changes {
+        let old = read_to_string(site_root.join(path))?;
+        let new = apply_suggestions(&old, suggestions).map_err(|missing| {
+            Report::msg(format!(
+                "{path} changed since, no longer has: {}",
+                missing
+                    .iter()
+                    .map(|excerpt| format!("\"{excerpt}\""))
+                    .join(", ")
+            ))
+        })?;
+        diffs.push(unified_diff(path, &old, &new));
+    }
+    Ok(format!(
+        "From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001\nFrom: {author}\nDate: {}\nSubject: [PATCH] {subject}\n\n---\n{}",
+        chrono::Utc::now().to_rfc2822(),
+        diffs.concat()
+    ))
+}
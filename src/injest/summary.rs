@@ -0,0 +1,204 @@
+use color_eyre::Result;
+use lol_html::html_content::ContentType;
+use lol_html::{comments, element, rewrite_str, text, Settings};
+use pulldown_cmark::{Event, Parser, Tag};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use words_count::WordsCount;
+
+// `<!-- more -->`, everything before it is the summary
+pub const MORE_MARKER: &str = "more";
+
+pub const DEFAULT_SUMMARY_LENGTH: usize = 200;
+
+// never part of a summary, cutting into them makes no sense and neither does a summary of them
+const SKIPPED: &str = "pre, script, style, table, figure, h1, h2, h3, h4, h5, h6, hr, math, \
+    .math, .footnote-definition, .footnote-reference, .footnotes";
+
+// what templates get as `content.summary_html` and `content.summary_text`, and what feeds use
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Summary {
+    pub html: String,
+    pub text: String,
+    // cut at <!-- more --> and not by length
+    pub explicit: bool,
+}
+
+struct Cut {
+    length: usize,
+    limit: usize,
+    in_math: bool,
+    done: bool,
+    text: String,
+}
+
+impl Cut {
+    // where in `chunk` the summary ends, if it does. The first sentence end after the limit,
+    // outside of $math$, or the first space after twice the limit for text that won't end.
+    fn end(&mut self, chunk: &str) -> Option<(usize, bool)> {
+        for (at, c) in chunk.char_indices() {
+            if c == '$' {
+                self.in_math = !self.in_math;
+            }
+            self.length += 1;
+            if self.length < self.limit || self.in_math {
+                continue;
+            }
+
+            let end = at + c.len_utf8();
+            let followed_by_space = chunk[end..]
+                .chars()
+                .next()
+                .map_or(true, char::is_whitespace);
+            match c {
+                '.' | '!' | '?' if followed_by_space => return Some((end, false)),
+                '。' | '！' | '？' => return Some((end, false)),
+                c if c.is_whitespace() && self.length >= self.limit * 2 => return Some((at, true)),
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// The rendered content up to <!-- more --> if there is one, without code, math blocks,
+// footnotes and headings.
+fn summary_source(html: &str) -> Result<(String, bool)> {
+    let found = RefCell::new(false);
+    let source = rewrite_str(
+        html,
+        Settings {
+            element_content_handlers: vec![
+                element!(SKIPPED, |el| {
+                    el.remove();
+                    Ok(())
+                }),
+                element!("*", |el| {
+                    if *found.borrow() {
+                        el.remove();
+                    }
+                    Ok(())
+                }),
+                comments!("*", |comment| {
+                    if comment.text().trim() == MORE_MARKER {
+                        *found.borrow_mut() = true;
+                    }
+                    comment.remove();
+                    Ok(())
+                }),
+                text!("*", |chunk| {
+                    if *found.borrow() {
+                        chunk.remove();
+                    }
+                    Ok(())
+                }),
+            ],
+            ..Settings::default()
+        },
+    )?;
+    Ok((source, found.into_inner()))
+}
+
+// A summary of the rendered content of a page that ends at a sentence and never inside an
+// element, as html and as plain text.
+pub fn summarize(html: &str, length: usize) -> Result<Summary> {
+    let (source, explicit) = summary_source(html)?;
+    let cut = RefCell::new(Cut {
+        length: 0,
+        limit: match explicit {
+            true => usize::MAX,
+            false => length,
+        },
+        in_math: false,
+        done: false,
+        text: String::new(),
+    });
+
+    let summary = rewrite_str(
+        &source,
+        Settings {
+            element_content_handlers: vec![
+                element!("*", |el| {
+                    if cut.borrow().done {
+                        el.remove();
+                    }
+                    Ok(())
+                }),
+                text!("*", |chunk| {
+                    let mut cut = cut.borrow_mut();
+                    if cut.done {
+                        chunk.remove();
+                        return Ok(());
+                    }
+                    let text = chunk.as_str().to_string();
+                    match cut.end(&text) {
+                        Some((end, hard)) => {
+                            let kept = match hard {
+                                true => format!("{}…", &text[..end]),
+                                false => text[..end].to_string(),
+                            };
+                            cut.text.push_str(&kept);
+                            cut.done = true;
+                            chunk.replace(&kept, ContentType::Html);
+                        }
+                        None => cut.text.push_str(&text),
+                    }
+                    Ok(())
+                }),
+            ],
+            ..Settings::default()
+        },
+    )?;
+
+    let text = cut.into_inner().text;
+    Ok(Summary {
+        html: summary.trim().to_string(),
+        text: collapse_whitespace(&html_escape::decode_html_entities(&text)),
+        explicit,
+    })
+}
+
+// `$x$` and `$$…$$` become a single word each
+fn mask_math(text: &str) -> String {
+    let mut masked = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        let delimiter = match rest[start..].starts_with("$$") {
+            true => "$$",
+            false => "$",
+        };
+        let after = &rest[start + delimiter.len()..];
+        match after.find(delimiter) {
+            Some(end) => {
+                masked.push_str(&rest[..start]);
+                masked.push_str(" math ");
+                rest = &after[end + delimiter.len()..];
+            }
+            None => break,
+        }
+    }
+    masked.push_str(rest);
+    masked
+}
+
+// Word counts of what a reader reads: code blocks left out, inline code and every math span one
+// word each.
+pub fn prose_count(markup: &str) -> WordsCount {
+    let mut prose = String::with_capacity(markup.len());
+    let mut in_code_block = false;
+    for event in Parser::new(markup) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+            Event::Text(text) if !in_code_block => prose.push_str(&text),
+            Event::Code(_) => prose.push_str(" code "),
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => prose.push(' '),
+            _ => {}
+        }
+    }
+    words_count::count(mask_math(&prose))
+}
@@ -0,0 +1,143 @@
+use color_eyre::Result;
+use lol_html::{comments, element, rewrite_str, text, Settings};
+use serde::{Deserialize, Serialize};
+
+// `[build.svg]` in site.toml
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SvgOptions {
+    #[serde(default = "default_true")]
+    pub sanitize: bool,
+    #[serde(default = "default_true")]
+    pub minify: bool,
+    // path prefixes (relative to the content repo) of svgs that are kept as they are, scripts and
+    // all. `diagrams` trusts the output of the diagram renderers.
+    #[serde(default)]
+    pub trusted: Vec<String>,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        SvgOptions {
+            sanitize: true,
+            minify: true,
+            trusted: vec![],
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+pub const TRUSTED_DIAGRAMS: &str = "diagrams";
+
+impl SvgOptions {
+    pub fn is_trusted(&self, source: &str) -> bool {
+        self.trusted.iter().any(|trusted| {
+            source
+                .trim_start_matches('/')
+                .starts_with(trusted.trim_start_matches('/'))
+        })
+    }
+
+    // sanitized and minified as configured, `source` is checked against the allowlist
+    pub fn process(&self, svg: &str, source: &str) -> Result<String> {
+        if self.is_trusted(source) {
+            return Ok(svg.to_string());
+        }
+        let svg = match self.sanitize {
+            true => sanitize_svg(svg)?,
+            false => svg.to_string(),
+        };
+        match self.minify {
+            true => minify_svg(&svg),
+            false => Ok(svg),
+        }
+    }
+}
+
+// only references inside the document or embedded data may stay
+fn is_local_reference(link: &str) -> bool {
+    let link = link.trim();
+    link.starts_with('#') || link.starts_with("data:image/")
+}
+
+// Removes everything that can run code or pull something in from elsewhere: scripts, foreign
+// objects, event handler attributes, javascript: links and references to other documents.
+pub fn sanitize_svg(svg: &str) -> Result<String> {
+    let settings = Settings {
+        element_content_handlers: vec![
+            element!("script, foreignObject, iframe, embed, object", |el| {
+                el.remove();
+                Ok(())
+            }),
+            element!("*", |el| {
+                let names = el
+                    .attributes()
+                    .iter()
+                    .map(|attribute| attribute.name())
+                    .collect::<Vec<_>>();
+                for name in names {
+                    let value = el.get_attribute(&name).unwrap_or_default();
+                    let lowered = value.trim().to_ascii_lowercase();
+                    let dangerous = name.starts_with("on")
+                        || lowered.starts_with("javascript:")
+                        || (matches!(name.as_str(), "href" | "xlink:href" | "src")
+                            && !is_local_reference(&value))
+                        || lowered.contains("url(http")
+                        || lowered.contains("url(//");
+                    if dangerous {
+                        el.remove_attribute(&name);
+                    }
+                }
+                Ok(())
+            }),
+            // @import and remote urls are the only ways out of a stylesheet
+            text!("style", |txt| {
+                let lowered = txt.as_str().to_ascii_lowercase();
+                if lowered.contains("@import")
+                    || lowered.contains("url(http")
+                    || lowered.contains("url(//")
+                {
+                    txt.remove();
+                }
+                Ok(())
+            }),
+        ],
+        ..Settings::default()
+    };
+    Ok(rewrite_str(svg, settings)?)
+}
+
+// drops comments, metadata, the xml prolog and whitespace between elements
+pub fn minify_svg(svg: &str) -> Result<String> {
+    let settings = Settings {
+        element_content_handlers: vec![
+            comments!("*", |comment| {
+                comment.remove();
+                Ok(())
+            }),
+            element!("metadata", |el| {
+                el.remove();
+                Ok(())
+            }),
+            text!(
+                "svg, g, defs, symbol, clipPath, mask, pattern, linearGradient, radialGradient",
+                |txt| {
+                    if txt.as_str().trim().is_empty() {
+                        txt.remove();
+                    }
+                    Ok(())
+                }
+            ),
+        ],
+        ..Settings::default()
+    };
+
+    // the prolog parses as a comment outside of any element, cut it off here instead
+    let svg = match svg.find("<svg") {
+        Some(start) => &svg[start..],
+        None => svg,
+    };
+    Ok(rewrite_str(svg, settings)?.trim().to_string())
+}
@@ -0,0 +1,128 @@
+use crate::injest::report::BuildReport;
+use color_eyre::{Report, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+// `[tags]` in site.toml, the one spelling of a tag every other spelling becomes
+//
+// [tags]
+// file = "tags.toml"
+//
+// [tags.aliases]
+// rust = ["rustlang", "rust-lang"]
+// "c++" = ["cpp", "cplusplus"]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagOptions {
+    // canonical tag -> its aliases
+    #[serde(default)]
+    pub aliases: BTreeMap<String, Vec<String>>,
+    // toml in the content repo with more aliases, the same shape as `aliases`
+    pub file: Option<String>,
+}
+
+// what two spellings of one tag have in common, `Rust-Lang` and `rust_lang` are both `rustlang`
+fn fold(tag: &str) -> String {
+    tag.trim()
+        .chars()
+        .filter(|c| !matches!(c, '-' | '_' | '.') && !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// Every alias and canonical tag, folded, to the canonical tag. A tag that isn't in it is kept the
+// way it was written.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TagMap {
+    canonical: HashMap<String, String>,
+}
+
+impl TagMap {
+    // an alias given to two tags is reported against `site_file` and goes to the first of them
+    pub fn load(
+        options: &TagOptions,
+        site_root: &Path,
+        site_file: &Path,
+        report: &mut BuildReport,
+    ) -> Result<TagMap> {
+        let mut aliases = options.aliases.clone();
+        if let Some(file) = &options.file {
+            let extra = read_to_string(site_root.join(file))
+                .map_err(|why| Report::msg(format!("tag aliases {file}: {why}")))?;
+            for (tag, more) in toml::from_str::<BTreeMap<String, Vec<String>>>(&extra)? {
+                aliases.entry(tag).or_default().extend(more);
+            }
+        }
+
+        let mut canonical = HashMap::new();
+        for tag in aliases.keys() {
+            canonical.insert(fold(tag), tag.clone());
+        }
+        for (tag, tag_aliases) in &aliases {
+            for alias in tag_aliases {
+                match canonical.get(&fold(alias)) {
+                    Some(other) if other != tag => report.error(
+                        site_file,
+                        format!("tag alias \"{alias}\" is for both \"{other}\" and \"{tag}\""),
+                    ),
+                    Some(_) => {}
+                    None => {
+                        canonical.insert(fold(alias), tag.clone());
+                    }
+                }
+            }
+        }
+        Ok(TagMap { canonical })
+    }
+
+    pub fn canonical(&self, tag: &str) -> String {
+        self.canonical
+            .get(&fold(tag))
+            .cloned()
+            .unwrap_or_else(|| tag.trim().to_string())
+    }
+
+    // in the order written, a tag given twice under different spellings only once
+    pub fn canonical_list(&self, tags: &[String]) -> Vec<String> {
+        let mut canonical = Vec::with_capacity(tags.len());
+        for tag in tags.iter().map(|tag| self.canonical(tag)) {
+            if !tag.is_empty() && !canonical.contains(&tag) {
+                canonical.push(tag);
+            }
+        }
+        canonical
+    }
+}
+
+// Tags that are still spelled more than one way after aliasing, `Rust` on one page and `rust` on
+// another, each with the pages that use it. `uses` is canonical tag -> pages.
+pub fn report_near_duplicates(
+    uses: &BTreeMap<String, BTreeSet<PathBuf>>,
+    site_file: &Path,
+    report: &mut BuildReport,
+) {
+    let mut spellings: BTreeMap<String, Vec<&String>> = BTreeMap::new();
+    for tag in uses.keys() {
+        spellings.entry(fold(tag)).or_default().push(tag);
+    }
+    for tags in spellings.values().filter(|tags| tags.len() > 1) {
+        let listed = tags
+            .iter()
+            .map(|tag| {
+                let pages = uses[*tag]
+                    .iter()
+                    .map(|page| page.display().to_string())
+                    .collect::<Vec<_>>();
+                format!("\"{tag}\" ({})", pages.join(", "))
+            })
+            .collect::<Vec<_>>();
+        report.warn(
+            site_file,
+            format!(
+                "tags {} look like one tag, add the others as aliases of one under [tags.aliases]",
+                listed.join(", ")
+            ),
+        );
+    }
+}
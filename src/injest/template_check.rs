@@ -0,0 +1,282 @@
+use crate::injest::{
+    context_schema::context_schema,
+    fragment::source_template,
+    report::BuildReport,
+    search::edit_distance,
+    templates::{SiteTheme, THEME_FILE},
+};
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tera::ast::{Expr, ExprVal, MacroDefinition, Node};
+use tera::{Template, Tera};
+
+// what moklog puts into a page's context, anything else a template reads is its own
+const NAMESPACES: &[&str] = &[
+    "page", "content", "site", "auto", "custom", "request", "viewer",
+];
+
+// `[build] template_variables`, off unless asked for
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TemplateVariables {
+    #[default]
+    Off,
+    // problems are warnings
+    Warn,
+    // problems are errors and fail the build
+    Strict,
+}
+
+// what one template reads, and the names it binds itself
+#[derive(Default)]
+struct Usage {
+    // as written, up to the first `[`
+    read: BTreeSet<String>,
+    // `set`, `for` and macro arguments
+    bound: BTreeSet<String>,
+}
+
+impl Usage {
+    fn ident(&mut self, ident: &str) {
+        let ident = ident.split('[').next().unwrap_or_default();
+        self.read.insert(ident.to_string());
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        self.value(&expr.val);
+        for filter in &expr.filters {
+            filter.args.values().for_each(|arg| self.expr(arg));
+        }
+    }
+
+    fn value(&mut self, value: &ExprVal) {
+        match value {
+            ExprVal::Ident(ident) => self.ident(ident),
+            ExprVal::Math(math) => {
+                self.expr(&math.lhs);
+                self.expr(&math.rhs);
+            }
+            ExprVal::Logic(logic) => {
+                self.expr(&logic.lhs);
+                self.expr(&logic.rhs);
+            }
+            ExprVal::Test(test) => {
+                self.ident(&test.ident);
+                test.args.iter().for_each(|arg| self.expr(arg));
+            }
+            ExprVal::MacroCall(call) => call.args.values().for_each(|arg| self.expr(arg)),
+            ExprVal::FunctionCall(call) => call.args.values().for_each(|arg| self.expr(arg)),
+            ExprVal::Array(values) => values.iter().for_each(|value| self.expr(value)),
+            ExprVal::StringConcat(concat) => {
+                concat.values.iter().for_each(|value| self.value(value))
+            }
+            ExprVal::In(within) => {
+                self.expr(&within.lhs);
+                self.expr(&within.rhs);
+            }
+            _ => {}
+        }
+    }
+
+    fn macro_definition(&mut self, definition: &MacroDefinition) {
+        self.bound.extend(definition.args.keys().cloned());
+        definition
+            .args
+            .values()
+            .flatten()
+            .for_each(|arg| self.expr(arg));
+        self.nodes(&definition.body);
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            match node {
+                Node::VariableBlock(_, expr) => self.expr(expr),
+                Node::Set(_, set) => {
+                    self.bound.insert(set.key.clone());
+                    self.expr(&set.value);
+                }
+                Node::FilterSection(_, section, _) => {
+                    section.filter.args.values().for_each(|arg| self.expr(arg));
+                    self.nodes(&section.body);
+                }
+                Node::Block(_, block, _) => self.nodes(&block.body),
+                Node::Forloop(_, forloop, _) => {
+                    self.bound.extend(forloop.key.iter().cloned());
+                    self.bound.insert(forloop.value.clone());
+                    self.expr(&forloop.container);
+                    self.nodes(&forloop.body);
+                    if let Some(empty) = &forloop.empty_body {
+                        self.nodes(empty);
+                    }
+                }
+                Node::If(condition, _) => {
+                    for (_, expr, body) in &condition.conditions {
+                        self.expr(expr);
+                        self.nodes(body);
+                    }
+                    if let Some((_, body)) = &condition.otherwise {
+                        self.nodes(body);
+                    }
+                }
+                Node::MacroDefinition(_, definition, _) => self.macro_definition(definition),
+                _ => {}
+            }
+        }
+    }
+
+    fn template(template: &Template) -> Usage {
+        let mut usage = Usage::default();
+        usage.nodes(&template.ast);
+        template
+            .macros
+            .values()
+            .for_each(|definition| usage.macro_definition(definition));
+        usage
+    }
+
+    // what it reads out of moklog's namespaces, without what it shadows
+    fn injected(&self) -> impl Iterator<Item = &String> {
+        self.read.iter().filter(|ident| {
+            let root = ident.split('.').next().unwrap_or_default();
+            NAMESPACES.contains(&root) && !self.bound.contains(root)
+        })
+    }
+}
+
+// a schema with its `$ref`s followed and its `anyOf`s (what an Option is) flattened
+fn branches<'a>(definitions: &'a Value, schema: &'a Value, into: &mut Vec<&'a Value>) {
+    if let Some(name) = schema["$ref"]
+        .as_str()
+        .and_then(|reference| reference.strip_prefix("#/definitions/"))
+    {
+        return branches(definitions, &definitions[name], into);
+    }
+    let mut alternatives = ["anyOf", "oneOf", "allOf"]
+        .into_iter()
+        .filter_map(|key| schema[key].as_array())
+        .flatten()
+        .peekable();
+    if alternatives.peek().is_none() {
+        into.push(schema);
+    }
+    for alternative in alternatives {
+        branches(definitions, alternative, into);
+    }
+}
+
+// Whether the schema has `ident` (`page.previous.title`). Err has the segment it stops matching
+// at, and the names there are in its place.
+fn resolve(schema: &Value, ident: &str) -> Result<(), (usize, Vec<String>)> {
+    let definitions = &schema["definitions"];
+    let mut at = vec![schema];
+    for (depth, segment) in ident.split('.').enumerate() {
+        let mut schemas = vec![];
+        for schema in at {
+            branches(definitions, schema, &mut schemas);
+        }
+        let mut next = vec![];
+        let mut names = vec![];
+        for schema in schemas {
+            match (schema["properties"].as_object(), &schema["items"]) {
+                (Some(properties), _) => match properties.get(segment) {
+                    Some(property) => next.push(property),
+                    None => names.extend(properties.keys().cloned()),
+                },
+                (None, Value::Object(_)) if segment.parse::<usize>().is_ok() => {
+                    next.push(&schema["items"])
+                }
+                // a map, `custom` or `site.theme` without a theme to list its options
+                (None, _) if schema["type"] == "object" => return Ok(()),
+                _ => {}
+            }
+        }
+        if next.is_empty() {
+            return Err((depth, names));
+        }
+        at = next;
+    }
+    Ok(())
+}
+
+// `ident` with the segment at `depth` swapped for the closest of `names`, if one is close
+fn did_you_mean(ident: &str, depth: usize, names: &[String]) -> Option<String> {
+    let mut segments = ident.split('.').collect::<Vec<_>>();
+    let close = names
+        .iter()
+        .map(|name| (edit_distance(name, segments[depth]), name))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()?
+        .1;
+    segments[depth] = close;
+    Some(segments.join("."))
+}
+
+// the variables a template reads and doesn't set itself, `page` for `page.title`
+pub fn template_reads(tera: &Tera, name: &str) -> BTreeSet<String> {
+    let usage = match tera.templates.get(name) {
+        Some(template) => Usage::template(template),
+        None => return BTreeSet::new(),
+    };
+    usage
+        .read
+        .iter()
+        .filter_map(|ident| ident.split('.').next())
+        .filter(|root| !root.is_empty() && !usage.bound.contains(*root))
+        .map(ToString::to_string)
+        .collect()
+}
+
+// Variables the theme's templates read that moklog never sets, `page.titel` rendering as nothing,
+// and options the theme declares in theme.toml that no template reads.
+pub fn check_template_variables(
+    theme: &SiteTheme,
+    tera: &Tera,
+    mode: TemplateVariables,
+    report: &mut BuildReport,
+) -> Result<()> {
+    if mode == TemplateVariables::Off {
+        return Ok(());
+    }
+    let schema = context_schema(Some(&theme.metadata))?;
+    let mut problem = |path: PathBuf, message: String| match mode {
+        TemplateVariables::Strict => report.error(path, message),
+        _ => report.warn(path, message),
+    };
+
+    let mut options_read = BTreeSet::new();
+    let mut names = tera.get_template_names().collect::<Vec<_>>();
+    names.sort_unstable();
+    for name in names {
+        let usage = Usage::template(&tera.templates[name]);
+
+        for ident in usage.injected() {
+            if let Some(option) = ident.strip_prefix("site.theme.") {
+                options_read.insert(option.split('.').next().unwrap_or_default().to_string());
+            } else if ["site", "site.theme"].contains(&ident.as_str()) {
+                // handed on whole, to a macro or a `set`
+                options_read.extend(theme.metadata.options.keys().cloned());
+            }
+            if let Err((depth, names)) = resolve(&schema, ident) {
+                let message = match did_you_mean(ident, depth, &names) {
+                    Some(close) => format!("{ident} is never set, did you mean {close}?"),
+                    None => format!("{ident} is never set, see `moklog theme schema`"),
+                };
+                problem(Path::new("templates").join(source_template(name)), message);
+            }
+        }
+    }
+
+    for option in theme.metadata.options.keys() {
+        if !options_read.contains(option) {
+            problem(
+                PathBuf::from(THEME_FILE),
+                format!("the theme option {option} is never read by a template"),
+            );
+        }
+    }
+    Ok(())
+}
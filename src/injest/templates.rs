@@ -1,23 +1,27 @@
 use crate::injest::{
+    bundle::BundleSpec,
     path_relativizie,
+    picture::{ColorSchemes, COLOR_SCHEME_STYLESHEET},
     static_file::{StaticFile},
     stylesheet::{compile_sass, optimize_css},
 };
 use color_eyre::Result;
 use dashmap::DashMap;
 use ignore::WalkBuilder;
-use memmap2::Mmap;
 use minify_js::TopLevelMode;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
-use std::str::pattern::Pattern;
+use std::path::Path;
 use std::sync::Arc;
 use tera::Tera;
-use tokio::{fs::File, io::AsyncReadExt};
+use tokio::{
+    fs::{read, read_to_string, File},
+    io::AsyncReadExt,
+};
 use tracing::warn;
 use crate::injest::static_file::process_static_file;
-use crate::{mmap_load, walker};
+use crate::walker;
 
 pub struct SiteTheme {
     pub metadata: SiteThemeMetadata,
@@ -26,6 +30,7 @@ pub struct SiteTheme {
     pub functions: Arc<DashMap<String, String>>,
     pub filters: Arc<DashMap<String, String>>,
     pub testers: Arc<DashMap<String, String>>,
+    pub comparators: Arc<DashMap<String, String>>,
     pub styles: Arc<DashMap<String, String>>,
     pub js_scripts: Arc<DashMap<String, String>>,
     pub files: Arc<DashMap<u64, StaticFile>>,
@@ -40,6 +45,7 @@ impl From<SerializeSiteTheme> for SiteTheme {
             functions: Arc::new(sst.functions.into_iter().collect()),
             filters: Arc::new(sst.filters.into_iter().collect()),
             testers: Arc::new(sst.testers.into_iter().collect()),
+            comparators: Arc::new(sst.comparators.into_iter().collect()),
             styles: Arc::new(sst.styles.into_iter().collect()),
             js_scripts: Arc::new(sst.js_scripts.into_iter().collect()),
             files: Arc::new(sst.files.into_iter().collect()),
@@ -55,6 +61,8 @@ struct SerializeSiteTheme {
     pub functions: BTreeMap<String, String>,
     pub filters: BTreeMap<String, String>,
     pub testers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub comparators: BTreeMap<String, String>,
     pub styles: BTreeMap<String, String>,
     pub js_scripts: BTreeMap<String, String>,
     pub files: BTreeMap<u64, StaticFile>,
@@ -69,6 +77,7 @@ impl From<SiteTheme> for SerializeSiteTheme {
             functions: st.functions.into_iter().collect(),
             filters: st.filters.into_iter().collect(),
             testers: Default::default(),
+            comparators: st.comparators.into_iter().collect(),
             styles: st.styles.into_iter().collect(),
             js_scripts: st.js_scripts.into_iter().collect(),
             files: st.files.into_iter().collect(),
@@ -76,25 +85,44 @@ impl From<SiteTheme> for SerializeSiteTheme {
     }
 }
 
+// at the root of a theme directory, the metadata below
+pub const THEME_FILE: &str = "theme.toml";
+
 #[derive(Serialize, Deserialize)]
 pub struct SiteThemeMetadata {
     pub authors: Vec<String>,
     pub name: String,
     pub link: String,
     pub version: Version,
+    #[serde(default)]
+    pub options: BTreeMap<String, ThemeOption>,
+    // template name -> the styles and scripts it needs
+    #[serde(default)]
+    pub bundles: BTreeMap<String, BundleSpec>,
+    #[serde(default)]
+    pub colors: ColorSchemes,
+}
+
+// an option the theme reads from `site.theme.<name>`, set by the site under `[theme]` in site.toml
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ThemeOption {
+    pub description: Option<String>,
+    pub default: Option<toml::Value>,
+    #[serde(default)]
+    pub required: bool,
 }
 
 pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme> {
     macro_rules! template_dir {
         ($path:expr) => {
-            format!("{template_dir}/{}", $path)
+            Path::new(template_dir.as_ref()).join($path)
         };
     }
 
     // template metadata
 
     let mut template_metadata = String::new();
-    File::open(TEMPLATE_DIR)
+    File::open(template_dir!(THEME_FILE))
         .await?
         .read_to_string(&mut template_metadata)
         .await?;
@@ -105,13 +133,12 @@ pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme
     let mut shortcode = DashMap::new();
     // verify in tera
     {
-        let mut tera = Tera::new(&template_dir!("shortcodes"))?;
+        let mut tera = Tera::new(&template_dir!("shortcodes").to_string_lossy())?;
         tera.add_template_files(template_files.into_iter())?;
     }
-    for shrtcde in walker!(template_dir, "shortcodes") {
+    for shrtcde in walker!(template_dir!("shortcodes")).build() {
         let shrtcde = shrtcde?;
-        let file_name =
-            path_relativizie(template_dir!(template_dir, "shortcodes"), shrtcde.path())?;
+        let file_name = path_relativizie(template_dir!("shortcodes"), shrtcde.path())?;
         let mut short_code = String::new();
         File::open(shrtcde.path())
             .await?
@@ -123,7 +150,7 @@ pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme
     // add tera templates
 
     let mut template_files = vec![];
-    for template_entry in walker!(template_dir, "templates") {
+    for template_entry in walker!(template_dir!("templates")).build() {
         let template_entry = template_entry?;
         let file_extension = template_entry
             .path()
@@ -131,10 +158,7 @@ pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme
             .unwrap_or_default()
             .to_str()
             .unwrap_or_default();
-        let file_name = path_relativizie(
-            template_dir!(template_dir, "templates"),
-            template_entry.path(),
-        )?;
+        let file_name = path_relativizie(template_dir!("templates"), template_entry.path())?;
         if file_extension != "html" || file_extension != "tera" {
             continue;
         }
@@ -144,7 +168,7 @@ pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme
     // compile scss, css
 
     let mut styles = DashMap::new();
-    for style_entry in walker!(template_dir, "stylesheets") {
+    for style_entry in walker!(template_dir!("stylesheets")).build() {
         let style_entry = style_entry?;
         let file_extension = style_entry
             .path()
@@ -157,28 +181,32 @@ pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme
             continue;
         }
 
-        let file_name = path_relativizie(
-            template_dir!(template_dir, "stylesheets"),
-            style_entry.path(),
-        )?;
+        let file_name = path_relativizie(template_dir!("stylesheets"), style_entry.path())?;
 
         if file_extension == "css" {
-            let memmap = unsafe { Mmap::map(style_entry.path())? }.to_str()?;
-            let optimized = optimize_css(memmap).await?;
+            let stylesheet = read_to_string(style_entry.path()).await?;
+            let optimized = optimize_css(&stylesheet).await?;
             styles.insert(file_name, optimized);
         } else if file_extension == "scss" {
-            let memmap = unsafe { Mmap::map(style_entry.path())? };
-            let compiled = compile_sass(memmap.as_ref()).await?;
+            let stylesheet = read(style_entry.path()).await?;
+            let compiled = compile_sass(&stylesheet).await?;
             let optimized = optimize_css(&compiled).await?;
             styles.insert(file_name, optimized);
         }
     }
 
+    // light/dark custom properties
+
+    if !metadata.colors.is_empty() {
+        let optimized = optimize_css(&metadata.colors.to_css()).await?;
+        styles.insert(COLOR_SCHEME_STYLESHEET.to_string(), optimized);
+    }
+
     // minify JS
 
     let mut js_scripts = DashMap::new();
     let session = minify_js::Session::new();
-    for script_entry in walker!(template_dir, "scripts") {
+    for script_entry in walker!(template_dir!("scripts")).build() {
         let script_entry = script_entry?;
         let file_extension = script_entry
             .path()
@@ -190,12 +218,11 @@ pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme
         if file_length == 0 {
             continue;
         }
-        let file_name =
-            path_relativizie(template_dir!(template_dir, "scripts"), script_entry.path())?;
+        let file_name = path_relativizie(template_dir!("scripts"), script_entry.path())?;
         if file_extension == "js" {
-            let reader = mmap_load!(script_entry.path());
+            let script = read(script_entry.path()).await?;
             let mut out = Vec::new();
-            minify_js::minify(&session, TopLevelMode::Global, &reader, &mut out)?;
+            minify_js::minify(&session, TopLevelMode::Global, &script, &mut out)?;
             js_scripts.insert(file_name, String::from_utf8(out)?);
         }
     }
@@ -203,7 +230,7 @@ pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme
     // load rhai functions
 
     let mut functions = DashMap::new();
-    for func in walker!(template_dir, "functions") {
+    for func in walker!(template_dir!("functions")).build() {
         let func = func?;
         if ft
             .path()
@@ -215,9 +242,9 @@ pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme
         {
             continue;
         }
-        let file_name = path_relativizie(template_dir!(template_dir, "shortcodes"), func.path())?;
-        if file_name.ends_with(".rhai") {
-            file_name.strip_suffix_of(".rhai")
+        let mut file_name = path_relativizie(template_dir!("shortcodes"), func.path())?;
+        if let Some(name) = file_name.strip_suffix(".rhai") {
+            file_name = name.to_string();
         }
         let mut function = String::new();
         File::open(func.path())
@@ -230,7 +257,7 @@ pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme
     // load rhai filters
 
     let mut filters = DashMap::new();
-    for ft in walker!(template_dir, "filters") {
+    for ft in walker!(template_dir!("filters")).build() {
         let ft = ft?;
         if ft
             .path()
@@ -242,9 +269,9 @@ pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme
         {
             continue;
         }
-        let file_name = path_relativizie("filters", ft.path())?;
-        if file_name.ends_with(".rhai") {
-            file_name.strip_suffix_of(".rhai")
+        let mut file_name = path_relativizie(template_dir!("filters"), ft.path())?;
+        if let Some(name) = file_name.strip_suffix(".rhai") {
+            file_name = name.to_string();
         }
         let mut filter = String::new();
         File::open(ft.path())
@@ -256,7 +283,7 @@ pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme
     // load rhai testers
 
     let mut testers = DashMap::new();
-    for ft in walker!(template_dir, "testers") {
+    for ft in walker!(template_dir!("testers")).build() {
         let ft = ft?;
         if ft
             .path()
@@ -268,9 +295,9 @@ pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme
         {
             continue;
         }
-        let mut file_name = path_relativizie("testers", ft.path())?;
-        if file_name.ends_with(".rhai") {
-            file_name.strip_suffix_of(".rhai")
+        let mut file_name = path_relativizie(template_dir!("testers"), ft.path())?;
+        if let Some(name) = file_name.strip_suffix(".rhai") {
+            file_name = name.to_string();
         }
         let mut test = String::new();
         File::open(ft.path())
@@ -280,10 +307,37 @@ pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme
         testers.insert(file_name, test);
     }
 
+    // load rhai listing comparators
+
+    let mut comparators = DashMap::new();
+    for cmp in walker!(template_dir!("comparators")).build() {
+        let cmp = cmp?;
+        if cmp
+            .path()
+            .extension()
+            .unwrap_or_default()
+            .to_str()
+            .unwrap_or_default()
+            != "rhai"
+        {
+            continue;
+        }
+        let mut file_name = path_relativizie(template_dir!("comparators"), cmp.path())?;
+        if let Some(name) = file_name.strip_suffix(".rhai") {
+            file_name = name.to_string();
+        }
+        let mut comparator = String::new();
+        File::open(cmp.path())
+            .await?
+            .read_to_string(&mut comparator)
+            .await?;
+        comparators.insert(file_name, comparator);
+    }
+
     // load static files
 
     let mut files = DashMap::new();
-    for file in walker!(template_dir, "static") {
+    for file in walker!(template_dir!("static")).build() {
         let file = file?;
         match process_static_file(file) {
             Some(file) => {
@@ -305,5 +359,6 @@ pub async fn build_site_theme(template_dir: impl AsRef<str>) -> Result<SiteTheme
         js_scripts: Arc::new(js_scripts),
         files: Arc::new(files),
         testers: Arc::new(testers),
+        comparators: Arc::new(comparators),
     })
 }
@@ -0,0 +1,222 @@
+use crate::injest::{template_check::template_reads, templates::SiteTheme};
+use color_eyre::Result;
+use dashmap::DashMap;
+use html_escape::encode_text;
+use rhai::Engine;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tera::Tera;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThemeItemKind {
+    Shortcode,
+    Filter,
+    Tester,
+    Function,
+}
+
+impl ThemeItemKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThemeItemKind::Shortcode => "shortcode",
+            ThemeItemKind::Filter => "filter",
+            ThemeItemKind::Tester => "tester",
+            ThemeItemKind::Function => "function",
+        }
+    }
+
+    // the function of the script tera calls, and which of its parameters has the arguments
+    fn entry(&self) -> Option<(&'static str, usize)> {
+        match self {
+            ThemeItemKind::Shortcode => None,
+            ThemeItemKind::Filter => Some(("filter", 1)),
+            ThemeItemKind::Tester => Some(("test", 1)),
+            ThemeItemKind::Function => Some(("main", 0)),
+        }
+    }
+}
+
+// How often each of the theme's scripts ran in one build, the same counters they're handed as
+// `times`. A build starts with its own, so what a script sees doesn't depend on earlier builds.
+#[derive(Clone, Debug, Default)]
+pub struct ThemeCalls {
+    counters: Arc<DashMap<(ThemeItemKind, String), Arc<AtomicU64>>>,
+}
+
+impl ThemeCalls {
+    pub fn counter(&self, kind: ThemeItemKind, name: &str) -> Arc<AtomicU64> {
+        self.counters
+            .entry((kind, name.to_string()))
+            .or_default()
+            .clone()
+    }
+
+    pub fn count(&self, kind: ThemeItemKind, name: &str) -> u64 {
+        self.counters
+            .get(&(kind, name.to_string()))
+            .map_or(0, |counter| counter.load(Ordering::SeqCst))
+    }
+}
+
+// one shortcode, filter, tester or function of the theme
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ThemeItem {
+    pub kind: ThemeItemKind,
+    pub name: String,
+    pub source: String,
+    // the arguments it reads, `args.name` or `args["name"]` in a script, `[0]` for a tester's
+    // positional ones, the variables of a shortcode
+    pub arguments: Vec<String>,
+    pub calls: u64,
+    // why the arguments couldn't be read, a script that doesn't compile
+    pub problem: Option<String>,
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// what `source` reads out of the variable `param`, as written after it
+fn keys_read(source: &str, param: &str) -> BTreeSet<String> {
+    let mut keys = BTreeSet::new();
+    for (start, _) in source.match_indices(param) {
+        let before = source[..start].chars().next_back();
+        let rest = &source[start + param.len()..];
+        if before.map_or(false, is_ident) {
+            continue;
+        }
+        if let Some(rest) = rest.strip_prefix('.') {
+            let key = rest
+                .split(|c: char| !is_ident(c))
+                .next()
+                .unwrap_or_default();
+            // `args.len()` is a method, not an argument
+            if !key.is_empty() && !rest[key.len()..].starts_with('(') {
+                keys.insert(key.to_string());
+            }
+        } else if let Some(rest) = rest.strip_prefix('[') {
+            let index = rest.split(']').next().unwrap_or_default().trim();
+            let key = index.trim_matches(|c| c == '"' || c == '\'');
+            match index.parse::<usize>() {
+                Ok(position) => keys.insert(format!("[{position}]")),
+                Err(_) if key.len() + 2 == index.len() && !key.is_empty() => {
+                    keys.insert(key.to_string())
+                }
+                // a variable as the key, there's no telling which
+                Err(_) => false,
+            };
+        }
+    }
+    keys
+}
+
+fn script_arguments(kind: ThemeItemKind, source: &str) -> Result<Vec<String>> {
+    let (entry, position) = match kind.entry() {
+        Some(entry) => entry,
+        None => return Ok(vec![]),
+    };
+    let ast = Engine::new().compile(source)?;
+    let param = ast
+        .iter_functions()
+        .find(|function| function.name == entry)
+        .and_then(|function| function.params.get(position).map(ToString::to_string));
+    Ok(match param {
+        Some(param) => keys_read(source, &param).into_iter().collect(),
+        None => vec![],
+    })
+}
+
+fn shortcode_arguments(source: &str) -> Result<Vec<String>> {
+    let mut tera = Tera::default();
+    tera.add_raw_template("shortcode", source)?;
+    Ok(template_reads(&tera, "shortcode")
+        .into_iter()
+        // the count the build hands every shortcode
+        .filter(|name| name != "times")
+        .collect())
+}
+
+// everything the theme registers with tera, in the order of the page
+pub fn theme_items(theme: &SiteTheme, calls: &ThemeCalls) -> Vec<ThemeItem> {
+    let sources = [
+        (ThemeItemKind::Shortcode, &theme.shortcode),
+        (ThemeItemKind::Filter, &theme.filters),
+        (ThemeItemKind::Tester, &theme.testers),
+        (ThemeItemKind::Function, &theme.functions),
+    ];
+    let mut items = vec![];
+    for (kind, scripts) in sources {
+        for script in scripts.iter() {
+            let arguments = match kind {
+                ThemeItemKind::Shortcode => shortcode_arguments(script.value()),
+                _ => script_arguments(kind, script.value()),
+            };
+            let (arguments, problem) = match arguments {
+                Ok(arguments) => (arguments, None),
+                Err(why) => (vec![], Some(why.to_string())),
+            };
+            items.push(ThemeItem {
+                kind,
+                name: script.key().clone(),
+                source: script.value().clone(),
+                arguments,
+                calls: calls.count(kind, script.key()),
+                problem,
+            });
+        }
+    }
+    items.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+    items
+}
+
+// The page `/api/admin/theme` shows. Plain html, it isn't the theme's to render.
+pub fn theme_docs_page(theme: &SiteTheme, items: &[ThemeItem]) -> String {
+    let mut page = String::new();
+    let name = encode_text(&theme.metadata.name);
+    let _ = write!(
+        page,
+        r#"<!DOCTYPE html><html lang="en"><head><meta charset="utf-8"><meta name="robots" content="noindex"><title>{name}</title><style>body{{font-family:sans-serif;max-width:60rem;margin:auto;padding:1rem}}pre{{background:#f4f4f4;padding:.5rem;overflow-x:auto}}code{{font-size:.9em}}</style></head><body><h1>{name}</h1><p>calls are counted over the last build</p>"#
+    );
+    let mut kind = None;
+    for item in items {
+        if kind != Some(item.kind) {
+            kind = Some(item.kind);
+            let _ = write!(page, "<h2>{}s</h2>", item.kind.name());
+        }
+        let _ = write!(
+            page,
+            r#"<section id="{}-{}"><h3><code>{}</code></h3><p>{} calls</p>"#,
+            item.kind.name(),
+            html_escape::encode_double_quoted_attribute(&item.name),
+            encode_text(&item.name),
+            item.calls
+        );
+        match (&item.problem, item.arguments.is_empty()) {
+            (Some(problem), _) => {
+                let _ = write!(page, "<p>arguments unknown: {}</p>", encode_text(problem));
+            }
+            (None, true) => page.push_str("<p>no arguments</p>"),
+            (None, false) => {
+                page.push_str("<ul>");
+                for argument in &item.arguments {
+                    let _ = write!(page, "<li><code>{}</code></li>", encode_text(argument));
+                }
+                page.push_str("</ul>");
+            }
+        }
+        let _ = write!(
+            page,
+            "<pre><code>{}</code></pre></section>",
+            encode_text(&item.source)
+        );
+    }
+    if items.is_empty() {
+        page.push_str("<p>the theme registers no shortcodes, filters, testers or functions</p>");
+    }
+    page.push_str("</body></html>");
+    page
+}
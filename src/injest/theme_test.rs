@@ -0,0 +1,478 @@
+use crate::injest::{
+    anchors::page_sections,
+    build::theme_tera,
+    fragment::FragmentCache,
+    generate::insert_nested,
+    print::{PRINT_STYLE, PRINT_TEMPLATE},
+    slug::SlugStrategy,
+    summary::{summarize, DEFAULT_SUMMARY_LENGTH},
+    templates::SiteTheme,
+    theme_docs::ThemeCalls,
+};
+use crate::plugin::rhai::stdlib::{ScriptOptions, ScriptStdlib};
+use chrono::{DateTime, FixedOffset};
+use color_eyre::Result;
+use language_tags::LanguageTag;
+use serde_json::{json, Value};
+use std::fmt::Write;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::{Path, PathBuf};
+use tera::Context;
+
+// expected output of every case, `<theme>/tests/golden/<case>.html`
+pub const GOLDEN_DIR: &str = "tests/golden";
+
+// every page gets generic.html if nothing else, a theme without it can't build anything
+const REQUIRED_TEMPLATE: &str = "generic.html";
+
+// a synthetic page, rendered with the same context keys the build fills in
+pub struct ThemeCase {
+    pub name: &'static str,
+    pub template: &'static str,
+    pub context: Context,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CaseOutcome {
+    // rendered and the same as the golden file
+    Passed,
+    // rendered, there is no golden file to compare with
+    Rendered,
+    // written as the new golden file
+    Blessed,
+    // the theme has no template for this kind of page
+    Skipped,
+    Failed(String),
+    Mismatch { golden: PathBuf, line: usize },
+}
+
+impl CaseOutcome {
+    pub fn is_failure(&self) -> bool {
+        matches!(self, CaseOutcome::Failed(_) | CaseOutcome::Mismatch { .. })
+    }
+}
+
+fn date(date: &str) -> DateTime<FixedOffset> {
+    DateTime::parse_from_rfc3339(date).unwrap()
+}
+
+fn site_context(theme: &SiteTheme, path: &str, language: &str) -> Context {
+    let mut context = Context::new();
+    // the theme's own defaults, and something for every required option it doesn't default
+    let options = theme
+        .metadata
+        .options
+        .iter()
+        .map(|(name, option)| {
+            let value = match &option.default {
+                Some(value) => serde_json::to_value(value).unwrap_or(Value::Null),
+                None => json!(format!("test {name}")),
+            };
+            (name.clone(), value)
+        })
+        .collect::<serde_json::Map<_, _>>();
+
+    insert_nested(&mut context, "site.title", "Theme Test");
+    insert_nested(&mut context, "site.base_url", "https://example.com/");
+    insert_nested(
+        &mut context,
+        "site.description",
+        "A site that only exists to test themes",
+    );
+    insert_nested(&mut context, "site.author", "Test Author");
+    insert_nested(
+        &mut context,
+        "site.social",
+        &json!([{ "name": "mastodon", "url": "https://example.com/@test" }]),
+    );
+    insert_nested(&mut context, "site.theme", &options);
+    insert_nested(
+        &mut context,
+        "site.menu",
+        &json!([{ "label": "Blog", "url": "https://example.com/blog/", "active": path.starts_with("/blog") }]),
+    );
+    insert_nested(
+        &mut context,
+        "page.url",
+        &format!("https://example.com{path}"),
+    );
+    insert_nested(&mut context, "page.language", language);
+    insert_nested(
+        &mut context,
+        "page.base_slug",
+        path.rsplit('/').next().unwrap_or_default(),
+    );
+    insert_nested(&mut context, "page.group", "default");
+    insert_nested(&mut context, "page.translations", &Vec::<String>::new());
+    insert_nested(&mut context, "page.rss_enabled", &true);
+    insert_nested(&mut context, "page.index_enabled", &true);
+    insert_nested(&mut context, "page.template", &Option::<String>::None);
+    insert_nested(
+        &mut context,
+        "page.children_template",
+        &Option::<String>::None,
+    );
+    insert_nested(&mut context, "page.display", "default");
+    insert_nested(&mut context, "page.redirect_from", &Vec::<String>::new());
+    insert_nested(&mut context, "page.redirect_to", &Option::<String>::None);
+    insert_nested(&mut context, "page.pinned", &false);
+    insert_nested(&mut context, "page.previous", &Option::<Value>::None);
+    insert_nested(&mut context, "page.next", &Option::<Value>::None);
+    insert_nested(&mut context, "page.docs", &Option::<Value>::None);
+    insert_nested(&mut context, "page.version", &Option::<Value>::None);
+    insert_nested(
+        &mut context,
+        "page.graph",
+        &json!({ "url": "https://example.com/graph.json", "incoming": 1, "outgoing": 1, "degree": 2 }),
+    );
+    insert_nested(&mut context, "page.ebook", &Option::<Value>::None);
+    insert_nested(&mut context, "page.print", &Option::<String>::None);
+    insert_nested(
+        &mut context,
+        "page.categories",
+        &json!([{ "display": "Blog", "link": "blog", "subcategories": [], "links": [] }]),
+    );
+    insert_nested(
+        &mut context,
+        "page.default_translation",
+        &Option::<Value>::None,
+    );
+    insert_nested(
+        &mut context,
+        "page.this_translation",
+        &Option::<Value>::None,
+    );
+    insert_nested(
+        &mut context,
+        "page.breadcrumbs",
+        &json!([
+            { "title": "Theme Test", "url": "https://example.com/" },
+            { "title": "Blog", "url": "https://example.com/blog/" },
+        ]),
+    );
+    insert_nested(
+        &mut context,
+        "auto.build_time",
+        &date("2023-04-01T00:00:00Z"),
+    );
+    insert_nested(&mut context, "auto.build_init", "theme test");
+    insert_nested(&mut context, "auto.build_id", &0);
+    context
+}
+
+fn content(context: &mut Context, raw: &str, html: &str, table_of_contents: &str) {
+    let words = raw.split_whitespace().count();
+    insert_nested(context, "content.raw", raw);
+    insert_nested(context, "content.html", html);
+    let summary = summarize(html, DEFAULT_SUMMARY_LENGTH).unwrap_or_default();
+    insert_nested(context, "content.summary_html", &summary.html);
+    insert_nested(context, "content.summary_text", &summary.text);
+    insert_nested(context, "content.table_of_contents", table_of_contents);
+    insert_nested(
+        context,
+        "content.sections",
+        &page_sections(html, "https://example.com/blog/hello/").unwrap_or_default(),
+    );
+    insert_nested(
+        context,
+        "content.backlinks",
+        &json!([{ "title": "An older post", "url": "https://example.com/blog/older/" }]),
+    );
+    insert_nested(context, "content.word_count", &words);
+    insert_nested(context, "content.character_count", &raw.chars().count());
+    insert_nested(context, "content.cjk", &0);
+    insert_nested(
+        context,
+        "content.whitespace",
+        &raw.matches(char::is_whitespace).count(),
+    );
+    insert_nested(
+        context,
+        "content.reading_time_seconds",
+        &(words as f64 / 150.0).round(),
+    );
+}
+
+fn article(context: &mut Context, title: &str, tags: &[&str], authors: &[&str]) {
+    insert_nested(context, "page.type", "article");
+    insert_nested(context, "content.title", title);
+    insert_nested(context, "content.tags", tags);
+    insert_nested(context, "content.authors", authors);
+    insert_nested(context, "content.date", &date("2023-03-14T09:00:00+09:00"));
+    insert_nested(
+        context,
+        "content.edited_dates",
+        &[date("2023-03-20T12:00:00+09:00")],
+    );
+    insert_nested(
+        context,
+        "content.summary",
+        "What this article is about, in a sentence.",
+    );
+}
+
+// The corpus every theme is tested against: one page of each kind, plus the edge cases themes
+// tend to break on.
+pub fn corpus(theme: &SiteTheme) -> Vec<ThemeCase> {
+    let body = "<h2 id=\"hello\">Hello</h2><p>Some <em>text</em> and <a href=\"/blog/\">a link</a>.</p><pre><code>fn main() {}</code></pre>";
+    let raw = "## Hello\n\nSome *text* and [a link](/blog/).\n\n```rust\nfn main() {}\n```\n";
+    let mut cases = vec![];
+
+    let mut context = site_context(theme, "/blog/hello", "en");
+    insert_nested(&mut context, "page.type", "generic");
+    insert_nested(&mut context, "content.title", "A generic page");
+    insert_nested(&mut context, "content.tags", &["test"]);
+    insert_nested(&mut context, "content.authors", &["Test Author"]);
+    insert_nested(
+        &mut context,
+        "content.date",
+        &date("2023-03-14T09:00:00+09:00"),
+    );
+    content(&mut context, raw, body, "- [Hello](#hello)\n");
+    cases.push(ThemeCase {
+        name: "generic",
+        template: "generic.html",
+        context,
+    });
+
+    let mut context = site_context(theme, "/blog/hello", "en");
+    article(
+        &mut context,
+        "An article",
+        &["rust", "testing"],
+        &["Test Author"],
+    );
+    content(&mut context, raw, body, "- [Hello](#hello)\n");
+    insert_nested(
+        &mut context,
+        "page.previous",
+        &json!({ "slug": "older", "title": "An older post", "url": "https://example.com/blog/older/", "date": date("2023-03-01T09:00:00+09:00"), "weight": 0, "pinned": false }),
+    );
+    cases.push(ThemeCase {
+        name: "article",
+        template: "article.html",
+        context,
+    });
+
+    let mut context = site_context(theme, "/blog/hello", "en");
+    article(&mut context, "No tags, no authors", &[], &[]);
+    insert_nested(&mut context, "content.summary", &Option::<String>::None);
+    content(&mut context, "", "", "");
+    cases.push(ThemeCase {
+        name: "article-empty",
+        template: "article.html",
+        context,
+    });
+
+    // long enough that a toc sidebar has to scroll or collapse
+    let mut context = site_context(theme, "/blog/long", "en");
+    article(
+        &mut context,
+        "A very long article with a very long title that has to wrap somewhere",
+        &["long"],
+        &["Test Author", "Another Author", "A Third Author"],
+    );
+    let (mut long_raw, mut long_html, mut toc) = (String::new(), String::new(), String::new());
+    for section in 1..=200 {
+        let _ = write!(
+            long_raw,
+            "## Section {section}\n\nText of section {section}.\n\n"
+        );
+        let _ = write!(
+            long_html,
+            "<h2 id=\"section-{section}\">Section {section}</h2><p>Text of section {section}.</p>"
+        );
+        let _ = writeln!(toc, "- [Section {section}](#section-{section})");
+    }
+    content(&mut context, &long_raw, &long_html, &toc);
+    cases.push(ThemeCase {
+        name: "article-huge-toc",
+        template: "article.html",
+        context,
+    });
+
+    let mut context = site_context(theme, "/blog/hello", "ja");
+    article(&mut context, "翻訳された記事", &["翻訳"], &["テスト"]);
+    content(
+        &mut context,
+        "## こんにちは\n\n日本語の本文。\n",
+        "<h2 id=\"こんにちは\">こんにちは</h2><p>日本語の本文。</p>",
+        "- [こんにちは](#こんにちは)\n",
+    );
+    insert_nested(&mut context, "content.cjk", &8);
+    let alternates = json!([
+        { "language": "en", "path": "/blog/hello", "url": "https://example.com/blog/hello/", "default": true },
+        { "language": "ja", "path": "/ja/blog/hello", "url": "https://example.com/ja/blog/hello/", "default": false },
+    ]);
+    insert_nested(&mut context, "page.translations", &alternates);
+    insert_nested(&mut context, "page.default_translation", &alternates[0]);
+    insert_nested(&mut context, "page.this_translation", &alternates[1]);
+    cases.push(ThemeCase {
+        name: "translation",
+        template: "article.html",
+        context,
+    });
+
+    let mut context = site_context(theme, "/blog/series", "en");
+    insert_nested(&mut context, "page.type", "series");
+    insert_nested(&mut context, "content.title", "A series");
+    insert_nested(&mut context, "content.on_going", &false);
+    insert_nested(
+        &mut context,
+        "content.date_started",
+        &date("2023-01-01T00:00:00Z"),
+    );
+    insert_nested(
+        &mut context,
+        "content.date_completed",
+        &date("2023-06-01T00:00:00Z"),
+    );
+    insert_nested(&mut context, "content.edited_dates", &Vec::<String>::new());
+    insert_nested(&mut context, "content.authors", &["Test Author"]);
+    insert_nested(&mut context, "content.tags", &["series"]);
+    insert_nested(
+        &mut context,
+        "page.ebook",
+        &json!({
+            "epub": "https://example.com/blog/series/series.epub",
+            "pdf": "https://example.com/blog/series/series.pdf",
+        }),
+    );
+    content(&mut context, raw, body, "- [Hello](#hello)\n");
+    cases.push(ThemeCase {
+        name: "series",
+        template: "series.html",
+        context,
+    });
+
+    let mut context = site_context(theme, "/blog", "en");
+    insert_nested(&mut context, "page.type", "category");
+    insert_nested(&mut context, "content.title", "Blog");
+    insert_nested(
+        &mut context,
+        "content.entries",
+        &json!([
+            { "slug": "pinned", "title": "A pinned post", "url": "https://example.com/blog/pinned/", "date": date("2022-01-01T00:00:00Z"), "weight": 0, "pinned": true },
+            { "slug": "hello", "title": "An article", "url": "https://example.com/blog/hello/", "date": date("2023-03-14T09:00:00+09:00"), "weight": 0, "pinned": false },
+            { "slug": "undated", "title": "No date", "url": "https://example.com/blog/undated/", "date": null, "weight": 0, "pinned": false },
+        ]),
+    );
+    content(&mut context, "", "", "");
+    cases.push(ThemeCase {
+        name: "category",
+        template: "category.html",
+        context,
+    });
+
+    let mut context = site_context(theme, "/blog/hello", "en");
+    article(
+        &mut context,
+        "An article",
+        &["rust", "testing"],
+        &["Test Author"],
+    );
+    content(&mut context, raw, body, "- [Hello](#hello)\n");
+    insert_nested(
+        &mut context,
+        "page.print",
+        "https://example.com/blog/hello/print.html",
+    );
+    insert_nested(&mut context, "page.print_style", PRINT_STYLE);
+    // the body as expand_link_urls leaves it
+    insert_nested(
+        &mut context,
+        "content.html",
+        "<h2 id=\"hello\">Hello</h2><p>Some <em>text</em> and <a href=\"/blog/\">a link</a> <span class=\"print-url\">(https://example.com/blog/)</span>.</p><pre><code>fn main() {}</code></pre>",
+    );
+    cases.push(ThemeCase {
+        name: "print",
+        template: PRINT_TEMPLATE,
+        context,
+    });
+
+    cases
+}
+
+// the first line (1-based) the two differ on
+fn first_difference(expected: &str, actual: &str) -> usize {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => return line,
+            (a, b) if a != b => return line,
+            _ => line += 1,
+        }
+    }
+}
+
+// Renders the corpus against the theme in `theme_dir`. With `bless` the output becomes the new
+// golden files, otherwise it is compared against them where they exist.
+pub fn test_theme(
+    theme: &SiteTheme,
+    theme_dir: &Path,
+    bless: bool,
+) -> Result<Vec<(&'static str, CaseOutcome)>> {
+    let offset = FixedOffset::east_opt(0).unwrap();
+    // nothing a test render does should reach out of the machine or into the real store
+    let stdlib = ScriptStdlib::new(
+        &ScriptOptions::default(),
+        &format!("{}-test", theme.metadata.name),
+        offset,
+    )?;
+    let tera = theme_tera(
+        theme,
+        &LanguageTag::parse("en")?,
+        offset,
+        SlugStrategy::default(),
+        &stdlib,
+        &ThemeCalls::default(),
+        &FragmentCache::new(),
+    )?;
+    let golden_dir = theme_dir.join(GOLDEN_DIR);
+    let mut outcomes = vec![];
+
+    for case in corpus(theme) {
+        let has_template = tera.get_template_names().any(|name| name == case.template);
+        if !has_template && case.template != REQUIRED_TEMPLATE {
+            outcomes.push((case.name, CaseOutcome::Skipped));
+            continue;
+        }
+
+        let rendered = match tera.render(case.template, &case.context) {
+            Ok(rendered) => rendered,
+            Err(why) => {
+                // tera keeps the useful part in the source chain
+                let mut message = why.to_string();
+                let mut source = std::error::Error::source(&why);
+                while let Some(inner) = source {
+                    let _ = write!(message, ": {inner}");
+                    source = inner.source();
+                }
+                outcomes.push((case.name, CaseOutcome::Failed(message)));
+                continue;
+            }
+        };
+
+        let golden = golden_dir.join(format!("{}.html", case.name));
+        let outcome = if bless {
+            create_dir_all(&golden_dir)?;
+            write(&golden, &rendered)?;
+            CaseOutcome::Blessed
+        } else if golden.is_file() {
+            let expected = read_to_string(&golden)?;
+            match expected == rendered {
+                true => CaseOutcome::Passed,
+                false => CaseOutcome::Mismatch {
+                    line: first_difference(&expected, &rendered),
+                    golden,
+                },
+            }
+        } else {
+            CaseOutcome::Rendered
+        };
+        outcomes.push((case.name, outcome));
+    }
+    Ok(outcomes)
+}
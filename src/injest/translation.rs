@@ -0,0 +1,148 @@
+use crate::injest::build::SPLITTER;
+use crate::injest::config_meta::front_matter;
+use crate::injest::links::SiteUrl;
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use language_tags::LanguageTag;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+// A translation only has to contain what it changes, everything else comes from the default
+// language. Tables merge key by key, anything else (strings, arrays, dates) is replaced whole.
+pub fn merge_front_matter(default: &Value, translation: &Value) -> Value {
+    match (default, translation) {
+        (Value::Table(default), Value::Table(translation)) => {
+            let mut merged = default.clone();
+            for (key, value) in translation {
+                let value = match default.get(key) {
+                    Some(default_value) => merge_front_matter(default_value, value),
+                    None => value.clone(),
+                };
+                merged.insert(key.clone(), value);
+            }
+            Value::Table(merged)
+        }
+        (_, translation) => translation.clone(),
+    }
+}
+
+// The page a translation renders as: its front matter merged over the default language's, and its
+// own body, or the default language's body if it doesn't have one.
+pub fn resolve_translation(default: &str, translation: &str) -> Result<String> {
+    let default_front = toml::from_str::<Value>(front_matter(default))?;
+    let translated_front = toml::from_str::<Value>(front_matter(translation))?;
+    let merged = merge_front_matter(&default_front, &translated_front);
+
+    let body = match translation.split_once(SPLITTER) {
+        Some((_, body)) if !body.trim().is_empty() => body,
+        _ => default
+            .split_once(SPLITTER)
+            .map(|(_, body)| body)
+            .unwrap_or_default(),
+    };
+
+    Ok(format!("{}{SPLITTER}{body}", toml::to_string(&merged)?))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranslationStatus {
+    // the default language source of the page
+    pub path: PathBuf,
+    // expected languages without a translation
+    pub missing: Vec<String>,
+    // translations last edited before the default language was
+    pub stale: Vec<String>,
+}
+
+impl TranslationStatus {
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty() && self.stale.is_empty()
+    }
+}
+
+pub fn translation_status(
+    default_source: &Path,
+    translations: &[(&LanguageTag, &Path)],
+    expected: &[LanguageTag],
+    edit_times: &HashMap<PathBuf, DateTime<Utc>>,
+) -> TranslationStatus {
+    let missing = expected
+        .iter()
+        .filter(|language| !translations.iter().any(|(lang, _)| lang == language))
+        .map(|language| language.to_string())
+        .collect();
+
+    let stale = match edit_times.get(default_source) {
+        Some(default_edited) => translations
+            .iter()
+            .filter(|(_, path)| {
+                edit_times
+                    .get(*path)
+                    .map(|edited| edited < default_edited)
+                    .unwrap_or(false)
+            })
+            .map(|(language, _)| language.to_string())
+            .collect(),
+        None => vec![],
+    };
+
+    TranslationStatus {
+        path: default_source.to_path_buf(),
+        missing,
+        stale,
+    }
+}
+
+// where the `language` version of the page at `path` lives. the default language is at the
+// plain path unless the site puts every language under its own prefix.
+pub fn translated_path(
+    path: &str,
+    language: &LanguageTag,
+    default_language: &LanguageTag,
+    prefix_default: bool,
+) -> String {
+    if language == default_language && !prefix_default {
+        return path.to_string();
+    }
+    match path {
+        "/" => format!("/{}/", language.as_str()),
+        path => format!("/{}{path}", language.as_str()),
+    }
+}
+
+// one language version of a page, for `page.translations` and hreflang links
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Alternate {
+    pub language: String,
+    pub path: String,
+    pub url: String,
+    pub default: bool,
+}
+
+pub fn alternates(
+    path: &str,
+    languages: &[&LanguageTag],
+    default_language: &LanguageTag,
+    prefix_default: bool,
+    urls: &SiteUrl,
+) -> Vec<Alternate> {
+    let mut alternates = vec![];
+    for language in std::iter::once(default_language).chain(
+        languages
+            .iter()
+            .copied()
+            .filter(|language| *language != default_language),
+    ) {
+        let path = translated_path(path, language, default_language, prefix_default);
+        alternates.push(Alternate {
+            language: language.to_string(),
+            url: urls.link(&path),
+            path,
+            default: language == default_language,
+        });
+    }
+    alternates
+}
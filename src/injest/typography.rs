@@ -0,0 +1,184 @@
+use color_eyre::Result;
+use dashmap::DashMap;
+use hyphenation::{Hyphenator, Language, Load, Standard};
+use language_tags::LanguageTag;
+use lol_html::html_content::ContentType;
+use lol_html::{element, rewrite_str, text, Settings};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+const NO_BREAK_SPACE: char = '\u{a0}';
+const NARROW_NO_BREAK_SPACE: char = '\u{202f}';
+const SOFT_HYPHEN: char = '\u{ad}';
+// where text isn't prose and has to come out the way it was written
+const VERBATIM: &str = "pre, code, kbd, samp, script, style, textarea";
+
+// the patterns of a language, loaded the first time a page in it is built
+static HYPHENATORS: Lazy<DashMap<Language, Option<Arc<Standard>>>> = Lazy::new(DashMap::new);
+
+// what happens to the text of pages in one language, everything off unless set
+//
+// [build.typography.fr]
+// quotes = ["« ", " »"]
+// french_spacing = true
+// widows = true
+// hyphenate = 12
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypographyRules {
+    // what the curly double quotes of smart punctuation become, opening and closing
+    pub quotes: Option<(String, String)>,
+    // no-break spaces before `: ; ! ?` and inside `« »`, so they never start a line
+    #[serde(default)]
+    pub french_spacing: bool,
+    // the last two words of a paragraph are kept on one line
+    #[serde(default)]
+    pub widows: bool,
+    // soft hyphens in words at least this many characters long
+    pub hyphenate: Option<usize>,
+}
+
+// `[build.typography]` by language tag, a page takes the rules of its language, of its primary
+// language, or of `*`, in that order
+pub type TypographyOptions = BTreeMap<String, TypographyRules>;
+
+pub fn rules_for<'a>(
+    options: &'a TypographyOptions,
+    language: &LanguageTag,
+) -> Option<&'a TypographyRules> {
+    options
+        .get(language.as_str())
+        .or_else(|| options.get(language.primary_language()))
+        .or_else(|| options.get("*"))
+}
+
+fn hyphenator(language: &LanguageTag) -> Option<Arc<Standard>> {
+    let language = Language::try_from_code(language.as_str().to_lowercase())
+        .or_else(|| Language::try_from_code(language.primary_language()))?;
+    HYPHENATORS
+        .entry(language)
+        .or_insert_with(|| Standard::from_embedded(language).ok().map(Arc::new))
+        .clone()
+}
+
+fn french_spacing(text: &str) -> String {
+    let mut spaced = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            (' ', Some(';' | '!' | '?' | '»')) => spaced.push(NARROW_NO_BREAK_SPACE),
+            (' ', Some(':')) => spaced.push(NO_BREAK_SPACE),
+            ('«', Some(' ')) => {
+                chars.next();
+                spaced.push_str(&format!("«{NARROW_NO_BREAK_SPACE}"));
+            }
+            (c, _) => spaced.push(c),
+        }
+    }
+    spaced
+}
+
+// the space before the last word, if there's a word before it in the same text
+fn bind_last_words(text: &str) -> String {
+    let trimmed = text.trim_end();
+    match trimmed.rfind(' ') {
+        Some(space) if !trimmed[..space].trim().is_empty() => {
+            format!("{}{NO_BREAK_SPACE}{}", &text[..space], &text[space + 1..])
+        }
+        _ => text.to_string(),
+    }
+}
+
+fn hyphenate(text: &str, hyphenator: &Standard, min_length: usize) -> String {
+    let mut hyphenated = String::with_capacity(text.len());
+    let mut word = String::new();
+    let flush = |word: &mut String, hyphenated: &mut String| {
+        if word.chars().count() >= min_length {
+            let mut start = 0;
+            for end in hyphenator.hyphenate(word).breaks {
+                hyphenated.push_str(&word[start..end]);
+                hyphenated.push(SOFT_HYPHEN);
+                start = end;
+            }
+            hyphenated.push_str(&word[start..]);
+        } else {
+            hyphenated.push_str(word);
+        }
+        word.clear();
+    };
+    // entities like `&amp;` are left whole, they're in the text as written
+    let mut in_entity = false;
+    for c in text.chars() {
+        in_entity = match c {
+            '&' => true,
+            ';' => false,
+            _ => in_entity,
+        };
+        if c.is_alphabetic() && !in_entity {
+            word.push(c);
+        } else {
+            flush(&mut word, &mut hyphenated);
+            hyphenated.push(c);
+        }
+    }
+    flush(&mut word, &mut hyphenated);
+    hyphenated
+}
+
+// One text node of the page, as html. Widows are bound in every text node, not only the one that
+// ends a paragraph, so a word before a link or emphasis stays with it too.
+pub fn typeset(text: &str, rules: &TypographyRules, language: &LanguageTag) -> String {
+    let mut text = text.to_string();
+    if let Some((open, close)) = &rules.quotes {
+        text = text.replace('“', open).replace('”', close);
+    }
+    if rules.french_spacing {
+        text = french_spacing(&text);
+    }
+    if rules.widows {
+        text = bind_last_words(&text);
+    }
+    if let Some((min_length, hyphenator)) = rules
+        .hyphenate
+        .and_then(|min_length| Some((min_length, hyphenator(language)?)))
+    {
+        text = hyphenate(&text, &hyphenator, min_length);
+    }
+    text
+}
+
+// every text node of the body that's prose, code and scripts are left alone
+pub fn typeset_document(
+    document: &str,
+    rules: &TypographyRules,
+    language: &LanguageTag,
+) -> Result<String> {
+    let verbatim = Rc::new(Cell::new(0usize));
+    Ok(rewrite_str(
+        document,
+        Settings {
+            element_content_handlers: vec![
+                element!(VERBATIM, |el| {
+                    verbatim.set(verbatim.get() + 1);
+                    let verbatim = verbatim.clone();
+                    el.on_end_tag(move |_| {
+                        verbatim.set(verbatim.get().saturating_sub(1));
+                        Ok(())
+                    })?;
+                    Ok(())
+                }),
+                text!("body *", |chunk| {
+                    if verbatim.get() == 0 && !chunk.as_str().trim().is_empty() {
+                        let typeset = typeset(chunk.as_str(), rules, language);
+                        chunk.replace(&typeset, ContentType::Html);
+                    }
+                    Ok(())
+                }),
+            ],
+            ..Settings::default()
+        },
+    )?)
+}
@@ -0,0 +1,290 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// `[build] html_validation`, off unless asked for
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HtmlValidation {
+    #[default]
+    Off,
+    // problems are warnings
+    Warn,
+    // problems are errors and fail the build
+    Strict,
+}
+
+const VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+const RAW_TEXT: &[&str] = &["script", "style", "textarea", "title"];
+
+// elements whose end tag can be left out
+const OPTIONAL_END: &[&str] = &[
+    "html", "head", "body", "p", "li", "dt", "dd", "tr", "td", "th", "option", "optgroup",
+    "thead", "tbody", "tfoot", "colgroup", "rp", "rt",
+];
+
+// starting any of these closes an open <p>
+const CLOSES_P: &[&str] = &[
+    "address", "article", "aside", "blockquote", "details", "div", "dl", "fieldset",
+    "figcaption", "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr",
+    "main", "menu", "nav", "ol", "p", "pre", "section", "table", "ul",
+];
+
+const PHRASING_ONLY: &[&str] = &[
+    "span", "em", "strong", "b", "i", "u", "s", "small", "code", "label", "abbr", "cite", "q",
+    "sub", "sup",
+];
+
+const INTERACTIVE: &[&str] = &["a", "button", "input", "select", "textarea"];
+
+struct Open {
+    name: String,
+    line: usize,
+}
+
+// does starting `tag` end the open `open` element
+fn implies_end(open: &str, tag: &str) -> bool {
+    match open {
+        "p" => CLOSES_P.contains(&tag),
+        "li" => tag == "li",
+        "dt" | "dd" => tag == "dt" || tag == "dd",
+        "tr" => tag == "tr" || tag == "tbody" || tag == "tfoot",
+        "td" | "th" => matches!(tag, "td" | "th" | "tr" | "tbody" | "tfoot"),
+        "option" => tag == "option" || tag == "optgroup",
+        "optgroup" => tag == "optgroup",
+        "thead" | "tbody" => tag == "tbody" || tag == "tfoot",
+        "rp" | "rt" => tag == "rp" || tag == "rt",
+        _ => false,
+    }
+}
+
+struct Tag<'a> {
+    name: String,
+    end: bool,
+    self_closing: bool,
+    attributes: Vec<(String, &'a str)>,
+}
+
+fn parse_attributes(mut rest: &str) -> Vec<(String, &str)> {
+    let mut attributes = vec![];
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            break;
+        }
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let mut value = "";
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (found, remaining) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => match after[1..].find(quote) {
+                    Some(end) => (&after[1..end + 1], &after[end + 2..]),
+                    None => (&after[1..], ""),
+                },
+                _ => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            value = found;
+            rest = remaining;
+        }
+        attributes.push((name, value));
+    }
+    attributes
+}
+
+// the tag starting at `html[start]` (a '<') and the offset right after it
+fn parse_tag(html: &str, start: usize) -> Option<(Tag, usize)> {
+    let bytes = html.as_bytes();
+    let mut idx = start + 1;
+    let end = bytes.get(idx) == Some(&b'/');
+    if end {
+        idx += 1;
+    }
+    if !bytes.get(idx)?.is_ascii_alphabetic() {
+        return None;
+    }
+
+    // find the closing '>' outside of quoted attribute values
+    let mut quote = None;
+    let mut close = None;
+    for (offset, c) in html[idx..].char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => {
+                close = Some(idx + offset);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let close = close?;
+
+    let inner = &html[idx..close];
+    let name_end = inner
+        .find(|c: char| c.is_whitespace() || c == '/')
+        .unwrap_or(inner.len());
+    Some((
+        Tag {
+            name: inner[..name_end].to_ascii_lowercase(),
+            end,
+            self_closing: inner.ends_with('/'),
+            attributes: parse_attributes(&inner[name_end..]),
+        },
+        close + 1,
+    ))
+}
+
+// Problems with the markup of a finished page: unclosed and stray tags, duplicate ids and elements
+// nested where html doesn't allow them. Not a full html5 parser, just enough of one to catch what
+// themes and post processing tend to get wrong.
+pub fn validate_html(html: &str) -> Vec<String> {
+    let line_of = |offset: usize| html[..offset].matches('\n').count() + 1;
+
+    let mut problems = vec![];
+    let mut stack: Vec<Open> = vec![];
+    let mut ids: HashMap<String, usize> = HashMap::new();
+    let mut idx = 0;
+
+    while let Some(found) = html[idx..].find('<') {
+        let start = idx + found;
+        let rest = &html[start..];
+
+        if rest.starts_with("<!--") {
+            idx = match rest.find("-->") {
+                Some(end) => start + end + 3,
+                None => {
+                    problems.push(format!("line {}: unclosed comment", line_of(start)));
+                    break;
+                }
+            };
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            idx = rest.find('>').map(|end| start + end + 1).unwrap_or(html.len());
+            continue;
+        }
+
+        let (tag, after) = match parse_tag(html, start) {
+            Some(parsed) => parsed,
+            None => {
+                idx = start + 1;
+                continue;
+            }
+        };
+        idx = after;
+        let line = line_of(start);
+
+        if tag.end {
+            match stack.iter().rposition(|open| open.name == tag.name) {
+                Some(position) => {
+                    for open in stack.drain(position..).skip(1) {
+                        if !OPTIONAL_END.contains(&open.name.as_str()) {
+                            problems.push(format!(
+                                "line {line}: <{}> from line {} is not closed before </{}>",
+                                open.name, open.line, tag.name
+                            ));
+                        }
+                    }
+                }
+                None if VOID.contains(&tag.name.as_str()) => problems.push(format!(
+                    "line {line}: </{}> on an element that can't have an end tag",
+                    tag.name
+                )),
+                None => problems.push(format!("line {line}: stray </{}>", tag.name)),
+            }
+            continue;
+        }
+
+        while let Some(open) = stack.last() {
+            if implies_end(&open.name, &tag.name) {
+                stack.pop();
+            } else {
+                break;
+            }
+        }
+
+        let name = tag.name.as_str();
+        let inside = |parent: &str| stack.iter().any(|open| open.name == parent);
+        let parent = stack.last().map(|open| open.name.as_str());
+
+        if name == "a" && inside("a") {
+            problems.push(format!("line {line}: <a> inside another <a>"));
+        }
+        if name == "form" && inside("form") {
+            problems.push(format!("line {line}: <form> inside another <form>"));
+        }
+        if INTERACTIVE.contains(&name) && inside("button") {
+            problems.push(format!("line {line}: <{name}> inside a <button>"));
+        }
+        match (name, parent) {
+            ("li", Some(parent)) if !matches!(parent, "ul" | "ol" | "menu") => {
+                problems.push(format!("line {line}: <li> inside <{parent}> instead of a list"))
+            }
+            ("tr", Some(parent)) if !matches!(parent, "table" | "thead" | "tbody" | "tfoot") => {
+                problems.push(format!("line {line}: <tr> inside <{parent}> instead of a table"))
+            }
+            ("td" | "th", Some(parent)) if parent != "tr" => {
+                problems.push(format!("line {line}: <{name}> inside <{parent}> instead of a <tr>"))
+            }
+            (name, Some(parent))
+                if CLOSES_P.contains(&name) && PHRASING_ONLY.contains(&parent) =>
+            {
+                problems.push(format!("line {line}: block <{name}> inside inline <{parent}>"))
+            }
+            _ => {}
+        }
+
+        for (attribute, value) in &tag.attributes {
+            if attribute == "id" {
+                if let Some(first) = ids.insert(value.to_string(), line) {
+                    problems.push(format!(
+                        "line {line}: duplicate id \"{value}\", first used on line {first}"
+                    ));
+                }
+            }
+        }
+
+        if VOID.contains(&name) || tag.self_closing {
+            continue;
+        }
+        if RAW_TEXT.contains(&name) {
+            // nothing inside is markup, skip straight to the end tag
+            let closing = format!("</{name}");
+            match html[idx..].to_ascii_lowercase().find(&closing) {
+                Some(end) => {
+                    idx += end;
+                    stack.push(Open { name: tag.name, line });
+                }
+                None => {
+                    problems.push(format!("line {line}: <{name}> is never closed"));
+                    break;
+                }
+            }
+            continue;
+        }
+        stack.push(Open { name: tag.name, line });
+    }
+
+    for open in stack {
+        if !OPTIONAL_END.contains(&open.name.as_str()) {
+            problems.push(format!(
+                "line {}: <{}> is never closed",
+                open.line, open.name
+            ));
+        }
+    }
+
+    problems
+}
@@ -0,0 +1,218 @@
+use crate::injest::links::SiteUrl;
+use schemars::JsonSchema;
+use semver::Version;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+// a page of a versioned category, as the build found it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionPage {
+    // the directory of the version, `v2`
+    pub version: String,
+    // the site path of that directory
+    pub root: String,
+    pub path: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct VersionLink {
+    pub name: String,
+    // the same page in that version, or the version itself when it doesn't have the page
+    pub url: String,
+    pub current: bool,
+    pub latest: bool,
+    pub frozen: bool,
+}
+
+// what a page of a versioned category gets as `page.version`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct VersionNav {
+    pub current: String,
+    pub latest: String,
+    // not the latest version, for a banner pointing at `latest_url`
+    pub outdated: bool,
+    pub frozen: bool,
+    pub latest_url: String,
+    // the switcher, newest first
+    pub versions: Vec<VersionLink>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct VersionDir {
+    name: String,
+    root: String,
+    frozen: bool,
+    // relative to root, the root itself is ""
+    pages: BTreeSet<String>,
+}
+
+impl VersionDir {
+    fn covers(&self, path: &str) -> Option<String> {
+        match path.strip_prefix(&self.root) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => Some(rest.to_string()),
+            _ => None,
+        }
+    }
+
+    fn url(&self, relative: &str, urls: &SiteUrl) -> String {
+        match self.pages.contains(relative) {
+            true => urls.absolute(&format!("{}{relative}", self.root)),
+            false => urls.absolute(&self.root),
+        }
+    }
+}
+
+// `v1.2`, `2.0.0-beta.1` and `3` as semver, anything else isn't one
+fn parse_version(name: &str) -> Option<Version> {
+    let name = name.trim_start_matches(|c| c == 'v' || c == 'V');
+    let (core, rest) = match name.find(|c| c == '-' || c == '+') {
+        Some(at) => name.split_at(at),
+        None => (name, ""),
+    };
+    let padded = match core.matches('.').count() {
+        0 => format!("{core}.0.0{rest}"),
+        1 => format!("{core}.0{rest}"),
+        _ => name.to_string(),
+    };
+    Version::parse(&padded).ok()
+}
+
+// newest first, versions that aren't semver after the ones that are, by name
+fn newest_first(a: &str, b: &str) -> Ordering {
+    match (parse_version(a), parse_version(b)) {
+        (Some(a), Some(b)) => b.cmp(&a),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => b.cmp(a),
+    }
+}
+
+// One category with `versioned = true`, every directory of it is a version. The latest is the
+// newest unless `latest_version` says otherwise, the others are outdated and kept out of search
+// engines.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VersionTree {
+    root: String,
+    versions: Vec<VersionDir>,
+    latest: usize,
+}
+
+impl VersionTree {
+    pub fn new(
+        root: &str,
+        pages: Vec<VersionPage>,
+        latest: Option<&str>,
+        frozen: &[String],
+    ) -> VersionTree {
+        let mut dirs = BTreeMap::<String, VersionDir>::new();
+        for page in pages {
+            let dir = dirs
+                .entry(page.version.clone())
+                .or_insert_with(|| VersionDir {
+                    name: page.version.clone(),
+                    root: page.root.clone(),
+                    frozen: frozen.contains(&page.version),
+                    pages: BTreeSet::new(),
+                });
+            if let Some(relative) = dir.covers(&page.path) {
+                dir.pages.insert(relative);
+            }
+        }
+        let mut versions = dirs.into_values().collect::<Vec<_>>();
+        versions.sort_by(|a, b| newest_first(&a.name, &b.name));
+        let latest = latest
+            .and_then(|latest| versions.iter().position(|version| version.name == latest))
+            .unwrap_or_default();
+        VersionTree {
+            root: root.to_string(),
+            versions,
+            latest,
+        }
+    }
+
+    pub fn has_version(&self, name: &str) -> bool {
+        self.versions.iter().any(|version| version.name == name)
+    }
+
+    fn find(&self, path: &str) -> Option<(usize, String)> {
+        self.versions
+            .iter()
+            .enumerate()
+            .find_map(|(index, version)| Some((index, version.covers(path)?)))
+    }
+
+    pub fn is_outdated(&self, path: &str) -> bool {
+        matches!(self.find(path), Some((index, _)) if index != self.latest)
+    }
+
+    // frozen versions are never the latest, whatever `frozen_versions` says
+    pub fn is_frozen(&self, path: &str) -> bool {
+        match self.find(path) {
+            Some((index, _)) => index != self.latest && self.versions[index].frozen,
+            None => false,
+        }
+    }
+
+    // the category's own page isn't in a version, it gets None
+    pub fn nav(&self, path: &str, urls: &SiteUrl) -> Option<VersionNav> {
+        let (current, relative) = self.find(path)?;
+        let latest = &self.versions[self.latest];
+        Some(VersionNav {
+            current: self.versions[current].name.clone(),
+            latest: latest.name.clone(),
+            outdated: current != self.latest,
+            frozen: self.is_frozen(path),
+            latest_url: latest.url(&relative, urls),
+            versions: self
+                .versions
+                .iter()
+                .enumerate()
+                .map(|(index, version)| VersionLink {
+                    name: version.name.clone(),
+                    url: version.url(&relative, urls),
+                    current: index == current,
+                    latest: index == self.latest,
+                    frozen: version.frozen && index != self.latest,
+                })
+                .collect(),
+        })
+    }
+
+    // the directories the versions are in, newest first
+    pub fn roots(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.versions
+            .iter()
+            .map(|version| (version.name.as_str(), version.root.as_str()))
+    }
+}
+
+// every versioned category of the site, by the site path of the category
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VersionTrees {
+    trees: BTreeMap<String, VersionTree>,
+}
+
+impl VersionTrees {
+    pub fn insert(&mut self, tree: VersionTree) {
+        self.trees.insert(tree.root.clone(), tree);
+    }
+
+    fn tree(&self, path: &str) -> Option<&VersionTree> {
+        self.trees
+            .values()
+            .find(|tree| path.starts_with(&format!("{}/", tree.root.trim_end_matches('/'))))
+    }
+
+    pub fn nav(&self, path: &str, urls: &SiteUrl) -> Option<VersionNav> {
+        self.tree(path)?.nav(path, urls)
+    }
+
+    pub fn is_outdated(&self, path: &str) -> bool {
+        self.tree(path).map_or(false, |tree| tree.is_outdated(path))
+    }
+
+    pub fn is_frozen(&self, path: &str) -> bool {
+        self.tree(path).map_or(false, |tree| tree.is_frozen(path))
+    }
+}
@@ -0,0 +1,233 @@
+use crate::injest::{
+    build::{BuildInformation, BuiltSite},
+    links::SiteUrl,
+};
+use crate::models::published_page;
+use color_eyre::{Report, Result};
+use hmac::{Hmac, Mac};
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::env::var;
+use std::time::Duration;
+use tera::{Context, Tera};
+use tracing::warn;
+
+pub const SIGNATURE_HEADER: &str = "X-Moklog-Signature";
+pub const EVENT_HEADER: &str = "X-Moklog-Event";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    BuildStarted,
+    BuildSucceeded,
+    BuildFailed,
+    // once per page, the first time a build has it
+    PagePublished,
+}
+
+impl WebhookEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::BuildStarted => "build_started",
+            WebhookEvent::BuildSucceeded => "build_succeeded",
+            WebhookEvent::BuildFailed => "build_failed",
+            WebhookEvent::PagePublished => "page_published",
+        }
+    }
+}
+
+// `[[webhooks]]` in site.toml
+//
+// [[webhooks]]
+// url = "https://discord.com/api/webhooks/..."
+// events = ["build_failed", "page_published"]
+// payload = '{"content": "{{ event }}: {{ page.url | default(value=error) }}"}'
+// secret_env = "MOKLOG_DISCORD_SECRET"
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    // every event if empty
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+    // tera template of the request body, rendered with the event as context. the event as json if
+    // not set.
+    pub payload: Option<String>,
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    // name of the environment variable holding the hmac key, the content repo is no place for it
+    pub secret_env: Option<String>,
+}
+
+fn default_content_type() -> String {
+    "application/json".to_string()
+}
+
+impl WebhookConfig {
+    pub fn wants(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+
+    fn body(&self, payload: &WebhookPayload) -> Result<String> {
+        match &self.payload {
+            Some(template) => Ok(Tera::one_off(
+                template,
+                &Context::from_serialize(payload)?,
+                false,
+            )?),
+            None => Ok(serde_json::to_string(payload)?),
+        }
+    }
+
+    fn secret(&self) -> Result<Option<String>> {
+        match &self.secret_env {
+            Some(name) => var(name)
+                .map(Some)
+                .map_err(|_| Report::msg(format!("{name} is not set, not sending unsigned"))),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublishedPage {
+    pub path: String,
+    pub url: String,
+}
+
+// what a webhook gets to say, `payload` templates see these fields
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+    pub site: String,
+    pub build: Option<BuildInformation>,
+    pub page: Option<PublishedPage>,
+    pub error: Option<String>,
+}
+
+// `sha256=<hex>` of the body, the same scheme github uses
+pub fn sign(secret: &str, body: &str) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|why| Report::msg(why.to_string()))?;
+    mac.update(body.as_bytes());
+    Ok(format!(
+        "sha256={}",
+        hex::encode(mac.finalize().into_bytes())
+    ))
+}
+
+pub struct Webhooks {
+    hooks: Vec<WebhookConfig>,
+    client: reqwest::Client,
+}
+
+impl Webhooks {
+    pub fn new(hooks: Vec<WebhookConfig>) -> Result<Webhooks> {
+        Ok(Webhooks {
+            hooks,
+            client: reqwest::Client::builder().timeout(TIMEOUT).build()?,
+        })
+    }
+
+    async fn send(&self, hook: &WebhookConfig, payload: &WebhookPayload) -> Result<()> {
+        let body = hook.body(payload)?;
+        let mut request = self
+            .client
+            .post(&hook.url)
+            .header("content-type", &hook.content_type)
+            .header(EVENT_HEADER, payload.event.name());
+        for (name, value) in &hook.headers {
+            request = request.header(name, value);
+        }
+        if let Some(secret) = hook.secret()? {
+            request = request.header(SIGNATURE_HEADER, sign(&secret, &body)?);
+        }
+
+        let response = request.body(body).send().await?;
+        if !response.status().is_success() {
+            return Err(Report::msg(format!("answered {}", response.status())));
+        }
+        Ok(())
+    }
+
+    // a webhook that fails is logged and otherwise ignored, it never fails the build
+    pub async fn notify(&self, payload: &WebhookPayload) {
+        for hook in self.hooks.iter().filter(|hook| hook.wants(payload.event)) {
+            if let Err(why) = self.send(hook, payload).await {
+                warn!("webhook {} for {}: {why}", hook.url, payload.event.name());
+            }
+        }
+    }
+
+    pub async fn build_started(&self, site: &str, build: &BuildInformation) {
+        self.notify(&WebhookPayload {
+            event: WebhookEvent::BuildStarted,
+            site: site.to_string(),
+            build: Some(build.clone()),
+            page: None,
+            error: None,
+        })
+        .await
+    }
+
+    // build_succeeded or build_failed, then page_published for every page no build had before
+    pub async fn build_finished(
+        &self,
+        site: &str,
+        build: &BuildInformation,
+        built: &Result<BuiltSite>,
+        urls: &SiteUrl,
+        database: &DatabaseConnection,
+    ) {
+        let (event, error) = match built {
+            Ok(_) => (WebhookEvent::BuildSucceeded, None),
+            Err(why) => (WebhookEvent::BuildFailed, Some(why.to_string())),
+        };
+        self.notify(&WebhookPayload {
+            event,
+            site: site.to_string(),
+            build: Some(build.clone()),
+            page: None,
+            error,
+        })
+        .await;
+
+        let built = match built {
+            Ok(built) => built,
+            Err(_) => return,
+        };
+        // recorded even without a hook that wants them, so adding one later doesn't announce the
+        // whole site at once. unlisted and private pages are announced once they go public.
+        let paths = built
+            .pages
+            .keys()
+            .filter(|path| !built.access.contains_key(*path))
+            .cloned()
+            .collect::<Vec<_>>();
+        let new_pages = match published_page::first_published(database, &paths).await {
+            Ok(pages) => pages,
+            Err(why) => {
+                warn!("could not record published pages: {why}");
+                return;
+            }
+        };
+        for path in new_pages {
+            self.notify(&WebhookPayload {
+                event: WebhookEvent::PagePublished,
+                site: site.to_string(),
+                build: Some(build.clone()),
+                page: Some(PublishedPage {
+                    url: urls.link(&path),
+                    path,
+                }),
+                error: None,
+            })
+            .await;
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use crate::injest::{links::SiteUrl, slug::SlugStrategy};
+use html_escape::encode_text;
+use pulldown_cmark::{Event, Parser, Tag};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+// a page wikilinks can point at, as the build found it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WikiPage {
+    pub path: String,
+    pub title: String,
+}
+
+// `[[Target]]`, `[[target|what the link says]]` or `[[target#heading]]` in a page's markdown
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WikiLink<'a> {
+    // the byte range of the whole `[[...]]`
+    pub start: usize,
+    pub end: usize,
+    pub target: &'a str,
+    pub heading: Option<&'a str>,
+    pub label: Option<&'a str>,
+}
+
+impl WikiLink<'_> {
+    fn text(&self) -> &str {
+        self.label.unwrap_or(self.target)
+    }
+}
+
+fn is_fence(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("```") || line.starts_with("~~~")
+}
+
+fn parse_link(inside: &str) -> Option<(&str, Option<&str>, Option<&str>)> {
+    if inside.contains(|c| matches!(c, '[' | ']' | '\n')) {
+        return None;
+    }
+    let (target, label) = match inside.split_once('|') {
+        Some((target, label)) => (target, Some(label.trim()).filter(|label| !label.is_empty())),
+        None => (inside, None),
+    };
+    let (target, heading) = match target.split_once('#') {
+        Some((target, heading)) => (target.trim(), Some(heading.trim())),
+        None => (target.trim(), None),
+    };
+    match target.is_empty() {
+        true => None,
+        false => Some((target, heading, label)),
+    }
+}
+
+// Every wikilink of a markdown body, outside of code blocks and code spans. `\[[` is left alone,
+// markdown shows it as written.
+pub fn wikilinks(body: &str) -> Vec<WikiLink> {
+    let mut links = vec![];
+    let mut in_fence = false;
+    let mut offset = 0;
+    for line in body.split_inclusive('\n') {
+        let at = offset;
+        offset += line.len();
+        if is_fence(line) {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+
+        let mut cursor = 0;
+        while cursor < line.len() {
+            let rest = &line[cursor..];
+            if rest.starts_with('`') {
+                // a code span runs to the next run of as many backticks
+                let ticks = rest.len() - rest.trim_start_matches('`').len();
+                let fence = &rest[..ticks];
+                cursor += match rest[ticks..].find(fence) {
+                    Some(close) => ticks + close + ticks,
+                    None => ticks,
+                };
+                continue;
+            }
+            if rest.starts_with("\\[") {
+                cursor += 2;
+                continue;
+            }
+            if rest.starts_with("[[") {
+                if let Some(close) = rest[2..].find("]]") {
+                    if let Some((target, heading, label)) = parse_link(&rest[2..2 + close]) {
+                        links.push(WikiLink {
+                            start: at + cursor,
+                            end: at + cursor + close + 4,
+                            target,
+                            heading,
+                            label,
+                        });
+                        cursor += close + 4;
+                        continue;
+                    }
+                }
+            }
+            cursor += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    links
+}
+
+pub enum Resolution<'a> {
+    Page(&'a str),
+    Missing,
+    // more than one page has that title or name
+    Ambiguous(Vec<&'a str>),
+}
+
+// Every page by its site path, its title and the last segment of its path. A target starting with
+// `/` is a site path, anything else is matched against titles and names without caring about
+// case, and as a slug.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WikiIndex {
+    titles: BTreeMap<String, String>,
+    names: BTreeMap<String, BTreeSet<String>>,
+    strategy: SlugStrategy,
+}
+
+impl WikiIndex {
+    pub fn new(pages: &[WikiPage], strategy: SlugStrategy) -> WikiIndex {
+        let mut names = BTreeMap::<String, BTreeSet<String>>::new();
+        for page in pages {
+            let last = page.path.rsplit('/').next().unwrap_or_default();
+            for name in [page.title.to_lowercase(), last.to_lowercase()] {
+                if !name.is_empty() {
+                    names.entry(name).or_default().insert(page.path.clone());
+                }
+            }
+        }
+        WikiIndex {
+            titles: pages
+                .iter()
+                .map(|page| (page.path.clone(), page.title.clone()))
+                .collect(),
+            names,
+            strategy,
+        }
+    }
+
+    pub fn resolve(&self, target: &str) -> Resolution {
+        if target.starts_with('/') {
+            let path = match target.trim_end_matches('/') {
+                "" => "/",
+                path => path,
+            };
+            return match self.titles.get_key_value(path) {
+                Some((path, _)) => Resolution::Page(path),
+                None => Resolution::Missing,
+            };
+        }
+        let found = [target.to_lowercase(), self.strategy.slugify(target)]
+            .into_iter()
+            .find_map(|name| self.names.get(&name));
+        match found {
+            Some(paths) if paths.len() == 1 => Resolution::Page(paths.iter().next().unwrap()),
+            Some(paths) => Resolution::Ambiguous(paths.iter().map(String::as_str).collect()),
+            None => Resolution::Missing,
+        }
+    }
+
+    // The body with its wikilinks as markdown links to site paths, post processing makes them
+    // urls. A link that doesn't resolve is the text of it in a `wikilink-missing` span.
+    pub fn expand(&self, body: &str) -> String {
+        let mut expanded = String::with_capacity(body.len());
+        let mut at = 0;
+        for link in wikilinks(body) {
+            expanded.push_str(&body[at..link.start]);
+            at = link.end;
+            match self.resolve(link.target) {
+                Resolution::Page(path) => {
+                    let heading = link
+                        .heading
+                        .map(|heading| format!("#{}", self.strategy.slugify(heading)))
+                        .unwrap_or_default();
+                    expanded.push_str(&format!("[{}](<{path}{heading}>)", link.text()));
+                }
+                _ => expanded.push_str(&format!(
+                    r#"<span class="wikilink-missing">{}</span>"#,
+                    encode_text(link.text())
+                )),
+            }
+        }
+        expanded.push_str(&body[at..]);
+        expanded
+    }
+}
+
+// what `content.backlinks` lists
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, JsonSchema)]
+pub struct Backlink {
+    pub title: String,
+    pub url: String,
+}
+
+// The site path a link of a page goes to, when it goes to a page of this site. Relative links are
+// left out, what they're relative to depends on how the page was requested.
+fn linked_path(destination: &str, urls: &SiteUrl) -> Option<String> {
+    let destination = destination.split(|c| c == '#' || c == '?').next()?;
+    let path = match destination.strip_prefix(&urls.origin()) {
+        Some(path) => urls.strip_base(path)?,
+        None if destination.starts_with('/') && !destination.starts_with("//") => destination,
+        None => return None,
+    };
+    match path.trim_end_matches('/') {
+        "" => Some("/".to_string()),
+        path => Some(path.to_string()),
+    }
+}
+
+// Which page links to which, from the markdown links and wikilinks of every markdown page.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PageLinks {
+    titles: BTreeMap<String, String>,
+    // site path -> the pages it links to
+    outgoing: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl PageLinks {
+    pub fn new(index: &WikiIndex) -> PageLinks {
+        PageLinks {
+            titles: index.titles.clone(),
+            outgoing: BTreeMap::new(),
+        }
+    }
+
+    // `body` with its wikilinks already expanded, links to anything that isn't a page are dropped
+    pub fn add(&mut self, path: &str, body: &str, urls: &SiteUrl) {
+        let linked = Parser::new(body)
+            .filter_map(|event| match event {
+                Event::Start(Tag::Link(_, destination, _)) => linked_path(&destination, urls),
+                _ => None,
+            })
+            .filter(|linked| linked != path && self.titles.contains_key(linked))
+            .collect::<BTreeSet<_>>();
+        if !linked.is_empty() {
+            self.outgoing
+                .entry(path.to_string())
+                .or_default()
+                .extend(linked);
+        }
+    }
+
+    // every page that can be linked to, by site path, with its title
+    pub fn pages(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.titles
+            .iter()
+            .map(|(path, title)| (path.as_str(), title.as_str()))
+    }
+
+    // every link as the page it's on and the page it goes to
+    pub fn links(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.outgoing
+            .iter()
+            .flat_map(|(from, linked)| linked.iter().map(move |to| (from.as_str(), to.as_str())))
+    }
+
+    // the pages linking to `path`, by title
+    pub fn backlinks(&self, path: &str, urls: &SiteUrl) -> Vec<Backlink> {
+        let mut backlinks = self
+            .outgoing
+            .iter()
+            .filter(|(_, linked)| linked.contains(path))
+            .map(|(from, _)| Backlink {
+                title: self.titles[from].clone(),
+                url: urls.absolute(from),
+            })
+            .collect::<Vec<_>>();
+        backlinks.sort();
+        backlinks
+    }
+}
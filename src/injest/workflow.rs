@@ -0,0 +1,60 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+// where a page is on its way to being published, `state = "review"` in its front matter. Pages
+// without one are published, the site has always worked that way.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkflowState {
+    Draft,
+    Review,
+    #[default]
+    Published,
+}
+
+impl WorkflowState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkflowState::Draft => "draft",
+            WorkflowState::Review => "review",
+            WorkflowState::Published => "published",
+        }
+    }
+
+    pub fn parse(state: &str) -> Option<WorkflowState> {
+        match state {
+            "draft" => Some(WorkflowState::Draft),
+            "review" => Some(WorkflowState::Review),
+            "published" => Some(WorkflowState::Published),
+            _ => None,
+        }
+    }
+}
+
+// `[workflow]` in site.toml, who besides an admin may see pages that aren't published yet. A
+// page's own `reviewers` may see it while it's in review too.
+//
+// [workflow]
+// draft = ["editor"]
+// review = ["editor", "copyedit"]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkflowOptions {
+    #[serde(default)]
+    pub draft: BTreeSet<String>,
+    #[serde(default)]
+    pub review: BTreeSet<String>,
+}
+
+impl WorkflowOptions {
+    // the roles that may see a page in `state` with `reviewers`, nobody is kept from a published one
+    pub fn roles(&self, state: WorkflowState, reviewers: &BTreeSet<String>) -> BTreeSet<String> {
+        match state {
+            WorkflowState::Draft => self.draft.clone(),
+            WorkflowState::Review => self.review.union(reviewers).cloned().collect(),
+            WorkflowState::Published => BTreeSet::new(),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use crate::config::Config;
+use sea_orm::DatabaseConnection;
+use tokio::sync::RwLock;
+
+use crate::injest::access::AccessRule;
+use crate::injest::build::BuildInformation;
+use crate::injest::cache_policy::CachePolicy;
+use crate::injest::security_headers::SecurityHeaders;
+use crate::injest::templates::SiteTheme;
+use crate::injest::theme_docs::ThemeCalls;
+use crate::plugin::route::PluginRoute;
+use crate::serve::builds::BuildQueue;
+use crate::serve::cache::ResponseCache;
+use crate::serve::dynamic::DynamicSite;
+use crate::serve::search::SearchIndex;
+use std::sync::Arc;
+
+pub mod config;
+pub mod embed;
+pub mod injest;
+pub mod models;
+pub mod plugin;
+pub mod serve;
+pub mod util;
+
+pub use crate::embed::SiteBuild;
+pub use crate::injest::build::BuiltSite;
+pub use crate::injest::report::BuildReport;
+pub use crate::injest::site::BuildOptions;
+
+pub const SITE_CONTENT: &str = "sitecontents";
+pub const SERVE_DIR: &str = "srv";
+pub const CACHE_DIR: &str = "cache";
+// fingerprinted static files, shared by the builds still kept
+pub const BLOB_DIR: &str = "blobs";
+
+pub struct State {
+    pub database: DatabaseConnection,
+    pub cache: ResponseCache,
+    pub config: Config,
+    // loaded at startup from THEME, read again by every build
+    pub theme: RwLock<Arc<SiteTheme>>,
+    // plugin scripts serving their own url prefixes
+    pub routes: Vec<Arc<PluginRoute>>,
+    // `[[access]]` from site.toml
+    pub access: Vec<AccessRule>,
+    // `[cache]` from site.toml
+    pub cache_policy: CachePolicy,
+    // `[headers]` from site.toml
+    pub security_headers: SecurityHeaders,
+    // set by whatever finishes a build, /readyz waits for it
+    pub last_successful_build: RwLock<Option<BuildInformation>>,
+    // how often the theme's scripts ran in that build, for /api/admin/theme
+    pub theme_calls: RwLock<ThemeCalls>,
+    // the dynamic pages of that build, rendered per request
+    pub dynamic: RwLock<Option<Arc<DynamicSite>>>,
+    pub builds: BuildQueue,
+    pub search: SearchIndex,
+}
@@ -1,38 +1,200 @@
-#![feature(async_iterator)]
-#![feature(async_iter_from_iter)]
-#![feature(arc_unwrap_or_clone)]
-#![feature(path_file_prefix)]
-use crate::config::Config;
-use axum::body::Bytes;
-use moka::future::Cache;
-use sea_orm::DatabaseConnection;
-use tokio::sync::Mutex;
-
-use crate::injest::templates::SiteTheme;
-#[cfg(not(target_env = "msvc"))]
+use clap::{Parser, Subcommand};
+use color_eyre::{Report, Result};
+use moklog::config::Config;
+use moklog::injest::{
+    config_meta::ConfigMeta,
+    context_schema::context_schema,
+    disqus::{import_disqus, parse_export},
+    dry_run::dry_run,
+    templates::build_site_theme,
+    theme_test::{test_theme, CaseOutcome},
+};
+use moklog::serve::{self, doctor::doctor, openapi::openapi};
+use moklog::SITE_CONTENT;
+use sea_orm::Database;
+use std::path::PathBuf;
+#[cfg(all(feature = "jemalloc", not(windows)))]
 use tikv_jemallocator::Jemalloc;
 
-#[cfg(not(target_env = "msvc"))]
+// an allocator is the binary's choice, a program embedding moklog keeps its own
+#[cfg(all(feature = "jemalloc", not(windows)))]
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
-mod config;
-mod injest;
-mod models;
-mod plugin;
-mod util;
+#[derive(Parser)]
+#[command(author, version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
 
-pub const SITE_CONTENT: &str = "sitecontents";
-pub const SERVE_DIR: &str = "srv";
+#[derive(Subcommand)]
+enum Commands {
+    /// Check the `.moklog` files of a content directory and explain any problems
+    Check {
+        #[arg(default_value = SITE_CONTENT)]
+        path: PathBuf,
+        /// Also run the whole build against this theme, without writing anything the server uses
+        #[arg(long)]
+        theme: Option<String>,
+        /// Print the report as json
+        #[arg(long)]
+        json: bool,
+        /// The json report of an earlier run, to show which pages would change
+        #[arg(long)]
+        base: Option<PathBuf>,
+    },
+    /// Print example `.moklog` files with every option filled in
+    ExampleConfig,
+    /// Tools for theme authors
+    Theme {
+        #[command(subcommand)]
+        command: ThemeCommands,
+    },
+    /// Check the environment, database, content repository and directories the server needs, and
+    /// say how to fix what's wrong
+    Doctor {
+        /// Load this theme instead of the one THEME points to
+        #[arg(long)]
+        theme: Option<String>,
+    },
+    /// Import the approved comments of a Disqus xml export onto the pages their threads were on,
+    /// through redirects from old urls
+    ImportDisqus {
+        path: PathBuf,
+        /// Only list which thread goes to which page
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print the OpenAPI document of the server's http api, for generating clients
+    Openapi,
+    /// Run the moklog server (the default)
+    Serve,
+}
 
-pub struct State {
-    pub database: DatabaseConnection,
-    pub cache: Cache<String, Bytes>,
-    pub config: Config,
-    pub theme: Option<SiteTheme>,
-    pub build_mutex: Mutex<()>,
+#[derive(Subcommand)]
+enum ThemeCommands {
+    /// Render a corpus of test pages with a theme and compare them against its golden files
+    Test {
+        path: PathBuf,
+        /// Write the output as the new golden files instead of comparing
+        #[arg(long)]
+        bless: bool,
+    },
+    /// Print the json schema of the variables templates are rendered with
+    Schema {
+        /// Describe the options of this theme under `site.theme`
+        path: Option<PathBuf>,
+    },
 }
 
-fn main() {
-    println!("Hello, world!");
+#[tokio::main]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+    let cli = Cli::parse();
+
+    match cli.command {
+        Some(Commands::Check {
+            path,
+            theme,
+            json,
+            base,
+        }) => {
+            let report = dry_run(&path, theme.as_deref(), base.as_deref()).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                for diagnostic in report.errors.iter().chain(&report.warnings) {
+                    println!("{diagnostic}");
+                }
+                if base.is_some() {
+                    for change in &report.diff {
+                        println!("{} {}", change.kind.name(), change.slug);
+                    }
+                }
+            }
+            if !report.ok {
+                return Err(Report::msg("check failed"));
+            }
+            if !json {
+                println!("{}: ok", path.display());
+            }
+        }
+        Some(Commands::ExampleConfig) => {
+            print!("{}", ConfigMeta::example_document()?);
+        }
+        Some(Commands::Theme {
+            command: ThemeCommands::Test { path, bless },
+        }) => {
+            let theme = build_site_theme(path.to_string_lossy()).await?;
+            let outcomes = test_theme(&theme, &path, bless)?;
+            for (case, outcome) in &outcomes {
+                match outcome {
+                    CaseOutcome::Passed => println!("{case}: ok"),
+                    CaseOutcome::Rendered => println!("{case}: rendered, no golden file"),
+                    CaseOutcome::Blessed => println!("{case}: blessed"),
+                    CaseOutcome::Skipped => {
+                        println!("{case}: skipped, the theme has no template for it")
+                    }
+                    CaseOutcome::Failed(why) => println!("{case}: failed to render: {why}"),
+                    CaseOutcome::Mismatch { golden, line } => println!(
+                        "{case}: differs from {} starting at line {line}",
+                        golden.display()
+                    ),
+                }
+            }
+            if outcomes.iter().any(|(_, outcome)| outcome.is_failure()) {
+                return Err(Report::msg("theme test failed"));
+            }
+        }
+        Some(Commands::Theme {
+            command: ThemeCommands::Schema { path },
+        }) => {
+            let theme = match path {
+                Some(path) => Some(build_site_theme(path.to_string_lossy()).await?),
+                None => None,
+            };
+            let schema = context_schema(theme.as_ref().map(|theme| &theme.metadata))?;
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+        }
+        Some(Commands::Doctor { theme }) => {
+            let checks = doctor(theme.as_deref()).await;
+            for check in &checks {
+                println!("{check}");
+            }
+            if checks.iter().any(|check| !check.is_ok()) {
+                return Err(Report::msg("doctor found problems"));
+            }
+        }
+        Some(Commands::ImportDisqus { path, dry_run }) => {
+            let config = Config::new()?;
+            let export = parse_export(&std::fs::read_to_string(&path)?)?;
+            let database = Database::connect(config.postgres()).await?;
+            let import = import_disqus(&database, &export, config.site_url(), dry_run).await?;
+            for outcome in &import.threads {
+                match &outcome.page {
+                    Some(page) => println!("{} -> {page}", outcome.thread.link),
+                    None => println!(
+                        "{}: no page, {} comments left out",
+                        outcome.thread.link, outcome.comments
+                    ),
+                }
+            }
+            if !dry_run {
+                println!("imported {} comments", import.imported);
+            }
+            if import.unmatched().next().is_some() {
+                println!("add redirects from the old urls above and import again for the rest");
+            }
+        }
+        Some(Commands::Openapi) => {
+            let document = openapi(&Config::offline()?)?;
+            println!("{}", serde_json::to_string_pretty(&document)?);
+        }
+        Some(Commands::Serve) | None => {
+            serve::run(Config::new()?).await?;
+        }
+    }
+
+    Ok(())
 }
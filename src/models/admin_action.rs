@@ -0,0 +1,99 @@
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use schemars::JsonSchema;
+use sea_orm::entity::prelude::*;
+use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::{QueryOrder, QuerySelect};
+use serde::Serialize;
+
+// What admins changed, for when more than one person has the key. Append only: nothing here
+// updates or deletes a row, revoking UPDATE and DELETE on the table from the server's database role
+// makes that hold for anyone else too.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, JsonSchema)]
+#[sea_orm(table_name = "admin_actions")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub at: DateTimeUtc,
+    // `session:<id prefix>`, `key` or `proxy:<user>`
+    pub actor: String,
+    pub ip: String,
+    pub method: String,
+    pub path: String,
+    // the query and form fields sent, credentials left out
+    pub summary: String,
+    // what it was answered with, refused and failed attempts are kept too
+    pub status: i32,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewAction {
+    pub actor: String,
+    pub ip: String,
+    pub method: String,
+    pub path: String,
+    pub summary: String,
+    pub status: u16,
+}
+
+pub async fn record(db: &DatabaseConnection, action: NewAction) -> Result<()> {
+    ActiveModel {
+        id: NotSet,
+        at: Set(Utc::now()),
+        actor: Set(action.actor),
+        ip: Set(action.ip),
+        method: Set(action.method),
+        path: Set(action.path),
+        summary: Set(action.summary),
+        status: Set(action.status.into()),
+    }
+    .insert(db)
+    .await?;
+    Ok(())
+}
+
+// what to look for, everything left None matches
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ActionFilter {
+    pub actor: Option<String>,
+    pub ip: Option<String>,
+    // a path prefix, `/api/admin/builds`
+    pub path: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+// newest first
+pub async fn find(
+    db: &DatabaseConnection,
+    filter: &ActionFilter,
+    limit: u64,
+) -> Result<Vec<Model>> {
+    let mut query = Entity::find();
+    if let Some(actor) = &filter.actor {
+        query = query.filter(Column::Actor.eq(actor.as_str()));
+    }
+    if let Some(ip) = &filter.ip {
+        query = query.filter(Column::Ip.eq(ip.as_str()));
+    }
+    if let Some(path) = &filter.path {
+        query = query.filter(Column::Path.starts_with(path));
+    }
+    if let Some(since) = filter.since {
+        query = query.filter(Column::At.gte(since));
+    }
+    if let Some(until) = filter.until {
+        query = query.filter(Column::At.lt(until));
+    }
+    Ok(query
+        .order_by_desc(Column::At)
+        .order_by_desc(Column::Id)
+        .limit(limit)
+        .all(db)
+        .await?)
+}
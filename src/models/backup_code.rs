@@ -0,0 +1,75 @@
+use chrono::Utc;
+use color_eyre::Result;
+use sea_orm::entity::prelude::*;
+use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::{sea_query::Expr, TransactionTrait};
+use sha2::{Digest, Sha256};
+
+// One time codes for signing in without the authenticator app. Only their hashes are stored, the
+// codes themselves are shown once when they're made.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "backup_codes")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub hash: String,
+    pub created: DateTimeUtc,
+    pub used: Option<DateTimeUtc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+// `ABCDE-12345` and `abcde12345` are the same code
+fn code_hash(code: &str) -> String {
+    let code = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect::<String>();
+    hex::encode(Sha256::digest(code.as_bytes()))
+}
+
+// the codes from before are gone, used or not
+pub async fn replace(db: &DatabaseConnection, codes: &[String]) -> Result<()> {
+    let txn = db.begin().await?;
+    Entity::delete_many().exec(&txn).await?;
+    let now = Utc::now();
+    for code in codes {
+        ActiveModel {
+            id: NotSet,
+            hash: Set(code_hash(code)),
+            created: Set(now),
+            used: Set(None),
+        }
+        .insert(&txn)
+        .await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+// uses up `code`, false if it isn't one or was used already
+pub async fn redeem(db: &DatabaseConnection, code: &str) -> Result<bool> {
+    let updated = Entity::update_many()
+        .col_expr(Column::Used, Expr::value(Some(Utc::now())))
+        .filter(Column::Hash.eq(code_hash(code)))
+        .filter(Column::Used.is_null())
+        .exec(db)
+        .await?
+        .rows_affected;
+    Ok(updated > 0)
+}
+
+pub async fn remaining(db: &DatabaseConnection) -> Result<u64> {
+    Ok(Entity::find()
+        .filter(Column::Used.is_null())
+        .count(db)
+        .await?)
+}
+
+pub async fn delete_all(db: &DatabaseConnection) -> Result<u64> {
+    Ok(Entity::delete_many().exec(db).await?.rows_affected)
+}
@@ -0,0 +1,90 @@
+use crate::injest::diff::{DiffKind, SiteContentDiffElem};
+use color_eyre::{Report, Result};
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveValue::Set, QueryOrder};
+use std::collections::BTreeSet;
+
+// the pages a build changed, kept per build
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "build_diff")]
+pub struct Model {
+    // postgres has no unsigned integers, build ids fit anyway
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub build_id: i64,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub slug: String,
+    pub kind: String,
+    pub old_hash: Option<String>,
+    pub new_hash: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+fn build_key(build_id: u64) -> Result<i64> {
+    i64::try_from(build_id).map_err(|_| Report::msg(format!("build id {build_id} is too large")))
+}
+
+pub async fn save(
+    db: &impl ConnectionTrait,
+    build_id: u64,
+    diff: &[SiteContentDiffElem],
+) -> Result<()> {
+    if diff.is_empty() {
+        return Ok(());
+    }
+    let build_id = build_key(build_id)?;
+    Entity::insert_many(diff.iter().map(|elem| ActiveModel {
+        build_id: Set(build_id),
+        slug: Set(elem.slug.clone()),
+        kind: Set(elem.kind.name().to_string()),
+        old_hash: Set(elem.old_hash.clone()),
+        new_hash: Set(elem.new_hash.clone()),
+    }))
+    .exec(db)
+    .await?;
+    Ok(())
+}
+
+pub async fn for_build(db: &DatabaseConnection, build_id: u64) -> Result<Vec<SiteContentDiffElem>> {
+    Entity::find()
+        .filter(Column::BuildId.eq(build_key(build_id)?))
+        .order_by_asc(Column::Slug)
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| {
+            Ok(SiteContentDiffElem {
+                kind: DiffKind::from_name(&model.kind)
+                    .ok_or_else(|| Report::msg(format!("unknown diff kind {}", model.kind)))?,
+                slug: model.slug,
+                old_hash: model.old_hash,
+                new_hash: model.new_hash,
+            })
+        })
+        .collect()
+}
+
+// every build with a diff, newest last
+pub async fn build_ids(db: &DatabaseConnection) -> Result<BTreeSet<u64>> {
+    Ok(Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| model.build_id as u64)
+        .collect())
+}
+
+pub async fn delete_builds(db: &DatabaseConnection, build_ids: &[u64]) -> Result<()> {
+    let keys = build_ids
+        .iter()
+        .map(|build_id| build_key(*build_id))
+        .collect::<Result<Vec<_>>>()?;
+    Entity::delete_many()
+        .filter(Column::BuildId.is_in(keys))
+        .exec(db)
+        .await?;
+    Ok(())
+}
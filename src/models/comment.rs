@@ -0,0 +1,116 @@
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use schemars::JsonSchema;
+use sea_orm::entity::prelude::*;
+use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::{sea_query::Expr, QueryOrder, TransactionTrait};
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, JsonSchema)]
+#[sea_orm(table_name = "comments")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    // site path of the page it's on
+    pub page: String,
+    // the comment it replies to
+    pub parent: Option<i64>,
+    pub author: String,
+    // never shown, for answering data requests about the author
+    pub email: Option<String>,
+    // html
+    pub body: String,
+    pub created_at: DateTimeUtc,
+    pub approved: bool,
+    // where an imported comment came from, `disqus:<post id>`, None for ones written here
+    pub source: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+// a comment from another system, parents are by their source as well
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportedComment {
+    pub source: String,
+    pub parent: Option<String>,
+    pub page: String,
+    pub author: String,
+    pub email: Option<String>,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Inserts the comments that aren't there yet, oldest first so replies find their parents, and
+// returns how many that was. Importing the same export twice adds nothing the second time.
+pub async fn import(db: &DatabaseConnection, mut comments: Vec<ImportedComment>) -> Result<u64> {
+    comments.sort_by_key(|comment| comment.created_at);
+    let txn = db.begin().await?;
+    let mut ids = Entity::find()
+        .filter(Column::Source.is_not_null())
+        .all(&txn)
+        .await?
+        .into_iter()
+        .filter_map(|model| Some((model.source?, model.id)))
+        .collect::<HashMap<_, _>>();
+
+    let mut imported = 0;
+    for comment in comments {
+        if ids.contains_key(&comment.source) {
+            continue;
+        }
+        let parent = comment
+            .parent
+            .as_ref()
+            .and_then(|parent| ids.get(parent))
+            .copied();
+        let model = ActiveModel {
+            id: NotSet,
+            page: Set(comment.page),
+            parent: Set(parent),
+            author: Set(comment.author),
+            email: Set(comment.email),
+            body: Set(comment.body),
+            created_at: Set(comment.created_at),
+            approved: Set(true),
+            source: Set(Some(comment.source.clone())),
+        }
+        .insert(&txn)
+        .await?;
+        ids.insert(comment.source, model.id);
+        imported += 1;
+    }
+    txn.commit().await?;
+    Ok(imported)
+}
+
+pub async fn by_email(db: &DatabaseConnection, email: &str) -> Result<Vec<Model>> {
+    Ok(Entity::find()
+        .filter(Column::Email.eq(email))
+        .order_by_asc(Column::CreatedAt)
+        .all(db)
+        .await?)
+}
+
+// replies to the deleted comments stay, as replies to nothing
+pub async fn delete_by_email(db: &DatabaseConnection, email: &str) -> Result<u64> {
+    Ok(Entity::delete_many()
+        .filter(Column::Email.eq(email))
+        .exec(db)
+        .await?
+        .rows_affected)
+}
+
+// drops the emails of comments written before `before`, the comments themselves stay
+pub async fn forget_emails(db: &DatabaseConnection, before: DateTime<Utc>) -> Result<u64> {
+    Ok(Entity::update_many()
+        .col_expr(Column::Email, Expr::value(Option::<String>::None))
+        .filter(Column::Email.is_not_null())
+        .filter(Column::CreatedAt.lt(before))
+        .exec(db)
+        .await?
+        .rows_affected)
+}
@@ -0,0 +1,62 @@
+use chrono::Utc;
+use color_eyre::{Report, Result};
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveValue::Set, QueryOrder};
+use std::collections::BTreeSet;
+
+// the cid every build was published to ipfs as
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "ipfs_publish")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub build_id: i64,
+    pub cid: String,
+    pub published: DateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub async fn record(db: &DatabaseConnection, build_id: u64, cid: &str) -> Result<()> {
+    let build_id = i64::try_from(build_id)
+        .map_err(|_| Report::msg(format!("build id {build_id} is too large")))?;
+    ActiveModel {
+        build_id: Set(build_id),
+        cid: Set(cid.to_string()),
+        published: Set(Utc::now()),
+    }
+    .insert(db)
+    .await?;
+    Ok(())
+}
+
+pub async fn latest(db: &DatabaseConnection) -> Result<Option<Model>> {
+    Ok(Entity::find()
+        .order_by_desc(Column::Published)
+        .one(db)
+        .await?)
+}
+
+// every build that was published, newest last
+pub async fn build_ids(db: &DatabaseConnection) -> Result<BTreeSet<u64>> {
+    Ok(Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| model.build_id as u64)
+        .collect())
+}
+
+pub async fn delete_builds(db: &DatabaseConnection, build_ids: &[u64]) -> Result<()> {
+    let keys = build_ids
+        .iter()
+        .map(|build_id| *build_id as i64)
+        .collect::<Vec<_>>();
+    Entity::delete_many()
+        .filter(Column::BuildId.is_in(keys))
+        .exec(db)
+        .await?;
+    Ok(())
+}
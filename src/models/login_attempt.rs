@@ -0,0 +1,91 @@
+use chrono::{Duration, Utc};
+use color_eyre::Result;
+use schemars::JsonSchema;
+use sea_orm::entity::prelude::*;
+use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::{sea_query::Expr, QueryOrder};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+// every sign in attempt, what login rate limiting counts
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, JsonSchema)]
+#[sea_orm(table_name = "login_attempts")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub ip: String,
+    pub at: DateTimeUtc,
+    pub success: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+// recorded as failed before anything is checked, so attempts racing each other all see it in
+// `recent_failures`, `succeeded` flips it once the attempt passes
+pub async fn begin(db: &DatabaseConnection, ip: &str) -> Result<i64> {
+    let attempt = ActiveModel {
+        id: NotSet,
+        ip: Set(ip.to_string()),
+        at: Set(Utc::now()),
+        success: Set(false),
+    }
+    .insert(db)
+    .await?;
+    Ok(attempt.id)
+}
+
+pub async fn succeeded(db: &DatabaseConnection, id: i64) -> Result<()> {
+    Entity::update_many()
+        .col_expr(Column::Success, Expr::value(true))
+        .filter(Column::Id.eq(id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn recent_failures(db: &DatabaseConnection, ip: &str, window: Duration) -> Result<u64> {
+    Ok(Entity::find()
+        .filter(Column::Ip.eq(ip))
+        .filter(Column::Success.eq(false))
+        .filter(Column::At.gt(Utc::now() - window))
+        .count(db)
+        .await?)
+}
+
+// anything older than `window` doesn't count for anything anymore
+pub async fn prune(db: &DatabaseConnection, window: Duration) -> Result<u64> {
+    Ok(Entity::delete_many()
+        .filter(Column::At.lte(Utc::now() - window))
+        .exec(db)
+        .await?
+        .rows_affected)
+}
+
+// every address with an attempt, for finding the ones behind an ip hash
+pub async fn ips(db: &DatabaseConnection) -> Result<BTreeSet<String>> {
+    Ok(Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|attempt| attempt.ip)
+        .collect())
+}
+
+pub async fn by_ips(db: &DatabaseConnection, ips: &[String]) -> Result<Vec<Model>> {
+    Ok(Entity::find()
+        .filter(Column::Ip.is_in(ips.iter().cloned()))
+        .order_by_asc(Column::At)
+        .all(db)
+        .await?)
+}
+
+pub async fn delete_by_ips(db: &DatabaseConnection, ips: &[String]) -> Result<u64> {
+    Ok(Entity::delete_many()
+        .filter(Column::Ip.is_in(ips.iter().cloned()))
+        .exec(db)
+        .await?
+        .rows_affected)
+}
@@ -1,3 +1,18 @@
 pub mod template;
+pub mod admin_action;
 pub mod article;
 pub mod article_histories;
+pub mod backup_code;
+pub mod build_diff;
+pub mod comment;
+pub mod ipfs_publish;
+pub mod login_attempt;
+pub mod page_access;
+pub mod page_hash;
+pub mod plugin_kv;
+pub mod published_page;
+pub mod redirect;
+pub mod review_assignment;
+pub mod session;
+pub mod totp_secret;
+pub mod translation_suggestion;
@@ -0,0 +1,102 @@
+use crate::injest::generate::PageAccess;
+use crate::injest::workflow::WorkflowState;
+use color_eyre::Result;
+use itertools::Itertools;
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveValue::Set, TransactionTrait};
+use std::collections::{BTreeMap, BTreeSet};
+
+// pages of the last build that aren't public, public pages have no row
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "page_access")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub path: String,
+    pub unlisted: bool,
+    pub private: bool,
+    pub state: String,
+    // comma separated, like in role tokens
+    pub reviewers: String,
+    pub roles: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub fn split_roles(roles: &str) -> BTreeSet<String> {
+    roles
+        .split(',')
+        .filter(|role| !role.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+impl From<Model> for PageAccess {
+    fn from(model: Model) -> Self {
+        PageAccess {
+            unlisted: model.unlisted,
+            private: model.private,
+            // a row from before workflow states is a published page
+            state: WorkflowState::parse(&model.state).unwrap_or_default(),
+            reviewers: split_roles(&model.reviewers),
+            roles: split_roles(&model.roles),
+        }
+    }
+}
+
+pub async fn find_access(db: &DatabaseConnection, path: &str) -> Result<Option<PageAccess>> {
+    Ok(Entity::find_by_id(path.to_string())
+        .one(db)
+        .await?
+        .map(PageAccess::from))
+}
+
+// the rows of `paths` there are, by path
+pub async fn find_many(
+    db: &DatabaseConnection,
+    paths: &[String],
+) -> Result<BTreeMap<String, PageAccess>> {
+    Ok(Entity::find()
+        .filter(Column::Path.is_in(paths.iter().cloned()))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| (model.path.clone(), PageAccess::from(model)))
+        .collect())
+}
+
+// every page in `state`, by path
+pub async fn in_state(
+    db: &DatabaseConnection,
+    state: WorkflowState,
+) -> Result<BTreeMap<String, PageAccess>> {
+    Ok(Entity::find()
+        .filter(Column::State.eq(state.as_str()))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| (model.path.clone(), PageAccess::from(model)))
+        .collect())
+}
+
+// swap out the access of the previous build for the one of the latest build
+pub async fn replace(db: &DatabaseConnection, access: &BTreeMap<String, PageAccess>) -> Result<()> {
+    let txn = db.begin().await?;
+    Entity::delete_many().exec(&txn).await?;
+    if !access.is_empty() {
+        Entity::insert_many(access.iter().map(|(path, access)| ActiveModel {
+            path: Set(path.clone()),
+            unlisted: Set(access.unlisted),
+            private: Set(access.private),
+            state: Set(access.state.as_str().to_string()),
+            reviewers: Set(access.reviewers.iter().join(",")),
+            roles: Set(access.roles.iter().join(",")),
+        }))
+        .exec(&txn)
+        .await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
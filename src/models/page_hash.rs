@@ -0,0 +1,41 @@
+use color_eyre::Result;
+use sea_orm::entity::prelude::*;
+use sea_orm::ActiveValue::Set;
+use std::collections::BTreeMap;
+
+// the pages of the last finished build, what the next build is diffed against
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "page_hash")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub path: String,
+    pub hash: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub async fn all(db: &impl ConnectionTrait) -> Result<BTreeMap<String, String>> {
+    Ok(Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| (model.path, model.hash))
+        .collect())
+}
+
+pub async fn replace(db: &impl ConnectionTrait, pages: &BTreeMap<String, String>) -> Result<()> {
+    Entity::delete_many().exec(db).await?;
+    if pages.is_empty() {
+        return Ok(());
+    }
+    Entity::insert_many(pages.iter().map(|(path, hash)| ActiveModel {
+        path: Set(path.clone()),
+        hash: Set(hash.clone()),
+    }))
+    .exec(db)
+    .await?;
+    Ok(())
+}
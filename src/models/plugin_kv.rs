@@ -0,0 +1,67 @@
+use color_eyre::Result;
+use sea_orm::entity::prelude::*;
+use sea_orm::{sea_query::OnConflict, ActiveValue::Set, QuerySelect};
+
+// key value storage for plugin routes, every plugin only ever sees its own keys
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "plugin_kv")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub plugin: String,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub async fn get(db: &DatabaseConnection, plugin: &str, key: &str) -> Result<Option<String>> {
+    Ok(Entity::find_by_id((plugin.to_string(), key.to_string()))
+        .one(db)
+        .await?
+        .map(|model| model.value))
+}
+
+pub async fn set(db: &DatabaseConnection, plugin: &str, key: &str, value: &str) -> Result<()> {
+    Entity::insert(ActiveModel {
+        plugin: Set(plugin.to_string()),
+        key: Set(key.to_string()),
+        value: Set(value.to_string()),
+    })
+    .on_conflict(
+        OnConflict::columns([Column::Plugin, Column::Key])
+            .update_column(Column::Value)
+            .to_owned(),
+    )
+    .exec(db)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete(db: &DatabaseConnection, plugin: &str, key: &str) -> Result<()> {
+    Entity::delete_by_id((plugin.to_string(), key.to_string()))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+// keys starting with `prefix`, at most `limit` of them
+pub async fn keys(
+    db: &DatabaseConnection,
+    plugin: &str,
+    prefix: &str,
+    limit: u64,
+) -> Result<Vec<String>> {
+    Ok(Entity::find()
+        .filter(Column::Plugin.eq(plugin))
+        .filter(Column::Key.starts_with(prefix))
+        .limit(limit)
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| model.key)
+        .collect())
+}
@@ -0,0 +1,54 @@
+use chrono::Utc;
+use color_eyre::Result;
+use sea_orm::entity::prelude::*;
+use sea_orm::{sea_query::OnConflict, ActiveValue::Set};
+use std::collections::HashSet;
+
+// every site path that has ever been built, so a page is only announced the first time
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "published_page")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub path: String,
+    pub first_published: DateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+// Records `paths` and returns the ones that were not there before. The very first build only
+// records, or every page the site already had would count as new.
+pub async fn first_published(db: &DatabaseConnection, paths: &[String]) -> Result<Vec<String>> {
+    if paths.is_empty() {
+        return Ok(vec![]);
+    }
+    let seeded = Entity::find().one(db).await?.is_some();
+
+    let known = Entity::find()
+        .filter(Column::Path.is_in(paths.iter().cloned()))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| model.path)
+        .collect::<HashSet<_>>();
+    let new = paths
+        .iter()
+        .filter(|path| !known.contains(*path))
+        .cloned()
+        .collect::<Vec<_>>();
+    if new.is_empty() {
+        return Ok(new);
+    }
+
+    let now = Utc::now();
+    Entity::insert_many(new.iter().map(|path| ActiveModel {
+        path: Set(path.clone()),
+        first_published: Set(now),
+    }))
+    .on_conflict(OnConflict::column(Column::Path).do_nothing().to_owned())
+    .exec(db)
+    .await?;
+    Ok(if seeded { new } else { vec![] })
+}
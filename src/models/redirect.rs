@@ -0,0 +1,118 @@
+use crate::injest::{links::SiteUrl, redirect::RedirectEntry};
+use color_eyre::Result;
+use schemars::JsonSchema;
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveValue::Set, TransactionTrait};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, JsonSchema)]
+#[sea_orm(table_name = "redirects")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub from_path: String,
+    pub to_path: String,
+    pub permanent: bool,
+    // written by the build, as opposed to added by hand
+    pub generated: bool,
+    // generated from a category that was renamed, kept across builds since the build after the
+    // rename has no way of knowing about it again
+    pub renamed: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub async fn find_redirect(db: &DatabaseConnection, path: &str) -> Result<Option<Model>> {
+    Ok(Entity::find_by_id(path.to_string()).one(db).await?)
+}
+
+// from -> to, of every redirect
+pub async fn all(db: &DatabaseConnection) -> Result<BTreeMap<String, String>> {
+    Ok(Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| (model.from_path, model.to_path))
+        .collect())
+}
+
+// swap out every build generated redirect with the ones from the latest build
+pub async fn replace_generated(db: &DatabaseConnection, redirects: &[RedirectEntry]) -> Result<()> {
+    let txn = db.begin().await?;
+    Entity::delete_many()
+        .filter(Column::Generated.eq(true))
+        .filter(Column::Renamed.eq(false))
+        .exec(&txn)
+        .await?;
+    for redirect in redirects {
+        // hand written redirects win over generated ones
+        if Entity::find_by_id(redirect.from.clone())
+            .one(&txn)
+            .await?
+            .is_some()
+        {
+            continue;
+        }
+        ActiveModel {
+            from_path: Set(redirect.from.clone()),
+            to_path: Set(redirect.to.clone()),
+            permanent: Set(redirect.permanent),
+            generated: Set(true),
+            renamed: Set(false),
+        }
+        .insert(&txn)
+        .await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+// Redirects for renamed categories, on top of the ones from before. Redirects that pointed at a
+// path that moved now point where it went, so a category renamed twice doesn't redirect twice,
+// and one whose old path is a page again (`live`) is dropped.
+pub async fn record_renamed(
+    db: &DatabaseConnection,
+    redirects: &[RedirectEntry],
+    urls: &SiteUrl,
+    live: &BTreeMap<String, String>,
+) -> Result<()> {
+    let txn = db.begin().await?;
+    Entity::delete_many()
+        .filter(Column::Renamed.eq(true))
+        .filter(Column::FromPath.is_in(live.keys().cloned()))
+        .exec(&txn)
+        .await?;
+    for redirect in redirects {
+        for mut earlier in Entity::find()
+            .filter(Column::ToPath.eq(urls.link(&redirect.from)))
+            .all(&txn)
+            .await?
+            .into_iter()
+            .map(ActiveModel::from)
+        {
+            earlier.to_path = Set(redirect.to.clone());
+            earlier.update(&txn).await?;
+        }
+        match Entity::find_by_id(redirect.from.clone()).one(&txn).await? {
+            // hand written redirects win over generated ones
+            Some(existing) if !existing.generated => continue,
+            Some(existing) => {
+                existing.delete(&txn).await?;
+            }
+            None => {}
+        }
+        ActiveModel {
+            from_path: Set(redirect.from.clone()),
+            to_path: Set(redirect.to.clone()),
+            permanent: Set(redirect.permanent),
+            generated: Set(true),
+            renamed: Set(true),
+        }
+        .insert(&txn)
+        .await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
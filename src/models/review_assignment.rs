@@ -0,0 +1,61 @@
+use crate::models::page_access::split_roles;
+use chrono::Utc;
+use color_eyre::Result;
+use itertools::Itertools;
+use sea_orm::entity::prelude::*;
+use sea_orm::{sea_query::OnConflict, ActiveValue::Set};
+use std::collections::{BTreeMap, BTreeSet};
+
+// Reviewers an admin assigned to a page through the api, on top of the ones in its front matter.
+// Kept across builds, unlike page_access, so an assignment outlives the build that follows it.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "review_assignment")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub path: String,
+    // comma separated, like in role tokens
+    pub roles: String,
+    pub assigned: DateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+// replaces whoever was assigned to `path` before, no roles unassigns everyone
+pub async fn assign(db: &DatabaseConnection, path: &str, roles: &BTreeSet<String>) -> Result<()> {
+    if roles.is_empty() {
+        Entity::delete_by_id(path.to_string()).exec(db).await?;
+        return Ok(());
+    }
+    Entity::insert(ActiveModel {
+        path: Set(path.to_string()),
+        roles: Set(roles.iter().join(",")),
+        assigned: Set(Utc::now()),
+    })
+    .on_conflict(
+        OnConflict::column(Column::Path)
+            .update_columns([Column::Roles, Column::Assigned])
+            .to_owned(),
+    )
+    .exec(db)
+    .await?;
+    Ok(())
+}
+
+pub async fn for_page(db: &DatabaseConnection, path: &str) -> Result<BTreeSet<String>> {
+    Ok(Entity::find_by_id(path.to_string())
+        .one(db)
+        .await?
+        .map_or_else(BTreeSet::new, |model| split_roles(&model.roles)))
+}
+
+pub async fn all(db: &DatabaseConnection) -> Result<BTreeMap<String, BTreeSet<String>>> {
+    Ok(Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| (model.path, split_roles(&model.roles)))
+        .collect())
+}
@@ -0,0 +1,107 @@
+use chrono::{Duration, Utc};
+use color_eyre::Result;
+use rand::{distributions::Alphanumeric, Rng};
+use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::Expr;
+use sea_orm::ActiveValue::Set;
+use sha2::{Digest, Sha256};
+
+// A signed in browser. Only the hash of the cookie is stored, so the table alone can't be used to
+// sign in as anyone.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "sessions")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: String,
+    pub admin: bool,
+    // what forms posted with this session have to send back
+    pub csrf: String,
+    pub created: DateTimeUtc,
+    pub expires: DateTimeUtc,
+    // signed in with a code from the authenticator app, or a backup code
+    pub second_factor: bool,
+    // until when destructive admin actions go through without asking for a code again
+    pub elevated: Option<DateTimeUtc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+fn random_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(43)
+        .map(char::from)
+        .collect()
+}
+
+fn session_id(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// the cookie value and the new session, elevated for `elevation` if it passed a second factor
+pub async fn create(
+    db: &DatabaseConnection,
+    admin: bool,
+    lifetime: Duration,
+    second_factor: bool,
+    elevation: Duration,
+) -> Result<(String, Model)> {
+    let token = random_token();
+    let now = Utc::now();
+    let session = ActiveModel {
+        id: Set(session_id(&token)),
+        admin: Set(admin),
+        csrf: Set(random_token()),
+        created: Set(now),
+        expires: Set(now + lifetime),
+        second_factor: Set(second_factor),
+        elevated: Set(second_factor.then(|| now + elevation)),
+    }
+    .insert(db)
+    .await?;
+    Ok((token, session))
+}
+
+// expired sessions are as good as gone
+pub async fn find(db: &DatabaseConnection, token: &str) -> Result<Option<Model>> {
+    Ok(Entity::find_by_id(session_id(token))
+        .filter(Column::Expires.gt(Utc::now()))
+        .one(db)
+        .await?)
+}
+
+pub async fn delete(db: &DatabaseConnection, token: &str) -> Result<()> {
+    Entity::delete_by_id(session_id(token)).exec(db).await?;
+    Ok(())
+}
+
+// a code was just given, `id` is the stored id of the session and not its cookie
+pub async fn elevate(db: &DatabaseConnection, id: &str, elevation: Duration) -> Result<()> {
+    Entity::update_many()
+        .col_expr(Column::SecondFactor, Expr::value(true))
+        .col_expr(Column::Elevated, Expr::value(Some(Utc::now() + elevation)))
+        .filter(Column::Id.eq(id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+// once a second factor is enrolled the sessions signed in without one are signed out
+pub async fn delete_single_factor(db: &DatabaseConnection) -> Result<u64> {
+    Ok(Entity::delete_many()
+        .filter(Column::SecondFactor.eq(false))
+        .exec(db)
+        .await?
+        .rows_affected)
+}
+
+pub async fn delete_expired(db: &DatabaseConnection) -> Result<u64> {
+    Ok(Entity::delete_many()
+        .filter(Column::Expires.lte(Utc::now()))
+        .exec(db)
+        .await?
+        .rows_affected)
+}
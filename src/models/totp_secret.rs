@@ -0,0 +1,96 @@
+use chrono::Utc;
+use color_eyre::Result;
+use sea_orm::entity::prelude::*;
+use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::{sea_query::Expr, Condition, TransactionTrait};
+
+// The admin's authenticator app. A new one waits unconfirmed until a code from it comes back, the
+// confirmed one is what sign ins are checked against.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "totp_secrets")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    // base32, as the app was given it
+    pub secret: String,
+    pub created: DateTimeUtc,
+    pub confirmed: Option<DateTimeUtc>,
+    // the time step of the last code accepted, a code can't be used twice
+    pub last_step: Option<i64>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub async fn confirmed(db: &DatabaseConnection) -> Result<Option<Model>> {
+    Ok(Entity::find()
+        .filter(Column::Confirmed.is_not_null())
+        .one(db)
+        .await?)
+}
+
+pub async fn pending(db: &DatabaseConnection) -> Result<Option<Model>> {
+    Ok(Entity::find()
+        .filter(Column::Confirmed.is_null())
+        .one(db)
+        .await?)
+}
+
+// replaces an enrollment that was never confirmed, a confirmed one stays until this one is
+pub async fn start(db: &DatabaseConnection, secret: &str) -> Result<Model> {
+    let txn = db.begin().await?;
+    Entity::delete_many()
+        .filter(Column::Confirmed.is_null())
+        .exec(&txn)
+        .await?;
+    let model = ActiveModel {
+        id: NotSet,
+        secret: Set(secret.to_string()),
+        created: Set(Utc::now()),
+        confirmed: Set(None),
+        last_step: Set(None),
+    }
+    .insert(&txn)
+    .await?;
+    txn.commit().await?;
+    Ok(model)
+}
+
+// the pending secret becomes the one, the code it was confirmed with is used up
+pub async fn confirm(db: &DatabaseConnection, id: i64, step: i64) -> Result<()> {
+    let txn = db.begin().await?;
+    Entity::delete_many()
+        .filter(Column::Id.ne(id))
+        .exec(&txn)
+        .await?;
+    Entity::update_many()
+        .col_expr(Column::Confirmed, Expr::value(Some(Utc::now())))
+        .col_expr(Column::LastStep, Expr::value(Some(step)))
+        .filter(Column::Id.eq(id))
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+// false if a code of `step` or later was used already, by a request that got there first
+pub async fn use_step(db: &DatabaseConnection, id: i64, step: i64) -> Result<bool> {
+    let updated = Entity::update_many()
+        .col_expr(Column::LastStep, Expr::value(Some(step)))
+        .filter(Column::Id.eq(id))
+        .filter(
+            Condition::any()
+                .add(Column::LastStep.is_null())
+                .add(Column::LastStep.lt(step)),
+        )
+        .exec(db)
+        .await?
+        .rows_affected;
+    Ok(updated == 1)
+}
+
+pub async fn delete_all(db: &DatabaseConnection) -> Result<u64> {
+    Ok(Entity::delete_many().exec(db).await?.rows_affected)
+}
@@ -0,0 +1,191 @@
+use chrono::{DateTime, Duration, Utc};
+use color_eyre::Result;
+use schemars::JsonSchema;
+use sea_orm::entity::prelude::*;
+use sea_orm::{
+    sea_query::Expr,
+    ActiveValue::{NotSet, Set},
+    Condition, QueryOrder,
+};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+pub const PENDING: &str = "pending";
+pub const ACCEPTED: &str = "accepted";
+pub const REJECTED: &str = "rejected";
+
+// a reader's correction to one translation of a page, waiting on an admin
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, JsonSchema)]
+#[sea_orm(table_name = "translation_suggestion")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    // site path of the page, without the language prefix
+    pub page: String,
+    pub language: String,
+    // the translation leaf in the content repo
+    pub source: String,
+    pub original: String,
+    pub suggested: String,
+    // the lines around `original` when it was suggested
+    pub context_before: String,
+    pub context_after: String,
+    pub note: Option<String>,
+    pub submitter: Option<String>,
+    pub ip: String,
+    pub status: String,
+    pub created: DateTimeUtc,
+    pub reviewed: Option<DateTimeUtc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub struct NewSuggestion {
+    pub page: String,
+    pub language: String,
+    pub source: String,
+    pub original: String,
+    pub suggested: String,
+    pub context_before: String,
+    pub context_after: String,
+    pub note: Option<String>,
+    pub submitter: Option<String>,
+    pub ip: String,
+}
+
+pub async fn create(db: &DatabaseConnection, new: NewSuggestion) -> Result<Model> {
+    Ok(ActiveModel {
+        id: NotSet,
+        page: Set(new.page),
+        language: Set(new.language),
+        source: Set(new.source),
+        original: Set(new.original),
+        suggested: Set(new.suggested),
+        context_before: Set(new.context_before),
+        context_after: Set(new.context_after),
+        note: Set(new.note),
+        submitter: Set(new.submitter),
+        ip: Set(new.ip),
+        status: Set(PENDING.to_string()),
+        created: Set(Utc::now()),
+        reviewed: Set(None),
+    }
+    .insert(db)
+    .await?)
+}
+
+// how many an address sent within `window`, what keeps one reader from flooding the queue
+pub async fn recent_from(db: &DatabaseConnection, ip: &str, window: Duration) -> Result<u64> {
+    Ok(Entity::find()
+        .filter(Column::Ip.eq(ip))
+        .filter(Column::Created.gt(Utc::now() - window))
+        .count(db)
+        .await?)
+}
+
+// oldest first, the order they're reviewed in
+pub async fn with_status(db: &DatabaseConnection, status: &str) -> Result<Vec<Model>> {
+    Ok(Entity::find()
+        .filter(Column::Status.eq(status))
+        .order_by_asc(Column::Created)
+        .all(db)
+        .await?)
+}
+
+pub async fn find(db: &DatabaseConnection, ids: &[i64]) -> Result<Vec<Model>> {
+    Ok(Entity::find()
+        .filter(Column::Id.is_in(ids.iter().copied()))
+        .order_by_asc(Column::Created)
+        .all(db)
+        .await?)
+}
+
+// None if there is no such suggestion
+pub async fn review(db: &DatabaseConnection, id: i64, status: &str) -> Result<Option<Model>> {
+    let suggestion = match Entity::find_by_id(id).one(db).await? {
+        Some(suggestion) => suggestion,
+        None => return Ok(None),
+    };
+    let mut suggestion: ActiveModel = suggestion.into();
+    suggestion.status = Set(status.to_string());
+    suggestion.reviewed = Set(Some(Utc::now()));
+    Ok(Some(suggestion.update(db).await?))
+}
+
+// every address a suggestion still has, for finding the ones behind an ip hash
+pub async fn ips(db: &DatabaseConnection) -> Result<BTreeSet<String>> {
+    Ok(Entity::find()
+        .filter(Column::Ip.ne(""))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|suggestion| suggestion.ip)
+        .collect())
+}
+
+// suggestions sent by `email` or from any of `ips`, neither matches nothing
+fn from_subject(email: Option<&str>, ips: &[String]) -> Condition {
+    let mut condition = Condition::any();
+    if let Some(email) = email {
+        condition = condition.add(Column::Submitter.eq(email));
+    }
+    if !ips.is_empty() {
+        condition = condition.add(Column::Ip.is_in(ips.iter().cloned()));
+    }
+    condition
+}
+
+pub async fn by_subject(
+    db: &DatabaseConnection,
+    email: Option<&str>,
+    ips: &[String],
+) -> Result<Vec<Model>> {
+    if email.is_none() && ips.is_empty() {
+        return Ok(vec![]);
+    }
+    Ok(Entity::find()
+        .filter(from_subject(email, ips))
+        .order_by_asc(Column::Created)
+        .all(db)
+        .await?)
+}
+
+pub async fn delete_by_subject(
+    db: &DatabaseConnection,
+    email: Option<&str>,
+    ips: &[String],
+) -> Result<u64> {
+    if email.is_none() && ips.is_empty() {
+        return Ok(0);
+    }
+    Ok(Entity::delete_many()
+        .filter(from_subject(email, ips))
+        .exec(db)
+        .await?
+        .rows_affected)
+}
+
+// blanks the addresses of suggestions sent before `before`, rate limiting only looks at the last
+// hour anyway
+pub async fn forget_ips(db: &DatabaseConnection, before: DateTime<Utc>) -> Result<u64> {
+    Ok(Entity::update_many()
+        .col_expr(Column::Ip, Expr::value(""))
+        .filter(Column::Ip.ne(""))
+        .filter(Column::Created.lt(before))
+        .exec(db)
+        .await?
+        .rows_affected)
+}
+
+pub async fn forget_submitters(db: &DatabaseConnection, before: DateTime<Utc>) -> Result<u64> {
+    Ok(Entity::update_many()
+        .col_expr(Column::Submitter, Expr::value(Option::<String>::None))
+        .filter(Column::Submitter.is_not_null())
+        .filter(Column::Created.lt(before))
+        .exec(db)
+        .await?
+        .rows_affected)
+}
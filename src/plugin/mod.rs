@@ -1 +1,3 @@
-mod rhai;
+pub mod rhai;
+pub mod route;
+pub mod wasm;
@@ -1 +1,2 @@
 mod config;
+pub mod stdlib;
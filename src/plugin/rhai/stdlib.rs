@@ -0,0 +1,321 @@
+use crate::injest::dates::{format_date, parse_date};
+use crate::injest::limits::{BuildLimits, Deadline};
+use crate::CACHE_DIR;
+use chrono::{DateTime, FixedOffset, Utc};
+use color_eyre::{Report, Result};
+use reqwest::redirect::Policy;
+use rhai::{Array, Dynamic, Engine, EvalAltResult};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use url::Url;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_FETCH_BODY: u64 = 1024 * 1024;
+const MAX_FETCH_REDIRECTS: usize = 5;
+const MAX_KV_KEYS: usize = 1000;
+const MAX_KV_VALUE: usize = 64 * 1024;
+
+// `[scripts]` in site.toml, what theme scripts are allowed to do besides computing things
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScriptOptions {
+    // `shell()` runs anything on the build machine, only for themes the site trusts completely
+    #[serde(default)]
+    pub allow_shell: bool,
+    // hosts `http_get` may reach, it can't reach anything if empty
+    #[serde(default)]
+    pub fetch_hosts: Vec<String>,
+    #[serde(default = "default_fetches_per_minute")]
+    pub fetches_per_minute: u32,
+}
+
+fn default_fetches_per_minute() -> u32 {
+    30
+}
+
+impl Default for ScriptOptions {
+    fn default() -> Self {
+        ScriptOptions {
+            allow_shell: false,
+            fetch_hosts: vec![],
+            fetches_per_minute: default_fetches_per_minute(),
+        }
+    }
+}
+
+fn script_error(why: impl ToString) -> Box<EvalAltResult> {
+    why.to_string().into()
+}
+
+// Key value storage that outlives the build, one json file per namespace in the cache. Builds
+// don't have the database, and a theme's cache is exactly as persistent as the rest of the cache.
+struct KvFile {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<String, String>>,
+}
+
+impl KvFile {
+    fn open(namespace: &str) -> Result<KvFile> {
+        let name = namespace
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        let dir = Path::new(CACHE_DIR).join("kv");
+        create_dir_all(&dir)?;
+        let path = dir.join(format!("{name}.json"));
+        let entries = match path.is_file() {
+            true => serde_json::from_str(&read_to_string(&path)?)?,
+            false => BTreeMap::new(),
+        };
+        Ok(KvFile {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    fn get(&self, key: &str) -> Dynamic {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .map(Dynamic::from)
+            .unwrap_or(Dynamic::UNIT)
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<(), Box<EvalAltResult>> {
+        if value.len() > MAX_KV_VALUE {
+            return Err(script_error(format!(
+                "kv values can be at most {MAX_KV_VALUE} bytes"
+            )));
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(key) && entries.len() >= MAX_KV_KEYS {
+            return Err(script_error(format!(
+                "kv can hold at most {MAX_KV_KEYS} keys"
+            )));
+        }
+        entries.insert(key.to_string(), value.to_string());
+        self.flush(&entries)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Box<EvalAltResult>> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.remove(key).is_some() {
+            self.flush(&entries)?;
+        }
+        Ok(())
+    }
+
+    fn keys(&self, prefix: &str) -> Array {
+        self.entries
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .map(Dynamic::from)
+            .collect()
+    }
+
+    fn flush(&self, entries: &BTreeMap<String, String>) -> Result<(), Box<EvalAltResult>> {
+        let json = serde_json::to_string(entries).map_err(script_error)?;
+        write(&self.path, json).map_err(script_error)
+    }
+}
+
+struct Fetcher {
+    hosts: Vec<String>,
+    per_minute: u32,
+    // start of the current minute and how many fetches went out in it
+    window: Mutex<(Instant, u32)>,
+    client: reqwest::blocking::Client,
+}
+
+// an http(s) url on one of `hosts` or a subdomain of one
+fn fetchable(hosts: &[String], url: &Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+        && url.host_str().map_or(false, |host| {
+            hosts
+                .iter()
+                .any(|allowed| host == allowed || host.ends_with(&format!(".{allowed}")))
+        })
+}
+
+// every hop is checked like the first url, an allowed host can't send a script anywhere else
+fn redirect_policy(hosts: Vec<String>) -> Policy {
+    Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_FETCH_REDIRECTS {
+            attempt.error(format!("more than {MAX_FETCH_REDIRECTS} redirects"))
+        } else if fetchable(&hosts, attempt.url()) {
+            attempt.follow()
+        } else {
+            let why = format!(
+                "redirected to {}, not a host under fetch_hosts",
+                attempt.url()
+            );
+            attempt.error(why)
+        }
+    })
+}
+
+impl Fetcher {
+    fn take_slot(&self) -> bool {
+        let mut window = self.window.lock().unwrap();
+        if window.0.elapsed() >= Duration::from_secs(60) {
+            *window = (Instant::now(), 0);
+        }
+        if window.1 >= self.per_minute {
+            return false;
+        }
+        window.1 += 1;
+        true
+    }
+
+    fn get(&self, url: &str) -> Result<String, Box<EvalAltResult>> {
+        let parsed = Url::parse(url).map_err(script_error)?;
+        if !fetchable(&self.hosts, &parsed) {
+            return Err(script_error(format!(
+                "{url} is not on a host under fetch_hosts in [scripts]"
+            )));
+        }
+        if !self.take_slot() {
+            return Err(script_error(format!(
+                "more than {} fetches in a minute",
+                self.per_minute
+            )));
+        }
+
+        let response = self.client.get(parsed).send().map_err(script_error)?;
+        if !response.status().is_success() {
+            return Err(script_error(format!(
+                "{url} answered {}",
+                response.status()
+            )));
+        }
+        let mut body = String::new();
+        response
+            .take(MAX_FETCH_BODY + 1)
+            .read_to_string(&mut body)
+            .map_err(script_error)?;
+        if body.len() as u64 > MAX_FETCH_BODY {
+            return Err(script_error(format!(
+                "{url} is larger than {MAX_FETCH_BODY} bytes"
+            )));
+        }
+        Ok(body)
+    }
+}
+
+fn date(text: &str, offset: &FixedOffset) -> Result<DateTime<FixedOffset>, Box<EvalAltResult>> {
+    parse_date(text, offset).ok_or_else(|| script_error(format!("\"{text}\" is not a date")))
+}
+
+// What every theme script gets on top of plain rhai: fetching from allowed hosts, a key value
+// store of its own, json and dates. Shared by every engine of one theme so the fetch limit and
+// the store are per theme and not per script.
+#[derive(Clone)]
+pub struct ScriptStdlib {
+    options: ScriptOptions,
+    offset: FixedOffset,
+    kv: Arc<KvFile>,
+    fetcher: Arc<Fetcher>,
+    // from `[limits]`, None outside of a build
+    max_operations: Option<u64>,
+    deadline: Option<Deadline>,
+}
+
+impl ScriptStdlib {
+    pub fn new(
+        options: &ScriptOptions,
+        namespace: &str,
+        offset: FixedOffset,
+    ) -> Result<ScriptStdlib> {
+        Ok(ScriptStdlib {
+            options: options.clone(),
+            offset,
+            kv: Arc::new(KvFile::open(namespace)?),
+            fetcher: Arc::new(Fetcher {
+                hosts: options.fetch_hosts.clone(),
+                per_minute: options.fetches_per_minute,
+                window: Mutex::new((Instant::now(), 0)),
+                client: reqwest::blocking::Client::builder()
+                    .timeout(FETCH_TIMEOUT)
+                    .redirect(redirect_policy(options.fetch_hosts.clone()))
+                    .build()
+                    .map_err(|why| Report::msg(why.to_string()))?,
+            }),
+            max_operations: None,
+            deadline: None,
+        })
+    }
+
+    // scripts of a build stop at `script_operations` and once the build is out of time
+    pub fn limit(&mut self, limits: &BuildLimits, deadline: Deadline) {
+        self.max_operations = Some(limits.script_operations);
+        self.deadline = Some(deadline);
+    }
+
+    pub fn allow_shell(&self) -> bool {
+        self.options.allow_shell
+    }
+
+    pub fn register(&self, engine: &mut Engine) {
+        if let Some(max) = self.max_operations {
+            engine.set_max_operations(max);
+        }
+        if let Some(deadline) = self.deadline {
+            engine.on_progress(move |_| {
+                deadline
+                    .passed()
+                    .then(|| Dynamic::from("the build ran past [limits] build_seconds"))
+            });
+        }
+
+        let fetcher = self.fetcher.clone();
+        engine.register_fn("http_get", move |url: &str| fetcher.get(url));
+
+        let kv = self.kv.clone();
+        engine.register_fn("kv_get", move |key: &str| kv.get(key));
+        let kv = self.kv.clone();
+        engine.register_fn("kv_set", move |key: &str, value: &str| kv.set(key, value));
+        let kv = self.kv.clone();
+        engine.register_fn("kv_delete", move |key: &str| kv.delete(key));
+        let kv = self.kv.clone();
+        engine.register_fn("kv_keys", move |prefix: &str| kv.keys(prefix));
+
+        engine.register_fn("parse_json", |text: &str| {
+            let value = serde_json::from_str::<serde_json::Value>(text).map_err(script_error)?;
+            rhai::serde::to_dynamic(value)
+        });
+        engine.register_fn("to_json", |value: Dynamic| {
+            let value = rhai::serde::from_dynamic::<serde_json::Value>(&value)?;
+            serde_json::to_string(&value).map_err(script_error)
+        });
+
+        // dates go in and out as rfc 3339 strings, anything parse_date understands is accepted
+        engine.register_fn("now", || Utc::now().to_rfc3339());
+        let offset = self.offset;
+        engine.register_fn("parse_date", move |text: &str| {
+            Ok::<_, Box<EvalAltResult>>(date(text, &offset)?.to_rfc3339())
+        });
+        let offset = self.offset;
+        engine.register_fn("format_date", move |text: &str, format: &str| {
+            format_date(&date(text, &offset)?, format).map_err(script_error)
+        });
+        let offset = self.offset;
+        engine.register_fn("days_between", move |from: &str, to: &str| {
+            Ok::<_, Box<EvalAltResult>>((date(to, &offset)? - date(from, &offset)?).num_days())
+        });
+    }
+}
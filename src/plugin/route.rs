@@ -0,0 +1,273 @@
+use crate::models::plugin_kv;
+use color_eyre::{Report, Result};
+use rhai::{format_map_as_json, Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tracing::warn;
+
+// request bodies past this are refused before the script sees them
+pub const MAX_BODY: usize = 64 * 1024;
+
+const MAX_KEYS: u64 = 1000;
+
+// the only request headers a plugin gets to see
+const VISIBLE_HEADERS: &[&str] = &[
+    "accept",
+    "accept-language",
+    "content-type",
+    "referer",
+    "user-agent",
+];
+
+// `[routes.<name>]` in site.toml
+//
+// [routes.guestbook]
+// prefix = "/api/guestbook/"
+// script = "plugins/guestbook.rhai"
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteConfig {
+    // site path everything under which goes to the plugin
+    pub prefix: String,
+    // relative to the content repo, has to define `fn handle(request)`
+    pub script: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PluginRequest {
+    pub method: String,
+    // below the prefix, always starting with `/`
+    pub path: String,
+    pub query: BTreeMap<String, String>,
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+impl PluginRequest {
+    fn to_rhai(&self) -> Map {
+        let strings = |map: &BTreeMap<String, String>| {
+            map.iter()
+                .map(|(key, value)| (key.as_str().into(), value.clone().into()))
+                .collect::<Map>()
+        };
+
+        let mut map = Map::new();
+        map.insert("method".into(), self.method.clone().into());
+        map.insert("path".into(), self.path.clone().into());
+        map.insert("query".into(), strings(&self.query).into());
+        map.insert("headers".into(), strings(&self.headers).into());
+        map.insert("body".into(), self.body.clone().into());
+        map
+    }
+}
+
+pub fn is_visible_header(name: &str) -> bool {
+    VISIBLE_HEADERS.contains(&name.to_ascii_lowercase().as_str())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PluginResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+impl PluginResponse {
+    // a plain string is a 200 text/plain, a map is `#{ status, headers, body }` with every field
+    // optional, and a map with `json` in it is that value as json
+    fn from_rhai(value: Dynamic) -> Result<PluginResponse> {
+        let mut response = PluginResponse {
+            status: 200,
+            headers: BTreeMap::from([(
+                "content-type".to_string(),
+                "text/plain; charset=utf-8".to_string(),
+            )]),
+            body: String::new(),
+        };
+
+        if value.is_string() {
+            response.body = value.into_string().unwrap();
+            return Ok(response);
+        }
+        let map = value
+            .try_cast::<Map>()
+            .ok_or_else(|| Report::msg("handle() has to return a string or a map"))?;
+
+        if let Some(status) = map.get("status") {
+            response.status = status
+                .as_int()
+                .ok()
+                .and_then(|status| u16::try_from(status).ok())
+                .ok_or_else(|| Report::msg("status has to be a number"))?;
+        }
+        if let Some(headers) = map
+            .get("headers")
+            .and_then(|headers| headers.read_lock::<Map>())
+        {
+            for (name, value) in headers.iter() {
+                response
+                    .headers
+                    .insert(name.to_ascii_lowercase(), value.to_string());
+            }
+        }
+        if let Some(json) = map.get("json") {
+            response.body = match json.read_lock::<Map>() {
+                Some(object) => format_map_as_json(&object),
+                None => json.to_string(),
+            };
+            response
+                .headers
+                .insert("content-type".to_string(), "application/json".to_string());
+        } else if let Some(body) = map.get("body") {
+            response.body = body.to_string();
+        }
+        Ok(response)
+    }
+}
+
+// A script serving everything under its prefix. It only gets the request as a map and a key value
+// store namespaced to the plugin, with limits on how much it can do per request.
+pub struct PluginRoute {
+    pub name: String,
+    pub prefix: String,
+    engine: Engine,
+    script: AST,
+}
+
+fn kv_error(why: Report) -> Box<EvalAltResult> {
+    format!("kv: {why}").into()
+}
+
+// what `kv_*` in a script talks to. scripts run on a blocking thread, so waiting on the database
+// from them is fine.
+#[derive(Clone)]
+struct KvStore {
+    database: DatabaseConnection,
+    handle: Handle,
+    plugin: String,
+}
+
+impl KvStore {
+    fn get(&self, key: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+        let value = self
+            .handle
+            .block_on(plugin_kv::get(&self.database, &self.plugin, key))
+            .map_err(kv_error)?;
+        Ok(value.map(Dynamic::from).unwrap_or(Dynamic::UNIT))
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<(), Box<EvalAltResult>> {
+        self.handle
+            .block_on(plugin_kv::set(&self.database, &self.plugin, key, value))
+            .map_err(kv_error)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Box<EvalAltResult>> {
+        self.handle
+            .block_on(plugin_kv::delete(&self.database, &self.plugin, key))
+            .map_err(kv_error)
+    }
+
+    fn keys(&self, prefix: &str) -> Result<Array, Box<EvalAltResult>> {
+        let keys = self
+            .handle
+            .block_on(plugin_kv::keys(
+                &self.database,
+                &self.plugin,
+                prefix,
+                MAX_KEYS,
+            ))
+            .map_err(kv_error)?;
+        Ok(keys.into_iter().map(Dynamic::from).collect())
+    }
+}
+
+impl PluginRoute {
+    pub fn load(
+        name: &str,
+        config: &RouteConfig,
+        content_root: &Path,
+        database: DatabaseConnection,
+    ) -> Result<PluginRoute> {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(1_000_000)
+            .set_max_call_levels(32)
+            .set_max_string_size(1024 * 1024)
+            .set_max_array_size(10_000)
+            .set_max_map_size(10_000);
+
+        let kv = KvStore {
+            database,
+            handle: Handle::current(),
+            plugin: name.to_string(),
+        };
+        engine.register_fn("kv_get", {
+            let kv = kv.clone();
+            move |key: &str| kv.get(key)
+        });
+        engine.register_fn("kv_set", {
+            let kv = kv.clone();
+            move |key: &str, value: &str| kv.set(key, value)
+        });
+        engine.register_fn("kv_delete", {
+            let kv = kv.clone();
+            move |key: &str| kv.delete(key)
+        });
+        engine.register_fn("kv_keys", move |prefix: &str| kv.keys(prefix));
+
+        let script = engine
+            .compile(read_to_string(content_root.join(&config.script))?)
+            .map_err(|why| Report::msg(format!("plugin {name}: {why}")))?;
+
+        Ok(PluginRoute {
+            name: name.to_string(),
+            prefix: format!("/{}/", config.prefix.trim_matches('/')),
+            engine,
+            script,
+        })
+    }
+
+    // the path below the prefix if this route serves `path`
+    pub fn matches<'a>(&self, path: &'a str) -> Option<&'a str> {
+        match path.strip_prefix(self.prefix.trim_end_matches('/')) {
+            Some("") => Some("/"),
+            Some(rest) if rest.starts_with('/') => Some(rest),
+            _ => None,
+        }
+    }
+
+    // blocks, call it from a blocking thread
+    pub fn handle(&self, request: &PluginRequest) -> Result<PluginResponse> {
+        let mut scope = Scope::new();
+        let result = self
+            .engine
+            .call_fn::<Dynamic>(&mut scope, &self.script, "handle", (request.to_rhai(),))
+            .map_err(|why| Report::msg(format!("plugin {}: {why}", self.name)))?;
+        PluginResponse::from_rhai(result)
+    }
+}
+
+// every route configured in site.toml, a route that fails to load is left out
+pub fn load_routes(
+    content_root: &Path,
+    routes: &BTreeMap<String, RouteConfig>,
+    database: &DatabaseConnection,
+) -> Vec<Arc<PluginRoute>> {
+    routes
+        .iter()
+        .filter_map(|(name, config)| {
+            match PluginRoute::load(name, config, content_root, database.clone()) {
+                Ok(route) => Some(Arc::new(route)),
+                Err(why) => {
+                    warn!("not serving {}: {why}", config.prefix);
+                    None
+                }
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,262 @@
+use crate::injest::report::BuildReport;
+use color_eyre::{Report, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::read_to_string;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use wasmtime::component::{Component as WasmComponent, Linker};
+use wasmtime::{Config, Engine, Store, StoreLimits, StoreLimitsBuilder};
+
+wasmtime::component::bindgen!({
+    path: "wit/plugin.wit",
+    world: "plugin",
+});
+
+// `plugin.toml` in the plugin's directory
+pub const MANIFEST_FILE: &str = "plugin.toml";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PluginManifest {
+    pub name: String,
+    // the component, relative to the plugin's directory
+    pub component: String,
+    #[serde(default)]
+    pub capabilities: Capabilities,
+    #[serde(default)]
+    pub limits: PluginLimits,
+}
+
+// everything a plugin can do past computing things, nothing unless asked for
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    // files and directories of the content repo it may read
+    #[serde(default)]
+    pub read: Vec<String>,
+    // may add pages of its own
+    #[serde(default)]
+    pub emit: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PluginLimits {
+    // per call into the plugin, roughly one unit per instruction
+    #[serde(default = "default_fuel")]
+    pub fuel: u64,
+    #[serde(default = "default_memory")]
+    pub memory: usize,
+}
+
+fn default_fuel() -> u64 {
+    100_000_000
+}
+
+fn default_memory() -> usize {
+    64 * 1024 * 1024
+}
+
+impl Default for PluginLimits {
+    fn default() -> Self {
+        PluginLimits {
+            fuel: default_fuel(),
+            memory: default_memory(),
+        }
+    }
+}
+
+// relative, and staying inside whatever it is relative to
+fn contained(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path.trim_start_matches('/'));
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then(|| path.to_path_buf())
+}
+
+struct PluginState {
+    name: String,
+    content_root: PathBuf,
+    capabilities: Capabilities,
+    transforms: Vec<String>,
+    pages: Vec<(String, String)>,
+    limits: StoreLimits,
+}
+
+impl host::Host for PluginState {
+    fn read_file(&mut self, path: String) -> wasmtime::Result<Result<String, String>> {
+        let relative = match contained(&path) {
+            Some(relative) => relative,
+            None => return Ok(Err(format!("{path} is not a path inside the content repo"))),
+        };
+        if !self
+            .capabilities
+            .read
+            .iter()
+            .any(|allowed| relative.starts_with(allowed.trim_matches('/')))
+        {
+            return Ok(Err(format!(
+                "{path} is not under `read` in {MANIFEST_FILE}"
+            )));
+        }
+        Ok(read_to_string(self.content_root.join(relative)).map_err(|why| why.to_string()))
+    }
+
+    fn register_transform(&mut self, name: String) -> wasmtime::Result<Result<(), String>> {
+        if !self.transforms.contains(&name) {
+            self.transforms.push(name);
+        }
+        Ok(Ok(()))
+    }
+
+    fn emit_page(&mut self, path: String, html: String) -> wasmtime::Result<Result<(), String>> {
+        if !self.capabilities.emit {
+            return Ok(Err(format!(
+                "emitting pages needs `emit = true` in {MANIFEST_FILE}"
+            )));
+        }
+        match contained(&path) {
+            Some(_) => {
+                self.pages
+                    .push((format!("/{}", path.trim_matches('/')), html));
+                Ok(Ok(()))
+            }
+            None => Ok(Err(format!("{path} is not a site path"))),
+        }
+    }
+
+    fn log(&mut self, message: String) -> wasmtime::Result<()> {
+        tracing::info!("plugin {}: {message}", self.name);
+        Ok(())
+    }
+}
+
+pub struct WasmPlugin {
+    pub name: String,
+    fuel: u64,
+    store: Mutex<Store<PluginState>>,
+    bindings: Plugin,
+}
+
+impl WasmPlugin {
+    fn load(
+        engine: &Engine,
+        content_root: &Path,
+        dir: &Path,
+        max_fuel: Option<u64>,
+    ) -> Result<WasmPlugin> {
+        let manifest = toml::from_str::<PluginManifest>(&read_to_string(dir.join(MANIFEST_FILE))?)?;
+        // `[limits] plugin_fuel` caps what the manifest asks for
+        let fuel = max_fuel.map_or(manifest.limits.fuel, |max| max.min(manifest.limits.fuel));
+        let component = WasmComponent::from_file(engine, dir.join(&manifest.component))
+            .map_err(|why| Report::msg(format!("plugin {}: {why}", manifest.name)))?;
+
+        let mut linker = Linker::new(engine);
+        Plugin::add_to_linker(&mut linker, |state: &mut PluginState| state)
+            .map_err(|why| Report::msg(why.to_string()))?;
+
+        let mut store = Store::new(
+            engine,
+            PluginState {
+                name: manifest.name.clone(),
+                content_root: content_root.to_path_buf(),
+                capabilities: manifest.capabilities.clone(),
+                transforms: vec![],
+                pages: vec![],
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(manifest.limits.memory)
+                    .build(),
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        store
+            .add_fuel(fuel)
+            .map_err(|why| Report::msg(why.to_string()))?;
+
+        let (bindings, _) = Plugin::instantiate(&mut store, &component, &linker)
+            .map_err(|why| Report::msg(format!("plugin {}: {why}", manifest.name)))?;
+        bindings
+            .call_init(&mut store)
+            .map_err(|why| Report::msg(format!("plugin {}: {why}", manifest.name)))?
+            .map_err(|why| Report::msg(format!("plugin {}: {why}", manifest.name)))?;
+
+        Ok(WasmPlugin {
+            name: manifest.name,
+            fuel,
+            store: Mutex::new(store),
+            bindings,
+        })
+    }
+
+    // every call gets the full budget again, unused fuel doesn't carry over
+    fn refuel(&self, store: &mut Store<PluginState>) -> Result<()> {
+        let remaining = store
+            .consume_fuel(0)
+            .map_err(|why| Report::msg(why.to_string()))?;
+        store
+            .add_fuel(self.fuel.saturating_sub(remaining))
+            .map_err(|why| Report::msg(why.to_string()))
+    }
+
+    fn transform(&self, path: &str, html: String) -> Result<String> {
+        let mut store = self.store.lock().unwrap();
+        let transforms = store.data().transforms.clone();
+        let mut html = html;
+        for name in transforms {
+            self.refuel(&mut store)?;
+            html = self
+                .bindings
+                .call_transform(&mut *store, &name, path, &html)
+                .map_err(|why| Report::msg(format!("plugin {}: {why}", self.name)))?
+                .map_err(|why| Report::msg(format!("plugin {} ({name}): {why}", self.name)))?;
+        }
+        Ok(html)
+    }
+}
+
+// The wasm plugins of a site, for what rhai is too slow or too limited for. Each one runs in its
+// own store with a fuel and memory budget, and reaches the outside only through the host api in
+// wit/plugin.wit as far as its manifest allows.
+#[derive(Default)]
+pub struct WasmPlugins {
+    plugins: Vec<WasmPlugin>,
+}
+
+impl WasmPlugins {
+    // `dirs` relative to the content repo, a plugin that fails to load is reported and left out
+    pub fn load(
+        content_root: &Path,
+        dirs: &[String],
+        max_fuel: Option<u64>,
+        report: &mut BuildReport,
+    ) -> Result<WasmPlugins> {
+        if dirs.is_empty() {
+            return Ok(WasmPlugins::default());
+        }
+        let mut config = Config::new();
+        config.wasm_component_model(true).consume_fuel(true);
+        let engine = Engine::new(&config).map_err(|why| Report::msg(why.to_string()))?;
+
+        let mut plugins = vec![];
+        for dir in dirs {
+            let dir = content_root.join(dir);
+            match WasmPlugin::load(&engine, content_root, &dir, max_fuel) {
+                Ok(plugin) => plugins.push(plugin),
+                Err(why) => report.error(dir.join(MANIFEST_FILE), format!("failed to load: {why}")),
+            }
+        }
+        Ok(WasmPlugins { plugins })
+    }
+
+    // pages the plugins emitted, site path -> html
+    pub fn take_pages(&self) -> Vec<(String, String)> {
+        self.plugins
+            .iter()
+            .flat_map(|plugin| std::mem::take(&mut plugin.store.lock().unwrap().data_mut().pages))
+            .collect()
+    }
+
+    // the rendered page at `path` through every registered transform, in plugin order
+    pub fn transform(&self, path: &str, html: String) -> Result<String> {
+        self.plugins
+            .iter()
+            .try_fold(html, |html, plugin| plugin.transform(path, html))
+    }
+}
@@ -0,0 +1,130 @@
+use crate::injest::access::rule_for;
+use crate::serve::{
+    admin::is_admin,
+    errors::error_response,
+    private::{page_path, served_path, sign_token, verify_token},
+    security::{cookie, set_cookie, SameSite},
+};
+use crate::State;
+use axum::{
+    extract,
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use itertools::Itertools;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+// where a role token is kept once it has been used
+pub const ACCESS_COOKIE: &str = "moklog_access";
+// `?access=<role token>` on a link, turned into the cookie on the way out
+pub const ACCESS_PARAM: &str = "access";
+
+// who is asking, there are no accounts (yet), only the admin key and role tokens
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Viewer {
+    pub admin: bool,
+    // handed a valid role token, even if it has no role a rule wants
+    pub signed_in: bool,
+    pub roles: BTreeSet<String>,
+}
+
+impl Viewer {
+    pub fn may_see(&self, state: &State, path: &str) -> bool {
+        match rule_for(&state.access, path) {
+            Some(rule) => self.admin || rule.allows(&self.roles),
+            None => true,
+        }
+    }
+}
+
+// `<roles>.<expires>.<signature>`, roles comma separated
+pub fn role_token(secret: &str, roles: &BTreeSet<String>, expires: i64) -> String {
+    let roles = roles.iter().join(",");
+    format!(
+        "{roles}.{}",
+        sign_token(secret, &format!("roles:{roles}"), expires)
+    )
+}
+
+fn token_roles(secret: &str, token: &str) -> Option<BTreeSet<String>> {
+    let (roles, signed) = token.split_once('.')?;
+    verify_token(secret, &format!("roles:{roles}"), signed).then(|| {
+        roles
+            .split(',')
+            .filter(|role| !role.is_empty())
+            .map(ToString::to_string)
+            .collect()
+    })
+}
+
+fn query_param(request_query: Option<&str>, name: &str) -> Option<String> {
+    url::form_urlencoded::parse(request_query?.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+// a token in the query wins over the cookie, it's the newer one
+pub async fn viewer(state: &State, headers: &HeaderMap, query: Option<&str>) -> Viewer {
+    let token = query_param(query, ACCESS_PARAM).or_else(|| cookie(headers, ACCESS_COOKIE));
+    let roles = token.and_then(|token| token_roles(state.config.admin_key(), &token));
+    Viewer {
+        admin: is_admin(state, headers).await,
+        signed_in: roles.is_some(),
+        roles: roles.unwrap_or_default(),
+    }
+}
+
+// Parts of the site covered by an `[[access]]` rule are only served to viewers with one of its
+// roles: 401 for anyone without a token, 403 for a token without the role. A valid `?access=`
+// on any page is kept as a cookie.
+pub async fn access_layer<B>(
+    extract::State(state): extract::State<Arc<State>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = match served_path(request.uri().path()) {
+        Some(path) => page_path(&path),
+        // ServeDir won't serve it either
+        None => return error_response(StatusCode::NOT_FOUND).await,
+    };
+    let covered = rule_for(&state.access, &path).is_some();
+    let new_token = query_param(request.uri().query(), ACCESS_PARAM);
+    if !covered && new_token.is_none() {
+        return next.run(request).await;
+    }
+
+    let viewer = viewer(&state, request.headers(), request.uri().query()).await;
+    if !viewer.may_see(&state, &path) {
+        if viewer.admin || viewer.signed_in {
+            return error_response(StatusCode::FORBIDDEN).await;
+        }
+        let mut response = error_response(StatusCode::UNAUTHORIZED).await;
+        response
+            .headers_mut()
+            .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        return response;
+    }
+
+    let new_token = new_token.filter(|_| viewer.signed_in);
+    let mut response = next.run(request).await;
+    if covered {
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("private, no-store"),
+        );
+    }
+    if let Some(token) = new_token {
+        // Lax, the token arrives by following a link from somewhere else
+        set_cookie(
+            &state,
+            response.headers_mut(),
+            ACCESS_COOKIE,
+            &token,
+            SameSite::Lax,
+            None,
+        );
+    }
+    response
+}
@@ -0,0 +1,277 @@
+use crate::config::Config;
+use crate::injest::context_schema::context_schema;
+use crate::injest::manifest::{read_manifest, Manifest, MANIFEST_FILE};
+use crate::injest::theme_docs::{theme_docs_page, theme_items};
+use crate::serve::access::{role_token, ACCESS_PARAM};
+use crate::serve::builds::{BuildPriority, BuildTrigger};
+use crate::serve::errors::error_response;
+use crate::serve::private::{page_path, sign_token, TOKEN_PARAM};
+use crate::serve::proxy::proxy_user;
+use crate::serve::security::{constant_time_eq, current_session};
+use crate::serve::two_factor::{elevation_required, header_code_passes, session_is_admin};
+use crate::{models::build_diff, State, SERVE_DIR};
+use axum::{
+    extract::{self, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tracing::warn;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AdminRole {
+    Session,
+    Bearer,
+    Proxy,
+}
+
+// How a request would be admin, and so which part of TWO_FACTOR_REQUIRED it goes by. Bearer only
+// with the admin key itself, anything else in Authorization is left to the session it comes with.
+pub fn admin_role(config: &Config, headers: &HeaderMap) -> AdminRole {
+    if proxy_user(config, headers).is_some() {
+        return AdminRole::Proxy;
+    }
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match bearer {
+        Some(given) if constant_time_eq(given.as_bytes(), config.admin_key().as_bytes()) => {
+            AdminRole::Bearer
+        }
+        _ => AdminRole::Session,
+    }
+}
+
+// `Authorization: Bearer <SECRET>`, a browser signed in with it, or anyone the auth proxy signed in
+pub async fn is_admin(state: &State, headers: &HeaderMap) -> bool {
+    match admin_role(&state.config, headers) {
+        AdminRole::Proxy | AdminRole::Bearer => header_code_passes(state, headers).await,
+        AdminRole::Session => current_session(state, headers)
+            .await
+            .map_or(false, |session| session_is_admin(state, &session)),
+    }
+}
+
+pub async fn build_diff(
+    extract::State(state): extract::State<Arc<State>>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    match build_diff::for_build(&state.database, id).await {
+        Ok(diff) => Json(diff).into_response(),
+        Err(why) => {
+            warn!("failed to load the diff of build {id}: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// `?path=/blog/post&hours=24`, a link that never expires without `hours`
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct TokenParams {
+    pub path: String,
+    pub hours: Option<i64>,
+}
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct PageLink {
+    pub path: String,
+    pub token: String,
+    pub url: String,
+}
+
+pub async fn page_link(
+    extract::State(state): extract::State<Arc<State>>,
+    Query(params): Query<TokenParams>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let path = page_path(&params.path);
+    let expires = params
+        .hours
+        .map_or(0, |hours| Utc::now().timestamp() + hours.max(1) * 3600);
+    let token = sign_token(state.config.admin_key(), &path, expires);
+    let url = format!(
+        "{}?{TOKEN_PARAM}={token}",
+        state.config.site_url().absolute(&path)
+    );
+    Json(PageLink { path, token, url }).into_response()
+}
+
+// `?roles=member,patron&hours=720`, a token that never expires without `hours`
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct RoleTokenParams {
+    pub roles: String,
+    pub hours: Option<i64>,
+}
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct RoleToken {
+    pub roles: BTreeSet<String>,
+    pub token: String,
+    // a link that signs in whoever opens it
+    pub url: String,
+}
+
+pub async fn issue_role_token(
+    extract::State(state): extract::State<Arc<State>>,
+    Query(params): Query<RoleTokenParams>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let roles = params
+        .roles
+        .split(',')
+        .map(str::trim)
+        .filter(|role| !role.is_empty())
+        .map(ToString::to_string)
+        .collect::<BTreeSet<_>>();
+    if roles.is_empty() || roles.iter().any(|role| role.contains('.')) {
+        return (StatusCode::BAD_REQUEST, "no roles, or a role with a `.`").into_response();
+    }
+    let expires = params
+        .hours
+        .map_or(0, |hours| Utc::now().timestamp() + hours.max(1) * 3600);
+    let token = role_token(state.config.admin_key(), &roles, expires);
+    let url = format!(
+        "{}?{ACCESS_PARAM}={}",
+        state.config.site_url().absolute("/"),
+        url::form_urlencoded::byte_serialize(token.as_bytes()).collect::<String>()
+    );
+    Json(RoleToken { roles, token, url }).into_response()
+}
+
+pub async fn build_queue(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    Json(state.builds.state().await).into_response()
+}
+
+// `?force=true` builds the frozen versions of versioned categories too
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+pub struct TriggerParams {
+    #[serde(default)]
+    pub force: bool,
+}
+
+// joins whatever is already pending, and gets it going without waiting for it to settle
+pub async fn trigger_build(
+    extract::State(state): extract::State<Arc<State>>,
+    Query(params): Query<TriggerParams>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if let Some(response) = elevation_required(&state, &headers).await {
+        return response;
+    }
+    let mut trigger = BuildTrigger::new(BuildPriority::Manual, "admin");
+    trigger.force = params.force;
+    let queue = state.builds.trigger(trigger).await;
+    (StatusCode::ACCEPTED, Json(queue)).into_response()
+}
+
+// the manifest of the build being served, a 404 before the first build wrote one
+pub async fn manifest(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        // it names private pages, so it isn't there for anyone else
+        return error_response(StatusCode::NOT_FOUND).await;
+    }
+    match read_manifest(SERVE_DIR) {
+        Ok(manifest) => Json(manifest).into_response(),
+        Err(why) => {
+            warn!("failed to read the build manifest: {why}");
+            StatusCode::NOT_FOUND.into_response()
+        }
+    }
+}
+
+// post an earlier manifest, get the paths that changed since, what a cdn needs purged
+pub async fn manifest_changes(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+    Json(previous): Json<Manifest>,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    match read_manifest(SERVE_DIR) {
+        Ok(manifest) => Json(manifest.changed_since(&previous)).into_response(),
+        Err(why) => {
+            warn!("failed to read the build manifest: {why}");
+            StatusCode::NOT_FOUND.into_response()
+        }
+    }
+}
+
+// what `moklog theme schema` prints for the theme being served, for editors that fetch it
+pub async fn theme_schema(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let theme = state.theme.read().await.clone();
+    match context_schema(Some(&theme.metadata)) {
+        Ok(schema) => Json(schema).into_response(),
+        Err(why) => {
+            warn!("failed to describe the template context: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// the theme's shortcodes, filters, testers and functions with their sources and calls
+pub async fn theme_docs(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return error_response(StatusCode::NOT_FOUND).await;
+    }
+    let theme = state.theme.read().await.clone();
+    let items = theme_items(&theme, &*state.theme_calls.read().await);
+    (
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        theme_docs_page(&theme, &items),
+    )
+        .into_response()
+}
+
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        // shadows the file in the serve dir, private_layer keeps the rest of the ways to it out
+        .route(&format!("/{MANIFEST_FILE}"), get(manifest))
+        .route("/api/admin/manifest", get(manifest))
+        .route("/api/admin/manifest/changes", post(manifest_changes))
+        .route("/api/admin/builds", get(build_queue).post(trigger_build))
+        .route("/api/admin/builds/:id/diff", get(build_diff))
+        .route("/api/admin/page-link", get(page_link))
+        .route("/api/admin/role-token", get(issue_role_token))
+        .route("/api/admin/theme", get(theme_docs))
+        .route("/api/admin/theme/schema", get(theme_schema))
+        .with_state(state)
+}
@@ -0,0 +1,179 @@
+use crate::models::admin_action::{self, ActionFilter, NewAction};
+use crate::serve::admin::is_admin;
+use crate::serve::proxy::{client_ip, proxy_user};
+use crate::serve::security::{constant_time_eq, current_session, is_form, read_limited};
+use crate::State;
+use axum::{
+    body::Body,
+    extract::{self, ConnectInfo, Query},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::warn;
+
+// everything under it that changes something is written down
+const ADMIN_PREFIX: &str = "/api/admin/";
+// gets that hand out credentials, which is as much a change as anything posted
+const ISSUING: &[&str] = &["/api/admin/role-token", "/api/admin/page-link"];
+// credentials, never written down
+const SECRET_FIELDS: &[&str] = &["key", "code", "secret", "token", "_csrf"];
+// a field's value is cut off after this many characters
+const MAX_VALUE: usize = 80;
+const DEFAULT_LIMIT: u64 = 100;
+const MAX_LIMIT: u64 = 1000;
+
+// who the request claims to be, None for nobody in particular. Whether that holds (a second factor
+// included) is for the handler to decide, its answer is recorded along with this.
+async fn actor(state: &State, headers: &HeaderMap) -> Option<String> {
+    if let Some(user) = proxy_user(&state.config, headers) {
+        return Some(format!("proxy:{user}"));
+    }
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(given) = bearer {
+        return constant_time_eq(given.as_bytes(), state.config.admin_key().as_bytes())
+            .then(|| "key".to_string());
+    }
+    current_session(state, headers)
+        .await
+        .filter(|session| session.admin)
+        .map(|session| format!("session:{}", &session.id[..12]))
+}
+
+// `reason=typo, roles=editor,copyedit, code=[redacted]`
+fn summarize(fields: impl Iterator<Item = (String, String)>) -> String {
+    fields
+        .map(|(name, value)| {
+            let value = match SECRET_FIELDS.contains(&name.as_str()) {
+                true => "[redacted]".to_string(),
+                false if value.chars().count() > MAX_VALUE => {
+                    format!("{}…", value.chars().take(MAX_VALUE).collect::<String>())
+                }
+                false => value,
+            };
+            format!("{name}={value}")
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Every admin request that changes something, whoever it's from and whatever it was answered
+// with. Signed out and anonymous requests are left to the handlers to turn away.
+pub async fn admin_log_layer(
+    extract::State(state): extract::State<Arc<State>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let path = request.uri().path();
+    let safe = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) && !ISSUING.contains(&path);
+    if safe || !path.starts_with(ADMIN_PREFIX) {
+        return next.run(request).await;
+    }
+    let actor = match actor(&state, request.headers()).await {
+        Some(actor) => actor,
+        None => return next.run(request).await,
+    };
+    let ip = client_ip(&state.config, address.ip(), request.headers()).to_string();
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+
+    let query = request.uri().query().unwrap_or_default().to_string();
+    let mut fields = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect::<Vec<_>>();
+    let request = match is_form(request.headers()) {
+        true => {
+            let (parts, body) = request.into_parts();
+            let body = match read_limited(body).await {
+                Some(body) => body,
+                None => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+            };
+            fields.extend(url::form_urlencoded::parse(&body).into_owned());
+            Request::from_parts(parts, Body::from(body))
+        }
+        false => request,
+    };
+    let mut summary = summarize(fields.into_iter());
+    // anything else, json mostly, is only described
+    if let Some(length) = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| !is_form(request.headers()))
+    {
+        if !summary.is_empty() {
+            summary.push_str(", ");
+        }
+        summary.push_str(&format!("{length} byte body"));
+    }
+
+    let response = next.run(request).await;
+    let action = NewAction {
+        actor,
+        ip,
+        method,
+        path,
+        summary,
+        status: response.status().as_u16(),
+    };
+    if let Err(why) = admin_action::record(&state.database, action).await {
+        warn!("failed to record an admin action: {why}");
+    }
+    response
+}
+
+// `?actor=key&path=/api/admin/builds&since=2024-01-01T00:00:00Z&limit=50`
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct LogParams {
+    pub actor: Option<String>,
+    pub ip: Option<String>,
+    pub path: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<u64>,
+}
+
+pub async fn admin_log(
+    extract::State(state): extract::State<Arc<State>>,
+    Query(params): Query<LogParams>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let filter = ActionFilter {
+        actor: params.actor,
+        ip: params.ip,
+        path: params.path,
+        since: params.since,
+        until: params.until,
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    match admin_action::find(&state.database, &filter, limit).await {
+        Ok(actions) => Json(actions).into_response(),
+        Err(why) => {
+            warn!("failed to read the admin log: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        .route("/api/admin/log", get(admin_log))
+        .with_state(state)
+}
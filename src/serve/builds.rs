@@ -0,0 +1,530 @@
+use crate::injest::{
+    access::AccessRule,
+    blobs::BlobStore,
+    build::{build_site, BuildInformation, BuildStatus, BuiltSite},
+    cdn::purge_cdns,
+    diff::{category_renames, record_diff},
+    generate::PageAccess,
+    ipfs::{add_site, pin_remote, IpfsOptions},
+    notify::{notify, Notification, NotifyEvent, Subscription},
+    redirect::RedirectEntry,
+    search::collect_documents,
+    site::SiteMeta,
+    templates::build_site_theme,
+    webhook::{sign, Webhooks, SIGNATURE_HEADER},
+    workflow::WorkflowState,
+};
+use crate::models::{ipfs_publish, page_access, page_hash, redirect, review_assignment};
+use crate::serve::{dynamic, proxy::client_ip, retention, security::constant_time_eq};
+use crate::{State, BLOB_DIR, SERVE_DIR, SITE_CONTENT};
+use axum::{
+    extract::{self, ConnectInfo},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use color_eyre::{Report, Result};
+use itertools::Itertools;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify};
+use tracing::warn;
+
+// what asked for a build, in the order they win
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildPriority {
+    Scheduled,
+    Webhook,
+    Manual,
+}
+
+impl BuildPriority {
+    pub fn name(self) -> &'static str {
+        match self {
+            BuildPriority::Scheduled => "scheduled",
+            BuildPriority::Webhook => "webhook",
+            BuildPriority::Manual => "manual",
+        }
+    }
+
+    // How long a trigger waits for others to pile onto the same build. A push tends to come with
+    // more pushes, someone pressing the button wants it now.
+    fn settle(self) -> chrono::Duration {
+        match self {
+            BuildPriority::Scheduled => chrono::Duration::seconds(30),
+            BuildPriority::Webhook => chrono::Duration::seconds(10),
+            BuildPriority::Manual => chrono::Duration::zero(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct BuildTrigger {
+    pub priority: BuildPriority,
+    // who or what, `admin`, the address a webhook came from
+    pub by: String,
+    pub at: DateTime<Utc>,
+    // rebuild the frozen versions of versioned categories too
+    #[serde(default)]
+    pub force: bool,
+}
+
+impl BuildTrigger {
+    pub fn new(priority: BuildPriority, by: impl Into<String>) -> BuildTrigger {
+        BuildTrigger {
+            priority,
+            by: by.into(),
+            at: Utc::now(),
+            force: false,
+        }
+    }
+}
+
+// Every trigger that came in since the last build started. A build always builds the whole site
+// from what is there when it starts, so any number of them make one build.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PendingBuild {
+    pub priority: BuildPriority,
+    pub triggers: Vec<BuildTrigger>,
+    pub starts_at: DateTime<Utc>,
+}
+
+impl PendingBuild {
+    fn new(trigger: BuildTrigger) -> PendingBuild {
+        PendingBuild {
+            priority: trigger.priority,
+            starts_at: trigger.at + trigger.priority.settle(),
+            triggers: vec![trigger],
+        }
+    }
+
+    fn add(&mut self, trigger: BuildTrigger) {
+        self.priority = self.priority.max(trigger.priority);
+        self.starts_at = self.starts_at.min(trigger.at + trigger.priority.settle());
+        self.triggers.push(trigger);
+    }
+
+    // one forced trigger forces the whole build
+    pub fn forced(&self) -> bool {
+        self.triggers.iter().any(|trigger| trigger.force)
+    }
+
+    // what ends up in BuildInformation.initiated, highest priority first
+    pub fn initiated(&self) -> String {
+        self.triggers
+            .iter()
+            .sorted_by(|a, b| b.priority.cmp(&a.priority).then(a.at.cmp(&b.at)))
+            .map(|trigger| format!("{} ({})", trigger.priority.name(), trigger.by))
+            .dedup()
+            .join(", ")
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct QueueState {
+    pub running: Option<BuildInformation>,
+    pub pending: Option<PendingBuild>,
+    pub last: Option<BuildInformation>,
+}
+
+// At most one build runs, and at most one waits behind it with everything that asked for it since.
+pub struct BuildQueue {
+    state: Mutex<QueueState>,
+    wake: Notify,
+}
+
+impl BuildQueue {
+    pub fn new() -> BuildQueue {
+        BuildQueue {
+            state: Mutex::new(QueueState::default()),
+            wake: Notify::new(),
+        }
+    }
+
+    pub async fn trigger(&self, trigger: BuildTrigger) -> QueueState {
+        let mut state = self.state.lock().await;
+        match &mut state.pending {
+            Some(pending) => pending.add(trigger),
+            None => state.pending = Some(PendingBuild::new(trigger)),
+        }
+        self.wake.notify_one();
+        state.clone()
+    }
+
+    pub async fn state(&self) -> QueueState {
+        self.state.lock().await.clone()
+    }
+
+    // waits for the pending build to settle, and takes it
+    async fn next(&self) -> PendingBuild {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().await;
+                match &state.pending {
+                    Some(pending) if pending.starts_at <= Utc::now() => {
+                        return state.pending.take().expect("checked just above");
+                    }
+                    // negative if it passed since the check above, then it starts right away
+                    Some(pending) => Some(
+                        (pending.starts_at - Utc::now())
+                            .to_std()
+                            .unwrap_or_default(),
+                    ),
+                    None => None,
+                }
+            };
+            match wait {
+                // a new trigger can make it start sooner
+                Some(wait) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait) => {}
+                        _ = self.wake.notified() => {}
+                    }
+                }
+                None => self.wake.notified().await,
+            }
+        }
+    }
+
+    async fn started(&self, build: BuildInformation) {
+        self.state.lock().await.running = Some(build);
+    }
+
+    async fn finished(&self, build: BuildInformation) {
+        let mut state = self.state.lock().await;
+        state.running = None;
+        state.last = Some(build);
+    }
+}
+
+impl Default for BuildQueue {
+    fn default() -> Self {
+        BuildQueue::new()
+    }
+}
+
+// A frozen page keeps the hash it was last built with, so it doesn't show up in the diff and
+// nothing cached of it is purged. One that was never built before is built like any other.
+fn keep_frozen(previous: &BTreeMap<String, String>, built: &mut BuiltSite) {
+    for path in &built.frozen {
+        if let Some(hash) = previous.get(path) {
+            built.pages.insert(path.clone(), hash.clone());
+        }
+    }
+}
+
+async fn build(state: &Arc<State>, id: u64, force: bool) -> Result<BuiltSite> {
+    let site = SiteMeta::load(SITE_CONTENT)?;
+    let default_language = site.default_language().to_string();
+    let cdns = site.cdn.clone();
+    let cache_policy = site.cache.clone();
+    let ipfs = site.ipfs.clone();
+    let rules = site.access.clone();
+    let subscriptions = site.notify.clone();
+    // read again every build, a push can change the theme as well as the content
+    let theme = Arc::new(build_site_theme(state.config.theme_dir()).await?);
+    let mut built = {
+        let state = state.clone();
+        let theme = theme.clone();
+        tokio::task::spawn_blocking(move || {
+            let blobs = BlobStore::new(BLOB_DIR);
+            let built = build_site(
+                SITE_CONTENT,
+                SERVE_DIR,
+                &state.config,
+                &site,
+                &theme,
+                Some(&blobs),
+            )?;
+            blobs.record_manifest(id, &built.manifest)?;
+            Ok::<_, Report>(built)
+        })
+        .await??
+    };
+    // the dynamic pages of this build are rendered with the theme it was built with
+    *state.theme.write().await = theme;
+
+    let previous = page_hash::all(&state.database).await?;
+    if !force {
+        keep_frozen(&previous, &mut built);
+    }
+    let diff = record_diff(&state.database, id, &built.pages).await?;
+    state.cache.apply_diff(&diff).await;
+    let in_review = page_access::in_state(&state.database, WorkflowState::Review).await?;
+    page_access::replace(&state.database, &built.access).await?;
+    redirect::replace_generated(&state.database, &built.redirects).await?;
+    redirect_renames(state, &previous, &mut built).await?;
+    announce_reviews(state, &subscriptions, &in_review, &built).await;
+    reindex(state, &built, default_language).await;
+    purge_cdns(&cdns, &diff, state.config.site_url(), &cache_policy).await;
+    if let Some(ipfs) = &ipfs {
+        publish_ipfs(state, ipfs, id, &built, &rules).await;
+    }
+    Ok(built)
+}
+
+// categories renamed since the last build keep their old urls working, and the build says so
+async fn redirect_renames(
+    state: &State,
+    previous: &BTreeMap<String, String>,
+    built: &mut BuiltSite,
+) -> Result<()> {
+    let urls = state.config.site_url();
+    let mut redirects = vec![];
+    for rename in category_renames(previous, &built.pages) {
+        built.report.warn(
+            Path::new(&rename.from),
+            format!(
+                "renamed to {}, its {} pages are redirected to their new paths",
+                rename.to,
+                rename.pages.len()
+            ),
+        );
+        redirects.push(RedirectEntry {
+            from: rename.from.clone(),
+            to: urls.link(&rename.to),
+            permanent: true,
+        });
+        redirects.extend(
+            rename
+                .pages
+                .iter()
+                .filter(|(from, _)| *from != rename.from)
+                .map(|(from, to)| RedirectEntry {
+                    from: from.clone(),
+                    to: urls.link(to),
+                    permanent: true,
+                }),
+        );
+    }
+    redirect::record_renamed(&state.database, &redirects, urls, &built.pages).await
+}
+
+// tells the roles that may review a page once it enters review, not again for every build after
+async fn announce_reviews(
+    state: &State,
+    subscriptions: &[Subscription],
+    in_review: &BTreeMap<String, PageAccess>,
+    built: &BuiltSite,
+) {
+    if subscriptions.is_empty() {
+        return;
+    }
+    let entered = built
+        .access
+        .iter()
+        .filter(|(path, access)| {
+            access.state == WorkflowState::Review && !in_review.contains_key(*path)
+        })
+        .collect::<Vec<_>>();
+    for (path, access) in entered {
+        let mut reviewers = access.roles.clone();
+        match review_assignment::for_page(&state.database, path).await {
+            Ok(assigned) => reviewers.extend(assigned),
+            Err(why) => warn!("failed to look up the reviewers of {path}: {why}"),
+        }
+        let notification = Notification {
+            event: NotifyEvent::EnteredReview,
+            site: state.config.sitename().to_string(),
+            subject: format!("{path} is ready for review"),
+            body: format!(
+                "{path} entered review, and you are one of the roles that may review it."
+            ),
+            url: Some(state.config.site_url().absolute(path)),
+        };
+        notify(subscriptions, &notification, Some(&reviewers)).await;
+    }
+}
+
+// unlisted pages stay out of search too, a search would list them
+async fn reindex(state: &Arc<State>, built: &BuiltSite, default_language: String) {
+    let hidden = built
+        .access
+        .iter()
+        .filter(|(_, access)| access.unlisted || access.is_hidden())
+        .map(|(path, _)| path.clone())
+        .collect::<BTreeSet<_>>();
+    let state = state.clone();
+    let indexed = tokio::task::spawn_blocking(move || {
+        let documents = collect_documents(SERVE_DIR, &hidden, &default_language)?;
+        state.search.rebuild(&documents)
+    })
+    .await;
+    match indexed {
+        Ok(Ok(())) => {}
+        Ok(Err(why)) => warn!("search still answers from the last index, indexing failed: {why}"),
+        Err(why) => warn!("indexing panicked: {why}"),
+    }
+}
+
+// a mirror that fails to publish is logged, the site itself is up to date either way
+async fn publish_ipfs(
+    state: &State,
+    options: &IpfsOptions,
+    id: u64,
+    built: &BuiltSite,
+    rules: &[AccessRule],
+) {
+    let cid = match add_site(options, SERVE_DIR, &built.access, rules).await {
+        Ok(cid) => cid,
+        Err(why) => {
+            warn!("failed to add build {id} to ipfs: {why}");
+            return;
+        }
+    };
+    if let Err(why) = ipfs_publish::record(&state.database, id, &cid).await {
+        warn!("failed to record cid {cid} of build {id}: {why}");
+    }
+    if let Err(why) = pin_remote(
+        options,
+        &cid,
+        &format!("{} build {id}", state.config.sitename()),
+    )
+    .await
+    {
+        warn!("failed to pin {cid} remotely: {why}");
+    }
+}
+
+// Takes builds off the queue one at a time for as long as the server runs.
+pub async fn run_builds(state: Arc<State>) {
+    loop {
+        let pending = state.builds.next().await;
+        let mut info = BuildInformation {
+            initiated: pending.initiated(),
+            id: Utc::now().timestamp_millis() as u64,
+            start_time: Utc::now(),
+            end_time: None,
+            status: BuildStatus::Running,
+        };
+        state.builds.started(info.clone()).await;
+
+        // webhooks and notifications come from the site.toml of the build, one that doesn't load
+        // has none
+        let (webhooks, subscriptions) = match SiteMeta::load(SITE_CONTENT)
+            .and_then(|site| Ok((Webhooks::new(site.webhooks)?, site.notify)))
+        {
+            Ok((webhooks, subscriptions)) => (Some(webhooks), subscriptions),
+            Err(why) => {
+                warn!("build {} runs without webhooks: {why}", info.id);
+                (None, vec![])
+            }
+        };
+        if let Some(webhooks) = &webhooks {
+            webhooks.build_started(state.config.sitename(), &info).await;
+        }
+
+        let built = build(&state, info.id, pending.forced()).await;
+        info.end_time = Some(Utc::now());
+        info.status = match &built {
+            Ok(_) => BuildStatus::Succeeded,
+            Err(why) => {
+                warn!("build {} ({}) failed: {why}", info.id, info.initiated);
+                BuildStatus::Failed
+            }
+        };
+        if let Some(webhooks) = &webhooks {
+            webhooks
+                .build_finished(
+                    state.config.sitename(),
+                    &info,
+                    &built,
+                    state.config.site_url(),
+                    &state.database,
+                )
+                .await;
+        }
+        match &built {
+            Ok(built) => {
+                *state.last_successful_build.write().await = Some(info.clone());
+                *state.theme_calls.write().await = built.calls.clone();
+                dynamic::reload(&state).await;
+                retention::prune(&state).await;
+            }
+            Err(why) => {
+                let notification = Notification {
+                    event: NotifyEvent::BuildFailed,
+                    site: state.config.sitename().to_string(),
+                    subject: format!("build {} failed", info.id),
+                    body: format!("build {} ({}) failed: {why}", info.id, info.initiated),
+                    url: None,
+                };
+                notify(&subscriptions, &notification, None).await;
+            }
+        }
+        state.builds.finished(info).await;
+    }
+}
+
+// scheduled builds, for sites with content that changes without a push (dates, plugin data)
+pub async fn schedule_builds(state: Arc<State>, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    // the first tick is right away, a restart shouldn't queue a build on its own
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        state
+            .builds
+            .trigger(BuildTrigger::new(BuildPriority::Scheduled, "schedule"))
+            .await;
+    }
+}
+
+// `X-Hub-Signature-256`, what git hosts sign push webhooks with
+pub const PUSH_SIGNATURE_HEADER: &str = "x-hub-signature-256";
+
+// A push webhook from the git host, signed with PUSH_WEBHOOK_SECRET the way github does it. The
+// secret is one the git host has to know, so it is not the admin key. The body is not looked at,
+// whatever got pushed the whole site is built.
+pub async fn push_webhook(
+    extract::State(state): extract::State<Arc<State>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let secret = match state.config.push_webhook_secret() {
+        Some(secret) => secret,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let given = headers
+        .get(PUSH_SIGNATURE_HEADER)
+        .or_else(|| headers.get(SIGNATURE_HEADER))
+        .and_then(|value| value.to_str().ok());
+    let expected = match sign(secret, &body) {
+        Ok(expected) => expected,
+        Err(why) => {
+            warn!("could not sign a push webhook: {why}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if !given.map_or(false, |given| {
+        constant_time_eq(given.as_bytes(), expected.as_bytes())
+    }) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let queue = state
+        .builds
+        .trigger(BuildTrigger::new(
+            BuildPriority::Webhook,
+            client_ip(&state.config, address.ip(), &headers).to_string(),
+        ))
+        .await;
+    (StatusCode::ACCEPTED, Json(queue)).into_response()
+}
+
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        .route("/api/builds/webhook", post(push_webhook))
+        .with_state(state)
+}
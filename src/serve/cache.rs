@@ -0,0 +1,324 @@
+use crate::injest::{
+    cache_policy::{CachePolicy, ContentClass},
+    diff::{slugs, DiffKind, SiteContentDiffElem},
+};
+use crate::{State, SERVE_DIR};
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use bytes::BufMut;
+use moka::future::Cache;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tower::ServiceExt;
+use tower_http::services::ServeDir;
+use tracing::warn;
+
+const SURROGATE_CONTROL: &str = "surrogate-control";
+
+// responses bigger than this are streamed from disk every time
+const MAX_ENTRY: u64 = 2 * 1024 * 1024;
+const MAX_TOTAL: u64 = 256 * 1024 * 1024;
+
+// What a request has to agree on to get the same response, the headers that aren't in the `vary`
+// of its class are left empty.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub path: String,
+    pub encoding: &'static str,
+    pub language: String,
+}
+
+impl CacheKey {
+    fn new<B>(request: &Request<B>, policy: &CachePolicy) -> CacheKey {
+        let class = ContentClass::of(request.uri().path());
+        let header = |name: HeaderName| match policy.varies_on(class, &name) {
+            true => request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_ascii_lowercase(),
+            false => String::new(),
+        };
+        let accepted = header(header::ACCEPT_ENCODING);
+        let encoding = ["br", "gzip"]
+            .into_iter()
+            .find(|encoding| accepted.contains(encoding))
+            .unwrap_or("identity");
+        // only the first choice, every variant of a q-list getting its own entry is not worth it
+        let language = header(header::ACCEPT_LANGUAGE)
+            .split([',', ';', '-'])
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+
+        CacheKey {
+            path: request.uri().path().to_string(),
+            encoding,
+            language,
+        }
+    }
+}
+
+// `/blog/post/` and `/blog/post/index.html` are both the page `/blog/post`
+fn site_path(path: &str) -> &str {
+    let path = path.strip_suffix("index.html").unwrap_or(path);
+    match path.trim_end_matches('/') {
+        "" => "/",
+        path => path,
+    }
+}
+
+pub struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    // as it reached the serve dir, for refreshing without the request that made it
+    uri: Uri,
+    request_headers: HeaderMap,
+    stale: AtomicBool,
+    refreshing: AtomicBool,
+}
+
+impl CachedResponse {
+    fn response(&self) -> Response {
+        let mut response = (self.status, self.body.clone()).into_response();
+        *response.headers_mut() = self.headers.clone();
+        response
+    }
+}
+
+async fn collect<B: HttpBody>(body: B) -> Option<Bytes> {
+    let mut body = Box::pin(body);
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        bytes.put(chunk.ok()?);
+    }
+    Some(bytes.into())
+}
+
+fn cacheable<B: HttpBody>(status: StatusCode, body: &B) -> bool {
+    status == StatusCode::OK
+        && body
+            .size_hint()
+            .exact()
+            .map_or(false, |size| size <= MAX_ENTRY)
+}
+
+// Rendered responses of the site. After a build the pages it changed are marked stale instead of
+// dropped: the next request still gets the old page right away while a fresh one is read in the
+// background, and everything the build didn't touch stays as it is.
+#[derive(Clone)]
+pub struct ResponseCache {
+    entries: Cache<CacheKey, Arc<CachedResponse>>,
+}
+
+impl ResponseCache {
+    pub fn new() -> ResponseCache {
+        ResponseCache {
+            entries: Cache::builder()
+                .weigher(|_, entry: &Arc<CachedResponse>| {
+                    u32::try_from(entry.body.len()).unwrap_or(u32::MAX)
+                })
+                .max_capacity(MAX_TOTAL)
+                .build(),
+        }
+    }
+
+    fn matching(&self, paths: &[String]) -> Vec<(Arc<CacheKey>, Arc<CachedResponse>)> {
+        let paths = paths
+            .iter()
+            .map(|path| site_path(path))
+            .collect::<HashSet<_>>();
+        self.entries
+            .iter()
+            .filter(|(key, _)| paths.contains(site_path(&key.path)))
+            .collect()
+    }
+
+    // pages that changed, served stale until their refresh is in
+    pub fn revalidate(&self, paths: &[String]) {
+        for (_, entry) in self.matching(paths) {
+            entry.stale.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // when there is no telling what a build changed, a new theme for example
+    pub fn revalidate_all(&self) {
+        for (_, entry) in self.entries.iter() {
+            entry.stale.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // pages that are gone, serving them stale would bring them back for a request
+    pub async fn purge(&self, paths: &[String]) {
+        for (key, _) in self.matching(paths) {
+            self.entries.invalidate(key.as_ref()).await;
+        }
+    }
+
+    // updated pages get refreshed in the background, removed ones go right away. added pages were
+    // not cached under their path before, unless it 404ed, which is never cached.
+    pub async fn apply_diff(&self, diff: &[SiteContentDiffElem]) {
+        self.revalidate(&slugs(diff, &[DiffKind::Updated]));
+        self.purge(&slugs(diff, &[DiffKind::Removed])).await;
+    }
+
+    async fn store(
+        &self,
+        key: CacheKey,
+        uri: Uri,
+        request_headers: HeaderMap,
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Arc<CachedResponse> {
+        let entry = Arc::new(CachedResponse {
+            status,
+            headers,
+            body,
+            uri,
+            request_headers,
+            stale: AtomicBool::new(false),
+            refreshing: AtomicBool::new(false),
+        });
+        self.entries.insert(key, entry.clone()).await;
+        entry
+    }
+
+    async fn refresh(&self, key: CacheKey, stale: Arc<CachedResponse>) {
+        let mut request = Request::new(Body::empty());
+        *request.uri_mut() = stale.uri.clone();
+        *request.headers_mut() = stale.request_headers.clone();
+
+        let response = match ServeDir::new(SERVE_DIR).oneshot(request).await {
+            Ok(response) => response,
+            Err(never) => match never {},
+        };
+        let (parts, body) = response.into_parts();
+        // gone, or too big to keep now
+        if !cacheable(parts.status, &body) {
+            self.entries.invalidate(&key).await;
+            return;
+        }
+        match collect(body).await {
+            Some(body) => {
+                self.store(
+                    key,
+                    stale.uri.clone(),
+                    stale.request_headers.clone(),
+                    parts.status,
+                    parts.headers,
+                    body,
+                )
+                .await;
+            }
+            None => {
+                warn!("failed to refresh {}", key.path);
+                stale.refreshing.store(false, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        ResponseCache::new()
+    }
+}
+
+// the headers a refresh has to send again for the serve dir to answer the same way
+fn forwarded_headers(headers: &HeaderMap) -> HeaderMap {
+    [header::ACCEPT_ENCODING, header::ACCEPT_LANGUAGE]
+        .into_iter()
+        .filter_map(|name| Some((name.clone(), headers.get(&name)?.clone())))
+        .collect()
+}
+
+// sits right in front of the serve dir, after the canonical rewrite, so every page is cached once
+pub async fn cache_layer(
+    extract::State(state): extract::State<Arc<State>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if request.method() != Method::GET || request.headers().contains_key(header::RANGE) {
+        return next.run(request).await;
+    }
+    // what no shared cache may keep, this is one
+    if !state
+        .cache_policy
+        .shared(ContentClass::of(request.uri().path()))
+    {
+        return next.run(request).await;
+    }
+
+    let key = CacheKey::new(&request, &state.cache_policy);
+    if let Some(entry) = state.cache.entries.get(&key) {
+        if entry.stale.load(Ordering::SeqCst) && !entry.refreshing.swap(true, Ordering::SeqCst) {
+            let cache = state.cache.clone();
+            let (key, entry) = (key.clone(), entry.clone());
+            tokio::spawn(async move { cache.refresh(key, entry).await });
+        }
+        return entry.response();
+    }
+
+    let uri = request.uri().clone();
+    let request_headers = forwarded_headers(request.headers());
+    let response = next.run(request).await;
+    if !cacheable(response.status(), response.body()) {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    match collect(body).await {
+        Some(body) => state
+            .cache
+            .store(key, uri, request_headers, parts.status, parts.headers, body)
+            .await
+            .response(),
+        None => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+// The headers of `[cache]` for what the response is. A Cache-Control set closer to the response
+// (pages behind an access rule) is left as it is, and errors get none, a 404 of a hashed name
+// is not immutable.
+pub async fn policy_layer<B>(
+    extract::State(state): extract::State<Arc<State>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let class = ContentClass::of(request.uri().path());
+    let mut response = next.run(request).await;
+    let policy = state.cache_policy.headers(class);
+    let status = response.status();
+    let headers = response.headers_mut();
+
+    // checked when the server starts, see CachePolicy::problems
+    let value = |value: Option<String>| HeaderValue::from_str(&value?).ok();
+    let answered = status.is_success() || status == StatusCode::NOT_MODIFIED;
+    if answered && !headers.contains_key(header::CACHE_CONTROL) {
+        if let Some(cache_control) = value(policy.cache_control) {
+            headers.insert(header::CACHE_CONTROL, cache_control);
+        }
+        if let Some(surrogate_control) = value(policy.surrogate_control) {
+            headers.insert(
+                HeaderName::from_static(SURROGATE_CONTROL),
+                surrogate_control,
+            );
+        }
+    }
+    for name in policy.vary.unwrap_or_default() {
+        if let Some(name) = value(Some(name)) {
+            headers.append(header::VARY, name);
+        }
+    }
+    response
+}
@@ -0,0 +1,50 @@
+use crate::{
+    injest::links::{is_page_path, TrailingSlash},
+    serve::redirect::redirect_response,
+    State,
+};
+use axum::{
+    extract,
+    http::{Method, Request, Uri},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+
+// 301s anything that isn't in its canonical form, then maps the canonical form onto the
+// `dir/index.html` layout of the serve dir
+pub async fn canonical_layer<B>(
+    extract::State(state): extract::State<Arc<State>>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return next.run(request).await;
+    }
+
+    let urls = state.config.site_url();
+    let path = request.uri().path();
+    let canonical = urls.policy().canonical_path(path);
+
+    if canonical != path {
+        let mut location = urls.root_relative(&canonical);
+        if let Some(query) = request.uri().query() {
+            location.push('?');
+            location.push_str(query);
+        }
+        return redirect_response(&location, true);
+    }
+
+    if urls.policy().trailing_slash == TrailingSlash::Never && path != "/" && is_page_path(path) {
+        let mut rewritten = format!("{path}/");
+        if let Some(query) = request.uri().query() {
+            rewritten.push('?');
+            rewritten.push_str(query);
+        }
+        if let Ok(uri) = rewritten.parse::<Uri>() {
+            *request.uri_mut() = uri;
+        }
+    }
+
+    next.run(request).await
+}
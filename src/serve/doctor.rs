@@ -0,0 +1,341 @@
+use crate::injest::report::{BuildReport, Severity};
+use crate::injest::site::{SiteMeta, SITE_FILE};
+use crate::injest::templates::build_site_theme;
+use crate::models::{
+    admin_action, backup_code, build_diff, comment, ipfs_publish, login_attempt, page_access,
+    page_hash, plugin_kv, published_page, redirect, review_assignment, session, totp_secret,
+    translation_suggestion,
+};
+use crate::{config::Config, BLOB_DIR, CACHE_DIR, SERVE_DIR, SITE_CONTENT};
+use color_eyre::{Report, Result};
+use git2::{Cred, Direction, Remote, RemoteCallbacks};
+use sea_orm::{ConnectionTrait, Database, DatabaseConnection, EntityTrait, Statement};
+use std::env::var;
+use std::fs::{create_dir_all, remove_file, write};
+use std::net::TcpListener;
+use std::path::Path;
+
+// everything Config::new reads
+const REQUIRED_ENV: &[&str] = &[
+    "POSTGRES_URL",
+    "SECRET",
+    "GIT_URL",
+    "GIT_BRANCH",
+    "TIMEZONE_DEFAULT",
+    "SITENAME",
+    "INDEX",
+    "BIND_ADDRESS",
+    "BASE_URL",
+];
+
+// one thing `moklog doctor` looked at, and what to do about it if it's wrong
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DoctorCheck {
+    pub name: String,
+    pub problem: Option<String>,
+    pub fix: Option<String>,
+}
+
+impl DoctorCheck {
+    fn ok(name: impl Into<String>) -> DoctorCheck {
+        DoctorCheck {
+            name: name.into(),
+            problem: None,
+            fix: None,
+        }
+    }
+
+    fn failed(
+        name: impl Into<String>,
+        problem: impl ToString,
+        fix: impl Into<String>,
+    ) -> DoctorCheck {
+        DoctorCheck {
+            name: name.into(),
+            problem: Some(problem.to_string()),
+            fix: Some(fix.into()),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.problem.is_none()
+    }
+}
+
+impl std::fmt::Display for DoctorCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.problem, &self.fix) {
+            (None, _) => write!(f, "ok   {}", self.name),
+            (Some(problem), fix) => {
+                write!(f, "FAIL {}: {problem}", self.name)?;
+                if let Some(fix) = fix {
+                    write!(f, "\n     fix: {fix}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn environment() -> DoctorCheck {
+    let missing = REQUIRED_ENV
+        .iter()
+        .filter(|name| var(name).is_err())
+        .copied()
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return DoctorCheck::failed(
+            "environment",
+            format!("{} not set", missing.join(", ")),
+            "set them in the environment moklog runs in, src/config.rs reads each one",
+        );
+    }
+    match Config::new() {
+        Ok(_) => DoctorCheck::ok("environment"),
+        Err(why) => DoctorCheck::failed(
+            "environment",
+            why,
+            "TIMEZONE_DEFAULT is an offset in hours, BIND_ADDRESS an ip and port like \
+             0.0.0.0:8080 and BASE_URL an absolute url",
+        ),
+    }
+}
+
+async fn database(config: &Config) -> (DoctorCheck, Option<DatabaseConnection>) {
+    let connected = async {
+        let database = Database::connect(config.postgres()).await?;
+        database
+            .execute(Statement::from_string(
+                database.get_database_backend(),
+                "SELECT 1".to_string(),
+            ))
+            .await?;
+        Ok::<_, Report>(database)
+    }
+    .await;
+    match connected {
+        Ok(database) => (DoctorCheck::ok("database"), Some(database)),
+        Err(why) => (
+            DoctorCheck::failed(
+                "database",
+                why,
+                "check that postgres is running and POSTGRES_URL has the right host, database, \
+                 user and password",
+            ),
+            None,
+        ),
+    }
+}
+
+// moklog has no migrations, so a table or column it expects and doesn't find is only noticed
+// when a query touches it. selecting a row of every table reads every column.
+async fn schema(database: &DatabaseConnection) -> DoctorCheck {
+    macro_rules! tables {
+        ($($model:ident),* $(,)?) => {
+            vec![$((
+                stringify!($model),
+                $model::Entity::find().one(database).await.err(),
+            )),*]
+        };
+    }
+    let failed = tables![
+        admin_action,
+        backup_code,
+        build_diff,
+        comment,
+        ipfs_publish,
+        login_attempt,
+        page_access,
+        page_hash,
+        plugin_kv,
+        published_page,
+        redirect,
+        review_assignment,
+        session,
+        totp_secret,
+        translation_suggestion,
+    ]
+    .into_iter()
+    .filter_map(|(model, why)| Some(format!("{model} ({})", why?)))
+    .collect::<Vec<_>>();
+
+    match failed.is_empty() {
+        true => DoctorCheck::ok("schema"),
+        false => DoctorCheck::failed(
+            "schema",
+            failed.join(", "),
+            "create the missing tables and columns, src/models has the columns each one needs",
+        ),
+    }
+}
+
+// the remote is reachable with the credentials this user has, and has the branch
+fn git(url: &str, branch: &str) -> DoctorCheck {
+    let listed = (|| {
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(|url, username, allowed| {
+            if allowed.is_ssh_key() {
+                Cred::ssh_key_from_agent(username.unwrap_or("git"))
+            } else if allowed.is_user_pass_plaintext() {
+                Cred::credential_helper(&git2::Config::open_default()?, url, username)
+            } else {
+                Cred::default()
+            }
+        });
+        let mut remote = Remote::create_detached(url)?;
+        remote.connect_auth(Direction::Fetch, Some(callbacks), None)?;
+        let heads = remote
+            .list()?
+            .iter()
+            .map(|head| head.name().to_string())
+            .collect::<Vec<_>>();
+        Ok::<_, git2::Error>(heads)
+    })();
+
+    match listed {
+        Ok(heads) if heads.contains(&format!("refs/heads/{branch}")) => DoctorCheck::ok("git"),
+        Ok(_) => DoctorCheck::failed(
+            "git",
+            format!("{url} has no branch {branch}"),
+            "set GIT_BRANCH to a branch the content repository has",
+        ),
+        Err(why) => DoctorCheck::failed(
+            "git",
+            format!("{url}: {}", why.message()),
+            "check GIT_URL, and that the ssh agent or git credential helper of the user moklog \
+             runs as has a key or token for it",
+        ),
+    }
+}
+
+// writes and removes a file, that the directory exists isn't enough
+fn writable(name: &str, dir: &Path) -> DoctorCheck {
+    let probe = dir.join(".moklog-doctor");
+    let written = create_dir_all(dir)
+        .and_then(|_| write(&probe, b"moklog"))
+        .and_then(|_| remove_file(&probe));
+    match written {
+        Ok(_) => DoctorCheck::ok(name),
+        Err(why) => DoctorCheck::failed(
+            name,
+            format!("{}: {why}", dir.display()),
+            format!(
+                "create {} and give the user moklog runs as write access to it",
+                dir.display()
+            ),
+        ),
+    }
+}
+
+fn writable_dirs(config: &Config) -> Vec<DoctorCheck> {
+    vec![
+        writable("serve dir", Path::new(SERVE_DIR)),
+        writable("index dir", Path::new(&config.index_dir)),
+        writable("cache dir", Path::new(CACHE_DIR)),
+        writable("blob store", Path::new(BLOB_DIR)),
+    ]
+}
+
+fn site_file() -> DoctorCheck {
+    let path = Path::new(SITE_CONTENT).join(SITE_FILE);
+    let site = match SiteMeta::load(SITE_CONTENT) {
+        Ok(site) => site,
+        Err(why) => {
+            return DoctorCheck::failed(
+                SITE_FILE,
+                why,
+                format!("fix the toml of {}", path.display()),
+            )
+        }
+    };
+    let mut report = BuildReport::new();
+    site.validate(&path, &mut report);
+    let errors = report
+        .diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .map(|diagnostic| diagnostic.message.clone())
+        .collect::<Vec<_>>();
+    match errors.is_empty() {
+        true => DoctorCheck::ok(SITE_FILE),
+        false => DoctorCheck::failed(
+            SITE_FILE,
+            errors.join("; "),
+            format!("`moklog check {SITE_CONTENT}` explains each of these"),
+        ),
+    }
+}
+
+async fn theme(path: &str) -> DoctorCheck {
+    match build_site_theme(path).await {
+        Ok(_) => DoctorCheck::ok("theme"),
+        Err(why) => DoctorCheck::failed(
+            "theme",
+            why,
+            format!("`moklog theme test {path}` renders every template of it"),
+        ),
+    }
+}
+
+fn port(config: &Config) -> DoctorCheck {
+    let address = config.bind_address();
+    match TcpListener::bind(address) {
+        Ok(_) => DoctorCheck::ok("port"),
+        Err(why) => DoctorCheck::failed(
+            "port",
+            format!("{address}: {why}"),
+            "stop whatever is listening there, another moklog maybe, or change BIND_ADDRESS",
+        ),
+    }
+}
+
+// everything, for `moklog doctor`. a check that can't run because an earlier one failed is left
+// out.
+pub async fn doctor(theme_path: Option<&str>) -> Vec<DoctorCheck> {
+    let environment = environment();
+    if !environment.is_ok() {
+        return vec![environment];
+    }
+    let config = match Config::new() {
+        Ok(config) => config,
+        Err(_) => return vec![environment],
+    };
+
+    let mut checks = vec![environment];
+    let (database_check, database) = database(&config).await;
+    checks.push(database_check);
+    if let Some(database) = database {
+        checks.push(schema(&database).await);
+    }
+    let (url, branch) = (config.git().to_string(), config.branch().to_string());
+    checks.push(
+        tokio::task::spawn_blocking(move || git(&url, &branch))
+            .await
+            .unwrap_or_else(|why| DoctorCheck::failed("git", why, "run doctor again")),
+    );
+    checks.extend(writable_dirs(&config));
+    checks.push(site_file());
+    checks.push(theme(theme_path.unwrap_or(config.theme_dir())).await);
+    checks.push(port(&config));
+    checks
+}
+
+// What the server checks before binding, the things that would otherwise fail a request or a
+// build later. The network ones, git and the port, are left to `moklog doctor`.
+pub async fn startup_check(config: &Config, database: &DatabaseConnection) -> Result<()> {
+    let mut checks = vec![schema(database).await];
+    checks.extend(writable_dirs(config));
+
+    let failed = checks
+        .iter()
+        .filter(|check| !check.is_ok())
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    match failed.is_empty() {
+        true => Ok(()),
+        false => Err(Report::msg(format!(
+            "startup check failed, `moklog doctor` checks everything:\n{}",
+            failed.join("\n")
+        ))),
+    }
+}
@@ -0,0 +1,31 @@
+use crate::injest::downloads::DOWNLOADS_DIR;
+use axum::{
+    http::{header, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+
+// files under /downloads/ are saved, not opened in the browser. the index page is left alone.
+pub async fn downloads_layer<B>(request: Request<B>, next: Next<B>) -> Response {
+    // already relative to the base path, the router is nested under it
+    let file_name = request
+        .uri()
+        .path()
+        .strip_prefix(&format!("/{DOWNLOADS_DIR}/"))
+        .filter(|name| !name.is_empty() && !name.contains('/') && *name != "index.html")
+        .map(|name| name.replace('"', ""));
+
+    let mut response = next.run(request).await;
+    if let Some(file_name) = file_name {
+        if response.status().is_success() {
+            if let Ok(value) =
+                HeaderValue::from_str(&format!(r#"attachment; filename="{file_name}""#))
+            {
+                response
+                    .headers_mut()
+                    .insert(header::CONTENT_DISPOSITION, value);
+            }
+        }
+    }
+    response
+}
@@ -0,0 +1,262 @@
+use crate::injest::{
+    build::{theme_tera, BuildInformation},
+    dynamic::{read_dynamic_pages, DynamicPage, DynamicPages},
+    fragment::FragmentCache,
+    generate::{insert_nested, populate_site},
+    links::SiteUrl,
+    report::BuildReport,
+    site::{SiteMeta, SiteVariables, SITE_FILE},
+    templates::SiteTheme,
+    theme_docs::ThemeCalls,
+};
+use crate::plugin::rhai::stdlib::ScriptStdlib;
+use crate::serve::{
+    access::{viewer, Viewer, ACCESS_PARAM},
+    errors::error_response,
+    private::{page_path, TOKEN_PARAM},
+};
+use crate::{State, SERVE_DIR, SITE_CONTENT};
+use axum::{
+    body::Body,
+    extract,
+    http::{header, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use color_eyre::Result;
+use language_tags::LanguageTag;
+use moka::future::Cache;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tera::{Context, Tera};
+use tracing::warn;
+
+const MAX_RENDERS: u64 = 4096;
+
+// Everything a request for a dynamic page gets the same render for. The credentials in the query
+// are left out, they're already in the viewer.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct RenderKey {
+    path: String,
+    query: BTreeMap<String, String>,
+    admin: bool,
+    signed_in: bool,
+    roles: BTreeSet<String>,
+}
+
+struct Render {
+    at: Instant,
+    html: String,
+}
+
+// The dynamic pages of the last build with the theme to render them, rebuilt after every build.
+// Renders are kept for the ttl of their page, moka has no expiry per entry so the age of one is
+// checked when it's read, and the longest ttl evicts them.
+pub struct DynamicSite {
+    pages: DynamicPages,
+    tera: Tera,
+    site: SiteMeta,
+    variables: SiteVariables,
+    language: LanguageTag,
+    renders: Cache<RenderKey, Arc<Render>>,
+}
+
+impl DynamicSite {
+    // none without dynamic pages, there is nothing to render then
+    pub fn load(state: &State, theme: &SiteTheme) -> Result<Option<DynamicSite>> {
+        let pages = read_dynamic_pages(SERVE_DIR)?;
+        if pages.is_empty() {
+            return Ok(None);
+        }
+
+        let site = SiteMeta::load(SITE_CONTENT)?;
+        let offset = state.config.default_offset()?;
+        let stdlib = ScriptStdlib::new(&site.scripts, &theme.metadata.name, offset)?;
+        let language = site.default_language();
+        let tera = theme_tera(
+            theme,
+            &language,
+            offset,
+            site.slugs.strategy,
+            &stdlib,
+            &ThemeCalls::default(),
+            // kept across requests, a fragment's ttl is how long it lives
+            &FragmentCache::new(),
+        )?;
+        // what's wrong with the theme's options was reported by the build
+        let variables = site.variables(
+            &state.config,
+            &theme.metadata,
+            &Path::new(SITE_CONTENT).join(SITE_FILE),
+            &mut BuildReport::new(),
+        );
+        let longest = pages
+            .values()
+            .map(|page| page.ttl)
+            .max()
+            .unwrap_or_default();
+        Ok(Some(DynamicSite {
+            pages,
+            tera,
+            site,
+            variables,
+            language,
+            renders: Cache::builder()
+                .max_capacity(MAX_RENDERS)
+                .time_to_live(Duration::from_secs(longest.max(1)))
+                .build(),
+        }))
+    }
+
+    fn render(
+        &self,
+        urls: &SiteUrl,
+        key: &RenderKey,
+        page: &DynamicPage,
+        build: Option<&BuildInformation>,
+    ) -> Result<String> {
+        let mut context = Context::new();
+        populate_site(
+            &mut context,
+            &self.site,
+            &self.variables,
+            urls,
+            &key.path,
+            &self.language,
+        );
+        insert_nested(&mut context, "page.type", "dynamic");
+        insert_nested(
+            &mut context,
+            "page.base_slug",
+            key.path.rsplit('/').next().unwrap_or_default(),
+        );
+        insert_nested(&mut context, "page.template", &page.template);
+        insert_nested(&mut context, "content.html", &page.html);
+        insert_nested(&mut context, "content.raw", &page.raw);
+        insert_nested(&mut context, "content.title", &page.title);
+        insert_nested(&mut context, "content.authors", &page.authors);
+        insert_nested(&mut context, "content.tags", &page.tags);
+        context.insert("custom", &page.custom);
+        if let Some(build) = build {
+            insert_nested(&mut context, "auto.build_time", &build.start_time);
+            insert_nested(&mut context, "auto.build_init", &build.initiated);
+            insert_nested(&mut context, "auto.build_id", &build.id);
+        }
+        insert_nested(&mut context, "auto.render_time", &Utc::now());
+        insert_nested(&mut context, "request.path", &key.path);
+        insert_nested(&mut context, "request.query", &key.query);
+        insert_nested(&mut context, "viewer.admin", &key.admin);
+        insert_nested(&mut context, "viewer.signed_in", &key.signed_in);
+        insert_nested(&mut context, "viewer.roles", &key.roles);
+        Ok(self.tera.render(&page.template, &context)?)
+    }
+}
+
+fn render_key(path: String, query: Option<&str>, viewer: Viewer) -> RenderKey {
+    let query = url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+        .into_owned()
+        .filter(|(name, _)| name != ACCESS_PARAM && name != TOKEN_PARAM)
+        .collect();
+    RenderKey {
+        path,
+        query,
+        admin: viewer.admin,
+        signed_in: viewer.signed_in,
+        roles: viewer.roles,
+    }
+}
+
+fn respond(html: String, ttl: u64) -> Response {
+    let mut response = (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        html,
+    )
+        .into_response();
+    // what the viewer sees is theirs, no shared cache may keep it
+    if let Ok(value) = HeaderValue::from_str(&format!("private, max-age={ttl}")) {
+        response.headers_mut().insert(header::CACHE_CONTROL, value);
+    }
+    response
+}
+
+// Renders dynamic pages when they're requested, everything else goes on to the serve dir. Inside
+// the access and private layers so their rules apply, outside the response cache, which would
+// hand one viewer's page to the next.
+pub async fn dynamic_layer(
+    extract::State(state): extract::State<Arc<State>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return next.run(request).await;
+    }
+    let dynamic = match state.dynamic.read().await.clone() {
+        Some(dynamic) => dynamic,
+        None => return next.run(request).await,
+    };
+    let path = page_path(request.uri().path());
+    let page = match dynamic.pages.get(&path) {
+        Some(page) => page.clone(),
+        None => return next.run(request).await,
+    };
+
+    let viewer = viewer(&state, request.headers(), request.uri().query()).await;
+    let key = render_key(path, request.uri().query(), viewer);
+    if let Some(render) = dynamic.renders.get(&key) {
+        if render.at.elapsed() < Duration::from_secs(page.ttl) {
+            return respond(render.html.clone(), page.ttl);
+        }
+    }
+
+    let build = state.last_successful_build.read().await.clone();
+    let urls = state.config.site_url().clone();
+    let rendered = {
+        let (dynamic, key, page) = (dynamic.clone(), key.clone(), page.clone());
+        tokio::task::spawn_blocking(move || dynamic.render(&urls, &key, &page, build.as_ref()))
+            .await
+    };
+    let html = match rendered {
+        Ok(Ok(html)) => html,
+        Ok(Err(why)) => {
+            warn!("dynamic page {} failed to render: {why}", key.path);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR).await;
+        }
+        Err(why) => {
+            warn!("dynamic page {} panicked: {why}", key.path);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR).await;
+        }
+    };
+    if page.ttl > 0 {
+        let render = Render {
+            at: Instant::now(),
+            html: html.clone(),
+        };
+        dynamic.renders.insert(key, Arc::new(render)).await;
+    }
+    respond(html, page.ttl)
+}
+
+// after a build, and at startup for the build already in the serve dir
+pub async fn reload(state: &Arc<State>) {
+    let loaded = {
+        let state = state.clone();
+        let theme = state.theme.read().await.clone();
+        tokio::task::spawn_blocking(move || DynamicSite::load(&state, &theme)).await
+    };
+    let dynamic = match loaded {
+        Ok(Ok(dynamic)) => dynamic.map(Arc::new),
+        Ok(Err(why)) => {
+            warn!("dynamic pages are not served: {why}");
+            None
+        }
+        Err(why) => {
+            warn!("loading the dynamic pages panicked: {why}");
+            None
+        }
+    };
+    *state.dynamic.write().await = dynamic;
+}
@@ -0,0 +1,24 @@
+use crate::injest::errors::ERROR_DIR;
+use crate::SERVE_DIR;
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::path::Path;
+
+// The page the build rendered for `status` from the theme's error.html, or the plain reason if
+// the theme has none.
+pub async fn error_response(status: StatusCode) -> Response {
+    let page = Path::new(SERVE_DIR)
+        .join(ERROR_DIR)
+        .join(format!("{}.html", status.as_u16()));
+    match tokio::fs::read_to_string(page).await {
+        Ok(html) => (
+            status,
+            [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+            html,
+        )
+            .into_response(),
+        Err(_) => (status, status.canonical_reason().unwrap_or_default()).into_response(),
+    }
+}
@@ -0,0 +1,95 @@
+use crate::{State, SERVE_DIR};
+use axum::{
+    extract,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use sea_orm::{ConnectionTrait, Statement};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+pub const HEALTH_PATH: &str = "/healthz";
+pub const READY_PATH: &str = "/readyz";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    fn pass() -> Check {
+        Check {
+            ok: true,
+            detail: None,
+        }
+    }
+
+    fn fail(detail: impl ToString) -> Check {
+        Check {
+            ok: false,
+            detail: Some(detail.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+// the process is up and answering, nothing else
+pub async fn healthz() -> Response {
+    Json(BTreeMap::from([("status", "alive")])).into_response()
+}
+
+async fn database_check(state: &State) -> Check {
+    let backend = state.database.get_database_backend();
+    match state
+        .database
+        .execute(Statement::from_string(backend, "SELECT 1".to_string()))
+        .await
+    {
+        Ok(_) => Check::pass(),
+        Err(why) => Check::fail(why),
+    }
+}
+
+// a build from before a restart is still being served, so a built site on disk counts too
+async fn build_check(state: &State) -> Check {
+    if state.last_successful_build.read().await.is_some()
+        || Path::new(SERVE_DIR).join("index.html").is_file()
+    {
+        Check::pass()
+    } else {
+        Check::fail("no build has succeeded yet")
+    }
+}
+
+fn index_check(state: &State) -> Check {
+    match state.search.snapshot() {
+        Some(_) => Check::pass(),
+        None => Check::fail("no search index has been built yet"),
+    }
+}
+
+// 503 until everything a request could need is there, with what is still missing in the body
+pub async fn readyz(extract::State(state): extract::State<Arc<State>>) -> Response {
+    let checks = BTreeMap::from([
+        ("database", database_check(&state).await),
+        ("build", build_check(&state).await),
+        ("index", index_check(&state)),
+    ]);
+    let ready = checks.values().all(|check| check.ok);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(Readiness { ready, checks })).into_response()
+}
@@ -0,0 +1,62 @@
+use crate::injest::ipfs::dnslink;
+use crate::models::ipfs_publish;
+use crate::State;
+use axum::{
+    extract,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::warn;
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct IpfsMirror {
+    pub cid: String,
+    pub build_id: i64,
+    pub published: DateTime<Utc>,
+    // what goes in the `_dnslink.<domain>` TXT record
+    pub dnslink: String,
+}
+
+async fn latest_mirror(state: &State) -> Result<IpfsMirror, Response> {
+    match ipfs_publish::latest(&state.database).await {
+        Ok(Some(latest)) => Ok(IpfsMirror {
+            dnslink: dnslink(&latest.cid),
+            cid: latest.cid,
+            build_id: latest.build_id,
+            published: latest.published,
+        }),
+        Ok(None) => Err(StatusCode::NOT_FOUND.into_response()),
+        Err(why) => {
+            warn!("failed to look up the latest ipfs cid: {why}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+pub async fn latest(extract::State(state): extract::State<Arc<State>>) -> Response {
+    match latest_mirror(&state).await {
+        Ok(mirror) => Json(mirror).into_response(),
+        Err(response) => response,
+    }
+}
+
+// plain text, for a script that updates the TXT record
+pub async fn latest_dnslink(extract::State(state): extract::State<Arc<State>>) -> Response {
+    match latest_mirror(&state).await {
+        Ok(mirror) => mirror.dnslink.into_response(),
+        Err(response) => response,
+    }
+}
+
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        .route("/api/ipfs", get(latest))
+        .route("/api/ipfs/dnslink", get(latest_dnslink))
+        .with_state(state)
+}
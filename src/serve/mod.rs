@@ -0,0 +1,204 @@
+use crate::injest::site::{SiteMeta, SITE_FILE};
+use crate::injest::templates::build_site_theme;
+use crate::injest::theme_docs::ThemeCalls;
+use crate::plugin::route::load_routes;
+use crate::serve::builds::BuildQueue;
+use crate::serve::cache::ResponseCache;
+use crate::serve::search::SearchIndex;
+use crate::{config::Config, State, SERVE_DIR, SITE_CONTENT};
+use axum::{middleware, routing::get, Router};
+use color_eyre::{Report, Result};
+use sea_orm::Database;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tower_http::services::ServeDir;
+
+pub mod access;
+pub mod admin;
+pub mod admin_log;
+pub mod builds;
+pub mod cache;
+pub mod canonical;
+pub mod doctor;
+pub mod downloads;
+pub mod dynamic;
+pub mod errors;
+pub mod health;
+pub mod ipfs;
+pub mod openapi;
+pub mod plugin;
+pub mod privacy;
+pub mod private;
+pub mod proxy;
+pub mod redirect;
+pub mod retention;
+pub mod search;
+pub mod security;
+pub mod session;
+pub mod suggestions;
+pub mod two_factor;
+pub mod workflow;
+
+pub fn router(state: Arc<State>) -> Router {
+    let site = Router::new()
+        // answers range requests too, which is what lets video and audio seek
+        .fallback_service(ServeDir::new(SERVE_DIR))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            cache::cache_layer,
+        ))
+        // rendered per viewer, so it can't be behind the cache
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            dynamic::dynamic_layer,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            private::private_layer,
+        ))
+        .layer(middleware::from_fn(downloads::downloads_layer))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            canonical::canonical_layer,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            redirect::redirect_layer,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            plugin::plugin_layer,
+        ))
+        // outside of the plugins too, a plugin's prefix under a covered category is covered
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            access::access_layer,
+        ))
+        .with_state(state.clone());
+
+    // the api is not content, none of the page layers apply to it
+    let site = admin::router(state.clone())
+        .merge(admin_log::router(state.clone()))
+        .merge(builds::router(state.clone()))
+        .merge(ipfs::router(state.clone()))
+        .merge(openapi::router(state.clone()))
+        .merge(privacy::router(state.clone()))
+        .merge(two_factor::router(state.clone()))
+        .merge(retention::router(state.clone()))
+        .merge(search::router(state.clone()))
+        .merge(session::router(state.clone()))
+        .merge(suggestions::router(state.clone()))
+        .merge(workflow::router(state.clone()))
+        .merge(site)
+        // over everything a browser could post to, api included
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            security::csrf_layer,
+        ))
+        // outside of the csrf check, so what it turns away is recorded too
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            admin_log::admin_log_layer,
+        ))
+        // outside of everything that sets its own, which it leaves alone
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            cache::policy_layer,
+        ))
+        // every response, errors and the api included
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            security::headers_layer,
+        ))
+        // before anything asks whether the request is admin, the code in it only works once
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            two_factor::header_code_layer,
+        ))
+        // before anything looks at who is asking
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            proxy::admin_allow_layer,
+        ));
+
+    // probes stay at the root whatever the base path, and skip the site's layers
+    let probes = Router::new()
+        .route(health::HEALTH_PATH, get(health::healthz))
+        .route(health::READY_PATH, get(health::readyz))
+        .with_state(state.clone());
+
+    // deployed under a subpath (`https://example.com/blog/`), everything else 404s
+    match state.config.site_url().base_path().trim_end_matches('/') {
+        "" => probes.merge(site),
+        base_path => probes.nest(base_path, site),
+    }
+}
+
+pub async fn run(config: Config) -> Result<()> {
+    let database = Database::connect(config.postgres()).await?;
+    doctor::startup_check(&config, &database).await?;
+    let bind_address = config.bind_address();
+    // without it the access rules are unknown, and serving anyway would serve everything
+    let site = SiteMeta::load(SITE_CONTENT)
+        .map_err(|why| Report::msg(format!("{SITE_FILE} failed to load: {why}")))?;
+    if let Some(problem) = site
+        .cache
+        .problems()
+        .into_iter()
+        .chain(site.headers.problems())
+        .next()
+    {
+        return Err(Report::msg(format!("{SITE_FILE}: {problem}")));
+    }
+    // nothing can be built without it
+    let theme = build_site_theme(config.theme_dir()).await.map_err(|why| {
+        Report::msg(format!(
+            "the theme in {} failed to load: {why}",
+            config.theme_dir()
+        ))
+    })?;
+    let routes = load_routes(Path::new(SITE_CONTENT), &site.routes, &database);
+    let search = SearchIndex::load(&config.index_dir);
+    let state = Arc::new(State {
+        database,
+        cache: ResponseCache::new(),
+        config,
+        theme: RwLock::new(Arc::new(theme)),
+        routes,
+        access: site.access,
+        cache_policy: site.cache,
+        security_headers: site.headers,
+        last_successful_build: RwLock::new(None),
+        theme_calls: RwLock::new(ThemeCalls::default()),
+        dynamic: RwLock::new(None),
+        builds: BuildQueue::new(),
+        search,
+    });
+
+    dynamic::reload(&state).await;
+    tokio::spawn(builds::run_builds(state.clone()));
+    if let Some(minutes) = state.config.build_interval() {
+        tokio::spawn(builds::schedule_builds(
+            state.clone(),
+            std::time::Duration::from_secs(minutes * 60),
+        ));
+    }
+
+    let pruned = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            session::prune(&pruned).await;
+            privacy::forget(&pruned).await;
+        }
+    });
+
+    // the peer address is what sign ins are rate limited by
+    axum::Server::bind(&bind_address)
+        .serve(router(state).into_make_service_with_connect_info::<SocketAddr>())
+        .await?;
+    Ok(())
+}
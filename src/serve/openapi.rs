@@ -0,0 +1,382 @@
+use crate::config::Config;
+use crate::injest::diff::SiteContentDiffElem;
+use crate::models::{admin_action, translation_suggestion};
+use crate::serve::admin::{PageLink, RoleToken, RoleTokenParams, TokenParams, TriggerParams};
+use crate::serve::admin_log::LogParams;
+use crate::serve::builds::QueueState;
+use crate::serve::ipfs::IpfsMirror;
+use crate::serve::privacy::{PersonalData, Purged, Subject};
+use crate::serve::search::{SearchParams, SearchResponse, SEARCH_PATH};
+use crate::serve::security::{CSRF_FIELD, CSRF_HEADER, SESSION_COOKIE};
+use crate::serve::session::{LoginForm, SessionInfo};
+use crate::serve::suggestions::{PatchParams, QueueParams, SuggestionForm};
+use crate::serve::two_factor::{BackupCodes, CodeForm, Enrollment, TwoFactorStatus};
+use crate::serve::workflow::{AssignForm, WorkflowPage, WorkflowParams};
+use crate::State;
+use axum::{
+    extract,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use color_eyre::Result;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use tracing::warn;
+
+pub const OPENAPI_PATH: &str = "/api/openapi.json";
+
+const FORM: &str = "application/x-www-form-urlencoded";
+const JSON: &str = "application/json";
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+
+// One route of the api. axum has no way of listing what a router holds, so they're written down
+// here, and tests/openapi.rs fails for a route under /api/ that isn't.
+struct Operation {
+    method: &'static str,
+    // `{id}` for what axum calls `:id`
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    // signed in as the admin, with a session or the key
+    admin: bool,
+    // a struct, each of its fields is a parameter
+    query: Option<SchemaFn>,
+    body: Option<(&'static str, SchemaFn)>,
+    status: u16,
+    response: Option<(&'static str, SchemaFn)>,
+}
+
+impl Operation {
+    fn new(method: &'static str, path: &'static str, tag: &'static str) -> Operation {
+        Operation {
+            method,
+            path,
+            tag,
+            summary: "",
+            admin: false,
+            query: None,
+            body: None,
+            status: 200,
+            response: None,
+        }
+    }
+
+    fn admin(method: &'static str, path: &'static str, tag: &'static str) -> Operation {
+        Operation {
+            admin: true,
+            ..Operation::new(method, path, tag)
+        }
+    }
+
+    fn summary(self, summary: &'static str) -> Operation {
+        Operation { summary, ..self }
+    }
+
+    fn query<T: JsonSchema>(self) -> Operation {
+        Operation {
+            query: Some(T::json_schema),
+            ..self
+        }
+    }
+
+    fn form<T: JsonSchema>(self) -> Operation {
+        Operation {
+            body: Some((FORM, SchemaGenerator::subschema_for::<T>)),
+            ..self
+        }
+    }
+
+    fn json<T: JsonSchema>(self) -> Operation {
+        Operation {
+            body: Some((JSON, SchemaGenerator::subschema_for::<T>)),
+            ..self
+        }
+    }
+
+    fn answers<T: JsonSchema>(self, status: u16) -> Operation {
+        Operation {
+            status,
+            response: Some((JSON, SchemaGenerator::subschema_for::<T>)),
+            ..self
+        }
+    }
+
+    fn answers_text(self, media_type: &'static str) -> Operation {
+        Operation {
+            response: Some((media_type, SchemaGenerator::subschema_for::<String>)),
+            ..self
+        }
+    }
+
+    fn empty(self, status: u16) -> Operation {
+        Operation {
+            status,
+            response: None,
+            ..self
+        }
+    }
+}
+
+// anything json, the manifest and the retention plan aren't described any closer
+type Untyped = BTreeMap<String, Value>;
+
+fn operations() -> Vec<Operation> {
+    vec![
+        Operation::new("post", "/api/login", "session")
+            .summary("Sign in with the admin key, and a code once a second factor is enrolled")
+            .form::<LoginForm>()
+            .answers::<SessionInfo>(200),
+        Operation::new("post", "/api/logout", "session")
+            .summary("Sign out of the current session")
+            .empty(204),
+        Operation::new("get", "/api/session", "session")
+            .summary("The session the request was made with, 401 without one")
+            .answers::<SessionInfo>(200),
+        Operation::new("get", SEARCH_PATH, "search")
+            .summary("Search the published pages")
+            .query::<SearchParams>()
+            .answers::<SearchResponse>(200),
+        Operation::admin("get", "/api/admin/builds", "builds")
+            .summary("The running, pending and last build")
+            .answers::<QueueState>(200),
+        Operation::admin("post", "/api/admin/builds", "builds")
+            .summary("Queue a build, needs elevation")
+            .query::<TriggerParams>()
+            .answers::<QueueState>(202),
+        Operation::admin("get", "/api/admin/builds/{id}/diff", "builds")
+            .summary("The pages a build added, updated and removed")
+            .answers::<Vec<SiteContentDiffElem>>(200),
+        Operation::new("post", "/api/builds/webhook", "builds")
+            .summary(
+                "Queue a build from a push webhook, the body signed with PUSH_WEBHOOK_SECRET \
+                 in `X-Hub-Signature-256`",
+            )
+            .answers::<QueueState>(202),
+        Operation::admin("get", "/api/admin/manifest", "builds")
+            .summary("The manifest of the last build")
+            .answers::<Untyped>(200),
+        Operation::admin("post", "/api/admin/manifest/changes", "builds")
+            .summary("What changed since the manifest posted")
+            .json::<Untyped>()
+            .answers::<Untyped>(200),
+        Operation::new("get", "/api/ipfs", "builds")
+            .summary("Where the last build was published to ipfs")
+            .answers::<IpfsMirror>(200),
+        Operation::new("get", "/api/ipfs/dnslink", "builds")
+            .summary("The dnslink record of the last build published to ipfs")
+            .answers_text("text/plain"),
+        Operation::new("post", "/api/translations/suggestions", "translations")
+            .summary("Suggest a change to the translation of a page")
+            .form::<SuggestionForm>()
+            .empty(202),
+        Operation::admin("get", "/api/admin/translations", "translations")
+            .summary("Translation suggestions, `pending` ones without a status")
+            .query::<QueueParams>()
+            .answers::<Vec<translation_suggestion::Model>>(200),
+        Operation::admin("get", "/api/admin/translations/patch", "translations")
+            .summary("Accepted suggestions as a patch for `git am`")
+            .query::<PatchParams>()
+            .answers_text("text/x-patch"),
+        Operation::admin(
+            "post",
+            "/api/admin/translations/{id}/accept",
+            "translations",
+        )
+        .summary("Accept a translation suggestion, needs elevation")
+        .answers::<translation_suggestion::Model>(200),
+        Operation::admin(
+            "post",
+            "/api/admin/translations/{id}/reject",
+            "translations",
+        )
+        .summary("Reject a translation suggestion, needs elevation")
+        .answers::<translation_suggestion::Model>(200),
+        Operation::admin("get", "/api/admin/workflow", "workflow")
+            .summary("Pages in draft or in review")
+            .query::<WorkflowParams>()
+            .answers::<Vec<WorkflowPage>>(200),
+        Operation::admin("post", "/api/admin/workflow/reviewers", "workflow")
+            .summary("Assign reviewer roles to a page in review, needs elevation")
+            .form::<AssignForm>()
+            .answers::<BTreeSet<String>>(200),
+        Operation::new("get", "/api/workflow", "workflow")
+            .summary("The drafts and reviews the viewer's role token shows")
+            .answers::<Vec<WorkflowPage>>(200),
+        Operation::admin("get", "/api/admin/page-link", "access")
+            .summary("A link that shows a private page or draft to whoever has it")
+            .query::<TokenParams>()
+            .answers::<PageLink>(200),
+        Operation::admin("get", "/api/admin/role-token", "access")
+            .summary("A link that signs its holder in with roles")
+            .query::<RoleTokenParams>()
+            .answers::<RoleToken>(200),
+        Operation::admin("get", "/api/admin/personal-data", "privacy")
+            .summary("Everything stored about an email, ip or ip hash")
+            .query::<Subject>()
+            .answers::<PersonalData>(200),
+        Operation::admin("delete", "/api/admin/personal-data", "privacy")
+            .summary("Delete everything stored about an email, ip or ip hash, needs elevation")
+            .query::<Subject>()
+            .answers::<Purged>(200),
+        Operation::admin("get", "/api/admin/retention", "privacy")
+            .summary("What the next pruning would delete")
+            .answers::<Untyped>(200),
+        Operation::admin("get", "/api/admin/2fa", "two factor")
+            .summary("Whether a second factor is enrolled and required")
+            .answers::<TwoFactorStatus>(200),
+        Operation::admin("post", "/api/admin/2fa/enroll", "two factor")
+            .summary("Start enrolling an authenticator app")
+            .answers::<Enrollment>(200),
+        Operation::admin("post", "/api/admin/2fa/confirm", "two factor")
+            .summary("Finish enrolling with a code from the app")
+            .form::<CodeForm>()
+            .answers::<BackupCodes>(200),
+        Operation::admin("post", "/api/admin/2fa/elevate", "two factor")
+            .summary("Confirm a code again for the actions that ask for it")
+            .form::<CodeForm>()
+            .empty(204),
+        Operation::admin("post", "/api/admin/2fa/backup-codes", "two factor")
+            .summary("Replace the backup codes")
+            .answers::<BackupCodes>(200),
+        Operation::admin("delete", "/api/admin/2fa", "two factor")
+            .summary("Remove the second factor, needs elevation")
+            .empty(204),
+        Operation::admin("get", "/api/admin/log", "admin")
+            .summary("What admins changed, newest first")
+            .query::<LogParams>()
+            .answers::<Vec<admin_action::Model>>(200),
+        Operation::admin("get", "/api/admin/theme/schema", "admin")
+            .summary("The json schema of the template context of the theme being served")
+            .answers::<Untyped>(200),
+        Operation::admin("get", "/api/admin/theme", "admin")
+            .summary("The theme's shortcodes, filters and functions")
+            .answers_text("text/html"),
+    ]
+}
+
+fn content(gen: &mut SchemaGenerator, (media_type, schema): (&str, SchemaFn)) -> Result<Value> {
+    Ok(json!({ media_type: { "schema": serde_json::to_value(schema(gen))? } }))
+}
+
+fn parameters(gen: &mut SchemaGenerator, operation: &Operation) -> Result<Vec<Value>> {
+    let mut parameters = operation
+        .path
+        .split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| {
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": { "type": "integer", "format": "int64" },
+            })
+        })
+        .collect::<Vec<_>>();
+    let query = match operation.query {
+        Some(query) => serde_json::to_value(query(gen))?,
+        None => return Ok(parameters),
+    };
+    let required = query["required"].as_array().cloned().unwrap_or_default();
+    if let Some(properties) = query["properties"].as_object() {
+        for (name, schema) in properties {
+            parameters.push(json!({
+                "name": name,
+                "in": "query",
+                "required": required.contains(&json!(name)),
+                "schema": schema,
+            }));
+        }
+    }
+    Ok(parameters)
+}
+
+// The api as OpenAPI 3.0, for generating clients and trying it out. The schemas are those of the
+// types the handlers take and answer with.
+pub fn openapi(config: &Config) -> Result<Value> {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+
+    let mut paths = Map::new();
+    for operation in operations() {
+        let mut object = json!({
+            "tags": [operation.tag],
+            "summary": operation.summary,
+            "responses": {},
+        });
+        let parameters = parameters(&mut gen, &operation)?;
+        if !parameters.is_empty() {
+            object["parameters"] = json!(parameters);
+        }
+        if let Some(body) = operation.body {
+            object["requestBody"] =
+                json!({ "required": true, "content": content(&mut gen, body)? });
+        }
+        let description = StatusCode::from_u16(operation.status)
+            .ok()
+            .and_then(|status| status.canonical_reason())
+            .unwrap_or_default();
+        let mut response = json!({ "description": description });
+        if let Some(body) = operation.response {
+            response["content"] = content(&mut gen, body)?;
+        }
+        object["responses"][operation.status.to_string()] = response;
+        if operation.admin {
+            object["security"] = json!([{ "bearer": [] }, { "session": [] }]);
+            object["responses"]["401"] = json!({ "description": "not signed in as the admin" });
+        }
+        paths.entry(operation.path).or_insert_with(|| json!({}))[operation.method] = object;
+    }
+
+    let schemas = serde_json::to_value(gen.take_definitions())?;
+    Ok(json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": config.sitename(),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        // the paths are from the base path on, where the server nests the api
+        "servers": [{ "url": config.site_url().absolute("/").trim_end_matches('/') }],
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "the admin key",
+                },
+                "session": {
+                    "type": "apiKey",
+                    "in": "cookie",
+                    "name": SESSION_COOKIE,
+                    "description": format!(
+                        "from /api/login, anything posted with it also needs its csrf token in \
+                         the `{CSRF_HEADER}` header or the `{CSRF_FIELD}` field"
+                    ),
+                },
+            },
+        },
+    }))
+}
+
+pub async fn openapi_document(extract::State(state): extract::State<Arc<State>>) -> Response {
+    match openapi(&state.config) {
+        Ok(document) => Json(document).into_response(),
+        Err(why) => {
+            warn!("failed to describe the api: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        .route(OPENAPI_PATH, get(openapi_document))
+        .with_state(state)
+}
@@ -0,0 +1,93 @@
+use crate::{
+    plugin::route::{is_visible_header, PluginRequest, MAX_BODY},
+    State,
+};
+use axum::{
+    body::{Body, HttpBody},
+    extract,
+    http::{HeaderMap, HeaderName, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tracing::warn;
+
+// hands anything under a plugin's prefix to its script, everything else goes on to the site
+pub async fn plugin_layer(
+    extract::State(state): extract::State<Arc<State>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let path = request.uri().path();
+    let (route, below) = match state.routes.iter().find_map(|route| {
+        route
+            .matches(path)
+            .map(|below| (route.clone(), below.to_string()))
+    }) {
+        Some(found) => found,
+        None => return next.run(request).await,
+    };
+
+    let (parts, mut body) = request.into_parts();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        };
+        if bytes.len() + chunk.len() > MAX_BODY {
+            return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    let plugin_request = PluginRequest {
+        method: parts.method.to_string(),
+        path: below,
+        query: parts
+            .uri
+            .query()
+            .map(|query| {
+                url::form_urlencoded::parse(query.as_bytes())
+                    .into_owned()
+                    .collect()
+            })
+            .unwrap_or_default(),
+        headers: parts
+            .headers
+            .iter()
+            .filter(|(name, _)| is_visible_header(name.as_str()))
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect::<BTreeMap<_, _>>(),
+        body: String::from_utf8_lossy(&bytes).into_owned(),
+    };
+
+    let name = route.name.clone();
+    let response = match tokio::task::spawn_blocking(move || route.handle(&plugin_request)).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(why)) => {
+            warn!("{why}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        Err(why) => {
+            warn!("plugin {name} panicked: {why}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let mut headers = HeaderMap::new();
+    for (header, value) in &response.headers {
+        match (
+            HeaderName::from_bytes(header.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(header), Ok(value)) => {
+                headers.insert(header, value);
+            }
+            _ => warn!("plugin {name} sent an invalid header"),
+        }
+    }
+    let status = StatusCode::from_u16(response.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    (status, headers, response.body).into_response()
+}
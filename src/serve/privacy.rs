@@ -0,0 +1,219 @@
+use crate::injest::{retention::RetentionOptions, site::SiteMeta};
+use crate::models::{comment, login_attempt, translation_suggestion};
+use crate::serve::admin::is_admin;
+use crate::serve::two_factor::elevation_required;
+use crate::{State, SITE_CONTENT};
+use axum::{
+    extract::{self, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::{Duration, Utc};
+use color_eyre::Result;
+use schemars::JsonSchema;
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+// `?email=someone@example.com&ip=203.0.113.7`, who a data request is about, any of them
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct Subject {
+    pub email: Option<String>,
+    pub ip: Option<String>,
+    // hex sha256 of the address, for requests that only have the hash, see `hash_ip`
+    pub ip_hash: Option<String>,
+}
+
+impl Subject {
+    fn email(&self) -> Option<&str> {
+        self.email
+            .as_deref()
+            .map(str::trim)
+            .filter(|email| !email.is_empty())
+    }
+
+    fn ip(&self) -> Option<&str> {
+        self.ip
+            .as_deref()
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty())
+    }
+
+    fn ip_hash(&self) -> Option<String> {
+        self.ip_hash
+            .as_deref()
+            .map(str::trim)
+            .filter(|hash| !hash.is_empty())
+            .map(str::to_ascii_lowercase)
+    }
+}
+
+fn hash_ip(ip: &str) -> String {
+    hex::encode(Sha256::digest(ip.as_bytes()))
+}
+
+// Suggestions and sign in attempts keep the raw address, so a hash is matched against every
+// address they have.
+async fn addresses(db: &DatabaseConnection, subject: &Subject) -> Result<Vec<String>> {
+    let mut addresses = subject
+        .ip()
+        .map(str::to_string)
+        .into_iter()
+        .collect::<Vec<_>>();
+    if let Some(hash) = subject.ip_hash() {
+        let mut known = login_attempt::ips(db).await?;
+        known.extend(translation_suggestion::ips(db).await?);
+        addresses.extend(known.into_iter().filter(|ip| hash_ip(ip) == hash));
+    }
+    addresses.sort();
+    addresses.dedup();
+    Ok(addresses)
+}
+
+// everything the server keeps about a subject
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct PersonalData {
+    pub comments: Vec<comment::Model>,
+    pub translation_suggestions: Vec<translation_suggestion::Model>,
+    pub login_attempts: Vec<login_attempt::Model>,
+}
+
+// rows deleted, by table
+#[derive(Clone, Debug, Default, Serialize, JsonSchema)]
+pub struct Purged {
+    pub comments: u64,
+    pub translation_suggestions: u64,
+    pub login_attempts: u64,
+}
+
+async fn collect(db: &DatabaseConnection, subject: &Subject) -> Result<PersonalData> {
+    let ips = addresses(db, subject).await?;
+    Ok(PersonalData {
+        comments: match subject.email() {
+            Some(email) => comment::by_email(db, email).await?,
+            None => vec![],
+        },
+        translation_suggestions: translation_suggestion::by_subject(db, subject.email(), &ips)
+            .await?,
+        login_attempts: login_attempt::by_ips(db, &ips).await?,
+    })
+}
+
+async fn purge(db: &DatabaseConnection, subject: &Subject) -> Result<Purged> {
+    let ips = addresses(db, subject).await?;
+    Ok(Purged {
+        comments: match subject.email() {
+            Some(email) => comment::delete_by_email(db, email).await?,
+            None => 0,
+        },
+        translation_suggestions: translation_suggestion::delete_by_subject(
+            db,
+            subject.email(),
+            &ips,
+        )
+        .await?,
+        login_attempts: login_attempt::delete_by_ips(db, &ips).await?,
+    })
+}
+
+// a request about nobody would match every row without an email, ip or ip hash
+fn check_subject(subject: &Subject) -> Option<Response> {
+    match (subject.email(), subject.ip(), subject.ip_hash()) {
+        (None, None, None) => {
+            let why = "an email, an ip or an ip hash is needed";
+            Some((StatusCode::BAD_REQUEST, why).into_response())
+        }
+        _ => None,
+    }
+}
+
+pub async fn export_personal_data(
+    extract::State(state): extract::State<Arc<State>>,
+    Query(subject): Query<Subject>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if let Some(response) = check_subject(&subject) {
+        return response;
+    }
+    match collect(&state.database, &subject).await {
+        Ok(data) => Json(data).into_response(),
+        Err(why) => {
+            warn!("failed to export personal data: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn purge_personal_data(
+    extract::State(state): extract::State<Arc<State>>,
+    Query(subject): Query<Subject>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if let Some(response) = check_subject(&subject) {
+        return response;
+    }
+    if let Some(response) = elevation_required(&state, &headers).await {
+        return response;
+    }
+    match purge(&state.database, &subject).await {
+        Ok(purged) => {
+            info!(
+                "purged {} comments, {} translation suggestions and {} sign in attempts on request",
+                purged.comments, purged.translation_suggestions, purged.login_attempts
+            );
+            Json(purged).into_response()
+        }
+        Err(why) => {
+            warn!("failed to purge personal data: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// Drops addresses and emails past `ip_days` and `email_days`.
+pub async fn forget_expired(db: &DatabaseConnection, options: &RetentionOptions) -> Result<()> {
+    if let Some(days) = options.ip_days {
+        let window = Duration::days(days.into());
+        login_attempt::prune(db, window).await?;
+        translation_suggestion::forget_ips(db, Utc::now() - window).await?;
+    }
+    if let Some(days) = options.email_days {
+        let before = Utc::now() - Duration::days(days.into());
+        comment::forget_emails(db, before).await?;
+        translation_suggestion::forget_submitters(db, before).await?;
+    }
+    Ok(())
+}
+
+// by the `[retention]` of site.toml, hourly next to `session::prune` whether or not anything builds
+pub async fn forget(state: &State) {
+    let options = match SiteMeta::load(SITE_CONTENT) {
+        Ok(site) => site.retention,
+        Err(why) => {
+            warn!("not forgetting personal data, {why}");
+            return;
+        }
+    };
+    if let Err(why) = forget_expired(&state.database, &options).await {
+        warn!("failed to forget expired personal data: {why}");
+    }
+}
+
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        .route(
+            "/api/admin/personal-data",
+            get(export_personal_data).delete(purge_personal_data),
+        )
+        .with_state(state)
+}
@@ -0,0 +1,218 @@
+use crate::injest::{generate::PageAccess, manifest::MANIFEST_FILE, workflow::WorkflowState};
+use crate::models::{page_access, review_assignment};
+use crate::serve::{
+    access::viewer,
+    errors::error_response,
+    security::{cookie, set_cookie_at, SameSite},
+};
+use crate::State;
+use axum::{
+    extract,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use percent_encoding::percent_decode_str;
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use tracing::warn;
+
+// `?token=` on a link to a private page
+pub const TOKEN_PARAM: &str = "token";
+// the same token, kept for the files next to the page, which are requested without it
+pub const PAGE_TOKEN_COOKIE: &str = "moklog_page_token";
+
+fn token_mac(secret: &str, subject: &str, expires: i64) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac takes keys of any length");
+    mac.update(format!("{subject}\n{expires}").as_bytes());
+    mac
+}
+
+// `<expires>.<signature>`, `expires` a unix timestamp or 0 for a token that never expires. The
+// subject is the page path for private pages and `roles:<roles>` for role tokens. Signed with
+// SECRET, changing it revokes every token handed out.
+pub fn sign_token(secret: &str, subject: &str, expires: i64) -> String {
+    let signature = token_mac(secret, subject, expires).finalize().into_bytes();
+    format!("{expires}.{}", hex::encode(signature))
+}
+
+pub fn verify_token(secret: &str, subject: &str, token: &str) -> bool {
+    let (expires, signature) = match token.split_once('.') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let (expires, signature) = match (expires.parse::<i64>(), hex::decode(signature)) {
+        (Ok(expires), Ok(signature)) => (expires, signature),
+        _ => return false,
+    };
+    if expires != 0 && expires < Utc::now().timestamp() {
+        return false;
+    }
+    token_mac(secret, subject, expires)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+// the page a request is for, `/blog/post/index.html` and `/blog/post/` are both `/blog/post`
+pub fn page_path(request_path: &str) -> String {
+    let path = request_path
+        .strip_suffix("index.html")
+        .unwrap_or(request_path)
+        .trim_end_matches('/');
+    match path {
+        "" => "/".to_string(),
+        path => path.to_string(),
+    }
+}
+
+// The path ServeDir answers a request for: percent decoded, with empty and `.` segments dropped.
+// Access is decided on this and not the raw path, or `/p%6Fst` and `//post` would get past it.
+// None for what ServeDir refuses, `..` and paths that aren't utf-8.
+pub fn served_path(request_path: &str) -> Option<String> {
+    let decoded = percent_decode_str(request_path).decode_utf8().ok()?;
+    let mut path = String::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            segment => {
+                path.push('/');
+                path.push_str(segment);
+            }
+        }
+    }
+    if path.is_empty() || decoded.ends_with('/') {
+        path.push('/');
+    }
+    Some(path)
+}
+
+// `/blog/post/image.png`, `/blog/post`, `/blog` and `/`: the path and the pages whose directories
+// it is in, nearest first
+pub fn covering_paths(path: &str) -> Vec<String> {
+    let mut paths = vec![];
+    let mut path = path;
+    loop {
+        paths.push(path.to_string());
+        match path.rsplit_once('/') {
+            Some(("", _)) if path != "/" => path = "/",
+            Some((parent, _)) if !parent.is_empty() => path = parent,
+            _ => break,
+        }
+    }
+    paths
+}
+
+// The nearest hidden page `path` is or is under. Everything in the directory of a page that
+// isn't public, its images as well as the pages below it, is as hidden as the page.
+pub fn hidden_cover(
+    rows: &BTreeMap<String, PageAccess>,
+    path: &str,
+) -> Option<(String, PageAccess)> {
+    covering_paths(path).into_iter().find_map(|path| {
+        let access = rows.get(&path).filter(|access| access.is_hidden())?.clone();
+        Some((path, access))
+    })
+}
+
+// whether a viewer with `roles` may see a page that isn't published yet, through the workflow
+// rules of the build or as a reviewer assigned to it since
+async fn workflow_allows(
+    state: &State,
+    path: &str,
+    access: &PageAccess,
+    roles: &BTreeSet<String>,
+) -> bool {
+    if access.private || access.state == WorkflowState::Published || roles.is_empty() {
+        return false;
+    }
+    if !access.roles.is_disjoint(roles) {
+        return true;
+    }
+    if access.state != WorkflowState::Review {
+        return false;
+    }
+    match review_assignment::for_page(&state.database, path).await {
+        Ok(assigned) => !assigned.is_disjoint(roles),
+        Err(why) => {
+            warn!("failed to look up the reviewers of {path}: {why}");
+            false
+        }
+    }
+}
+
+// Private and unpublished pages are served to admins and to links with a valid token, drafts and
+// pages in review to the roles the workflow lets see them too. They're a 404 to everyone else so
+// they don't give away that they exist, and so is everything in their directories. Without the
+// database nothing private can be told apart, so nothing is served.
+pub async fn private_layer<B>(
+    extract::State(state): extract::State<Arc<State>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = match served_path(request.uri().path()) {
+        Some(path) => page_path(&path),
+        // ServeDir won't serve it either
+        None => return error_response(StatusCode::NOT_FOUND).await,
+    };
+    // it names every private page, the admin route in front answers for it and the file in the
+    // serve dir is never served, however its name is spelled
+    if path == format!("/{MANIFEST_FILE}") {
+        return error_response(StatusCode::NOT_FOUND).await;
+    }
+    let (page, access) = match page_access::find_many(&state.database, &covering_paths(&path)).await
+    {
+        Ok(rows) => match hidden_cover(&rows, &path) {
+            Some(cover) => cover,
+            None => return next.run(request).await,
+        },
+        Err(why) => {
+            warn!("failed to look up access for {path}: {why}");
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+    };
+
+    let valid = |token: &String| verify_token(state.config.admin_key(), &page, token);
+    let new_token = request
+        .uri()
+        .query()
+        .and_then(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == TOKEN_PARAM)
+                .map(|(_, value)| value.into_owned())
+        })
+        .filter(valid);
+    let viewer = viewer(&state, request.headers(), request.uri().query()).await;
+    let allowed = viewer.admin
+        || new_token.is_some()
+        || cookie(request.headers(), PAGE_TOKEN_COOKIE).map_or(false, |token| valid(&token))
+        || workflow_allows(&state, &page, &access, &viewer.roles).await;
+    if !allowed {
+        return error_response(StatusCode::NOT_FOUND).await;
+    }
+
+    let mut response = next.run(request).await;
+    // nothing in between gets to keep a copy
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("private, no-store"),
+    );
+    if let Some(token) = new_token {
+        // Lax like role tokens, the link is followed from somewhere else
+        let scope = state.config.site_url().root_relative(&page);
+        set_cookie_at(
+            &state,
+            response.headers_mut(),
+            PAGE_TOKEN_COOKIE,
+            &token,
+            SameSite::Lax,
+            None,
+            &scope,
+        );
+    }
+    response
+}
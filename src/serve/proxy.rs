@@ -0,0 +1,133 @@
+use crate::config::Config;
+use crate::injest::manifest::MANIFEST_FILE;
+use crate::serve::{errors::error_response, security::constant_time_eq};
+use crate::State;
+use axum::{
+    extract::{self, ConnectInfo},
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tracing::warn;
+
+// who an auth proxy in front signed in, believed only with AUTH_PROXY_SECRET next to it
+pub const FORWARDED_USER: &str = "x-forwarded-user";
+pub const AUTH_PROXY_SECRET_HEADER: &str = "x-auth-proxy-secret";
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+fn listed(networks: &[IpNet], ip: IpAddr) -> bool {
+    networks.iter().any(|network| network.contains(&ip))
+}
+
+// `192.0.2.60`, `"[2001:db8:cafe::17]:4711"` or `198.51.100.17:8080`. `unknown` and obfuscated
+// identifiers (`_hidden`) are None.
+fn node_ip(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(address) = node.parse::<SocketAddr>() {
+        return Some(address.ip());
+    }
+    node.strip_prefix('[')?.split(']').next()?.parse().ok()
+}
+
+// the `for=` of every element of a Forwarded header (rfc 7239), nearest the client first
+fn forwarded_for(value: &str) -> Vec<Option<IpAddr>> {
+    value
+        .split(',')
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, node)| node_ip(node))
+        })
+        .collect()
+}
+
+// The address of whoever is asking. A request from one of TRUSTED_PROXIES carries the addresses
+// it was forwarded for, those are followed from the nearest hop back for as long as the hops
+// are trusted proxies too. Forwarded wins over X-Forwarded-For, a hop that can't be read ends it.
+pub fn client_ip(config: &Config, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    if !listed(config.trusted_proxies(), peer) {
+        return peer;
+    }
+    let joined = |name: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let hops = match joined(header::FORWARDED.as_str()) {
+        forwarded if !forwarded.is_empty() => forwarded_for(&forwarded),
+        _ => joined(X_FORWARDED_FOR)
+            .split(',')
+            .filter(|hop| !hop.trim().is_empty())
+            .map(node_ip)
+            .collect(),
+    };
+
+    let mut client = peer;
+    for hop in hops.into_iter().rev() {
+        match hop {
+            Some(hop) => client = hop,
+            None => break,
+        }
+        if !listed(config.trusted_proxies(), client) {
+            break;
+        }
+    }
+    client
+}
+
+// Whoever the auth proxy says is signed in, when it proves it is the proxy. Without
+// AUTH_PROXY_SECRET nobody is.
+pub fn proxy_user(config: &Config, headers: &HeaderMap) -> Option<String> {
+    let secret = config.auth_proxy_secret()?;
+    let given = headers.get(AUTH_PROXY_SECRET_HEADER)?.as_bytes();
+    if !constant_time_eq(given, secret.as_bytes()) {
+        return None;
+    }
+    headers
+        .get(FORWARDED_USER)
+        .and_then(|user| user.to_str().ok())
+        .map(str::trim)
+        .filter(|user| !user.is_empty())
+        .map(ToString::to_string)
+}
+
+// the admin api, signing in to it, and the build manifest the admin router serves at the root
+pub fn is_admin_surface(path: &str) -> bool {
+    path == "/api/admin"
+        || path.starts_with("/api/admin/")
+        || path == "/api/login"
+        || path.strip_prefix('/') == Some(MANIFEST_FILE)
+}
+
+// Outside of ADMIN_ALLOW the admin api isn't there, whatever key comes with the request.
+pub async fn admin_allow_layer<B>(
+    extract::State(state): extract::State<Arc<State>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let allow = state.config.admin_allow();
+    if allow.is_empty() || !is_admin_surface(request.uri().path()) {
+        return next.run(request).await;
+    }
+    let client = client_ip(&state.config, peer.ip(), request.headers());
+    if !listed(allow, client) {
+        warn!(
+            "{client} asked for {} from outside ADMIN_ALLOW",
+            request.uri().path()
+        );
+        return error_response(StatusCode::NOT_FOUND).await;
+    }
+    next.run(request).await
+}
@@ -0,0 +1,35 @@
+use crate::{models::redirect::find_redirect, State};
+use axum::{
+    extract,
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use tracing::warn;
+
+pub fn redirect_response(to: &str, permanent: bool) -> Response {
+    let status = if permanent {
+        StatusCode::MOVED_PERMANENTLY
+    } else {
+        StatusCode::FOUND
+    };
+    (status, [(header::LOCATION, to.to_string())]).into_response()
+}
+
+pub async fn redirect_layer<B>(
+    extract::State(state): extract::State<Arc<State>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = request.uri().path().trim_end_matches('/');
+    let path = if path.is_empty() { "/" } else { path };
+
+    match find_redirect(&state.database, path).await {
+        Ok(Some(redirect)) => return redirect_response(&redirect.to_path, redirect.permanent),
+        Ok(None) => {}
+        Err(why) => warn!("failed to look up redirect for {path}: {why}"),
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,110 @@
+use crate::injest::{
+    blobs::BlobStore,
+    retention::{cache_overflow, remove_files, serve_leftovers, PrunePlan, RetentionOptions},
+    site::SiteMeta,
+};
+use crate::models::{build_diff, ipfs_publish};
+use crate::serve::admin::is_admin;
+use crate::{State, BLOB_DIR, CACHE_DIR, SERVE_DIR, SITE_CONTENT};
+use axum::{
+    extract,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use color_eyre::Result;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::{info, warn};
+
+// What `options` would have pruned right now. Builds are known by the diffs and ipfs cids
+// recorded for them, a build id is the millisecond it started.
+async fn plan(state: &State, options: &RetentionOptions) -> Result<PrunePlan> {
+    let mut plan = PrunePlan::default();
+    let mut oldest_blobs_kept = None;
+    if let Some(keep) = options.keep_builds.filter(|keep| *keep > 0) {
+        let mut builds = build_diff::build_ids(&state.database).await?;
+        builds.extend(ipfs_publish::build_ids(&state.database).await?);
+        let newest_first = builds.into_iter().rev().collect::<Vec<_>>();
+        if let Some(oldest_kept) = newest_first.get(keep - 1) {
+            plan.builds = newest_first[keep..].to_vec();
+            oldest_blobs_kept = Some(*oldest_kept);
+            let cutoff = UNIX_EPOCH + Duration::from_millis(*oldest_kept);
+            plan.add(
+                tokio::task::spawn_blocking(move || serve_leftovers(SERVE_DIR, cutoff)).await??,
+            );
+        }
+    }
+    // without keep_builds every recorded build is kept, only what none of them links is garbage
+    plan.add(
+        tokio::task::spawn_blocking(move || {
+            let blobs = BlobStore::new(BLOB_DIR);
+            let kept = blobs.kept_builds(oldest_blobs_kept)?;
+            blobs.garbage(&kept, Path::new(SERVE_DIR))
+        })
+        .await??,
+    );
+    if let Some(max_bytes) = options.max_cache_bytes {
+        plan.add(tokio::task::spawn_blocking(move || cache_overflow(CACHE_DIR, max_bytes)).await??);
+    }
+    Ok(plan)
+}
+
+// Prunes by the `[retention]` of site.toml. Run between builds, so nothing a build is writing or
+// reading from the caches goes out from under it.
+pub async fn prune(state: &State) {
+    let options = match SiteMeta::load(SITE_CONTENT) {
+        Ok(site) => site.retention,
+        Err(why) => {
+            warn!("not pruning, {why}");
+            return;
+        }
+    };
+    let pruned = async {
+        let plan = plan(state, &options).await?;
+        build_diff::delete_builds(&state.database, &plan.builds).await?;
+        ipfs_publish::delete_builds(&state.database, &plan.builds).await?;
+        let files = plan.files.clone();
+        tokio::task::spawn_blocking(move || remove_files(&files)).await??;
+        Ok::<_, color_eyre::Report>(plan)
+    };
+    match pruned.await {
+        Ok(plan) if plan.builds.is_empty() && plan.files.is_empty() => {}
+        Ok(plan) => info!(
+            "pruned {} builds and {} files, {} bytes",
+            plan.builds.len(),
+            plan.files.len(),
+            plan.bytes
+        ),
+        Err(why) => warn!("pruning failed: {why}"),
+    }
+}
+
+// a dry run, what the next pruning would remove
+pub async fn preview(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let options = match SiteMeta::load(SITE_CONTENT) {
+        Ok(site) => site.retention,
+        Err(why) => return (StatusCode::INTERNAL_SERVER_ERROR, why.to_string()).into_response(),
+    };
+    match plan(&state, &options).await {
+        Ok(plan) => Json(plan).into_response(),
+        Err(why) => {
+            warn!("failed to plan pruning: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        .route("/api/admin/retention", get(preview))
+        .with_state(state)
+}
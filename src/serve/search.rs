@@ -0,0 +1,156 @@
+use crate::injest::search::{
+    current_snapshot, index_pages, remove_stale_snapshots, search, SearchDocument, SearchHit,
+    SearchSnapshot, DEFAULT_SNIPPET_LENGTH,
+};
+use crate::serve::access::viewer;
+use crate::State;
+use axum::{
+    extract::{self, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use language_tags::LanguageTag;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tantivy::query::QueryParserError;
+use tracing::warn;
+
+pub const SEARCH_PATH: &str = "/api/search";
+
+const DEFAULT_LIMIT: usize = 10;
+const MAX_LIMIT: usize = 50;
+
+// `?q=…&lang=en&limit=10&snippet=160`, `lang=ko` is any korean page and `lang=ko-KR` only those
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct SearchParams {
+    pub q: String,
+    pub lang: Option<String>,
+    pub limit: Option<usize>,
+    // characters per snippet, capped at MAX_SNIPPET_LENGTH
+    pub snippet: Option<usize>,
+}
+
+// The snapshot searches are answered from. A search holds on to the one it started with, so a
+// rebuild never pulls the index out from under it.
+pub struct SearchIndex {
+    index_dir: PathBuf,
+    current: RwLock<Option<Arc<SearchSnapshot>>>,
+}
+
+impl SearchIndex {
+    // the snapshot of the last build before a restart, if there is one
+    pub fn load(index_dir: impl Into<PathBuf>) -> SearchIndex {
+        let index_dir = index_dir.into();
+        if let Err(why) = remove_stale_snapshots(&index_dir) {
+            warn!("failed to clean up {}: {why}", index_dir.display());
+        }
+        let current = match current_snapshot(&index_dir)
+            .and_then(|snapshot| snapshot.map(SearchSnapshot::open).transpose())
+        {
+            Ok(snapshot) => snapshot.map(Arc::new),
+            Err(why) => {
+                warn!("no search until the next build, the index failed to open: {why}");
+                None
+            }
+        };
+        SearchIndex {
+            index_dir,
+            current: RwLock::new(current),
+        }
+    }
+
+    pub fn snapshot(&self) -> Option<Arc<SearchSnapshot>> {
+        self.current.read().unwrap().clone()
+    }
+
+    // Indexes `documents` into a new snapshot and starts answering from it. The one before is
+    // removed once the searches still using it are done.
+    pub fn rebuild(&self, documents: &[SearchDocument]) -> color_eyre::Result<()> {
+        let snapshot = Arc::new(SearchSnapshot::open(index_pages(
+            &self.index_dir,
+            documents,
+        )?)?);
+        let previous = self.current.write().unwrap().replace(snapshot);
+        if let Some(previous) = previous {
+            previous.retire();
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct SearchResponse {
+    pub query: String,
+    pub hits: Vec<SearchHit>,
+    // "did you mean", see search::suggest
+    pub suggestion: Option<String>,
+    // the hits are for words close to the query's, nothing matched it as written
+    pub fuzzy: bool,
+}
+
+pub async fn search_pages(
+    extract::State(state): extract::State<Arc<State>>,
+    Query(params): Query<SearchParams>,
+    headers: HeaderMap,
+) -> Response {
+    if params.q.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, "empty query").into_response();
+    }
+    if let Some(language) = &params.lang {
+        if LanguageTag::parse(language).is_err() {
+            let why = format!("\"{language}\" is not a language tag");
+            return (StatusCode::BAD_REQUEST, why).into_response();
+        }
+    }
+    let snapshot = match state.search.snapshot() {
+        Some(snapshot) => snapshot,
+        None => return (StatusCode::SERVICE_UNAVAILABLE, "nothing is indexed yet").into_response(),
+    };
+    let query = params.q.clone();
+    let hits = tokio::task::spawn_blocking(move || {
+        search(
+            &snapshot,
+            &params.q,
+            params.lang.as_deref(),
+            params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+            params.snippet.unwrap_or(DEFAULT_SNIPPET_LENGTH),
+        )
+    })
+    .await;
+
+    match hits {
+        Ok(Ok(mut results)) => {
+            // pages behind an access rule don't show up for viewers who couldn't open them
+            let viewer = viewer(&state, &headers, None).await;
+            results.hits.retain(|hit| viewer.may_see(&state, &hit.path));
+            Json(SearchResponse {
+                query,
+                hits: results.hits,
+                suggestion: results.suggestion,
+                fuzzy: results.fuzzy,
+            })
+            .into_response()
+        }
+        Ok(Err(why)) if why.downcast_ref::<QueryParserError>().is_some() => {
+            (StatusCode::BAD_REQUEST, why.to_string()).into_response()
+        }
+        Ok(Err(why)) => {
+            warn!("search for \"{query}\" failed: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(why) => {
+            warn!("search for \"{query}\" panicked: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        .route(SEARCH_PATH, get(search_pages))
+        .with_state(state)
+}
@@ -0,0 +1,202 @@
+use crate::models::session;
+use crate::State;
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use tracing::warn;
+
+// the session cookie of a signed in browser
+pub const SESSION_COOKIE: &str = "moklog_session";
+// where a browser posted form puts its csrf token, scripts send the header instead
+pub const CSRF_FIELD: &str = "_csrf";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+// forms are small, anything bigger than this isn't a form we need to look into
+const MAX_FORM_SIZE: usize = 64 * 1024;
+
+// compares secrets without giving away how much of them matched
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+pub fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+}
+
+// HttpOnly always, Secure whenever the site is served over https, scoped to the base path.
+// `max_age` in seconds, None for a cookie that goes away with the browser.
+pub fn set_cookie(
+    state: &State,
+    headers: &mut HeaderMap,
+    name: &str,
+    value: &str,
+    same_site: SameSite,
+    max_age: Option<i64>,
+) {
+    let path = state.config.site_url().base_path();
+    set_cookie_at(state, headers, name, value, same_site, max_age, path);
+}
+
+// only sent along with requests for `path` and what's under it
+pub fn set_cookie_at(
+    state: &State,
+    headers: &mut HeaderMap,
+    name: &str,
+    value: &str,
+    same_site: SameSite,
+    max_age: Option<i64>,
+    path: &str,
+) {
+    let secure = match state.config.site_url().base().scheme() {
+        "https" => "; Secure",
+        _ => "",
+    };
+    let same_site = match same_site {
+        SameSite::Strict => "Strict",
+        SameSite::Lax => "Lax",
+    };
+    let max_age = max_age
+        .map(|max_age| format!("; Max-Age={max_age}"))
+        .unwrap_or_default();
+    let cookie =
+        format!("{name}={value}; Path={path}; HttpOnly; SameSite={same_site}{secure}{max_age}");
+    match HeaderValue::from_str(&cookie) {
+        Ok(cookie) => {
+            headers.append(header::SET_COOKIE, cookie);
+        }
+        Err(why) => warn!("not setting cookie {name}: {why}"),
+    }
+}
+
+pub fn clear_cookie(state: &State, headers: &mut HeaderMap, name: &str) {
+    set_cookie(state, headers, name, "", SameSite::Strict, Some(0));
+}
+
+// the session behind the cookie, None when signed out or it expired
+pub async fn current_session(state: &State, headers: &HeaderMap) -> Option<session::Model> {
+    let token = cookie(headers, SESSION_COOKIE)?;
+    match session::find(&state.database, &token).await {
+        Ok(session) => session,
+        Err(why) => {
+            warn!("failed to look up a session: {why}");
+            None
+        }
+    }
+}
+
+fn form_field(body: &[u8], name: &str) -> Option<String> {
+    url::form_urlencoded::parse(body)
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+pub fn is_form(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| {
+            value.starts_with("application/x-www-form-urlencoded")
+        })
+}
+
+// Anything that changes something and rides on the session cookie has to prove it came from one of
+// our own pages, with the session's csrf token in the `X-CSRF-Token` header or a `_csrf` form
+// field. Requests without the cookie (the admin key, anonymous) can't be forged by another site.
+pub async fn csrf_layer(
+    extract::State(state): extract::State<Arc<State>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let safe = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    if safe || cookie(request.headers(), SESSION_COOKIE).is_none() {
+        return next.run(request).await;
+    }
+    let session = match current_session(&state, request.headers()).await {
+        Some(session) => session,
+        // a stale cookie gets no more than a signed out browser would
+        None => return next.run(request).await,
+    };
+
+    let header_token = request
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(ToString::to_string);
+    let (request, given) = match header_token {
+        Some(token) => (request, Some(token)),
+        None if is_form(request.headers()) => {
+            let (parts, body) = request.into_parts();
+            let body = match read_limited(body).await {
+                Some(body) => body,
+                None => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+            };
+            let token = form_field(&body, CSRF_FIELD);
+            (Request::from_parts(parts, Body::from(body)), token)
+        }
+        None => (request, None),
+    };
+
+    match given {
+        Some(given) if constant_time_eq(given.as_bytes(), session.csrf.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => (StatusCode::FORBIDDEN, "missing or wrong csrf token").into_response(),
+    }
+}
+
+pub async fn read_limited(mut body: Body) -> Option<Bytes> {
+    let mut read = Vec::new();
+    while let Some(chunk) = body.data().await {
+        read.extend_from_slice(&chunk.ok()?);
+        if read.len() > MAX_FORM_SIZE {
+            return None;
+        }
+    }
+    Some(read.into())
+}
+
+// `[headers]` of site.toml for the path, on whatever answers it. A header the response already
+// has is left alone, a plugin or page that sets its own knows better.
+pub async fn headers_layer<B>(
+    extract::State(state): extract::State<Arc<State>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let headers = state.security_headers.for_path(request.uri().path());
+    let mut response = next.run(request).await;
+    let https = state.config.site_url().base().scheme() == "https";
+    // checked when the server starts, see SecurityHeaders::problems
+    for (name, value) in headers.headers(https) {
+        if response.headers().contains_key(&name) {
+            continue;
+        }
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+    response
+}
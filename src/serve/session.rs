@@ -0,0 +1,173 @@
+use crate::models::{login_attempt, session};
+use crate::serve::proxy::client_ip;
+use crate::serve::security::{
+    clear_cookie, constant_time_eq, cookie, current_session, set_cookie, SameSite, SESSION_COOKIE,
+};
+use crate::serve::two_factor::{check_code, SecondFactor, ELEVATION_MINUTES};
+use crate::State;
+use axum::{
+    extract::{self, ConnectInfo},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Form, Json, Router,
+};
+use chrono::Duration;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::warn;
+
+// failed sign ins from one address within the window before it has to wait
+pub const MAX_FAILED_LOGINS: u64 = 5;
+pub const LOGIN_WINDOW_MINUTES: i64 = 15;
+pub const SESSION_DAYS: i64 = 14;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct LoginForm {
+    pub key: String,
+    // from the authenticator app, or a backup code, once one is enrolled
+    #[serde(default)]
+    pub code: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct SessionInfo {
+    pub admin: bool,
+    // signed in with a code, without one the policy may only let it enroll
+    pub second_factor: bool,
+    // for the `X-CSRF-Token` header or `_csrf` field of anything posted with this session
+    pub csrf: String,
+}
+
+pub async fn login(
+    extract::State(state): extract::State<Arc<State>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Form(form): Form<LoginForm>,
+) -> Response {
+    let window = Duration::minutes(LOGIN_WINDOW_MINUTES);
+    let ip = client_ip(&state.config, address.ip(), &headers).to_string();
+    let attempt = match login_attempt::begin(&state.database, &ip).await {
+        Ok(attempt) => attempt,
+        Err(why) => {
+            warn!("failed to record a sign in from {ip}: {why}");
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+    };
+    // this attempt is already counted as a failure
+    match login_attempt::recent_failures(&state.database, &ip, window).await {
+        Ok(failures) if failures > MAX_FAILED_LOGINS => {
+            let mut response =
+                (StatusCode::TOO_MANY_REQUESTS, "too many failed sign ins").into_response();
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(window.num_seconds()));
+            return response;
+        }
+        Ok(_) => {}
+        // without the count there is no limit, so no sign in either
+        Err(why) => {
+            warn!("failed to count sign ins from {ip}: {why}");
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+    }
+
+    let key_matches = constant_time_eq(form.key.as_bytes(), state.config.admin_key().as_bytes());
+    // with an authenticator app enrolled the key alone doesn't sign in, a code is only checked
+    // (and used up) after the key
+    let factor = match key_matches {
+        true => match check_code(&state.database, form.code.as_deref()).await {
+            Ok(factor) => factor,
+            Err(why) => {
+                warn!("failed to check a sign in code from {ip}: {why}");
+                return StatusCode::SERVICE_UNAVAILABLE.into_response();
+            }
+        },
+        false => SecondFactor::Failed,
+    };
+    if factor == SecondFactor::Failed {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if let Err(why) = login_attempt::succeeded(&state.database, attempt).await {
+        warn!("failed to record a sign in from {ip}: {why}");
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+
+    let lifetime = Duration::days(SESSION_DAYS);
+    let second_factor = factor == SecondFactor::Passed;
+    let elevation = Duration::minutes(ELEVATION_MINUTES);
+    match session::create(&state.database, true, lifetime, second_factor, elevation).await {
+        Ok((token, session)) => {
+            let mut response = Json(SessionInfo {
+                admin: session.admin,
+                second_factor: session.second_factor,
+                csrf: session.csrf,
+            })
+            .into_response();
+            set_cookie(
+                &state,
+                response.headers_mut(),
+                SESSION_COOKIE,
+                &token,
+                SameSite::Strict,
+                Some(lifetime.num_seconds()),
+            );
+            response
+        }
+        Err(why) => {
+            warn!("failed to create a session: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn logout(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(token) = cookie(&headers, SESSION_COOKIE) {
+        if let Err(why) = session::delete(&state.database, &token).await {
+            warn!("failed to delete a session: {why}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    clear_cookie(&state, response.headers_mut(), SESSION_COOKIE);
+    response
+}
+
+pub async fn current(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    match current_session(&state, &headers).await {
+        Some(session) => Json(SessionInfo {
+            admin: session.admin,
+            second_factor: session.second_factor,
+            csrf: session.csrf,
+        })
+        .into_response(),
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+// old sessions and sign in attempts, nothing reads them once they're past their time
+pub async fn prune(state: &State) {
+    if let Err(why) = session::delete_expired(&state.database).await {
+        warn!("failed to prune expired sessions: {why}");
+    }
+    let window = Duration::minutes(LOGIN_WINDOW_MINUTES);
+    if let Err(why) = login_attempt::prune(&state.database, window).await {
+        warn!("failed to prune sign in attempts: {why}");
+    }
+}
+
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        .route("/api/login", post(login))
+        .route("/api/logout", post(logout))
+        .route("/api/session", get(current))
+        .with_state(state)
+}
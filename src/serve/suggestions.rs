@@ -0,0 +1,319 @@
+use crate::injest::{
+    notify::{notify, Notification, NotifyEvent},
+    site::SiteMeta,
+    suggestion::{suggestion_context, suggestion_patch, translation_source, untranslated_path},
+};
+use crate::models::{
+    page_access::find_access,
+    translation_suggestion::{self, NewSuggestion, ACCEPTED, PENDING, REJECTED},
+};
+use crate::serve::{
+    access::viewer, admin::is_admin, private::page_path, proxy::client_ip,
+    two_factor::elevation_required,
+};
+use crate::{State, SITE_CONTENT};
+use axum::{
+    extract::{self, ConnectInfo, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Form, Json, Router,
+};
+use chrono::Duration;
+use itertools::Itertools;
+use language_tags::LanguageTag;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::warn;
+
+// per address per hour, plenty for a reader going through a page
+const MAX_SUGGESTIONS_PER_HOUR: u64 = 20;
+const MAX_EXCERPT_LENGTH: usize = 4000;
+const MAX_NOTE_LENGTH: usize = 1000;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct SuggestionForm {
+    pub page: String,
+    pub language: String,
+    // the text as it is now, quoted from the page
+    pub original: String,
+    pub suggested: String,
+    pub note: Option<String>,
+    pub name: Option<String>,
+}
+
+fn bad_request(why: impl Into<String>) -> Response {
+    (StatusCode::BAD_REQUEST, why.into()).into_response()
+}
+
+// A reader proposing a correction to a translation. Only pages the reader can see and that have a
+// translation of their own in that language take suggestions.
+pub async fn suggest(
+    extract::State(state): extract::State<Arc<State>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Form(form): Form<SuggestionForm>,
+) -> Response {
+    let language = match LanguageTag::parse(&form.language) {
+        Ok(language) => language,
+        Err(_) => return bad_request(format!("\"{}\" is not a language", form.language)),
+    };
+    if form.original.len() > MAX_EXCERPT_LENGTH || form.suggested.len() > MAX_EXCERPT_LENGTH {
+        return bad_request(format!(
+            "quote and suggest at most {MAX_EXCERPT_LENGTH} bytes at a time"
+        ));
+    }
+    if form.note.as_ref().map_or(0, String::len) > MAX_NOTE_LENGTH {
+        return bad_request(format!("notes are at most {MAX_NOTE_LENGTH} bytes"));
+    }
+    if form.original == form.suggested {
+        return bad_request("the suggestion changes nothing");
+    }
+
+    let page = page_path(&form.page);
+    let hidden = match find_access(&state.database, &page).await {
+        Ok(access) => access.map_or(false, |access| access.is_hidden()),
+        Err(why) => {
+            warn!("failed to look up access for {page}: {why}");
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+    };
+    let viewer = viewer(&state, &headers, None).await;
+    if (hidden && !viewer.admin) || !viewer.may_see(&state, &page) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let ip = client_ip(&state.config, address.ip(), &headers).to_string();
+    match translation_suggestion::recent_from(&state.database, &ip, Duration::hours(1)).await {
+        Ok(recent) if recent >= MAX_SUGGESTIONS_PER_HOUR => {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                "too many suggestions, try again later",
+            )
+                .into_response()
+        }
+        Ok(_) => {}
+        Err(why) => {
+            warn!("failed to count suggestions from {ip}: {why}");
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+    }
+
+    let site_root = std::path::Path::new(SITE_CONTENT);
+    let source = match translation_source(site_root, &page, &language) {
+        Some(source) => source,
+        None => return (StatusCode::NOT_FOUND, "the page has no such translation").into_response(),
+    };
+    let context = match read_to_string(site_root.join(&source))
+        .map_err(|why| why.to_string())
+        .and_then(|text| suggestion_context(&text, &form.original).map_err(|why| why.to_string()))
+    {
+        Ok(context) => context,
+        Err(why) => return bad_request(why),
+    };
+
+    let created = translation_suggestion::create(
+        &state.database,
+        NewSuggestion {
+            page: untranslated_path(&page, &language),
+            language: language.to_string(),
+            source: source.to_string_lossy().replace('\\', "/"),
+            original: form.original,
+            suggested: form.suggested,
+            context_before: context.before,
+            context_after: context.after,
+            note: form.note.filter(|note| !note.trim().is_empty()),
+            submitter: form.name.filter(|name| !name.trim().is_empty()),
+            ip,
+        },
+    )
+    .await;
+    match created {
+        Ok(suggestion) => {
+            // the reader doesn't wait on anyone being told
+            tokio::spawn(tell_moderators(state, suggestion));
+            StatusCode::ACCEPTED.into_response()
+        }
+        Err(why) => {
+            warn!("failed to store a translation suggestion: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn tell_moderators(state: Arc<State>, suggestion: translation_suggestion::Model) {
+    let subscriptions = match SiteMeta::load(SITE_CONTENT) {
+        Ok(site) => site.notify,
+        Err(why) => {
+            warn!("no notifications for suggestion {}: {why}", suggestion.id);
+            return;
+        }
+    };
+    let notification = Notification {
+        event: NotifyEvent::AwaitingModeration,
+        site: state.config.sitename().to_string(),
+        subject: format!(
+            "a {} translation suggestion for {}",
+            suggestion.language, suggestion.page
+        ),
+        body: format!(
+            "\"{}\" should read \"{}\" in {}, see /api/admin/translations",
+            suggestion.original, suggestion.suggested, suggestion.source
+        ),
+        url: Some(state.config.site_url().absolute(&suggestion.page)),
+    };
+    notify(&subscriptions, &notification, None).await;
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct QueueParams {
+    pub status: Option<String>,
+}
+
+pub async fn queue(
+    extract::State(state): extract::State<Arc<State>>,
+    Query(params): Query<QueueParams>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let status = params.status.as_deref().unwrap_or(PENDING);
+    if ![PENDING, ACCEPTED, REJECTED].contains(&status) {
+        return bad_request(format!("unknown status {status}"));
+    }
+    match translation_suggestion::with_status(&state.database, status).await {
+        Ok(suggestions) => Json(suggestions).into_response(),
+        Err(why) => {
+            warn!("failed to load translation suggestions: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn review(state: &State, headers: &HeaderMap, id: i64, status: &str) -> Response {
+    if !is_admin(state, headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if let Some(response) = elevation_required(state, headers).await {
+        return response;
+    }
+    match translation_suggestion::review(&state.database, id, status).await {
+        Ok(Some(suggestion)) => Json(suggestion).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(why) => {
+            warn!("failed to review translation suggestion {id}: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn accept(
+    extract::State(state): extract::State<Arc<State>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Response {
+    review(&state, &headers, id, ACCEPTED).await
+}
+
+pub async fn reject(
+    extract::State(state): extract::State<Arc<State>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Response {
+    review(&state, &headers, id, REJECTED).await
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct PatchParams {
+    // comma separated, every accepted suggestion if not given
+    pub ids: Option<String>,
+}
+
+// Accepted suggestions as a patch against the content repo as it is now, `git am` makes it a
+// commit. A 409 if a file changed so much since that a suggestion no longer applies.
+pub async fn patch(
+    extract::State(state): extract::State<Arc<State>>,
+    Query(params): Query<PatchParams>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let suggestions = match &params.ids {
+        Some(ids) => {
+            let ids = match ids
+                .split(',')
+                .map(|id| id.trim().parse::<i64>())
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(ids) => ids,
+                Err(_) => return bad_request("ids are comma separated numbers"),
+            };
+            translation_suggestion::find(&state.database, &ids).await
+        }
+        None => translation_suggestion::with_status(&state.database, ACCEPTED).await,
+    };
+    let suggestions = match suggestions {
+        Ok(suggestions) => suggestions
+            .into_iter()
+            .filter(|suggestion| suggestion.status == ACCEPTED)
+            .collect::<Vec<_>>(),
+        Err(why) => {
+            warn!("failed to load translation suggestions: {why}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if suggestions.is_empty() {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+
+    let mut by_source = BTreeMap::<String, Vec<(&str, &str)>>::new();
+    for suggestion in &suggestions {
+        by_source
+            .entry(suggestion.source.clone())
+            .or_default()
+            .push((&suggestion.original, &suggestion.suggested));
+    }
+    let changes = by_source.into_iter().collect::<Vec<_>>();
+    let submitters = suggestions
+        .iter()
+        .filter_map(|suggestion| suggestion.submitter.as_deref())
+        .unique()
+        .join(", ");
+    let subject = match submitters.is_empty() {
+        true => format!("Apply {} translation suggestions", suggestions.len()),
+        false => format!(
+            "Apply {} translation suggestions from {submitters}",
+            suggestions.len()
+        ),
+    };
+    let author = format!("{} <moklog@localhost>", state.config.sitename());
+    match suggestion_patch(
+        std::path::Path::new(SITE_CONTENT),
+        &subject,
+        &author,
+        &changes,
+    ) {
+        Ok(patch) => (
+            [(header::CONTENT_TYPE, "text/x-patch; charset=utf-8")],
+            patch,
+        )
+            .into_response(),
+        Err(why) => (StatusCode::CONFLICT, why.to_string()).into_response(),
+    }
+}
+
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        .route("/api/translations/suggestions", post(suggest))
+        .route("/api/admin/translations", get(queue))
+        .route("/api/admin/translations/patch", get(patch))
+        .route("/api/admin/translations/:id/accept", post(accept))
+        .route("/api/admin/translations/:id/reject", post(reject))
+        .with_state(state)
+}
@@ -0,0 +1,537 @@
+use crate::config::{Enforcement, TwoFactorPolicy};
+use crate::models::{backup_code, login_attempt, session, totp_secret};
+use crate::serve::admin::{admin_role, is_admin, AdminRole};
+use crate::serve::proxy::client_ip;
+use crate::serve::security::{constant_time_eq, current_session};
+use crate::serve::session::{LOGIN_WINDOW_MINUTES, MAX_FAILED_LOGINS};
+use crate::State;
+use axum::{
+    extract::{self, ConnectInfo},
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Form, Json, Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use color_eyre::{Report, Result};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng, RngCore};
+use schemars::JsonSchema;
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+// where bearer and auth proxy requests put their code, when TWO_FACTOR_REQUIRED asks them for one
+pub const OTP_HEADER: &str = "x-otp-code";
+// what header_code_layer put in place of it, never taken from the client
+const FACTOR_HEADER: &str = "x-moklog-second-factor";
+// how long a code keeps a session allowed to do destructive things
+pub const ELEVATION_MINUTES: i64 = 10;
+
+// rfc 6238 as every authenticator app does it, sha1 and six digits every thirty seconds
+const STEP_SECONDS: i64 = 30;
+const DIGITS: usize = 6;
+// the steps either side of now count too, for phones whose clocks are a little off
+const SKEW: i64 = 1;
+const SECRET_BYTES: usize = 20;
+const BACKUP_CODES: usize = 10;
+
+fn code_at(secret: &[u8], step: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("hmac takes keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        truncated % 10u32.pow(DIGITS as u32),
+        width = DIGITS
+    )
+}
+
+// the time step `code` is from, None if it's from none close enough to now
+fn matching_step(secret: &str, code: &str, now: DateTime<Utc>) -> Option<i64> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.trim();
+    if code.len() != DIGITS {
+        return None;
+    }
+    let current = now.timestamp() / STEP_SECONDS;
+    (current - SKEW..=current + SKEW)
+        .find(|step| constant_time_eq(code_at(&secret, *step).as_bytes(), code.as_bytes()))
+}
+
+fn new_secret() -> String {
+    let mut secret = [0; SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+// what the authenticator app is given, usually as a qr code of it
+fn provisioning_uri(issuer: &str, secret: &str) -> String {
+    let issuer = url_escape::encode_component(issuer);
+    format!(
+        "otpauth://totp/{issuer}:admin?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}"
+    )
+}
+
+// `k3x9q-m2v7a`
+fn new_backup_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..BACKUP_CODES)
+        .map(|_| {
+            let code = (&mut rng)
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect::<String>();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SecondFactor {
+    // there is no authenticator app to ask, the admin key is all there is
+    NotEnrolled,
+    Passed,
+    Failed,
+}
+
+// A code from the authenticator app or a backup code, either one is used up by this. Without a
+// code it failed, unless nothing is enrolled.
+pub async fn check_code(db: &DatabaseConnection, code: Option<&str>) -> Result<SecondFactor> {
+    let secret = match totp_secret::confirmed(db).await? {
+        Some(secret) => secret,
+        None => return Ok(SecondFactor::NotEnrolled),
+    };
+    let code = match code.map(str::trim).filter(|code| !code.is_empty()) {
+        Some(code) => code,
+        None => return Ok(SecondFactor::Failed),
+    };
+    let passed = match matching_step(&secret.secret, code, Utc::now()) {
+        Some(step) => totp_secret::use_step(db, secret.id, step).await?,
+        None => backup_code::redeem(db, code).await?,
+    };
+    Ok(match passed {
+        true => SecondFactor::Passed,
+        false => SecondFactor::Failed,
+    })
+}
+
+// A code in X-Otp-Code from the authenticator app, used up by this like any other code. Only
+// header_code_layer asks, once for each request.
+pub async fn header_factor(db: &DatabaseConnection, headers: &HeaderMap) -> Result<SecondFactor> {
+    let secret = match totp_secret::confirmed(db).await? {
+        Some(secret) => secret,
+        None => return Ok(SecondFactor::NotEnrolled),
+    };
+    let step = headers
+        .get(OTP_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|code| matching_step(&secret.secret, code, Utc::now()));
+    let passed = match step {
+        Some(step) => totp_secret::use_step(db, secret.id, step).await?,
+        None => false,
+    };
+    Ok(match passed {
+        true => SecondFactor::Passed,
+        false => SecondFactor::Failed,
+    })
+}
+
+// The code is checked once on the way in and the answer travels with the request in its place,
+// so a layer and its handler asking both get the same one. Whatever a client sent as the answer
+// itself is dropped.
+pub async fn header_code_layer<B>(
+    extract::State(state): extract::State<Arc<State>>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    request.headers_mut().remove(FACTOR_HEADER);
+    if request.headers().contains_key(OTP_HEADER) {
+        let factor = match header_factor(&state.database, request.headers()).await {
+            Ok(factor) => factor,
+            Err(why) => {
+                warn!("failed to check a request's code: {why}");
+                SecondFactor::Failed
+            }
+        };
+        let answer = match factor {
+            SecondFactor::NotEnrolled => "not-enrolled",
+            SecondFactor::Passed => "passed",
+            SecondFactor::Failed => "failed",
+        };
+        request.headers_mut().remove(OTP_HEADER);
+        request
+            .headers_mut()
+            .insert(FACTOR_HEADER, HeaderValue::from_static(answer));
+    }
+    next.run(request).await
+}
+
+// what header_code_layer made of the request's code, without one whether one was needed
+async fn header_code(state: &State, headers: &HeaderMap) -> Result<SecondFactor> {
+    match headers.get(FACTOR_HEADER).map(HeaderValue::as_bytes) {
+        Some(b"passed") => Ok(SecondFactor::Passed),
+        Some(b"not-enrolled") => Ok(SecondFactor::NotEnrolled),
+        Some(_) => Ok(SecondFactor::Failed),
+        None => Ok(match totp_secret::confirmed(&state.database).await? {
+            Some(_) => SecondFactor::Failed,
+            None => SecondFactor::NotEnrolled,
+        }),
+    }
+}
+
+fn enforcement(state: &State, role: AdminRole) -> Enforcement {
+    let policy = state.config.two_factor();
+    match role {
+        AdminRole::Session => policy.session,
+        AdminRole::Bearer => policy.bearer,
+        AdminRole::Proxy => policy.proxy,
+    }
+}
+
+// A signed in session counts as an admin one once it passed a second factor, or before that when
+// the policy doesn't always ask for one.
+pub fn session_is_admin(state: &State, session: &session::Model) -> bool {
+    session.admin
+        && (session.second_factor || state.config.two_factor().session != Enforcement::Always)
+}
+
+// Whether a bearer or auth proxy request that is admin otherwise is let through, with `always`
+// for its role only with a current code in X-Otp-Code.
+pub async fn header_code_passes(state: &State, headers: &HeaderMap) -> bool {
+    if enforcement(state, admin_role(&state.config, headers)) != Enforcement::Always {
+        return true;
+    }
+    match header_code(state, headers).await {
+        Ok(factor) => factor == SecondFactor::Passed,
+        Err(why) => {
+            warn!("failed to check a request's code: {why}");
+            false
+        }
+    }
+}
+
+// None when the request may go ahead with something destructive, a response asking for a code
+// otherwise. A session has to be elevated, a bearer or auth proxy request has to send a current
+// code, unless the policy for it is `never`. Nothing can be asked for when nothing is enrolled.
+pub async fn elevation_required(state: &State, headers: &HeaderMap) -> Option<Response> {
+    let role = admin_role(&state.config, headers);
+    if enforcement(state, role) == Enforcement::Never {
+        return None;
+    }
+    let factor = match role {
+        AdminRole::Session => match current_session(state, headers).await {
+            Some(session) if session.elevated.map_or(false, |until| until > Utc::now()) => {
+                Ok(SecondFactor::Passed)
+            }
+            Some(_) => totp_secret::confirmed(&state.database)
+                .await
+                .map(|secret| match secret {
+                    Some(_) => SecondFactor::Failed,
+                    None => SecondFactor::NotEnrolled,
+                }),
+            None => return Some(StatusCode::UNAUTHORIZED.into_response()),
+        },
+        AdminRole::Bearer | AdminRole::Proxy => header_code(state, headers).await,
+    };
+    match factor {
+        Ok(SecondFactor::Passed) | Ok(SecondFactor::NotEnrolled) => None,
+        Ok(SecondFactor::Failed) => {
+            let why = match role {
+                AdminRole::Session => {
+                    "this needs a fresh code, post one to /api/admin/2fa/elevate first"
+                }
+                AdminRole::Bearer | AdminRole::Proxy => "this needs a current code in X-Otp-Code",
+            };
+            Some((StatusCode::FORBIDDEN, why).into_response())
+        }
+        Err(why) => {
+            warn!("failed to look up the second factor: {why}");
+            Some(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+// enrolling works from a session that signed in with the key alone, that's how the first one
+// gets there when the policy asks for a second factor
+async fn admin_session(state: &State, headers: &HeaderMap) -> Option<session::Model> {
+    current_session(state, headers)
+        .await
+        .filter(|session| session.admin)
+}
+
+fn elevation() -> Duration {
+    Duration::minutes(ELEVATION_MINUTES)
+}
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct TwoFactorStatus {
+    pub enrolled: bool,
+    pub backup_codes: u64,
+    // of the session asking, None for bearer requests
+    pub elevated: Option<DateTime<Utc>>,
+    pub policy: TwoFactorPolicy,
+}
+
+pub async fn status(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    let session = admin_session(&state, &headers).await;
+    if session.is_none() && !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let found = async {
+        Ok::<_, Report>((
+            totp_secret::confirmed(&state.database).await?.is_some(),
+            backup_code::remaining(&state.database).await?,
+        ))
+    };
+    match found.await {
+        Ok((enrolled, backup_codes)) => Json(TwoFactorStatus {
+            enrolled,
+            backup_codes,
+            elevated: session.and_then(|session| session.elevated),
+            policy: state.config.two_factor(),
+        })
+        .into_response(),
+        Err(why) => {
+            warn!("failed to look up the second factor: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct Enrollment {
+    // base32, for typing into the app
+    pub secret: String,
+    // `otpauth://totp/...`, for showing as a qr code
+    pub uri: String,
+}
+
+// A new secret, waiting for a code from the app it was given to before it counts. Replacing the
+// one enrolled already needs a fresh code from it.
+pub async fn enroll(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    if admin_session(&state, &headers).await.is_none() {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if let Some(response) = elevation_required(&state, &headers).await {
+        return response;
+    }
+    let secret = new_secret();
+    match totp_secret::start(&state.database, &secret).await {
+        Ok(_) => Json(Enrollment {
+            uri: provisioning_uri(state.config.sitename(), &secret),
+            secret,
+        })
+        .into_response(),
+        Err(why) => {
+            warn!("failed to start enrolling a second factor: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct CodeForm {
+    pub code: String,
+}
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct BackupCodes {
+    // shown this once, only their hashes are kept
+    pub codes: Vec<String>,
+}
+
+// The pending secret becomes the one sign ins need a code from. Comes back with fresh backup
+// codes, and every session that signed in without a code is signed out.
+pub async fn confirm(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+    Form(form): Form<CodeForm>,
+) -> Response {
+    let session = match admin_session(&state, &headers).await {
+        Some(session) => session,
+        None => return StatusCode::UNAUTHORIZED.into_response(),
+    };
+    let pending = match totp_secret::pending(&state.database).await {
+        Ok(Some(pending)) => pending,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, "nothing to confirm, enroll first").into_response()
+        }
+        Err(why) => {
+            warn!("failed to look up the pending second factor: {why}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let step = match matching_step(&pending.secret, &form.code, Utc::now()) {
+        Some(step) => step,
+        None => {
+            return (StatusCode::BAD_REQUEST, "that code is not from this secret").into_response()
+        }
+    };
+    let codes = new_backup_codes();
+    let confirmed = async {
+        totp_secret::confirm(&state.database, pending.id, step).await?;
+        backup_code::replace(&state.database, &codes).await?;
+        session::elevate(&state.database, &session.id, elevation()).await?;
+        session::delete_single_factor(&state.database).await
+    };
+    match confirmed.await {
+        Ok(signed_out) => {
+            info!("a second factor was enrolled, {signed_out} sessions without one signed out");
+            Json(BackupCodes { codes }).into_response()
+        }
+        Err(why) => {
+            warn!("failed to confirm a second factor: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// new backup codes, the old ones stop working
+pub async fn regenerate_backup_codes(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if let Some(response) = elevation_required(&state, &headers).await {
+        return response;
+    }
+    match totp_secret::confirmed(&state.database).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return (StatusCode::NOT_FOUND, "no second factor is enrolled").into_response(),
+        Err(why) => {
+            warn!("failed to look up the second factor: {why}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+    let codes = new_backup_codes();
+    match backup_code::replace(&state.database, &codes).await {
+        Ok(()) => Json(BackupCodes { codes }).into_response(),
+        Err(why) => {
+            warn!("failed to replace the backup codes: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// A code lets the session do destructive things for ELEVATION_MINUTES. Wrong codes count against
+// the address like failed sign ins do.
+pub async fn elevate(
+    extract::State(state): extract::State<Arc<State>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Form(form): Form<CodeForm>,
+) -> Response {
+    let session = match admin_session(&state, &headers).await {
+        Some(session) => session,
+        None => return StatusCode::UNAUTHORIZED.into_response(),
+    };
+    let window = Duration::minutes(LOGIN_WINDOW_MINUTES);
+    let ip = client_ip(&state.config, address.ip(), &headers).to_string();
+    let attempt = match login_attempt::begin(&state.database, &ip).await {
+        Ok(attempt) => attempt,
+        Err(why) => {
+            warn!("failed to record a code from {ip}: {why}");
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+    };
+    // this attempt is already counted as a failure
+    match login_attempt::recent_failures(&state.database, &ip, window).await {
+        Ok(failures) if failures > MAX_FAILED_LOGINS => {
+            let mut response =
+                (StatusCode::TOO_MANY_REQUESTS, "too many wrong codes").into_response();
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(window.num_seconds()));
+            return response;
+        }
+        Ok(_) => {}
+        Err(why) => {
+            warn!("failed to count sign ins from {ip}: {why}");
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+    }
+
+    let factor = match check_code(&state.database, Some(&form.code)).await {
+        Ok(factor) => factor,
+        Err(why) => {
+            warn!("failed to check a code: {why}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if factor == SecondFactor::NotEnrolled {
+        return (StatusCode::NOT_FOUND, "no second factor is enrolled").into_response();
+    }
+    if factor != SecondFactor::Passed {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if let Err(why) = login_attempt::succeeded(&state.database, attempt).await {
+        warn!("failed to record a code from {ip}: {why}");
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    match session::elevate(&state.database, &session.id, elevation()).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(why) => {
+            warn!("failed to elevate a session: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// back to signing in with the key alone, unless the policy still asks for a code
+pub async fn disable(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if let Some(response) = elevation_required(&state, &headers).await {
+        return response;
+    }
+    let removed = async {
+        totp_secret::delete_all(&state.database).await?;
+        backup_code::delete_all(&state.database).await
+    };
+    match removed.await {
+        Ok(_) => {
+            info!("the second factor was removed");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(why) => {
+            warn!("failed to remove the second factor: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        .route("/api/admin/2fa", get(status).delete(disable))
+        .route("/api/admin/2fa/enroll", post(enroll))
+        .route("/api/admin/2fa/confirm", post(confirm))
+        .route("/api/admin/2fa/elevate", post(elevate))
+        .route("/api/admin/2fa/backup-codes", post(regenerate_backup_codes))
+        .with_state(state)
+}
@@ -0,0 +1,212 @@
+use crate::injest::{
+    generate::PageAccess,
+    notify::{notify, Notification, NotifyEvent},
+    site::SiteMeta,
+    workflow::WorkflowState,
+};
+use crate::models::{
+    page_access::{self, find_access},
+    review_assignment,
+};
+use crate::serve::{
+    access::viewer, admin::is_admin, private::page_path, two_factor::elevation_required,
+};
+use crate::{State, SITE_CONTENT};
+use axum::{
+    extract::{self, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Form, Json, Router,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tracing::warn;
+
+// a page on its way to being published, as of the last build
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct WorkflowPage {
+    pub path: String,
+    pub state: WorkflowState,
+    // from its front matter
+    pub reviewers: BTreeSet<String>,
+    // through the api since
+    pub assigned: BTreeSet<String>,
+    // everyone besides an admin who may see it
+    pub roles: BTreeSet<String>,
+}
+
+// every draft and review page with who may see it, by state then path
+async fn workflow_pages(state: &State) -> color_eyre::Result<Vec<WorkflowPage>> {
+    let mut assignments = review_assignment::all(&state.database).await?;
+    let mut pages = vec![];
+    for workflow_state in [WorkflowState::Draft, WorkflowState::Review] {
+        for (path, access) in page_access::in_state(&state.database, workflow_state).await? {
+            let assigned = match workflow_state {
+                WorkflowState::Review => assignments.remove(&path).unwrap_or_default(),
+                _ => BTreeSet::new(),
+            };
+            pages.push(WorkflowPage {
+                roles: access.roles.union(&assigned).cloned().collect(),
+                path,
+                state: access.state,
+                reviewers: access.reviewers,
+                assigned,
+            });
+        }
+    }
+    Ok(pages)
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct WorkflowParams {
+    // `draft` or `review`, both without it
+    pub state: Option<String>,
+}
+
+pub async fn pages(
+    extract::State(state): extract::State<Arc<State>>,
+    Query(params): Query<WorkflowParams>,
+    headers: HeaderMap,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let wanted = match params.state.as_deref().map(WorkflowState::parse) {
+        Some(Some(WorkflowState::Published)) | Some(None) => {
+            return (StatusCode::BAD_REQUEST, "state is `draft` or `review`").into_response()
+        }
+        Some(wanted) => wanted,
+        None => None,
+    };
+    match workflow_pages(&state).await {
+        Ok(pages) => Json(
+            pages
+                .into_iter()
+                .filter(|page| wanted.map_or(true, |wanted| page.state == wanted))
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(why) => {
+            warn!("failed to load the pages in the workflow: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// the roles just assigned to a page, the ones there before were told already
+async fn tell_reviewers(state: Arc<State>, path: String, roles: BTreeSet<String>) {
+    let subscriptions = match SiteMeta::load(SITE_CONTENT) {
+        Ok(site) => site.notify,
+        Err(why) => {
+            warn!("no notifications for the reviewers of {path}: {why}");
+            return;
+        }
+    };
+    let notification = Notification {
+        event: NotifyEvent::EnteredReview,
+        site: state.config.sitename().to_string(),
+        subject: format!("{path} is ready for review"),
+        body: format!("you were assigned to review {path}"),
+        url: Some(state.config.site_url().absolute(&path)),
+    };
+    notify(&subscriptions, &notification, Some(&roles)).await;
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct AssignForm {
+    pub path: String,
+    // comma separated, none unassigns everyone
+    pub roles: String,
+}
+
+// Assigns reviewer roles to a page in review, on top of its front matter. A role token with one of
+// them (see /api/admin/role-token) is what the reviewer opens the page with.
+pub async fn assign(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+    Form(form): Form<AssignForm>,
+) -> Response {
+    if !is_admin(&state, &headers).await {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if let Some(response) = elevation_required(&state, &headers).await {
+        return response;
+    }
+    let roles = form
+        .roles
+        .split(',')
+        .map(str::trim)
+        .filter(|role| !role.is_empty())
+        .map(ToString::to_string)
+        .collect::<BTreeSet<_>>();
+    if roles.iter().any(|role| role.contains('.')) {
+        return (StatusCode::BAD_REQUEST, "a role with a `.`").into_response();
+    }
+    let path = page_path(&form.path);
+    match find_access(&state.database, &path).await {
+        Ok(Some(PageAccess {
+            state: WorkflowState::Review,
+            ..
+        })) => {}
+        Ok(_) => return (StatusCode::CONFLICT, "the page is not in review").into_response(),
+        Err(why) => {
+            warn!("failed to look up access for {path}: {why}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+    let before = match review_assignment::for_page(&state.database, &path).await {
+        Ok(before) => before,
+        Err(why) => {
+            warn!("failed to look up the reviewers of {path}: {why}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    match review_assignment::assign(&state.database, &path, &roles).await {
+        Ok(()) => {
+            let added = roles.difference(&before).cloned().collect::<BTreeSet<_>>();
+            if !added.is_empty() {
+                tokio::spawn(tell_reviewers(state.clone(), path, added));
+            }
+            Json(roles).into_response()
+        }
+        Err(why) => {
+            warn!("failed to assign reviewers to {path}: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// the drafts and reviews the viewer's role token lets them see, what a reviewer works through
+pub async fn assigned(
+    extract::State(state): extract::State<Arc<State>>,
+    headers: HeaderMap,
+) -> Response {
+    let viewer = viewer(&state, &headers, None).await;
+    if !viewer.admin && !viewer.signed_in {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    match workflow_pages(&state).await {
+        Ok(pages) => Json(
+            pages
+                .into_iter()
+                .filter(|page| viewer.admin || !page.roles.is_disjoint(&viewer.roles))
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(why) => {
+            warn!("failed to load the pages in the workflow: {why}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        .route("/api/admin/workflow", get(pages))
+        .route("/api/admin/workflow/reviewers", post(assign))
+        .route("/api/workflow", get(assigned))
+        .with_state(state)
+}
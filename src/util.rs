@@ -1,31 +1,141 @@
+use color_eyre::{Report, Result};
+use memmap2::Mmap;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{ErrorKind, Read};
+use std::ops::Deref;
+use std::path::Path;
 
+// below this a read is cheaper than setting up a mapping
+pub const MMAP_THRESHOLD: u64 = 4 * 1024 * 1024;
+// what anything streamed through (hashing, checksums) holds at once
+pub const STREAM_BUFFER: usize = 64 * 1024;
+// Pages are parsed as a whole, a source bigger than this is a mistake (a dataset named `.md`)
+// rather than something to try and fit in memory.
+pub const MAX_SOURCE_SIZE: u64 = 64 * 1024 * 1024;
 
-pub struct Empty {}
+// a file read into memory, or mapped when it's big enough for that to pay off
+pub enum FileContents {
+    Read(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for FileContents {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileContents::Read(contents) => contents,
+            FileContents::Mapped(map) => map,
+        }
+    }
+}
 
-impl AsRef<[u8]> for Empty {
+impl AsRef<[u8]> for FileContents {
     fn as_ref(&self) -> &[u8] {
-        const NOTHING: &[u8] = &[];
-        NOTHING
+        self
     }
 }
 
-#[macro_export]
-macro_rules! mmap_load {
-    ($path:expr) => {{
-        let a: Box<impl AsRef<[u8]>> = match unsafe { MmapOptions::new().map(path) } {
-            Ok(a) => Box::new(a),
-            Err(_) => Box::new(Empty {}),
+fn with_path(path: &Path, why: impl std::fmt::Display) -> Report {
+    Report::msg(format!("{}: {why}", path.display()))
+}
+
+// For when something needs all of a file at once. Anything that only needs to look at every byte
+// (hashing) should stream it instead.
+pub fn load_file(path: impl AsRef<Path>) -> Result<FileContents> {
+    let path = path.as_ref();
+    let mut file = File::open(path).map_err(|why| with_path(path, why))?;
+    let length = file.metadata().map_err(|why| with_path(path, why))?.len();
+    if length >= MMAP_THRESHOLD {
+        // the file could change under the map, the content repo isn't written to during a build
+        let map = unsafe { Mmap::map(&file) }.map_err(|why| with_path(path, why))?;
+        return Ok(FileContents::Mapped(map));
+    }
+    let mut contents = Vec::with_capacity(length as usize);
+    file.read_to_end(&mut contents)
+        .map_err(|why| with_path(path, why))?;
+    Ok(FileContents::Read(contents))
+}
+
+// a page source, which is always read and never larger than `limit`, `[limits] file_bytes`
+pub fn read_source(path: impl AsRef<Path>, limit: u64) -> Result<Box<[u8]>> {
+    let path = path.as_ref();
+    let length = path.metadata().map_err(|why| with_path(path, why))?.len();
+    if length > limit {
+        return Err(with_path(
+            path,
+            format!(
+                "{length} bytes is too large for a page source, the limit is {limit}, raise \
+                 [limits] file_bytes if that's right"
+            ),
+        ));
+    }
+    Ok(std::fs::read(path)
+        .map_err(|why| with_path(path, why))?
+        .into_boxed_slice())
+}
+
+// hands the file to `each` STREAM_BUFFER bytes at a time, the length once it's done
+pub fn stream_file(path: impl AsRef<Path>, mut each: impl FnMut(&[u8])) -> Result<u64> {
+    let path = path.as_ref();
+    let mut file = File::open(path).map_err(|why| with_path(path, why))?;
+    let mut buffer = vec![0; STREAM_BUFFER];
+    let mut length = 0;
+    loop {
+        let read = match file.read(&mut buffer) {
+            Ok(0) => return Ok(length),
+            Ok(read) => read,
+            Err(why) if why.kind() == ErrorKind::Interrupted => continue,
+            Err(why) => return Err(with_path(path, why)),
         };
-        a
-    }};
+        each(&buffer[..read]);
+        length += read as u64;
+    }
+}
+
+// the same hash as `hash_file` of the whole contents, without holding them
+pub fn hash_path(path: impl AsRef<Path>) -> Result<u64> {
+    let mut hasher = seahash::SeaHasher::new();
+    stream_file(path, |chunk| hasher.write(chunk))?;
+    Ok(hasher.finish())
+}
+
+// byte for byte, without holding either file
+pub fn same_contents(a: impl AsRef<Path>, b: impl AsRef<Path>) -> Result<bool> {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    let metadata = |path: &Path| path.metadata().map_err(|why| with_path(path, why));
+    if metadata(a)?.len() != metadata(b)?.len() {
+        return Ok(false);
+    }
+    let mut other = File::open(b).map_err(|why| with_path(b, why))?;
+    let mut buffer = vec![0; STREAM_BUFFER];
+    let mut same = true;
+    stream_file(a, |chunk| {
+        same = same
+            && other.read_exact(&mut buffer[..chunk.len()]).is_ok()
+            && buffer[..chunk.len()] == *chunk;
+    })?;
+    Ok(same)
+}
+
+// Path::file_prefix, which is nightly only. the name up to its first `.`, `archive.tar.gz` is
+// `archive`, and a leading dot is part of it.
+pub fn file_prefix(path: &Path) -> Option<&str> {
+    let name = path.file_name()?.to_str()?;
+    let end = name
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| *c == '.')
+        .map_or(name.len(), |(dot, _)| dot);
+    Some(&name[..end])
 }
 
 #[macro_export]
 macro_rules! walker {
         ($dir:expr) => {{
-            let w = WalkBuilder::new($dir)
-                .ignore(true)
-                .add_custom_ignore_filename(".mkignore");
+            let mut w = WalkBuilder::new($dir);
+            w.ignore(true).add_custom_ignore_filename(".mkignore");
             w
         }};
     }
@@ -0,0 +1,196 @@
+// Runs the fixture sites under tests/fixtures through the whole build, into a temp dir, and keeps
+// what the server would store in an in-memory sqlite database. Each test file that uses it has its
+// own copy (`mod common;`), so not every helper is used by every one.
+#![allow(dead_code)]
+
+use moklog::injest::report::Severity;
+use moklog::models::{build_diff, page_hash, redirect};
+use moklog::{BuildOptions, BuiltSite, SiteBuild};
+use sea_orm::{ConnectionTrait, Database, DatabaseConnection, EntityTrait, Schema};
+use std::fs::{copy, create_dir_all, read_to_string};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+const BUILD_SCRIPT: &str = "build.rhai";
+
+pub fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+// the tables of everything the build writes, made from the entities since there are no
+// migrations to run
+pub async fn ephemeral_database() -> DatabaseConnection {
+    let database = Database::connect("sqlite::memory:").await.unwrap();
+    let schema = Schema::new(database.get_database_backend());
+    let backend = database.get_database_backend();
+    for table in [
+        schema.create_table_from_entity(build_diff::Entity),
+        schema.create_table_from_entity(page_hash::Entity),
+        schema.create_table_from_entity(redirect::Entity),
+    ] {
+        database.execute(backend.build(&table)).await.unwrap();
+    }
+    database
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    for entry in walkdir(from) {
+        let relative = entry.strip_prefix(from).unwrap();
+        let target = to.join(relative);
+        if entry.is_dir() {
+            create_dir_all(&target).unwrap();
+        } else {
+            create_dir_all(target.parent().unwrap()).unwrap();
+            copy(&entry, &target).unwrap();
+        }
+    }
+}
+
+// dotfiles included, `.moklog` is what makes a directory a category
+fn walkdir(dir: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![dir.to_path_buf()];
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        match path.is_dir() {
+            true => paths.extend(walkdir(&path)),
+            false => paths.push(path),
+        }
+    }
+    paths
+}
+
+pub struct Fixture {
+    // removed with the fixture
+    pub dir: TempDir,
+    pub content: PathBuf,
+    pub output: PathBuf,
+    pub built: BuiltSite,
+    pub database: DatabaseConnection,
+}
+
+pub struct FixtureBuild {
+    site: String,
+    theme: String,
+    options: Option<BuildOptions>,
+}
+
+// `tests/fixtures/sites/<site>` built with `tests/fixtures/themes/<theme>`
+pub fn fixture(site: &str, theme: &str) -> FixtureBuild {
+    FixtureBuild {
+        site: site.to_string(),
+        theme: theme.to_string(),
+        options: None,
+    }
+}
+
+impl FixtureBuild {
+    pub fn options(mut self, options: BuildOptions) -> FixtureBuild {
+        self.options = Some(options);
+        self
+    }
+
+    // the content is copied first, a build writes next to it (caches, the build script's output)
+    pub async fn build(self) -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let content = dir.path().join(&self.site);
+        let output = dir.path().join("srv");
+        copy_dir(&fixtures().join("sites").join(&self.site), &content);
+        copy(
+            fixtures().join("sites").join(BUILD_SCRIPT),
+            dir.path().join(BUILD_SCRIPT),
+        )
+        .unwrap();
+
+        let mut build = SiteBuild::new(
+            &content,
+            &output,
+            fixtures().join("themes").join(&self.theme),
+        )
+        .unwrap();
+        if let Some(options) = self.options {
+            build = build.options(options);
+        }
+        let built = build.run().await.unwrap();
+
+        // what a server build stores once it's done
+        let database = ephemeral_database().await;
+        page_hash::replace(&database, &built.pages).await.unwrap();
+        redirect::replace_generated(&database, &built.redirects)
+            .await
+            .unwrap();
+
+        Fixture {
+            dir,
+            content,
+            output,
+            built,
+            database,
+        }
+    }
+}
+
+impl Fixture {
+    // the output file of a site path, `/blog` is `blog/index.html`
+    pub fn file(&self, site_path: &str) -> PathBuf {
+        let relative = site_path.trim_start_matches('/');
+        match Path::new(relative).extension() {
+            Some(_) => self.output.join(relative),
+            None => self.output.join(relative).join("index.html"),
+        }
+    }
+
+    pub fn html(&self, site_path: &str) -> String {
+        let file = self.file(site_path);
+        read_to_string(&file).unwrap_or_else(|why| panic!("{}: {why}", file.display()))
+    }
+
+    pub fn assert_no_errors(&self) {
+        let errors = self
+            .built
+            .report
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert!(errors.is_empty(), "build errors:\n{}", errors.join("\n"));
+    }
+
+    pub fn assert_built(&self, site_path: &str) {
+        assert!(
+            self.file(site_path).is_file(),
+            "{site_path} wasn't written to {}",
+            self.file(site_path).display()
+        );
+    }
+
+    pub fn assert_html_contains(&self, site_path: &str, needle: &str) {
+        let html = self.html(site_path);
+        assert!(
+            html.contains(needle),
+            "{site_path} has no {needle:?}:\n{html}"
+        );
+    }
+
+    // the redirect page in the output, and the row the server answers with
+    pub async fn assert_redirect(&self, from: &str, to: &str) {
+        let entry = self
+            .built
+            .redirects
+            .iter()
+            .find(|entry| entry.from.trim_end_matches('/') == from.trim_end_matches('/'))
+            .unwrap_or_else(|| panic!("no redirect from {from}"));
+        assert!(
+            entry.to.ends_with(to),
+            "{from} redirects to {}, not {to}",
+            entry.to
+        );
+
+        let stored = redirect::Entity::find_by_id(entry.from.clone())
+            .one(&self.database)
+            .await
+            .unwrap()
+            .unwrap_or_else(|| panic!("the redirect from {from} wasn't stored"));
+        assert_eq!(stored.to_path, entry.to);
+    }
+}
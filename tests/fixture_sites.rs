@@ -0,0 +1,170 @@
+mod common;
+
+use common::fixture;
+use moklog::injest::listing::ListingEntry;
+use moklog::injest::manifest::MANIFEST_FILE;
+use moklog::BuildOptions;
+use std::path::Path;
+
+#[tokio::test]
+async fn basic_site_builds() {
+    let site = fixture("basic", "basic").build().await;
+    site.assert_no_errors();
+    assert!(site
+        .built
+        .pages
+        .keys()
+        .any(|page| page.contains("hello-world")));
+}
+
+#[tokio::test]
+async fn pages_are_rendered_into_the_theme() {
+    let site = fixture("basic", "basic")
+        .options(BuildOptions {
+            structured_data: true,
+            ..BuildOptions::default()
+        })
+        .build()
+        .await;
+    site.assert_no_errors();
+    // content.title and content.html, which the theme only finds as fields of `content`
+    site.assert_html_contains("/", "<title>Home</title>");
+    site.assert_html_contains("/", r#"<meta property="og:title" content="Home">"#);
+    site.assert_html_contains("/", "<main><h1");
+    site.assert_html_contains("/", "Welcome to the fixture site.");
+    site.assert_html_contains("/", r#"<link rel="canonical" href="#);
+    site.assert_html_contains("/", r#"<script type="application/ld+json">"#);
+    site.assert_html_contains("/", r#""@type":"WebPage""#);
+    site.assert_html_contains("/", r#""name":"Home""#);
+}
+
+#[tokio::test]
+async fn pages_render_with_the_template_they_name() {
+    let site = fixture("basic", "basic").build().await;
+    site.assert_no_errors();
+    site.assert_html_contains("/about", r#"<main class="wide">"#);
+    site.assert_html_contains("/about", "A page with a template of its own.");
+}
+
+#[tokio::test]
+async fn translations_render_under_their_language() {
+    let site = fixture("basic", "basic").build().await;
+    site.assert_no_errors();
+    site.assert_html_contains("/fr/", "<title>Accueil</title>");
+    // no body of its own, so the default language's
+    site.assert_html_contains("/fr/", "Welcome to the fixture site.");
+    site.assert_html_contains("/", "<title>Home</title>");
+    // changes with the page it translates
+    let translated = site
+        .built
+        .pages
+        .iter()
+        .find(|(path, _)| path.starts_with("/fr"))
+        .map(|(_, hash)| hash);
+    assert_eq!(translated, site.built.pages.get("/"));
+}
+
+#[tokio::test]
+async fn redirect_directory_becomes_a_redirect() {
+    let site = fixture("basic", "basic").build().await;
+    site.assert_built("/old-projects");
+    site.assert_html_contains("/old-projects", r#"http-equiv="refresh""#);
+    site.assert_redirect("/old-projects", "/blog").await;
+}
+
+#[tokio::test]
+async fn redirects_are_listed_under_their_category_path() {
+    let site = fixture("basic", "basic").build().await;
+    let titles = |category: &str| {
+        site.built.nav_links[category]
+            .iter()
+            .map(|link| link.title.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(titles("/"), ["Blog"]);
+    assert_eq!(titles("/guest"), ["Older Visits"]);
+}
+
+#[tokio::test]
+async fn manifest_lists_the_output() {
+    let site = fixture("basic", "basic").build().await;
+    site.assert_built(&format!("/{MANIFEST_FILE}"));
+    assert!(site
+        .built
+        .manifest
+        .files
+        .keys()
+        .any(|file| file.ends_with("old-projects/index.html")));
+}
+
+#[tokio::test]
+async fn rebuild_is_stable() {
+    let first = fixture("basic", "basic").build().await;
+    let second = fixture("basic", "basic").build().await;
+    assert_eq!(first.built.pages, second.built.pages);
+}
+
+#[tokio::test]
+async fn pages_under_a_role_path_are_sanitized() {
+    let site = fixture("basic", "basic").build().await;
+    site.assert_no_errors();
+    site.assert_html_contains("/guest/first-visit", "Thanks for having me.");
+    let html = site.html("/guest/first-visit");
+    assert!(!html.contains("<script>"), "a script survived:\n{html}");
+    assert!(
+        !html.contains("onclick"),
+        "an event handler survived:\n{html}"
+    );
+}
+
+#[tokio::test]
+async fn pinned_pages_are_listed_first() {
+    let site = fixture("basic", "basic").build().await;
+    site.assert_no_errors();
+    let listing = &site.built.listings[Path::new("guest")];
+    let slugs = |entries: &[ListingEntry]| {
+        entries
+            .iter()
+            .map(|entry| entry.slug.clone())
+            .collect::<Vec<_>>()
+    };
+    // newest first, but for the pinned one
+    assert_eq!(slugs(&listing.entries), ["first-visit", "second-visit"]);
+    // without `pinned_in_feeds` the feed is by date alone
+    assert_eq!(slugs(&listing.feed), ["second-visit", "first-visit"]);
+    site.assert_html_contains("/guest/first-visit", r#"<p class="pinned">"#);
+    assert!(!site
+        .html("/guest/second-visit")
+        .contains(r#"<p class="pinned">"#));
+}
+
+#[tokio::test]
+async fn pages_link_their_neighbours_in_the_listing() {
+    let site = fixture("basic", "basic").build().await;
+    site.assert_no_errors();
+    // pinned, so first even though it's older
+    let first = site.html("/guest/first-visit");
+    assert!(!first.contains(r#"rel="prev""#), "{first}");
+    assert!(first.contains(r#"<a rel="next""#), "{first}");
+    assert!(first.contains(">Second Visit</a>"), "{first}");
+    let second = site.html("/guest/second-visit");
+    assert!(second.contains(r#"<a rel="prev""#), "{second}");
+    assert!(second.contains(">First Visit</a>"), "{second}");
+    assert!(!second.contains(r#"rel="next""#), "{second}");
+}
+
+#[tokio::test]
+async fn articles_are_described_as_blog_postings() {
+    let site = fixture("basic", "basic")
+        .options(BuildOptions {
+            structured_data: true,
+            ..BuildOptions::default()
+        })
+        .build()
+        .await;
+    site.assert_no_errors();
+    site.assert_html_contains("/guest/first-visit", r#""@type":"BlogPosting""#);
+    site.assert_html_contains("/guest/first-visit", r#""headline":"First Visit""#);
+    let html = site.html("/guest/first-visit");
+    assert!(!html.contains(r#""@type":"WebPage""#), "{html}");
+}
@@ -0,0 +1,98 @@
+use moklog::config::Config;
+use moklog::injest::manifest::MANIFEST_FILE;
+use moklog::serve::health::{HEALTH_PATH, READY_PATH};
+use moklog::serve::openapi::{openapi, OPENAPI_PATH};
+use moklog::serve::search::SEARCH_PATH;
+use std::collections::BTreeSet;
+use std::fs::{read_dir, read_to_string};
+use std::path::Path;
+
+// `:id` in axum is `{id}` in the document
+fn openapi_path(route: &str) -> String {
+    route
+        .split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => format!("{{{name}}}"),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// axum can't list what a router holds, so the routes are read out of the `.route(..)` calls
+fn routed_api_paths() -> BTreeSet<String> {
+    let constants = [
+        ("SEARCH_PATH", SEARCH_PATH.to_string()),
+        ("OPENAPI_PATH", OPENAPI_PATH.to_string()),
+        ("health::HEALTH_PATH", HEALTH_PATH.to_string()),
+        ("health::READY_PATH", READY_PATH.to_string()),
+        (
+            "&format!(\"/{MANIFEST_FILE}\")",
+            format!("/{MANIFEST_FILE}"),
+        ),
+    ];
+    let serve = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/serve");
+    let mut paths = BTreeSet::new();
+    for file in read_dir(serve).unwrap() {
+        let file = file.unwrap().path();
+        let source = read_to_string(&file).unwrap();
+        for call in source.split(".route(").skip(1) {
+            let call = call.trim_start();
+            let route = match call.strip_prefix('"') {
+                Some(literal) => literal.split('"').next().unwrap().to_string(),
+                None => constants
+                    .iter()
+                    .find(|(name, _)| call.starts_with(name))
+                    .map(|(_, path)| path.clone())
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "{}: a route this test doesn't know the path of",
+                            file.display()
+                        )
+                    }),
+            };
+            paths.insert(openapi_path(&route));
+        }
+    }
+    paths
+        .into_iter()
+        .filter(|path| path.starts_with("/api/") && path != OPENAPI_PATH)
+        .collect()
+}
+
+#[test]
+fn every_api_route_is_documented() {
+    let document = openapi(&Config::offline().unwrap()).unwrap();
+    let documented = document["paths"]
+        .as_object()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    let routed = routed_api_paths();
+    assert_eq!(
+        routed.difference(&documented).collect::<Vec<_>>(),
+        Vec::<&String>::new(),
+        "routed but not in the document"
+    );
+    assert_eq!(
+        documented.difference(&routed).collect::<Vec<_>>(),
+        Vec::<&String>::new(),
+        "in the document but not routed"
+    );
+}
+
+#[test]
+fn every_schema_is_used() {
+    let document = openapi(&Config::offline().unwrap()).unwrap();
+    let paths = document["paths"].to_string();
+    let schemas = document["components"]["schemas"].as_object().unwrap();
+    for name in schemas.keys() {
+        let reference = format!("#/components/schemas/{name}");
+        let referenced = paths.contains(&reference)
+            || schemas
+                .iter()
+                .any(|(other, schema)| other != name && schema.to_string().contains(&reference));
+        assert!(referenced, "{name} is described but nothing uses it");
+    }
+}
@@ -0,0 +1,129 @@
+use moklog::injest::access::{rule_for, AccessRule};
+use moklog::injest::generate::PageAccess;
+use moklog::injest::workflow::WorkflowState;
+use moklog::models::page_access;
+use moklog::serve::private::{covering_paths, hidden_cover, page_path, served_path};
+use sea_orm::{ConnectionTrait, Database, Schema};
+use std::collections::{BTreeMap, BTreeSet};
+
+fn private() -> PageAccess {
+    PageAccess {
+        private: true,
+        ..PageAccess::default()
+    }
+}
+
+// what private_layer looks up for a request, None for one it turns away
+fn cover(rows: &BTreeMap<String, PageAccess>, request_path: &str) -> Option<String> {
+    let path = page_path(&served_path(request_path)?);
+    hidden_cover(rows, &path).map(|(page, _)| page)
+}
+
+fn rows() -> BTreeMap<String, PageAccess> {
+    BTreeMap::from([
+        ("/blog/post".to_string(), private()),
+        // unlisted is still public
+        (
+            "/blog/listed".to_string(),
+            PageAccess {
+                unlisted: true,
+                ..PageAccess::default()
+            },
+        ),
+        (
+            "/drafts/next".to_string(),
+            PageAccess {
+                state: WorkflowState::Draft,
+                ..PageAccess::default()
+            },
+        ),
+    ])
+}
+
+#[test]
+fn served_paths_are_decoded_and_normalized() {
+    assert_eq!(served_path("/blog/p%6Fst/").as_deref(), Some("/blog/post/"));
+    assert_eq!(served_path("//blog//post").as_deref(), Some("/blog/post"));
+    assert_eq!(served_path("/blog/./post/").as_deref(), Some("/blog/post/"));
+    assert_eq!(served_path("/").as_deref(), Some("/"));
+    assert_eq!(served_path("/blog/%2e%2e/post"), None);
+    assert_eq!(served_path("/blog/%ff"), None);
+}
+
+#[test]
+fn covering_paths_go_up_to_the_root() {
+    assert_eq!(
+        covering_paths("/blog/post/image.png"),
+        ["/blog/post/image.png", "/blog/post", "/blog", "/"]
+    );
+    assert_eq!(covering_paths("/"), ["/"]);
+}
+
+#[test]
+fn encoded_requests_find_the_private_page() {
+    let rows = rows();
+    assert_eq!(cover(&rows, "/blog/post/").as_deref(), Some("/blog/post"));
+    assert_eq!(cover(&rows, "/blog/p%6Fst/").as_deref(), Some("/blog/post"));
+    assert_eq!(
+        cover(&rows, "/blog/post%2Findex.html").as_deref(),
+        Some("/blog/post")
+    );
+}
+
+#[test]
+fn double_slashes_find_the_private_page() {
+    let rows = rows();
+    assert_eq!(cover(&rows, "//blog/post").as_deref(), Some("/blog/post"));
+    assert_eq!(cover(&rows, "/blog//post/").as_deref(), Some("/blog/post"));
+}
+
+#[test]
+fn files_next_to_a_private_page_are_private() {
+    let rows = rows();
+    assert_eq!(
+        cover(&rows, "/blog/post/image.png").as_deref(),
+        Some("/blog/post")
+    );
+    assert_eq!(
+        cover(&rows, "/drafts/next/figures/plot.svg").as_deref(),
+        Some("/drafts/next")
+    );
+    assert_eq!(cover(&rows, "/blog/postscript/image.png"), None);
+    assert_eq!(cover(&rows, "/blog/listed/image.png"), None);
+    assert_eq!(cover(&rows, "/blog"), None);
+}
+
+#[test]
+fn access_rules_cover_the_served_path() {
+    let rules = [AccessRule {
+        path: None,
+        category: Some("members".to_string()),
+        roles: BTreeSet::from(["member".to_string()]),
+    }];
+    for request in ["/members/x", "/%6Dembers/x", "//members/x", "/members//x/"] {
+        let path = page_path(&served_path(request).unwrap());
+        assert!(
+            rule_for(&rules, &path).is_some(),
+            "{request} is not covered"
+        );
+    }
+    let path = page_path(&served_path("/membership").unwrap());
+    assert!(rule_for(&rules, &path).is_none());
+}
+
+#[tokio::test]
+async fn covering_rows_come_from_the_database() {
+    let database = Database::connect("sqlite::memory:").await.unwrap();
+    let backend = database.get_database_backend();
+    let table = Schema::new(backend).create_table_from_entity(page_access::Entity);
+    database.execute(backend.build(&table)).await.unwrap();
+    page_access::replace(&database, &rows()).await.unwrap();
+
+    let path = page_path(&served_path("/blog/p%6Fst/image.png").unwrap());
+    let found = page_access::find_many(&database, &covering_paths(&path))
+        .await
+        .unwrap();
+    let (page, access) = hidden_cover(&found, &path).unwrap();
+    assert_eq!(page, "/blog/post");
+    assert!(access.private);
+}
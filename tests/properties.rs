@@ -0,0 +1,179 @@
+use base64::alphabet::URL_SAFE;
+use base64::engine::{GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use chrono::{FixedOffset, TimeZone};
+use language_tags::LanguageTag;
+use moklog::injest::assets::AssetStore;
+use moklog::injest::config_meta::{front_matter, ConfigMeta};
+use moklog::injest::generate::{toml_v_to_json_v, PageHeader};
+use moklog::injest::links::{LinkStyle, RoutePolicy, SiteUrl};
+use moklog::injest::locale::Locale;
+use moklog::injest::processor::{html_post_processor, PostProcessContext};
+use moklog::injest::report::BuildReport;
+use moklog::injest::site::BuildOptions;
+use moklog::injest::static_file::{fingerprinted_name, parse_filename};
+use moklog::util::file_prefix;
+use proptest::prelude::*;
+use std::path::Path;
+use std::sync::Mutex;
+use toml::Value;
+
+fn toml_value() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        any::<String>().prop_map(Value::String),
+        any::<i64>().prop_map(Value::Integer),
+        any::<f64>().prop_map(Value::Float),
+        any::<bool>().prop_map(Value::Boolean),
+    ];
+    leaf.prop_recursive(4, 64, 8, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..8).prop_map(Value::Array),
+            prop::collection::btree_map(any::<String>(), inner, 0..8)
+                .prop_map(|table| Value::Table(table.into_iter().collect())),
+        ]
+    })
+}
+
+// tags, attributes and text that html parsers trip over, put together at random
+fn adversarial_html() -> impl Strategy<Value = String> {
+    let piece = prop_oneof![
+        Just("<a href=\"/x#y\">".to_string()),
+        Just("<a href=\"https://example.org/\" target=_blank>".to_string()),
+        Just("<img src=\"/image.png\">".to_string()),
+        Just("<video src=\"video.mp4\">".to_string()),
+        Just("<script>if (a < b) { document.write('</p>') }</script>".to_string()),
+        Just("<style>p > a { color: red }</style>".to_string()),
+        Just("<pre><code>&lt;&amp;</code></pre>".to_string()),
+        Just("<!-- more -->".to_string()),
+        Just("<!--".to_string()),
+        Just("</div></p></html>".to_string()),
+        Just("<p id=\"\" class>".to_string()),
+        Just("<meta property=\"og:image\" content=\"\">".to_string()),
+        Just("<![CDATA[ x ]]>".to_string()),
+        Just("&#xFFFFFF;&nosuchentity;".to_string()),
+        "[^<>&]{0,24}",
+        any::<String>(),
+    ];
+    prop::collection::vec(piece, 0..32).prop_map(|pieces| pieces.concat())
+}
+
+proptest! {
+    #[test]
+    fn fingerprinted_names_round_trip(
+        hash in any::<u64>(),
+        name in "[a-zA-Z0-9_-]{1,24}",
+        extension in "[a-z0-9]{1,5}(\\.[a-z0-9]{1,3})?",
+    ) {
+        let original = format!("{name}.{extension}");
+        let (_, fingerprinted) = fingerprinted_name(hash, &original).unwrap();
+        prop_assert_eq!(parse_filename(&fingerprinted), Some((hash, original)));
+    }
+
+    // what builds before hex fingerprints wrote
+    #[test]
+    fn legacy_names_still_parse(hash in any::<u64>(), name in "[a-zA-Z0-9_-]{1,24}") {
+        let engine = GeneralPurpose::new(
+            &URL_SAFE,
+            GeneralPurposeConfig::new().with_encode_padding(true),
+        );
+        let legacy = format!("{name}-{}.png", engine.encode(hash.to_le_bytes()));
+        prop_assert_eq!(parse_filename(legacy), Some((hash, format!("{name}.png"))));
+    }
+
+    #[test]
+    fn parse_filename_never_panics(filename in any::<String>()) {
+        let _ = parse_filename(&filename);
+    }
+
+    // any first character, `ü.md` and `日本.md` too
+    #[test]
+    fn file_prefix_stops_at_the_first_dot(
+        first in any::<char>().prop_filter("a name", |c| *c != '.' && *c != '/' && *c != '\0'),
+        rest in "[^./]{0,12}",
+        extension in "[a-zA-Z0-9.]{0,8}",
+    ) {
+        let stem = format!("{first}{rest}");
+        let name = match extension.is_empty() {
+            true => stem.clone(),
+            false => format!("{stem}.{extension}"),
+        };
+        prop_assert_eq!(file_prefix(Path::new(&name)), Some(stem.as_str()));
+    }
+
+    #[test]
+    fn front_matter_is_a_prefix(data in any::<String>()) {
+        prop_assert!(data.starts_with(front_matter(&data)));
+    }
+
+    #[test]
+    fn headers_never_panic(data in any::<String>(), hours in -12i32..=14) {
+        let offset = FixedOffset::east_opt(hours * 3600).unwrap();
+        let _ = PageHeader::parse(&data, &offset);
+        let _ = ConfigMeta::parse(&data);
+    }
+
+    #[test]
+    fn date_patterns_never_panic(pattern in any::<String>(), language in "en|ko|ja|de") {
+        let date = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2023, 1, 1, 0, 0, 0)
+            .unwrap();
+        let locale = Locale::for_language(&LanguageTag::parse(&language).unwrap());
+        let _ = locale.format_date(&date, Some(&pattern));
+    }
+
+    #[test]
+    fn toml_to_json_keeps_the_shape(value in toml_value()) {
+        fn same_shape(toml: &Value, json: &serde_json::Value) -> bool {
+            match (toml, json) {
+                (Value::Array(toml), serde_json::Value::Array(json)) => {
+                    toml.len() == json.len()
+                        && toml.iter().zip(json).all(|(toml, json)| same_shape(toml, json))
+                }
+                (Value::Table(toml), serde_json::Value::Object(json)) => {
+                    toml.len() == json.len()
+                        && toml.iter().all(|(key, toml)| {
+                            json.get(key).map_or(false, |json| same_shape(toml, json))
+                        })
+                }
+                (Value::Float(float), serde_json::Value::Null) => !float.is_finite(),
+                (Value::Array(_) | Value::Table(_), _) => false,
+                _ => true,
+            }
+        }
+        let json = toml_v_to_json_v(value.clone());
+        prop_assert!(same_shape(&value, &json));
+    }
+
+    #[test]
+    fn post_processor_never_panics(html in adversarial_html(), stages in any::<bool>()) {
+        let urls =
+            SiteUrl::new("https://example.com/", LinkStyle::default(), RoutePolicy::default())
+                .unwrap();
+        // the stages that don't need anything outside the process
+        let options = BuildOptions {
+            structured_data: stages,
+            accessibility_audit: stages,
+            ..BuildOptions::default()
+        };
+        let report = Mutex::new(BuildReport::new());
+        let language = LanguageTag::parse("en").unwrap();
+        let post = PostProcessContext {
+            urls: &urls,
+            bundle: None,
+            options: &options,
+            breadcrumbs: &[],
+            structured_data: None,
+            canonical: "/fuzz",
+            alternates: &[],
+            site_root: Path::new("."),
+            source: Path::new("fuzz.md"),
+            report: &report,
+            og_image: Some("/fuzz/card.png"),
+            summary: Default::default(),
+            noindex: stages,
+            language: &language,
+        };
+        let _ = html_post_processor("/fuzz", &AssetStore::new(), &post, &html);
+    }
+}
@@ -0,0 +1,85 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+use chrono::Utc;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use moklog::config::Config;
+use moklog::models::totp_secret;
+use moklog::serve::admin::{admin_role, AdminRole};
+use moklog::serve::security::SESSION_COOKIE;
+use moklog::serve::two_factor::{header_factor, SecondFactor, OTP_HEADER};
+use sea_orm::{ConnectionTrait, Database, Schema};
+use sha1::Sha1;
+
+const SECRET: &str = "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP";
+
+fn config() -> Config {
+    Config {
+        admin_key: "the-admin-key".to_string(),
+        ..Config::offline().unwrap()
+    }
+}
+
+fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+        headers.insert(
+            header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+            HeaderValue::from_str(value).unwrap(),
+        );
+    }
+    headers
+}
+
+// what the authenticator app shows for `step`, rfc 6238 the long way round
+fn code_at(step: i64) -> String {
+    let secret = BASE32_NOPAD.decode(SECRET.as_bytes()).unwrap();
+    let mut mac = Hmac::<Sha1>::new_from_slice(&secret).unwrap();
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!("{:06}", truncated % 1_000_000)
+}
+
+#[test]
+fn only_the_admin_key_makes_a_bearer_request() {
+    let config = config();
+    let bearer = headers(&[("authorization", "Bearer the-admin-key")]);
+    assert_eq!(admin_role(&config, &bearer), AdminRole::Bearer);
+    let wrong = headers(&[("authorization", "Bearer some-other-key")]);
+    assert_eq!(admin_role(&config, &wrong), AdminRole::Session);
+}
+
+#[test]
+fn a_session_with_other_authorization_stays_a_session() {
+    let cookie = format!("{SESSION_COOKIE}=abc");
+    let request = headers(&[("cookie", &cookie), ("authorization", "Basic x")]);
+    assert_eq!(admin_role(&config(), &request), AdminRole::Session);
+}
+
+#[tokio::test]
+async fn a_header_code_only_works_once() {
+    let database = Database::connect("sqlite::memory:").await.unwrap();
+    let backend = database.get_database_backend();
+    let table = Schema::new(backend).create_table_from_entity(totp_secret::Entity);
+    database.execute(backend.build(&table)).await.unwrap();
+    let secret = totp_secret::start(&database, SECRET).await.unwrap();
+    // confirmed a while ago, with a code long gone
+    totp_secret::confirm(&database, secret.id, 0).await.unwrap();
+
+    let step = Utc::now().timestamp() / 30;
+    let request = headers(&[(OTP_HEADER, &code_at(step))]);
+    assert_eq!(
+        header_factor(&database, &request).await.unwrap(),
+        SecondFactor::Passed
+    );
+    assert_eq!(
+        header_factor(&database, &request).await.unwrap(),
+        SecondFactor::Failed
+    );
+}